@@ -0,0 +1,61 @@
+//! `cargo bench` (from this directory): how long lexing, parsing and
+//! evaluating take on generated expressions of a few sizes, so that
+//! regressions show up as changes from the last run. The expressions come
+//! from `stoncc::generate` with fixed seeds, so every run times the same
+//! input.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use stoncc::generate::{random, sized, Rng};
+use stoncc::limits::{EvalLimits, Meter};
+use stoncc::{Env, Lexer, Value};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+fn lex(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lex");
+    for bytes in SIZES {
+        let src = sized(&mut Rng::new(1), bytes);
+        group.throughput(Throughput::Bytes(src.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(bytes), &src, |b, src| {
+            b.iter(|| Lexer::from_bytes(black_box(src.as_bytes())).tokens().unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for bytes in SIZES {
+        let src = sized(&mut Rng::new(1), bytes);
+        group.throughput(Throughput::Bytes(src.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(bytes), &src, |b, src| {
+            b.iter(|| stoncc::expr(black_box(src.as_bytes())).unwrap())
+        });
+    }
+
+    // Every operator, not just the two `sized` uses.
+    let mut rng = Rng::new(2);
+    let src: String = (0..200).map(|_| random(&mut rng, 6).to_infix() + "\n").collect();
+    group.throughput(Throughput::Bytes(src.len() as u64));
+    group.bench_function("operators", |b| b.iter(|| stoncc::program(black_box(src.as_bytes())).0.unwrap()));
+    group.finish();
+}
+
+fn eval(c: &mut Criterion) {
+    let mut group = c.benchmark_group("eval");
+    let env = Env::new();
+    for bytes in SIZES {
+        let ast = stoncc::expr(sized(&mut Rng::new(1), bytes).as_bytes()).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(bytes), &ast, |b, ast| {
+            b.iter(|| {
+                let mut meter = Meter::new(EvalLimits::default());
+                let v: Value = stoncc::eval(black_box(ast), &env, &mut meter).unwrap();
+                v
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lex, parse, eval);
+criterion_main!(benches);
@@ -0,0 +1,15 @@
+//! `cargo fuzz run parse`: the parser never panics, and a program that
+//! parses comes back the same from its infix source.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(p) = stoncc::program(data).0 else { return };
+    let infix = p.to_infix();
+    match stoncc::program(infix.as_bytes()).0 {
+        Ok(again) => assert_eq!(again.to_string(), p.to_string(), "{infix:?}"),
+        Err(e) => panic!("{infix:?}: {e}"),
+    }
+});
@@ -0,0 +1,59 @@
+/*
+ * The C interface of stoncc plugins (`stoncc --plugin libfoo.so`).
+ *
+ * A plugin is a shared library exporting `stoncc_plugin_init`. stoncc
+ * loads it before reading any program and calls that once, with a table
+ * of functions through which the plugin registers what it adds:
+ *
+ * - builtins, which programs call as `name(args...)`;
+ * - infix operators, each a new spelling that parses like an existing
+ *   operator and calls a builtin with its two operands;
+ * - output formats, which `--out=NAME` selects.
+ *
+ * Numbers cross the interface as doubles: a builtin's arguments are
+ * converted to them, and its result is a float.
+ *
+ * The ABI is versioned. `abi` is STONCC_PLUGIN_ABI for the stoncc that
+ * loads the plugin; this version only ever gains fields at the end of
+ * `struct stoncc_host`, and a plugin built against an older header keeps
+ * working. A plugin should fail its init if `abi` is older than the one it
+ * was built against.
+ */
+
+#ifndef STONCC_PLUGIN_H
+#define STONCC_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define STONCC_PLUGIN_ABI 1
+
+/* Sets *result from the argc arguments, and returns 0; or returns any other
+ * value for an error, which stops the program. */
+typedef int (*stoncc_builtin)(const double *args, size_t argc, double *result);
+
+/* Writes value as text, ending in a NUL, to buf of len bytes, and returns
+ * 0; or returns any other value if it doesn't fit or can't be written. */
+typedef int (*stoncc_format)(double value, char *buf, size_t len);
+
+struct stoncc_host {
+    uint32_t abi;
+    /* Passed back to each function below. */
+    void *ctx;
+    /* Adds builtin `name`, taking from min_args to max_args arguments. */
+    int (*builtin)(void *ctx, const char *name, size_t min_args, size_t max_args, stoncc_builtin f);
+    /* Adds the infix operator `spelling`, with the precedence and
+     * associativity of the infix operator `like`, calling the builtin
+     * `function` with its operands. */
+    int (*infix)(void *ctx, const char *spelling, const char *like, const char *function);
+    /* Adds the output format `name`. */
+    int (*format)(void *ctx, const char *name, stoncc_format f);
+};
+
+/* Each registering function returns 0, or nonzero if what it was given
+ * isn't valid: a name that is taken or empty, or an operator that can't
+ * be spelled that way. The plugin's init returns 0, or nonzero to fail
+ * loading. */
+int stoncc_plugin_init(const struct stoncc_host *host);
+
+#endif
@@ -0,0 +1,245 @@
+//! Expression trees in one pool. An `Ast` keeps all of its nodes in a
+//! single `Vec`, with children named by `NodeId`, so a tree of a million
+//! nodes takes a handful of allocations rather than a million, and is
+//! freed at once. The parser builds it directly, and it evaluates like a
+//! tree of `Node`s.
+
+use std::fmt;
+
+use crate::eval::{walk, Tree, Values, View};
+use crate::lexer::Span;
+use crate::limits::Meter;
+use crate::parser::{self, Builder, LeafVal, Node, NodeVal, ParseError, Value};
+use crate::{Builtins, Env, EvalError, Function};
+
+/// A node of an `Ast`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+#[derive(Debug, Clone)]
+pub enum Entry {
+    Leaf(LeafVal, Span),
+    /// An operator node, with `len` children from `first` on in the pool
+    /// of children.
+    Node { v: NodeVal, first: u32, len: u32, span: Span },
+    Error(Span),
+}
+
+/// An expression tree whose nodes all live in one pool. A node's children
+/// come before it.
+#[derive(Debug, Clone)]
+pub struct Ast {
+    nodes: Vec<Entry>,
+    children: Vec<NodeId>,
+    root: NodeId,
+}
+
+impl Ast {
+    fn new() -> Self {
+        Ast { nodes: Vec::new(), children: Vec::new(), root: NodeId(0) }
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn get(&self, id: NodeId) -> &Entry {
+        &self.nodes[id.0 as usize]
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        match self.get(id) {
+            Entry::Leaf(..) | Entry::Error(_) => &[],
+            Entry::Node { first, len, .. } => &self.children[*first as usize..(first + len) as usize],
+        }
+    }
+
+    /// How many nodes the tree under `id` has, leaves included.
+    pub fn size(&self, id: NodeId) -> usize {
+        parser::size(self, id)
+    }
+
+    /// How many nodes the pool holds.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The subtree under `id` as `Node`s, built without recursion.
+    pub fn to_node(&self, id: NodeId) -> Node {
+        let mut tasks = vec![(id, false)];
+        let mut done = Vec::new();
+        while let Some((id, ready)) = tasks.pop() {
+            match self.get(id) {
+                Entry::Leaf(v, span) => done.push(Node::Leaf(v.clone(), *span)),
+                Entry::Error(span) => done.push(Node::Error(*span)),
+                Entry::Node { v, len, span, .. } if ready => {
+                    let children = done.split_off(done.len() - *len as usize);
+                    done.push(Node::Node { v: v.clone(), children, span: *span });
+                }
+                Entry::Node { .. } => {
+                    tasks.push((id, true));
+                    tasks.extend(self.children(id).iter().rev().map(|&c| (c, false)));
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+
+    fn push(&mut self, e: Entry) -> NodeId {
+        let id = u32::try_from(self.nodes.len()).expect("too many nodes for an Ast");
+        self.nodes.push(e);
+        NodeId(id)
+    }
+}
+
+impl Builder for Ast {
+    type Expr = NodeId;
+
+    fn leaf(&mut self, v: LeafVal, span: Span) -> NodeId {
+        self.push(Entry::Leaf(v, span))
+    }
+
+    fn node(&mut self, v: NodeVal, children: impl IntoIterator<Item = NodeId>, span: Span) -> NodeId {
+        let first = self.children.len();
+        self.children.extend(children);
+        let len = (self.children.len() - first) as u32;
+        self.push(Entry::Node { v, first: first as u32, len, span })
+    }
+
+    fn span(&self, &id: &NodeId) -> Span {
+        let (Entry::Leaf(_, span) | Entry::Node { span, .. } | Entry::Error(span)) = self.get(id);
+        *span
+    }
+
+    fn respan(&mut self, id: NodeId, to: Span) -> NodeId {
+        let (Entry::Leaf(_, span) | Entry::Node { span, .. } | Entry::Error(span)) = &mut self.nodes[id.0 as usize];
+        *span = to;
+        id
+    }
+
+    // The percentage node itself stays in the pool, unused.
+    fn percentage(&mut self, id: NodeId) -> Result<(NodeId, NodeId), NodeId> {
+        match (self.get(id), self.children(id)) {
+            (Entry::Node { v: NodeVal::Div, .. }, &[p, hundred])
+                if matches!(self.get(hundred), Entry::Leaf(LeafVal::Decimal(d), _) if d == "100.0") =>
+            {
+                Ok((p, hundred))
+            }
+            _ => Err(id),
+        }
+    }
+}
+
+/// Copies a tree of `Node`s into a pool.
+impl From<&Node> for Ast {
+    fn from(node: &Node) -> Self {
+        let mut ast = Ast::new();
+        let mut tasks = vec![(node, false)];
+        let mut done = Vec::new();
+        while let Some((node, ready)) = tasks.pop() {
+            match node {
+                Node::Leaf(v, span) => done.push(ast.leaf(v.clone(), *span)),
+                Node::Error(span) => done.push(ast.push(Entry::Error(*span))),
+                Node::Node { v, children, span } if ready => {
+                    let children = done.split_off(done.len() - children.len());
+                    done.push(ast.node(v.clone(), children, *span));
+                }
+                Node::Node { children, .. } => {
+                    tasks.push((node, true));
+                    tasks.extend(children.iter().rev().map(|c| (c, false)));
+                }
+            }
+        }
+        ast.root = done.pop().unwrap();
+        ast
+    }
+}
+
+impl<'a> Tree<'a> for &'a Ast {
+    type Id = NodeId;
+    type Children = std::iter::Copied<std::slice::Iter<'a, NodeId>>;
+
+    fn view(self, id: NodeId) -> View<'a, Self::Children> {
+        match self.get(id) {
+            Entry::Leaf(v, span) => View::Leaf(v, *span),
+            Entry::Error(span) => View::Error(*span),
+            Entry::Node { v, first, len, span } => {
+                View::Node(v, self.children[*first as usize..][..*len as usize].iter().copied(), *span)
+            }
+        }
+    }
+
+    // Bodies of functions are `Node`s.
+    fn body(self, _: &'a Function) -> Option<NodeId> {
+        None
+    }
+}
+
+/// Prints the tree from the root like a `Node`, precision included.
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        parser::write_tree(f, self, self.root)
+    }
+}
+
+/// Parses `s` into a pool, silently skipping stray characters, like
+/// `parser::expr`.
+pub fn expr(s: &[u8]) -> Result<Ast, ParseError> {
+    let (mut ast, result) = parser::expr_into(s, Ast::new());
+    ast.root = result?;
+    Ok(ast)
+}
+
+/// Evaluates `ast` like `eval_with` evaluates a `Node`.
+pub fn eval_with(ast: &Ast, env: &Env, builtins: &Builtins, meter: &mut Meter) -> Result<Value, EvalError> {
+    walk(&Values, ast, ast.root, env, builtins, meter)
+}
+
+#[test]
+fn tests() {
+    for s in ["1 + 2 * 3", "-(x)! ^ 2 ^ 3", "max(1, f(2), 3) ? a && b : c || d", "((1))", "~1 << 4 | 12 & 10 % 7 >> 1"] {
+        let ast = expr(s.as_bytes()).unwrap();
+        let node = parser::expr(s.as_bytes()).unwrap();
+        assert_eq!(ast.to_string(), node.to_string());
+        assert_eq!(ast.to_node(ast.root()), node);
+        assert_eq!(Ast::from(&node).to_node(ast.root()), node);
+    }
+
+    // Spans are those of the parser, parentheses included.
+    let ast = expr(b"2 * (x + 1)").unwrap();
+    let &[two, sum] = ast.children(ast.root()) else { panic!() };
+    assert_eq!(ast.span(&two), Span { start: 0, end: 1 });
+    assert_eq!(ast.span(&sum), Span { start: 4, end: 11 });
+    assert_eq!(ast.len(), 5);
+    assert_eq!(format!("{ast:.2}"), "(* 2 (+ ... ...))");
+    assert_eq!(format!("{ast:#.1}"), "(* <1 node> <3 nodes>)");
+    assert_eq!(ast.size(sum), 3);
+
+    let mut builtins = Builtins::default();
+    builtins.define("f", Function { params: vec!["x".into()], body: parser::expr(b"x * 10 + y").unwrap() });
+    builtins.define("g", Function { params: vec![], body: parser::expr(b"g() + 1").unwrap() });
+    let env: Env = [("x".to_string(), Value::Int(1)), ("y".to_string(), Value::Int(2))].into();
+    let run = |s: &str| {
+        let mut meter = Meter::new(Default::default());
+        eval_with(&expr(s.as_bytes()).unwrap(), &env, &builtins, &mut meter)
+    };
+    assert_eq!(run("f(5) + x"), Ok(Value::Int(53)));
+    assert_eq!(run("f(f(0))"), Ok(Value::Int(22)));
+    assert_eq!(run("0 && g() || x > 0 ? f(2) : g()"), Ok(Value::Int(22)));
+    assert!(matches!(run("g()"), Err(EvalError::Recursion(..))));
+    assert_eq!(run("y / (x - 1)").unwrap_err().code(), "division-by-zero");
+
+    // A percentage that `of` takes apart stays in the pool, unused.
+    let mut ast = expr(b"20 / 100.0").unwrap();
+    let &[p, hundred] = ast.children(ast.root()) else { panic!() };
+    assert_eq!(ast.percentage(ast.root()), Ok((p, hundred)));
+    assert_eq!(ast.percentage(p), Err(p));
+
+    let n = 100_000;
+    let deep = format!("{}1{}", "(".repeat(n), ")".repeat(n));
+    assert_eq!(expr(deep.as_bytes()).unwrap().len(), 1);
+}
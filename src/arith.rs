@@ -0,0 +1,269 @@
+//! Integer semantics. Integers are 32 bits wide, and an `ArithMode` says
+//! what happens when an exact result does not fit. Division by zero is an
+//! error in every mode.
+
+use core::fmt;
+use core::str::FromStr;
+
+use crate::parser::{NodeVal, Value};
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArithMode {
+    /// Wrap around in two's complement, like the compiled backends.
+    Wrap,
+    /// Clamp to the nearest representable value.
+    Saturate,
+    /// Fail with `ArithError::Overflow`.
+    #[default]
+    Checked,
+    /// Continue in 128 bits (`Value::Wide`), failing only past that.
+    Promote,
+}
+
+/// Why an integer operator has no result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ArithError {
+    Overflow,
+    /// `n / 0`, `n % 0`, and `0 ^ n` for negative `n`.
+    DivisionByZero,
+    NegativeFactorial,
+    /// `a << b` or `a >> b` for negative `b`.
+    NegativeShift,
+    /// A float, fraction or decimal with a fractional part given to an
+    /// operator that only takes integers, like `&`.
+    NotInteger(NodeVal, NonInteger),
+    /// A duration given to an operator that can't take one, like `1h * 1h`.
+    Duration,
+    /// Quantities of different dimensions added or compared, like
+    /// `1 m + 1 s`, or a quantity given to an operator that can't take one.
+    Units,
+}
+
+/// The kind of value with a fractional part that each mode computes with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonInteger {
+    Float,
+    /// In `--rational` mode.
+    Fraction,
+    /// In `--decimal` mode.
+    Decimal,
+}
+
+impl fmt::Display for NonInteger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            NonInteger::Float => "floats",
+            NonInteger::Fraction => "fractions",
+            NonInteger::Decimal => "decimals",
+        })
+    }
+}
+
+impl ArithMode {
+    pub const NAMES: [&'static str; 4] = ["wrap", "saturate", "checked", "promote"];
+
+    /// This mode for integers `bits` wide: 32, the width of `Value::Int`,
+    /// or 128, the width of `Value::Wide`, which only fails on overflow and
+    /// so is `Promote`.
+    pub fn with_width(self, bits: u32) -> Result<ArithMode, String> {
+        match (bits, self) {
+            (32, mode) => Ok(mode),
+            (128, ArithMode::Checked | ArithMode::Promote) => Ok(ArithMode::Promote),
+            (128, _) => Err("128-bit integers fail on overflow, and cannot wrap or saturate".to_string()),
+            (bits, _) => Err(format!("integers are 32 or 128 bits wide, not {bits}")),
+        }
+    }
+
+    /// `v` as a value of this mode.
+    pub fn fit(self, v: i128) -> Result<Value, ArithError> {
+        match (self, i32::try_from(v)) {
+            (_, Ok(v)) => Ok(Value::Int(v)),
+            (ArithMode::Wrap, Err(_)) => Ok(Value::Int(v as i32)),
+            (ArithMode::Saturate, Err(_)) => Ok(Value::Int(v.clamp(i32::MIN as i128, i32::MAX as i128) as i32)),
+            (ArithMode::Checked, Err(_)) => Err(ArithError::Overflow),
+            (ArithMode::Promote, Err(_)) => Ok(Value::Wide(v)),
+        }
+    }
+
+    /// Brings a value computed elsewhere, like the result of a builtin
+    /// function, into the range of this mode.
+    pub fn narrow(self, v: Value) -> Result<Value, ArithError> {
+        match v {
+            Value::Wide(v) => self.fit(v),
+            v => Ok(v),
+        }
+    }
+
+    /// Applies `op` to integer operands.
+    pub fn apply(self, op: &NodeVal, args: &[i128]) -> Result<Value, ArithError> {
+        let args = match self {
+            ArithMode::Promote => args.to_vec(),
+            _ => args.iter().map(|&a| self.fit(a).map(Value::as_i128)).collect::<Result<_, _>>()?,
+        };
+
+        let exact = match (op, &args[..]) {
+            (NodeVal::AddWrap, [a, b]) => return Ok(Value::Int((*a as i32).wrapping_add(*b as i32))),
+            (NodeVal::MulWrap, [a, b]) => return Ok(Value::Int((*a as i32).wrapping_mul(*b as i32))),
+            (NodeVal::AddSat, [a, b]) => return ArithMode::Saturate.apply(&NodeVal::Add, &[*a, *b]),
+            (NodeVal::MulSat, [a, b]) => return ArithMode::Saturate.apply(&NodeVal::Mul, &[*a, *b]),
+            (op, [a, b]) if op.is_comparison() => return Ok(Value::Int(op.compare(a, b))),
+            (NodeVal::Add, [a]) => Some(*a),
+            (NodeVal::Sub, [a]) => a.checked_neg(),
+            (NodeVal::Add, [a, b]) => a.checked_add(*b),
+            (NodeVal::Sub, [a, b]) => a.checked_sub(*b),
+            (NodeVal::Mul, [a, b]) => a.checked_mul(*b),
+            (NodeVal::Div, [_, 0]) => return Err(ArithError::DivisionByZero),
+            (NodeVal::Div, [a, b]) => a.checked_div(*b),
+            // Like `1 / a^-b`, truncated.
+            (NodeVal::Exp, [a, b]) if *b < 0 => {
+                return match a {
+                    0 => Err(ArithError::DivisionByZero),
+                    1 => Ok(Value::Int(1)),
+                    -1 => Ok(Value::Int(if b % 2 == 0 { 1 } else { -1 })),
+                    _ => Ok(Value::Int(0)),
+                };
+            }
+            // Huge exponents only matter for bases 0 and ±1, and then only
+            // their parity does.
+            (NodeVal::Exp, [a, b]) => a.checked_pow(u32::try_from(*b).unwrap_or(u32::MAX - 1 + (b & 1) as u32)),
+            (NodeVal::Fac, [a]) if *a < 0 => return Err(ArithError::NegativeFactorial),
+            (NodeVal::Fac, [a]) => (2..=*a).try_fold(1i128, |p, n| p.checked_mul(n)),
+            (NodeVal::Mod, [_, 0]) => return Err(ArithError::DivisionByZero),
+            (NodeVal::Mod, [a, b]) => Some(a.wrapping_rem(*b)),
+            (NodeVal::Shl | NodeVal::Shr, [_, b]) if *b < 0 => return Err(ArithError::NegativeShift),
+            // Exact unless bits are shifted out, which is overflow.
+            (NodeVal::Shl, [0, _]) => Some(0),
+            (NodeVal::Shl, [a, b]) => u32::try_from(*b).ok().filter(|&b| b < 128).map(|b| a << b).filter(|r| r >> b == *a),
+            (NodeVal::Shr, [a, b]) => Some(a >> (*b).min(127)),
+            (NodeVal::BitAnd, [a, b]) => Some(a & b),
+            (NodeVal::BitOr, [a, b]) => Some(a | b),
+            (NodeVal::BitNot, [a]) => Some(!a),
+            _ => panic!("Operator {op} cannot take {} operands", args.len()),
+        };
+
+        match (exact, self) {
+            (Some(v), _) => self.fit(v),
+            // Only powers, factorials and left shifts of 32-bit operands
+            // outgrow 128 bits.
+            (None, ArithMode::Wrap) => Ok(Value::Int(match (op, &args[..]) {
+                (NodeVal::Exp, [a, b]) => (*a as i32).wrapping_pow(*b as u32),
+                // 34! and up are multiples of 2^32, as are shifts by 32 and up.
+                _ => 0,
+            })),
+            (None, ArithMode::Saturate) => Ok(Value::Int(match (op, &args[..]) {
+                (NodeVal::Exp, [a, b]) if *a < 0 && b % 2 == 1 => i32::MIN,
+                (NodeVal::Shl, [a, _]) if *a < 0 => i32::MIN,
+                _ => i32::MAX,
+            })),
+            (None, _) => Err(ArithError::Overflow),
+        }
+    }
+}
+
+impl FromStr for ArithMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "wrap" => Ok(ArithMode::Wrap),
+            "saturate" => Ok(ArithMode::Saturate),
+            "checked" => Ok(ArithMode::Checked),
+            "promote" => Ok(ArithMode::Promote),
+            _ => Err(format!("unknown arithmetic mode '{s}' (expected wrap, saturate, checked or promote)")),
+        }
+    }
+}
+
+impl fmt::Display for ArithError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArithError::Overflow => write!(f, "integer overflow"),
+            ArithError::DivisionByZero => write!(f, "division by zero"),
+            ArithError::NegativeFactorial => write!(f, "factorial of a negative number"),
+            ArithError::NegativeShift => write!(f, "negative shift count"),
+            ArithError::NotInteger(op, kind) => write!(f, "operator {op} takes integers, not {kind}"),
+            ArithError::Duration => write!(f, "operator cannot take a duration here"),
+            ArithError::Units => write!(f, "operands have incompatible units"),
+        }
+    }
+}
+
+impl core::error::Error for ArithError {}
+
+impl ArithError {
+    /// A stable name for the kind of error, for `--expect-error`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ArithError::Overflow => "arithmetic-overflow",
+            ArithError::DivisionByZero => "division-by-zero",
+            ArithError::NegativeFactorial => "negative-factorial",
+            ArithError::NegativeShift => "negative-shift",
+            ArithError::NotInteger(..) => "not-an-integer",
+            ArithError::Duration => "duration-operand",
+            ArithError::Units => "unit-mismatch",
+        }
+    }
+}
+
+#[test]
+fn tests() {
+    use ArithMode::*;
+    let max = i32::MAX as i128;
+    let apply = |mode: ArithMode, op: NodeVal, args: &[i128]| mode.apply(&op, args).map(|v| v.to_string());
+
+    assert_eq!(apply(Wrap, NodeVal::Add, &[max, 1]), Ok("-2147483648".into()));
+    assert_eq!(apply(Saturate, NodeVal::Add, &[max, 1]), Ok("2147483647".into()));
+    assert_eq!(apply(Checked, NodeVal::Add, &[max, 1]), Err(ArithError::Overflow));
+    assert_eq!(apply(Promote, NodeVal::Add, &[max, 1]), Ok("2147483648".into()));
+    assert_eq!(Promote.apply(&NodeVal::Sub, &[max + 1, 1]), Ok(Value::Int(i32::MAX)));
+
+    assert_eq!(apply(Wrap, NodeVal::Div, &[i32::MIN as i128, -1]), Ok("-2147483648".into()));
+    assert_eq!(apply(Saturate, NodeVal::Sub, &[i32::MIN as i128]), Ok("2147483647".into()));
+    assert_eq!(apply(Wrap, NodeVal::Div, &[1, 0]), Err(ArithError::DivisionByZero));
+    assert_eq!(apply(Promote, NodeVal::Exp, &[0, -1]), Err(ArithError::DivisionByZero));
+    assert_eq!(apply(Checked, NodeVal::Exp, &[-1, -3]), Ok("-1".into()));
+    assert_eq!(apply(Checked, NodeVal::Exp, &[2, -1]), Ok("0".into()));
+
+    assert_eq!(apply(Wrap, NodeVal::Exp, &[3, 100]), Ok(3i32.wrapping_pow(100).to_string()));
+    assert_eq!(apply(Saturate, NodeVal::Exp, &[-2, 99]), Ok("-2147483648".into()));
+    assert_eq!(apply(Promote, NodeVal::Exp, &[2, 100]), Ok((1i128 << 100).to_string()));
+    assert_eq!(apply(Promote, NodeVal::Exp, &[2, 127]), Err(ArithError::Overflow));
+    assert_eq!(apply(Promote, NodeVal::Exp, &[-1, 1 << 40]), Ok("1".into()));
+
+    assert_eq!(apply(Wrap, NodeVal::Fac, &[13]), Ok((6227020800i64 as i32).to_string()));
+    assert_eq!(apply(Wrap, NodeVal::Fac, &[40]), Ok("0".into()));
+    assert_eq!(apply(Saturate, NodeVal::Fac, &[40]), Ok("2147483647".into()));
+    assert_eq!(apply(Promote, NodeVal::Fac, &[25]), Ok("15511210043330985984000000".into()));
+    assert_eq!(apply(Checked, NodeVal::Fac, &[-1]), Err(ArithError::NegativeFactorial));
+
+    assert_eq!(apply(Checked, NodeVal::Mod, &[-7, 3]), Ok("-1".into()));
+    assert_eq!(apply(Wrap, NodeVal::Mod, &[i32::MIN as i128, -1]), Ok("0".into()));
+    assert_eq!(apply(Checked, NodeVal::Mod, &[1, 0]), Err(ArithError::DivisionByZero));
+    assert_eq!(apply(Checked, NodeVal::Shl, &[1, 30]), Ok("1073741824".into()));
+    assert_eq!(apply(Checked, NodeVal::Shl, &[1, 31]), Err(ArithError::Overflow));
+    assert_eq!(apply(Wrap, NodeVal::Shl, &[3, 31]), Ok("-2147483648".into()));
+    assert_eq!(apply(Wrap, NodeVal::Shl, &[3, 40]), Ok("0".into()));
+    assert_eq!(apply(Saturate, NodeVal::Shl, &[-3, 200]), Ok("-2147483648".into()));
+    assert_eq!(apply(Promote, NodeVal::Shl, &[1, 40]), Ok((1i128 << 40).to_string()));
+    assert_eq!(apply(Promote, NodeVal::Shl, &[0, 1 << 40]), Ok("0".into()));
+    assert_eq!(apply(Checked, NodeVal::Shr, &[-16, 2]), Ok("-4".into()));
+    assert_eq!(apply(Promote, NodeVal::Shr, &[-16, 1 << 40]), Ok("-1".into()));
+    assert_eq!(apply(Checked, NodeVal::Shr, &[1, -1]), Err(ArithError::NegativeShift));
+    assert_eq!(apply(Checked, NodeVal::BitAnd, &[12, 10]), Ok("8".into()));
+    assert_eq!(apply(Checked, NodeVal::BitOr, &[12, 3]), Ok("15".into()));
+    assert_eq!(apply(Checked, NodeVal::BitNot, &[0]), Ok("-1".into()));
+
+    // Explicit wrapping and saturating operators ignore the mode.
+    assert_eq!(apply(Checked, NodeVal::AddWrap, &[max, 1]), Ok("-2147483648".into()));
+    assert_eq!(apply(Promote, NodeVal::MulSat, &[max, 2]), Ok("2147483647".into()));
+
+    assert_eq!(Checked.narrow(Value::Wide(max + 1)), Err(ArithError::Overflow));
+    assert_eq!(Checked.with_width(128), Ok(Promote));
+    assert_eq!(Wrap.with_width(32), Ok(Wrap));
+    assert!(Saturate.with_width(128).is_err());
+    assert_eq!(Checked.with_width(64), Err("integers are 32 or 128 bits wide, not 64".into()));
+    assert_eq!("saturate".parse(), Ok(Saturate));
+    assert!("bogus".parse::<ArithMode>().is_err());
+}
@@ -0,0 +1,468 @@
+//! Trees as JSON and as S-expressions, for tools outside stoncc.
+//!
+//! In JSON, a leaf is `{"int": 1}`, `{"bool": true}`, `{"decimal": "1.5"}`,
+//! `{"sym": "x"}` or `{"placeholder": 1}`. An operator is `{"op": "+", "args": [...]}`, named
+//! as in the S-expression form, and a call is `{"call": "f", "args": [...]}`.
+//! What a recovering parse could not read is `{"error": {}}`, and `<error>`
+//! in the S-expression form.
+//!
+//! The S-expression form is what `Node` displays as. A symbol or function
+//! name that is not a plain name (ASCII letters, digits and `_`, not
+//! starting with a digit) is written between bars, `|like this|`, with `\|`
+//! and `\\` standing for `|` and `\`.
+//!
+//! A statement written on its own, as `--dump-ast=json` does, carries the
+//! version of the JSON form, `{"version": 2, "expr": ...}`. Statements
+//! written before there were versions have none and are version 0.
+//! `Stmt::from_json` reads every version up to [`SCHEMA_VERSION`],
+//! upgrading older ones, so stored trees keep working as `Node` grows.
+
+use alloc::borrow::Cow;
+use core::iter::Peekable;
+
+use crate::json::{self, Json};
+use crate::lexer::Span;
+use crate::parser::{LeafVal, Node, NodeVal, Stmt};
+use crate::prelude::*;
+
+/// Whether `name` can be written in an S-expression as it is.
+fn plain(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// `name` as an S-expression atom.
+pub(crate) fn quote(name: &str) -> Cow<'_, str> {
+    if plain(name) {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("|{}|", name.replace('\\', "\\\\").replace('|', "\\|")))
+    }
+}
+
+/// The operator written `name` applied to `children`, if it takes that
+/// many operands.
+fn operator(name: &str, children: Vec<Node>) -> Result<Node, String> {
+    let Some(v) = NodeVal::SHAPES.into_iter().map(|(v, _)| v).find(|v| v.to_string() == name) else {
+        return Err(format!("unknown operator '{name}'"));
+    };
+    if !NodeVal::SHAPES.contains(&(v.clone(), children.len())) {
+        return Err(format!("operator '{v}' cannot take {} operands", children.len()));
+    }
+    Ok(Node::op(v, children))
+}
+
+/// The version of the JSON form that `Stmt::to_versioned_json` writes.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Upgrades a statement from each version to the next, starting at 0.
+const MIGRATIONS: [fn(Json) -> Json; SCHEMA_VERSION as usize] = [
+    // 1 only added the version, which is not part of the statement.
+    |v| v,
+    // 2 added `{"bool": ...}` leaves, which older statements don't have.
+    |v| v,
+];
+
+fn decimal(s: &str) -> bool {
+    s.parse::<f64>().is_ok_and(f64::is_finite)
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Open,
+    Close,
+    /// A `|quoted|` name, unescaped.
+    Quoted(String),
+    Bare(&'a str),
+}
+
+fn tokens(s: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut out = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '(' => out.push(Token::Open),
+            ')' => out.push(Token::Close),
+            '|' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '|')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c @ ('|' | '\\'))) => name.push(c),
+                            _ => return Err(format!("invalid escape in the name at byte {i}")),
+                        },
+                        Some((_, c)) => name.push(c),
+                        None => return Err(format!("unterminated name at byte {i}")),
+                    }
+                }
+                out.push(Token::Quoted(name));
+            }
+            c if c.is_whitespace() => {}
+            _ => {
+                let mut end = s.len();
+                while let Some(&(j, c)) = chars.peek() {
+                    if c.is_whitespace() || "()|".contains(c) {
+                        end = j;
+                        break;
+                    }
+                    chars.next();
+                }
+                out.push(Token::Bare(&s[i..end]));
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn atom(t: &str) -> Result<LeafVal, String> {
+    if let Some(n) = t.strip_prefix('?') {
+        return n.parse().map(LeafVal::Placeholder).map_err(|_| format!("invalid placeholder '{t}'"));
+    }
+    if let Ok(b) = t.parse() {
+        return Ok(LeafVal::Bool(b));
+    }
+    if plain(t) {
+        return Ok(LeafVal::Sym(t.into()));
+    }
+    if let Ok(v) = t.parse() {
+        return Ok(LeafVal::Int(v));
+    }
+    if decimal(t) {
+        return Ok(LeafVal::Decimal(t.to_string()));
+    }
+    Err(format!("invalid atom '{t}'"))
+}
+
+fn sexp<'a>(tokens: &mut Peekable<impl Iterator<Item = Token<'a>>>) -> Result<Node, String> {
+    match tokens.next() {
+        None => Err("unexpected end of input".to_string()),
+        Some(Token::Close) => Err("unexpected ')'".to_string()),
+        Some(Token::Quoted(name)) => Ok(Node::leaf(LeafVal::Sym(name.into()))),
+        Some(Token::Bare(t)) => atom(t).map(Node::leaf),
+        Some(Token::Open) => {
+            let head = tokens.next();
+            let mut children = Vec::new();
+            while tokens.next_if_eq(&Token::Close).is_none() {
+                children.push(sexp(tokens)?);
+            }
+            match head {
+                Some(Token::Quoted(name)) => Ok(Node::op(NodeVal::Call(name), children)),
+                Some(Token::Bare(name)) if plain(name) => Ok(Node::op(NodeVal::Call(name.to_string()), children)),
+                Some(Token::Bare(name)) => operator(name, children),
+                _ => Err("expected an operator or function name after '('".to_string()),
+            }
+        }
+    }
+}
+
+fn from_json(v: &Json) -> Result<Node, String> {
+    let args = || match v.get("args") {
+        Some(Json::Array(args)) => args.iter().map(from_json).collect::<Result<Vec<_>, _>>(),
+        _ => Err("expected \"args\": [...] in an operator or call".to_string()),
+    };
+    let leaf = |v: LeafVal| Ok(Node::leaf(v));
+
+    if let Some(Json::Bool(b)) = v.get("bool") {
+        return leaf(LeafVal::Bool(*b));
+    }
+    match (v.get("int"), v.get("decimal"), v.get("sym"), v.get("placeholder")) {
+        (Some(Json::Number(n)), ..) => n.parse().map(LeafVal::Int).map(Node::leaf).map_err(|_| format!("invalid int {n}")),
+        (_, Some(Json::String(s)), ..) if decimal(s) => leaf(LeafVal::Decimal(s.clone())),
+        (_, _, Some(Json::String(s)), _) => leaf(LeafVal::Sym(s.as_str().into())),
+        (.., Some(Json::Number(n))) => n.parse().map(LeafVal::Placeholder).map(Node::leaf).map_err(|_| format!("invalid placeholder {n}")),
+        _ => match (v.get("op"), v.get("call"), v.get("error")) {
+            (Some(Json::String(name)), ..) => operator(name, args()?),
+            (_, Some(Json::String(name)), _) => Ok(Node::op(NodeVal::Call(name.clone()), args()?)),
+            (.., Some(_)) => Ok(Node::Error(Span::default())),
+            _ => Err("expected a node: an object with \"int\", \"bool\", \"decimal\", \"sym\", \"placeholder\", \"op\" or \"call\"".to_string()),
+        },
+    }
+}
+
+fn node(v: &Json, key: &str) -> Result<Node, String> {
+    from_json(v.get(key).ok_or_else(|| format!("expected \"{key}\" in a statement"))?)
+}
+
+fn stmt(v: &Json, key: &str) -> Result<Box<Stmt>, String> {
+    stmt_from_json(v.get(key).ok_or_else(|| format!("expected \"{key}\" in a statement"))?).map(Box::new)
+}
+
+fn name(v: &Json) -> Result<String, String> {
+    match v {
+        Json::String(s) => Ok(s.clone()),
+        _ => Err("expected a name".to_string()),
+    }
+}
+
+fn stmt_from_json(v: &Json) -> Result<Stmt, String> {
+    let span = Span::default();
+    if v.get("expr").is_some() {
+        Ok(Stmt::Expr(node(v, "expr")?))
+    } else if let Some(n) = v.get("assign") {
+        Ok(Stmt::Assign { name: name(n)?, value: node(v, "value")?, span })
+    } else if let Some(n) = v.get("def") {
+        let params = match v.get("params") {
+            Some(Json::Array(params)) => params.iter().map(name).collect::<Result<_, _>>()?,
+            _ => return Err("expected \"params\": [...] in a definition".to_string()),
+        };
+        Ok(Stmt::Def { name: name(n)?, params, body: node(v, "body")?, span })
+    } else if let Some(stmts) = v.get("block") {
+        let Json::Array(stmts) = stmts else { return Err("expected \"block\": [...]".to_string()) };
+        Ok(Stmt::Block { stmts: stmts.iter().map(stmt_from_json).collect::<Result<_, _>>()?, span })
+    } else if v.get("if").is_some() {
+        let els = v.get("else").map(|_| stmt(v, "else")).transpose()?;
+        Ok(Stmt::If { cond: node(v, "if")?, then: stmt(v, "then")?, els, span })
+    } else if v.get("while").is_some() {
+        Ok(Stmt::While { cond: node(v, "while")?, body: stmt(v, "body")?, span })
+    } else {
+        Err("expected a statement: an object with \"expr\", \"assign\", \"def\", \"block\", \"if\" or \"while\"".to_string())
+    }
+}
+
+/// A pass over a tree, driven by `Node::visit`. `enter` sees each node
+/// before its operands and `leave` sees it after them.
+pub trait Visitor {
+    fn enter(&mut self, _node: &Node) {}
+    fn leave(&mut self, _node: &Node) {}
+}
+
+/// A rewrite of a tree, driven by `Node::fold`, from the leaves up. By
+/// default every node is kept as it is, so a folder only says what it
+/// changes.
+pub trait Folder {
+    type Error;
+
+    /// What replaces a leaf.
+    fn fold_leaf(&mut self, v: &LeafVal, span: Span) -> Result<Node, Self::Error> {
+        Ok(Node::Leaf(v.clone(), span))
+    }
+
+    /// What replaces an operator or call, given its operands already
+    /// folded.
+    fn fold_node(&mut self, v: &NodeVal, children: Vec<Node>, span: Span) -> Result<Node, Self::Error> {
+        Ok(Node::Node { v: v.clone(), children, span })
+    }
+}
+
+impl Node {
+    /// Walks the tree depth-first, operands left to right. It keeps a stack
+    /// rather than recursing, so deep trees take memory rather than stack.
+    pub fn visit(&self, visitor: &mut impl Visitor) {
+        enum Step<'a> {
+            Enter(&'a Node),
+            Leave(&'a Node),
+        }
+        let mut steps = vec![Step::Enter(self)];
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Enter(node) => {
+                    visitor.enter(node);
+                    steps.push(Step::Leave(node));
+                    if let Node::Node { children, .. } = node {
+                        steps.extend(children.iter().rev().map(Step::Enter));
+                    }
+                }
+                Step::Leave(node) => visitor.leave(node),
+            }
+        }
+    }
+
+    /// The tree as `folder` rewrites it, or its first error. Like `visit`,
+    /// it keeps a stack rather than recursing.
+    pub fn fold<F: Folder>(&self, folder: &mut F) -> Result<Node, F::Error> {
+        let mut tasks = vec![(self, false)];
+        let mut done = Vec::new();
+        while let Some((node, ready)) = tasks.pop() {
+            match node {
+                Node::Leaf(v, span) => done.push(folder.fold_leaf(v, *span)?),
+                Node::Error(span) => done.push(Node::Error(*span)),
+                Node::Node { v, children, span } if ready => {
+                    let children = done.split_off(done.len() - children.len());
+                    done.push(folder.fold_node(v, children, *span)?);
+                }
+                Node::Node { children, .. } => {
+                    tasks.push((node, true));
+                    tasks.extend(children.iter().rev().map(|c| (c, false)));
+                }
+            }
+        }
+        Ok(done.pop().unwrap())
+    }
+
+    /// The tree as JSON, in the form described in the module docs.
+    pub fn to_json(&self) -> String {
+        match self {
+            Node::Leaf(v, _) => match v {
+                LeafVal::Int(v) => format!("{{\"int\": {v}}}"),
+                LeafVal::Bool(b) => format!("{{\"bool\": {b}}}"),
+                LeafVal::Decimal(v) => format!("{{\"decimal\": {}}}", json::string(v)),
+                LeafVal::Sym(v) => format!("{{\"sym\": {}}}", json::string(v)),
+                LeafVal::Placeholder(n) => format!("{{\"placeholder\": {n}}}"),
+            },
+            Node::Error(_) => "{\"error\": {}}".to_string(),
+            Node::Node { v, children, .. } => {
+                let kind = if matches!(v, NodeVal::Call(_)) { "call" } else { "op" };
+                let args = json::array(children.iter().map(Node::to_json));
+                format!("{{\"{kind}\": {}, \"args\": {args}}}", json::string(&v.to_string()))
+            }
+        }
+    }
+
+    /// Reads a tree written by `to_json`. The tree has no spans.
+    pub fn from_json(s: &str) -> Result<Node, String> {
+        from_json(&json::parse(s)?)
+    }
+
+    /// Reads a tree in the S-expression form it displays as. The tree has
+    /// no spans.
+    pub fn from_sexp(s: &str) -> Result<Node, String> {
+        let mut tokens = tokens(s)?.into_iter().peekable();
+        let node = sexp(&mut tokens)?;
+        match tokens.next() {
+            None => Ok(node),
+            Some(t) => Err(format!("unexpected {t:?} after the expression")),
+        }
+    }
+}
+
+impl Stmt {
+    /// The statement as JSON: `{"expr": NODE}`, `{"assign": NAME, "value":
+    /// NODE}`, `{"def": NAME, "params": [...], "body": NODE}`, `{"block":
+    /// [...]}`, `{"if": NODE, "then": STMT, "else": STMT}` (with `else`
+    /// optional) or `{"while": NODE, "body": STMT}`.
+    pub fn to_json(&self) -> String {
+        match self {
+            Stmt::Expr(e) => format!("{{\"expr\": {}}}", e.to_json()),
+            Stmt::Assign { name, value, .. } => {
+                format!("{{\"assign\": {}, \"value\": {}}}", json::string(name), value.to_json())
+            }
+            Stmt::Def { name, params, body, .. } => {
+                let params = json::array(params.iter().map(|p| json::string(p)));
+                format!("{{\"def\": {}, \"params\": {params}, \"body\": {}}}", json::string(name), body.to_json())
+            }
+            Stmt::Block { stmts, .. } => format!("{{\"block\": {}}}", json::array(stmts.iter().map(Stmt::to_json))),
+            Stmt::If { cond, then, els, .. } => {
+                let els = els.as_ref().map(|s| format!(", \"else\": {}", s.to_json())).unwrap_or_default();
+                format!("{{\"if\": {}, \"then\": {}{els}}}", cond.to_json(), then.to_json())
+            }
+            Stmt::While { cond, body, .. } => format!("{{\"while\": {}, \"body\": {}}}", cond.to_json(), body.to_json()),
+        }
+    }
+
+    /// `to_json` with the version of the form, for a statement stored on
+    /// its own.
+    pub fn to_versioned_json(&self) -> String {
+        format!("{{\"version\": {SCHEMA_VERSION}, {}", &self.to_json()[1..])
+    }
+
+    /// Reads a statement written by `to_versioned_json` in this or an
+    /// earlier version, or by `to_json`, which is version 0. The statement
+    /// has no spans.
+    pub fn from_json(s: &str) -> Result<Stmt, String> {
+        let mut v = json::parse(s)?;
+        let version = match v.get("version") {
+            None => 0,
+            Some(Json::Number(n)) => n.parse::<u32>().map_err(|_| format!("invalid version {n}"))?,
+            Some(_) => return Err("expected a number as the version".to_string()),
+        };
+        if version > SCHEMA_VERSION {
+            return Err(format!("version {version} is newer than this stoncc reads ({SCHEMA_VERSION})"));
+        }
+        for migrate in &MIGRATIONS[version as usize..] {
+            v = migrate(v);
+        }
+        stmt_from_json(&v)
+    }
+}
+
+#[test]
+fn tests() {
+    use crate::parser::expr;
+
+    for s in ["x + -2 * f(y, 1.5e3)!", "a < b ? ?1 : max()", "2147483647 +% 1 ^ 0.25", "true && false < x"] {
+        let e = expr(s.as_bytes()).unwrap();
+        assert_eq!(Node::from_sexp(&e.to_string()), Ok(e.clone()), "{s}");
+        assert_eq!(Node::from_json(&e.to_json()), Ok(e), "{s}");
+    }
+    assert_eq!(expr(b"true - 1").unwrap().to_string(), "(- true 1)");
+    assert_eq!(expr(b"true - 1").unwrap().to_json(), r#"{"op": "-", "args": [{"bool": true}, {"int": 1}]}"#);
+    assert_eq!(expr(b"-x + 1").unwrap().to_json(), r#"{"op": "+", "args": [{"op": "-", "args": [{"sym": "x"}]}, {"int": 1}]}"#);
+
+    // Hand-built trees can hold any name, and negative integers.
+    let odd = Node::op(
+        NodeVal::Call("a b|c".into()),
+        vec![Node::leaf(LeafVal::Sym("\\".into())), Node::leaf(LeafVal::Sym("1x".into())), Node::leaf(LeafVal::Int(-3))],
+    );
+    assert_eq!(odd.to_string(), r"(|a b\|c| |\\| |1x| -3)");
+    assert_eq!(Node::from_sexp(&odd.to_string()), Ok(odd.clone()));
+    assert_eq!(Node::from_json(&odd.to_json()), Ok(odd));
+    assert_eq!(Node::from_sexp("(|+| 1)"), Ok(Node::op(NodeVal::Call("+".into()), vec![Node::leaf(LeafVal::Int(1))])));
+
+    assert_eq!(Node::from_sexp("(! 1 2)"), Err("operator '!' cannot take 2 operands".into()));
+    assert_eq!(Node::from_sexp("(** 1 2)"), Err("unknown operator '**'".into()));
+    assert_eq!(Node::from_sexp("(+ 1 2"), Err("unexpected end of input".into()));
+    assert_eq!(Node::from_sexp("|x"), Err("unterminated name at byte 0".into()));
+    assert!(Node::from_sexp("1 2").is_err());
+    assert!(Node::from_json(r#"{"decimal": "abc"}"#).is_err());
+    assert!(Node::from_json(r#"{"op": "+"}"#).is_err());
+    let broken = Node::op(NodeVal::Sub, vec![Node::Error(Span::default())]);
+    assert_eq!((broken.to_string(), broken.to_json()), ("(- <error>)".to_string(), r#"{"op": "-", "args": [{"error": {}}]}"#.to_string()));
+    assert_eq!(Node::from_json(&broken.to_json()), Ok(broken));
+
+    let program = crate::parser::program(b"f(x) = x\nif (1) { y = 2 } else 3").0.unwrap();
+    let json: Vec<String> = program.stmts.iter().map(Stmt::to_json).collect();
+    assert_eq!(json[0], r#"{"def": "f", "params": ["x"], "body": {"sym": "x"}}"#);
+    assert_eq!(json[1], r#"{"if": {"int": 1}, "then": {"block": [{"assign": "y", "value": {"int": 2}}]}, "else": {"expr": {"int": 3}}}"#);
+
+    // Stored statements read back whether or not they have a version.
+    let program = crate::parser::program(b"f(x, y) = x * y\nwhile (a) { b = 1; if (b) c }\nd + 1").0.unwrap();
+    for stmt in &program.stmts {
+        let versioned = stmt.to_versioned_json();
+        assert!(versioned.starts_with(r#"{"version": 2, "#), "{versioned}");
+        assert_eq!(Stmt::from_json(&versioned).map(|s| s.to_json()), Ok(stmt.to_json()));
+        assert_eq!(Stmt::from_json(&stmt.to_json()).map(|s| s.to_json()), Ok(stmt.to_json()));
+    }
+    assert_eq!(Stmt::from_json(r#"{"version": 3, "expr": {"int": 1}}"#), Err("version 3 is newer than this stoncc reads (2)".into()));
+    assert!(Stmt::from_json(r#"{"def": "f", "body": {"int": 1}}"#).is_err());
+    assert!(Stmt::from_json(r#"{"int": 1}"#).is_err());
+
+    // Visitors and folders see nodes in order, down to the deepest trees
+    // the parser makes.
+    struct Order(String);
+    impl Visitor for Order {
+        fn enter(&mut self, node: &Node) {
+            self.0 += &format!("<{}", node.to_string().split(' ').next().unwrap().trim_start_matches('('));
+        }
+        fn leave(&mut self, _: &Node) {
+            self.0 += ">";
+        }
+    }
+    let mut order = Order(String::new());
+    expr(b"-a * f(b, 1)").unwrap().visit(&mut order);
+    assert_eq!(order.0, "<*<-<a>><f<b><1>>>");
+
+    struct Rename;
+    impl Folder for Rename {
+        type Error = String;
+        fn fold_leaf(&mut self, v: &LeafVal, span: Span) -> Result<Node, String> {
+            match v {
+                LeafVal::Sym(s) if s == "bad" => Err("bad".into()),
+                LeafVal::Sym(s) => Ok(Node::Leaf(LeafVal::Sym(s.to_uppercase().into()), span)),
+                v => Ok(Node::Leaf(v.clone(), span)),
+            }
+        }
+    }
+    assert_eq!(expr(b"a + g(b) * 2").unwrap().fold(&mut Rename).unwrap().to_string(), "(+ A (* (g B) 2))");
+    assert_eq!(expr(b"a + bad").unwrap().fold(&mut Rename), Err("bad".into()));
+    struct Count(usize);
+    impl Visitor for Count {
+        fn leave(&mut self, _: &Node) {
+            self.0 += 1;
+        }
+    }
+    let deep = expr(format!("{}x", "-".repeat(crate::MAX_DEPTH - 1)).as_bytes()).unwrap();
+    let mut count = Count(0);
+    deep.fold(&mut Rename).unwrap().visit(&mut count);
+    assert_eq!(count.0, crate::MAX_DEPTH);
+}
@@ -0,0 +1,145 @@
+//! Audit logs: a JSON line for every evaluation, saying what was evaluated
+//! (by the SHA-256 of each input), with which variables, what it came to
+//! and how long it took, so that a number computed long ago can be traced
+//! back to where it came from. `stoncc --audit-log=FILE` appends one for
+//! each run, and a [`Session`](crate::Session) built with
+//! [`SessionBuilder::audit`](crate::SessionBuilder::audit) makes one for
+//! each program.
+//!
+//! ```text
+//! {"time_ms": 1767225600000, "inputs": [{"name": "prog.stc", "sha256": "9f86…"}], "vars": {"rate": "3"}, "result": "42", "duration_us": 87}
+//! {"time_ms": 1767225600012, "inputs": [{"name": "<expr>", "sha256": "…"}], "vars": {}, "error": {"code": "division-by-zero", "message": "division by zero"}, "duration_us": 5}
+//! ```
+//!
+//! A run whose last statement has no value has `"result": null`.
+
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::json;
+
+/// One evaluation, to log.
+pub struct Entry<'a> {
+    /// When it started.
+    pub time: SystemTime,
+    /// The name and text of each input.
+    pub inputs: Vec<(String, &'a [u8])>,
+    /// The variables given to it, by name, as they print.
+    pub vars: Vec<(String, String)>,
+    /// The value of its last statement that has one, as it printed, or the
+    /// code and message of the error that stopped it.
+    pub outcome: Result<Option<String>, (String, String)>,
+    pub duration: Duration,
+}
+
+impl Entry<'_> {
+    /// The entry as one line of JSON, without the line break.
+    pub fn to_json(&self) -> String {
+        let time = self.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let inputs = self.inputs.iter().map(|(name, text)| {
+            let hash = sha256(text).iter().fold(String::new(), |mut hex, b| {
+                let _ = write!(hex, "{b:02x}");
+                hex
+            });
+            format!("{{\"name\": {}, \"sha256\": \"{hash}\"}}", json::string(name))
+        });
+        let vars = self.vars.iter().map(|(name, v)| format!("{}: {}", json::string(name), json::string(v)));
+        let outcome = match &self.outcome {
+            Ok(Some(v)) => format!("\"result\": {}", json::string(v)),
+            Ok(None) => "\"result\": null".to_string(),
+            Err((code, message)) => format!("\"error\": {{\"code\": {}, \"message\": {}}}", json::string(code), json::string(message)),
+        };
+        format!(
+            "{{\"time_ms\": {time}, \"inputs\": {}, \"vars\": {{{}}}, {outcome}, \"duration_us\": {}}}",
+            json::array(inputs),
+            vars.collect::<Vec<_>>().join(", "),
+            self.duration.as_micros()
+        )
+    }
+}
+
+/// Round constants of SHA-256: the fractional parts of the cube roots of
+/// the first 64 primes.
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// The SHA-256 digest of `bytes`.
+pub fn sha256(bytes: &[u8]) -> [u8; 32] {
+    let mut h: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+    // The message, a 1 bit, zeros to 56 bytes short of a whole block, and
+    // the length in bits.
+    let mut padded = bytes.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&(bytes.len() as u64 * 8).to_be_bytes());
+
+    for block in padded.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            (hh, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0; 32];
+    for (out, h) in digest.chunks_exact_mut(4).zip(h) {
+        out.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+#[test]
+fn tests() {
+    let hex = |bytes: &[u8]| sha256(bytes).iter().map(|b| format!("{b:02x}")).collect::<String>();
+    assert_eq!(hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    assert_eq!(hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    // Long enough that the length spills into a block of its own.
+    assert_eq!(hex(&[b'a'; 56]), "b35439a4ac6f0948b6d6f9e3c6af0f5f590ce20f1bde7090ef7970686ec6738a");
+    assert_eq!(hex(&[b'x'; 200]), "aa20c23e3201834050679e1d88941b9a6fed0557c9a705cb2c315e2e63fd486d");
+
+    let entry = Entry {
+        time: UNIX_EPOCH + Duration::from_millis(1500),
+        inputs: vec![("p.stc".to_string(), b"abc")],
+        vars: vec![("x".to_string(), "2".to_string())],
+        outcome: Ok(Some("4".to_string())),
+        duration: Duration::from_micros(12),
+    };
+    assert_eq!(
+        entry.to_json(),
+        "{\"time_ms\": 1500, \"inputs\": [{\"name\": \"p.stc\", \"sha256\": \"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad\"}], \
+         \"vars\": {\"x\": \"2\"}, \"result\": \"4\", \"duration_us\": 12}"
+    );
+    let failed = Entry { vars: vec![], outcome: Err(("division-by-zero".to_string(), "division by zero".to_string())), ..entry };
+    let line = failed.to_json();
+    assert!(line.contains("\"vars\": {}, \"error\": {\"code\": \"division-by-zero\", \"message\": \"division by zero\"}, "), "{line}");
+    assert!(json::parse(&line).is_ok());
+}
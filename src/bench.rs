@@ -0,0 +1,263 @@
+//! `stoncc bench`: runs a program many times on each evaluation backend,
+//! reports how fast each one is, and checks that those with the same
+//! semantics give the same result.
+
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use stoncc::limits::Meter;
+use stoncc::*;
+
+use crate::config::Config;
+use crate::{diagnostic, input};
+
+/// The backends built in.
+pub const BACKENDS: &[&str] = &[
+    "eval",
+    "arena",
+    "ir",
+    "vm",
+    #[cfg(feature = "decimal")]
+    "decimal",
+    #[cfg(feature = "bigint")]
+    "bigint",
+];
+/// The backends of 32-bit integers and floats, which must agree. Decimal
+/// and arbitrary-precision integers give other results by design.
+const CHECKED: [&str; 4] = ["eval", "arena", "ir", "vm"];
+
+/// How one backend did: its time for all iterations, the operators it
+/// applied, and the value of the program's last statement.
+struct Run {
+    time: Duration,
+    ops: u64,
+    result: Result<String, String>,
+}
+
+/// Runs `program` once from scratch, returning the value of its last
+/// statement that has one.
+fn once<V: Truth>(
+    program: &Program,
+    mut eval: impl FnMut(&Node, &Env<V>, &Builtins) -> Result<V, EvalError>,
+) -> Result<Option<V>, EvalError> {
+    let (mut env, mut builtins) = (Env::new(), Builtins::default());
+    let mut last = None;
+    for stmt in &program.stmts {
+        last = exec(stmt, &mut env, &mut builtins, &mut eval)?.or(last);
+    }
+    Ok(last)
+}
+
+fn bench(program: &Program, backend: &str, iters: u64) -> Run {
+    let mut meter = Meter::new(Default::default());
+    let ops = meter.ops();
+    let start = Instant::now();
+
+    let result = 'run: {
+        // Lowered once, like a VM loading its bytecode.
+        let mut code = HashMap::new();
+        let mut compiled = HashMap::new();
+        let mut pools = HashMap::new();
+        let mut last = String::new();
+        for _ in 0..iters {
+            let v = match backend {
+                "eval" => once(program, |ast, env, b| eval_with(ast, env, b, &mut meter)).map(|v| v.map(|v| v.to_string())),
+                "arena" => once(program, |ast, env, b| {
+                    let pool = pools.entry(ast as *const Node).or_insert_with(|| arena::Ast::from(ast));
+                    arena::eval_with(pool, env, b, &mut meter)
+                })
+                .map(|v| v.map(|v| v.to_string())),
+                "ir" => once(program, |ast, env, b| {
+                    let code = code.entry(ast as *const Node).or_insert_with(|| ir::lower(ast));
+                    ir::eval(code, env, b, &mut meter)
+                })
+                .map(|v| v.map(|v| v.to_string())),
+                "vm" => once(program, |ast, env, b| {
+                    let c = compiled.entry(ast as *const Node).or_insert_with(|| vm::CompiledExpr::with_builtins(ast, Builtins::empty()));
+                    c.eval_with(env, b, &mut meter)
+                })
+                .map(|v| v.map(|v| v.to_string())),
+                #[cfg(feature = "decimal")]
+                "decimal" => {
+                    let scale = decimal::DEFAULT_SCALE;
+                    once(program, |ast, env, b| decimal::eval(ast, env, b, scale, &mut meter))
+                        .map(|v| v.map(|v| decimal::format(v, scale)))
+                }
+                // Only for `stoncc conformance`: its fractions don't compare
+                // with the other backends' results.
+                #[cfg(feature = "rational")]
+                "rational" => once(program, |ast, env, b| rational::eval(ast, env, b, &mut meter)).map(|v| v.map(|v| v.to_string())),
+                #[cfg(feature = "bigint")]
+                "bigint" => once(program, |ast, env, b| bigint::eval(ast, env, b, &mut meter)).map(|v| v.map(|v| v.to_string())),
+                _ => unreachable!("{backend} is not a backend built in"),
+            };
+            last = match v {
+                Ok(v) => v.unwrap_or_default(),
+                Err(e) => break 'run Err(e.to_string()),
+            };
+        }
+        Ok(last)
+    };
+    Run { time: start.elapsed(), ops: ops.load(Ordering::Relaxed), result }
+}
+
+/// The value of the last statement of `program` on `backend`, or its
+/// error, from one run.
+pub fn run(program: &Program, backend: &str) -> Result<String, String> {
+    bench(program, backend, 1).result
+}
+
+/// Whether two results denote the same number. Floats and decimals are
+/// rounded differently, so they only need to be close.
+pub fn agree(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(x), Ok(y)) => (x - y).abs() <= 1e-9 * x.abs().max(y.abs()),
+        _ => false,
+    }
+}
+
+fn duration(d: Duration) -> String {
+    match d.as_nanos() {
+        n if n < 1_000 => format!("{n}ns"),
+        n if n < 1_000_000 => format!("{:.1}µs", n as f64 / 1e3),
+        n if n < 1_000_000_000 => format!("{:.1}ms", n as f64 / 1e6),
+        n => format!("{:.2}s", n as f64 / 1e9),
+    }
+}
+
+fn rate(per_sec: f64) -> String {
+    match per_sec {
+        r if r >= 1e9 => format!("{:.1}G", r / 1e9),
+        r if r >= 1e6 => format!("{:.1}M", r / 1e6),
+        r if r >= 1e3 => format!("{:.1}k", r / 1e3),
+        r => format!("{r:.0}"),
+    }
+}
+
+/// How fast `len` bytes were parsed, into `Node`s and maybe into an arena.
+fn parse_report(len: usize, nodes: Duration, arena: Option<Duration>) -> String {
+    let speed = |d: Duration| format!("{} ({}B/s)", duration(d), rate(len as f64 / d.as_secs_f64().max(1e-9)));
+    let mut out = format!("parsed {}B in {}", rate(len as f64), speed(nodes));
+    if let Some(d) = arena {
+        out += &format!(", or in {} into an arena", speed(d));
+    }
+    out
+}
+
+fn usage() -> ! {
+    eprintln!("usage: stoncc bench FILE [--iters N] [--backend eval|arena|ir|vm|decimal|bigint|all]");
+    std::process::exit(2);
+}
+
+/// `stoncc bench FILE [--iters N] [--backend B]`: times each backend over
+/// `N` runs of the program and fails if any two of [`CHECKED`] give
+/// different results, or one of them an error and another not.
+pub fn main(args: &[String]) {
+    let mut iters = 100;
+    let mut backends = BACKENDS.to_vec();
+    let mut path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--iters" => iters = args.next().and_then(|n| n.parse().ok()).filter(|&n| n > 0).unwrap_or_else(|| usage()),
+            "--backend" => {
+                backends = match args.next().map(String::as_str) {
+                    Some("all") => BACKENDS.to_vec(),
+                    Some(b) if BACKENDS.contains(&b) => vec![b],
+                    _ => usage(),
+                }
+            }
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.as_str()),
+            _ => usage(),
+        }
+    }
+    let Some(path) = path else { usage() };
+
+    let s = input::read(path, Config::load().encoding.unwrap_or_default());
+    let start = Instant::now();
+    let program = parser::program(&s).0.unwrap_or_else(|e| {
+        eprintln!("{}", diagnostic::render("error", &e.kind.to_string(), path, &s, e.span));
+        std::process::exit(1);
+    });
+    let parsed = start.elapsed();
+    // A file of one expression can also be parsed into an arena.
+    let start = Instant::now();
+    let pooled = arena::expr(&s).is_ok().then(|| start.elapsed());
+    println!("{}", parse_report(s.len(), parsed, pooled));
+
+    let runs: Vec<(&str, Run)> = backends.iter().map(|&b| (b, bench(&program, b, iters))).collect();
+
+    println!("{:<9}{:>12}{:>12}{:>12}  result", "backend", "total", "per run", "ops/s");
+    for (backend, run) in &runs {
+        let per_run = run.time.div_f64(iters as f64);
+        let ops = rate(run.ops as f64 / run.time.as_secs_f64().max(1e-9));
+        let result = match &run.result {
+            Ok(v) => v.clone(),
+            Err(e) => format!("error: {e}"),
+        };
+        println!("{backend:<9}{:>12}{:>12}{ops:>12}  {result}", duration(run.time), duration(per_run));
+    }
+
+    if let Some((a, b)) = disagreement(&runs) {
+        println!("{a}, but {b}");
+        std::process::exit(1);
+    }
+}
+
+/// The first of the runs of [`CHECKED`] backends that gives something else
+/// than the first of them, each as `backend gives result`.
+fn disagreement(runs: &[(&str, Run)]) -> Option<(String, String)> {
+    let gives = |(b, r): &&(&str, Run)| match &r.result {
+        Ok(v) => format!("{b} gives {v}"),
+        Err(e) => format!("{b} gives error: {e}"),
+    };
+    let mut checked = runs.iter().filter(|(b, _)| CHECKED.contains(b));
+    let first = checked.next()?;
+    let same = |r: &Run| match (&first.1.result, &r.result) {
+        (Ok(a), Ok(b)) => agree(a, b),
+        (a, b) => a.is_err() && b.is_err(),
+    };
+    checked.find(|(_, r)| !same(r)).map(|other| (gives(&first), gives(&other)))
+}
+
+#[test]
+fn tests() {
+    assert!(agree("42", "42"));
+    assert!(agree("0.30000000000000004", "0.3000000000"));
+    assert!(agree("-2147483648", "-2147483648.0000000000"));
+    assert!(!agree("0", "4294967296"));
+    assert!(!agree("", "1"));
+
+    assert_eq!(duration(Duration::from_nanos(950)), "950ns");
+    assert_eq!(duration(Duration::from_micros(1500)), "1.5ms");
+    assert_eq!(duration(Duration::from_millis(2500)), "2.50s");
+    assert_eq!(rate(2.5e6), "2.5M");
+    assert_eq!(
+        parse_report(2_000_000, Duration::from_millis(100), Some(Duration::from_millis(50))),
+        "parsed 2.0MB in 100.0ms (20.0MB/s), or in 50.0ms (40.0MB/s) into an arena"
+    );
+    assert_eq!(parse_report(512, Duration::from_micros(8), None), "parsed 512B in 8.0µs (64.0MB/s)");
+
+    let program = parser::program(b"f(n) = n < 2 ? n : f(n - 1) + f(n - 2)\nx = f(10)\nwhile (x < 50) x = x + 1\nx * 4 - 3").0.unwrap();
+    for backend in BACKENDS {
+        let run = bench(&program, backend, 3);
+        assert!(agree(run.result.as_deref().unwrap(), "217"), "{backend}");
+        assert!(run.ops > 0);
+    }
+    #[cfg(feature = "bigint")]
+    assert!(bench(&parser::program(b"1.5").0.unwrap(), "bigint", 1).result.is_err());
+
+    // Decimal isn't held to the others' integer division, but an error
+    // where another backend has a value is a disagreement.
+    let program = parser::program(b"7 / 2").0.unwrap();
+    let runs: Vec<(&str, Run)> = BACKENDS.iter().map(|&b| (b, bench(&program, b, 1))).collect();
+    assert_eq!(disagreement(&runs), None);
+    let mut runs = runs;
+    runs[2].1.result = Err("division by zero".into());
+    assert_eq!(disagreement(&runs), Some(("eval gives 3".into(), "ir gives error: division by zero".into())));
+}
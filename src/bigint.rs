@@ -0,0 +1,503 @@
+//! Arbitrary-precision integer evaluation (`--bigint`). Integers never
+//! overflow, so `30!` and `2 ^ 100` are exact. A value is a sign and a
+//! magnitude in base-2^32 limbs, least significant first.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+use crate::arith::ArithError;
+use crate::eval::{walk, Numbers};
+use crate::lexer::Span;
+use crate::limits::{LimitExceeded, Meter};
+use crate::parser::{LeafVal, Node, NodeVal, Nodes, Value};
+use crate::{Builtins, Env, EvalError};
+
+/// Widest result, in bits, that an operator may produce. Beyond it, a
+/// product takes long enough to look like a hang.
+pub const MAX_BITS: u64 = 1 << 24;
+
+/// Fails with an overflow at `span` if a result of `bits` bits would be
+/// wider than `MAX_BITS`, before the work of computing it.
+pub(crate) fn fits(bits: u64, span: Span) -> Result<(), EvalError> {
+    if bits > MAX_BITS {
+        return Err(EvalError::Arith(ArithError::Overflow, span));
+    }
+    Ok(())
+}
+
+/// Bits in `n!`, by Stirling's approximation of its logarithm, which is
+/// never more and at most one less.
+fn factorial_bits(n: u32) -> u64 {
+    if n < 2 {
+        return 1;
+    }
+    let n = n as f64;
+    let ln = n * n.ln() - n + 0.5 * (2.0 * std::f64::consts::PI * n).ln();
+    (ln / std::f64::consts::LN_2) as u64 + 1
+}
+
+/// `n!` for the factorial operator at `span`. How wide it is is known
+/// beforehand, so one too wide for `MAX_BITS` or the meter is refused
+/// rather than computed.
+pub(crate) fn factorial(n: &BigInt, span: Span, meter: &Meter) -> Result<BigInt, EvalError> {
+    if n.negative {
+        return Err(EvalError::Arith(ArithError::NegativeFactorial, span));
+    }
+    let Some(n) = n.to_u32() else { return Err(EvalError::Arith(ArithError::Overflow, span)) };
+    let bits = factorial_bits(n);
+    fits(bits, span)?;
+    meter.bits(bits)?;
+    Ok((2..=n).fold(BigInt::from_i64(1), |acc, k| &acc * &BigInt::from_i64(k as i64)))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    /// No trailing zero limbs, so zero is empty (and never negative).
+    mag: Vec<u32>,
+}
+
+fn trim(mut mag: Vec<u32>) -> Vec<u32> {
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+    mag
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (a, b) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut out = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0u64;
+    for (i, &x) in a.iter().enumerate() {
+        let sum = x as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        out.push(sum as u32);
+        carry = sum >> 32;
+    }
+    out.push(carry as u32);
+    trim(out)
+}
+
+/// `a - b`, for `a >= b`.
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &x) in a.iter().enumerate() {
+        let mut diff = x as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = (diff < 0) as i64;
+        if diff < 0 {
+            diff += 1 << 32;
+        }
+        out.push(diff as u32);
+    }
+    trim(out)
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let t = x as u64 * y as u64 + out[i + j] as u64 + carry;
+            out[i + j] = t as u32;
+            carry = t >> 32;
+        }
+        out[i + b.len()] = carry as u32;
+    }
+    trim(out)
+}
+
+fn divrem_small(a: &[u32], d: u32) -> (Vec<u32>, u32) {
+    let mut q = vec![0u32; a.len()];
+    let mut r = 0u64;
+    for (i, &x) in a.iter().enumerate().rev() {
+        let cur = (r << 32) | x as u64;
+        q[i] = (cur / d as u64) as u32;
+        r = cur % d as u64;
+    }
+    (trim(q), r as u32)
+}
+
+/// Quotient and remainder of magnitudes, by binary long division.
+fn divrem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if let [d] = b {
+        let (q, r) = divrem_small(a, *d);
+        return (q, trim(vec![r]));
+    }
+
+    let mut q = vec![0u32; a.len()];
+    let mut r: Vec<u32> = Vec::new();
+    for i in (0..a.len() * 32).rev() {
+        // r = r * 2 + bit i of a
+        let mut carry = (a[i / 32] >> (i % 32)) & 1;
+        for limb in r.iter_mut() {
+            let next = *limb >> 31;
+            *limb = (*limb << 1) | carry;
+            carry = next;
+        }
+        if carry != 0 {
+            r.push(carry);
+        }
+
+        if cmp_mag(&r, b) != Ordering::Less {
+            r = sub_mag(&r, b);
+            q[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (trim(q), r)
+}
+
+impl BigInt {
+    fn new(negative: bool, mag: Vec<u32>) -> Self {
+        let mag = trim(mag);
+        Self { negative: negative && !mag.is_empty(), mag }
+    }
+
+    pub fn from_i64(v: i64) -> Self {
+        let m = v.unsigned_abs();
+        Self::new(v < 0, vec![m as u32, (m >> 32) as u32])
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+
+    pub fn is_odd(&self) -> bool {
+        self.mag.first().is_some_and(|v| v & 1 == 1)
+    }
+
+    /// Bits needed for the magnitude.
+    pub fn bits(&self) -> u64 {
+        match self.mag.last() {
+            Some(top) => self.mag.len() as u64 * 32 - top.leading_zeros() as u64,
+            None => 0,
+        }
+    }
+
+    /// The value, if it fits in a `u32`.
+    pub(crate) fn to_u32(&self) -> Option<u32> {
+        match self.mag[..] {
+            _ if self.negative => None,
+            [] => Some(0),
+            [v] => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn abs(&self) -> Self {
+        Self::new(false, self.mag.clone())
+    }
+
+    /// `self ^ e`, however wide it is; check it `fits` first.
+    pub fn pow(&self, mut e: u32) -> Self {
+        let mut base = self.clone();
+        let mut acc = Self::from_i64(1);
+        while e > 0 {
+            if e & 1 == 1 {
+                acc = &acc * &base;
+            }
+            e >>= 1;
+            if e > 0 {
+                base = &base * &base;
+            }
+        }
+        acc
+    }
+
+    pub fn gcd(&self, other: &Self) -> Self {
+        let (mut a, mut b) = (self.abs(), other.abs());
+        while !b.is_zero() {
+            let r = &a % &b;
+            a = b;
+            b = r;
+        }
+        a
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, o: &BigInt) -> BigInt {
+        if self.negative == o.negative {
+            return BigInt::new(self.negative, add_mag(&self.mag, &o.mag));
+        }
+        match cmp_mag(&self.mag, &o.mag) {
+            Ordering::Less => BigInt::new(o.negative, sub_mag(&o.mag, &self.mag)),
+            _ => BigInt::new(self.negative, sub_mag(&self.mag, &o.mag)),
+        }
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.mag.clone())
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, o: &BigInt) -> BigInt {
+        self + &-o
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, o: &BigInt) -> BigInt {
+        BigInt::new(self.negative != o.negative, mul_mag(&self.mag, &o.mag))
+    }
+}
+
+/// Rounds towards zero, and panics on a zero divisor, like `/` on machine
+/// integers.
+impl Div for &BigInt {
+    type Output = BigInt;
+
+    fn div(self, o: &BigInt) -> BigInt {
+        if o.is_zero() {
+            panic!("Division by zero");
+        }
+        BigInt::new(self.negative != o.negative, divrem_mag(&self.mag, &o.mag).0)
+    }
+}
+
+/// Has the sign of the dividend, like `%` on machine integers.
+impl Rem for &BigInt {
+    type Output = BigInt;
+
+    fn rem(self, o: &BigInt) -> BigInt {
+        if o.is_zero() {
+            panic!("Division by zero");
+        }
+        BigInt::new(self.negative, divrem_mag(&self.mag, &o.mag).1)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, o: &Self) -> Ordering {
+        match (self.negative, o.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &o.mag),
+            (true, true) => cmp_mag(&o.mag, &self.mag),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, o: &Self) -> Option<Ordering> {
+        Some(self.cmp(o))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut chunks = Vec::new();
+        let mut mag = self.mag.clone();
+        while !mag.is_empty() {
+            let (q, r) = divrem_small(&mag, 1_000_000_000);
+            chunks.push(r);
+            mag = q;
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+        match chunks.pop() {
+            Some(top) => write!(f, "{top}")?,
+            None => write!(f, "0")?,
+        }
+        for c in chunks.iter().rev() {
+            write!(f, "{c:09}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts a literal like `12` or `1e30`, found at `span`, which must be
+/// an integer.
+pub fn parse(text: &str, span: Span) -> Result<BigInt, EvalError> {
+    let too_large = EvalError::Arith(ArithError::Overflow, span);
+    let (mantissa, exp) = match text.split_once(['e', 'E']) {
+        Some((m, e)) => (m, e.parse::<i64>().map_err(|_| too_large.clone())?),
+        None => (text, 0),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let mut digits = format!("{int}{frac}");
+
+    // Powers of ten left after the digits are read as an integer.
+    let shift = exp - frac.len() as i64;
+    if shift < 0 {
+        let keep = digits.len().saturating_sub(shift.unsigned_abs() as usize);
+        if digits[keep..].bytes().any(|c| c != b'0') {
+            return Err(EvalError::Unsupported(format!("--bigint only works with integers, not {text}"), span));
+        }
+        digits.truncate(keep);
+    }
+
+    let ten = BigInt::from_i64(10);
+    let v = digits.bytes().fold(BigInt::default(), |acc, c| &(&acc * &ten) + &BigInt::from_i64((c - b'0') as i64));
+    let n = u32::try_from(shift.max(0)).map_err(|_| too_large)?;
+    // 10^n takes a little over 3.32 bits a power.
+    fits(v.bits() + n as u64 * 10 / 3, span)?;
+    Ok(&v * &ten.pow(n))
+}
+
+/// `v`, if it is an integer.
+pub fn from_value(v: Value) -> Option<BigInt> {
+    match v {
+        Value::Int(v) => Some(BigInt::from_i64(v as i64)),
+        Value::Wide(v) => {
+            let e18 = 1_000_000_000_000_000_000;
+            let high = &BigInt::from_i64((v / e18) as i64) * &BigInt::from_i64(e18 as i64);
+            Some(&high + &BigInt::from_i64((v % e18) as i64))
+        }
+        Value::Float(_) | Value::Duration(_) | Value::Quantity(..) => None,
+    }
+}
+
+fn apply(op: &NodeVal, args: &[BigInt], span: Span, meter: &Meter) -> Result<BigInt, EvalError> {
+    let arith = |e| Err(EvalError::Arith(e, span));
+    // Results too wide are refused before the time goes into them.
+    let room = |bits: u64| -> Result<(), EvalError> {
+        fits(bits, span)?;
+        Ok(meter.bits(bits)?)
+    };
+    Ok(match (op, args) {
+        (NodeVal::Add, [a]) => a.clone(),
+        (NodeVal::Sub, [a]) => -a,
+        (NodeVal::Add, [a, b]) => a + b,
+        (NodeVal::Sub, [a, b]) => a - b,
+        (NodeVal::Mul, [a, b]) => {
+            room((a.bits() + b.bits()).saturating_sub(1))?;
+            a * b
+        }
+        (NodeVal::Div | NodeVal::Mod, [_, b]) if b.is_zero() => return arith(ArithError::DivisionByZero),
+        (NodeVal::Div, [a, b]) => a / b,
+        (NodeVal::Mod, [a, b]) => a % b,
+        // Like `1 / a^-b`, truncated, as for machine integers; only bases
+        // 0 and ±1 have powers of any size that are not zero, and then
+        // only the parity of the exponent matters.
+        (NodeVal::Exp, [a, b]) if a.bits() <= 1 && (b.negative || b.to_u32().is_none()) => match (a.is_zero(), a.negative) {
+            (true, _) if b.negative => return arith(ArithError::DivisionByZero),
+            (true, _) | (false, false) => a.clone(),
+            (false, true) => BigInt::from_i64(if b.is_odd() { -1 } else { 1 }),
+        },
+        (NodeVal::Exp, [_, b]) if b.negative => BigInt::default(),
+        (NodeVal::Exp, [a, b]) => match b.to_u32() {
+            Some(e) => {
+                room(a.bits().saturating_sub(1) * e as u64 + 1)?;
+                a.pow(e)
+            }
+            None => return arith(ArithError::Overflow),
+        },
+        (NodeVal::Fac, [a]) => factorial(a, span, meter)?,
+        (op, [a, b]) if op.is_comparison() => BigInt::from_i64(op.compare(a, b) as i64),
+        (NodeVal::Call(name), _) => match (name.as_str(), args) {
+            ("abs", [a]) => a.abs(),
+            ("min", [_, ..]) => args.iter().min().unwrap().clone(),
+            ("max", [_, ..]) => args.iter().max().unwrap().clone(),
+            ("gcd", [a, b]) => a.gcd(b),
+            ("pow", [a, b]) => return apply(&NodeVal::Exp, &[a.clone(), b.clone()], span, meter),
+            _ => return Err(EvalError::Unsupported(format!("function {name}() is not available in --bigint mode"), span)),
+        },
+        (NodeVal::AddWrap | NodeVal::MulWrap | NodeVal::AddSat | NodeVal::MulSat, _)
+        | (NodeVal::Shl | NodeVal::Shr | NodeVal::BitAnd | NodeVal::BitOr | NodeVal::BitNot, _) => {
+            let msg = format!("operator {op} models fixed-width integers and is not available in --bigint mode");
+            return Err(EvalError::Unsupported(msg, span));
+        }
+        _ => unreachable!("operator {op} cannot take {} operands", args.len()),
+    })
+}
+
+/// Evaluates `ast` with arbitrary-precision integers. Of `builtins`, only
+/// the functions the program defined itself are used, as in `decimal`.
+pub fn eval(ast: &Node, env: &Env<BigInt>, builtins: &Builtins, meter: &mut Meter) -> Result<BigInt, EvalError> {
+    walk(&Big, Nodes, ast, env, builtins, meter)
+}
+
+struct Big;
+
+impl Numbers for Big {
+    type V = BigInt;
+
+    fn literal(&self, leaf: &LeafVal, span: Span) -> Result<BigInt, EvalError> {
+        match leaf {
+            LeafVal::Int(v) => Ok(BigInt::from_i64(*v as i64)),
+            LeafVal::Decimal(s) => parse(s, span),
+            _ => unreachable!(),
+        }
+    }
+
+    fn is_true(&self, v: &BigInt) -> bool {
+        !v.is_zero()
+    }
+
+    fn truth(&self, b: bool) -> BigInt {
+        BigInt::from_i64(b as i64)
+    }
+
+    fn apply(&self, v: &NodeVal, args: &[BigInt], span: Span, _: &Builtins, meter: &Meter) -> Result<BigInt, EvalError> {
+        apply(v, args, span, meter)
+    }
+
+    fn check(&self, v: &BigInt, meter: &Meter) -> Result<(), LimitExceeded> {
+        meter.bits(v.bits())
+    }
+}
+
+#[test]
+fn tests() {
+    let result = |s: &str| {
+        let mut meter = Meter::new(Default::default());
+        eval(&crate::parser::expr(s.as_bytes()).unwrap(), &Env::new(), &Builtins::default(), &mut meter)
+    };
+    let run = |s: &str| result(s).unwrap().to_string();
+
+    assert_eq!(run("30!"), "265252859812191058636308480000000");
+    assert_eq!(run("2 ^ 100"), "1267650600228229401496703205376");
+    assert_eq!(run("-(2 ^ 64) + 1"), "-18446744073709551615");
+    assert_eq!(run("(3 ^ 50) / (3 ^ 48) - 9"), "0");
+    assert_eq!(run("(10 ^ 30 + 7) / 10 ^ 15"), "1000000000000000");
+    assert_eq!(run("-7 / 2"), "-3");
+    assert_eq!(run("-(2 ^ 70 + 5) % 2 ^ 35"), "-5");
+    assert_eq!(run("gcd(2 ^ 80, 6 ^ 40)"), "1099511627776");
+    assert_eq!(run("1e30 > 2 ^ 99 && max(-5, 3, 1.20e1) == 12"), "1");
+    assert_eq!(run("abs(-(2 ^ 40)) == 2 ^ 40 ? 1 ^ (2 ^ 40) : 0"), "1");
+    assert_eq!(run("2 ^ -1 + (-1) ^ -3 + 1 ^ -(2 ^ 40) + (-1) ^ (2 ^ 40)"), "1");
+    assert_eq!(parse("4.00e2", Span::default()).unwrap().to_string(), "400");
+    assert_eq!(BigInt::from_i64(i64::MIN).to_string(), "-9223372036854775808");
+    assert_eq!(&BigInt::from_i64(-7) % &BigInt::from_i64(2), BigInt::from_i64(-1));
+    assert_eq!(from_value(Value::Wide(-(1 << 100) - 7)).unwrap().to_string(), "-1267650600228229401496703205383");
+    assert_eq!(from_value(Value::Float(0.5)), None);
+
+    // Errors, at the operator or literal that has no result.
+    let span = |start, end| Span { start, end };
+    assert_eq!(result("1 + 2 / (3 - 3)"), Err(EvalError::Arith(ArithError::DivisionByZero, span(4, 15))));
+    assert_eq!(result("5 % 0"), Err(EvalError::Arith(ArithError::DivisionByZero, span(0, 5))));
+    assert_eq!(result("0 ^ -1"), Err(EvalError::Arith(ArithError::DivisionByZero, span(0, 6))));
+    assert_eq!(result("(2 - 5)!"), Err(EvalError::Arith(ArithError::NegativeFactorial, span(0, 8))));
+    assert_eq!(result("1000000!"), Err(EvalError::Arith(ArithError::Overflow, span(0, 8))));
+    for n in [0, 1, 2, 3, 10, 34, 35, 100, 1000] {
+        let bits = result(&format!("{n}!")).unwrap().bits();
+        assert!((bits - 1..=bits).contains(&factorial_bits(n)), "{n}!");
+    }
+    assert_eq!(result("2 ^ (2 ^ 24)"), Err(EvalError::Arith(ArithError::Overflow, span(0, 12))));
+    assert_eq!(result("3 ^ (2 ^ 40)"), Err(EvalError::Arith(ArithError::Overflow, span(0, 12))));
+    assert_eq!(result("1 + 1e99999999").map_err(|e| e.code()), Err("arithmetic-overflow"));
+    assert_eq!(result("1 + 2.5").map_err(|e| (e.to_string(), e.span())), Err(("--bigint only works with integers, not 2.5".into(), Some(span(4, 7)))));
+    assert_eq!(result("sqrt(4)").map_err(|e| e.to_string()), Err("function sqrt() is not available in --bigint mode".into()));
+    assert_eq!(result("1 << 2").map_err(|e| e.code()), Err("unsupported"));
+    let mut meter = Meter::new(crate::limits::EvalLimits { max_value_bits: Some(64), ..Default::default() });
+    let ast = crate::parser::expr(b"(2 ^ 40) * (2 ^ 40)").unwrap();
+    assert_eq!(eval(&ast, &Env::new(), &Builtins::default(), &mut meter), Err(EvalError::Limit(LimitExceeded::ValueBits(64))));
+}
@@ -0,0 +1,520 @@
+use core::cmp::Ordering;
+use core::ops::RangeInclusive;
+
+use crate::arith::{ArithError, ArithMode};
+use crate::eval::Env;
+use crate::parser::{Doc, Node, NodeVal, Value};
+use crate::prelude::*;
+use crate::units::Unit;
+
+type Native = Box<dyn Fn(&[Value]) -> Result<Value, String> + Send + Sync>;
+
+struct Builtin {
+    arity: RangeInclusive<usize>,
+    f: Native,
+    /// Parameter names and documentation, for the default functions.
+    doc: Option<(&'static str, Doc)>,
+    /// Whether its integer results are bit patterns, which keep all their
+    /// bits whatever the `ArithMode`.
+    wide: bool,
+}
+
+/// Documentation for the default functions, with their parameter names.
+const DOCS: [(&str, &str, &str, &str); 22] = [
+    ("min", "a, ...", "The smallest argument.", "min(3, -1, 2)"),
+    ("max", "a, ...", "The largest argument.", "max(3, 3.5)"),
+    ("abs", "a", "Absolute value.", "abs(-4)"),
+    ("gcd", "a, b", "Greatest common divisor of two integers, never negative.", "gcd(12, -18)"),
+    ("sqrt", "a", "Square root, as a float.", "sqrt(2)"),
+    ("pow", "a, b", "a raised to the power b, like a ^ b.", "pow(2, 10)"),
+    ("isprime", "n", "1 if the integer n is prime, otherwise 0.", "isprime(97)"),
+    ("nextprime", "n", "The smallest prime greater than n.", "nextprime(89)"),
+    ("duration", "n, ms", "n units of ms milliseconds each, as a duration; the literal 3h is duration(3, 3600000).", "duration(90, 60000)"),
+    ("factorint", "n, i", "With only n, how many prime factors the positive integer n has, counted with multiplicity; with i, the i-th of them from 0, smallest first.", "factorint(360, 3)"),
+    ("phi", "n", "Euler's totient: how many integers from 1 to n are coprime to n.", "phi(36)"),
+    ("bytes", "n, base, power", "n units of base ^ power bytes each, rounded to a whole byte; the literal 4KiB is bytes(4, 1024, 1).", "bytes(1.5, 1000, 3)"),
+    ("bits", "x", "The IEEE-754 bits of x as a 64-bit float, as an unsigned integer in every --arith mode, though only --arith=promote computes with it.", "bits(5e-324)"),
+    ("fromfloatbits", "n", "The 64-bit float whose IEEE-754 bits are the integer n.", "fromfloatbits(1)"),
+    ("nextafter", "a, b", "The float next to a in the direction of b, or b if they are equal.", "nextafter(1, 2) - 1"),
+    ("ulp", "x", "The gap between x and the next float further from zero.", "ulp(1.0)"),
+    ("popcount", "n", "How many bits of the 32-bit integer n are set.", "popcount(255)"),
+    ("clz", "n", "How many bits of the 32-bit integer n are clear above the highest set one; 32 for 0.", "clz(1)"),
+    ("ctz", "n", "How many bits of the 32-bit integer n are clear below the lowest set one; 32 for 0.", "ctz(8)"),
+    ("rotl", "n, k", "The 32 bits of n rotated left by k, of which only the low 5 bits count.", "rotl(1, 31)"),
+    ("rotr", "n, k", "The 32 bits of n rotated right by k, of which only the low 5 bits count.", "rotr(1, 1)"),
+    ("quantity", "n, i", "n of the unit at index i of units::UNITS, as a quantity; with --units the literal 3 km is quantity(3, 1).", "quantity(1.5, 1)"),
+];
+
+/// A function defined by the program, `name(params...) = body`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub params: Vec<String>,
+    pub body: Node,
+}
+
+impl Function {
+    /// The scope of one call: each parameter bound to its argument.
+    pub fn bind<V: Clone>(&self, args: &[V]) -> Result<Env<V>, String> {
+        if args.len() != self.params.len() {
+            return Err(expects(&(self.params.len()..=self.params.len()), args.len()));
+        }
+        Ok(self.params.iter().cloned().zip(args.iter().cloned()).collect())
+    }
+}
+
+/// Functions that expressions can call as `name(args...)`. Integer results
+/// may be `Value::Wide`; the evaluator narrows them to its `ArithMode`. The
+/// default table holds the functions documented in `DOCS`, which `stoncc
+/// doc` lists; `register` adds more. Functions the program defines itself
+/// go into the same table and take precedence.
+pub struct Builtins {
+    fns: Map<String, Builtin>,
+    defined: Map<String, Function>,
+}
+
+impl Builtins {
+    /// A table without any functions.
+    pub fn empty() -> Self {
+        Self { fns: Map::new(), defined: Map::new() }
+    }
+
+    /// Makes `f` callable as `name` with a number of arguments in `arity`,
+    /// replacing any function already called `name`. Errors returned by `f`
+    /// are reported together with the call that caused them. `f` may be
+    /// called from several threads at once.
+    pub fn register(
+        &mut self,
+        name: &str,
+        arity: RangeInclusive<usize>,
+        f: impl Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    ) {
+        self.fns.insert(name.to_string(), Builtin { arity, f: Box::new(f), doc: None, wide: false });
+    }
+
+    /// The parameters and documentation of the Rust function `name`, if it
+    /// is one of the default functions.
+    pub fn doc(&self, name: &str) -> Option<(&'static str, Doc)> {
+        self.fns.get(name)?.doc
+    }
+
+    /// How many arguments the Rust function `name` takes.
+    pub fn arity(&self, name: &str) -> Option<RangeInclusive<usize>> {
+        self.fns.get(name).map(|b| b.arity.clone())
+    }
+
+    /// `v`, a result of the Rust function `name`, in the range of `mode`.
+    pub(crate) fn narrow(&self, name: &str, v: Value, mode: ArithMode) -> Result<Value, ArithError> {
+        match self.fns.get(name) {
+            Some(b) if b.wide => Ok(v),
+            _ => mode.narrow(v),
+        }
+    }
+
+    /// Defines `name` as a function written in the language itself,
+    /// replacing any earlier definition.
+    pub fn define(&mut self, name: &str, f: Function) {
+        self.defined.insert(name.to_string(), f);
+    }
+
+    /// Puts `defined` in place of every program-defined function, and
+    /// returns those it replaces.
+    #[cfg(feature = "std")]
+    pub(crate) fn swap_defined(&mut self, defined: Map<String, Function>) -> Map<String, Function> {
+        core::mem::replace(&mut self.defined, defined)
+    }
+
+    /// The program-defined functions, by name.
+    pub fn definitions(&self) -> Vec<(&str, &Function)> {
+        let mut defined: Vec<_> = self.defined.iter().map(|(name, f)| (name.as_str(), f)).collect();
+        defined.sort_by_key(|&(name, _)| name);
+        defined
+    }
+
+    /// The program-defined function called `name`, if there is one.
+    pub fn defined(&self, name: &str) -> Option<&Function> {
+        self.defined.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.defined.contains_key(name) || self.fns.contains_key(name)
+    }
+
+    /// Names of all functions, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.fns.keys().chain(self.defined.keys()).map(String::as_str).collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Calls the Rust function `name` with `args`, or returns `None` if
+    /// there is no such function. Program-defined functions need an
+    /// evaluator, so `eval` calls those itself.
+    pub fn call(&self, name: &str, args: &[Value]) -> Option<Result<Value, String>> {
+        let b = self.fns.get(name)?;
+
+        if !b.arity.contains(&args.len()) {
+            return Some(Err(expects(&b.arity, args.len())));
+        }
+
+        Some((b.f)(args))
+    }
+}
+
+/// Why a function taking `arity` arguments can't be called with `got`.
+pub(crate) fn expects(arity: &RangeInclusive<usize>, got: usize) -> String {
+    let expected = match (*arity.start(), *arity.end()) {
+        (1, 1) => "1 argument".to_string(),
+        (n, m) if n == m => format!("{n} arguments"),
+        (1, usize::MAX) => "at least 1 argument".to_string(),
+        (n, usize::MAX) => format!("at least {n} arguments"),
+        (n, m) => format!("{n} to {m} arguments"),
+    };
+    format!("expects {expected}, got {got}")
+}
+
+fn ints(args: &[Value]) -> Option<Vec<i128>> {
+    args.iter()
+        .map(|a| match a {
+            Value::Int(v) => Some(*v as i128),
+            Value::Wide(v) => Some(*v),
+            Value::Float(_) | Value::Duration(_) | Value::Quantity(..) => None,
+        })
+        .collect()
+}
+
+/// The arguments in milliseconds if they are all durations, or `None` if
+/// none is. Mixing durations and numbers is an error.
+fn durations(args: &[Value]) -> Result<Option<Vec<i64>>, String> {
+    let d: Vec<i64> = args.iter().filter_map(|a| if let Value::Duration(v) = a { Some(*v) } else { None }).collect();
+    match d.len() {
+        0 => Ok(None),
+        n if n == args.len() => Ok(Some(d)),
+        _ => Err("cannot mix durations and numbers".to_string()),
+    }
+}
+
+/// The magnitudes of the arguments and their unit if they are all
+/// quantities of one dimension, or `None` if none is a quantity.
+fn quantities(args: &[Value]) -> Result<Option<(Vec<f64>, Unit)>, String> {
+    let Some(unit) = args.iter().find_map(|a| if let Value::Quantity(_, u) = a { Some(*u) } else { None }) else { return Ok(None) };
+    let q: Vec<f64> = args.iter().filter_map(|a| match a {
+        Value::Quantity(v, u) if *u == unit => Some(*v),
+        _ => None,
+    }).collect();
+    if q.len() < args.len() {
+        return Err("cannot mix quantities of different units, or with numbers".to_string());
+    }
+    Ok(Some((q, unit)))
+}
+
+/// `v` as a float, if it is a number.
+fn float(v: Value) -> Result<f64, String> {
+    match v {
+        Value::Duration(_) => Err("expects a number, got a duration".to_string()),
+        Value::Quantity(..) => Err("expects a number, got a quantity".to_string()),
+        v => Ok(v.as_f64()),
+    }
+}
+
+/// The float after `x` in the direction of `to`.
+fn next_after(x: f64, to: f64) -> f64 {
+    match x.partial_cmp(&to) {
+        None => f64::NAN,
+        Some(Ordering::Equal) => to,
+        Some(Ordering::Less) => x.next_up(),
+        Some(Ordering::Greater) => x.next_down(),
+    }
+}
+
+/// The only argument, which must be a 32-bit integer.
+fn int32(args: &[Value]) -> Result<i32, String> {
+    let n = ints(args).ok_or("expects an integer")?[0];
+    i32::try_from(n).map_err(|_| format!("expects a 32-bit integer, got {n}"))
+}
+
+/// The first argument rotated by the second with `f`, as 32-bit integers.
+/// Only the low 5 bits of the count matter, as in hardware.
+fn rotate(args: &[Value], f: fn(i32, u32) -> i32) -> Result<Value, String> {
+    let v = ints(args).ok_or("expects integers")?;
+    let n = i32::try_from(v[0]).map_err(|_| format!("expects a 32-bit integer, got {}", v[0]))?;
+    Ok(Value::Int(f(n, (v[1] & 31) as u32)))
+}
+
+fn gcd(a: u128, b: u128) -> u128 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    (a as u128 * b as u128 % m as u128) as u64
+}
+
+fn pow_mod(mut b: u64, mut e: u64, m: u64) -> u64 {
+    let mut r = 1;
+    while e > 0 {
+        if e & 1 == 1 {
+            r = mul_mod(r, b, m);
+        }
+        b = mul_mod(b, b, m);
+        e >>= 1;
+    }
+    r
+}
+
+/// Miller-Rabin with the first twelve primes as witnesses, which is
+/// deterministic for every `n` below 2^64.
+fn is_prime(n: u64) -> bool {
+    const WITNESSES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    if n < 2 {
+        return false;
+    }
+    if let Some(&p) = WITNESSES.iter().find(|&&p| n % p == 0) {
+        return n == p;
+    }
+
+    let s = (n - 1).trailing_zeros();
+    let d = (n - 1) >> s;
+    WITNESSES.iter().all(|&a| {
+        let mut x = pow_mod(a, d, n);
+        if x == 1 || x == n - 1 {
+            return true;
+        }
+        (1..s).any(|_| {
+            x = mul_mod(x, x, n);
+            x == n - 1
+        })
+    })
+}
+
+/// The prime factors of `n`, smallest first and each as often as it
+/// divides `n`, by trial division.
+fn factors(mut n: u64) -> Vec<u64> {
+    let mut out = Vec::new();
+    let mut p = 2;
+    while p * p <= n {
+        while n % p == 0 {
+            out.push(p);
+            n /= p;
+        }
+        p += 1;
+    }
+    if n > 1 {
+        out.push(n);
+    }
+    out
+}
+
+/// Euler's totient, by trial division.
+fn phi(mut n: u64) -> u64 {
+    let mut r = n;
+    let mut p = 2;
+    while p * p <= n {
+        if n % p == 0 {
+            while n % p == 0 {
+                n /= p;
+            }
+            r -= r / p;
+        }
+        p += 1;
+    }
+    if n > 1 {
+        r -= r / n;
+    }
+    r
+}
+
+impl Default for Builtins {
+    fn default() -> Self {
+        let mut b = Self::empty();
+
+        b.register("min", 1..=usize::MAX, |args| {
+            if let Some(d) = durations(args)? {
+                return Ok(Value::Duration(d.into_iter().min().unwrap()));
+            }
+            if let Some((q, unit)) = quantities(args)? {
+                return Ok(Value::Quantity(q.into_iter().fold(f64::INFINITY, f64::min), unit));
+            }
+            Ok(match ints(args) {
+                Some(v) => Value::int(v.into_iter().min().unwrap()),
+                None => Value::Float(args.iter().map(|a| a.as_f64()).fold(f64::INFINITY, f64::min)),
+            })
+        });
+        b.register("max", 1..=usize::MAX, |args| {
+            if let Some(d) = durations(args)? {
+                return Ok(Value::Duration(d.into_iter().max().unwrap()));
+            }
+            if let Some((q, unit)) = quantities(args)? {
+                return Ok(Value::Quantity(q.into_iter().fold(f64::NEG_INFINITY, f64::max), unit));
+            }
+            Ok(match ints(args) {
+                Some(v) => Value::int(v.into_iter().max().unwrap()),
+                None => Value::Float(args.iter().map(|a| a.as_f64()).fold(f64::NEG_INFINITY, f64::max)),
+            })
+        });
+        b.register("abs", 1..=1, |args| match args[0] {
+            Value::Int(v) => Ok(Value::int((v as i128).abs())),
+            Value::Wide(v) => v.checked_abs().map(Value::int).ok_or_else(|| format!("gives |{v}|, which is out of range")),
+            Value::Float(v) => Ok(Value::Float(v.abs())),
+            Value::Duration(v) => v.checked_abs().map(Value::Duration).ok_or_else(|| "gives a duration that is out of range".to_string()),
+            Value::Quantity(v, unit) => Ok(Value::Quantity(v.abs(), unit)),
+        });
+        b.register("gcd", 2..=2, |args| {
+            let v = ints(args).ok_or("expects integers")?;
+            let g = gcd(v[0].unsigned_abs(), v[1].unsigned_abs());
+            i128::try_from(g).map(Value::int).map_err(|_| format!("gives {g}, which is out of range"))
+        });
+        b.register("sqrt", 1..=1, |args| Ok(Value::Float(float(args[0])?.sqrt())));
+        b.register("pow", 2..=2, |args| NodeVal::Exp.apply(args, ArithMode::Promote).map_err(|e| e.to_string()));
+        b.register("isprime", 1..=1, |args| {
+            let n = int32(args)?;
+            Ok(Value::Int(is_prime(n.max(0) as u64) as i32))
+        });
+        b.register("nextprime", 1..=1, |args| {
+            let n = int32(args)?;
+            let p = (n.max(1) as u64 + 1..).find(|&p| is_prime(p)).unwrap();
+            i32::try_from(p).map(Value::Int).map_err(|_| format!("gives {p}, which is out of range"))
+        });
+        b.register("duration", 2..=2, |args| crate::duration::from(args[0], args[1]));
+        // The language has no lists, so the factors are counted and then
+        // read one at a time.
+        b.register("factorint", 1..=2, |args| {
+            let n = match int32(&args[..1])? {
+                n if n > 0 => n,
+                n => return Err(format!("expects a positive integer, got {n}")),
+            };
+            let f = factors(n as u64);
+            let Some(i) = args.get(1) else { return Ok(Value::Int(f.len() as i32)) };
+            match ints(&[*i]).ok_or("expects an integer index")?[0] {
+                i if (0..f.len() as i128).contains(&i) => Ok(Value::Int(f[i as usize] as i32)),
+                i => Err(format!("expects an index from 0 to {}, got {i}", f.len() as i128 - 1)),
+            }
+        });
+        b.register("phi", 1..=1, |args| match int32(args)? {
+            n if n > 0 => Ok(Value::Int(phi(n as u64) as i32)),
+            n => Err(format!("expects a positive integer, got {n}")),
+        });
+        b.register("bytes", 3..=3, |args| crate::bytes::from(args[0], args[1], args[2]));
+        b.register("quantity", 2..=2, |args| crate::units::from(args[0], args[1]));
+        b.register("bits", 1..=1, |args| Ok(Value::int(float(args[0])?.to_bits() as i128)));
+        b.fns.get_mut("bits").unwrap().wide = true;
+        b.register("fromfloatbits", 1..=1, |args| {
+            let n = ints(args).ok_or("expects an integer")?[0];
+            let bits = u64::try_from(n).or_else(|_| i64::try_from(n).map(|n| n as u64));
+            bits.map(|b| Value::Float(f64::from_bits(b))).map_err(|_| format!("expects an integer of at most 64 bits, got {n}"))
+        });
+        b.register("popcount", 1..=1, |args| Ok(Value::Int(int32(args)?.count_ones() as i32)));
+        b.register("clz", 1..=1, |args| Ok(Value::Int(int32(args)?.leading_zeros() as i32)));
+        b.register("ctz", 1..=1, |args| Ok(Value::Int(int32(args)?.trailing_zeros() as i32)));
+        b.register("rotl", 2..=2, |args| rotate(args, i32::rotate_left));
+        b.register("rotr", 2..=2, |args| rotate(args, i32::rotate_right));
+        b.register("nextafter", 2..=2, |args| Ok(Value::Float(next_after(float(args[0])?, float(args[1])?))));
+        b.register("ulp", 1..=1, |args| {
+            let x = float(args[0])?.abs();
+            Ok(Value::Float(match x {
+                // The largest float has no next one, so take the gap below it.
+                f64::MAX => x - x.next_down(),
+                f64::INFINITY => x,
+                x => x.next_up() - x,
+            }))
+        });
+
+        for (name, params, summary, example) in DOCS {
+            b.fns.get_mut(name).unwrap().doc = Some((params, Doc { summary, example }));
+        }
+        b
+    }
+}
+
+#[test]
+fn tests() {
+    let b = Builtins::default();
+    let call = |name: &str, args: &[Value]| b.call(name, args).unwrap().map(|v| v.to_string());
+
+    assert_eq!(call("min", &[Value::Int(3), Value::Int(-1), Value::Int(2)]), Ok("-1".into()));
+    assert_eq!(call("max", &[Value::Int(3), Value::Float(3.5)]), Ok("3.5".into()));
+    assert_eq!(call("abs", &[Value::Int(-4)]), Ok("4".into()));
+    assert_eq!(call("gcd", &[Value::Int(12), Value::Int(-18)]), Ok("6".into()));
+    assert_eq!(call("sqrt", &[Value::Int(16)]), Ok("4.0".into()));
+    assert_eq!(call("pow", &[Value::Int(2), Value::Int(10)]), Ok("1024".into()));
+    assert_eq!(call("isprime", &[Value::Int(2147483647)]), Ok("1".into()));
+    assert_eq!(call("isprime", &[Value::Int(561)]), Ok("0".into()));
+    assert_eq!(call("nextprime", &[Value::Int(-5)]), Ok("2".into()));
+    assert_eq!(call("nextprime", &[Value::Int(89)]), Ok("97".into()));
+    assert_eq!(call("phi", &[Value::Int(36)]), Ok("12".into()));
+    assert_eq!(call("phi", &[Value::Int(1)]), Ok("1".into()));
+
+    assert_eq!(call("abs", &[Value::Int(i32::MIN)]), Ok("2147483648".into()));
+    assert_eq!(call("pow", &[Value::Int(0), Value::Int(-1)]), Err("division by zero".into()));
+    assert_eq!(call("phi", &[Value::Wide(1 << 40)]), Err("expects a 32-bit integer, got 1099511627776".into()));
+    assert_eq!(call("gcd", &[Value::Int(1), Value::Float(1.0)]), Err("expects integers".into()));
+    assert_eq!(call("min", &[]), Err("expects at least 1 argument, got 0".into()));
+    assert_eq!(call("abs", &[]), Err("expects 1 argument, got 0".into()));
+    assert_eq!(call("pow", &[Value::Int(1)]), Err("expects 2 arguments, got 1".into()));
+    assert_eq!(call("nextprime", &[Value::Int(i32::MAX)]), Err("gives 2147483659, which is out of range".into()));
+    assert_eq!(call("phi", &[Value::Int(0)]), Err("expects a positive integer, got 0".into()));
+    assert_eq!(call("factorint", &[Value::Int(360)]), Ok("6".into()));
+    let f: Vec<String> = (0..6).map(|i| call("factorint", &[Value::Int(360), Value::Int(i)]).unwrap()).collect();
+    assert_eq!(f, ["2", "2", "2", "3", "3", "5"]);
+    assert_eq!(call("factorint", &[Value::Int(2147483647), Value::Int(0)]), Ok("2147483647".into()));
+    assert_eq!(call("factorint", &[Value::Int(1)]), Ok("0".into()));
+    assert_eq!(call("factorint", &[Value::Int(12), Value::Int(3)]), Err("expects an index from 0 to 2, got 3".into()));
+    assert_eq!(call("factorint", &[Value::Int(-12)]), Err("expects a positive integer, got -12".into()));
+    assert!(b.call("nope", &[]).is_none());
+
+    assert_eq!(call("bits", &[Value::Float(1.0)]), Ok("4607182418800017408".into()));
+    assert_eq!(call("bits", &[Value::Float(-0.0)]), Ok("9223372036854775808".into()));
+    assert_eq!(call("bits", &[Value::Int(2)]), call("bits", &[Value::Float(2.0)]));
+    // The bits are all there whatever the mode, where other results narrow.
+    let bits = Value::Wide(0x3ff0 << 48);
+    assert_eq!(b.narrow("bits", bits, ArithMode::Checked), Ok(bits));
+    assert_eq!(b.narrow("abs", bits, ArithMode::Checked), Err(ArithError::Overflow));
+    assert_eq!(b.narrow("abs", bits, ArithMode::Wrap), Ok(Value::Int(0)));
+    assert_eq!(call("fromfloatbits", &[Value::Wide(0x4009_21fb_5444_2d18)]), Ok("3.141592653589793".into()));
+    assert_eq!(call("fromfloatbits", &[Value::Int(-1)]), Ok("NaN".into()));
+    assert_eq!(call("fromfloatbits", &[Value::Int(1)]), Ok("5e-324".into()));
+    assert_eq!(call("fromfloatbits", &[Value::Wide(1 << 64)]), Err("expects an integer of at most 64 bits, got 18446744073709551616".into()));
+    assert_eq!(call("fromfloatbits", &[Value::Float(1.0)]), Err("expects an integer".into()));
+    assert_eq!(call("nextafter", &[Value::Int(1), Value::Int(2)]), Ok("1.0000000000000002".into()));
+    assert_eq!(call("nextafter", &[Value::Float(0.0), Value::Int(-1)]), Ok("-5e-324".into()));
+    assert_eq!(call("nextafter", &[Value::Float(1.5), Value::Float(1.5)]), Ok("1.5".into()));
+    assert_eq!(call("nextafter", &[Value::Float(f64::NAN), Value::Int(1)]), Ok("NaN".into()));
+    assert_eq!(call("ulp", &[Value::Float(1.0)]), Ok(format!("{:?}", f64::EPSILON)));
+    assert_eq!(call("ulp", &[Value::Float(-0.0)]), Ok("5e-324".into()));
+    assert_eq!(call("ulp", &[Value::Float(f64::MAX)]), Ok("1.99584030953472e292".into()));
+    assert_eq!(call("ulp", &[Value::Float(f64::NEG_INFINITY)]), Ok("inf".into()));
+    assert_eq!(call("ulp", &[Value::Duration(1)]), Err("expects a number, got a duration".into()));
+
+    assert_eq!(call("popcount", &[Value::Int(255)]), Ok("8".into()));
+    assert_eq!(call("popcount", &[Value::Int(-1)]), Ok("32".into()));
+    assert_eq!(call("clz", &[Value::Int(1)]), Ok("31".into()));
+    assert_eq!(call("clz", &[Value::Int(-1)]), Ok("0".into()));
+    assert_eq!(call("clz", &[Value::Int(0)]), Ok("32".into()));
+    assert_eq!(call("ctz", &[Value::Int(8)]), Ok("3".into()));
+    assert_eq!(call("ctz", &[Value::Int(0)]), Ok("32".into()));
+    assert_eq!(call("ctz", &[Value::Int(i32::MIN)]), Ok("31".into()));
+    assert_eq!(call("rotl", &[Value::Int(1), Value::Int(31)]), Ok("-2147483648".into()));
+    assert_eq!(call("rotl", &[Value::Int(i32::MIN), Value::Int(1)]), Ok("1".into()));
+    assert_eq!(call("rotr", &[Value::Int(1), Value::Int(1)]), Ok("-2147483648".into()));
+    assert_eq!(call("rotr", &[Value::Int(6), Value::Int(33)]), Ok("3".into()));
+    assert_eq!(call("rotl", &[Value::Int(3), Value::Int(-1)]), call("rotr", &[Value::Int(3), Value::Int(1)]));
+    assert_eq!(call("popcount", &[Value::Wide(1 << 40)]), Err("expects a 32-bit integer, got 1099511627776".into()));
+    assert_eq!(call("rotl", &[Value::Wide(1 << 40), Value::Int(1)]), Err("expects a 32-bit integer, got 1099511627776".into()));
+    assert_eq!(call("rotr", &[Value::Int(1), Value::Float(1.0)]), Err("expects integers".into()));
+
+    // Strong pseudoprimes to several of the witnesses, and large primes.
+    assert!(!is_prime(3_215_031_751));
+    assert!(!is_prime(3_825_123_056_546_413_051));
+    assert!(is_prime(18_446_744_073_709_551_557));
+    assert!(!is_prime(u64::MAX));
+
+    let mut b = Builtins::empty();
+    b.register("double", 1..=1, |args| Ok(Value::Float(args[0].as_f64() * 2.0)));
+    assert_eq!(b.call("double", &[Value::Int(2)]), Some(Ok(Value::Float(4.0))));
+    assert_eq!(b.doc("double"), None);
+
+    // Replacing a default function drops its documentation.
+    let mut b = Builtins::default();
+    assert_eq!(b.doc("abs").map(|d| d.0), Some("a"));
+    b.register("abs", 1..=1, |args| Ok(args[0]));
+    assert_eq!(b.doc("abs"), None);
+}
@@ -0,0 +1,99 @@
+//! Byte sizes. With `Extensions::bytes`, a number followed by a unit
+//! (`4KiB`, `1.5GB`) is the byte count it stands for, a plain integer, and
+//! `--out=human` prints integer results back in units.
+//!
+//! Counts past 2GiB don't fit the 32-bit integers of the default
+//! arithmetic; `--arith=promote` widens them.
+
+use crate::parser::Value;
+use crate::prelude::*;
+
+/// Each unit as its base raised to a power, binary units first.
+pub const UNITS: [(&str, i32, i32); 11] = [
+    ("B", 1024, 0),
+    ("KiB", 1024, 1),
+    ("MiB", 1024, 2),
+    ("GiB", 1024, 3),
+    ("TiB", 1024, 4),
+    ("PiB", 1024, 5),
+    ("kB", 1000, 1),
+    ("MB", 1000, 2),
+    ("GB", 1000, 3),
+    ("TB", 1000, 4),
+    ("PB", 1000, 5),
+];
+
+/// The base and power of `unit`.
+pub fn unit(name: &str) -> Option<(i32, i32)> {
+    UNITS.iter().find(|(u, ..)| *u == name).map(|&(_, base, power)| (base, power))
+}
+
+/// `n` bytes in the largest unit of the given base that fits once rounded
+/// to two decimal places, and whether the rounding was exact.
+fn scaled(n: u128, base: i32) -> (String, bool) {
+    let len = |p: i32| (base as u128).pow(p as u32);
+    let (name, _, power) = UNITS.iter().rev().find(|&&(_, b, p)| p == 0 || b == base && len(p) <= n + len(p) / 200).unwrap();
+    let len = len(*power);
+    let (whole, rest) = (n / len, n % len);
+    let hundredths = (rest * 100 + len / 2) / len;
+    let (whole, hundredths) = if hundredths == 100 { (whole + 1, 0) } else { (whole, hundredths) };
+    let text = match hundredths {
+        0 => format!("{whole}{name}"),
+        h if h % 10 == 0 => format!("{whole}.{}{name}", h / 10),
+        h => format!("{whole}.{h:02}{name}"),
+    };
+    (text, rest * 100 % len == 0)
+}
+
+/// The byte count `n` in units, such as `4KiB`, `1.5GB` or `1.4GiB` (for
+/// 1.5GB). Whichever of the binary and the decimal unit gives an exact
+/// result in fewer characters is used, otherwise the binary one rounded.
+pub fn human(n: i128) -> String {
+    let sign = if n < 0 { "-" } else { "" };
+    let candidates = [scaled(n.unsigned_abs(), 1024), scaled(n.unsigned_abs(), 1000)];
+    let exact = candidates.iter().filter(|(_, exact)| *exact).min_by_key(|(text, _)| text.len());
+    format!("{sign}{}", exact.unwrap_or(&candidates[0]).0)
+}
+
+/// `n` units of `base ^ power` bytes each, rounded to a whole byte, for
+/// the `bytes` builtin that byte-size literals become.
+pub fn from(n: Value, base: Value, power: Value) -> Result<Value, String> {
+    let (Value::Int(base @ 1..), Value::Int(power @ 0..=5)) = (base, power) else {
+        return Err("expects a number, a positive base and a power from 0 to 5".to_string());
+    };
+    let len = (base as i128).pow(power as u32);
+    match n {
+        Value::Duration(_) => Err("expects a number of units, got a duration".to_string()),
+        Value::Float(f) => {
+            let v = (f * len as f64).round();
+            if v.is_finite() && v.abs() < i128::MAX as f64 { Ok(Value::int(v as i128)) } else { Err(format!("overflows on {f} units")) }
+        }
+        n => n.as_i128().checked_mul(len).map(Value::int).ok_or_else(|| format!("overflows on {n} units")),
+    }
+}
+
+#[test]
+fn tests() {
+    assert_eq!(unit("KiB"), Some((1024, 1)));
+    assert_eq!(unit("kB"), Some((1000, 1)));
+    assert_eq!(unit("KB"), None);
+
+    assert_eq!(human(0), "0B");
+    assert_eq!(human(999), "999B");
+    assert_eq!(human(1000), "1kB");
+    assert_eq!(human(4096), "4KiB");
+    assert_eq!(human(1536), "1.5KiB");
+    assert_eq!(human(1_500_000_000), "1.5GB");
+    assert_eq!(human(1_500_000_001), "1.4GiB");
+    assert_eq!(human(-3 << 30), "-3GiB");
+    assert_eq!(human((1 << 20) - 1), "1MiB");
+    assert_eq!(human(1 << 60), "1024PiB");
+
+    let from = |n, base, power| from(n, Value::Int(base), Value::Int(power));
+    assert_eq!(from(Value::Int(4), 1024, 1), Ok(Value::Int(4096)));
+    assert_eq!(from(Value::Float(1.5), 1000, 3), Ok(Value::Int(1_500_000_000)));
+    assert_eq!(from(Value::Float(0.3), 1024, 1), Ok(Value::Int(307)));
+    assert_eq!(from(Value::Int(4), 1024, 3), Ok(Value::Wide(4 << 30)));
+    assert!(from(Value::Int(1), 1024, 6).is_err());
+    assert!(from(Value::Duration(1), 1024, 1).is_err());
+}
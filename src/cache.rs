@@ -0,0 +1,276 @@
+//! On-disk cache of parsed programs (`--cache`). Entries live in
+//! `$XDG_CACHE_HOME/stoncc/` or `~/.cache/stoncc/` and are keyed by a hash of
+//! the source, the rewrites applied to it and the compiler version and
+//! commit (see `provenance`), so an unchanged file skips lexing, parsing
+//! and rewriting. The cache is only
+//! ever a shortcut: an entry that is missing, stale or unreadable is
+//! treated as a miss, and failing to write one is not an error.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use std::str::{FromStr, SplitWhitespace};
+
+use stoncc::*;
+
+use crate::provenance;
+
+const MAGIC: &str = "stoncc-ast";
+
+/// 64-bit FNV-1a.
+fn fnv1a(bytes: &[u8], mut hash: u64) -> u64 {
+    for &b in bytes {
+        hash = (hash ^ b as u64).wrapping_mul(0x100_0000_01b3);
+    }
+    hash
+}
+
+/// The key for `source` after the rewrites described by `rewrites`.
+pub fn key(source: &[u8], rewrites: &str) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325;
+    for part in [env!("CARGO_PKG_VERSION").as_bytes(), provenance::COMMIT.as_bytes(), rewrites.as_bytes(), source] {
+        hash = fnv1a(part, hash);
+        hash = fnv1a(&[0], hash);
+    }
+    hash
+}
+
+fn dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+    Some(base.join("stoncc"))
+}
+
+fn path(key: u64) -> Option<PathBuf> {
+    Some(dir()?.join(format!("{key:016x}.ast")))
+}
+
+/// The cached program for `key` and the stray characters skipped while
+/// parsing it, whose text is recovered from `source`.
+pub fn load(key: u64, source: &[u8]) -> Option<(Program, Vec<ParseError>)> {
+    decode(&fs::read_to_string(path(key)?).ok()?, source)
+}
+
+/// Saves `program` under `key`, writing to a temporary file first so that
+/// a concurrent run never reads half an entry.
+pub fn store(key: u64, source: &[u8], program: &Program, skipped: &[ParseError]) {
+    let Some(path) = path(key) else { return };
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    let text = encode(source, program, skipped);
+    let ok = path.parent().is_some_and(|d| fs::create_dir_all(d).is_ok()) && fs::write(&tmp, text).is_ok();
+    if !ok || fs::rename(&tmp, &path).is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+}
+
+/// Writes the entry as whitespace-separated tokens: a header, the spans of
+/// skipped characters, then the statements in prefix order.
+fn encode(source: &[u8], program: &Program, skipped: &[ParseError]) -> String {
+    let mut out = format!("{MAGIC} {} {} {}\n{}", env!("CARGO_PKG_VERSION"), provenance::COMMIT, source.len(), skipped.len());
+    for e in skipped {
+        let _ = write!(out, " {} {}", e.span.start, e.span.end);
+    }
+    let _ = write!(out, "\n{}", program.stmts.len());
+    for stmt in &program.stmts {
+        out.push('\n');
+        encode_stmt(&mut out, stmt);
+    }
+    out.push('\n');
+    out
+}
+
+fn encode_span(out: &mut String, span: Span) {
+    let _ = write!(out, " {} {}", span.start, span.end);
+}
+
+fn encode_node(out: &mut String, node: &Node) {
+    match node {
+        Node::Leaf(v, span) => {
+            let _ = match v {
+                LeafVal::Int(v) => write!(out, " I {v}"),
+                LeafVal::Bool(b) => write!(out, " B {}", *b as u8),
+                LeafVal::Decimal(v) => write!(out, " D {v}"),
+                LeafVal::Sym(v) => write!(out, " S {v}"),
+                LeafVal::Placeholder(v) => write!(out, " P {v}"),
+            };
+            encode_span(out, *span);
+        }
+        Node::Error(span) => {
+            out.push_str(" E");
+            encode_span(out, *span);
+        }
+        Node::Node { v, children, span } => {
+            let kind = if matches!(v, NodeVal::Call(_)) { 'C' } else { 'N' };
+            let _ = write!(out, " {kind} {v} {}", children.len());
+            encode_span(out, *span);
+            children.iter().for_each(|c| encode_node(out, c));
+        }
+    }
+}
+
+fn encode_stmt(out: &mut String, stmt: &Stmt) {
+    match stmt {
+        Stmt::Expr(e) => {
+            out.push_str("expr");
+            encode_node(out, e);
+        }
+        Stmt::Assign { name, value, span } => {
+            let _ = write!(out, "assign {name}");
+            encode_span(out, *span);
+            encode_node(out, value);
+        }
+        Stmt::Def { name, params, body, span } => {
+            let _ = write!(out, "def {name} {}", params.len());
+            params.iter().for_each(|p| *out += &format!(" {p}"));
+            encode_span(out, *span);
+            encode_node(out, body);
+        }
+        Stmt::Block { stmts, span } => {
+            let _ = write!(out, "block {}", stmts.len());
+            encode_span(out, *span);
+            for s in stmts {
+                out.push(' ');
+                encode_stmt(out, s);
+            }
+        }
+        Stmt::If { cond, then, els, span } => {
+            let _ = write!(out, "if {}", els.is_some() as u8);
+            encode_span(out, *span);
+            encode_node(out, cond);
+            for s in std::iter::once(then).chain(els) {
+                out.push(' ');
+                encode_stmt(out, s);
+            }
+        }
+        Stmt::While { cond, body, span } => {
+            out.push_str("while");
+            encode_span(out, *span);
+            encode_node(out, cond);
+            out.push(' ');
+            encode_stmt(out, body);
+        }
+    }
+}
+
+fn decode(text: &str, source: &[u8]) -> Option<(Program, Vec<ParseError>)> {
+    let mut r = Reader(text.split_whitespace());
+    if r.word()? != MAGIC || r.word()? != env!("CARGO_PKG_VERSION") || r.word()? != provenance::COMMIT || r.num::<usize>()? != source.len() {
+        return None;
+    }
+
+    let mut skipped = Vec::new();
+    for _ in 0..r.num::<usize>()? {
+        let span = r.span()?;
+        let text = String::from_utf8_lossy(source.get(span.start..span.end)?).into_owned();
+        skipped.push(ParseError { kind: ParseErrorKind::UnknownChar(text), span });
+    }
+    let mut program = Program::default();
+    for _ in 0..r.num::<usize>()? {
+        program.stmts.push(r.stmt()?);
+    }
+    r.0.next().is_none().then_some((program, skipped))
+}
+
+struct Reader<'a>(SplitWhitespace<'a>);
+
+impl<'a> Reader<'a> {
+    fn word(&mut self) -> Option<&'a str> {
+        self.0.next()
+    }
+
+    fn num<T: FromStr>(&mut self) -> Option<T> {
+        self.word()?.parse().ok()
+    }
+
+    fn span(&mut self) -> Option<Span> {
+        Some(Span { start: self.num()?, end: self.num()? })
+    }
+
+    fn node(&mut self) -> Option<Node> {
+        let kind = self.word()?;
+        if let Some(leaf) = match kind {
+            "I" => Some(LeafVal::Int(self.num()?)),
+            "B" => Some(LeafVal::Bool(self.num::<u8>()? == 1)),
+            "D" => Some(LeafVal::Decimal(self.word()?.to_string())),
+            "S" => Some(LeafVal::Sym(self.word()?.into())),
+            "P" => Some(LeafVal::Placeholder(self.num()?)),
+            _ => None,
+        } {
+            return Some(Node::Leaf(leaf, self.span()?));
+        }
+        if kind == "E" {
+            return Some(Node::Error(self.span()?));
+        }
+
+        let head = self.word()?;
+        let v = match kind {
+            "C" => NodeVal::Call(head.to_string()),
+            "N" => NodeVal::SHAPES.into_iter().map(|(v, _)| v).find(|v| v.to_string() == head)?,
+            _ => return None,
+        };
+        let argc = self.num::<usize>()?;
+        let span = self.span()?;
+        let children = (0..argc).map(|_| self.node()).collect::<Option<_>>()?;
+        Some(Node::Node { v, children, span })
+    }
+
+    fn stmt(&mut self) -> Option<Stmt> {
+        Some(match self.word()? {
+            "expr" => Stmt::Expr(self.node()?),
+            "assign" => {
+                let name = self.word()?.to_string();
+                Stmt::Assign { name, span: self.span()?, value: self.node()? }
+            }
+            "def" => {
+                let name = self.word()?.to_string();
+                let n = self.num::<usize>()?;
+                let params = (0..n).map(|_| self.word().map(str::to_string)).collect::<Option<_>>()?;
+                Stmt::Def { name, params, span: self.span()?, body: self.node()? }
+            }
+            "block" => {
+                let n = self.num::<usize>()?;
+                let span = self.span()?;
+                Stmt::Block { stmts: (0..n).map(|_| self.stmt()).collect::<Option<_>>()?, span }
+            }
+            "if" => {
+                let has_else = self.num::<u8>()? == 1;
+                let span = self.span()?;
+                let cond = self.node()?;
+                let then = Box::new(self.stmt()?);
+                let els = if has_else { Some(Box::new(self.stmt()?)) } else { None };
+                Stmt::If { cond, then, els, span }
+            }
+            "while" => {
+                let span = self.span()?;
+                Stmt::While { cond: self.node()?, body: Box::new(self.stmt()?), span }
+            }
+            _ => return None,
+        })
+    }
+}
+
+#[test]
+fn tests() {
+    let source = b"f(a, b) = a ? b : -1.5\nx = $1 + gcd(4, 6)\nif (x < 3) { while (x) x = x - 1 } else f(x, 2)";
+    let (program, skipped) = program(source);
+    let program = program.unwrap();
+    assert_eq!(skipped.len(), 1);
+
+    let text = encode(source, &program, &skipped);
+    let (decoded, again) = decode(&text, source).unwrap();
+    assert_eq!(decoded, program);
+    assert_eq!(again, skipped);
+    let spans = |p: &Program| p.stmts.iter().flat_map(Stmt::exprs).map(Node::span).collect::<Vec<_>>();
+    assert_eq!(spans(&decoded), spans(&program));
+
+    assert!(decode(&text, b"something else").is_none());
+    assert!(decode(&text[..text.len() - 4], source).is_none());
+    assert!(decode(&text.replace("expr", "exp"), source).is_none());
+
+    assert_eq!(key(source, ""), key(source, ""));
+    assert_ne!(key(source, ""), key(source, "optimize"));
+    assert_ne!(key(b"1", ""), key(b"2", ""));
+    assert_ne!(key(b"ab", "c"), key(b"b", "ac"));
+}
@@ -0,0 +1,78 @@
+use crate::{cli, provenance};
+use stoncc::json;
+use stoncc::lexer;
+use stoncc::limits::EvalLimits;
+use stoncc::parser::NodeVal;
+use stoncc::{ArithMode, Builtins};
+
+/// The precedence and associativity of `v` used with `arity` operands.
+pub fn binding(v: &NodeVal, arity: usize) -> (i32, &'static str) {
+    match v.fixity(arity) {
+        "prefix" => (v.prefix_prec(), "right"),
+        "postfix" => (v.postfix_prec().unwrap(), "left"),
+        _ if v.is_comparison() => (v.infix_prec(), "none"),
+        _ => (v.infix_prec(), if v.is_lassoc() { "left" } else { "right" }),
+    }
+}
+
+fn operator((v, arity): (NodeVal, usize)) -> String {
+    let fixity = v.fixity(arity);
+    let (prec, assoc) = binding(&v, arity);
+
+    format!(
+        "{{\"symbol\": {}, \"fixity\": \"{fixity}\", \"arity\": {arity}, \"precedence\": {prec}, \"associativity\": \"{assoc}\", \"description\": {}}}",
+        json::string(&v.to_string()),
+        json::string(v.doc(arity).summary)
+    )
+}
+
+/// The document printed by `stoncc --capabilities`. Keys are only ever
+/// added, so consumers can rely on the ones they know about.
+pub fn document() -> String {
+    let strings = |v: &[&str]| json::array(v.iter().map(|s| json::string(s)));
+    let operators = NodeVal::SHAPES.into_iter().map(|s| format!("\n    {}", operator(s)));
+
+    let mut out = String::from("{\n");
+    out += &format!("  \"name\": \"{}\",\n", env!("CARGO_PKG_NAME"));
+    out += &format!("  \"version\": \"{}\",\n", env!("CARGO_PKG_VERSION"));
+    out += &format!("  \"commit\": {},\n", json::string(provenance::COMMIT));
+    out += &format!("  \"features\": {},\n", strings(&provenance::features()));
+    out += &format!("  \"operators\": [{}\n  ],\n", operators.collect::<Vec<_>>().join(","));
+    out += &format!("  \"literals\": {},\n", strings(&["integer", "boolean", "decimal", "symbol", "placeholder"]));
+    out += &format!("  \"functions\": {},\n", strings(&Builtins::default().names()));
+    out += "  \"integer\": {\"bits\": 32, \"signed\": true, \"prefixes\": [\"0x\", \"0o\", \"0b\"], \"digit_separator\": \"_\"},\n";
+    out += &format!("  \"arith_modes\": {},\n", strings(&ArithMode::NAMES));
+    out += "  \"float\": {\"bits\": 64},\n";
+    out += &format!("  \"encodings\": {},\n", strings(&["utf8", "latin1"]));
+    let backends = ["eval", "decimal", "bigint", "rational", "asm", "wat", "llvm", "ir"];
+    // Those behind a feature, and whether it is on.
+    let optional = [("decimal", cfg!(feature = "decimal")), ("bigint", cfg!(feature = "bigint")), ("rational", cfg!(feature = "rational")), ("wat", cfg!(feature = "wasm"))];
+    let backends: Vec<&str> = backends.into_iter().filter(|b| optional.iter().all(|(name, on)| name != b || *on)).collect();
+    out += &format!("  \"backends\": {},\n", strings(&backends));
+    let subcommands: Vec<&str> = cli::SUBCOMMANDS.iter().map(|c| c.name).collect();
+    out += &format!("  \"subcommands\": {},\n", strings(&subcommands));
+    out += &format!("  \"error_codes\": {},\n", strings(crate::ERROR_CODES));
+    out += &format!("  \"extensions\": {},\n", strings(&["calculator", "durations", "bytes", "units", "implicit-mul", "words"]));
+    out += &format!("  \"keywords\": {},\n", strings(&lexer::KEYWORDS));
+    out += &format!("  \"comments\": {},\n", strings(&["//", "#", "/* */"]));
+    out += &format!("  \"token_kinds\": {},\n", strings(&["int", "decimal", "symbol", "placeholder", "keyword", "punct", "unknown"]));
+    let sandbox = EvalLimits::SANDBOX;
+    out += &format!(
+        "  \"sandbox_limits\": {{\"max_depth\": {}, \"max_ops\": {}, \"max_value_bits\": {}, \"max_millis\": {}}},\n",
+        sandbox.max_depth.unwrap(),
+        sandbox.max_ops.unwrap(),
+        sandbox.max_value_bits.unwrap(),
+        sandbox.max_millis.unwrap()
+    );
+    let features = [
+        ("compressed", cfg!(feature = "compressed")),
+        ("bigint", cfg!(feature = "bigint")),
+        ("decimal", cfg!(feature = "decimal")),
+        ("rational", cfg!(feature = "rational")),
+        ("wasm", cfg!(feature = "wasm")),
+    ];
+    let features: Vec<String> = features.iter().map(|(name, on)| format!("\"{name}\": {on}")).collect();
+    out += &format!("  \"features\": {{{}}}\n", features.join(", "));
+    out += "}";
+    out
+}
@@ -0,0 +1,150 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::config::{Config, NO_CONFIG};
+use crate::{corpus, glob, theme};
+
+struct Report {
+    path: PathBuf,
+    ok: bool,
+    diagnostics: Vec<String>,
+}
+
+/// Parses one file and resolves its names in a child process so that a
+/// fatal error in one file can't take the whole run down with it. Warnings
+/// alone don't fail it.
+fn check(path: PathBuf, flags: &[String]) -> Report {
+    let exe = env::current_exe().unwrap();
+    let output = Command::new(exe)
+        .env(NO_CONFIG, "1")
+        .args(flags)
+        .arg("--parse-only")
+        .arg("--resolve")
+        .arg(&path)
+        .output()
+        .unwrap();
+
+    let diagnostics: Vec<String> = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .map(str::to_string)
+        .collect();
+
+    Report {
+        path,
+        ok: output.status.success() && !diagnostics.iter().any(|d| is_header(d) && !d.starts_with("warning")),
+        diagnostics,
+    }
+}
+
+/// Diagnostics span several lines; each one starts with a header line such
+/// as `error: ...`.
+fn is_header(line: &str) -> bool {
+    line.starts_with("error") || line.starts_with("warning")
+}
+
+/// Resolves a command-line operand to the files it names: a directory
+/// means every `.stc` file below it, anything with wildcards is a glob.
+/// Returns false for a glob that matches no file.
+fn resolve(arg: &str, files: &mut Vec<PathBuf>) -> bool {
+    let path = PathBuf::from(arg);
+
+    if path.is_dir() {
+        corpus::walk(&path, files);
+    } else if glob::is_glob(arg) {
+        let before = files.len();
+        files.extend(glob::expand(arg).into_iter().filter(|p| p.is_file()));
+        return files.len() > before;
+    } else {
+        files.push(path);
+    }
+    true
+}
+
+/// `stoncc check [-j N] [--encoding=E] PATH|DIR|GLOB...`: parses every
+/// file in parallel, resolves the names in it, and prints a summary.
+pub fn main(args: &[String]) {
+    let config = Config::load();
+    let mut jobs = config.jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    // The children read no config, so that its defaults for evaluation
+    // can't change what is checked, but files are in its encoding.
+    let mut flags: Vec<String> = config.encoding.iter().map(|e| format!("--encoding={e}")).collect();
+    let mut files = Vec::new();
+    // Globs that match nothing, which fail the check like a bad file would.
+    let mut unmatched = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-j" => {
+                jobs = args.next().and_then(|n| n.parse().ok()).filter(|&n| n > 0).unwrap_or_else(|| {
+                    eprintln!("{}: -j expects a positive number", theme::label("error"));
+                    std::process::exit(2);
+                })
+            }
+            _ if arg.starts_with("--encoding=") => flags.push(arg.clone()),
+            _ if !resolve(arg, &mut files) => unmatched.push(arg),
+            _ => {}
+        }
+    }
+
+    for pattern in &unmatched {
+        eprintln!("{}: no files match {pattern}", theme::label("error"));
+    }
+    if files.is_empty() && !unmatched.is_empty() {
+        std::process::exit(1);
+    }
+    if files.is_empty() {
+        eprintln!("usage: stoncc check [-j N] [--encoding=E] PATH|DIR|GLOB...");
+        std::process::exit(2);
+    }
+
+    let next = AtomicUsize::new(0);
+    let reports: Mutex<Vec<Option<Report>>> = Mutex::new(files.iter().map(|_| None).collect());
+
+    thread::scope(|s| {
+        for _ in 0..jobs.min(files.len()) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = files.get(i) else { break };
+
+                let report = check(path.clone(), &flags);
+                reports.lock().unwrap()[i] = Some(report);
+            });
+        }
+    });
+
+    let reports: Vec<Report> = reports.into_inner().unwrap().into_iter().flatten().collect();
+
+    let mut failed = 0;
+    let mut diagnostics = 0;
+    for r in &reports {
+        diagnostics += r.diagnostics.iter().filter(|d| is_header(d)).count();
+        if r.ok && r.diagnostics.is_empty() {
+            continue;
+        }
+
+        if r.ok {
+            println!("WARN {}", r.path.display());
+        } else {
+            failed += 1;
+            println!("FAIL {}", r.path.display());
+        }
+        for d in &r.diagnostics {
+            println!("  {d}");
+        }
+    }
+
+    println!(
+        "{} files: {} ok, {failed} failed, {diagnostics} diagnostics",
+        reports.len(),
+        reports.len() - failed
+    );
+
+    if failed > 0 || !unmatched.is_empty() {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,493 @@
+use stoncc::format::Notation;
+use stoncc::ArithMode;
+
+use crate::theme::Theme;
+
+/// A command-line flag. Flags with a `value` take an argument, written as
+/// `--flag=VALUE` for long flags and `-f VALUE` for short ones.
+pub struct Flag {
+    pub name: &'static str,
+    pub value: Option<&'static str>,
+    pub choices: &'static [&'static str],
+    pub help: &'static str,
+}
+
+pub struct Command {
+    pub name: &'static str,
+    pub args: &'static str,
+    pub help: &'static str,
+    pub flags: &'static [Flag],
+}
+
+const fn flag(name: &'static str, help: &'static str) -> Flag {
+    Flag { name, value: None, choices: &[], help }
+}
+
+const fn option(name: &'static str, value: &'static str, help: &'static str) -> Flag {
+    Flag { name, value: Some(value), choices: &[], help }
+}
+
+const ENCODING: Flag = Flag {
+    name: "--encoding",
+    value: Some("ENCODING"),
+    choices: &["utf8", "latin1"],
+    help: "character encoding of the input",
+};
+
+/// The top-level command: evaluate files and expressions.
+pub const MAIN: Command = Command {
+    name: "stoncc",
+    args: "[FILE|-]...",
+    help: "evaluate each FILE (or standard input, for -) in turn",
+    flags: &[
+        option("-e", "EXPR", "evaluate EXPR as if it were the contents of a file; can be repeated and mixed with files, which run in order"),
+        option("--expr", "EXPR", "the same as -e EXPR"),
+        option("--from", "FILE", "parse the JSON token stream in FILE, as --emit=tokens prints it, instead of lexing source"),
+        ENCODING,
+        flag("--calc", "accept calculator-style operators: p% (p / 100), a of b (a * b) and a per b (a / b)"),
+        flag("--durations", "accept duration literals like 3h, 45min and 1.5d (units d, h, min, s and ms)"),
+        flag("--bytes", "accept byte-size literals like 4KiB and 1.5GB (units B, KiB to PiB and kB to PB)"),
+        flag("--units", "accept quantities with physical units like 3 m and 9.81 N, checking their dimensions, so 3 m / 2 s is 1.5 m/s and 1 m + 1 s is an error"),
+        flag("--implicit-mul", "read operands side by side as a product: 2x, 3(x + 1) and (a)(b)"),
+        flag("--words", "accept plus, minus, times, divided by and to the power of as operators, for dictated input"),
+        flag("--decimal-comma", "read 3,14 as 3.14 and 1.000 as 1000, as numbers are written in much of Europe; output is unchanged"),
+        flag("--strict-tokens", "reject operators split by spaces, like '! =', and operands side by side, like '2 2'"),
+        option("--env-prefix", "PREFIX", "define variables from environment variables named PREFIX*"),
+        option("--vars", "FILE", "define variables from a JSON object, or - for standard input"),
+        option("--env", "FILE", "load the variables and functions saved in FILE, if it exists, and save them back to it after a run that succeeds"),
+        flag("--decimal", "evaluate in exact fixed-point decimal"),
+        option("--decimal", "SCALE", "fixed-point decimal with SCALE fractional digits"),
+        flag("--bigint", "evaluate with exact integers of any size"),
+        flag("--rational", "evaluate with exact fractions, so 1/3 + 1/6 is 1/2"),
+        Flag {
+            name: "--format",
+            value: Some("FORM"),
+            choices: &["exact", "cfrac"],
+            help: "with --rational, print results as fractions in lowest terms like 7/2 (exact, the default) or as continued fractions like [3; 2] (cfrac)",
+        },
+        flag("--symbolic", "leave symbols nothing defines in the result, printing x + 6 for x + 2*3"),
+        Flag {
+            name: "--arith",
+            value: Some("MODE"),
+            choices: &ArithMode::NAMES,
+            help: "what integer overflow does: wrap, saturate, fail (checked, the default) or widen to 128 bits (promote)",
+        },
+        Flag {
+            name: "--int-width",
+            value: Some("BITS"),
+            choices: &["32", "128"],
+            help: "how wide integers are: 32 bits (the default), or 128 bits, which fail on overflow like --arith=promote",
+        },
+        option("--max-depth", "N", "limit operator nesting during evaluation"),
+        option("--max-ops", "N", "limit the number of operations evaluated"),
+        option("--max-value-bits", "N", "limit the magnitude of intermediate values"),
+        option("--max-millis", "N", "limit evaluation time in milliseconds"),
+        option("--max-print-depth", "N", "print trees only N levels deep, with '...' for deeper subtrees"),
+        flag("--print-sizes", "with --max-print-depth, print deeper subtrees as their size, like <87 nodes>"),
+        flag("--no-stdlib", "leave out the standard library of functions written in stoncc, like choose and c_to_f"),
+        flag("--sandbox", "for untrusted programs: strict --max-* limits (which flags can only lower) and no file writes"),
+        option("--plugin", "LIB", "load a shared library adding builtins, infix operators and --out formats (see include/stoncc_plugin.h)"),
+        option("--rules", "FILE", "rewrite the tree with 'pattern => replacement' rules first"),
+        Flag {
+            name: "--opt",
+            value: Some("OPTIMIZER"),
+            choices: &["egraph"],
+            help: "simplify by e-graph saturation over built-in and --rules identities",
+        },
+        Flag {
+            name: "--opt-cost",
+            value: Some("COST"),
+            choices: &["size", "depth"],
+            help: "what --opt=egraph minimizes (implies --opt=egraph)",
+        },
+        flag("--optimize", "fold constants and remove identities like x+0 and x*1"),
+        option("--passes", "PASS,...", "run these optimization passes, in order: fold, simplify, canonicalize, cse and dce"),
+        flag("--explain-opt", "print each rewrite --optimize, --passes, --opt or --rules makes: the rule, where, and the subtree before and after"),
+        flag("--strict-fp", "give the same floats, bit for bit, whatever the optimizer and backend: keep x+0, refuse --rules, and have --check-ir compare bits"),
+        Flag {
+            name: "--emit",
+            value: Some("TARGET"),
+            choices: &["asm", "wat", "llvm", "ir", "tokens"],
+            help: "compile to x86-64 assembly (FILE.s), WebAssembly text (FILE.wat) or LLVM IR (FILE.ll), or print three-address code or the tokens as JSON, instead of evaluating",
+        },
+        Flag {
+            name: "--report",
+            value: Some("WHAT"),
+            choices: &["codegen"],
+            help: "print how many instructions each operator compiles to with every --emit target, and roughly what they cost, instead of evaluating",
+        },
+        Flag {
+            name: "--out",
+            value: Some("FORMAT"),
+            choices: &Notation::NAMES,
+            help: "how to print results: as they are (plain, the default), with integers as byte sizes like 1.5GiB (human), in hexadecimal or binary (hex, bin), in scientific notation (sci), or in one a --plugin adds",
+        },
+        option("--precision", "N", "print floats rounded to N digits after the point"),
+        flag("--thousands", "with --out=plain, separate thousands with commas, like 1,234,567"),
+        Flag {
+            name: "--theme",
+            value: Some("NAME"),
+            choices: &Theme::NAMES,
+            help: "how to color errors and warnings, in any subcommand: like rustc (default), in bright colors told apart without red and green (high-contrast), in bold and reverse video only (monochrome), or not at all (plain); by default plain when NO_COLOR is set or standard error isn't a terminal",
+        },
+        option("--audit-log", "FILE", "append a JSON line to FILE saying what was evaluated (by SHA-256), with which variables, and what came of it"),
+        option("--record", "FILE", "save the arguments, inputs, output and exit status of the run to FILE (see stoncc replay)"),
+        option("--timeout", "DURATION", "give up after DURATION (like 5s or 500ms), saying how far it got"),
+        option("--expect", "VALUE", "exit with status 1 unless the last statement evaluates to VALUE"),
+        option("--expect-error", "CODE", "exit with status 1 unless evaluation fails with error CODE (see --capabilities)"),
+        flag("--trace", "print each step of evaluation: the subtree reduced, and the tree it leaves"),
+        flag("--explain-result", "print how each expression is worked out, one sentence per step, saying why each step comes first"),
+        Flag {
+            name: "--visualize",
+            value: Some("WHAT"),
+            choices: &["stack"],
+            help: "run each expression on the bytecode VM, printing every instruction with the operand stack it leaves",
+        },
+        flag("--pause", "with --visualize, wait for Enter after each instruction"),
+        flag("--check-ir", "also evaluate the three-address code and fail if it disagrees"),
+        flag("--cache", "reuse the parsed and rewritten tree of an unchanged FILE from ~/.cache/stoncc"),
+        flag("--parse-only", "print the parsed tree without evaluating it, or every syntax error in it"),
+        flag("--resolve", "report variables read before they are assigned or never read, and functions called before they are defined or defined twice"),
+        flag("--format", "print the program back as infix source, with only the parentheses it needs"),
+        Flag {
+            name: "--dump-ast",
+            value: Some("FORMAT"),
+            choices: &["json", "sexp", "dot"],
+            help: "print the rewritten tree of each statement, one per line, as JSON or S-expressions, or all of them as one Graphviz graph",
+        },
+        option("--diff", "VAR", "print the derivative of each expression with respect to VAR, simplified"),
+        flag("--capabilities", "print supported features as JSON"),
+        flag("--build-info", "print the version, git commit and cargo features this stoncc was built with"),
+        option("--gen", "BYTES", "print a random expression of about BYTES bytes that evaluates without error, the same for the same --seed=N, for benchmarks"),
+        flag("--lsp", "serve the Language Server Protocol on standard input and output, for editors"),
+        flag("--help", "print this help"),
+    ],
+};
+
+pub const SUBCOMMANDS: &[Command] = &[
+    Command {
+        name: "apply",
+        args: "EXPR",
+        help: "evaluate EXPR for each line of standard input, with the number on it as x, and print the results",
+        flags: &[flag("--csv", "read each line as CSV fields, bound to f1, f2 and so on")],
+    },
+    Command {
+        name: "bench",
+        args: "FILE",
+        help: "time FILE on each evaluation backend and check that they agree",
+        flags: &[
+            option("--iters", "N", "runs per backend (default 100)"),
+            Flag {
+                name: "--backend",
+                value: Some("BACKEND"),
+                choices: &["eval", "arena", "ir", "vm", "decimal", "bigint", "all"],
+                help: "the backend to time (default all)",
+            },
+        ],
+    },
+    Command {
+        name: "bool-min",
+        args: "FILE",
+        help: "print a minimal sum of products (by Quine-McCluskey) equal to the boolean expression in FILE wherever its variables are 0 or 1",
+        flags: &[option("--max-vars", "N", "refuse expressions with more than N variables (default 12)")],
+    },
+    Command {
+        name: "build",
+        args: "FILE",
+        help: "compile FILE to an executable printing the value of each statement, with the system's cc (or $CC)",
+        flags: &[option("-o", "PROG", "the executable to write (default FILE without its extension)")],
+    },
+    Command {
+        name: "check",
+        args: "PATH|DIR|GLOB...",
+        help: "parse many files in parallel and summarize diagnostics",
+        flags: &[ENCODING, option("-j", "N", "number of parallel jobs")],
+    },
+    Command {
+        name: "completions",
+        args: "SHELL",
+        help: "print a completion script for bash, zsh or fish",
+        flags: &[],
+    },
+    Command {
+        name: "conformance",
+        args: "",
+        help: "run a built-in suite of semantic edge cases on every evaluation backend and compiled target, and print which ones each gets right",
+        flags: &[],
+    },
+    Command {
+        name: "coverage",
+        args: "PATH...",
+        help: "report which grammar productions a corpus exercises",
+        flags: &[flag("--verbose", "also print per-production counts")],
+    },
+    Command {
+        name: "deps",
+        args: "FILE",
+        help: "show which definitions in FILE use which, with cycles among them and definitions nothing uses",
+        flags: &[Flag {
+            name: "--format",
+            value: Some("FORMAT"),
+            choices: &["text", "dot"],
+            help: "print a line per definition (text, the default) or a Graphviz graph (dot)",
+        }],
+    },
+    Command {
+        name: "doc",
+        args: "[OPERATOR|FUNCTION]",
+        help: "describe an operator or builtin function, or list them all",
+        flags: &[],
+    },
+    Command {
+        name: "explain-parse",
+        args: "[-e] EXPR",
+        help: "print each decision the parser makes on EXPR: operands, precedences compared, nodes built",
+        flags: &[
+            option("-e", "EXPR", "the expression to explain"),
+            flag("--calc", "with the calculator extensions"),
+            flag("--durations", "with duration units"),
+            flag("--bytes", "with byte units"),
+            flag("--implicit-mul", "with implicit multiplication"),
+            flag("--words", "with operators in words"),
+            flag("--decimal-comma", "with decimal commas"),
+        ],
+    },
+    Command {
+        name: "gen",
+        args: "",
+        help: "generate a coverage-complete expression corpus",
+        flags: &[
+            option("--seed", "N", "random seed"),
+            option("--count", "N", "number of random expressions"),
+            option("--depth", "N", "maximum operator nesting"),
+            option("--out", "DIR", "write one .stc file per expression"),
+            option("--size", "BYTES", "print one expression of about BYTES bytes instead, for stoncc bench"),
+        ],
+    },
+    Command {
+        name: "map",
+        args: "--csv FILE --expr EXPR",
+        help: "evaluate EXPR over each CSV row, with columns as variables, and add it as a column",
+        flags: &[
+            option("--csv", "FILE", "the input, or - for standard input"),
+            option("--expr", "EXPR", "the expression to evaluate"),
+            option("--as", "COLUMN", "name of the new column (default value)"),
+            flag("--values", "print only the results, one per row, instead of the table"),
+        ],
+    },
+    Command {
+        name: "match",
+        args: "PATTERN EXPR",
+        help: "match EXPR against an S-expression pattern like (+ ?a (* ?b ?b))",
+        flags: &[],
+    },
+    Command {
+        name: "migrate-ast",
+        args: "FILE",
+        help: "rewrite statements saved by an older --dump-ast=json in the current version",
+        flags: &[],
+    },
+    Command {
+        name: "parse-matrix",
+        args: "",
+        help: "print how this version parses a fixed set of tricky expressions, one per line, to diff against another version",
+        flags: &[],
+    },
+    Command {
+        name: "repl",
+        args: "",
+        help: "run programs a line at a time in one session, printing each value; Ctrl-C stops the one running and keeps the session",
+        flags: &[],
+    },
+    Command {
+        name: "replay",
+        args: "TRANSCRIPT",
+        help: "rerun a --record transcript on its recorded input and check that the output is the same",
+        flags: &[],
+    },
+    Command {
+        name: "satisfies",
+        args: "EXPR --var NAME=FROM..TO",
+        help: "print the integer values of the variables in the ranges for which EXPR, like \"x^2 < 50\", is true",
+        flags: &[option("--var", "NAME=FROM..TO", "a variable and the integers to try it with; with several, every combination is tried")],
+    },
+    Command {
+        name: "shake",
+        args: "--entry EXPR FILE",
+        help: "print only the definitions in FILE that EXPR needs, each after those it uses",
+        flags: &[option("--entry", "EXPR", "the expression whose definitions to keep")],
+    },
+    Command {
+        name: "quiz",
+        args: "",
+        help: "practice converting random expressions between infix, S-expressions and reverse Polish notation, and evaluating them",
+        flags: &[option("--seed", "N", "ask the same questions as another run with N"), option("--rounds", "N", "questions to ask (default 5)")],
+    },
+    Command {
+        name: "solve-sym",
+        args: "EQUATION --for VAR",
+        help: "solve a linear or quadratic equation like \"2*x + 3 = 11\" symbolically",
+        flags: &[
+            option("--for", "VAR", "the variable to solve for"),
+            option("--max-steps", "N", "give up after N rewrites"),
+        ],
+    },
+    Command {
+        name: "template",
+        args: "TEMPLATE --args ARG...",
+        help: "fill ?N placeholders with parsed expressions",
+        flags: &[flag("--sexp", "print the result as an S-expression"), flag("--args", "start of the arguments")],
+    },
+    Command {
+        name: "test-corpus",
+        args: "DIR",
+        help: "run .stc files against their .expected results",
+        flags: &[flag("--tap", "TAP output (default)"), flag("--junit", "JUnit XML output")],
+    },
+    Command {
+        name: "truth-table",
+        args: "FILE",
+        help: "print whether the boolean expression in FILE holds for each assignment of 0 and 1 to its variables",
+        flags: &[option("--max-vars", "N", "refuse expressions with more than N variables, which have 2^N rows (default 12)")],
+    },
+];
+
+fn flag_usage(f: &Flag) -> String {
+    match (f.value, f.name.starts_with("--")) {
+        (Some(v), true) => format!("{}={v}", f.name),
+        (Some(v), false) => format!("{} {v}", f.name),
+        (None, _) => f.name.to_string(),
+    }
+}
+
+pub fn help() -> String {
+    let mut out = format!("usage: stoncc [OPTIONS] {}\n       stoncc COMMAND [ARGS]\n\noptions:\n", MAIN.args);
+    for f in MAIN.flags {
+        out += &format!("  {:<22}{}\n", flag_usage(f), f.help);
+    }
+
+    out += "\ncommands:\n";
+    for c in SUBCOMMANDS {
+        out += &format!("  {:<22}{}\n", c.name, c.help);
+    }
+    out
+}
+
+fn words(flags: &[Flag]) -> String {
+    flags.iter().map(|f| f.name).collect::<Vec<_>>().join(" ")
+}
+
+fn bash() -> String {
+    // Bash splits `--flag=value` into `--flag`, `=` and `value`.
+    let mut out = String::from(concat!(
+        "_stoncc() {\n",
+        "    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n",
+        "    if [[ $cur == = ]]; then cur=; elif [[ $prev == = ]]; then prev=\"${COMP_WORDS[COMP_CWORD-2]}\"; fi\n",
+        "    case \"$prev\" in\n",
+    ));
+    let mut seen = Vec::new();
+    for f in MAIN.flags.iter().chain(SUBCOMMANDS.iter().flat_map(|c| c.flags)) {
+        if !f.choices.is_empty() && !seen.contains(&f.name) {
+            seen.push(f.name);
+            out += &format!(
+                "        {}) COMPREPLY=($(compgen -W '{}' -- \"$cur\")); return ;;\n",
+                f.name,
+                f.choices.join(" ")
+            );
+        }
+    }
+    out += "    esac\n";
+    out += "    case \"${COMP_WORDS[1]}\" in\n";
+    for c in SUBCOMMANDS {
+        out += &format!(
+            "        {}) COMPREPLY=($(compgen -f -W '{}' -- \"$cur\")) ;;\n",
+            c.name,
+            words(c.flags)
+        );
+    }
+    let subcommands: Vec<&str> = SUBCOMMANDS.iter().map(|c| c.name).collect();
+    out += &format!(
+        "        *) COMPREPLY=($(compgen -f -W '{} {}' -- \"$cur\")) ;;\n",
+        subcommands.join(" "),
+        words(MAIN.flags)
+    );
+    out += "    esac\n}\ncomplete -o filenames -F _stoncc stoncc\n";
+    out
+}
+
+fn zsh_spec(f: &Flag) -> String {
+    let help = f.help.replace('\'', "'\\''");
+    match f.value {
+        Some(v) if !f.choices.is_empty() => format!("'{}=[{help}]:{v}:({})'", f.name, f.choices.join(" ")),
+        Some(v) if f.name.starts_with("--") => format!("'{}=[{help}]:{v}:_files'", f.name),
+        Some(v) => format!("'{}[{help}]:{v}:'", f.name),
+        None => format!("'{}[{help}]'", f.name),
+    }
+}
+
+fn zsh() -> String {
+    let mut out = String::from("#compdef stoncc\n\n_stoncc() {\n    if (( CURRENT == 2 )); then\n");
+    out += "        local -a commands=(\n";
+    for c in SUBCOMMANDS {
+        out += &format!("            '{}:{}'\n", c.name, c.help);
+    }
+    out += "        )\n        _describe command commands\n    fi\n\n    case $words[2] in\n";
+    for c in SUBCOMMANDS {
+        let specs: Vec<String> = c.flags.iter().map(zsh_spec).collect();
+        out += &format!("        {}) _arguments {} '*:file:_files' ;;\n", c.name, specs.join(" "));
+    }
+    let specs: Vec<String> = MAIN.flags.iter().map(zsh_spec).collect();
+    out += &format!("        *) _arguments {} '*:file:_files' ;;\n", specs.join(" "));
+    out += "    esac\n}\n\n_stoncc \"$@\"\n";
+    out
+}
+
+fn fish_flag(f: &Flag, condition: &str) -> String {
+    let mut out = format!("complete -c stoncc -n '{condition}'");
+    match f.name.strip_prefix("--") {
+        Some(long) => out += &format!(" -l {long}"),
+        None => out += &format!(" -s {}", &f.name[1..]),
+    }
+    if f.value.is_some() {
+        out += " -r";
+    }
+    if !f.choices.is_empty() {
+        out += &format!(" -a '{}'", f.choices.join(" "));
+    }
+    out + &format!(" -d '{}'\n", f.help.replace('\'', "\\'"))
+}
+
+fn fish() -> String {
+    let mut out = String::new();
+    let names: Vec<&str> = SUBCOMMANDS.iter().map(|c| c.name).collect();
+    let top = format!("not __fish_seen_subcommand_from {}", names.join(" "));
+
+    for c in SUBCOMMANDS {
+        out += &format!("complete -c stoncc -n '{top}' -a {} -d '{}'\n", c.name, c.help);
+    }
+    for f in MAIN.flags {
+        out += &fish_flag(f, &top);
+    }
+    for c in SUBCOMMANDS {
+        for f in c.flags {
+            out += &fish_flag(f, &format!("__fish_seen_subcommand_from {}", c.name));
+        }
+    }
+    out
+}
+
+/// `stoncc completions bash|zsh|fish`: prints a completion script generated
+/// from the command definitions above.
+pub fn completions(args: &[String]) {
+    let script = match args.first().map(String::as_str) {
+        Some("bash") => bash(),
+        Some("zsh") => zsh(),
+        Some("fish") => fish(),
+        _ => {
+            eprintln!("usage: stoncc completions bash|zsh|fish");
+            std::process::exit(2);
+        }
+    };
+
+    print!("{script}");
+}
@@ -0,0 +1,806 @@
+//! Code generation (`--emit=asm`, `--emit=wat`, `--emit=llvm`). Programs
+//! are compiled as a stack machine: every expression leaves its value as the
+//! *current* value, and operands waiting for the rest of an operation are
+//! pushed. The tree
+//! walk lives here, and each target implements [`Backend`] to turn those
+//! steps into its own instructions.
+//!
+//! Values are 32-bit integers, and arithmetic wraps on overflow, as the
+//! evaluator does with `--arith=wrap`, except in `X86::checked` code,
+//! which stops with an error as `--arith=checked` does. What it reports
+//! as an error in every mode, such as division by zero or a negative
+//! factorial, only stops an `--emit=asm` program with the same message;
+//! the other targets give whatever their instructions do with such
+//! operands, if anything.
+//!
+//! Targets with [`Backend::FLOATS`] also compile doubles. Each value's
+//! [`Ty`] is worked out before it is compiled: where the evaluator would
+//! promote an integer to a float, compiled code converts it, and a
+//! variable that is ever given a float is one throughout.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::{AddAssign, Sub};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use stoncc::lexer::Span;
+use stoncc::parser::{LeafVal, Node, NodeVal, Program, Stmt};
+use stoncc::{Env, Value};
+
+/// Most parameters a compiled function can take.
+pub const MAX_PARAMS: usize = 6;
+
+/// Instructions emitted so far by any backend, for `--timeout` to report.
+pub static EMITTED: AtomicU64 = AtomicU64::new(0);
+
+/// Instructions a backend emitted, and a rough count of the cycles they
+/// take to run once each, for `--report=codegen`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tally {
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+impl Tally {
+    /// Counts `instruction`, here and in [`EMITTED`].
+    pub fn count(&mut self, instruction: &str) {
+        EMITTED.fetch_add(1, Ordering::Relaxed);
+        self.instructions += 1;
+        self.cycles += cycles(instruction);
+    }
+}
+
+impl AddAssign for Tally {
+    fn add_assign(&mut self, other: Tally) {
+        self.instructions += other.instructions;
+        self.cycles += other.cycles;
+    }
+}
+
+impl Sub for Tally {
+    type Output = Tally;
+
+    fn sub(self, other: Tally) -> Tally {
+        Tally { instructions: self.instructions - other.instructions, cycles: self.cycles - other.cycles }
+    }
+}
+
+/// About how many cycles `instruction` of any target takes on a current
+/// x86-64 core: division is slow, multiplication a little, and a call
+/// costs its overhead but not what the helper called runs.
+fn cycles(instruction: &str) -> u64 {
+    // LLVM names the result first, as in `%t1 = sdiv i32 %a, %b`.
+    let op = instruction.split_once(" = ").map_or(instruction, |(_, op)| op);
+    match op.split_whitespace().next().unwrap_or("") {
+        "idiv" | "sdiv" | "srem" | "i32.div_s" | "i32.rem_s" => 26,
+        "imul" | "mul" | "i32.mul" => 3,
+        // LLVM intrinsics are single instructions.
+        "call" if op.contains("@llvm.") => 1,
+        "call" => 5,
+        _ => 1,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unary {
+    Neg,
+    Fac,
+    Abs,
+    Not,
+    /// Bits set, and clear above the highest or below the lowest set bit,
+    /// all 32 for 0.
+    Popcount,
+    Clz,
+    Ctz,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binary {
+    Add,
+    Sub,
+    Mul,
+    /// `+%` and `*%`, which wrap even where `Add` and `Mul` check for
+    /// overflow.
+    AddWrap,
+    MulWrap,
+    Div,
+    Rem,
+    Pow,
+    AddSat,
+    MulSat,
+    Min,
+    Max,
+    Gcd,
+    /// Shifts, which fill with zeros (left) or the sign (right) once the
+    /// count reaches 32, as with `--arith=wrap`.
+    Shl,
+    Shr,
+    And,
+    Or,
+    /// Rotations, by the low 5 bits of the count.
+    Rotl,
+    Rotr,
+    /// Comparisons, giving 1 or 0.
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Binary {
+    /// Whether `op` also applies to floats, on targets that have them.
+    /// Comparisons of floats give integers, and everything else a float.
+    pub fn on_floats(self) -> bool {
+        use Binary::*;
+        matches!(self, Add | Sub | Mul | Div | Pow | Min | Max | Lt | Le | Gt | Ge | Eq | Ne)
+    }
+}
+
+/// The type of a compiled value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ty {
+    /// A 32-bit integer.
+    Int,
+    /// A double, on targets with [`Backend::FLOATS`].
+    Float,
+}
+
+impl Ty {
+    /// The type that values of `self` and `other` both convert to.
+    fn join(self, other: Ty) -> Ty {
+        if self == Ty::Float || other == Ty::Float {
+            Ty::Float
+        } else {
+            Ty::Int
+        }
+    }
+}
+
+/// A target of the stack machine. Binary operations take their left
+/// operand from the last push and their right operand from the current
+/// value.
+pub trait Backend {
+    /// What starts a comment that runs to the end of the line.
+    const COMMENT: &'static str;
+    /// Whether the target has doubles. Programs with floats can't be
+    /// compiled for one without, and the `float` methods are never called.
+    const FLOATS: bool = false;
+
+    /// Starts the entry point, which runs the top-level statements.
+    fn begin_main(&mut self);
+    fn end_main(&mut self);
+    fn begin_function(&mut self, name: &str, params: usize);
+    fn end_function(&mut self);
+
+    /// Saves the current value as an operand of a later operation.
+    fn push(&mut self);
+    fn int(&mut self, v: i32);
+    fn load_global(&mut self, name: &str, ty: Ty);
+    fn load_param(&mut self, index: usize);
+    /// Stores the current value, which stays current.
+    fn store_global(&mut self, name: &str, ty: Ty);
+    fn unary(&mut self, op: Unary);
+    fn binary(&mut self, op: Binary);
+
+    fn float(&mut self, _v: f64) {
+        unreachable!("the target has no floats")
+    }
+
+    /// Converts the current value from an integer to a float.
+    fn to_float(&mut self) {
+        unreachable!("the target has no floats")
+    }
+
+    /// `Neg` or `Abs` of a float.
+    fn float_unary(&mut self, _op: Unary) {
+        unreachable!("the target has no floats")
+    }
+
+    /// `op` on two floats, one it is [`Binary::on_floats`] for.
+    fn float_binary(&mut self, _op: Binary) {
+        unreachable!("the target has no floats")
+    }
+
+    /// Calls a program-defined function with the last `argc` pushes.
+    fn call(&mut self, name: &str, argc: usize);
+    /// Runs what follows up to `begin_else` only if the current value is
+    /// nonzero, and what follows that up to `end_if` only if it is zero.
+    /// With a `value` type, both leave a result of it current; without,
+    /// neither does.
+    fn begin_if(&mut self, value: Option<Ty>);
+    fn begin_else(&mut self);
+    fn end_if(&mut self);
+    /// Repeats what follows up to `end_loop`, which leaves no value. The
+    /// `loop_test` in it leaves the loop if the current value is zero.
+    fn begin_loop(&mut self);
+    fn loop_test(&mut self);
+    fn end_loop(&mut self);
+    /// Drops the current value, which nothing uses.
+    fn discard(&mut self);
+    /// The current value, of type `ty`, is the result of a top-level
+    /// statement.
+    fn result(&mut self, ty: Ty);
+
+    /// What the backend has emitted so far.
+    fn tally(&self) -> Tally;
+
+    /// The finished output, with `globals` at their initial values, which
+    /// are integers or floats.
+    fn finish(self, globals: &BTreeMap<String, Value>) -> String;
+}
+
+/// Something in the program that has no machine code equivalent here.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unsupported {
+    pub what: String,
+    pub span: Span,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot compile {}", self.what)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+fn unsupported<T>(what: impl Into<String>, span: Span) -> Result<T, Unsupported> {
+    Err(Unsupported { what: what.into(), span })
+}
+
+struct Codegen<'a, B> {
+    b: B,
+    /// Parameter counts of the functions the program defines.
+    functions: &'a HashMap<String, usize>,
+    /// Types of the variables, which are floats if any value they are
+    /// given is.
+    globals: HashMap<String, Ty>,
+    /// Parameters of the function being compiled.
+    locals: HashMap<String, usize>,
+    /// What each operator compiled to, with the operands it pushes but not
+    /// the operators in them.
+    operators: BTreeMap<String, Tally>,
+    /// What the operators inside the one being compiled emitted.
+    nested: Tally,
+}
+
+impl<B: Backend> Codegen<'_, B> {
+    /// The type `node` compiles to. As in the evaluator, a float operand
+    /// makes arithmetic a float, but comparisons and logic give integers.
+    /// Parameters and what functions return are integers.
+    fn ty(&self, node: &Node) -> Ty {
+        if !B::FLOATS {
+            return Ty::Int;
+        }
+        match node {
+            Node::Leaf(LeafVal::Decimal(_), _) => Ty::Float,
+            Node::Leaf(LeafVal::Sym(s), _) if !self.locals.contains_key(s.as_str()) => {
+                self.globals.get(s.as_str()).copied().unwrap_or(Ty::Int)
+            }
+            Node::Node { v, children, .. } => match v {
+                NodeVal::Call(name) if self.functions.contains_key(name) => Ty::Int,
+                NodeVal::Call(name) if matches!(name.as_str(), "min" | "max" | "abs" | "pow") => self.join(children),
+                NodeVal::Add | NodeVal::Sub | NodeVal::Mul | NodeVal::Div | NodeVal::Exp => self.join(children),
+                NodeVal::Cond => self.join(&children[1..]),
+                _ => Ty::Int,
+            },
+            Node::Leaf(..) | Node::Error(_) => Ty::Int,
+        }
+    }
+
+    /// The type all of `nodes` convert to.
+    fn join(&self, nodes: &[Node]) -> Ty {
+        nodes.iter().fold(Ty::Int, |ty, n| ty.join(self.ty(n)))
+    }
+
+    /// The type of the value `stmt` leaves, if it leaves one.
+    fn stmt_ty(&self, stmt: &Stmt) -> Ty {
+        match stmt {
+            Stmt::Assign { name, .. } => self.globals[name],
+            Stmt::Expr(e) => self.ty(e),
+            Stmt::Block { stmts, .. } => stmts.last().map_or(Ty::Int, |s| self.stmt_ty(s)),
+            Stmt::If { then, els, .. } => els.iter().fold(self.stmt_ty(then), |ty, s| ty.join(self.stmt_ty(s))),
+            Stmt::Def { .. } | Stmt::While { .. } => Ty::Int,
+        }
+    }
+
+    /// Makes the variables `stmt` assigns a float to floats, returning
+    /// whether there were any new ones.
+    fn float_globals(&mut self, stmt: &Stmt) -> bool {
+        match stmt {
+            Stmt::Assign { name, value, .. } => self.ty(value) == Ty::Float && self.globals.insert(name.clone(), Ty::Float) != Some(Ty::Float),
+            Stmt::Block { stmts, .. } => stmts.iter().fold(false, |new, s| self.float_globals(s) | new),
+            Stmt::If { then, els, .. } => els.iter().fold(self.float_globals(then), |new, s| self.float_globals(s) | new),
+            Stmt::While { body, .. } => self.float_globals(body),
+            Stmt::Def { .. } | Stmt::Expr(_) => false,
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[Node], span: Span) -> Result<(), Unsupported> {
+        let ty = self.join(args);
+        match (name, args) {
+            (_, _) if self.functions.contains_key(name) => {
+                if ty == Ty::Float {
+                    return unsupported(format!("float argument to {name}()"), span);
+                }
+                for a in args {
+                    self.expr(a)?;
+                    self.b.push();
+                }
+                self.b.call(name, args.len());
+            }
+            ("min" | "max", [first, rest @ ..]) => {
+                let op = if name == "min" { Binary::Min } else { Binary::Max };
+                self.expr_as(first, ty)?;
+                for a in rest {
+                    self.binary(op, ty, None, a)?;
+                }
+            }
+            ("abs", [a]) => {
+                self.expr(a)?;
+                match ty {
+                    Ty::Int => self.b.unary(Unary::Abs),
+                    Ty::Float => self.b.float_unary(Unary::Abs),
+                }
+            }
+            ("pow", [a, b]) => self.binary(Binary::Pow, ty, Some(a), b)?,
+            _ if ty == Ty::Float => return unsupported(format!("function {name}() on floats"), span),
+            ("gcd", [a, b]) => self.binary(Binary::Gcd, ty, Some(a), b)?,
+            ("popcount" | "clz" | "ctz", [a]) => {
+                self.expr(a)?;
+                self.b.unary(match name {
+                    "popcount" => Unary::Popcount,
+                    "clz" => Unary::Clz,
+                    _ => Unary::Ctz,
+                });
+            }
+            ("rotl" | "rotr", [a, b]) => {
+                let op = if name == "rotl" { Binary::Rotl } else { Binary::Rotr };
+                self.binary(op, ty, Some(a), b)?;
+            }
+            _ => {
+                let n = args.len();
+                return unsupported(format!("function {name}() with {n} argument{}", if n == 1 { "" } else { "s" }), span);
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies `op` to `a` (or the current value) and `b`, both as `ty`.
+    fn binary(&mut self, op: Binary, ty: Ty, a: Option<&Node>, b: &Node) -> Result<(), Unsupported> {
+        if let Some(a) = a {
+            self.expr_as(a, ty)?;
+        }
+        self.b.push();
+        self.expr_as(b, ty)?;
+        match ty {
+            Ty::Int => self.b.binary(op),
+            Ty::Float => self.b.float_binary(op),
+        }
+        Ok(())
+    }
+
+    /// Compiles `node`, leaving 1 current if it is nonzero and 0 if not.
+    fn truth(&mut self, node: &Node) -> Result<(), Unsupported> {
+        let ty = self.ty(node);
+        self.expr(node)?;
+        self.b.push();
+        match ty {
+            Ty::Int => {
+                self.b.int(0);
+                self.b.binary(Binary::Ne);
+            }
+            Ty::Float => {
+                self.b.float(0.0);
+                self.b.float_binary(Binary::Ne);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles the condition `node`, leaving an integer current that is
+    /// nonzero if it holds.
+    fn cond(&mut self, node: &Node) -> Result<(), Unsupported> {
+        match self.ty(node) {
+            Ty::Int => self.expr(node),
+            Ty::Float => self.truth(node),
+        }
+    }
+
+    /// Compiles `node` as a value of type `ty`.
+    fn expr_as(&mut self, node: &Node, ty: Ty) -> Result<(), Unsupported> {
+        self.expr(node)?;
+        if ty == Ty::Float && self.ty(node) == Ty::Int {
+            self.b.to_float();
+        }
+        Ok(())
+    }
+
+    /// Compiles `node`, leaving its value current, and counts what its
+    /// operator compiled to.
+    fn expr(&mut self, node: &Node) -> Result<(), Unsupported> {
+        let (start, outer) = (self.b.tally(), std::mem::take(&mut self.nested));
+        self.operator(node)?;
+        let all = self.b.tally() - start;
+        if let Node::Node { v, children, .. } = node {
+            let name = match v {
+                NodeVal::Call(name) => format!("{name}()"),
+                v if v.fixity(children.len()) == "prefix" => format!("unary {v}"),
+                v => v.to_string(),
+            };
+            *self.operators.entry(name).or_default() += all - self.nested;
+            self.nested = outer;
+            self.nested += all;
+        } else {
+            // Operands count toward the operator that uses them.
+            self.nested = outer;
+        }
+        Ok(())
+    }
+
+    fn operator(&mut self, node: &Node) -> Result<(), Unsupported> {
+        let (v, children, span) = match node {
+            Node::Leaf(LeafVal::Int(v), _) => {
+                self.b.int(*v);
+                return Ok(());
+            }
+            Node::Leaf(LeafVal::Bool(b), _) => {
+                self.b.int(*b as i32);
+                return Ok(());
+            }
+            Node::Leaf(LeafVal::Decimal(s), _) if B::FLOATS => {
+                self.b.float(s.parse().unwrap());
+                return Ok(());
+            }
+            Node::Leaf(LeafVal::Sym(s), _) => {
+                match self.locals.get(s.as_str()) {
+                    Some(&i) => self.b.load_param(i),
+                    None => self.b.load_global(s, self.ty(node)),
+                }
+                return Ok(());
+            }
+            Node::Leaf(v, span) => {
+                let numbers = if B::FLOATS { "numbers" } else { "integers" };
+                return unsupported(format!("literal {v} (only {numbers} are compiled)"), *span);
+            }
+            Node::Error(span) => return unsupported("a syntax error".to_string(), *span),
+            Node::Node { v, children, span } => (v, children, *span),
+        };
+
+        let ty = self.join(children);
+        let op = match (v, &children[..]) {
+            (NodeVal::Call(name), args) => return self.call(name, args, span),
+            (NodeVal::Add, [a]) => return self.expr(a),
+            (NodeVal::Cond, [c, a, b]) => {
+                let ty = self.join(&children[1..]);
+                self.cond(c)?;
+                self.b.begin_if(Some(ty));
+                self.expr_as(a, ty)?;
+                self.b.begin_else();
+                self.expr_as(b, ty)?;
+                self.b.end_if();
+                return Ok(());
+            }
+            (NodeVal::And, [a, b]) => {
+                self.cond(a)?;
+                self.b.begin_if(Some(Ty::Int));
+                self.truth(b)?;
+                self.b.begin_else();
+                self.b.int(0);
+                self.b.end_if();
+                return Ok(());
+            }
+            (NodeVal::Or, [a, b]) => {
+                self.cond(a)?;
+                self.b.begin_if(Some(Ty::Int));
+                self.b.int(1);
+                self.b.begin_else();
+                self.truth(b)?;
+                self.b.end_if();
+                return Ok(());
+            }
+            (NodeVal::Sub | NodeVal::Fac | NodeVal::BitNot, [a]) => {
+                let op = match v {
+                    NodeVal::Sub => Unary::Neg,
+                    NodeVal::Fac => Unary::Fac,
+                    _ => Unary::Not,
+                };
+                if ty == Ty::Float && op != Unary::Neg {
+                    return unsupported(format!("operator {v} on floats"), span);
+                }
+                self.expr(a)?;
+                match ty {
+                    Ty::Int => self.b.unary(op),
+                    Ty::Float => self.b.float_unary(op),
+                }
+                return Ok(());
+            }
+            (NodeVal::Add, [_, _]) => Binary::Add,
+            (NodeVal::AddWrap, [_, _]) => Binary::AddWrap,
+            (NodeVal::Sub, [_, _]) => Binary::Sub,
+            (NodeVal::Mul, [_, _]) => Binary::Mul,
+            (NodeVal::MulWrap, [_, _]) => Binary::MulWrap,
+            (NodeVal::Div, [_, _]) => Binary::Div,
+            (NodeVal::Mod, [_, _]) => Binary::Rem,
+            (NodeVal::Shl, [_, _]) => Binary::Shl,
+            (NodeVal::Shr, [_, _]) => Binary::Shr,
+            (NodeVal::BitAnd, [_, _]) => Binary::And,
+            (NodeVal::BitOr, [_, _]) => Binary::Or,
+            (NodeVal::Exp, [_, _]) => Binary::Pow,
+            (NodeVal::AddSat, [_, _]) => Binary::AddSat,
+            (NodeVal::MulSat, [_, _]) => Binary::MulSat,
+            (NodeVal::Lt, [_, _]) => Binary::Lt,
+            (NodeVal::Le, [_, _]) => Binary::Le,
+            (NodeVal::Gt, [_, _]) => Binary::Gt,
+            (NodeVal::Ge, [_, _]) => Binary::Ge,
+            (NodeVal::Eq, [_, _]) => Binary::Eq,
+            (NodeVal::Ne, [_, _]) => Binary::Ne,
+            (v, args) => return unsupported(format!("operator {v} with {} operands", args.len()), span),
+        };
+        if ty == Ty::Float && !op.on_floats() {
+            return unsupported(format!("operator {v} on floats"), span);
+        }
+        self.binary(op, ty, Some(&children[0]), &children[1])
+    }
+
+    /// Compiles a statement inside `main`, returning the type of the value
+    /// it leaves current, if any. Functions are compiled separately, so
+    /// only top-level definitions are allowed.
+    fn stmt(&mut self, stmt: &Stmt, top: bool) -> Result<Option<Ty>, Unsupported> {
+        match stmt {
+            Stmt::Def { .. } if top => Ok(None),
+            Stmt::Def { span, .. } => unsupported("function definition inside a block", *span),
+            Stmt::Assign { name, value, .. } => {
+                let ty = self.globals[name];
+                self.expr_as(value, ty)?;
+                self.b.store_global(name, ty);
+                Ok(Some(ty))
+            }
+            Stmt::Expr(e) => self.expr(e).map(|()| Some(self.ty(e))),
+            Stmt::Block { stmts, .. } => {
+                let mut value = None;
+                for s in stmts {
+                    if value.is_some() {
+                        self.b.discard();
+                    }
+                    value = self.stmt(s, false)?;
+                }
+                Ok(value)
+            }
+            Stmt::If { cond, then, els, .. } => {
+                let value = stmt.has_value().then(|| self.stmt_ty(stmt));
+                self.cond(cond)?;
+                self.b.begin_if(value);
+                self.arm(then, value)?;
+                self.b.begin_else();
+                if let Some(els) = els {
+                    self.arm(els, value)?;
+                }
+                self.b.end_if();
+                Ok(value)
+            }
+            Stmt::While { cond, body, .. } => {
+                self.b.begin_loop();
+                self.cond(cond)?;
+                self.b.loop_test();
+                self.arm(body, None)?;
+                self.b.end_loop();
+                Ok(None)
+            }
+        }
+    }
+
+    /// Compiles the branch of an `if` or the body of a loop, dropping its
+    /// value unless `value` gives the type of the result.
+    fn arm(&mut self, stmt: &Stmt, value: Option<Ty>) -> Result<(), Unsupported> {
+        match (self.stmt(stmt, false)?, value) {
+            (Some(_), None) => self.b.discard(),
+            (Some(Ty::Int), Some(Ty::Float)) => self.b.to_float(),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// `name` spelled with ASCII letters, digits, `_` and `.` only, for targets
+/// whose labels can't hold other characters. Anything else becomes its code
+/// point in hex between dots, which can't clash with another name because
+/// names have no dots.
+pub fn mangle(name: &str) -> Cow<'_, str> {
+    if name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Cow::Borrowed(name);
+    }
+    let mut out = String::new();
+    for c in name.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => out.push(c),
+            _ => out += &format!(".{:x}.", c as u32),
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Adds the variables `stmt` assigns to `globals`, starting out as 0.
+fn assigned(stmt: &Stmt, globals: &mut BTreeMap<String, Value>) {
+    match stmt {
+        Stmt::Assign { name, .. } => {
+            globals.entry(name.clone()).or_insert(Value::Int(0));
+        }
+        Stmt::Block { stmts, .. } => stmts.iter().for_each(|s| assigned(s, globals)),
+        Stmt::If { then, els, .. } => {
+            assigned(then, globals);
+            els.iter().for_each(|s| assigned(s, globals));
+        }
+        Stmt::While { body, .. } => assigned(body, globals),
+        Stmt::Def { .. } | Stmt::Expr(_) => {}
+    }
+}
+
+/// Compiles `program` with backend `b`. Variables in `env` start out with
+/// their values. The program must have passed `sema::check`, so that every
+/// symbol it reads is defined and every call has the right arguments.
+pub fn compile<B: Backend>(b: B, program: &Program, env: &Env) -> Result<String, Unsupported> {
+    generate(b, program, env).map(|(code, _)| code)
+}
+
+/// What `program` compiles to with backend `b`: the tally of each operator
+/// it uses, by name, and of everything else under `"other"`.
+pub fn profile<B: Backend>(b: B, program: &Program, env: &Env) -> Result<BTreeMap<String, Tally>, Unsupported> {
+    generate(b, program, env).map(|(_, operators)| operators)
+}
+
+/// `--report=codegen`: a row per operator and a column per target of
+/// `profiles`, with the instructions and cycles of each.
+pub fn table(profiles: &[(&str, BTreeMap<String, Tally>)]) -> String {
+    let mut rows: Vec<&str> = profiles.iter().flat_map(|(_, p)| p.keys()).map(String::as_str).filter(|&r| r != "other").collect();
+    rows.sort_unstable();
+    rows.dedup();
+    rows.extend(["other", "total"]);
+    let cell = |t: Tally| format!("{} (~{})", t.instructions, t.cycles);
+    let width = rows.iter().map(|r| r.len()).chain(["operator".len()]).max().unwrap_or(0) + 2;
+    let cells: Vec<Vec<String>> = profiles
+        .iter()
+        .map(|(_, p)| {
+            let total = p.values().fold(Tally::default(), |mut a, &t| {
+                a += t;
+                a
+            });
+            rows.iter().map(|&r| cell(if r == "total" { total } else { p.get(r).copied().unwrap_or_default() })).collect()
+        })
+        .collect();
+    let widths: Vec<usize> = profiles.iter().zip(&cells).map(|((name, _), c)| c.iter().map(String::len).chain([name.len()]).max().unwrap_or(0) + 2).collect();
+
+    let mut out = format!("{:<width$}", "operator");
+    for ((name, _), w) in profiles.iter().zip(&widths) {
+        out += &format!("{name:<w$}");
+    }
+    out = out.trim_end().to_string() + "\n";
+    for (i, row) in rows.iter().enumerate() {
+        let mut line = format!("{row:<width$}");
+        for (c, w) in cells.iter().zip(&widths) {
+            line += &format!("{:<w$}", c[i]);
+        }
+        out += line.trim_end();
+        out += "\n";
+    }
+    out + "instructions emitted (~cycles to run them once, not counting what helpers do)\n"
+}
+
+fn generate<B: Backend>(b: B, program: &Program, env: &Env) -> Result<(String, BTreeMap<String, Tally>), Unsupported> {
+    let mut globals = BTreeMap::new();
+    for (name, v) in crate::sorted(env) {
+        match v {
+            Value::Int(_) => globals.insert(name.clone(), *v),
+            Value::Float(_) if B::FLOATS => globals.insert(name.clone(), *v),
+            Value::Float(_) => return unsupported(format!("float variable {name}"), Span::default()),
+            Value::Wide(_) => return unsupported(format!("128-bit variable {name}"), Span::default()),
+            Value::Duration(_) => return unsupported(format!("duration variable {name}"), Span::default()),
+            Value::Quantity(..) => return unsupported(format!("quantity variable {name}"), Span::default()),
+        };
+    }
+
+    let mut functions = HashMap::new();
+    for stmt in &program.stmts {
+        assigned(stmt, &mut globals);
+        if let Stmt::Def { name, params, span, .. } = stmt {
+            if params.len() > MAX_PARAMS {
+                return unsupported(format!("more than {MAX_PARAMS} parameters"), *span);
+            }
+            if functions.insert(name.clone(), params.len()).is_some() {
+                return unsupported(format!("redefinition of {name}()"), *span);
+            }
+        }
+    }
+
+    let types = globals.iter().map(|(name, v)| (name.clone(), if let Value::Float(_) = v { Ty::Float } else { Ty::Int })).collect();
+    let mut g = Codegen { b, functions: &functions, globals: types, locals: HashMap::new(), operators: BTreeMap::new(), nested: Tally::default() };
+    // Each float found can make more of them, until there are no more.
+    while program.stmts.iter().fold(false, |new, s| g.float_globals(s) | new) {}
+    for (name, v) in &mut globals {
+        if let (Ty::Float, Value::Int(i)) = (g.globals[name], *v) {
+            *v = Value::Float(i.into());
+        }
+    }
+
+    g.b.begin_main();
+    for stmt in &program.stmts {
+        if let Some(ty) = g.stmt(stmt, true)? {
+            g.b.result(ty);
+        }
+    }
+    g.b.end_main();
+
+    for stmt in &program.stmts {
+        if let Stmt::Def { name, params, body, span } = stmt {
+            g.locals = params.iter().cloned().zip(0..).collect();
+            if g.ty(body) == Ty::Float {
+                return unsupported(format!("function {name}() giving a float"), *span);
+            }
+            g.b.begin_function(name, params.len());
+            g.expr(body)?;
+            g.b.end_function();
+        }
+    }
+
+    let mut other = g.b.tally();
+    for &t in g.operators.values() {
+        other = other - t;
+    }
+    g.operators.insert("other".to_string(), other);
+    let code = format!("{} {}\n{}", B::COMMENT, crate::provenance::describe(), g.b.finish(&globals));
+    Ok((code, g.operators))
+}
+
+#[test]
+fn tests() {
+    let err = |s: &str| {
+        let program = stoncc::program(s.as_bytes()).0.unwrap();
+        compile(crate::x86::X86::default(), &program, &Env::new()).unwrap_err().what
+    };
+    assert_eq!(err("1.5 + 1"), "literal 1.5 (only integers are compiled)");
+    assert_eq!(err("sqrt(4)"), "function sqrt() with 1 argument");
+    assert_eq!(err("f(x) = x; f(x) = 2"), "redefinition of f()");
+    assert_eq!(err("f(a, b, c, d, e, g, h) = a"), "more than 6 parameters");
+    assert_eq!(err("{ f(x) = x }"), "function definition inside a block");
+
+    let err = |s: &str| {
+        let program = stoncc::program(s.as_bytes()).0.unwrap();
+        compile(crate::llvm::Llvm::default(), &program, &Env::new()).unwrap_err().what
+    };
+    assert_eq!(err("1.5 % 1"), "operator % on floats");
+    assert_eq!(err("~x; x = 0.5"), "operator ~ on floats");
+    assert_eq!(err("gcd(4, 2.0)"), "function gcd() on floats");
+    assert_eq!(err("f(a) = a; f(1.5)"), "float argument to f()");
+    assert_eq!(err("f(a) = a / 2.0; f(1)"), "function f() giving a float");
+
+    let env: Env = (0..20).map(|i| (format!("v{i:02}"), Value::Float(0.5))).collect();
+    let program = stoncc::program(b"1").0.unwrap();
+    assert_eq!(compile(crate::x86::X86::default(), &program, &env).unwrap_err().what, "float variable v00");
+
+    // Each operator counts its operands, but not the operators in them.
+    let program = stoncc::program(b"-(1 + 2 * 3) / 4").0.unwrap();
+    let ops = profile(crate::llvm::Llvm::default(), &program, &Env::new()).unwrap();
+    let names: Vec<&str> = ops.keys().map(String::as_str).collect();
+    assert_eq!(names, ["*", "+", "/", "other", "unary -"]);
+    assert_eq!(ops["*"], Tally { instructions: 1, cycles: 3 });
+    assert_eq!(ops["/"], Tally { instructions: 1, cycles: 5 });
+    assert_eq!(cycles("%t3 = sdiv i32 %t1, %t2"), 26);
+    assert_eq!(cycles("call i32 @llvm.ctpop.i32(i32 %t1)"), 1);
+    assert_eq!(cycles("idiv %edi"), 26);
+
+    let t = |instructions, cycles| Tally { instructions, cycles };
+    let asm = BTreeMap::from([("+".to_string(), t(4, 4)), ("other".to_string(), t(10, 14))]);
+    let wat = BTreeMap::from([("+".to_string(), t(1, 1)), ("other".to_string(), t(3, 3))]);
+    assert_eq!(
+        table(&[("asm", asm), ("wat", wat)]),
+        "operator  asm       wat\n+         4 (~4)    1 (~1)\nother     10 (~14)  3 (~3)\ntotal     14 (~18)  4 (~4)\n\
+         instructions emitted (~cycles to run them once, not counting what helpers do)\n"
+    );
+
+    assert_eq!(mangle("x_1"), "x_1");
+    assert_eq!(mangle("2\u{3c0}r"), "2.3c0.r");
+    assert_eq!(mangle("\u{e9}t\u{e9}"), ".e9.t.e9.");
+}
@@ -0,0 +1,157 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use stoncc::format::Notation;
+use stoncc::ArithMode;
+
+use crate::input::Encoding;
+use crate::theme;
+
+/// Set in the environment, stops the configuration files being read, for
+/// runs whose results mustn't depend on them, like the harnesses that run
+/// this binary on a corpus.
+pub const NO_CONFIG: &str = "STONCC_NO_CONFIG";
+
+/// Defaults read from configuration files. Every field is optional, so a
+/// later file only overrides the keys it sets, and command-line flags
+/// override all of them.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub encoding: Option<Encoding>,
+    pub jobs: Option<usize>,
+    /// What integer overflow does, as `--arith` says.
+    pub arith: Option<ArithMode>,
+    /// How results are printed, as `--out` says.
+    pub out: Option<Notation>,
+    /// Whether the standard library is loaded; `false` is `--no-stdlib`.
+    pub stdlib: Option<bool>,
+}
+
+/// Parses a value of the small TOML subset used by the config files:
+/// quoted strings, integers and booleans.
+fn value(raw: &str) -> Result<String, String> {
+    let raw = raw.trim();
+    if let Some(s) = raw.strip_prefix('"') {
+        return s.strip_suffix('"').map(str::to_string).ok_or("unterminated string".to_string());
+    }
+    if raw.is_empty() {
+        return Err("missing value".to_string());
+    }
+    Ok(raw.to_string())
+}
+
+/// Drops a trailing `# comment`, leaving `#` inside strings alone.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+impl Config {
+    /// Applies `key = value` lines from `text` on top of `self`. Problems
+    /// are reported as warnings so that a bad config never blocks a run.
+    fn apply(&mut self, text: &str, path: &Path) {
+        let mut section = String::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let warn = |msg: String| eprintln!("{}: {}:{}: {msg}", theme::label("warning"),  path.display(), n + 1);
+            let line = strip_comment(line).trim();
+
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = format!("{}.", name.trim());
+                continue;
+            }
+
+            let Some((key, raw)) = line.split_once('=') else {
+                warn(format!("expected `key = value`, found '{line}'"));
+                continue;
+            };
+            let key = format!("{section}{}", key.trim());
+            let v = match value(raw) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn(format!("{key}: {e}"));
+                    continue;
+                }
+            };
+
+            let res = match key.as_str() {
+                "encoding" => v.parse().map(|e| self.encoding = Some(e)),
+                "arith" => v.parse().map(|m| self.arith = Some(m)),
+                "out" => v.parse().map(|n| self.out = Some(n)),
+                "stdlib" => v
+                    .parse()
+                    .map(|b| self.stdlib = Some(b))
+                    .map_err(|_| format!("expected true or false, found '{v}'")),
+                "check.jobs" => v
+                    .parse()
+                    .map(|j| self.jobs = Some(j))
+                    .map_err(|_| format!("expected a number, found '{v}'")),
+                _ => Err("unknown key".to_string()),
+            };
+            if let Err(e) = res {
+                warn(format!("{key}: {e}"));
+            }
+        }
+    }
+
+    fn apply_file(&mut self, path: &Path) {
+        if let Ok(text) = fs::read_to_string(path) {
+            self.apply(&text, path);
+        }
+    }
+
+    /// The user-wide config file, `$XDG_CONFIG_HOME/stoncc/config.toml` or
+    /// `~/.config/stoncc/config.toml`.
+    fn user_path() -> Option<PathBuf> {
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+        Some(base.join("stoncc").join("config.toml"))
+    }
+
+    /// The nearest `stoncc.toml` in the current directory or its ancestors.
+    fn project_path() -> Option<PathBuf> {
+        let cwd = env::current_dir().ok()?;
+        cwd.ancestors().map(|d| d.join("stoncc.toml")).find(|p| p.is_file())
+    }
+
+    /// Loads the user config, then the project config on top of it, unless
+    /// [`NO_CONFIG`] is set.
+    pub fn load() -> Config {
+        let mut config = Config::default();
+        if env::var_os(NO_CONFIG).is_some() {
+            return config;
+        }
+        for path in [Self::user_path(), Self::project_path()].into_iter().flatten() {
+            config.apply_file(&path);
+        }
+        config
+    }
+}
+
+#[test]
+fn tests() {
+    let mut c = Config::default();
+    c.apply("# defaults\nencoding = \"latin1\" # legacy files\n\n[check]\njobs = 4\n", Path::new("t"));
+    assert_eq!(c, Config { encoding: Some(Encoding::Latin1), jobs: Some(4), ..Config::default() });
+
+    c.apply("encoding = \"utf8\"\nbogus = 1\njobs\n", Path::new("t"));
+    assert_eq!(c, Config { encoding: Some(Encoding::Utf8), jobs: Some(4), ..Config::default() });
+
+    c.apply("arith = \"saturate\"\nout = \"hex\"\nstdlib = false\n", Path::new("t"));
+    assert_eq!((c.arith, c.out, c.stdlib), (Some(ArithMode::Saturate), Some(Notation::Hex), Some(false)));
+    // Bad values leave what was there.
+    c.apply("arith = \"sloppy\"\nout = \"roman\"\nstdlib = 0\n", Path::new("t"));
+    assert_eq!((c.arith, c.out, c.stdlib), (Some(ArithMode::Saturate), Some(Notation::Hex), Some(false)));
+}
@@ -0,0 +1,285 @@
+//! `stoncc conformance`: runs a built-in suite of semantic edge cases on
+//! every evaluation backend and prints which of them each backend gets
+//! right, so that a backend can be trusted to match the tree interpreter.
+//! Compiled code is run with `cc`, `wasmtime` or `lli`, and its cases are
+//! skipped where those aren't installed.
+
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs};
+
+use stoncc::parser::{self, Program};
+use stoncc::Env;
+
+use crate::bench;
+use crate::{codegen, driver, llvm, x86};
+
+/// The interpreters, and the compiled code of each `--emit` target built
+/// in.
+const COLUMNS: &[&str] = &[
+    "eval",
+    "arena",
+    "ir",
+    "vm",
+    #[cfg(feature = "decimal")]
+    "decimal",
+    #[cfg(feature = "bigint")]
+    "bigint",
+    #[cfg(feature = "rational")]
+    "rational",
+    "asm",
+    #[cfg(feature = "wasm")]
+    "wat",
+    "llvm",
+];
+
+/// The backends with checked 32-bit integers and floats.
+const FIXED: &[&str] = &["eval", "arena", "ir", "vm"];
+/// Compiled code, whose 32-bit integers wrap.
+const COMPILED: &[&str] = &["asm", "wat", "llvm"];
+/// Those, and arbitrary-precision integers: the backends whose division
+/// of integers truncates.
+const INTEGER: &[&str] = &["eval", "arena", "ir", "vm", "bigint", "asm", "wat", "llvm"];
+/// The backends with 32-bit integers.
+const BITS: &[&str] = &["eval", "arena", "ir", "vm", "asm", "wat", "llvm"];
+/// The backends without a fixed width, which don't overflow.
+const EXACT: &[&str] = &["decimal", "bigint", "rational"];
+/// Every backend but those compiled to targets that don't stop on errors
+/// other than division by zero.
+const STOPPING: &[&str] = &["eval", "arena", "ir", "vm", "decimal", "bigint", "rational", "asm"];
+const ALL: &[&str] = &["eval", "arena", "ir", "vm", "decimal", "bigint", "rational", "asm", "wat", "llvm"];
+
+/// An expression, what it should give, and the backends whose semantics
+/// say so.
+struct Case {
+    source: &'static str,
+    /// The value, or `None` for an error.
+    expect: Option<&'static str>,
+    backends: &'static [&'static str],
+}
+
+const fn case(source: &'static str, expect: Option<&'static str>, backends: &'static [&'static str]) -> Case {
+    Case { source, expect, backends }
+}
+
+const SUITE: [Case; 39] = [
+    // Division of negative numbers, and by zero.
+    case("-7 / 2", Some("-3"), INTEGER),
+    case("7 / -2", Some("-3"), INTEGER),
+    case("-7 / 2", Some("-3.5"), &["decimal"]),
+    case("-7 / 2", Some("-7/2"), &["rational"]),
+    case("-7 % 2", Some("-1"), &["eval", "arena", "ir", "vm", "bigint", "rational", "asm", "wat", "llvm"]),
+    case("-7 >> 1", Some("-4"), BITS),
+    case("1 / 0", None, ALL),
+    case("1 % 0", None, &["eval", "arena", "ir", "vm", "bigint", "rational", "asm", "wat", "llvm"]),
+    // Overflow: an error, unless the operator says to wrap or saturate,
+    // or the code is compiled.
+    case("2147483647 + 1", None, FIXED),
+    case("2147483647 + 1", Some("-2147483648"), COMPILED),
+    case("2147483647 + 1", Some("2147483648"), EXACT),
+    case("(-2147483647 - 1) / -1", None, FIXED),
+    case("(-2147483647 - 1) / -1", Some("-2147483648"), COMPILED),
+    case("2 ^ 31", None, FIXED),
+    case("2 ^ 31", Some("-2147483648"), COMPILED),
+    case("2147483647 +% 1", Some("-2147483648"), BITS),
+    case("2147483647 +| 1", Some("2147483647"), BITS),
+    case("-65536 *| 65536", Some("-2147483648"), BITS),
+    // Precedence and associativity.
+    case("2 ^ 3 ^ 2", Some("512"), ALL),
+    case("100 / 10 / 5", Some("2"), ALL),
+    case("10 - 4 - 3", Some("3"), ALL),
+    case("-2 ^ 2", Some("-4"), ALL),
+    case("2 ^ 3!", Some("40320"), ALL),
+    // Factorials.
+    case("0!", Some("1"), ALL),
+    case("12!", Some("479001600"), ALL),
+    case("13!", None, FIXED),
+    case("13!", Some("1932053504"), COMPILED),
+    case("13!", Some("6227020800"), EXACT),
+    case("(-1)!", None, STOPPING),
+    case("3!!", Some("720"), ALL),
+    // Operands that aren't needed aren't evaluated.
+    case("0 && 1 / 0", Some("0"), ALL),
+    case("1 || 1 / 0", Some("1"), ALL),
+    case("1 ? 2 : 1 / 0", Some("2"), ALL),
+    // Floats, and integers that stay integers.
+    case("1 / 2.0", Some("0.5"), &["eval", "arena", "ir", "vm", "decimal", "wat", "llvm"]),
+    case("2 ^ 0.5 < 1.5", Some("1"), &["eval", "arena", "ir", "vm", "llvm"]),
+    case("1 / 2.0", Some("1/2"), &["rational"]),
+    case("2 ^ -1", Some("0"), INTEGER),
+    case("2 ^ -1", Some("1/2"), &["rational"]),
+    case("(-1) ^ -3", Some("-1"), INTEGER),
+];
+
+/// What a backend did on a case.
+#[derive(Debug, Clone, PartialEq)]
+enum Outcome {
+    Pass,
+    /// What it gave instead, or its error.
+    Fail(Result<String, String>),
+    /// The case isn't one of the backend's semantics.
+    Skipped,
+    /// The case is, but its compiled code needs this tool to run.
+    Unrun(&'static str),
+}
+
+fn check(case: &Case, backend: &str) -> Outcome {
+    if !case.backends.contains(&backend) {
+        return Outcome::Skipped;
+    }
+    let program = parser::program(case.source.as_bytes()).0.unwrap();
+    let got = match backend {
+        "asm" | "wat" | "llvm" => match run_compiled(&program, backend) {
+            Ok(got) => got,
+            Err(tool) => return Outcome::Unrun(tool),
+        },
+        _ => bench::run(&program, backend),
+    };
+    match (got, case.expect) {
+        (Ok(v), Some(want)) if bench::agree(&v, want) => Outcome::Pass,
+        (Err(_), None) => Outcome::Pass,
+        (got, _) => Outcome::Fail(got),
+    }
+}
+
+/// The last value `program` prints when compiled for `target` and run, or
+/// its error, or else the tool it needs and can't find to run it.
+fn run_compiled(program: &Program, target: &str) -> Result<Result<String, String>, &'static str> {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let env = Env::new();
+    let (code, tool) = match target {
+        "asm" => (codegen::compile(x86::X86::default(), program, &env), "cc"),
+        #[cfg(feature = "wasm")]
+        "wat" => (codegen::compile(crate::wat::Wat::default(), program, &env), "wasmtime"),
+        _ => (codegen::compile(llvm::Llvm::default(), program, &env), "lli"),
+    };
+    let code = match code {
+        Ok(code) => code,
+        Err(e) => return Ok(Err(e.to_string())),
+    };
+
+    let base = env::temp_dir().join(format!("stoncc-conformance-{}-{}", process::id(), RUNS.fetch_add(1, Ordering::Relaxed)));
+    let source = base.with_extension(match target {
+        "asm" => "s",
+        "wat" => "wat",
+        _ => "ll",
+    });
+    fs::write(&source, code).map_err(|_| tool)?;
+    let output = match target {
+        "asm" => {
+            let cc = driver::find_cc(env::var_os("CC"), env::var_os("PATH")).filter(|_| cfg!(target_arch = "x86_64"));
+            let built = cc.and_then(|cc| Command::new(cc).arg("-o").arg(&base).arg(&source).status().ok());
+            let output = built.filter(|s| s.success()).and_then(|_| Command::new(&base).output().ok());
+            let _ = fs::remove_file(&base);
+            output
+        }
+        "wat" => Command::new("wasmtime").args(["run", "--invoke", "main"]).arg(&source).output().ok(),
+        _ => Command::new("lli").arg(&source).output().ok(),
+    };
+    let _ = fs::remove_file(&source);
+
+    let output = output.ok_or(tool)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Ok(Err(stderr.lines().next().map_or_else(|| output.status.to_string(), str::to_string)));
+    }
+    Ok(Ok(String::from_utf8_lossy(&output.stdout).lines().last().unwrap_or("").to_string()))
+}
+
+/// The matrix of `outcomes`, a row per case and a column per backend, and
+/// a line for each failure and each backend that couldn't be run.
+fn report(outcomes: &[Vec<Outcome>]) -> String {
+    let expect = |c: &Case| c.expect.unwrap_or("error").to_string();
+    let width = SUITE.iter().map(|c| c.source.len()).max().unwrap_or(0) + 2;
+    let expect_width = SUITE.iter().map(|c| expect(c).len()).max().unwrap_or(0) + 2;
+    let mut out = format!("{:<width$}{:<expect_width$}", "case", "expected");
+    out += &COLUMNS.iter().map(|b| format!("{b:<9}")).collect::<String>();
+    out = out.trim_end().to_string() + "\n";
+
+    let mut failures = String::new();
+    for (case, row) in SUITE.iter().zip(outcomes) {
+        let mut line = format!("{:<width$}{:<expect_width$}", case.source, expect(case));
+        for (backend, outcome) in COLUMNS.iter().zip(row) {
+            let cell = match outcome {
+                Outcome::Pass => "ok",
+                Outcome::Fail(got) => {
+                    let got = got.as_ref().map_or_else(|e| format!("error: {e}"), |v| v.clone());
+                    failures += &format!("{backend}: {} gives {got}, expected {}\n", case.source, expect(case));
+                    "FAIL"
+                }
+                Outcome::Skipped => "-",
+                Outcome::Unrun(_) => "skip",
+            };
+            line += &format!("{cell:<9}");
+        }
+        out += line.trim_end();
+        out += "\n";
+    }
+
+    let mut unrun = String::new();
+    for (i, backend) in COLUMNS.iter().enumerate() {
+        if let Some(Outcome::Unrun(tool)) = outcomes.iter().map(|row| &row[i]).find(|o| matches!(o, Outcome::Unrun(_))) {
+            unrun += &format!("{backend}: skipped, running its code needs {tool}\n");
+        }
+    }
+
+    let checks = outcomes.iter().flatten().filter(|o| matches!(o, Outcome::Pass | Outcome::Fail(_))).count();
+    let failed = failures.lines().count();
+    format!("{out}{failures}{unrun}{} of {checks} checks pass\n", checks - failed)
+}
+
+fn outcomes() -> Vec<Vec<Outcome>> {
+    SUITE.iter().map(|case| COLUMNS.iter().map(|b| check(case, b)).collect()).collect()
+}
+
+/// `stoncc conformance`: prints the matrix, and fails if any backend gets
+/// any case wrong.
+pub fn main(args: &[String]) {
+    if !args.is_empty() {
+        eprintln!("usage: stoncc conformance");
+        std::process::exit(2);
+    }
+    let outcomes = outcomes();
+    let report = report(&outcomes);
+    print!("{report}");
+    if outcomes.iter().flatten().any(|o| matches!(o, Outcome::Fail(_))) {
+        std::process::exit(1);
+    }
+}
+
+#[test]
+fn tests() {
+    // Every backend passes every case that is one of its semantics.
+    let outcomes = outcomes();
+    for (case, row) in SUITE.iter().zip(&outcomes) {
+        for (backend, outcome) in COLUMNS.iter().zip(row) {
+            assert!(!matches!(outcome, Outcome::Fail(_)), "{backend}: {} gives {outcome:?}", case.source);
+        }
+    }
+    let passed = outcomes.iter().flatten().filter(|o| **o == Outcome::Pass).count();
+    let report = report(&outcomes);
+    // The matrix as it is with every exact backend built in.
+    let exact = cfg!(all(feature = "decimal", feature = "bigint", feature = "rational"));
+    let tail = if cfg!(feature = "wasm") { "-        -        -        -" } else { "-        -        -" };
+    let header = if cfg!(feature = "wasm") {
+        "case                    expected     eval     arena    ir       vm       decimal  bigint   rational asm      wat      llvm\n"
+    } else {
+        "case                    expected     eval     arena    ir       vm       decimal  bigint   rational asm      llvm\n"
+    };
+    assert!(!exact || report.starts_with(header), "{report}");
+    assert!(!exact || report.contains(&format!("\n-7 / 2                  -3.5         -        -        -        -        ok       -        {tail}\n")), "{report}");
+    assert!(report.ends_with(&format!("\n{passed} of {passed} checks pass\n")), "{report}");
+
+    // A wrong result shows in its cell and below the matrix, and code
+    // that can't be run in its column and once below.
+    let mut wrong: Vec<Vec<Outcome>> = outcomes.iter().map(|row| row.iter().map(|o| if *o == Outcome::Skipped { o.clone() } else { Outcome::Pass }).collect()).collect();
+    wrong[0][3] = Outcome::Fail(Ok("-4".into()));
+    let llvm = COLUMNS.len() - 1;
+    wrong[0][llvm] = Outcome::Unrun("lli");
+    wrong[1][llvm] = Outcome::Unrun("lli");
+    let checks = wrong.iter().flatten().filter(|o| matches!(o, Outcome::Pass | Outcome::Fail(_))).count();
+    let report = self::report(&wrong);
+    let row = if cfg!(feature = "wasm") { "ok       ok       skip" } else { "ok       skip" };
+    assert!(!exact || report.contains(&format!("\n-7 / 2                  -3           ok       ok       ok       FAIL     -        ok       -        {row}\n")), "{report}");
+    assert!(report.ends_with(&format!("\nvm: -7 / 2 gives -4, expected -3\nllvm: skipped, running its code needs lli\n{} of {checks} checks pass\n", checks - 1)), "{report}");
+}
@@ -0,0 +1,159 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::NO_CONFIG;
+use crate::theme;
+
+/// Outcome of running one corpus file through the binary.
+struct Case {
+    path: PathBuf,
+    expected: String,
+    actual: String,
+}
+
+impl Case {
+    fn passed(&self) -> bool {
+        self.expected == self.actual
+    }
+}
+
+/// Collects `.stc` files under `dir`, recursing into subdirectories, in a
+/// stable order.
+pub fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(rd) => rd.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(e) => {
+            eprintln!("{}: cannot read {}: {e}", theme::label("error"),  dir.display());
+            std::process::exit(1);
+        }
+    };
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            walk(&path, files);
+        } else if path.extension().is_some_and(|e| e == "stc") {
+            files.push(path);
+        }
+    }
+}
+
+/// Runs `path` through a fresh instance of this binary and returns either
+/// the value of its last statement or, if it failed, its diagnostics. It
+/// runs from the file's directory, so that diagnostics name the file the
+/// same way wherever the corpus is run from.
+fn run(path: &Path) -> String {
+    let exe = env::current_exe().unwrap();
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap();
+    let output = Command::new(exe).env(NO_CONFIG, "1").current_dir(dir).arg(name).output().unwrap();
+
+    if output.status.success() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let line = stdout.trim_end();
+        line.rsplit_once(": ").map_or(line, |(_, v)| v).to_string()
+    } else {
+        String::from_utf8_lossy(&output.stderr).trim_end().to_string()
+    }
+}
+
+fn tap(cases: &[Case]) {
+    println!("TAP version 13");
+    println!("1..{}", cases.len());
+
+    for (i, case) in cases.iter().enumerate() {
+        if case.passed() {
+            println!("ok {} - {}", i + 1, case.path.display());
+            continue;
+        }
+
+        println!("not ok {} - {}", i + 1, case.path.display());
+        println!("  ---");
+        println!("  expected: {:?}", case.expected);
+        println!("  actual: {:?}", case.actual);
+        println!("  ...");
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn junit(cases: &[Case]) {
+    let failures = cases.iter().filter(|c| !c.passed()).count();
+
+    println!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    println!(
+        "<testsuite name=\"stoncc\" tests=\"{}\" failures=\"{failures}\">",
+        cases.len()
+    );
+    for case in cases {
+        let name = xml_escape(&case.path.display().to_string());
+        if case.passed() {
+            println!("  <testcase name=\"{name}\"/>");
+            continue;
+        }
+
+        println!("  <testcase name=\"{name}\">");
+        println!(
+            "    <failure message=\"expected {}, got {}\"/>",
+            xml_escape(&case.expected),
+            xml_escape(&case.actual)
+        );
+        println!("  </testcase>");
+    }
+    println!("</testsuite>");
+}
+
+/// `stoncc test-corpus [--junit] DIR`: runs every `.stc` file that has a
+/// sibling `.expected` file and reports the results. The expected file holds
+/// either the value the expression evaluates to or the diagnostics it
+/// produces.
+pub fn main(args: &[String]) {
+    let mut junit_out = false;
+    let mut dir = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--junit" => junit_out = true,
+            "--tap" => junit_out = false,
+            _ if dir.is_none() => dir = Some(PathBuf::from(arg)),
+            _ => {
+                eprintln!("{}: unexpected argument '{arg}'", theme::label("error"));
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let Some(dir) = dir else {
+        eprintln!("usage: stoncc test-corpus [--tap|--junit] DIR");
+        std::process::exit(2);
+    };
+
+    let mut files = Vec::new();
+    walk(&dir, &mut files);
+
+    let cases: Vec<Case> = files
+        .into_iter()
+        .filter_map(|path| {
+            let expected = fs::read_to_string(path.with_extension("expected")).ok()?;
+            let actual = run(&path);
+            Some(Case { path, expected: expected.trim_end().to_string(), actual })
+        })
+        .collect();
+
+    if junit_out {
+        junit(&cases);
+    } else {
+        tap(&cases);
+    }
+
+    if cases.iter().any(|c| !c.passed()) {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::{corpus, fail, Failure};
+use stoncc::parser::{self, LeafVal, Node, NodeVal};
+
+/// An operator together with its arity, which distinguishes prefix `-` from
+/// infix `-`.
+pub type Shape = (NodeVal, usize);
+
+/// A grammar production as it shows up in a parsed tree.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Production {
+    Int,
+    Bool,
+    Decimal,
+    Sym,
+    Placeholder,
+    Op(Shape),
+    /// An operator appearing directly as operand `slot` of another operator.
+    /// These pairs are what precedence and associativity decide between.
+    Nest { parent: Shape, slot: usize, child: Shape },
+}
+
+fn shape_name((v, arity): &Shape) -> String {
+    format!("{} {v}", v.fixity(*arity))
+}
+
+impl fmt::Display for Production {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Production::Int => write!(f, "integer literal"),
+            Production::Bool => write!(f, "boolean literal"),
+            Production::Decimal => write!(f, "decimal literal"),
+            Production::Sym => write!(f, "symbol"),
+            Production::Placeholder => write!(f, "template placeholder"),
+            Production::Op(shape) => write!(f, "{}", shape_name(shape)),
+            Production::Nest { parent, slot, child } => {
+                let side = match (parent.1, slot) {
+                    (1, _) => "operand",
+                    (_, 0) => "lhs",
+                    _ => "rhs",
+                };
+                write!(f, "{} as {side} of {}", shape_name(child), shape_name(parent))
+            }
+        }
+    }
+}
+
+impl Production {
+    /// Every production the grammar can produce.
+    pub fn all() -> Vec<Production> {
+        let mut all = vec![Production::Int, Production::Bool, Production::Decimal, Production::Sym, Production::Placeholder];
+        all.extend(NodeVal::SHAPES.iter().cloned().map(Production::Op));
+
+        for parent in &NodeVal::SHAPES {
+            for slot in 0..parent.1 {
+                for child in &NodeVal::SHAPES {
+                    all.push(Production::Nest { parent: parent.clone(), slot, child: child.clone() });
+                }
+            }
+        }
+
+        all
+    }
+}
+
+fn shape(node: &Node) -> Option<Shape> {
+    match node {
+        Node::Node { v, children, .. } => Some((v.clone(), children.len())),
+        Node::Leaf(..) | Node::Error(_) => None,
+    }
+}
+
+/// Counts how often each production occurs in a set of trees.
+#[derive(Default)]
+pub struct Coverage {
+    counts: BTreeMap<Production, usize>,
+}
+
+impl Coverage {
+    pub fn add(&mut self, node: &Node) {
+        let p = match node {
+            Node::Leaf(LeafVal::Int(_), _) => Production::Int,
+            Node::Leaf(LeafVal::Bool(_), _) => Production::Bool,
+            Node::Leaf(LeafVal::Decimal(_), _) => Production::Decimal,
+            Node::Leaf(LeafVal::Sym(_), _) => Production::Sym,
+            Node::Leaf(LeafVal::Placeholder(_), _) => Production::Placeholder,
+            // Not part of the grammar.
+            Node::Error(_) => return,
+            Node::Node { children, .. } => {
+                let parent = shape(node).unwrap();
+                for (slot, c) in children.iter().enumerate() {
+                    if let Some(child) = shape(c) {
+                        *self.counts.entry(Production::Nest { parent: parent.clone(), slot, child }).or_default() += 1;
+                    }
+                    self.add(c);
+                }
+                Production::Op(parent)
+            }
+        };
+
+        *self.counts.entry(p).or_default() += 1;
+    }
+
+    pub fn covers(&self, p: &Production) -> bool {
+        self.counts.contains_key(p)
+    }
+
+    pub fn missing(&self) -> Vec<Production> {
+        Production::all().into_iter().filter(|p| !self.covers(p)).collect()
+    }
+}
+
+/// `stoncc coverage [--verbose] PATH...`: reports which grammar productions
+/// the given expression files (or directories of `.stc` files) exercise.
+pub fn main(args: &[String]) {
+    let verbose = args.iter().any(|a| a == "--verbose");
+    let mut files = Vec::new();
+
+    for arg in args.iter().filter(|a| *a != "--verbose") {
+        let path = PathBuf::from(arg);
+        if path.is_dir() {
+            corpus::walk(&path, &mut files);
+        } else {
+            files.push(path);
+        }
+    }
+
+    if files.is_empty() {
+        eprintln!("usage: stoncc coverage [--verbose] PATH...");
+        std::process::exit(2);
+    }
+
+    let mut cov = Coverage::default();
+    for file in &files {
+        let s = fs::read(file).unwrap_or_else(|e| fail(Failure::Run, format!("cannot read {}: {e}", file.display())));
+        match parser::program(&s).0 {
+            Ok(program) => program.stmts.iter().flat_map(parser::Stmt::exprs).for_each(|e| cov.add(e)),
+            Err(e) => eprintln!("{}: {e}", file.display()),
+        }
+    }
+
+    let all = Production::all();
+    let missing = cov.missing();
+    let covered = all.len() - missing.len();
+    println!(
+        "{} files, {covered}/{} productions covered ({:.1}%)",
+        files.len(),
+        all.len(),
+        100.0 * covered as f64 / all.len() as f64
+    );
+
+    if verbose {
+        for (p, n) in &cov.counts {
+            println!("  {n:6}  {p}");
+        }
+    }
+    for p in &missing {
+        println!("  missing: {p}");
+    }
+}
@@ -0,0 +1,276 @@
+//! Fixed-point decimal evaluation (`--decimal`). Every value is an `i128`
+//! count of `10^-scale` units, so `0.1 + 0.2` is exactly `0.3`. Products and
+//! quotients are computed with a 256-bit intermediate and rounded half to
+//! even (banker's rounding) back to the scale.
+
+use crate::arith::{ArithError, NonInteger};
+use crate::lexer::Span;
+use crate::limits::{LimitExceeded, Meter};
+use crate::parser::{LeafVal, Node, NodeVal, Nodes, Value};
+use crate::eval::{walk, Numbers};
+use crate::{Builtins, Env, EvalError};
+
+pub const DEFAULT_SCALE: u32 = 10;
+/// Largest scale for which `10^scale` still leaves room for integer digits.
+pub const MAX_SCALE: u32 = 30;
+
+fn pow10(scale: u32) -> i128 {
+    10i128.pow(scale)
+}
+
+/// `a * b` as a 256-bit `(hi, lo)` pair.
+fn mul_wide(a: u128, b: u128) -> (u128, u128) {
+    let (a1, a0) = (a >> 64, a & u64::MAX as u128);
+    let (b1, b0) = (b >> 64, b & u64::MAX as u128);
+
+    let lo = a0 * b0;
+    let (mid, carry) = (a0 * b1).overflowing_add(a1 * b0);
+    let (lo, c) = lo.overflowing_add(mid << 64);
+    let hi = a1 * b1 + (mid >> 64) + ((carry as u128) << 64) + c as u128;
+
+    (hi, lo)
+}
+
+/// Divides a 256-bit value by `d`, returning quotient and remainder, or
+/// `None` if the quotient doesn't fit in 128 bits.
+fn div_wide((hi, lo): (u128, u128), d: u128) -> Option<(u128, u128)> {
+    if hi >= d {
+        return None;
+    }
+
+    let (mut q, mut r) = (0u128, hi);
+    for i in (0..128).rev() {
+        let carry = r >> 127;
+        r = (r << 1) | ((lo >> i) & 1);
+        q <<= 1;
+        if carry == 1 || r >= d {
+            r = r.wrapping_sub(d);
+            q |= 1;
+        }
+    }
+
+    Some((q, r))
+}
+
+/// `a * b / d`, rounded half to even.
+fn mul_div(a: i128, b: i128, d: i128) -> Result<i128, ArithError> {
+    if d == 0 {
+        return Err(ArithError::DivisionByZero);
+    }
+
+    let negative = (a < 0) ^ (b < 0) ^ (d < 0);
+    let d = d.unsigned_abs();
+    let (mut q, r) = div_wide(mul_wide(a.unsigned_abs(), b.unsigned_abs()), d).ok_or(ArithError::Overflow)?;
+
+    if r > d - r || (r == d - r && q & 1 == 1) {
+        q += 1;
+    }
+
+    let limit = if negative { i128::MIN.unsigned_abs() } else { i128::MAX as u128 };
+    if q > limit {
+        return Err(ArithError::Overflow);
+    }
+
+    Ok(if negative { (q as i128).wrapping_neg() } else { q as i128 })
+}
+
+/// Converts a literal like `12`, `0.125` or `1e-3` to units, rounding extra
+/// fractional digits half to even, unless it is too large.
+pub fn parse(text: &str, scale: u32) -> Option<i128> {
+    let (mantissa, exp) = match text.split_once(['e', 'E']) {
+        Some((m, e)) => (m, e.parse::<i64>().ok()?),
+        None => (text, 0),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{int}{frac}");
+    let v: i128 = digits.parse().ok()?;
+    if v == 0 {
+        return Some(0);
+    }
+
+    // Digits after the point once the exponent has moved it.
+    let frac_len = frac.len() as i64 - exp;
+    if frac_len <= scale as i64 {
+        u32::try_from(scale as i64 - frac_len).ok()
+            .and_then(|n| 10i128.checked_pow(n))
+            .and_then(|p| v.checked_mul(p))
+    } else {
+        let n = u32::try_from(frac_len - scale as i64).unwrap_or(u32::MAX);
+        mul_div(v, 1, 10i128.checked_pow(n).unwrap_or(i128::MAX)).ok()
+    }
+}
+
+/// `v` in units of `10^-scale`.
+pub fn from_int(v: i32, scale: u32) -> i128 {
+    v as i128 * pow10(scale)
+}
+
+/// `v` in units of `10^-scale`, if it is a number that fits. Floats are
+/// converted from their shortest decimal representation, so `0.1` is
+/// exactly one tenth.
+pub fn from_value(v: Value, scale: u32) -> Option<i128> {
+    match v {
+        Value::Int(v) => Some(from_int(v, scale)),
+        Value::Wide(v) => v.checked_mul(pow10(scale)),
+        Value::Float(v) => parse(&format!("{v:?}"), scale),
+        Value::Duration(_) | Value::Quantity(..) => None,
+    }
+}
+
+pub fn format(v: i128, scale: u32) -> String {
+    if scale == 0 {
+        return v.to_string();
+    }
+
+    let sign = if v < 0 { "-" } else { "" };
+    let v = v.unsigned_abs();
+    let unit = pow10(scale) as u128;
+    format!("{sign}{}.{:0width$}", v / unit, v % unit, width = scale as usize)
+}
+
+/// The integer value of `v`, which must not have a fractional part.
+fn integral(op: &NodeVal, v: i128, scale: u32) -> Result<i128, ArithError> {
+    if v % pow10(scale) != 0 {
+        return Err(ArithError::NotInteger(op.clone(), NonInteger::Decimal));
+    }
+    Ok(v / pow10(scale))
+}
+
+fn apply(op: &NodeVal, args: &[i128], scale: u32, span: Span) -> Result<i128, EvalError> {
+    let one = pow10(scale);
+    let arith = |e| EvalError::Arith(e, span);
+    let exact = |v: Option<i128>| v.ok_or(arith(ArithError::Overflow));
+
+    Ok(match (op, args) {
+        (NodeVal::Add, [a]) => *a,
+        (NodeVal::Sub, [a]) => exact(a.checked_neg())?,
+        (NodeVal::Add, [a, b]) => exact(a.checked_add(*b))?,
+        (NodeVal::Sub, [a, b]) => exact(a.checked_sub(*b))?,
+        (NodeVal::Mul, [a, b]) => mul_div(*a, *b, one).map_err(arith)?,
+        (NodeVal::Div, [a, b]) => mul_div(*a, one, *b).map_err(arith)?,
+        // Units of the same scale have the remainder of integers.
+        (NodeVal::Mod, [_, 0]) => return Err(arith(ArithError::DivisionByZero)),
+        (NodeVal::Mod, [a, b]) => exact(a.checked_rem(*b))?,
+        // By squaring, rounding each product to the scale.
+        (NodeVal::Exp, [a, b]) => {
+            let n = integral(op, *b, scale).map_err(arith)?;
+            let (mut acc, mut base, mut e) = (one, *a, n.unsigned_abs());
+            while e > 0 {
+                if e & 1 == 1 {
+                    acc = mul_div(acc, base, one).map_err(arith)?;
+                }
+                e >>= 1;
+                if e > 0 {
+                    base = mul_div(base, base, one).map_err(arith)?;
+                }
+            }
+            if n < 0 { mul_div(one, one, acc).map_err(arith)? } else { acc }
+        }
+        (NodeVal::Fac, [a]) => {
+            let n = integral(op, *a, scale).map_err(arith)?;
+            if n < 0 {
+                return Err(arith(ArithError::NegativeFactorial));
+            }
+            // Past 34! nothing fits, so this stops soon.
+            (2..=n).try_fold(one, |acc, k| mul_div(acc, k * one, one)).map_err(arith)?
+        }
+        (op, [a, b]) if op.is_comparison() => op.compare(a, b) as i128 * one,
+        (NodeVal::Call(name), _) => match (name.as_str(), args) {
+            ("abs", [a]) => exact(a.checked_abs())?,
+            ("min", [_, ..]) => *args.iter().min().unwrap(),
+            ("max", [_, ..]) => *args.iter().max().unwrap(),
+            _ => return Err(EvalError::Unsupported(format!("function {name}() is not available in --decimal mode"), span)),
+        },
+        (NodeVal::AddWrap | NodeVal::MulWrap | NodeVal::AddSat | NodeVal::MulSat, _)
+        | (NodeVal::Shl | NodeVal::Shr | NodeVal::BitAnd | NodeVal::BitOr | NodeVal::BitNot, _) => {
+            let msg = format!("operator {op} models fixed-width integers and is not available in --decimal mode");
+            return Err(EvalError::Unsupported(msg, span));
+        }
+        _ => unreachable!("operator {op} cannot take {} operands", args.len()),
+    })
+}
+
+/// Evaluates `ast` in fixed point with `scale` fractional digits. Variables
+/// in `env` are already in units. Of `builtins`, only the functions the
+/// program defined itself are used; the Rust ones work on `Value`s.
+pub fn eval(ast: &Node, env: &Env<i128>, builtins: &Builtins, scale: u32, meter: &mut Meter) -> Result<i128, EvalError> {
+    walk(&Fixed(scale), Nodes, ast, env, builtins, meter)
+}
+
+/// Fixed-point units of `10^-scale`.
+struct Fixed(u32);
+
+impl Numbers for Fixed {
+    type V = i128;
+
+    fn literal(&self, leaf: &LeafVal, span: Span) -> Result<i128, EvalError> {
+        match leaf {
+            LeafVal::Int(v) => Ok(from_int(*v, self.0)),
+            LeafVal::Decimal(s) => parse(s, self.0).ok_or(EvalError::Arith(ArithError::Overflow, span)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn is_true(&self, v: &i128) -> bool {
+        *v != 0
+    }
+
+    fn truth(&self, b: bool) -> i128 {
+        from_int(b as i32, self.0)
+    }
+
+    fn apply(&self, v: &NodeVal, args: &[i128], span: Span, _: &Builtins, _: &Meter) -> Result<i128, EvalError> {
+        apply(v, args, self.0, span)
+    }
+
+    fn check(&self, v: &i128, meter: &Meter) -> Result<(), LimitExceeded> {
+        meter.value(*v)
+    }
+}
+
+#[test]
+fn tests() {
+    let result = |s: &str, scale| {
+        let mut meter = Meter::new(Default::default());
+        eval(&crate::parser::expr(s.as_bytes()).unwrap(), &Env::new(), &Builtins::default(), scale, &mut meter)
+    };
+    let run = |s: &str, scale| format(result(s, scale).unwrap(), scale);
+
+    assert_eq!(run("0.1 + 0.2", 10), "0.3000000000");
+    assert_eq!(run("1 / 3", 4), "0.3333");
+    assert_eq!(run("2 / 3", 4), "0.6667");
+    assert_eq!(run("-2 / 3", 4), "-0.6667");
+    assert_eq!(run("19.99 * 3", 2), "59.97");
+    assert_eq!(run("2 ^ -2", 2), "0.25");
+    assert_eq!(run("5!", 0), "120");
+    assert_eq!(run("max(0.1, abs(-0.25)) - min(1, 2)", 2), "-0.75");
+    assert_eq!(run("1e-3 + 2.5E2", 4), "250.0010");
+    assert_eq!(run("1e-3", 2), "0.00");
+    assert_eq!(run("1.1 ^ 3 + 1 ^ 2000000000", 2), "2.33");
+    assert_eq!(run("-7.5 % 2", 1), "-1.5");
+    assert_eq!(from_value(Value::Float(0.1), 2), Some(10));
+    assert_eq!(from_value(Value::Wide(i128::MAX), 2), None);
+    assert_eq!(run("0.01 > 0 && 1 / 3 ? 0.5 : 1 / 0", 2), "0.50");
+
+    // Halves round to the even neighbour.
+    assert_eq!(run("0.125", 2), "0.12");
+    assert_eq!(run("0.135", 2), "0.14");
+    assert_eq!(run("1 / 8", 2), "0.12");
+
+    // Products beyond 128 bits are fine as long as the result fits.
+    assert_eq!(run("1000000000.0 * 1000000000.0 / 7", 18), "142857142857142857.142857142857142857");
+
+    // Errors, at the operator or literal that has no result.
+    let span = |start, end| Span { start, end };
+    assert_eq!(result("1 + 1 / (0.5 - 0.5)", 2), Err(EvalError::Arith(ArithError::DivisionByZero, span(4, 19))));
+    assert_eq!(result("1 % 0.0", 2), Err(EvalError::Arith(ArithError::DivisionByZero, span(0, 7))));
+    assert_eq!(result("0 ^ -1", 2), Err(EvalError::Arith(ArithError::DivisionByZero, span(0, 6))));
+    assert_eq!(result("10 ^ 40", 0), Err(EvalError::Arith(ArithError::Overflow, span(0, 7))));
+    assert_eq!(result("35!", 0), Err(EvalError::Arith(ArithError::Overflow, span(0, 3))));
+    assert_eq!(result("(-1)!", 2), Err(EvalError::Arith(ArithError::NegativeFactorial, span(0, 5))));
+    assert_eq!(result("2 ^ 0.5", 2), Err(EvalError::Arith(ArithError::NotInteger(NodeVal::Exp, NonInteger::Decimal), span(0, 7))));
+    assert_eq!(ArithError::NotInteger(NodeVal::Exp, NonInteger::Decimal).to_string(), "operator ^ takes integers, not decimals");
+    assert_eq!(result("1 + 1e40", 2), Err(EvalError::Arith(ArithError::Overflow, span(4, 8))));
+    assert_eq!(result("sqrt(2)", 2).map_err(|e| e.to_string()), Err("function sqrt() is not available in --decimal mode".into()));
+    assert_eq!(result("1 & 2", 2).map_err(|e| e.code()), Err("unsupported"));
+}
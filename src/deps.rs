@@ -0,0 +1,289 @@
+//! `stoncc deps`: which definitions of a file use which, as text or as a
+//! Graphviz graph, with the cycles among them and the definitions nothing
+//! uses. Only top-level assignments and function definitions count as
+//! definitions, as for `stoncc shake`.
+
+use std::collections::HashMap;
+use std::fs;
+
+use stoncc::dot::Graph;
+use stoncc::parser::{self, Node, NodeVal, Program, Stmt};
+use stoncc::{Builtins, LeafVal, Visitor};
+
+use crate::{fail, Failure};
+
+/// What a definition defines. Variables and functions have names of their
+/// own, so `f` and `f(x)` don't clash.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Name {
+    Var(String),
+    Fn(String),
+}
+
+impl Name {
+    /// `x` or `f()`.
+    pub fn label(&self) -> String {
+        match self {
+            Name::Var(name) => name.clone(),
+            Name::Fn(name) => format!("{name}()"),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!("'{}'", self.label())
+    }
+}
+
+/// The variables and functions a tree uses, but for `params`.
+struct Uses<'a> {
+    params: &'a [String],
+    names: Vec<Name>,
+}
+
+impl Visitor for Uses<'_> {
+    fn enter(&mut self, node: &Node) {
+        match node {
+            Node::Leaf(LeafVal::Sym(s), _) if !self.params.iter().any(|p| p == s.as_str()) => self.names.push(Name::Var(s.to_string())),
+            Node::Node { v: NodeVal::Call(name), .. } => self.names.push(Name::Fn(name.clone())),
+            _ => {}
+        }
+    }
+}
+
+/// What `node` uses, in the order it uses them, repeats included.
+pub fn uses(node: &Node, params: &[String]) -> Vec<Name> {
+    let mut uses = Uses { params, names: Vec::new() };
+    node.visit(&mut uses);
+    uses.names
+}
+
+/// What `stmt` uses. The parameters of a function are its own.
+pub fn needs(stmt: &Stmt) -> Vec<Name> {
+    match stmt {
+        Stmt::Def { params, body, .. } => uses(body, params),
+        stmt => stmt.exprs().into_iter().flat_map(|e| uses(e, &[])).collect(),
+    }
+}
+
+/// What `stmt` defines, if it is a definition.
+pub fn defines(stmt: &Stmt) -> Option<Name> {
+    match stmt {
+        Stmt::Assign { name, .. } => Some(Name::Var(name.clone())),
+        Stmt::Def { name, .. } => Some(Name::Fn(name.clone())),
+        _ => None,
+    }
+}
+
+/// The statement of `program` defining each name, or the first name
+/// defined twice.
+pub fn definitions(program: &Program) -> Result<HashMap<Name, usize>, String> {
+    let mut defs = HashMap::new();
+    for (i, stmt) in program.stmts.iter().enumerate() {
+        let Some(name) = defines(stmt) else { continue };
+        if defs.insert(name.clone(), i).is_some() {
+            return Err(format!("{} is defined more than once", name.describe()));
+        }
+    }
+    Ok(defs)
+}
+
+/// The definitions of a file and what each uses.
+pub struct Deps {
+    /// Each definition in source order, with the definitions it uses, as
+    /// indices into this list, and the names it uses that nothing defines.
+    pub defs: Vec<(Name, Vec<usize>, Vec<Name>)>,
+    /// Every cycle of definitions using each other, as indices, each
+    /// starting from its first definition.
+    pub cycles: Vec<Vec<usize>>,
+    /// The definitions that neither another definition nor any other
+    /// statement uses.
+    pub unused: Vec<usize>,
+}
+
+pub fn deps(program: &Program) -> Result<Deps, String> {
+    let by_name = definitions(program)?;
+    let stmts: Vec<usize> = (0..program.stmts.len()).filter(|&i| defines(&program.stmts[i]).is_some()).collect();
+    let index: HashMap<usize, usize> = stmts.iter().enumerate().map(|(d, &i)| (i, d)).collect();
+    let builtins = Builtins::default();
+
+    let mut used = vec![false; stmts.len()];
+    let mut defs = Vec::new();
+    for (i, stmt) in program.stmts.iter().enumerate() {
+        let mut to = Vec::new();
+        let mut undefined = Vec::new();
+        for name in needs(stmt) {
+            match by_name.get(&name) {
+                Some(&j) => to.push(index[&j]),
+                None if matches!(&name, Name::Fn(f) if builtins.arity(f).is_some()) => {}
+                None => undefined.push(name),
+            }
+        }
+        to.sort_unstable();
+        to.dedup();
+        undefined.dedup();
+
+        let this = index.get(&i).copied();
+        to.iter().filter(|&&d| Some(d) != this).for_each(|&d| used[d] = true);
+        if let Some(name) = defines(stmt) {
+            defs.push((name, to, undefined));
+        }
+    }
+
+    let cycles = cycles(&defs);
+    let unused = (0..defs.len()).filter(|&d| !used[d]).collect();
+    Ok(Deps { defs, cycles, unused })
+}
+
+/// The cycles a depth-first walk finds, each once, whatever definition it
+/// was entered by.
+fn cycles(defs: &[(Name, Vec<usize>, Vec<Name>)]) -> Vec<Vec<usize>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        New,
+        Open,
+        Done,
+    }
+    let mut marks = vec![Mark::New; defs.len()];
+    let mut out: Vec<Vec<usize>> = Vec::new();
+
+    for root in 0..defs.len() {
+        if marks[root] != Mark::New {
+            continue;
+        }
+        // The path from `root`, each definition with the edges it has yet
+        // to follow. A stack of its own, so long chains take no stack.
+        marks[root] = Mark::Open;
+        let mut path = vec![(root, defs[root].1.iter())];
+        while let Some((d, edges)) = path.last_mut() {
+            let d = *d;
+            let Some(&to) = edges.next() else {
+                marks[d] = Mark::Done;
+                path.pop();
+                continue;
+            };
+            match marks[to] {
+                Mark::New => {
+                    marks[to] = Mark::Open;
+                    path.push((to, defs[to].1.iter()));
+                }
+                Mark::Open => {
+                    let from = path.iter().position(|&(p, _)| p == to).unwrap();
+                    let mut cycle: Vec<usize> = path[from..].iter().map(|&(p, _)| p).collect();
+                    let first = (0..cycle.len()).min_by_key(|&k| cycle[k]).unwrap();
+                    cycle.rotate_left(first);
+                    if !out.contains(&cycle) {
+                        out.push(cycle);
+                    }
+                }
+                Mark::Done => {}
+            }
+        }
+    }
+    out
+}
+
+impl Deps {
+    /// One line per definition, with what it uses, then the cycles and the
+    /// unused definitions.
+    pub fn to_text(&self) -> String {
+        let label = |d: &usize| self.defs[*d].0.label();
+        let mut out = String::new();
+        for (name, to, undefined) in &self.defs {
+            let mut uses: Vec<String> = to.iter().map(label).collect();
+            uses.extend(undefined.iter().map(|n| format!("{} (not defined)", n.label())));
+            match uses.is_empty() {
+                true => out += &format!("{}\n", name.label()),
+                false => out += &format!("{} -> {}\n", name.label(), uses.join(", ")),
+            }
+        }
+        out + &self.findings()
+    }
+
+    /// The cycles and the unused definitions, a line each.
+    pub fn findings(&self) -> String {
+        let label = |d: &usize| self.defs[*d].0.label();
+        let mut out = String::new();
+        for cycle in &self.cycles {
+            let names: Vec<String> = cycle.iter().chain(&cycle[..1]).map(label).collect();
+            out += &format!("cycle: {}\n", names.join(" -> "));
+        }
+        if !self.unused.is_empty() {
+            out += &format!("unused: {}\n", self.unused.iter().map(label).collect::<Vec<_>>().join(", "));
+        }
+        out
+    }
+
+    /// The graph, with an edge from each definition to each name it uses.
+    /// Variables are boxes and functions ellipses. Names nothing defines
+    /// are drawn once each, labelled as such.
+    pub fn to_graph(&self) -> Graph {
+        let mut graph = Graph::new();
+        let nodes: Vec<usize> = self.defs.iter().map(|(name, ..)| graph.node(name.label(), matches!(name, Name::Var(_)))).collect();
+        let mut undefined = HashMap::new();
+        for (d, (_, to, names)) in self.defs.iter().enumerate() {
+            to.iter().for_each(|&t| graph.edge(nodes[d], nodes[t]));
+            for name in names {
+                let n = *undefined
+                    .entry(name.clone())
+                    .or_insert_with(|| graph.node(format!("{} (not defined)", name.label()), matches!(name, Name::Var(_))));
+                graph.edge(nodes[d], n);
+            }
+        }
+        graph
+    }
+}
+
+/// `stoncc deps FILE [--format=text|dot]`.
+pub fn main(args: &[String]) {
+    let usage = || -> ! {
+        eprintln!("usage: stoncc deps FILE [--format=text|dot]");
+        std::process::exit(2);
+    };
+    let mut dot = false;
+    let mut path = None;
+    for arg in args {
+        match arg.as_str() {
+            "--format=dot" => dot = true,
+            "--format=text" => dot = false,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let Some(path) = path else { usage() };
+
+    let s = fs::read(path).unwrap_or_else(|e| fail(Failure::Run, format!("cannot read {path}: {e}")));
+    let program = parser::program(&s).0.unwrap_or_else(|e| fail(Failure::Run, format!("{path}: {e}")));
+    let deps = deps(&program).unwrap_or_else(|e| fail(Failure::Run, format!("{path}: {e}")));
+    if dot {
+        // The findings go to standard error, to keep the graph valid.
+        print!("{}", deps.to_graph());
+        eprint!("{}", deps.findings());
+    } else {
+        print!("{}", deps.to_text());
+    }
+}
+
+#[test]
+fn tests() {
+    let program = |s: &str| parser::program(s.as_bytes()).0.unwrap();
+    let lib = program("rate = 0.05\nunused = 99\nresult = total(base)\ntotal(x) = x + fee(x) + sqrt(x)\nbase = 100\nfee(x) = x * rate + flat\nresult + 1");
+    let found = deps(&lib).unwrap();
+    assert_eq!(
+        found.to_text(),
+        "rate\nunused\nresult -> total(), base\ntotal() -> fee()\nbase\nfee() -> rate, flat (not defined)\nunused: unused\n"
+    );
+    let dot = found.to_graph().to_string();
+    assert!(dot.contains("n0 [label=\"rate\", shape=box];") && dot.contains("n3 [label=\"total()\"];"), "{dot}");
+    assert!(dot.contains("n5 -> n0;\n  n5 -> n6;") && dot.contains("n6 [label=\"flat (not defined)\", shape=box];"), "{dot}");
+
+    // Cycles are found once each, however they are entered; a function
+    // calling itself is used only if something else calls it too.
+    let looped = deps(&program("a = b + 1\nb = c\nc = a * 2\nf(n) = n < 1 || f(n - 1)\ng(x) = h(x)\nh(x) = g(x) + a")).unwrap();
+    assert_eq!(looped.findings(), "cycle: a -> b -> c -> a\ncycle: f() -> f()\ncycle: g() -> h() -> g()\nunused: f()\n");
+    assert_eq!(deps(&program("x = 1\nx = 2")).err(), Some("'x' is defined more than once".into()));
+
+    let n = 100_000;
+    let chain: String = (1..n).map(|i| format!("v{i} = v{}\n", i - 1)).collect();
+    let long = deps(&program(&format!("v0 = v{}\n{chain}", n - 1))).unwrap();
+    assert_eq!((long.cycles.len(), long.cycles[0].len(), long.unused.len()), (1, n, 0));
+}
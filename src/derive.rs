@@ -0,0 +1,195 @@
+//! Symbolic differentiation.
+//!
+//! Derivatives follow stoncc's arithmetic: between integers `1 / 2` is
+//! still 0, so divide by a decimal (`x / 2.0`) to keep the fraction.
+
+use core::mem;
+
+use crate::ast::Visitor;
+use crate::parser::{LeafVal, Node, NodeVal};
+use crate::prelude::*;
+
+fn int(n: i32) -> Node {
+    Node::leaf(LeafVal::Int(n))
+}
+
+fn is_zero(node: &Node) -> bool {
+    match node {
+        Node::Leaf(LeafVal::Int(0), _) => true,
+        Node::Leaf(LeafVal::Decimal(d), _) => d.parse::<f64>() == Ok(0.0),
+        _ => false,
+    }
+}
+
+// These drop the zeros the rules produce for constant operands, which
+// `simplify` keeps: `x * 0` fails if `x` does, so it cannot fold to `0`.
+// A derivative has no such obligation.
+
+fn neg(a: Node) -> Node {
+    if is_zero(&a) { a } else { Node::op(NodeVal::Sub, vec![a]) }
+}
+
+fn add(a: Node, b: Node) -> Node {
+    match (is_zero(&a), is_zero(&b)) {
+        (true, _) => b,
+        (_, true) => a,
+        _ => Node::op(NodeVal::Add, vec![a, b]),
+    }
+}
+
+fn sub(a: Node, b: Node) -> Node {
+    match (is_zero(&a), is_zero(&b)) {
+        (true, _) => neg(b),
+        (_, true) => a,
+        _ => Node::op(NodeVal::Sub, vec![a, b]),
+    }
+}
+
+fn mul(a: Node, b: Node) -> Node {
+    if is_zero(&a) || is_zero(&b) {
+        return int(0);
+    }
+    Node::op(NodeVal::Mul, vec![a, b])
+}
+
+fn div(a: Node, b: Node) -> Node {
+    if is_zero(&a) { a } else { Node::op(NodeVal::Div, vec![a, b]) }
+}
+
+fn exp(a: Node, b: Node) -> Node {
+    Node::op(NodeVal::Exp, vec![a, b])
+}
+
+fn call(name: &str, args: Vec<Node>) -> Node {
+    Node::op(NodeVal::Call(name.to_string()), args)
+}
+
+/// Whether `var` occurs in `node`.
+fn mentions(node: &Node, var: &str) -> bool {
+    let mut todo = vec![node];
+    while let Some(node) = todo.pop() {
+        match node {
+            Node::Leaf(LeafVal::Sym(s), _) if s == var => return true,
+            Node::Leaf(..) | Node::Error(_) => {}
+            Node::Node { children, .. } => todo.extend(children),
+        }
+    }
+    false
+}
+
+/// `d/dvar a^b`, by the power rule and the chain rule, given `da`.
+fn power(a: &Node, b: &Node, da: Result<Node, String>, var: &str) -> Result<Node, String> {
+    if mentions(b, var) {
+        return Err(format!("cannot differentiate a power whose exponent depends on {var}: that needs a logarithm"));
+    }
+    let n = b.clone();
+    Ok(mul(mul(n.clone(), exp(a.clone(), sub(n, int(1)))), da?))
+}
+
+/// The derivative of `node` given those of its operands, `ds`. Those are
+/// only looked at where they are needed, so that an operand without a
+/// derivative only fails a node whose derivative uses it.
+fn derive(node: &Node, mut ds: Vec<Result<Node, String>>, var: &str) -> Result<Node, String> {
+    let (v, children) = match node {
+        Node::Leaf(LeafVal::Sym(s), _) => return Ok(int((s == var) as i32)),
+        Node::Leaf(LeafVal::Int(_) | LeafVal::Bool(_) | LeafVal::Decimal(_), _) => return Ok(int(0)),
+        Node::Leaf(LeafVal::Placeholder(n), _) => return Err(format!("cannot differentiate the placeholder ?{n}")),
+        Node::Error(_) => return Err("cannot differentiate what did not parse".to_string()),
+        Node::Node { v, children, .. } => (v, &children[..]),
+    };
+    let mut d = |i: usize| mem::replace(&mut ds[i], Ok(int(0)));
+
+    Ok(match (v, children) {
+        (NodeVal::Add, [_]) => d(0)?,
+        (NodeVal::Sub, [_]) => neg(d(0)?),
+        (NodeVal::Add, [_, _]) => add(d(0)?, d(1)?),
+        (NodeVal::Sub, [_, _]) => sub(d(0)?, d(1)?),
+        (NodeVal::Mul, [a, b]) => add(mul(d(0)?, b.clone()), mul(a.clone(), d(1)?)),
+        (NodeVal::Div, [a, b]) => {
+            let da = d(0);
+            match d(1)? {
+                db if is_zero(&db) => div(da?, b.clone()),
+                db => div(sub(mul(da?, b.clone()), mul(a.clone(), db)), exp(b.clone(), int(2))),
+            }
+        }
+        (NodeVal::Exp, [a, b]) => power(a, b, d(0), var)?,
+        // Comparisons and the logical operators are constant on either
+        // side of where they change value, and `?:` is whichever branch
+        // it takes.
+        (v, _) if v.is_comparison() => int(0),
+        (NodeVal::And | NodeVal::Or, _) => int(0),
+        (NodeVal::Cond, [c, _, _]) => Node::op(NodeVal::Cond, vec![c.clone(), d(1)?, d(2)?]),
+        // Calls are taken to be to the builtins of the same name.
+        (NodeVal::Call(f), [a]) if f == "sqrt" => div(d(0)?, mul(int(2), call("sqrt", vec![a.clone()]))),
+        (NodeVal::Call(f), [a]) if f == "abs" => mul(div(a.clone(), call("abs", vec![a.clone()])), d(0)?),
+        (NodeVal::Call(f), [a, b]) if f == "pow" => power(a, b, d(0), var)?,
+        (NodeVal::Call(f), _) => return Err(format!("cannot differentiate {f}()")),
+        (v, _) => return Err(format!("cannot differentiate '{v}'")),
+    })
+}
+
+/// Differentiates every node once its operands are, keeping a stack of
+/// their derivatives rather than recursing, so deep trees take memory
+/// rather than stack.
+struct Derive<'a> {
+    var: &'a str,
+    ds: Vec<Result<Node, String>>,
+}
+
+impl Visitor for Derive<'_> {
+    fn leave(&mut self, node: &Node) {
+        let n = match node {
+            Node::Node { children, .. } => children.len(),
+            _ => 0,
+        };
+        let ds = self.ds.split_off(self.ds.len() - n);
+        let d = derive(node, ds, self.var);
+        self.ds.push(d);
+    }
+}
+
+impl Node {
+    /// The derivative of the expression with respect to `var`, simplified.
+    /// Fails for operators without a derivative in closed form (`!`, the
+    /// wrapping and saturating operators, `min`, `max` and calls to other
+    /// functions) and for powers with `var` in the exponent.
+    pub fn differentiate(&self, var: &str) -> Result<Node, String> {
+        let mut d = Derive { var, ds: Vec::new() };
+        self.visit(&mut d);
+        Ok(d.ds.pop().unwrap()?.simplify())
+    }
+}
+
+#[test]
+fn tests() {
+    let diff = |s: &str, var: &str| crate::parser::expr(s.as_bytes()).unwrap().differentiate(var).map(|d| d.to_string());
+    let d = |s: &str| diff(s, "x").unwrap();
+
+    assert_eq!(d("x^3 + 2*x + 1"), "(+ (* 3 (^ x 2)) 2)");
+    assert_eq!(d("(x + 1)^2"), "(* 2 (+ x 1))");
+    assert_eq!(d("x^n"), "(* n (^ x (- n 1)))");
+    assert_eq!(d("x * y"), "y");
+    assert_eq!(d("-x - 0.5 * y"), "-1");
+    assert_eq!(d("5 + y"), "0");
+    assert_eq!(d("x / y"), "(/ 1 y)");
+    assert_eq!(d("1 / x"), "(/ -1 (^ x 2))");
+    assert_eq!(d("x / 2.0"), "(/ 1 2.0)");
+    assert_eq!(d("sqrt(x^2 + 1)"), "(/ (* 2 x) (* 2 (sqrt (+ (^ x 2) 1))))");
+    assert_eq!(d("abs(x)"), "(/ x (abs x))");
+    assert_eq!(d("pow(3 * x, 2)"), "(* (* 2 (* 3 x)) 3)");
+    assert_eq!(d("x < 0 ? -x : x"), "(?: (< x 0) -1 1)");
+    assert_eq!(diff("x * y", "y"), Ok("x".into()));
+
+    assert_eq!(diff("2 ^ x", "x"), Err("cannot differentiate a power whose exponent depends on x: that needs a logarithm".into()));
+    assert_eq!(diff("x!", "x"), Err("cannot differentiate '!'".into()));
+    assert_eq!(diff("max(x, 1)", "x"), Err("cannot differentiate max()".into()));
+    assert_eq!(diff("?1 * x", "x"), Err("cannot differentiate the placeholder ?1".into()));
+    // Only operands the derivative uses need one.
+    assert_eq!(d("x ^ (n!)"), "(* (! n) (^ x (- (! n) 1)))");
+    assert_eq!(d("(y! < 1) + x"), "1");
+
+    // As deep as the parser allows, on a thread with a small stack.
+    let deep = format!("{}x", "-".repeat(crate::MAX_DEPTH - 1));
+    let minus = std::thread::Builder::new().stack_size(1 << 21).spawn(move || diff(&deep, "x")).unwrap().join().unwrap();
+    assert_eq!(minus, Ok("-1".into()));
+}
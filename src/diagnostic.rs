@@ -0,0 +1,53 @@
+use stoncc::lexer::Span;
+
+use crate::theme::{self, Role};
+
+/// Formats a message about `span` of the file `path` in the style of rustc:
+/// a header, the `file:line:column` location, and the offending source line
+/// with carets under the span, styled in the current theme.
+pub fn render(severity: &str, message: &str, path: &str, src: &[u8], span: Span) -> String {
+    let start = span.start.min(src.len());
+    let line_start = src[..start].iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1);
+    let line_end = src[start..].iter().position(|&c| c == b'\n').map_or(src.len(), |i| start + i);
+
+    let line_no = 1 + src[..line_start].iter().filter(|&&c| c == b'\n').count();
+    let text = String::from_utf8_lossy(&src[line_start..line_end]);
+    let text = text.trim_end_matches('\r');
+
+    let chars = |bytes: &[u8]| String::from_utf8_lossy(bytes).chars().count();
+    let column = chars(&src[line_start..start]);
+    let width = chars(&src[start..span.end.clamp(start, line_end)]).max(1);
+
+    let gutter = " ".repeat(line_no.to_string().len());
+    let role = theme::severity(severity);
+    let (arrow, bar) = (theme::paint(Role::Location, "-->"), theme::paint(Role::Location, "|"));
+    format!(
+        "{}: {message}\n\
+         {gutter}{arrow} {path}:{line_no}:{}\n\
+         {gutter} {bar}\n\
+         {} {bar} {text}\n\
+         {gutter} {bar} {}{}",
+        theme::paint(role, severity),
+        column + 1,
+        theme::paint(Role::Location, &line_no.to_string()),
+        " ".repeat(column),
+        theme::paint(role, &"^".repeat(width)),
+    )
+}
+
+#[test]
+fn tests() {
+    let src = b"1 +\n  foo $ 2\n";
+    assert_eq!(
+        render("error", "unexpected character \"$\"", "a.stc", src, Span { start: 10, end: 11 }),
+        "error: unexpected character \"$\"\n \
+         --> a.stc:2:7\n  \
+         |\n\
+         2 |   foo $ 2\n  \
+         |       ^"
+    );
+
+    let src = b"1 + (2";
+    let s = render("error", "expected ')'", "-", src, Span { start: 6, end: 6 });
+    assert!(s.ends_with("1 | 1 + (2\n  |       ^"), "{s}");
+}
@@ -0,0 +1,116 @@
+//! `stoncc doc`: describes operators and builtin functions, with an
+//! example of each evaluated on the spot.
+
+use stoncc::limits::Meter;
+use stoncc::*;
+
+use crate::{capabilities, theme};
+
+/// The example and what it evaluates to.
+fn example(doc: &Doc) -> String {
+    let v = expr(doc.example.as_bytes())
+        .map_err(|e| e.to_string())
+        .and_then(|ast| eval(&ast, &Env::new(), &mut Meter::new(Default::default())).map_err(|e| e.to_string()));
+    match v {
+        Ok(v) => format!("{}  =>  {v}", doc.example),
+        Err(e) => format!("{}  =>  error: {e}", doc.example),
+    }
+}
+
+fn arguments(arity: std::ops::RangeInclusive<usize>) -> String {
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    match (*arity.start(), *arity.end()) {
+        (n, m) if n == m => format!("{n} argument{}", plural(n)),
+        (n, usize::MAX) => format!("at least {n} argument{}", plural(n)),
+        (n, m) => format!("{n} to {m} arguments"),
+    }
+}
+
+fn operator(v: &NodeVal, arity: usize) -> String {
+    let (prec, assoc) = capabilities::binding(v, arity);
+    let assoc = match assoc {
+        "none" => "non-associative".to_string(),
+        a => format!("{a}-associative"),
+    };
+    let doc = v.doc(arity);
+    format!("{v}  {}, precedence {prec}, {assoc}\n  {}\n  example: {}\n", v.fixity(arity), doc.summary, example(&doc))
+}
+
+fn function(builtins: &Builtins, name: &str) -> Option<String> {
+    let (params, doc) = builtins.doc(name)?;
+    let arity = arguments(builtins.arity(name)?);
+    Some(format!("{name}({params})  {arity}\n  {}\n  example: {}\n", doc.summary, example(&doc)))
+}
+
+/// The documentation for `name`: an operator symbol (`?` and `:` stand
+/// for `?:`) or the name of a builtin function.
+pub fn lookup(name: &str) -> Option<String> {
+    let symbol = if name == "?" || name == ":" { "?:" } else { name };
+    let shapes: Vec<String> = NodeVal::SHAPES
+        .into_iter()
+        .filter(|(v, _)| v.to_string() == symbol)
+        .map(|(v, arity)| operator(&v, arity))
+        .collect();
+    if !shapes.is_empty() {
+        return Some(shapes.join("\n"));
+    }
+    function(&Builtins::default(), name)
+}
+
+/// One line per operator and function.
+fn summary() -> String {
+    let mut out = String::from("operators:\n");
+    for (v, arity) in NodeVal::SHAPES {
+        out += &format!("  {:<4}{:<9}{}\n", v.to_string(), v.fixity(arity), v.doc(arity).summary);
+    }
+    out += "\nfunctions:\n";
+    let builtins = Builtins::default();
+    for name in builtins.names() {
+        if let Some((params, doc)) = builtins.doc(name) {
+            out += &format!("  {:<22}{}\n", format!("{name}({params})"), doc.summary);
+        }
+    }
+    out
+}
+
+/// `stoncc doc [OPERATOR|FUNCTION]`: prints the documentation of one
+/// operator or function, or a summary of all of them.
+pub fn main(args: &[String]) {
+    match args {
+        [] => print!("{}", summary()),
+        [name] => match lookup(name) {
+            Some(doc) => print!("{doc}"),
+            None => {
+                eprintln!("{}: no operator or function called '{name}' (run stoncc doc for a list)", theme::label("error"));
+                std::process::exit(1);
+            }
+        },
+        _ => {
+            eprintln!("usage: stoncc doc [OPERATOR|FUNCTION]");
+            std::process::exit(2);
+        }
+    }
+}
+
+#[test]
+fn tests() {
+    // Every example evaluates, and uses what it documents.
+    for (v, arity) in NodeVal::SHAPES {
+        let doc = v.doc(arity);
+        assert!(!example(&doc).contains("error"), "{v}: {}", example(&doc));
+        assert_eq!(expr(doc.example.as_bytes()).unwrap().to_string().split(' ').next(), Some(&*format!("({v}")));
+    }
+    let builtins = Builtins::default();
+    for name in builtins.names() {
+        let (_, doc) = builtins.doc(name).unwrap();
+        assert!(!example(&doc).contains("error"), "{name}: {}", example(&doc));
+        assert!(doc.example.starts_with(&format!("{name}(")));
+    }
+
+    assert_eq!(lookup("^").unwrap(), "^  infix, precedence 13, right-associative\n  Exponentiation. A negative integer exponent truncates like 1 / a^n.\n  example: 2 ^ 3 ^ 2  =>  512\n");
+    assert_eq!(lookup("-").unwrap().matches("example").count(), 2);
+    assert_eq!(lookup(":"), lookup("?:"));
+    assert!(lookup("gcd").unwrap().starts_with("gcd(a, b)  2 arguments\n"));
+    assert!(lookup("min").unwrap().starts_with("min(a, ...)  at least 1 argument\n"));
+    assert_eq!(lookup("frobnicate"), None);
+}
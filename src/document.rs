@@ -0,0 +1,471 @@
+//! Incremental parsing, for editors and interactive use. A [`Document`]
+//! keeps the source of a program with its tokens and tree. After an edit it
+//! lexes again only from shortly before the edit to where the tokens line
+//! up with the old ones, and parses again only the top-level statements
+//! those tokens change, keeping the trees of the others.
+//!
+//! ```
+//! use stoncc::document::Document;
+//! use stoncc::Extensions;
+//!
+//! let mut doc = Document::new("a = 1\nb = a + 1\nc = b * 2\n", Extensions::default());
+//! let stats = doc.apply_edit(14..15, "2");
+//! assert_eq!(doc.text(), "a = 1\nb = a + 2\nc = b * 2\n");
+//! assert_eq!(doc.program().stmts[1].to_string(), "(= b (+ a 2))");
+//! assert_eq!((stats.reparsed, stats.reused), (1, 2));
+//! ```
+//!
+//! Each edit returns [`EditStats`], with how much was done again and how
+//! long it took, to set against [`Document::reparse`], which does it all.
+
+use std::io::BufReader;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::lexer::{LexError, Lexed, Lexer, Span, Token};
+use crate::operators::OperatorTable;
+use crate::parser::{self, Extensions, Node, ParseError, Program, Stmt};
+
+/// How much input the lexer reads at a time, so that lexing a few tokens
+/// from the middle of a large document doesn't copy the rest of it.
+const READ_SIZE: usize = 4096;
+
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// The source of a program, its tokens and its tree, kept up to date as
+/// the source is edited.
+#[derive(Debug, Clone)]
+pub struct Document {
+    text: String,
+    ext: Extensions,
+    ops: OperatorTable,
+    /// The tokens of `text`, shared with the parser while it reads them.
+    /// Empty if lexing failed.
+    tokens: Arc<Vec<Lexed>>,
+    /// Each point where the parser was about to read a top-level statement,
+    /// as the index of the token next, and whether a statement was read
+    /// from there rather than a lone `;`.
+    points: Vec<(usize, bool)>,
+    program: Program,
+    skipped: Vec<ParseError>,
+    /// What stopped lexing or parsing. The program has the statements
+    /// before it.
+    error: Option<ParseError>,
+    /// Whether `tokens` are those of the whole text.
+    lexed: bool,
+}
+
+/// What an edit took: how many tokens were lexed and top-level statements
+/// parsed again, how many of each were kept, and the time spent lexing and
+/// parsing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EditStats {
+    pub relexed: usize,
+    pub reused_tokens: usize,
+    pub reparsed: usize,
+    pub reused: usize,
+    pub lex_time: Duration,
+    pub parse_time: Duration,
+}
+
+impl Document {
+    /// Parses `text` with the syntax extensions in `ext`.
+    pub fn new(text: impl Into<String>, ext: Extensions) -> Self {
+        Self::with_operators(text, ext, &OperatorTable::default())
+    }
+
+    /// Like `new`, with the operators in `ops` rather than the built-in
+    /// ones.
+    pub fn with_operators(text: impl Into<String>, ext: Extensions, ops: &OperatorTable) -> Self {
+        let mut doc = Document {
+            text: text.into(),
+            ext,
+            ops: ops.clone(),
+            tokens: Arc::default(),
+            points: Vec::new(),
+            program: Program::default(),
+            skipped: Vec::new(),
+            error: None,
+            lexed: false,
+        };
+        doc.reparse();
+        doc
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn tokens(&self) -> &[Lexed] {
+        &self.tokens
+    }
+
+    /// The program, up to the error that stopped parsing if there is one.
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// The error that stopped lexing or parsing, as `program_with` would
+    /// have returned it. The whole text is lexed before any of it is
+    /// parsed, so an error in lexing comes before any syntax error.
+    pub fn error(&self) -> Option<&ParseError> {
+        self.error.as_ref()
+    }
+
+    /// The stray characters skipped over.
+    pub fn skipped(&self) -> &[ParseError] {
+        &self.skipped
+    }
+
+    /// A lexer for the text from `offset`, which must be the start of the
+    /// text or of a token. Its spans are from `offset`.
+    fn lexer(&self, offset: usize) -> Lexer<BufReader<&[u8]>> {
+        Lexer::new(BufReader::with_capacity(READ_SIZE, &self.text.as_bytes()[offset..]))
+            .with_operators(self.ops.spellings())
+            .with_wide_ints(self.ext.wide_ints)
+            .with_words(self.ext.words)
+            .with_decimal_comma(self.ext.decimal_comma)
+            .with_literals(self.ops.literals())
+    }
+
+    /// Forgets everything but the text, for lexing that failed with `e`.
+    fn fail(&mut self, e: LexError) {
+        self.tokens = Arc::default();
+        self.points.clear();
+        self.program.stmts.clear();
+        self.skipped.clear();
+        self.error = Some(e.into());
+        self.lexed = false;
+    }
+
+    /// Lexes and parses the whole text again.
+    pub fn reparse(&mut self) -> EditStats {
+        let started = Instant::now();
+        let tokens = match self.lexer(0).tokens() {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                self.fail(e);
+                return EditStats { lex_time: started.elapsed(), ..EditStats::default() };
+            }
+        };
+        let lex_time = started.elapsed();
+        let relexed = tokens.len();
+        (self.tokens, self.lexed) = (Arc::new(tokens), true);
+
+        let started = Instant::now();
+        let all = Arc::clone(&self.tokens);
+        let lexer = Lexer::from_token_iter((0..all.len()).map(move |i| all[i].clone()));
+        let (points, end, skipped) = parser::resumable(lexer, self.ext, &self.ops, |_| false);
+        self.points = points.iter().map(|(at, stmt)| (self.index(*at), stmt.is_some())).collect();
+        self.program.stmts = points.into_iter().filter_map(|(_, stmt)| stmt).collect();
+        self.skipped = skipped;
+        self.error = end.err();
+
+        let reparsed = self.program.stmts.len();
+        EditStats { relexed, reparsed, lex_time, parse_time: started.elapsed(), ..EditStats::default() }
+    }
+
+    /// The index of the token at `offset`, or the number of tokens if none
+    /// starts there or after.
+    fn index(&self, offset: usize) -> usize {
+        self.tokens.partition_point(|t| t.span.start < offset)
+    }
+
+    /// Replaces the bytes in `range` of the text with `new_text`, and
+    /// brings the tokens and the tree up to date with as little lexing and
+    /// parsing as it can.
+    ///
+    /// # Panics
+    ///
+    /// If `range` is out of bounds or does not start and end on character
+    /// boundaries, as `String::replace_range` does.
+    pub fn apply_edit(&mut self, range: Range<usize>, new_text: &str) -> EditStats {
+        if !self.lexed {
+            self.text.replace_range(range, new_text);
+            return self.reparse();
+        }
+
+        // A token with white space before it starts the same way whatever
+        // comes after, and no token before it looks past that space.
+        let started = Instant::now();
+        let bytes = self.text.as_bytes();
+        let first = (0..self.index(range.start + 1)).rev().find(|&i| {
+            let start = self.tokens[i].span.start;
+            start <= range.start && start > 0 && bytes[start - 1].is_ascii_whitespace() && !bytes[start..].starts_with(BOM)
+        });
+        let (first, from, newline) = match first {
+            Some(i) => (i, self.tokens[i].span.start, self.tokens[i].newline),
+            None => (0, 0, false),
+        };
+
+        let (range_start, range_end) = (range.start, range.end);
+        let delta = new_text.len() as isize - range.len() as isize;
+        let edit_end = range.start + new_text.len();
+        self.text.replace_range(range, new_text);
+
+        // Lex until a token past the edit is where one was before, with the
+        // same space before it: the tokens from there on are the same.
+        let mut lexer = self.lexer(from);
+        let mut relexed = Vec::new();
+        let resync = loop {
+            let token = match lexer.next() {
+                Ok(token) => token,
+                Err(e) => {
+                    self.fail(LexError { span: moved(e.span, from as isize), ..e });
+                    return EditStats { lex_time: started.elapsed(), ..EditStats::default() };
+                }
+            };
+            if token == Token::Eof {
+                break self.tokens.len();
+            }
+            let span = moved(lexer.span(), from as isize);
+            let newline = lexer.newline_before() || (relexed.is_empty() && newline);
+            if span.start >= edit_end {
+                let was = (span.start as isize - delta) as usize;
+                let i = self.tokens.partition_point(|t| t.span.start < was);
+                if self.tokens.get(i).is_some_and(|t| t.span.start == was && t.newline == newline) {
+                    break i;
+                }
+            }
+            let text = String::from_utf8_lossy(&lexer.slice(lexer.span())).into_owned();
+            let mut lexed = Lexed { token, span: lexer.span(), newline, text };
+            move_token(&mut lexed, from as isize);
+            relexed.push(lexed);
+        };
+        drop(lexer);
+
+        let relexed_count = relexed.len();
+        let tokens = Arc::make_mut(&mut self.tokens);
+        tokens[resync..].iter_mut().for_each(|t| move_token(t, delta));
+        tokens.splice(first..resync, relexed);
+        let lex_time = started.elapsed();
+
+        // Parsing starts again at the last point before the first token
+        // lexed again, and stops at the first point after the last one at
+        // which it was before.
+        let started = Instant::now();
+        let changed_end = first + relexed_count;
+        let map = |i: usize| i - resync + changed_end;
+        let keep = self.points.partition_point(|&(t, _)| t < first).saturating_sub(1);
+        let start = self.points.get(keep).map_or(0, |&(t, _)| t);
+        let tail = self.points.partition_point(|&(t, _)| t < resync);
+        for point in &mut self.points[tail..] {
+            point.0 = map(point.0);
+        }
+
+        let all = Arc::clone(&self.tokens);
+        let lexer = Lexer::from_token_iter((start..all.len()).map(move |i| all[i].clone()));
+        let stop = |at: usize| {
+            let i = self.index(at);
+            i >= changed_end && self.points[tail..].binary_search_by_key(&i, |&(t, _)| t).is_ok()
+        };
+        let (points, end, skipped) = parser::resumable(lexer, self.ext, &self.ops, stop);
+
+        let stopped = match &end {
+            Ok(Some(at)) => {
+                let i = self.index(*at);
+                tail + self.points[tail..].partition_point(|&(t, _)| t < i)
+            }
+            _ => self.points.len(),
+        };
+        let count = |points: &[(usize, bool)]| points.iter().filter(|(_, stmt)| *stmt).count();
+        let (prefix, suffix) = (count(&self.points[..keep]), count(&self.points[..stopped]));
+        for stmt in &mut self.program.stmts[suffix..] {
+            shift(stmt, delta);
+        }
+
+        // Stray characters are replaced where parsing was done again, in
+        // the offsets of the new text.
+        self.skipped.retain(|e| !(range_start..range_end).contains(&e.span.start));
+        for e in self.skipped.iter_mut().filter(|e| e.span.start >= range_end) {
+            e.span = moved(e.span, delta);
+        }
+        let from_offset = self.tokens.get(start).map_or(self.text.len(), |t| t.span.start);
+        let to_offset = self.points.get(stopped).map(|&(t, _)| self.tokens[t].span.start);
+        let before = self.skipped.partition_point(|e| e.span.start < from_offset);
+        let after = match to_offset {
+            Some(to) => self.skipped.partition_point(|e| e.span.start < to).max(before),
+            None => self.skipped.len(),
+        };
+        self.skipped.splice(before..after, skipped);
+
+        match end {
+            Ok(Some(_)) => {
+                if let Some(e) = &mut self.error {
+                    e.span = moved(e.span, delta);
+                }
+            }
+            end => self.error = end.err(),
+        }
+
+        let reparsed = points.iter().filter(|(_, stmt)| stmt.is_some()).count();
+        let new_points: Vec<(usize, bool)> = points.iter().map(|(at, stmt)| (self.index(*at), stmt.is_some())).collect();
+        self.points.splice(keep..stopped, new_points);
+        self.program.stmts.splice(prefix..suffix, points.into_iter().filter_map(|(_, stmt)| stmt));
+
+        EditStats {
+            relexed: relexed_count,
+            reused_tokens: self.tokens.len() - relexed_count,
+            reparsed,
+            reused: self.program.stmts.len() - reparsed,
+            lex_time,
+            parse_time: started.elapsed(),
+        }
+    }
+}
+
+fn moved(span: Span, delta: isize) -> Span {
+    let at = |i: usize| (i as isize + delta) as usize;
+    Span { start: at(span.start), end: at(span.end) }
+}
+
+/// Moves `t` by `delta` bytes, with the span an unknown byte keeps.
+fn move_token(t: &mut Lexed, delta: isize) {
+    t.span = moved(t.span, delta);
+    if let Token::Unknown(_, span) = &mut t.token {
+        *span = moved(*span, delta);
+    }
+}
+
+/// Moves the spans in `stmt` by `delta` bytes.
+fn shift(stmt: &mut Stmt, delta: isize) {
+    match stmt {
+        Stmt::Assign { span, .. } | Stmt::Def { span, .. } => *span = moved(*span, delta),
+        Stmt::Expr(_) => {}
+        Stmt::Block { stmts, span } => {
+            *span = moved(*span, delta);
+            stmts.iter_mut().for_each(|s| shift(s, delta));
+        }
+        Stmt::If { then, els, span, .. } => {
+            *span = moved(*span, delta);
+            shift(then, delta);
+            els.iter_mut().for_each(|s| shift(s, delta));
+        }
+        Stmt::While { body, span, .. } => {
+            *span = moved(*span, delta);
+            shift(body, delta);
+        }
+    }
+    // Only the statement's own expressions: nested statements did theirs.
+    let own: Vec<&mut Node> = match stmt {
+        Stmt::Assign { value: e, .. } | Stmt::Def { body: e, .. } | Stmt::Expr(e) => vec![e],
+        Stmt::If { cond, .. } | Stmt::While { cond, .. } => vec![cond],
+        Stmt::Block { .. } => vec![],
+    };
+    let mut stack = own;
+    while let Some(node) = stack.pop() {
+        match node {
+            Node::Leaf(_, span) | Node::Error(span) => *span = moved(*span, delta),
+            Node::Node { children, span, .. } => {
+                *span = moved(*span, delta);
+                stack.extend(children.iter_mut());
+            }
+        }
+    }
+}
+
+#[test]
+fn tests() {
+    use crate::parser::program_with;
+
+    // The document after each edit is the one parsing its text afresh
+    // gives.
+    let check = |doc: &Document| {
+        let (fresh, skipped) = program_with(doc.text().as_bytes(), doc.ext);
+        match fresh {
+            Ok(program) => assert_eq!((doc.program(), doc.error()), (&program, None), "{:?}", doc.text()),
+            Err(e) => match Lexer::from_bytes(doc.text().as_bytes()).tokens() {
+                Err(lex) => assert_eq!(doc.error(), Some(&lex.into()), "{:?}", doc.text()),
+                Ok(_) => assert_eq!(doc.error(), Some(&e), "{:?}", doc.text()),
+            },
+        }
+        if doc.error().is_none() {
+            assert_eq!(doc.skipped(), skipped, "{:?}", doc.text());
+            let tokens = Lexer::from_bytes(doc.text().as_bytes()).tokens().unwrap();
+            assert_eq!(doc.tokens(), tokens, "{:?}", doc.text());
+        }
+    };
+
+    let src = "a = 1\nb = a + 1\nc = b * 2\nf(x) = x * c\nif (b > 1) { d = f(b) } else d = 0\nd\n";
+    let mut doc = Document::new(src, Extensions::default());
+    check(&doc);
+    assert_eq!(doc.program().stmts.len(), 6);
+
+    let stats = doc.apply_edit(14..15, "20");
+    check(&doc);
+    assert_eq!((stats.reparsed, stats.reused, stats.relexed), (1, 5, 1));
+
+    // An operator at the start of a line carries on the statement before.
+    let stats = doc.apply_edit(17..17, "- 3\n");
+    check(&doc);
+    assert_eq!(doc.program().stmts[1].to_string(), "(= b (- (+ a 20) 3))");
+    assert_eq!((doc.program().stmts.len(), stats.reparsed, stats.relexed), (6, 1, 2));
+    doc.apply_edit(17..21, "");
+    check(&doc);
+    assert_eq!(doc.program().stmts[1].to_string(), "(= b (+ a 20))");
+
+    // An opened comment swallows the rest; closing it brings it back.
+    doc.apply_edit(0..0, "x = 1 /* ");
+    check(&doc);
+    assert!(doc.error().is_some());
+    doc.apply_edit(9..9, "*/\n");
+    check(&doc);
+    assert_eq!(doc.program().stmts.len(), 7);
+
+    // A syntax error stops parsing until it is fixed.
+    doc.apply_edit(0..1, "(");
+    check(&doc);
+    doc.apply_edit(0..1, "y");
+    check(&doc);
+    assert!(doc.error().is_none());
+
+    // Stray characters are reported where they are now.
+    doc.apply_edit(2..2, "@ ");
+    check(&doc);
+    doc.apply_edit(0..0, "` \n");
+    check(&doc);
+    assert_eq!(doc.skipped().len(), 2);
+
+    // Stray characters before an edit that moves them are where they are
+    // now, once.
+    let mut doc = Document::new("$", Extensions::default());
+    doc.apply_edit(0..0, "\n");
+    check(&doc);
+    assert_eq!(doc.skipped().iter().map(|e| e.span).collect::<Vec<_>>(), [Span { start: 1, end: 2 }]);
+    doc.apply_edit(0..1, "\n;");
+    check(&doc);
+
+    // Random edits, from a fixed seed, give the document a fresh one of
+    // the new text would be.
+    let pieces = ["1", "x", " ", "\n", "+", "*", "(", ")", "=", ";", "{", "}", "f(", "if ", "else ", "/*", "*/", "//", "!", "?", "2.5", "$", "@"];
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut next = |n: usize| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        (seed % n as u64) as usize
+    };
+    let mut doc = Document::new(src, Extensions::default());
+    for _ in 0..2000 {
+        let len = doc.text().len();
+        let start = next(len + 1);
+        let end = (start + next(4)).min(len);
+        let text: String = (0..next(3)).map(|_| pieces[next(pieces.len())]).collect();
+        doc.apply_edit(start..end, &text);
+        check(&doc);
+        let fresh = Document::new(doc.text(), doc.ext);
+        assert_eq!((doc.tokens(), doc.program(), doc.error(), doc.skipped()), (fresh.tokens(), fresh.program(), fresh.error(), fresh.skipped()), "{:?}", doc.text());
+    }
+
+    // In a long document, an edit parses only what it changes.
+    let long: String = (0..20_000).map(|i| format!("v{i} = {i} * (x + {i})\n")).collect();
+    let mut doc = Document::new(long, Extensions::default());
+    let full = doc.clone().reparse();
+    let at = doc.text().find("v10000 = 10000").unwrap() + 9;
+    let stats = doc.apply_edit(at..at + 5, "7");
+    assert_eq!(doc.program().stmts[10_000].to_string(), "(= v10000 (* 7 (+ x 10000)))");
+    assert_eq!((stats.relexed, stats.reparsed, stats.reused), (1, 1, 19_999));
+    assert_eq!(full.reparsed, 20_000);
+}
+
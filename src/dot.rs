@@ -0,0 +1,139 @@
+//! Graphviz DOT output (`--dump-ast=dot`), for seeing how precedence and
+//! associativity grouped an expression. A [`Graph`] collects labelled
+//! nodes and edges from any pass; trees and statements add themselves with
+//! [`Graph::tree`] and [`Graph::stmt`].
+//!
+//! ```
+//! let mut graph = stoncc::dot::Graph::new();
+//! graph.tree(&stoncc::expr(b"1 - x").unwrap());
+//! assert!(graph.to_string().contains("n0 -> n1;\n  n0 -> n2;"));
+//! ```
+
+use std::fmt;
+
+use crate::ast::Visitor;
+use crate::parser::{Node, Stmt};
+
+/// A directed graph to be written as DOT. Nodes are numbered in the order
+/// they are added, and the edges out of a node are drawn in the order they
+/// are added, so operands appear left to right.
+#[derive(Debug, Default)]
+pub struct Graph {
+    /// The label of each node, and whether it is drawn as a box.
+    nodes: Vec<(String, bool)>,
+    edges: Vec<(usize, usize)>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a node, drawn as a box if it is a leaf, and returns its number.
+    pub fn node(&mut self, label: impl Into<String>, leaf: bool) -> usize {
+        self.nodes.push((label.into(), leaf));
+        self.nodes.len() - 1
+    }
+
+    pub fn edge(&mut self, from: usize, to: usize) {
+        self.edges.push((from, to));
+    }
+
+    /// Adds the tree `root`, operators labelled with their symbols and
+    /// leaves with their values. Returns the number of its root.
+    pub fn tree(&mut self, root: &Node) -> usize {
+        struct Builder<'g> {
+            graph: &'g mut Graph,
+            parents: Vec<usize>,
+            root: usize,
+        }
+
+        impl Visitor for Builder<'_> {
+            fn enter(&mut self, node: &Node) {
+                let id = match node {
+                    Node::Leaf(v, _) => self.graph.node(v.to_string(), true),
+                    Node::Error(_) => self.graph.node("<error>".to_string(), true),
+                    Node::Node { v, .. } => self.graph.node(v.to_string(), false),
+                };
+                match self.parents.last() {
+                    Some(&parent) => self.graph.edge(parent, id),
+                    None => self.root = id,
+                }
+                self.parents.push(id);
+            }
+
+            fn leave(&mut self, _: &Node) {
+                self.parents.pop();
+            }
+        }
+
+        let mut builder = Builder { graph: self, parents: Vec::new(), root: 0 };
+        root.visit(&mut builder);
+        builder.root
+    }
+
+    /// Adds `stmt`, with a node for its kind over its expressions and
+    /// nested statements. An expression statement is just its tree.
+    pub fn stmt(&mut self, stmt: &Stmt) -> usize {
+        let (label, nodes, stmts): (String, Vec<&Node>, Vec<&Stmt>) = match stmt {
+            Stmt::Expr(e) => return self.tree(e),
+            Stmt::Assign { name, value, .. } => (format!("{name} ="), vec![value], vec![]),
+            Stmt::Def { name, params, body, .. } => (format!("{name}({}) =", params.join(", ")), vec![body], vec![]),
+            Stmt::Block { stmts, .. } => ("{ }".into(), vec![], stmts.iter().collect()),
+            Stmt::If { cond, then, els, .. } => ("if".into(), vec![cond], [then].into_iter().chain(els).map(|s| &**s).collect()),
+            Stmt::While { cond, body, .. } => ("while".into(), vec![cond], vec![body]),
+        };
+        let id = self.node(label, false);
+        for child in nodes.into_iter().map(|n| self.tree(n)).collect::<Vec<_>>() {
+            self.edge(id, child);
+        }
+        for child in stmts.into_iter().map(|s| self.stmt(s)).collect::<Vec<_>>() {
+            self.edge(id, child);
+        }
+        id
+    }
+}
+
+/// `s` as a DOT string.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl fmt::Display for Graph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "digraph ast {{")?;
+        writeln!(f, "  ordering=out;")?;
+        for (i, (label, leaf)) in self.nodes.iter().enumerate() {
+            let shape = if *leaf { ", shape=box" } else { "" };
+            writeln!(f, "  n{i} [label={}{shape}];", quote(label))?;
+        }
+        for (from, to) in &self.edges {
+            writeln!(f, "  n{from} -> n{to};")?;
+        }
+        write!(f, "}}")
+    }
+}
+
+#[test]
+fn tests() {
+    use crate::parser::{expr, program, LeafVal, NodeVal};
+
+    let mut graph = Graph::new();
+    assert_eq!(graph.tree(&expr(b"a - b - f(c)").unwrap()), 0);
+    assert_eq!(
+        graph.to_string(),
+        "digraph ast {\n  ordering=out;\n  n0 [label=\"-\"];\n  n1 [label=\"-\"];\n  n2 [label=\"a\", shape=box];\n  n3 [label=\"b\", shape=box];\n  n4 [label=\"f\"];\n  n5 [label=\"c\", shape=box];\n  n0 -> n1;\n  n1 -> n2;\n  n1 -> n3;\n  n0 -> n4;\n  n4 -> n5;\n}"
+    );
+
+    let mut graph = Graph::new();
+    graph.tree(&Node::op(NodeVal::Sub, vec![Node::leaf(LeafVal::Sym("say \"hi\\\"".into()))]));
+    assert!(graph.to_string().contains(r#"n1 [label="|say \"hi\\\\\"|", shape=box];"#), "{graph}");
+
+    let mut graph = Graph::new();
+    for stmt in &program(b"f(x, y) = x\nif (1) { z = 2 } else 3").0.unwrap().stmts {
+        graph.stmt(stmt);
+    }
+    let labels: Vec<&str> = graph.nodes.iter().map(|(l, _)| l.as_str()).collect();
+    assert_eq!(labels, ["f(x, y) =", "x", "if", "1", "{ }", "z =", "2", "3"]);
+    assert_eq!(graph.edges, [(0, 1), (2, 3), (5, 6), (4, 5), (2, 4), (2, 7)]);
+}
@@ -0,0 +1,163 @@
+//! `stoncc build FILE [-o PROG] [--arith=checked|wrap]`: compiles a
+//! program to an executable that prints the value of each statement, the
+//! way a C compiler driver does. It parses, checks and simplifies the
+//! program, emits x86-64 assembly, and hands that to the system's C
+//! compiler to assemble and link. Like the evaluator, the executable stops
+//! on overflow unless it is built with `--arith=wrap`.
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+use stoncc::{parser, sema, ArithMode, Builtins, Env, Extensions, Stmt};
+
+use crate::{codegen, diagnostic, fail, x86, Failure};
+
+/// Compilers tried, in order, when `$CC` isn't set.
+const COMPILERS: [&str; 3] = ["cc", "gcc", "clang"];
+
+/// The compiler to assemble and link with: the value of `$CC` as it is,
+/// or else the first of `COMPILERS` found in a directory of `path`.
+pub fn find_cc(cc: Option<OsString>, path: Option<OsString>) -> Option<PathBuf> {
+    if let Some(cc) = cc.filter(|cc| !cc.is_empty()) {
+        return Some(cc.into());
+    }
+    let dirs: Vec<PathBuf> = env::split_paths(&path?).collect();
+    COMPILERS.iter().flat_map(|name| dirs.iter().map(move |dir| dir.join(name))).find(|p| p.is_file())
+}
+
+/// The assembly for the program in `s`, with the overflow behaviour of
+/// `arith`, which is `Checked` or `Wrap`, or the diagnostics that stop it
+/// compiling, rendered against `path`.
+fn compile(path: &str, s: &[u8], arith: ArithMode) -> Result<String, String> {
+    let render = |message: &str, span| diagnostic::render("error", message, path, s, span);
+
+    let (program, skipped) = parser::program_with(s, Extensions::default());
+    if !skipped.is_empty() {
+        return Err(skipped.iter().map(|e| render(&e.kind.to_string(), e.span)).collect::<Vec<_>>().join("\n"));
+    }
+    let mut program = program.map_err(|e| render(&e.kind.to_string(), e.span))?;
+
+    let errors = sema::check(&program.stmts, &Env::new(), &Builtins::default());
+    if !errors.is_empty() {
+        let rendered = errors.iter().map(|e| match e.span() {
+            Some(span) => render(&e.to_string(), span),
+            None => format!("error: {e}"),
+        });
+        return Err(rendered.collect::<Vec<_>>().join("\n"));
+    }
+
+    for ast in program.stmts.iter_mut().flat_map(Stmt::exprs_mut) {
+        *ast = ast.simplify();
+    }
+    let backend = if arith == ArithMode::Checked { x86::X86::checked() } else { x86::X86::default() };
+    codegen::compile(backend, &program, &Env::new()).map_err(|e| match Some(e.span).filter(|s| s.end > s.start) {
+        Some(span) => render(&e.to_string(), span),
+        None => format!("error: {e}"),
+    })
+}
+
+/// Assembles and links `asm` into the executable `out` with `cc`.
+fn link(cc: &Path, asm: &str, out: &Path) -> Result<(), String> {
+    let tmp = env::temp_dir().join(format!("stoncc-{}-{}.s", process::id(), out.file_name().unwrap_or_default().to_string_lossy()));
+    fs::write(&tmp, asm).map_err(|e| format!("cannot write {}: {e}", tmp.display()))?;
+    let status = Command::new(cc).arg("-o").arg(out).arg(&tmp).status();
+    let _ = fs::remove_file(&tmp);
+
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(_) => Err(format!("{} failed to assemble and link {}", cc.display(), out.display())),
+        Err(e) => Err(format!("cannot run {}: {e}", cc.display())),
+    }
+}
+
+pub fn main(args: &[String]) {
+    let usage = || -> ! {
+        eprintln!("usage: stoncc build FILE [-o PROG] [--arith=checked|wrap]");
+        process::exit(2);
+    };
+    let mut path = None;
+    let mut out = None;
+    let mut arith = ArithMode::Checked;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => out = Some(args.next().unwrap_or_else(|| usage())),
+            _ if arg.starts_with("--arith=") => {
+                arith = match arg["--arith=".len()..].parse() {
+                    Ok(mode @ (ArithMode::Checked | ArithMode::Wrap)) => mode,
+                    Ok(_) => fail(Failure::Usage, "compiled code checks for overflow or wraps, so stoncc build takes --arith=checked or --arith=wrap"),
+                    Err(e) => fail(Failure::Usage, e),
+                }
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let Some(path) = path else { usage() };
+    // Named after the file, where `cc` would make `a.out`.
+    let out = out.map_or_else(|| Path::new(path).with_extension(""), PathBuf::from);
+    if out == Path::new(path) {
+        fail(Failure::Usage, format!("{path} has no extension to drop: name the executable with -o"));
+    }
+
+    if !cfg!(target_arch = "x86_64") {
+        fail(Failure::Run, format!("stoncc build makes x86-64 executables, and this machine is {}", env::consts::ARCH));
+    }
+    let cc = find_cc(env::var_os("CC"), env::var_os("PATH"))
+        .unwrap_or_else(|| fail(Failure::Run, format!("no C compiler to assemble and link with: install one of {}, or set CC", COMPILERS.join(", "))));
+
+    let s = fs::read(path).unwrap_or_else(|e| fail(Failure::Run, format!("cannot read {path}: {e}")));
+    let asm = compile(path, &s, arith).unwrap_or_else(|diagnostics| {
+        eprintln!("{diagnostics}");
+        process::exit(1);
+    });
+    link(&cc, &asm, &out).unwrap_or_else(|e| fail(Failure::Run, e));
+}
+
+#[test]
+fn tests() {
+    let dir = env::temp_dir().join(format!("stoncc-driver-{}", process::id()));
+    fs::create_dir_all(dir.join("bin")).unwrap();
+    fs::write(dir.join("bin/gcc"), "").unwrap();
+
+    // $CC wins; otherwise the first compiler found, in the order of
+    // `COMPILERS` rather than of the path.
+    let path = env::join_paths([dir.join("none"), dir.join("bin")]).unwrap();
+    assert_eq!(find_cc(Some("my-cc".into()), Some(path.clone())), Some(PathBuf::from("my-cc")));
+    assert_eq!(find_cc(Some("".into()), Some(path.clone())), Some(dir.join("bin/gcc")));
+    fs::write(dir.join("cc"), "").unwrap();
+    let path = env::join_paths([dir.join("bin"), dir.clone()]).unwrap();
+    assert_eq!(find_cc(None, Some(path)), Some(dir.join("cc")));
+    assert_eq!(find_cc(None, Some(dir.join("none").into())), None);
+    assert_eq!(find_cc(None, None), None);
+
+    let err = compile("p.stc", b"x = 1\ny = z + 1", ArithMode::Checked).unwrap_err();
+    assert!(err.starts_with("error: symbol z is not defined\n --> p.stc:2:5"), "{err}");
+    assert!(compile("p.stc", b"1.5 * 2", ArithMode::Checked).unwrap_err().starts_with("error: cannot compile"));
+
+    // A whole build, where there is a compiler to do it.
+    if let (true, Some(cc)) = (cfg!(target_arch = "x86_64"), find_cc(None, env::var_os("PATH"))) {
+        let out = dir.join("prog");
+        let run = |s: &str, arith| {
+            link(&cc, &compile("p.stc", s.as_bytes(), arith).unwrap(), &out).unwrap();
+            let output = Command::new(&out).output().unwrap();
+            (String::from_utf8_lossy(&output.stdout).into_owned(), String::from_utf8_lossy(&output.stderr).into_owned())
+        };
+        assert_eq!(run("f(a) = a * 2\nx = 3 + 4\nf(x) + 1", ArithMode::Checked).0, "7\n15\n");
+
+        // Checked code stops where the evaluator reports an overflow, and
+        // gives what it gives up to there.
+        let fits = "2^30; (-2)^31; 12!; -2147483647 - 1; 1 << 30; -1 << 31; 0 << 40; abs(-5); gcd(-2147483647 - 1, 6); 5 +% 2147483647; 7 / -1";
+        let expected = "1073741824\n-2147483648\n479001600\n-2147483648\n1073741824\n-2147483648\n0\n5\n2\n-2147483644\n-7\n";
+        assert_eq!(run(fits, ArithMode::Checked), (expected.to_string(), String::new()));
+        let x = "x = -2147483647 - 1\n";
+        for overflow in ["2^30 * 2", "1 << 31", "3 << 30", "1 << 32", "2^31", "13!", "x - 1", "y = -x", "abs(x)", "x / -1", "gcd(x, 0)", "2147483647 + 1"] {
+            assert_eq!(run(&format!("{x}{overflow}"), ArithMode::Checked), ("-2147483648\n".into(), "error: integer overflow\n".into()), "{overflow}");
+        }
+        assert_eq!(run("2^30 * 2; 1 << 31", ArithMode::Wrap).0, "-2147483648\n-2147483648\n");
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
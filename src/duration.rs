@@ -0,0 +1,113 @@
+//! Durations: `Value::Duration`, a whole number of milliseconds. With
+//! `Extensions::durations`, a number followed by a unit (`3h`, `45min`,
+//! `1.5d`) is a duration literal, and `3h + 45min` prints as `3h 45min`.
+//!
+//! Durations add to and subtract from durations, scale by numbers, and
+//! divide by numbers (giving a duration) or by durations (giving a ratio).
+//! Everything else that mixes them with numbers is an `ArithError::Duration`.
+
+use crate::arith::ArithError;
+use crate::parser::{NodeVal, Value};
+use crate::prelude::*;
+
+/// Each unit with its length in milliseconds, longest first.
+pub const UNITS: [(&str, i64); 5] = [("d", 86_400_000), ("h", 3_600_000), ("min", 60_000), ("s", 1000), ("ms", 1)];
+
+/// The length of `unit` in milliseconds.
+pub fn unit(name: &str) -> Option<i64> {
+    UNITS.iter().find(|(u, _)| *u == name).map(|&(_, ms)| ms)
+}
+
+/// `ms` spelled out in units from days down, leaving out those that are
+/// zero: `1d 2h`, `-1min 30s` (that is, minus 90 seconds), `0s`.
+pub fn format(ms: i64) -> String {
+    if ms == 0 {
+        return "0s".to_string();
+    }
+    let mut rest = ms.unsigned_abs();
+    let mut parts = Vec::new();
+    for (name, len) in UNITS {
+        let n = rest / len as u64;
+        rest %= len as u64;
+        if n > 0 {
+            parts.push(format!("{n}{name}"));
+        }
+    }
+    format!("{}{}", if ms < 0 { "-" } else { "" }, parts.join(" "))
+}
+
+/// `d` times the number `n`, rounded to the millisecond.
+fn scale(d: i64, n: Value) -> Result<i64, ArithError> {
+    match n {
+        Value::Float(f) => {
+            let v = (d as f64 * f).round();
+            if v.is_finite() && v.abs() < i64::MAX as f64 { Ok(v as i64) } else { Err(ArithError::Overflow) }
+        }
+        n => i64::try_from(d as i128 * n.as_i128()).map_err(|_| ArithError::Overflow),
+    }
+}
+
+/// `n` units of `ms` milliseconds each, for the `duration` builtin that
+/// duration literals become.
+pub fn from(n: Value, ms: Value) -> Result<Value, String> {
+    match (n, ms) {
+        (Value::Duration(_), _) | (_, Value::Duration(_) | Value::Float(_)) => {
+            Err("expects a number and a whole number of milliseconds".to_string())
+        }
+        (n, ms) => {
+            let ms = i64::try_from(ms.as_i128()).map_err(|_| format!("gives {ms} milliseconds, which is out of range"))?;
+            scale(ms, n).map(Value::Duration).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Applies `op` to `args`, at least one of which is a duration.
+pub fn apply(op: &NodeVal, args: &[Value]) -> Result<Value, ArithError> {
+    use Value::Duration as D;
+
+    let overflow = |v: Option<i64>| v.map(D).ok_or(ArithError::Overflow);
+    match (op, args) {
+        (NodeVal::Add, [d @ D(_)]) => Ok(*d),
+        (NodeVal::Sub, [D(a)]) => overflow(a.checked_neg()),
+        (NodeVal::Add, [D(a), D(b)]) => overflow(a.checked_add(*b)),
+        (NodeVal::Sub, [D(a), D(b)]) => overflow(a.checked_sub(*b)),
+        (NodeVal::Mul, [D(d), n] | [n, D(d)]) if !matches!(n, D(_)) => scale(*d, *n).map(D),
+        (NodeVal::Div, [_, D(0)]) => Err(ArithError::DivisionByZero),
+        (NodeVal::Div, [D(a), D(b)]) => Ok(Value::Float(*a as f64 / *b as f64)),
+        (NodeVal::Div, [D(_), n]) if n.as_f64() == 0.0 => Err(ArithError::DivisionByZero),
+        (NodeVal::Div, [D(d), Value::Float(f)]) => scale(*d, Value::Float(1.0 / f)).map(D),
+        (NodeVal::Div, [D(d), n]) => Ok(D((*d as i128 / n.as_i128()) as i64)),
+        (op, [D(a), D(b)]) if op.is_comparison() => Ok(Value::Int(op.compare(a, b))),
+        _ => Err(ArithError::Duration),
+    }
+}
+
+#[test]
+fn tests() {
+    use crate::arith::ArithMode;
+
+    assert_eq!(format(3 * 3_600_000 + 45 * 60_000), "3h 45min");
+    assert_eq!(format(8 * 86_400_000), "8d");
+    assert_eq!(format(-90_500), "-1min 30s 500ms");
+    assert_eq!(format(0), "0s");
+    assert_eq!(unit("min"), Some(60_000));
+    assert_eq!(unit("m"), None);
+
+    let apply = |op: NodeVal, args: &[Value]| op.apply(args, ArithMode::default()).map(|v| v.to_string());
+    let (h, min) = (Value::Duration(3_600_000), Value::Duration(60_000));
+    assert_eq!(apply(NodeVal::Add, &[h, min]), Ok("1h 1min".into()));
+    assert_eq!(apply(NodeVal::Sub, &[min, h]), Ok("-59min".into()));
+    assert_eq!(apply(NodeVal::Mul, &[Value::Int(36), h]), Ok("1d 12h".into()));
+    assert_eq!(apply(NodeVal::Mul, &[min, Value::Float(0.25)]), Ok("15s".into()));
+    assert_eq!(apply(NodeVal::Div, &[h, min]), Ok("60.0".into()));
+    assert_eq!(apply(NodeVal::Div, &[h, Value::Int(7)]), Ok("8min 34s 285ms".into()));
+    assert_eq!(apply(NodeVal::Div, &[h, Value::Float(1.5)]), Ok("40min".into()));
+    assert_eq!(apply(NodeVal::Lt, &[min, h]), Ok("1".into()));
+    assert_eq!(apply(NodeVal::Div, &[h, Value::Int(0)]), Err(ArithError::DivisionByZero));
+    assert_eq!(apply(NodeVal::Mul, &[Value::Duration(i64::MAX), Value::Int(2)]), Err(ArithError::Overflow));
+
+    assert_eq!(from(Value::Float(1.5), Value::Int(3_600_000)), Ok(Value::Duration(5_400_000)));
+    assert!(from(Value::Int(1), Value::Float(1.0)).is_err());
+    assert_eq!(apply(NodeVal::Add, &[h, Value::Int(1)]), Err(ArithError::Duration));
+    assert_eq!(apply(NodeVal::Fac, &[h]), Err(ArithError::Duration));
+}
@@ -0,0 +1,370 @@
+//! E-graph simplification (`--opt=egraph`). Instead of rewriting the tree
+//! in place, every rewrite only records that two expressions are equal, so
+//! rules that would otherwise fight each other (like commutativity) can all
+//! be explored. Once nothing new is learned, the cheapest equivalent
+//! expression is extracted.
+
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+use stoncc::parser::{LeafVal, Node, NodeVal};
+use stoncc::Span;
+use crate::pattern::Pattern;
+use crate::rules::{self, Rule};
+
+/// Identities that hold in every evaluation mode and are always explored.
+const BUILTIN_RULES: &str = "
+(+ ?a ?b) => (+ ?b ?a)
+(* ?a ?b) => (* ?b ?a)
+(* ?a 1) => ?a
+";
+
+/// Explored unless `--strict-fp` is given, since -0.0 + 0 is 0.0.
+const ADD_ZERO: &str = "(+ ?a 0) => ?a";
+
+/// Saturation stops after this many rounds or once the graph holds this
+/// many nodes, whichever comes first.
+const MAX_ITERS: usize = 30;
+const MAX_NODES: usize = 10_000;
+
+/// What extraction minimizes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Cost {
+    /// Number of nodes in the tree.
+    #[default]
+    Size,
+    /// Operator nesting depth.
+    Depth,
+}
+
+impl FromStr for Cost {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "size" => Ok(Cost::Size),
+            "depth" => Ok(Cost::Depth),
+            _ => Err(format!("unknown cost function '{s}' (expected size or depth)")),
+        }
+    }
+}
+
+type Id = usize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Op {
+    Leaf(LeafVal),
+    Node(NodeVal),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct ENode {
+    op: Op,
+    children: Vec<Id>,
+}
+
+/// Equivalence classes of expressions. Each class holds every known way of
+/// writing the same value, with operands referring to other classes.
+#[derive(Default)]
+struct EGraph {
+    /// Union-find over class ids.
+    parent: Vec<Id>,
+    /// Nodes of each canonical class.
+    classes: BTreeMap<Id, Vec<ENode>>,
+    /// The class each canonical node belongs to.
+    memo: HashMap<ENode, Id>,
+    /// Where in the source the expressions of each class came from.
+    spans: HashMap<Id, Span>,
+}
+
+/// The input tree as classes, so extraction can hand each occurrence its
+/// own source location back.
+struct Origin {
+    id: Id,
+    span: Span,
+    children: Vec<Origin>,
+}
+
+/// Class assigned to each pattern variable by a match.
+type Subst = BTreeMap<String, Id>;
+
+impl EGraph {
+    fn find(&self, mut id: Id) -> Id {
+        while self.parent[id] != id {
+            id = self.parent[id];
+        }
+        id
+    }
+
+    fn canon(&self, n: &ENode) -> ENode {
+        ENode { op: n.op.clone(), children: n.children.iter().map(|&c| self.find(c)).collect() }
+    }
+
+    fn add(&mut self, n: ENode) -> Id {
+        let n = self.canon(&n);
+        if let Some(&id) = self.memo.get(&n) {
+            return self.find(id);
+        }
+
+        let id = self.parent.len();
+        self.parent.push(id);
+        self.classes.insert(id, vec![n.clone()]);
+        self.memo.insert(n, id);
+        id
+    }
+
+    fn add_tree(&mut self, node: &Node) -> Origin {
+        let (id, children) = match node {
+            Node::Leaf(v, _) => (self.add(ENode { op: Op::Leaf(v.clone()), children: vec![] }), vec![]),
+            Node::Error(_) => panic!("cannot optimize a tree with syntax errors"),
+            Node::Node { v, children, .. } => {
+                let children: Vec<Origin> = children.iter().map(|c| self.add_tree(c)).collect();
+                let ids = children.iter().map(|c| c.id).collect();
+                (self.add(ENode { op: Op::Node(v.clone()), children: ids }), children)
+            }
+        };
+        self.spans.entry(id).or_insert(node.span());
+        Origin { id, span: node.span(), children }
+    }
+
+    fn union(&mut self, a: Id, b: Id) -> bool {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+
+        self.parent[b] = a;
+        if let Some(span) = self.spans.remove(&b) {
+            self.spans.entry(a).or_insert(span);
+        }
+        let nodes = self.classes.remove(&b).unwrap();
+        self.classes.get_mut(&a).unwrap().extend(nodes);
+        true
+    }
+
+    /// Restores the invariant that equal nodes live in the same class:
+    /// merging two classes can make nodes that use them equal, too.
+    fn rebuild(&mut self) {
+        loop {
+            let mut memo = HashMap::new();
+            let mut merges = Vec::new();
+            for (&id, nodes) in &self.classes {
+                for n in nodes {
+                    match memo.get(&self.canon(n)) {
+                        Some(&other) if other != id => merges.push((other, id)),
+                        Some(_) => {}
+                        None => {
+                            memo.insert(self.canon(n), id);
+                        }
+                    }
+                }
+            }
+
+            if merges.is_empty() {
+                self.memo = memo;
+                break;
+            }
+            for (a, b) in merges {
+                self.union(a, b);
+            }
+        }
+
+        let canon: Vec<(Id, Vec<ENode>)> = self
+            .classes
+            .iter()
+            .map(|(&id, nodes)| {
+                let mut nodes: Vec<ENode> = nodes.iter().map(|n| self.canon(n)).collect();
+                nodes.sort();
+                nodes.dedup();
+                (id, nodes)
+            })
+            .collect();
+        self.classes = canon.into_iter().collect();
+    }
+
+    fn size(&self) -> usize {
+        self.classes.values().map(Vec::len).sum()
+    }
+
+    /// Every way `p` matches some node of class `id`, extending `s`.
+    fn ematch(&self, p: &Pattern, id: Id, s: &Subst) -> Vec<Subst> {
+        let id = self.find(id);
+        match p {
+            Pattern::Var(name) => match s.get(name) {
+                Some(&bound) if self.find(bound) == id => vec![s.clone()],
+                Some(_) => vec![],
+                None => {
+                    let mut s = s.clone();
+                    s.insert(name.clone(), id);
+                    vec![s]
+                }
+            },
+            Pattern::Leaf(v) => {
+                let leaf = Op::Leaf(v.clone());
+                match self.classes[&id].iter().any(|n| n.op == leaf) {
+                    true => vec![s.clone()],
+                    false => vec![],
+                }
+            }
+            Pattern::Node { v, children } => {
+                let mut out = Vec::new();
+                for n in &self.classes[&id] {
+                    if n.op != Op::Node(v.clone()) || n.children.len() != children.len() {
+                        continue;
+                    }
+
+                    let mut partial = vec![s.clone()];
+                    for (p, &c) in children.iter().zip(&n.children) {
+                        partial = partial.iter().flat_map(|s| self.ematch(p, c, s)).collect();
+                    }
+                    out.extend(partial);
+                }
+                out
+            }
+        }
+    }
+
+    fn instantiate(&mut self, p: &Pattern, s: &Subst) -> Id {
+        match p {
+            Pattern::Var(name) => s[name],
+            Pattern::Leaf(v) => self.add(ENode { op: Op::Leaf(v.clone()), children: vec![] }),
+            Pattern::Node { v, children } => {
+                let children = children.iter().map(|c| self.instantiate(c, s)).collect();
+                self.add(ENode { op: Op::Node(v.clone()), children })
+            }
+        }
+    }
+
+    /// Applies every rule everywhere until no new equality is found.
+    fn saturate(&mut self, rules: &[Rule]) {
+        for _ in 0..MAX_ITERS {
+            let mut matches = Vec::new();
+            for &id in self.classes.keys() {
+                for r in rules {
+                    for s in self.ematch(&r.lhs, id, &Subst::new()) {
+                        matches.push((r, id, s));
+                    }
+                }
+            }
+
+            let mut changed = false;
+            for (r, id, s) in matches {
+                let new = self.instantiate(&r.rhs, &s);
+                changed |= self.union(id, new);
+            }
+            self.rebuild();
+
+            if !changed || self.size() > MAX_NODES {
+                break;
+            }
+        }
+    }
+
+    /// The cheapest tree in class `root`.
+    fn extract(&self, root: &Origin, cost: Cost) -> Node {
+        let mut best: BTreeMap<Id, (u64, &ENode)> = BTreeMap::new();
+
+        loop {
+            let mut changed = false;
+            for (&id, nodes) in &self.classes {
+                for n in nodes {
+                    let costs: Option<Vec<u64>> =
+                        n.children.iter().map(|c| best.get(&self.find(*c)).map(|b| b.0)).collect();
+                    let Some(costs) = costs else { continue };
+
+                    let c = match cost {
+                        Cost::Size => 1 + costs.iter().sum::<u64>(),
+                        Cost::Depth => 1 + costs.iter().copied().max().unwrap_or(0),
+                    };
+                    if best.get(&id).is_none_or(|b| c < b.0) {
+                        best.insert(id, (c, n));
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        self.build(root.id, Some(root), &best)
+    }
+
+    /// Builds the chosen tree of class `id`. Where it still has the same
+    /// value as part of the input, that part's span is kept; anything else
+    /// takes the span of the first input expression in its class, if any.
+    fn build(&self, id: Id, origin: Option<&Origin>, best: &BTreeMap<Id, (u64, &ENode)>) -> Node {
+        let id = self.find(id);
+        let origin = origin.filter(|o| self.find(o.id) == id);
+        let span = origin.map(|o| o.span).or_else(|| self.spans.get(&id).copied()).unwrap_or_default();
+
+        let n = best[&id].1;
+        match &n.op {
+            Op::Leaf(v) => Node::Leaf(v.clone(), span),
+            Op::Node(v) => {
+                // Operands may have been reordered, so match them up by class.
+                let mut left: Vec<&Origin> = origin.map_or(vec![], |o| o.children.iter().collect());
+                let children = n
+                    .children
+                    .iter()
+                    .map(|&c| {
+                        let at = left.iter().position(|o| self.find(o.id) == self.find(c));
+                        self.build(c, at.map(|i| left.remove(i)), best)
+                    })
+                    .collect();
+                Node::Node { v: v.clone(), children, span }
+            }
+        }
+    }
+}
+
+/// The cheapest expression equivalent to `node` under the built-in
+/// identities and `rules`. With `strict_fp`, only under those that give
+/// floats back bit for bit.
+pub fn simplify(node: &Node, rules: &[Rule], cost: Cost, strict_fp: bool) -> Node {
+    let mut all = rules::parse(BUILTIN_RULES).unwrap();
+    if !strict_fp {
+        all.extend(rules::parse(ADD_ZERO).unwrap());
+    }
+    all.extend_from_slice(rules);
+
+    let mut g = EGraph::default();
+    let root = g.add_tree(node);
+    g.saturate(&all);
+    g.extract(&root, cost)
+}
+
+#[test]
+fn tests() {
+    let factor = rules::parse("(+ (* ?a ?b) (* ?a ?c)) => (* ?a (+ ?b ?c))").unwrap();
+    let run = |s: &str, rules: &[Rule], cost| {
+        simplify(&stoncc::parser::expr(s.as_bytes()).unwrap(), rules, cost, false).to_string()
+    };
+
+    // Factoring only applies once commutativity has flipped z * x.
+    assert_eq!(run("x * y + z * x", &factor, Cost::Size), "(* x (+ y z))");
+    assert_eq!(run("(a + 0) * 1 + b", &[], Cost::Size), "(+ a b)");
+    let strict = simplify(&stoncc::parser::expr(b"(a + 0) * 1 + b").unwrap(), &[], Cost::Size, true);
+    assert_eq!(strict.to_string(), "(+ (+ a 0) b)");
+
+    let assoc = rules::parse("(+ (+ ?a ?b) ?c) => (+ ?a (+ ?b ?c))\n(+ ?a (+ ?b ?c)) => (+ (+ ?a ?b) ?c)").unwrap();
+    let deep = run("a + b + c + d", &assoc, Cost::Depth);
+    assert_eq!(deep.matches('(').count(), 3);
+    assert!(deep.contains("(+ (+") && deep.contains(") (+"), "{deep}");
+
+    // Every occurrence keeps its own location, even once reordered; x * 1
+    // became x, which stands for all of it.
+    let tree = simplify(&stoncc::parser::expr(b"x * 1 + (1 / 0 + x)").unwrap(), &[], Cost::Size, false);
+    let mut spans = Vec::new();
+    let mut todo = vec![&tree];
+    while let Some(n) = todo.pop() {
+        if let Node::Node { children, .. } = n {
+            todo.extend(children);
+        }
+        spans.push((n.to_string(), n.span().start..n.span().end));
+    }
+    spans.sort_by_key(|s| s.1.start);
+    assert!(spans.contains(&("x".into(), 0..5)), "{spans:?}");
+    assert!(spans.contains(&("x".into(), 17..18)), "{spans:?}");
+    assert!(spans.contains(&("(/ 1 0)".into(), 9..14)), "{spans:?}");
+}
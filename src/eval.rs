@@ -0,0 +1,448 @@
+use core::fmt;
+
+use crate::arith::ArithError;
+use crate::builtins::{Builtins, Function};
+use crate::lexer::Span;
+use crate::limits::{LimitExceeded, Meter};
+use crate::parser::{LeafVal, Node, NodeVal, Nodes, ShortCircuit, Value};
+use crate::prelude::*;
+
+/// Values of variables, either predefined or assigned by the program. In
+/// `--decimal` mode the values are fixed-point units, and in `--bigint`
+/// mode `BigInt`s. A `HashMap`, or a `BTreeMap` without `std`.
+pub type Env<V = Value> = Map<String, V>;
+
+/// Where evaluation looks variables up: an `Env`, or `Layered` ones.
+pub trait Scope<V> {
+    fn lookup(&self, name: &str) -> Option<&V>;
+}
+
+impl<V> Scope<V> for Env<V> {
+    fn lookup(&self, name: &str) -> Option<&V> {
+        self.get(name)
+    }
+}
+
+/// The variables of `top` over those of `below`, which they shadow. Neither
+/// is copied, so a layer of a few variables over a large environment is
+/// cheap, and nothing assigned in the top layer reaches the one below.
+pub struct Layered<'a, V = Value> {
+    pub top: &'a Env<V>,
+    pub below: &'a dyn Scope<V>,
+}
+
+impl<V> Scope<V> for Layered<'_, V> {
+    fn lookup(&self, name: &str) -> Option<&V> {
+        self.top.get(name).or_else(|| self.below.lookup(name))
+    }
+}
+
+/// Deepest nesting, counting the bodies of called functions, at which a
+/// program-defined function may still be called, so that runaway recursion
+/// is an error.
+pub const MAX_CALL_DEPTH: usize = 256;
+
+/// Why evaluation stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EvalError {
+    Limit(LimitExceeded),
+    Unbound(String, Span),
+    Unfilled(usize, Span),
+    UnknownFunction(String, Span),
+    /// A function rejected its arguments.
+    Call(String, String, Span),
+    /// Calls nested deeper than `MAX_CALL_DEPTH`.
+    Recursion(String, Span),
+    /// An integer operator had no result in the meter's `ArithMode`.
+    Arith(ArithError, Span),
+    /// A `Node::Error` that a recovering parse left in the tree.
+    Syntax(Span),
+    /// Something the numbers of an evaluation mode don't have, like `<<`
+    /// in `bigint`, said by the message.
+    Unsupported(String, Span),
+    /// A `limits::CancelToken` stopped the evaluation.
+    Cancelled,
+}
+
+impl From<LimitExceeded> for EvalError {
+    fn from(e: LimitExceeded) -> Self {
+        EvalError::Limit(e)
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Limit(e) => write!(f, "{e}"),
+            EvalError::Unbound(s, _) => write!(f, "symbol {s} is not defined"),
+            EvalError::Unfilled(n, _) => write!(f, "placeholder ?{n} was not filled in"),
+            EvalError::UnknownFunction(s, _) => write!(f, "function {s} is not defined"),
+            EvalError::Call(s, msg, _) => write!(f, "{s}() {msg}"),
+            EvalError::Recursion(s, _) => write!(f, "calls to {s}() nest too deeply (is the recursion unbounded?)"),
+            EvalError::Arith(e, _) => write!(f, "{e}"),
+            EvalError::Syntax(_) => write!(f, "this did not parse"),
+            EvalError::Unsupported(msg, _) => write!(f, "{msg}"),
+            EvalError::Cancelled => write!(f, "evaluation was cancelled"),
+        }
+    }
+}
+
+/// As with `ParseError`, the message is the one of the `source` if there
+/// is one.
+impl core::error::Error for EvalError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            EvalError::Limit(e) => Some(e),
+            EvalError::Arith(e, _) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl EvalError {
+    /// A stable name for the kind of error, for `--expect-error`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            EvalError::Limit(e) => e.code(),
+            EvalError::Unbound(..) => "unbound-symbol",
+            EvalError::Unfilled(..) => "unfilled-placeholder",
+            EvalError::UnknownFunction(..) => "unknown-function",
+            EvalError::Call(..) => "bad-arguments",
+            EvalError::Recursion(..) => "recursion",
+            EvalError::Arith(e, _) => e.code(),
+            EvalError::Syntax(_) => "syntax-error",
+            EvalError::Unsupported(..) => "unsupported",
+            EvalError::Cancelled => "cancelled",
+        }
+    }
+
+    /// The offending source text, if the tree still knows where it was.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            EvalError::Limit(_) | EvalError::Cancelled => None,
+            EvalError::Unbound(_, span)
+            | EvalError::Unfilled(_, span)
+            | EvalError::UnknownFunction(_, span)
+            | EvalError::Call(_, _, span)
+            | EvalError::Recursion(_, span)
+            | EvalError::Arith(_, span)
+            | EvalError::Syntax(span)
+            | EvalError::Unsupported(_, span) => Some(*span).filter(|s| s.end > s.start),
+        }
+    }
+}
+
+/// Evaluates `ast` with the variables in `env`, charging every step to
+/// `meter`. Sharing one meter across several calls limits them as a whole.
+/// Function calls go to the default `Builtins`.
+pub fn eval(ast: &Node, env: &Env, meter: &mut Meter) -> Result<Value, EvalError> {
+    eval_with(ast, env, &Builtins::default(), meter)
+}
+
+/// Like `eval`, with calls going to `builtins`.
+pub fn eval_with(ast: &Node, env: &Env, builtins: &Builtins, meter: &mut Meter) -> Result<Value, EvalError> {
+    walk(&Values, Nodes, ast, env, builtins, meter)
+}
+
+/// Like `eval_with`, with the variables in `scope`.
+pub fn eval_in(ast: &Node, scope: &(impl Scope<Value> + ?Sized), builtins: &Builtins, meter: &mut Meter) -> Result<Value, EvalError> {
+    walk(&Values, Nodes, ast, scope, builtins, meter)
+}
+
+/// The numbers an evaluation works with: `Value`s here, fixed-point units
+/// in `decimal` and `BigInt`s in `bigint`.
+pub(crate) trait Numbers {
+    type V: Clone;
+
+    /// An `Int` or `Decimal` literal, found at `span`.
+    fn literal(&self, leaf: &LeafVal, span: Span) -> Result<Self::V, EvalError>;
+    fn is_true(&self, v: &Self::V) -> bool;
+    fn truth(&self, b: bool) -> Self::V;
+    /// An operator, or a call to a function the program didn't define.
+    fn apply(&self, v: &NodeVal, args: &[Self::V], span: Span, builtins: &Builtins, meter: &Meter) -> Result<Self::V, EvalError>;
+    /// Checks a value against the meter's limits.
+    fn check(&self, v: &Self::V, meter: &Meter) -> Result<(), LimitExceeded>;
+}
+
+pub(crate) struct Values;
+
+impl Numbers for Values {
+    type V = Value;
+
+    fn literal(&self, leaf: &LeafVal, _: Span) -> Result<Value, EvalError> {
+        match leaf {
+            LeafVal::Int(v) => Ok(Value::Int(*v)),
+            LeafVal::Decimal(s) => Ok(Value::Float(s.parse().unwrap())),
+            _ => unreachable!(),
+        }
+    }
+
+    fn is_true(&self, v: &Value) -> bool {
+        v.is_true()
+    }
+
+    fn truth(&self, b: bool) -> Value {
+        Value::Int(b as i32)
+    }
+
+    fn apply(&self, v: &NodeVal, args: &[Value], span: Span, builtins: &Builtins, meter: &Meter) -> Result<Value, EvalError> {
+        match v {
+            NodeVal::Call(name) => match builtins.call(name, args) {
+                Some(v) => {
+                    let v = v.map_err(|msg| EvalError::Call(name.clone(), msg, span))?;
+                    builtins.narrow(name, v, meter.arith()).map_err(|e| EvalError::Arith(e, span))
+                }
+                None => Err(EvalError::UnknownFunction(name.clone(), span)),
+            },
+            v => v.apply(args, meter.arith()).map_err(|e| EvalError::Arith(e, span)),
+        }
+    }
+
+    fn check(&self, v: &Value, meter: &Meter) -> Result<(), LimitExceeded> {
+        meter.value(v.as_i128())
+    }
+}
+
+/// A node of a tree as `walk` sees it.
+pub(crate) enum View<'a, C> {
+    Leaf(&'a LeafVal, Span),
+    Node(&'a NodeVal, C, Span),
+    Error(Span),
+}
+
+/// Trees `walk` can evaluate, with nodes named by `Id`: `Node`s, or the
+/// nodes of an `arena::Ast`.
+pub(crate) trait Tree<'a>: Copy {
+    type Id: Copy;
+    type Children: DoubleEndedIterator<Item = Self::Id> + ExactSizeIterator;
+
+    fn view(self, id: Self::Id) -> View<'a, Self::Children>;
+    /// The body of `f` as a node of this tree, if it can be one.
+    fn body(self, f: &'a Function) -> Option<Self::Id>;
+}
+
+impl<'a> Tree<'a> for Nodes {
+    type Id = &'a Node;
+    type Children = core::slice::Iter<'a, Node>;
+
+    fn view(self, node: &'a Node) -> View<'a, Self::Children> {
+        match node {
+            Node::Leaf(v, span) => View::Leaf(v, *span),
+            Node::Node { v, children, span } => View::Node(v, children.iter(), *span),
+            Node::Error(span) => View::Error(*span),
+        }
+    }
+
+    fn body(self, f: &'a Function) -> Option<&'a Node> {
+        Some(&f.body)
+    }
+}
+
+/// A step of `walk`. Nodes are evaluated at a depth, in the scope of one of
+/// the calls under way or at the top level.
+enum Task<Id> {
+    Eval(Id, usize, Option<usize>),
+    /// Applies a node to the values of its operands.
+    Apply(Id, usize),
+    /// Goes on with the operand of a short-circuit node that the value of
+    /// its first one selects.
+    Select(Id, usize, Option<usize>),
+    /// Replaces a value with its truth.
+    Truth,
+    /// Ends the innermost call.
+    Return,
+}
+
+/// Evaluates the tree under `root` with a stack of tasks rather than by
+/// recursion, so deep trees take memory rather than stack.
+pub(crate) fn walk<'a, N: Numbers, T: Tree<'a>, G: Scope<N::V> + ?Sized>(
+    n: &N,
+    tree: T,
+    root: T::Id,
+    globals: &G,
+    builtins: &'a Builtins,
+    meter: &mut Meter,
+) -> Result<N::V, EvalError> {
+    run(n, tree, Task::Eval(root, 0, None), Vec::new(), globals, builtins, meter)
+}
+
+/// Runs `first` and the tasks it leads to, with the parameters of the calls
+/// under way in `calls`.
+fn run<'a, N: Numbers, T: Tree<'a>, G: Scope<N::V> + ?Sized>(
+    n: &N,
+    tree: T,
+    first: Task<T::Id>,
+    mut calls: Vec<Env<N::V>>,
+    globals: &G,
+    builtins: &'a Builtins,
+    meter: &mut Meter,
+) -> Result<N::V, EvalError> {
+    let mut tasks = vec![first];
+    let mut values = Vec::new();
+
+    while let Some(task) = tasks.pop() {
+        let v = match task {
+            Task::Eval(node, depth, call) => {
+                meter.enter(depth)?;
+                match tree.view(node) {
+                    // Evaluates the first operand, and then only the one it selects.
+                    View::Node(v, mut children, _) if v.short_circuit(true).is_some() => {
+                        tasks.push(Task::Select(node, depth, call));
+                        tasks.push(Task::Eval(children.next().unwrap(), depth + 1, call));
+                        continue;
+                    }
+                    View::Node(_, children, _) => {
+                        tasks.push(Task::Apply(node, depth));
+                        tasks.extend(children.rev().map(|c| Task::Eval(c, depth + 1, call)));
+                        continue;
+                    }
+                    View::Leaf(LeafVal::Placeholder(i), span) => return Err(EvalError::Unfilled(*i, span)),
+                    View::Leaf(LeafVal::Sym(s), span) => {
+                        let v = call.and_then(|i| calls[i].get(s.as_str())).or_else(|| globals.lookup(s));
+                        v.cloned().ok_or_else(|| EvalError::Unbound(s.to_string(), span))?
+                    }
+                    View::Leaf(LeafVal::Bool(b), _) => n.truth(*b),
+                    View::Leaf(leaf, span) => n.literal(leaf, span)?,
+                    View::Error(span) => return Err(EvalError::Syntax(span)),
+                }
+            }
+            Task::Select(node, depth, call) => {
+                let View::Node(v, mut children, _) = tree.view(node) else { unreachable!() };
+                let first = values.pop().unwrap();
+                meter.op()?;
+                match v.short_circuit(n.is_true(&first)).unwrap() {
+                    ShortCircuit::Done(b) => n.truth(b),
+                    ShortCircuit::Truth(i) => {
+                        tasks.push(Task::Truth);
+                        tasks.push(Task::Eval(children.nth(i).unwrap(), depth + 1, call));
+                        continue;
+                    }
+                    ShortCircuit::Operand(i) => {
+                        tasks.push(Task::Eval(children.nth(i).unwrap(), depth + 1, call));
+                        continue;
+                    }
+                }
+            }
+            Task::Truth => {
+                let v = values.pop().unwrap();
+                n.truth(n.is_true(&v))
+            }
+            Task::Apply(node, depth) => {
+                let View::Node(v, children, span) = tree.view(node) else { unreachable!() };
+                let args = values.split_off(values.len() - children.len());
+                meter.op()?;
+                let defined = match v {
+                    NodeVal::Call(name) => builtins.defined(name).map(|f| (name, f)),
+                    _ => None,
+                };
+                let Some((name, f)) = defined else {
+                    let v = n.apply(v, &args, span, builtins, meter)?;
+                    n.check(&v, meter)?;
+                    values.push(v);
+                    continue;
+                };
+                if depth >= MAX_CALL_DEPTH {
+                    return Err(EvalError::Recursion(name.clone(), span));
+                }
+                let params = f.bind(&args).map_err(|msg| EvalError::Call(name.clone(), msg, span))?;
+                let Some(body) = tree.body(f) else {
+                    // A body of another kind of tree gets a walk of its own.
+                    let first = Task::Eval(&f.body, depth + 1, Some(0));
+                    values.push(run(n, Nodes, first, vec![params], globals, builtins, meter)?);
+                    continue;
+                };
+                calls.push(params);
+                tasks.push(Task::Return);
+                tasks.push(Task::Eval(body, depth + 1, Some(calls.len() - 1)));
+                continue;
+            }
+            // The value of the body is the value of the call.
+            Task::Return => {
+                calls.pop();
+                continue;
+            }
+        };
+        n.check(&v, meter)?;
+        values.push(v);
+    }
+
+    Ok(values.pop().unwrap())
+}
+
+#[test]
+fn tests() {
+    let mut builtins = Builtins::default();
+    let body = crate::parser::expr(b"x * 10 + y").unwrap();
+    builtins.define("f", crate::Function { params: vec!["x".into()], body });
+    let loops = crate::parser::expr(b"g() + 1").unwrap();
+    builtins.define("g", crate::Function { params: vec![], body: loops });
+    let deep = crate::parser::expr(format!("{}h()", "-".repeat(crate::MAX_DEPTH - 2)).as_bytes()).unwrap();
+    builtins.define("h", crate::Function { params: vec![], body: deep });
+
+    let env: Env = [("x".to_string(), Value::Int(1)), ("y".to_string(), Value::Int(2))].into();
+    let run = |s: &str| {
+        let mut meter = Meter::new(Default::default());
+        eval_with(&crate::parser::expr(s.as_bytes()).unwrap(), &env, &builtins, &mut meter)
+    };
+
+    // Parameters shadow variables of the same name; others stay visible.
+    assert_eq!(run("f(5) + x"), Ok(Value::Int(53)));
+    assert_eq!(run("f(f(0))"), Ok(Value::Int(22)));
+    assert!(matches!(run("f()"), Err(EvalError::Call(..))));
+    assert!(matches!(run("g()"), Err(EvalError::Recursion(..))));
+    assert_eq!(run("f(z)").unwrap_err().code(), "unbound-symbol");
+
+    // However deep the input, the trees the parser makes evaluate on a
+    // small stack, and calls can't pile them up.
+    let n = 100_000;
+    assert_eq!(run(&format!("{}f(0){}", "(".repeat(n), ")".repeat(n))), Ok(Value::Int(2)));
+    assert_eq!(run(&format!("{}x", "-".repeat(crate::MAX_DEPTH - 1))), Ok(Value::Int(-1)));
+    assert!(matches!(run("h()"), Err(EvalError::Recursion(..))));
+
+    // `true` and `false` are 1 and 0, and any number can be a condition.
+    assert_eq!(run("true + true"), Ok(Value::Int(2)));
+    assert_eq!(run("(x < y) == true && (x > y) == false"), Ok(Value::Int(1)));
+    assert_eq!(run("2.5 ? false : 3"), Ok(Value::Int(0)));
+
+    // The operands that aren't needed are never evaluated.
+    assert_eq!(run("0 && g() || x > 0 ? f(2) : g()"), Ok(Value::Int(22)));
+    assert_eq!(run("y || g()"), Ok(Value::Int(1)));
+    assert_eq!(run("0.5 && 2"), Ok(Value::Int(1)));
+
+    // Integer overflow follows the meter's mode; division by zero fails
+    // in all of them.
+    assert_eq!(run("y / (x - 1)").unwrap_err().code(), "division-by-zero");
+    assert_eq!(run("2147483647 + x").unwrap_err().code(), "arithmetic-overflow");
+    assert_eq!(run("abs(-2147483647 - x)").unwrap_err().code(), "arithmetic-overflow");
+    let mode = |m: crate::ArithMode, s: &str| {
+        let mut meter = Meter::new(Default::default()).with_arith(m);
+        eval_with(&crate::parser::expr(s.as_bytes()).unwrap(), &env, &builtins, &mut meter)
+    };
+    assert_eq!(mode(crate::ArithMode::Wrap, "2147483647 + x"), Ok(Value::Int(i32::MIN)));
+    assert_eq!(mode(crate::ArithMode::Promote, "(2147483647 + x) * 2 - 1"), Ok(Value::Wide((1 << 32) - 1)));
+
+    // Operands an operator can't take are errors too, not panics, even
+    // without `sema` to rule them out first.
+    assert_eq!(run("(x + 0.5)!").unwrap_err().code(), "not-an-integer");
+    assert_eq!(run("sqrt(y) & 1").unwrap_err().code(), "not-an-integer");
+    let hours = |s: &str| crate::parser::program_with(s.as_bytes(), crate::Extensions { durations: true, ..Default::default() }).0.unwrap();
+    let crate::Stmt::Expr(e) = &hours("2h * 1h").stmts[0] else { unreachable!() };
+    assert_eq!(eval_with(e, &env, &builtins, &mut Meter::new(Default::default())), Err(EvalError::Arith(ArithError::Duration, Span { start: 0, end: 7 })));
+    let units = |s: &str| {
+        let program = crate::parser::program_with(s.as_bytes(), crate::Extensions { units: true, ..Default::default() }).0.unwrap();
+        let crate::Stmt::Expr(e) = &program.stmts[0] else { unreachable!() };
+        eval_with(e, &env, &builtins, &mut Meter::new(Default::default())).map(|v| v.to_string())
+    };
+    assert_eq!(units("3 m / 2 s"), Ok("1.5 m/s".into()));
+    assert_eq!(units("2 kg * 9.81 m / (1 s)^2 > 19 N"), Ok("1".into()));
+    assert_eq!(units("1 m + 1 s").unwrap_err().code(), "unit-mismatch");
+
+    use std::error::Error;
+    let e = run("y / (x - 1)").unwrap_err();
+    assert_eq!(e.source().and_then(|s| s.downcast_ref::<ArithError>()), Some(&ArithError::DivisionByZero));
+    assert!(run("f(1, 2)").unwrap_err().source().is_none());
+    let broken = Node::op(NodeVal::Add, vec![Node::leaf(LeafVal::Int(1)), Node::Error(Span { start: 4, end: 6 })]);
+    let e = eval_with(&broken, &env, &builtins, &mut Meter::new(Default::default())).unwrap_err();
+    assert_eq!((e.code(), e.span()), ("syntax-error", Some(Span { start: 4, end: 6 })));
+}
@@ -0,0 +1,116 @@
+//! `stoncc explain-parse`: prints each decision the parser makes on an
+//! expression, for learning how precedence works and for checking what a
+//! change to the operator table does.
+
+use stoncc::operators::{Fixity, OperatorTable};
+use stoncc::parser::{self, Extensions, Step};
+use stoncc::Span;
+
+fn fixity(f: Fixity) -> &'static str {
+    match f {
+        Fixity::Prefix => "prefix",
+        Fixity::Infix => "infix",
+        Fixity::Postfix => "postfix",
+    }
+}
+
+/// One line describing `step`, under the text of `s` it is about.
+fn line(s: &str, step: &Step) -> String {
+    let text = |span: Span| s.get(span.start..span.end).unwrap_or("");
+    let (at, what) = match step {
+        Step::Operand(span) => (text(*span), "operand".to_string()),
+        Step::Open { op, prec, span } => (text(*span), format!("{op} reads an operand of operators above {prec}")),
+        Step::Compare { op, fixity: f, prec, lassoc, min_prec, binds, span } => {
+            let assoc = match f {
+                Fixity::Infix if *lassoc => " left",
+                Fixity::Infix => " right",
+                _ => "",
+            };
+            let verdict = match (binds, f) {
+                (true, Fixity::Postfix) => "binds",
+                (true, _) => "binds, its right operand follows",
+                (false, _) if prec == min_prec && *lassoc => "stops, left-associative",
+                (false, _) => "stops",
+            };
+            let at = if span.start == span.end { "(implicit)" } else { text(*span) };
+            (at, format!("{} {op}, {prec}{assoc} against {min_prec}: {verdict}", fixity(*f)))
+        }
+        Step::End(span) if span.start == span.end => ("", "end of the expression: end of input".to_string()),
+        Step::End(span) => (text(*span), "end of the expression".to_string()),
+        Step::Reduce { op, span } => (text(*span), format!("reduce {op}")),
+    };
+    format!("{at:<16} {what}")
+}
+
+/// The steps of parsing `s`, one per line, then the tree or the error.
+pub fn explain(s: &str, ext: Extensions) -> String {
+    let (result, steps) = parser::explain(s.as_bytes(), ext, &OperatorTable::default());
+    let mut out = String::new();
+    for (i, step) in steps.iter().enumerate() {
+        out += &format!("{:>3}  {}\n", i + 1, line(s, step));
+    }
+    match result {
+        Ok(node) => out += &format!("result: {node}\n"),
+        Err(e) => out += &format!("error: {e}\n"),
+    }
+    out
+}
+
+/// `stoncc explain-parse [-e] EXPR [--calc] [--durations] [--bytes]
+/// [--units] [--implicit-mul] [--words] [--decimal-comma]`.
+pub fn main(args: &[String]) {
+    let usage = || -> ! {
+        eprintln!("usage: stoncc explain-parse [-e] EXPR [--calc] [--durations] [--bytes] [--units] [--implicit-mul] [--words] [--decimal-comma]");
+        std::process::exit(2);
+    };
+
+    let mut ext = Extensions::default();
+    let mut expr = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--calc" => ext.calculator = true,
+            "--durations" => ext.durations = true,
+            "--bytes" => ext.bytes = true,
+            "--units" => ext.units = true,
+            "--implicit-mul" => ext.implicit_mul = true,
+            "--words" => ext.words = true,
+            "--decimal-comma" => ext.decimal_comma = true,
+            "-e" if expr.is_none() => expr = Some(args.next().unwrap_or_else(|| usage())),
+            _ if expr.is_none() && !arg.starts_with("--") => expr = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let Some(expr) = expr else { usage() };
+    print!("{}", explain(expr, ext));
+}
+
+#[test]
+fn tests() {
+    let plain = Extensions::default();
+    assert_eq!(
+        explain("1-2-3", plain),
+        concat!(
+            "  1  1                operand\n",
+            "  2  -                infix -, 7 left against 0: binds, its right operand follows\n",
+            "  3  2                operand\n",
+            "  4  -                infix -, 7 left against 7: stops, left-associative\n",
+            "  5  1-2              reduce -\n",
+            "  6  -                infix -, 7 left against 0: binds, its right operand follows\n",
+            "  7  3                operand\n",
+            "  8                   end of the expression: end of input\n",
+            "  9  1-2-3            reduce -\n",
+            " 10                   end of the expression: end of input\n",
+            "result: (- (- 1 2) 3)\n",
+        )
+    );
+    // `!` binds looser than `^`, so it stops before `d` and takes `c^d`.
+    let out = explain("a+b*c^d!", plain);
+    assert!(out.contains("  8  !                postfix !, 12 against 13: stops\n  9  c^d              reduce ^\n"));
+    assert!(out.ends_with("result: (+ a (* b (! (^ c d))))\n"));
+    let out = explain("-(x) 2", Extensions { implicit_mul: true, ..plain });
+    assert!(out.contains("  1  -                - reads an operand of operators above 11\n"));
+    assert!(out.contains("(implicit)       infix *, 9 left against 11: stops\n"));
+    assert!(explain("1 + * 2", plain).ends_with("error: syntax error at 4: expected literal, found '*'\n"));
+}
@@ -0,0 +1,176 @@
+//! Printing results. A [`Format`] says how integers and floats are
+//! written: in decimal, hexadecimal, binary, scientific notation or as
+//! byte sizes, with floats to a fixed number of places and decimal digits
+//! grouped in thousands if asked. It is what `--out`, `--precision` and
+//! `--thousands` set, and an embedding can print its results the same way.
+//!
+//! ```
+//! use stoncc::format::{Format, Notation};
+//! use stoncc::Value;
+//!
+//! let hex = Format { notation: Notation::Hex, ..Format::default() };
+//! assert_eq!(hex.value(Value::Int(-255)), "-0xff");
+//!
+//! let fixed = Format { precision: Some(2), thousands: true, ..Format::default() };
+//! assert_eq!(fixed.value(Value::Float(1234567.891)), "1,234,567.89");
+//! ```
+
+use std::str::FromStr;
+
+use crate::bytes;
+use crate::parser::Value;
+
+/// The notation numbers are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Notation {
+    /// As `Value` displays them: decimal integers, and floats as the
+    /// shortest text that reads back as the same float.
+    #[default]
+    Plain,
+    /// Integers as byte sizes like `1.5GiB` (see `bytes::human`).
+    Human,
+    /// Integers in hexadecimal, like `0xff`.
+    Hex,
+    /// Integers in binary, like `0b101`.
+    Binary,
+    /// Integers and floats as a mantissa and a power of ten, like `1.5e3`.
+    Scientific,
+}
+
+impl Notation {
+    /// The names `--out` takes.
+    pub const NAMES: [&'static str; 5] = ["plain", "human", "hex", "bin", "sci"];
+}
+
+impl FromStr for Notation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Notation, String> {
+        match s {
+            "plain" => Ok(Notation::Plain),
+            "human" => Ok(Notation::Human),
+            "hex" => Ok(Notation::Hex),
+            "bin" => Ok(Notation::Binary),
+            "sci" => Ok(Notation::Scientific),
+            _ => Err(format!("unknown --out format '{s}' (expected plain, human, hex, bin or sci)")),
+        }
+    }
+}
+
+/// How to print a value. The default prints it as `Value` displays it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Format {
+    pub notation: Notation,
+    /// Digits after the point of floats, and of mantissas in scientific
+    /// notation. Floats are rounded to it rather than printed in full.
+    pub precision: Option<usize>,
+    /// Separate groups of three digits before the point with commas. Only
+    /// plain decimal numbers are grouped.
+    pub thousands: bool,
+}
+
+impl Format {
+    /// `v` in this format. Durations always print in units, and quantities
+    /// as a float followed by their unit.
+    pub fn value(&self, v: Value) -> String {
+        let text = match (v, self.notation) {
+            (Value::Duration(_), _) => return v.to_string(),
+            (Value::Quantity(x, unit), _) => return format!("{} {unit}", self.value(Value::Float(x))),
+            (Value::Int(_) | Value::Wide(_), Notation::Human) => return bytes::human(v.as_i128()),
+            (Value::Int(_) | Value::Wide(_), Notation::Hex) => return radix(v.as_i128(), "0x", format!("{:x}", v.as_i128().unsigned_abs())),
+            (Value::Int(_) | Value::Wide(_), Notation::Binary) => return radix(v.as_i128(), "0b", format!("{:b}", v.as_i128().unsigned_abs())),
+            (Value::Int(_) | Value::Wide(_), Notation::Scientific) => match self.precision {
+                Some(p) => format!("{:.p$e}", v.as_f64()),
+                None => int_scientific(v.as_i128()),
+            },
+            (Value::Int(_) | Value::Wide(_), Notation::Plain) => v.to_string(),
+            (Value::Float(f), _) if !f.is_finite() => return v.to_string(),
+            (Value::Float(f), Notation::Scientific) => match self.precision {
+                Some(p) => format!("{f:.p$e}"),
+                None => format!("{f:e}"),
+            },
+            (Value::Float(f), _) => match self.precision {
+                Some(p) => format!("{f:.p$}"),
+                None => v.to_string(),
+            },
+        };
+        if self.thousands && self.notation == Notation::Plain {
+            grouped(&text)
+        } else {
+            text
+        }
+    }
+}
+
+/// `n`, whose magnitude is `digits` in some base, with `prefix` after the
+/// sign.
+fn radix(n: i128, prefix: &str, digits: String) -> String {
+    let sign = if n < 0 { "-" } else { "" };
+    format!("{sign}{prefix}{digits}")
+}
+
+/// `n` in scientific notation with every digit it has, which `f64` would
+/// round for integers past 2^53.
+fn int_scientific(n: i128) -> String {
+    let sign = if n < 0 { "-" } else { "" };
+    let digits = n.unsigned_abs().to_string();
+    let (first, rest) = digits.split_at(1);
+    let rest = rest.trim_end_matches('0');
+    let point = if rest.is_empty() { "" } else { "." };
+    format!("{sign}{first}{point}{rest}e{}", digits.len() - 1)
+}
+
+/// `text`, a decimal number, with commas between groups of three digits
+/// before the point or exponent.
+fn grouped(text: &str) -> String {
+    let (sign, unsigned) = text.split_at(usize::from(text.starts_with('-')));
+    let end = unsigned.find(['.', 'e']).unwrap_or(unsigned.len());
+    let (whole, tail) = unsigned.split_at(end);
+
+    let mut out = String::from(sign);
+    for (i, c) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out + tail
+}
+
+#[test]
+fn tests() {
+    let format = |notation, precision, thousands| Format { notation, precision, thousands };
+    let plain = Format::default();
+    assert_eq!(plain.value(Value::Int(-42)), "-42");
+    assert_eq!(plain.value(Value::Float(0.1 + 0.2)), "0.30000000000000004");
+    assert_eq!(plain.value(Value::Float(1.0)), "1.0");
+    assert_eq!(plain.value(Value::Duration(90_000)), Value::Duration(90_000).to_string());
+
+    let hex = format(Notation::Hex, None, false);
+    assert_eq!(hex.value(Value::Int(255)), "0xff");
+    assert_eq!(hex.value(Value::Int(i32::MIN)), "-0x80000000");
+    assert_eq!(hex.value(Value::Float(2.5)), "2.5");
+    assert_eq!(format(Notation::Binary, None, false).value(Value::Int(5)), "0b101");
+    assert_eq!(format(Notation::Human, None, false).value(Value::Int(4096)), "4KiB");
+
+    let sci = format(Notation::Scientific, None, false);
+    assert_eq!(sci.value(Value::Int(1500)), "1.5e3");
+    assert_eq!(sci.value(Value::Int(-7)), "-7e0");
+    assert_eq!(sci.value(Value::Wide(123456789012345678901)), "1.23456789012345678901e20");
+    assert_eq!(sci.value(Value::Float(0.00025)), "2.5e-4");
+    assert_eq!(format(Notation::Scientific, Some(2), false).value(Value::Int(12345)), "1.23e4");
+    assert_eq!(format(Notation::Scientific, Some(1), false).value(Value::Float(0.00025)), "2.5e-4");
+
+    let fixed = format(Notation::Plain, Some(3), false);
+    assert_eq!(fixed.value(Value::Float(2.0 / 3.0)), "0.667");
+    assert_eq!(fixed.value(Value::Int(7)), "7");
+    assert_eq!(fixed.value(Value::Float(f64::INFINITY)), "inf");
+
+    let thousands = format(Notation::Plain, None, true);
+    assert_eq!(thousands.value(Value::Int(1234567)), "1,234,567");
+    assert_eq!(thousands.value(Value::Int(-123456)), "-123,456");
+    assert_eq!(thousands.value(Value::Int(999)), "999");
+    assert_eq!(thousands.value(Value::Float(-12345.5)), "-12,345.5");
+    assert_eq!(thousands.value(Value::Float(1e21)), "1e21");
+    assert_eq!(format(Notation::Hex, None, true).value(Value::Int(65535)), "0xffff");
+}
@@ -0,0 +1,78 @@
+//! Random expressions, the same for the same seed on every platform, for
+//! benchmarks and for property and fuzz tests. [`random`] builds trees
+//! over every operator, and [`sized`] writes source text of a given size
+//! that parses and evaluates without error.
+//!
+//! ```
+//! use stoncc::generate::{sized, Rng};
+//!
+//! let src = sized(&mut Rng::new(7), 1000);
+//! assert_eq!(src, sized(&mut Rng::new(7), 1000));
+//! assert!(stoncc::expr(src.as_bytes()).is_ok());
+//! ```
+
+use crate::parser::{LeafVal, Node, NodeVal};
+
+/// Small deterministic PRNG (xorshift64*), so that a seed always produces
+/// the same expressions on every platform.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// A digit, or one of the variables `a` to `e`.
+pub fn leaf(rng: &mut Rng) -> Node {
+    if rng.below(3) == 0 {
+        let sym = (b'a' + rng.below(5) as u8) as char;
+        Node::leaf(LeafVal::Sym(sym.to_string().into()))
+    } else {
+        Node::leaf(LeafVal::Int(rng.below(10) as i32))
+    }
+}
+
+/// A random tree of at most `depth` operator levels.
+pub fn random(rng: &mut Rng, depth: usize) -> Node {
+    if depth == 0 || rng.below(4) == 0 {
+        return leaf(rng);
+    }
+
+    let (v, arity) = NodeVal::SHAPES[rng.below(NodeVal::SHAPES.len())].clone();
+    let children = (0..arity).map(|_| random(rng, depth - 1)).collect();
+    Node::op(v, children)
+}
+
+/// An expression of about `bytes` bytes: a balanced tree of `+` and `-`
+/// over digits, which is shallow and never overflows.
+pub fn sized(rng: &mut Rng, bytes: usize) -> String {
+    fn build(rng: &mut Rng, leaves: usize, out: &mut String) {
+        if leaves == 1 {
+            out.push((b'1' + rng.below(9) as u8) as char);
+            return;
+        }
+        out.push('(');
+        build(rng, leaves / 2, out);
+        out.push_str(if rng.below(2) == 0 { " + " } else { " - " });
+        build(rng, leaves - leaves / 2, out);
+        out.push(')');
+    }
+
+    // A digit, an operator and a pair of parentheses for each leaf.
+    let mut out = String::with_capacity(bytes);
+    build(rng, (bytes / 6).max(1), &mut out);
+    out
+}
@@ -0,0 +1,161 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::coverage::{Coverage, Production, Shape};
+use stoncc::generate::{leaf, random, sized, Rng};
+use stoncc::parser::{LeafVal, Node};
+
+fn build(shape: Shape, children: Vec<Node>) -> Node {
+    debug_assert_eq!(shape.1, children.len());
+    Node::op(shape.0, children)
+}
+
+/// The smallest tree exercising `p`.
+fn targeted(rng: &mut Rng, p: Production) -> Node {
+    match p {
+        Production::Int => Node::leaf(LeafVal::Int(rng.below(10) as i32)),
+        Production::Bool => Node::leaf(LeafVal::Bool(rng.below(2) == 1)),
+        Production::Decimal => Node::leaf(LeafVal::Decimal(format!("{}.{}", rng.below(10), rng.below(100)))),
+        Production::Sym => Node::leaf(LeafVal::Sym("x".into())),
+        Production::Placeholder => Node::leaf(LeafVal::Placeholder(1 + rng.below(3))),
+        Production::Op(shape) => {
+            let children = (0..shape.1).map(|_| leaf(rng)).collect();
+            build(shape, children)
+        }
+        Production::Nest { parent, slot, child } => {
+            let children = (0..parent.1)
+                .map(|i| if i == slot { targeted(rng, Production::Op(child.clone())) } else { leaf(rng) })
+                .collect();
+            build(parent, children)
+        }
+    }
+}
+
+/// Generates `count` random expressions, then tops the corpus up with
+/// targeted expressions for every production the random ones missed.
+pub fn corpus(seed: u64, count: usize, depth: usize) -> Vec<Node> {
+    let mut rng = Rng::new(seed);
+    let mut cov = Coverage::default();
+    let mut out = Vec::new();
+
+    for _ in 0..count {
+        let node = random(&mut rng, depth);
+        cov.add(&node);
+        out.push(node);
+    }
+
+    for p in cov.missing() {
+        if cov.covers(&p) {
+            continue;
+        }
+        let node = targeted(&mut rng, p);
+        cov.add(&node);
+        out.push(node);
+    }
+
+    out
+}
+
+fn usage() -> ! {
+    eprintln!("usage: stoncc gen [--seed N] [--count N] [--depth N] [--out DIR] [--size BYTES]");
+    std::process::exit(2);
+}
+
+/// `stoncc --gen=N [--seed=S]`: prints one expression of about `N` bytes,
+/// as `stoncc gen --size N` does.
+pub fn gen_flag(size: &str, args: &[String]) {
+    let usage = || -> ! {
+        eprintln!("usage: stoncc --gen=BYTES [--seed=N]");
+        std::process::exit(2);
+    };
+    let bytes = size.parse().unwrap_or_else(|_| usage());
+    let seed = match args {
+        [] => 0,
+        [seed] => seed.strip_prefix("--seed=").and_then(|s| s.parse().ok()).unwrap_or_else(|| usage()),
+        _ => usage(),
+    };
+    println!("{}", sized(&mut Rng::new(seed), bytes));
+}
+
+/// `stoncc gen`: prints a coverage-complete corpus of expressions, one per
+/// line, or writes one `.stc` file per expression into `--out DIR`. With
+/// `--size`, it prints one big expression instead.
+pub fn main(args: &[String]) {
+    let (mut seed, mut count, mut depth, mut out, mut size) = (0, 20, 4, None, None);
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--seed" => seed = value().parse().unwrap_or_else(|_| usage()),
+            "--count" => count = value().parse().unwrap_or_else(|_| usage()),
+            "--depth" => depth = value().parse().unwrap_or_else(|_| usage()),
+            "--out" => out = Some(PathBuf::from(value())),
+            "--size" => size = Some(value().parse().unwrap_or_else(|_| usage())),
+            _ => usage(),
+        }
+    }
+
+    if let Some(bytes) = size {
+        println!("{}", sized(&mut Rng::new(seed), bytes));
+        return;
+    }
+
+    let exprs = corpus(seed, count, depth);
+
+    let Some(dir) = out else {
+        for e in &exprs {
+            println!("{}", e.to_infix());
+        }
+        return;
+    };
+
+    fs::create_dir_all(&dir).unwrap();
+    for (i, e) in exprs.iter().enumerate() {
+        fs::write(dir.join(format!("gen_{i:04}.stc")), e.to_infix() + "\n").unwrap();
+    }
+}
+
+#[test]
+fn tests() {
+    let exprs = corpus(1, 10, 4);
+
+    let mut cov = Coverage::default();
+    for e in &exprs {
+        cov.add(e);
+    }
+    assert!(cov.missing().is_empty());
+
+    // Infix source parses back to the tree it was written from.
+    for e in exprs.iter().chain(&corpus(2, 500, 6)) {
+        let src = e.to_infix();
+        assert_eq!(stoncc::expr(src.as_bytes()).as_ref(), Ok(e), "{src}");
+        let ast = stoncc::arena::expr(src.as_bytes()).unwrap();
+        assert_eq!(&ast.to_node(ast.root()), e, "{src}");
+    }
+
+    let big = sized(&mut Rng::new(4), 100_000);
+    assert!((90_000..=110_000).contains(&big.len()), "{}", big.len());
+    let ast = stoncc::arena::expr(big.as_bytes()).unwrap();
+    let mut meter = stoncc::limits::Meter::new(Default::default());
+    let v = stoncc::arena::eval_with(&ast, &stoncc::Env::new(), &Default::default(), &mut meter).unwrap();
+    assert_eq!(Ok(v), stoncc::eval(&stoncc::expr(big.as_bytes()).unwrap(), &stoncc::Env::new(), &mut meter));
+
+    // The parser is total: arbitrary bytes, and soups of the tokens it
+    // knows, give a program or an error but never a panic. Programs that
+    // parse come back the same from their infix source.
+    const TOKENS: [&str; 24] =
+        ["1", "2.5", "x", "f", "?1", "(", ")", "{", "}", ",", "=", "+", "-", "*", "/", "^", "!", "<", "==", "&&", "?", ":", "if", "\n"];
+    let mut rng = Rng::new(3);
+    for i in 0..2000 {
+        let src: Vec<u8> = if i % 2 == 0 {
+            (0..rng.below(40)).map(|_| rng.next() as u8).collect()
+        } else {
+            (0..rng.below(40)).flat_map(|_| [TOKENS[rng.below(TOKENS.len())], " "].concat().into_bytes()).collect()
+        };
+        let Ok(p) = stoncc::program(&src).0 else { continue };
+        let infix = p.to_infix();
+        let again = stoncc::program(infix.as_bytes()).0.unwrap_or_else(|e| panic!("{infix:?}: {e}"));
+        assert_eq!(again.to_string(), p.to_string(), "{:?}", String::from_utf8_lossy(&src));
+    }
+}
@@ -0,0 +1,113 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Whether `pattern` contains any glob metacharacters.
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Matches a single path component against a pattern where `*` matches any
+/// run of characters and `?` matches exactly one. On a mismatch only the
+/// latest `*` takes one more character, which keeps it linear in practice
+/// and at worst quadratic, however many stars there are.
+fn matches(pat: &[char], s: &[char]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    // The position after the latest `*`, and where in `s` it stopped.
+    let mut star = None;
+
+    while i < s.len() {
+        match pat.get(p) {
+            Some('*') => {
+                p += 1;
+                star = Some((p, i));
+            }
+            Some(&c) if c == '?' || c == s[i] => {
+                p += 1;
+                i += 1;
+            }
+            _ => match star {
+                Some((after, from)) => {
+                    (p, i) = (after, from + 1);
+                    star = Some((after, from + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pat[p..].iter().all(|&c| c == '*')
+}
+
+fn entries(dir: &Path) -> Vec<PathBuf> {
+    let mut v: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|rd| rd.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+        .unwrap_or_default();
+    v.sort();
+    v
+}
+
+fn walk(path: PathBuf, comps: &[&str], out: &mut Vec<PathBuf>) {
+    let Some((&comp, rest)) = comps.split_first() else {
+        out.push(path);
+        return;
+    };
+
+    if comp == "**" {
+        walk(path.clone(), rest, out);
+        for e in entries(&path).into_iter().filter(|e| e.is_dir()) {
+            walk(e, comps, out);
+        }
+    } else if is_glob(comp) {
+        let pat: Vec<char> = comp.chars().collect();
+        for e in entries(&path) {
+            let name: Vec<char> = e.file_name().unwrap().to_string_lossy().chars().collect();
+            // Like a shell, wildcards don't match hidden files.
+            if name.first() != Some(&'.') && matches(&pat, &name) {
+                walk(e, rest, out);
+            }
+        }
+    } else {
+        let next = path.join(comp);
+        if next.exists() {
+            walk(next, rest, out);
+        }
+    }
+}
+
+/// Expands a glob such as `exprs/**/*.stc` into the existing paths it
+/// matches, in sorted order. `**` matches any number of directories.
+pub fn expand(pattern: &str) -> Vec<PathBuf> {
+    let (root, pattern) = match pattern.strip_prefix('/') {
+        Some(p) => (PathBuf::from("/"), p),
+        None => (PathBuf::from("."), pattern),
+    };
+    let comps: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
+
+    let mut out = Vec::new();
+    walk(root, &comps, &mut out);
+    out.sort();
+    out.dedup();
+
+    out.into_iter()
+        .map(|p| p.strip_prefix("./").map(Path::to_path_buf).unwrap_or(p))
+        .collect()
+}
+
+#[test]
+fn tests() {
+    let m = |p: &str, s: &str| matches(&p.chars().collect::<Vec<_>>(), &s.chars().collect::<Vec<_>>());
+
+    assert!(m("*.stc", "a.stc"));
+    assert!(m("*.stc", ".stc"));
+    assert!(!m("*.stc", "a.stcx"));
+    assert!(m("a?c*", "abc"));
+    assert!(!m("a?c", "ac"));
+    assert!(m("*b*", "abc"));
+    assert!(m("**", ""));
+    assert!(!m("a*b?", "ab"));
+    assert!(m("*a*b", "aXbab"));
+
+    // Each star would try every split if it backtracked on its own.
+    let stars = format!("{}b", "a*".repeat(30));
+    assert!(!m(&stars, &"a".repeat(60)));
+    assert!(m(&stars, &format!("{}b", "a".repeat(60))));
+}
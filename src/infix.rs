@@ -0,0 +1,157 @@
+//! Trees written back as infix source, with only the parentheses the
+//! parser needs to group them the same way.
+
+use crate::parser::{LeafVal, Node, NodeVal, Program, Stmt};
+use crate::prelude::*;
+
+/// How tightly `node` holds together when written out: the precedence of
+/// its operator, or `i32::MAX` for operands and calls.
+pub(crate) fn binding(node: &Node) -> i32 {
+    match node {
+        // Written with a leading `-`, which the parser reads as negation.
+        Node::Leaf(LeafVal::Int(n), _) if *n < 0 => NodeVal::Sub.prefix_prec(),
+        Node::Leaf(LeafVal::Decimal(d), _) if d.starts_with('-') => NodeVal::Sub.prefix_prec(),
+        Node::Leaf(..) | Node::Error(_) => i32::MAX,
+        Node::Node { v, children, .. } => match v.fixity(children.len()) {
+            "call" => i32::MAX,
+            "prefix" => v.prefix_prec(),
+            "postfix" => v.postfix_prec().unwrap(),
+            _ => v.infix_prec(),
+        },
+    }
+}
+
+/// Whether `child`, as operand `i` of `parent`, needs parentheses, or the
+/// parser would group it differently.
+pub(crate) fn parenthesized(parent: &Node, i: usize, child: &Node) -> bool {
+    let Node::Node { v, children, .. } = parent else { unreachable!() };
+    let (outer, inner) = (binding(parent), binding(child));
+    match (v.fixity(children.len()), i) {
+        ("prefix" | "postfix", _) => inner < outer,
+        // Arguments, and operands between `?` and `:`, are delimited anyway.
+        ("call", _) | ("ternary", 1) => false,
+        // A comparison can't be the left operand of another either.
+        (_, 0) => inner < outer || inner == outer && (!v.is_lassoc() || v.is_comparison()),
+        _ => inner < outer || inner == outer && v.is_lassoc(),
+    }
+}
+
+/// `child`, operand `i` of `parent`, parenthesized if it needs to be.
+fn operand(parent: &Node, i: usize, child: &Node) -> String {
+    let text = child.to_infix();
+    if parenthesized(parent, i, child) { format!("({text})") } else { text }
+}
+
+/// Whether `s` ends in an `if` without `else`, which would take an `else`
+/// written after `s` as its own.
+fn open_if(s: &Stmt) -> bool {
+    match s {
+        Stmt::If { els: None, .. } => true,
+        Stmt::If { els: Some(s), .. } | Stmt::While { body: s, .. } => open_if(s),
+        _ => false,
+    }
+}
+
+fn stmts(stmts: &[Stmt], indent: &str, out: &mut String) {
+    for (i, s) in stmts.iter().enumerate() {
+        let text = s.indented(indent);
+        out.push_str(indent);
+        // On a line of its own, a leading `-` or `+` would continue the
+        // statement before.
+        match s {
+            Stmt::Expr(_) if i > 0 && text.starts_with(['-', '+']) => *out += &format!("({text})"),
+            _ => out.push_str(&text),
+        }
+        out.push('\n');
+    }
+}
+
+impl Node {
+    /// The tree as infix source, such as `1 + 2 * 3` for `(+ 1 (* 2 3))` or
+    /// `(a ^ b) ^ c` for `(^ (^ a b) c)`. Parsing it gives the same tree.
+    pub fn to_infix(&self) -> String {
+        let Node::Node { v, children, .. } = self else {
+            return self.to_string();
+        };
+        let parts: Vec<String> = children.iter().enumerate().map(|(i, c)| operand(self, i, c)).collect();
+        match (v, &parts[..]) {
+            (NodeVal::Call(name), _) => format!("{name}({})", parts.join(", ")),
+            (v, [a]) if v.postfix_prec().is_some() => format!("{a}{v}"),
+            (v, [a]) => format!("{v}{a}"),
+            (v, [a, b]) => format!("{a} {v} {b}"),
+            (_, [c, a, b]) => format!("{c} ? {a} : {b}"),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Stmt {
+    /// The statement as infix source. Blocks span several lines, with
+    /// their statements indented by two spaces.
+    pub fn to_infix(&self) -> String {
+        self.indented("")
+    }
+
+    /// `to_infix` for a statement starting at `indent`.
+    fn indented(&self, indent: &str) -> String {
+        match self {
+            Stmt::Expr(e) => e.to_infix(),
+            Stmt::Assign { name, value, .. } => format!("{name} = {}", value.to_infix()),
+            Stmt::Def { name, params, body, .. } => format!("{name}({}) = {}", params.join(", "), body.to_infix()),
+            Stmt::Block { stmts: inner, .. } if inner.is_empty() => "{}".to_string(),
+            Stmt::Block { stmts: inner, .. } => {
+                let mut out = "{\n".to_string();
+                stmts(inner, &format!("{indent}  "), &mut out);
+                out + indent + "}"
+            }
+            Stmt::If { cond, then, els, .. } => {
+                let then = match (then.as_ref(), els) {
+                    (then, Some(_)) if open_if(then) => Stmt::Block { stmts: vec![then.clone()], span: then.span() },
+                    (then, _) => then.clone(),
+                };
+                let mut out = format!("if ({}) {}", cond.to_infix(), then.indented(indent));
+                if let Some(els) = els {
+                    out += &format!(" else {}", els.indented(indent));
+                }
+                out
+            }
+            Stmt::While { cond, body, .. } => format!("while ({}) {}", cond.to_infix(), body.indented(indent)),
+        }
+    }
+}
+
+impl Program {
+    /// The program as infix source, one statement per line.
+    pub fn to_infix(&self) -> String {
+        let mut out = String::new();
+        stmts(&self.stmts, "", &mut out);
+        out
+    }
+}
+
+#[test]
+fn tests() {
+    use crate::parser::{expr, program};
+
+    let infix = |s: &str| expr(s.as_bytes()).unwrap().to_infix();
+    assert_eq!(infix("1 + (2 * 3)"), "1 + 2 * 3");
+    assert_eq!(infix("(a ^ b) ^ (c ^ d)"), "(a ^ b) ^ c ^ d");
+    assert_eq!(infix("(a - b) - (c - d)"), "a - b - (c - d)");
+    assert_eq!(infix("max((a + b), -c) * (f())"), "max(a + b, -c) * f()");
+    assert_eq!(infix("(a ? b : c) ? (d || e) : (f ? g : h)"), "(a ? b : c) ? d || e : f ? g : h");
+    assert_eq!(infix("(-a)! + -(a!) + (-a) ^ 2 + -(a ^ 2) + 2 ^ -1"), "(-a)! + -a! + (-a) ^ 2 + -a ^ 2 + 2 ^ (-1)");
+    assert_eq!(infix("(a < b) == (c < d)"), "(a < b) == (c < d)");
+    let negative = Node::op(NodeVal::Exp, vec![Node::leaf(LeafVal::Int(-2)), Node::leaf(LeafVal::Int(2))]);
+    assert_eq!(negative.to_infix(), "(-2) ^ 2");
+
+    let source = "x = 1\n(-x)\n{\n  y = 2\n  {}\n  while (y) {\n    y = y - 1\n  }\n}\nf(a, b) = a * b\n\
+                  if (x) {\n  if (y) 1\n} else 2\nif (x) y = 1 else if (y) 2 else 3\n";
+    let mut p = program(source.as_bytes()).0.unwrap();
+    assert_eq!(p.to_infix(), source);
+
+    // Without its braces, the inner `if` would take the `else`.
+    let Stmt::If { then, .. } = &mut p.stmts[4] else { panic!() };
+    let Stmt::Block { stmts, .. } = then.as_mut() else { panic!() };
+    **then = stmts.remove(0);
+    assert_eq!(p.to_infix(), source);
+}
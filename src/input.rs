@@ -0,0 +1,121 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::str::{self, FromStr};
+
+use crate::{fail, transcript, Failure};
+
+/// Character encoding of an input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Latin1,
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Encoding::Utf8 => "utf8",
+            Encoding::Latin1 => "latin1",
+        })
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "utf8" | "utf-8" => Ok(Encoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            _ => Err(format!("unknown encoding '{s}' (expected utf8 or latin1)")),
+        }
+    }
+}
+
+/// Converts raw file contents to UTF-8. A UTF-8 byte-order mark is left in
+/// place (the lexer skips it) so that offsets still match the file.
+pub fn decode(bytes: Vec<u8>, encoding: Encoding) -> Result<Vec<u8>, String> {
+    match encoding {
+        Encoding::Utf8 => match str::from_utf8(&bytes) {
+            Ok(_) => Ok(bytes),
+            Err(e) => Err(format!(
+                "input is not valid UTF-8: invalid byte at offset {} (try --encoding=latin1)",
+                e.valid_up_to()
+            )),
+        },
+        Encoding::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect::<String>().into_bytes()),
+    }
+}
+
+/// Decompressor for a file extension, as `(extension, command, args)`.
+const DECOMPRESSORS: &[(&str, &str, &[&str])] = &[
+    ("gz", "gzip", &["-dc"]),
+    ("zst", "zstd", &["-dcq"]),
+];
+
+/// Decompresses `path` by streaming it through the system's `gzip`/`zstd`,
+/// which keeps the crate free of compression dependencies.
+#[cfg(feature = "compressed")]
+fn decompress(path: &str, cmd: &str, args: &[&str]) -> Vec<u8> {
+    use std::process::{Command, Stdio};
+
+    let file = File::open(path).unwrap_or_else(|e| fail(Failure::Run, format!("cannot open {path}: {e}")));
+    let output = Command::new(cmd)
+        .args(args)
+        .stdin(file)
+        .stderr(Stdio::piped())
+        .output()
+        .unwrap_or_else(|e| fail(Failure::Run, format!("cannot run {cmd} to decompress {path}: {e}")));
+
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        fail(Failure::Run, format!("{path}: {cmd} failed: {}", err.trim()));
+    }
+
+    output.stdout
+}
+
+#[cfg(not(feature = "compressed"))]
+fn decompress(path: &str, _: &str, _: &[&str]) -> Vec<u8> {
+    fail(Failure::Run, format!("{path}: compressed input requires building with the `compressed` feature"));
+}
+
+/// Reads and decodes `path`, or standard input for `-`. When a session is
+/// being recorded or replayed, the input also goes through the transcript.
+pub fn read(path: &str, encoding: Encoding) -> Vec<u8> {
+    transcript::input(path, || read_now(path, encoding))
+}
+
+fn read_now(path: &str, encoding: Encoding) -> Vec<u8> {
+    if path == "-" {
+        let mut s = Vec::new();
+        std::io::stdin().read_to_end(&mut s).unwrap_or_else(|e| fail(Failure::Run, format!("cannot read standard input: {e}")));
+        return decode(s, encoding).unwrap_or_else(|e| fail(Failure::Run, format!("<stdin>: {e}")));
+    }
+
+    let ext = path.rsplit_once('.').map(|(_, e)| e);
+    if let Some((_, cmd, args)) = DECOMPRESSORS.iter().find(|d| Some(d.0) == ext) {
+        let s = decompress(path, cmd, args);
+        return decode(s, encoding).unwrap_or_else(|e| fail(Failure::Run, format!("{path}: {e}")));
+    }
+
+    let mut file = File::open(path).unwrap_or_else(|e| fail(Failure::Run, format!("cannot open {path}: {e}")));
+    let metadata = file.metadata().unwrap();
+    let mut s = Vec::<u8>::with_capacity(metadata.len() as usize);
+
+    file.read_to_end(&mut s).unwrap_or_else(|e| fail(Failure::Run, format!("cannot read {path}: {e}")));
+
+    decode(s, encoding).unwrap_or_else(|e| fail(Failure::Run, format!("{path}: {e}")))
+}
+
+#[test]
+fn tests() {
+    assert_eq!(decode(b"1+2".to_vec(), Encoding::Utf8), Ok(b"1+2".to_vec()));
+
+    let err = decode(b"1 + \xff".to_vec(), Encoding::Utf8).unwrap_err();
+    assert!(err.contains("offset 4"), "{err}");
+
+    assert_eq!(decode(b"\xb5".to_vec(), Encoding::Latin1), Ok("\u{b5}".as_bytes().to_vec()));
+}
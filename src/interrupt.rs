@@ -0,0 +1,46 @@
+//! Ctrl-C: the first one cancels the evaluation running, through the
+//! `CancelToken` it was given, so that its error can be reported (or, in
+//! the REPL, the session carry on); a second one, or one that comes before
+//! anything is being evaluated, ends the process as Ctrl-C usually does.
+
+use std::sync::OnceLock;
+
+use stoncc::limits::CancelToken;
+
+static TOKEN: OnceLock<CancelToken> = OnceLock::new();
+
+#[cfg(unix)]
+mod sys {
+    pub const SIGINT: i32 = 2;
+
+    extern "C" {
+        pub fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+        pub fn _exit(status: i32) -> !;
+    }
+}
+
+/// Only does what is safe in a signal handler: loads and stores atomics.
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: i32) {
+    match TOKEN.get() {
+        Some(token) if !token.is_cancelled() => token.cancel(),
+        // SAFETY: _exit is async-signal-safe.
+        _ => unsafe { sys::_exit(130) },
+    }
+}
+
+/// The token Ctrl-C cancels, installing the handler the first time.
+/// Elsewhere than on Unix, Ctrl-C ends the process and the token is
+/// never cancelled.
+pub fn token() -> CancelToken {
+    TOKEN
+        .get_or_init(|| {
+            // SAFETY: the handler only touches atomics, or exits.
+            #[cfg(unix)]
+            unsafe {
+                sys::signal(sys::SIGINT, on_interrupt);
+            }
+            CancelToken::new()
+        })
+        .clone()
+}
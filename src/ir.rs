@@ -0,0 +1,252 @@
+//! Three-address code. `lower` flattens a tree into a list of instructions,
+//! each computing one virtual register from registers computed before it,
+//! so later passes and backends can work through a program without
+//! recursing. `eval` interprets the result, which `--check-ir` compares
+//! against the tree-walking evaluator.
+
+use std::fmt;
+
+use crate::builtins::Builtins;
+use crate::eval::{Env, EvalError, MAX_CALL_DEPTH};
+use crate::lexer::Span;
+use crate::limits::Meter;
+use crate::parser::{LeafVal, Node, NodeVal, Value};
+use crate::symbol::SymId;
+
+/// A virtual register. Each is assigned by exactly one instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reg(pub usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    /// `%d = 3`
+    Const { dst: Reg, value: Value },
+    /// `%d = x`, a parameter or variable.
+    Load { dst: Reg, name: SymId, span: Span },
+    /// `%d = ?1`, a placeholder that fails when reached.
+    Hole { dst: Reg, n: usize, span: Span },
+    /// `%d = %a + %b`, or `%d = f(%a, %b)` for calls.
+    Op { dst: Reg, op: NodeVal, args: Vec<Reg>, span: Span },
+    /// `%d = if %c { ... } else { ... }`, running only one of the two.
+    /// This is what `&&`, `||` and `?:` lower to.
+    Branch { dst: Reg, cond: Reg, then: Code, els: Code },
+}
+
+/// The lowered form of one expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Code {
+    pub insts: Vec<Inst>,
+    /// The register holding the expression's value.
+    pub result: Reg,
+}
+
+struct Lowering {
+    regs: usize,
+}
+
+impl Lowering {
+    fn reg(&mut self) -> Reg {
+        self.regs += 1;
+        Reg(self.regs - 1)
+    }
+
+    fn code(&mut self, f: impl FnOnce(&mut Self, &mut Vec<Inst>) -> Reg) -> Code {
+        let mut insts = Vec::new();
+        let result = f(self, &mut insts);
+        Code { insts, result }
+    }
+
+    fn int(&mut self, insts: &mut Vec<Inst>, v: i32) -> Reg {
+        let dst = self.reg();
+        insts.push(Inst::Const { dst, value: Value::Int(v) });
+        dst
+    }
+
+    /// 1 if `node` is nonzero, otherwise 0.
+    fn truth(&mut self, insts: &mut Vec<Inst>, node: &Node) -> Reg {
+        let v = self.emit(insts, node);
+        let zero = self.int(insts, 0);
+        let dst = self.reg();
+        insts.push(Inst::Op { dst, op: NodeVal::Ne, args: vec![v, zero], span: node.span() });
+        dst
+    }
+
+    fn emit(&mut self, insts: &mut Vec<Inst>, node: &Node) -> Reg {
+        let inst = match node {
+            Node::Leaf(LeafVal::Int(v), _) => Inst::Const { dst: self.reg(), value: Value::Int(*v) },
+            Node::Leaf(LeafVal::Bool(b), _) => Inst::Const { dst: self.reg(), value: Value::Int(*b as i32) },
+            Node::Leaf(LeafVal::Decimal(s), _) => Inst::Const { dst: self.reg(), value: Value::Float(s.parse().unwrap()) },
+            Node::Leaf(LeafVal::Sym(s), span) => Inst::Load { dst: self.reg(), name: *s, span: *span },
+            Node::Leaf(LeafVal::Placeholder(n), span) => Inst::Hole { dst: self.reg(), n: *n, span: *span },
+            Node::Error(_) => panic!("cannot lower a tree with syntax errors"),
+            Node::Node { v, children, .. } if v.short_circuit(true).is_some() => {
+                let cond = self.emit(insts, &children[0]);
+                let (then, els) = match v {
+                    NodeVal::And => (self.code(|l, i| l.truth(i, &children[1])), self.code(|l, i| l.int(i, 0))),
+                    NodeVal::Or => (self.code(|l, i| l.int(i, 1)), self.code(|l, i| l.truth(i, &children[1]))),
+                    _ => (self.code(|l, i| l.emit(i, &children[1])), self.code(|l, i| l.emit(i, &children[2]))),
+                };
+                Inst::Branch { dst: self.reg(), cond, then, els }
+            }
+            Node::Node { v, children, span } => {
+                let args = children.iter().map(|c| self.emit(insts, c)).collect();
+                Inst::Op { dst: self.reg(), op: v.clone(), args, span: *span }
+            }
+        };
+        let dst = inst.dst();
+        insts.push(inst);
+        dst
+    }
+}
+
+/// Flattens `node`, operands first, left to right. Only the operands of
+/// `&&`, `||` and `?:` that may be skipped end up in nested code.
+pub fn lower(node: &Node) -> Code {
+    Lowering { regs: 0 }.code(|l, insts| l.emit(insts, node))
+}
+
+impl Inst {
+    pub fn dst(&self) -> Reg {
+        match self {
+            Inst::Const { dst, .. }
+            | Inst::Load { dst, .. }
+            | Inst::Hole { dst, .. }
+            | Inst::Op { dst, .. }
+            | Inst::Branch { dst, .. } => *dst,
+        }
+    }
+}
+
+/// Interprets `code` the way `eval_with` walks the tree it came from.
+pub fn eval(code: &Code, env: &Env, builtins: &Builtins, meter: &mut Meter) -> Result<Value, EvalError> {
+    run(code, &mut Vec::new(), env, None, builtins, meter, 0)
+}
+
+fn run(
+    code: &Code,
+    regs: &mut Vec<Value>,
+    globals: &Env,
+    locals: Option<&Env>,
+    builtins: &Builtins,
+    meter: &mut Meter,
+    depth: usize,
+) -> Result<Value, EvalError> {
+    for inst in &code.insts {
+        let v = match inst {
+            Inst::Const { value, .. } => *value,
+            Inst::Load { name, span, .. } => match locals.and_then(|l| l.get(name.as_str())).or_else(|| globals.get(name.as_str())) {
+                Some(v) => *v,
+                None => return Err(EvalError::Unbound(name.to_string(), *span)),
+            },
+            Inst::Hole { n, span, .. } => return Err(EvalError::Unfilled(*n, *span)),
+            Inst::Branch { cond, then, els, .. } => {
+                meter.op()?;
+                let code = if regs[cond.0].is_true() { then } else { els };
+                run(code, regs, globals, locals, builtins, meter, depth)?
+            }
+            Inst::Op { op, args, span, .. } => {
+                let args: Vec<Value> = args.iter().map(|r| regs[r.0]).collect();
+                meter.op()?;
+                match op {
+                    NodeVal::Call(name) => match builtins.defined(name) {
+                        Some(f) => {
+                            if depth >= MAX_CALL_DEPTH {
+                                return Err(EvalError::Recursion(name.clone(), *span));
+                            }
+                            let locals = f.bind(&args).map_err(|msg| EvalError::Call(name.clone(), msg, *span))?;
+                            run(&lower(&f.body), &mut Vec::new(), globals, Some(&locals), builtins, meter, depth + 1)?
+                        }
+                        None => match builtins.call(name, &args) {
+                            Some(v) => {
+                                let v = v.map_err(|msg| EvalError::Call(name.clone(), msg, *span))?;
+                                builtins.narrow(name, v, meter.arith()).map_err(|e| EvalError::Arith(e, *span))?
+                            }
+                            None => return Err(EvalError::UnknownFunction(name.clone(), *span)),
+                        },
+                    },
+                    op => op.apply(&args, meter.arith()).map_err(|e| EvalError::Arith(e, *span))?,
+                }
+            }
+        };
+        meter.value(v.as_i128())?;
+        let dst = inst.dst().0;
+        if regs.len() <= dst {
+            regs.resize(dst + 1, Value::Int(0));
+        }
+        regs[dst] = v;
+    }
+
+    Ok(regs[code.result.0])
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inst::Const { dst, value } => write!(f, "{dst} = {value}"),
+            Inst::Load { dst, name, .. } => write!(f, "{dst} = {name}"),
+            Inst::Hole { dst, n, .. } => write!(f, "{dst} = ?{n}"),
+            Inst::Branch { dst, cond, then, els } => {
+                let block = |c: &Code| c.to_string().replace('\n', "\n  ");
+                write!(f, "{dst} = if {cond} {{\n  {}\n  }} else {{\n  {}\n  }}", block(then), block(els))
+            }
+            Inst::Op { dst, op, args, .. } => match (op, &args[..]) {
+                (NodeVal::Call(_), args) => {
+                    let args: Vec<String> = args.iter().map(Reg::to_string).collect();
+                    write!(f, "{dst} = {op}({})", args.join(", "))
+                }
+                (op, [a]) if op.postfix_prec().is_some() => write!(f, "{dst} = {a}{op}"),
+                (op, [a]) => write!(f, "{dst} = {op}{a}"),
+                (op, [a, b]) => write!(f, "{dst} = {a} {op} {b}"),
+                (op, args) => write!(f, "{dst} = {op} {args:?}"),
+            },
+        }
+    }
+}
+
+/// One instruction per line, then the result register.
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for inst in &self.insts {
+            writeln!(f, "  {inst}")?;
+        }
+        write!(f, "  ret {}", self.result)
+    }
+}
+
+#[test]
+fn tests() {
+    let code = lower(&crate::parser::expr(b"-x + max(2, 3!) * 1.5").unwrap());
+    assert_eq!(
+        code.to_string(),
+        "  %0 = x\n  %1 = -%0\n  %2 = 2\n  %3 = 3\n  %4 = %3!\n  %5 = max(%2, %4)\n  %6 = 1.5\n  %7 = %5 * %6\n  %8 = %1 + %7\n  ret %8"
+    );
+
+    let mut builtins = Builtins::default();
+    let body = crate::parser::expr(b"n * 2 + x").unwrap();
+    builtins.define("f", crate::Function { params: vec!["n".into()], body });
+    let env: Env = [("x".to_string(), Value::Int(1))].into();
+    let run = |s: &str| {
+        let mut meter = Meter::new(Default::default());
+        eval(&lower(&crate::parser::expr(s.as_bytes()).unwrap()), &env, &builtins, &mut meter)
+    };
+
+    assert_eq!(run("f(f(3)) - x"), Ok(Value::Int(14)));
+    assert_eq!(run("x / 2.0"), Ok(Value::Float(0.5)));
+    assert!(matches!(run("y + ?1"), Err(EvalError::Unbound(..))));
+    assert!(matches!(run("x + ?1"), Err(EvalError::Unfilled(1, _))));
+    assert!(matches!(run("g(1)"), Err(EvalError::UnknownFunction(..))));
+    assert_eq!(run("x > 0 ? f(2) : g(1)"), Ok(Value::Int(5)));
+    assert_eq!(run("0 && g(1) || 2.5"), Ok(Value::Int(1)));
+
+    let code = lower(&crate::parser::expr(b"a && b").unwrap());
+    assert_eq!(
+        code.to_string(),
+        "  %0 = a\n  %5 = if %0 {\n    %1 = b\n    %2 = 0\n    %3 = %1 != %2\n    ret %3\n  } else {\n    %4 = 0\n    ret %4\n  }\n  ret %5"
+    );
+}
@@ -0,0 +1,279 @@
+use core::fmt::Write;
+
+use crate::prelude::*;
+
+/// Quotes `s` as a JSON string literal.
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Renders a list of already-encoded JSON values.
+pub fn array<I: IntoIterator<Item = String>>(items: I) -> String {
+    format!("[{}]", items.into_iter().collect::<Vec<_>>().join(", "))
+}
+
+/// A value in a flat JSON object. Numbers keep their text so the caller
+/// decides between integer and float.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scalar {
+    Number(String),
+    String(String),
+}
+
+/// Any JSON value. Numbers keep their text, as in `Scalar`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    /// Members in the order written.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// The member `key` of an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+struct Reader<'a> {
+    s: &'a [u8],
+    i: usize,
+}
+
+impl Reader<'_> {
+    fn ws(&mut self) {
+        while self.s.get(self.i).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.i += 1;
+        }
+    }
+
+    fn error<T>(&self, expected: &str) -> Result<T, String> {
+        match self.s.get(self.i) {
+            Some(&c) => Err(format!("expected {expected} at byte {}, found {:?}", self.i, c as char)),
+            None => Err(format!("expected {expected}, found end of input")),
+        }
+    }
+
+    fn eat(&mut self, c: u8, expected: &str) -> Result<(), String> {
+        self.ws();
+        if self.s.get(self.i) != Some(&c) {
+            return self.error(expected);
+        }
+        self.i += 1;
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.eat(b'"', "a string")?;
+        let mut out = Vec::new();
+        loop {
+            let Some(&c) = self.s.get(self.i) else { return self.error("'\"'") };
+            self.i += 1;
+            match c {
+                b'"' => return String::from_utf8(out).map_err(|_| "string is not valid UTF-8".to_string()),
+                b'\\' => {
+                    let Some(&e) = self.s.get(self.i) else { return self.error("an escape") };
+                    self.i += 1;
+                    let c = match e {
+                        b'"' | b'\\' | b'/' => e as char,
+                        b'n' => '\n',
+                        b't' => '\t',
+                        b'r' => '\r',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'u' => {
+                            let Some(mut u) = self.hex() else { return self.error("four hex digits") };
+                            // A character outside the BMP is a surrogate pair.
+                            if (0xd800..0xdc00).contains(&u) && self.s[self.i..].starts_with(b"\\u") {
+                                self.i += 2;
+                                match self.hex() {
+                                    Some(low @ 0xdc00..0xe000) => u = 0x10000 + ((u - 0xd800) << 10) + (low - 0xdc00),
+                                    _ => return self.error("a low surrogate"),
+                                }
+                            }
+                            char::from_u32(u).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        _ => {
+                            self.i -= 1;
+                            return self.error("an escape");
+                        }
+                    };
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    /// Four hex digits, as after `\u`.
+    fn hex(&mut self) -> Option<u32> {
+        let hex = self.s.get(self.i..self.i + 4).and_then(|h| core::str::from_utf8(h).ok())?;
+        let u = u32::from_str_radix(hex, 16).ok()?;
+        self.i += 4;
+        Some(u)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.ws();
+        for (word, v) in [("true", Json::Bool(true)), ("false", Json::Bool(false)), ("null", Json::Null)] {
+            if self.s[self.i..].starts_with(word.as_bytes()) {
+                self.i += word.len();
+                return Ok(v);
+            }
+        }
+        match self.s.get(self.i) {
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b'[') => {
+                self.i += 1;
+                Ok(Json::Array(self.list(b']', Self::value)?))
+            }
+            Some(b'{') => {
+                self.i += 1;
+                let members = self.list(b'}', |r| {
+                    r.ws();
+                    let key = r.string()?;
+                    r.eat(b':', "':'")?;
+                    Ok((key, r.value()?))
+                })?;
+                Ok(Json::Object(members))
+            }
+            _ => Ok(Json::Number(self.number()?)),
+        }
+    }
+
+    /// Comma-separated items up to `close`, just after the opening bracket.
+    fn list<T>(&mut self, close: u8, mut item: impl FnMut(&mut Self) -> Result<T, String>) -> Result<Vec<T>, String> {
+        let mut out = Vec::new();
+        self.ws();
+        if self.s.get(self.i) == Some(&close) {
+            self.i += 1;
+            return Ok(out);
+        }
+        loop {
+            out.push(item(self)?);
+            self.ws();
+            match self.s.get(self.i) {
+                Some(b',') => self.i += 1,
+                Some(&c) if c == close => {
+                    self.i += 1;
+                    return Ok(out);
+                }
+                _ => return self.error(&format!("',' or '{}'", close as char)),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<String, String> {
+        let start = self.i;
+        while self.s.get(self.i).is_some_and(|c| c.is_ascii_digit() || b"+-.eE".contains(c)) {
+            self.i += 1;
+        }
+        if start == self.i {
+            return self.error("a number or string");
+        }
+        Ok(String::from_utf8_lossy(&self.s[start..self.i]).into_owned())
+    }
+}
+
+/// Parses a JSON document.
+pub fn parse(s: &str) -> Result<Json, String> {
+    let mut r = Reader { s: s.as_bytes(), i: 0 };
+    let v = r.value()?;
+    r.ws();
+    if r.i < r.s.len() {
+        return r.error("end of input");
+    }
+    Ok(v)
+}
+
+/// Parses a JSON object whose values are all numbers or strings.
+pub fn parse_object(s: &str) -> Result<Vec<(String, Scalar)>, String> {
+    let mut r = Reader { s: s.as_bytes(), i: 0 };
+    let mut out = Vec::new();
+
+    r.eat(b'{', "'{'")?;
+    r.ws();
+    if r.s.get(r.i) == Some(&b'}') {
+        r.i += 1;
+    } else {
+        loop {
+            r.ws();
+            let key = r.string()?;
+            r.eat(b':', "':'")?;
+            r.ws();
+            let value = match r.s.get(r.i) {
+                Some(b'"') => Scalar::String(r.string()?),
+                _ => Scalar::Number(r.number()?),
+            };
+            out.push((key, value));
+
+            r.ws();
+            match r.s.get(r.i) {
+                Some(b',') => r.i += 1,
+                Some(b'}') => {
+                    r.i += 1;
+                    break;
+                }
+                _ => return r.error("',' or '}'"),
+            }
+        }
+    }
+
+    r.ws();
+    if r.i < r.s.len() {
+        return r.error("end of input");
+    }
+    Ok(out)
+}
+
+#[test]
+fn tests() {
+    assert_eq!(string("a\"b\n"), "\"a\\\"b\\n\"");
+
+    let obj = parse_object(r#" {"n": 100, "rate": 1.5e-2, "s": "7", "esc": "\u00b5\"\\"} "#).unwrap();
+    assert_eq!(obj, [
+        ("n".into(), Scalar::Number("100".into())),
+        ("rate".into(), Scalar::Number("1.5e-2".into())),
+        ("s".into(), Scalar::String("7".into())),
+        ("esc".into(), Scalar::String("\u{b5}\"\\".into())),
+    ]);
+    assert_eq!(parse_object("{}"), Ok(vec![]));
+
+    assert_eq!(parse_object(r#"{"a": [1]}"#), Err("expected a number or string at byte 6, found '['".into()));
+    assert_eq!(parse_object(r#"{"a": 1"#), Err("expected ',' or '}', found end of input".into()));
+    assert_eq!(parse_object(r#"{"a": 1} x"#), Err("expected end of input at byte 9, found 'x'".into()));
+    assert_eq!(parse_object("[]"), Err("expected '{' at byte 0, found '['".into()));
+
+    let v = parse(r#"{"a": [1, {"b": "c"}], "d": []}"#).unwrap();
+    assert_eq!(v.get("a"), Some(&Json::Array(vec![
+        Json::Number("1".into()),
+        Json::Object(vec![("b".into(), Json::String("c".into()))]),
+    ])));
+    assert_eq!(v.get("d"), Some(&Json::Array(vec![])));
+    assert_eq!(parse("[1 2]"), Err("expected ',' or ']' at byte 3, found '2'".into()));
+    assert_eq!(parse(r#"[true, false, null]"#), Ok(Json::Array(vec![Json::Bool(true), Json::Bool(false), Json::Null])));
+    assert_eq!(parse(r#""\ud83d\ude00\u00e9""#), Ok(Json::String("\u{1f600}\u{e9}".into())));
+    assert!(parse(r#""\ud83dx""#).is_ok());
+    assert!(parse(r#""\ud83d\u0041""#).is_err());
+}
@@ -1,29 +1,266 @@
-use std::str;
+use alloc::collections::VecDeque;
+use core::fmt;
+use core::ops::Range;
 
-#[derive(Debug, PartialEq, Eq)]
+use crate::operators::{Literal, MAX_LITERAL};
+use crate::prelude::*;
+use crate::symbol::SymId;
+
+/// Byte range `start..end` of a piece of the input. Trees built by hand
+/// rather than parsed have the empty span `0..0`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A token produced by `Lexer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
     Int(i32),
-    Sym(String),
+    /// A literal with a fractional part or an exponent, kept as written
+    /// (`0.10`, `1e-3`) so that each arithmetic mode can convert it exactly.
+    /// Only digit separators are dropped.
+    Decimal(String),
+    Sym(SymId),
+    /// `true` or `false`.
+    Bool(bool),
+    /// `?N`, a hole in an expression template.
+    Placeholder(usize),
+    /// A literal that one added with `OperatorTable::add_literal`
+    /// recognized: the function it calls, and the arguments.
+    Literal(String, Vec<i32>),
     Plus,
     Minus,
     Star,
+    PlusWrap,
+    StarWrap,
+    PlusSat,
+    StarSat,
     Slash,
     LParen,
     RParen,
     Caret,
     Fac,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `==`, as opposed to `=`.
+    Eq,
+    /// `!=`, as opposed to `!`.
+    Ne,
+    AndAnd,
+    OrOr,
+    /// `?` not followed by a digit, which would make it a placeholder.
+    Question,
+    Colon,
+    /// `=`, binding a variable.
+    Assign,
+    /// `;`, ending a statement.
+    Semi,
+    /// `,`, separating function arguments.
+    Comma,
+    /// `{` and `}`, around a block of statements.
+    LBrace,
+    RBrace,
+    /// Keywords, which can't be used as names.
+    If,
+    Else,
+    While,
     // LBracket,
     // RBracket,
-    // LBrace,
-    // RBrace,
     // Dot,
-    // Percent,
+    /// `%` on its own: the remainder, or a percentage with the calculator
+    /// extensions.
+    Percent,
+    /// `<<` and `>>`, as opposed to comparisons.
+    Shl,
+    Shr,
+    /// `&`, `|` and `~` on their own: bitwise operators.
+    Amp,
+    Pipe,
+    Tilde,
+    /// An operator added to the lexer with `with_operators`.
+    Op(String),
+    Unknown(u8, Span),
     Eof,
 }
 
+/// Words that lex as keywords rather than as `Token::Sym`.
+pub const KEYWORDS: [&str; 5] = ["if", "else", "while", "true", "false"];
+
+/// A token as it was lexed: where it was, whether a line break came
+/// before it, and how it was written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lexed {
+    pub token: Token,
+    pub span: Span,
+    pub newline: bool,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LexErrorKind {
+    UnexpectedChar(char),
+    /// An integer literal too large for an `i32`.
+    IntegerOverflow(String),
+    /// A placeholder number too large for a `usize`.
+    PlaceholderOverflow(String),
+    /// The input could not be read past this point.
+    Io(String),
+    /// A `/*` with no `*/` after it.
+    UnterminatedComment,
+    /// Bytes that are not UTF-8.
+    InvalidUtf8,
+}
+
+/// Input the lexer could not turn into a token, and where it was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+impl LexError {
+    fn at(kind: LexErrorKind, start: usize) -> Self {
+        let width = match &kind {
+            LexErrorKind::UnexpectedChar(c) => c.len_utf8(),
+            LexErrorKind::IntegerOverflow(s) | LexErrorKind::PlaceholderOverflow(s) => s.len(),
+            LexErrorKind::Io(_) => 0,
+            LexErrorKind::UnterminatedComment => 2,
+            LexErrorKind::InvalidUtf8 => 1,
+        };
+        Self { kind, span: Span { start, end: start + width } }
+    }
+}
+
+impl LexErrorKind {
+    /// A stable name for the kind of error, for `--expect-error`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LexErrorKind::UnexpectedChar(_) => "unexpected-char",
+            LexErrorKind::IntegerOverflow(_) | LexErrorKind::PlaceholderOverflow(_) => "integer-overflow",
+            LexErrorKind::Io(_) => "read-error",
+            LexErrorKind::UnterminatedComment => "unterminated-comment",
+            LexErrorKind::InvalidUtf8 => "invalid-utf8",
+        }
+    }
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexErrorKind::UnexpectedChar(c) => write!(f, "unexpected character {c:?}"),
+            LexErrorKind::IntegerOverflow(s) => write!(f, "integer literal {s} is out of range for i32"),
+            LexErrorKind::PlaceholderOverflow(s) => write!(f, "placeholder ?{s} is out of range"),
+            LexErrorKind::Io(e) => write!(f, "cannot read input: {e}"),
+            LexErrorKind::UnterminatedComment => write!(f, "comment is not closed with */"),
+            LexErrorKind::InvalidUtf8 => write!(f, "input is not valid UTF-8"),
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "syntax error at {}: {}", self.span.start, self.kind)
+    }
+}
+
+impl core::error::Error for LexError {}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Int(v) => write!(f, "integer {v}"),
+            Token::Decimal(v) => write!(f, "number {v}"),
+            Token::Sym(v) => write!(f, "symbol {v}"),
+            Token::Bool(b) => write!(f, "'{b}'"),
+            Token::Placeholder(n) => write!(f, "placeholder ?{n}"),
+            Token::Literal(name, _) => write!(f, "{name} literal"),
+            Token::Plus => write!(f, "'+'"),
+            Token::Minus => write!(f, "'-'"),
+            Token::Star => write!(f, "'*'"),
+            Token::PlusWrap => write!(f, "'+%'"),
+            Token::StarWrap => write!(f, "'*%'"),
+            Token::PlusSat => write!(f, "'+|'"),
+            Token::StarSat => write!(f, "'*|'"),
+            Token::Slash => write!(f, "'/'"),
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+            Token::Caret => write!(f, "'^'"),
+            Token::Fac => write!(f, "'!'"),
+            Token::Lt => write!(f, "'<'"),
+            Token::Le => write!(f, "'<='"),
+            Token::Gt => write!(f, "'>'"),
+            Token::Ge => write!(f, "'>='"),
+            Token::Eq => write!(f, "'=='"),
+            Token::Ne => write!(f, "'!='"),
+            Token::AndAnd => write!(f, "'&&'"),
+            Token::OrOr => write!(f, "'||'"),
+            Token::Question => write!(f, "'?'"),
+            Token::Colon => write!(f, "':'"),
+            Token::Assign => write!(f, "'='"),
+            Token::Semi => write!(f, "';'"),
+            Token::Comma => write!(f, "','"),
+            Token::LBrace => write!(f, "'{{'"),
+            Token::RBrace => write!(f, "'}}'"),
+            Token::If => write!(f, "'if'"),
+            Token::Else => write!(f, "'else'"),
+            Token::While => write!(f, "'while'"),
+            Token::Percent => write!(f, "'%'"),
+            Token::Shl => write!(f, "'<<'"),
+            Token::Shr => write!(f, "'>>'"),
+            Token::Amp => write!(f, "'&'"),
+            Token::Pipe => write!(f, "'|'"),
+            Token::Tilde => write!(f, "'~'"),
+            Token::Op(s) => write!(f, "'{s}'"),
+            Token::Unknown(c, _) => write!(f, "byte {c:#04x}"),
+            Token::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+
 impl Token {
-    fn from_op(s: &[u8]) -> Self {
-        match s[0] {
+    /// The token a Unicode math symbol stands for. `√` is the function
+    /// `sqrt`, so `√(2)` calls it.
+    fn from_math(c: char) -> Option<Self> {
+        Some(match c {
+            '×' | '·' | '⋅' | '∙' => Token::Star,
+            '÷' | '∕' => Token::Slash,
+            '−' => Token::Minus,
+            '≤' => Token::Le,
+            '≥' => Token::Ge,
+            '≠' => Token::Ne,
+            '√' => Token::Sym("sqrt".into()),
+            _ => return None,
+        })
+    }
+
+    fn from_op(c: u8, next: Option<u8>) -> Result<(Self, usize), LexErrorKind> {
+        let t = match (c, next) {
+            (b'+', Some(b'%')) => Token::PlusWrap,
+            (b'*', Some(b'%')) => Token::StarWrap,
+            (b'+', Some(b'|')) => Token::PlusSat,
+            (b'*', Some(b'|')) => Token::StarSat,
+            (b'<', Some(b'<')) => Token::Shl,
+            (b'>', Some(b'>')) => Token::Shr,
+            (b'<', Some(b'=')) => Token::Le,
+            (b'>', Some(b'=')) => Token::Ge,
+            (b'=', Some(b'=')) => Token::Eq,
+            (b'!', Some(b'=')) => Token::Ne,
+            (b'&', Some(b'&')) => Token::AndAnd,
+            (b'|', Some(b'|')) => Token::OrOr,
+            _ => return Ok((Self::from_single(c)?, 1)),
+        };
+
+        Ok((t, 2))
+    }
+
+    fn from_single(c: u8) -> Result<Self, LexErrorKind> {
+        Ok(match c {
             b'+' => Token::Plus,
             b'-' => Token::Minus,
             b'*' => Token::Star,
@@ -32,105 +269,819 @@ impl Token {
             b')' => Token::RParen,
             b'^' => Token::Caret,
             b'!' => Token::Fac,
+            b'<' => Token::Lt,
+            b'>' => Token::Gt,
+            b'?' => Token::Question,
+            b':' => Token::Colon,
+            b'=' => Token::Assign,
+            b';' => Token::Semi,
+            b',' => Token::Comma,
+            b'{' => Token::LBrace,
+            b'}' => Token::RBrace,
             // b'[' => Token::LBracket,
             // b']' => Token::RBracket,
-            // b'{' => Token::LBrace,
-            // b'}' => Token::RBrace,
             // b'.' => Token::Dot,
-            // b'%' => Token::Percent,
-            _ => panic!("{}", s[0] as char),
+            b'%' => Token::Percent,
+            b'&' => Token::Amp,
+            b'|' => Token::Pipe,
+            b'~' => Token::Tilde,
+            _ => return Err(LexErrorKind::UnexpectedChar(c as char)),
+        })
+    }
+
+    /// An integer or decimal literal. With `wide`, an integer too large for
+    /// an `i32` is a `Decimal` in base 10, for the exact modes to convert.
+    /// With `comma`, `,` is the decimal point and `.` separates groups of
+    /// three digits, so `1.234,5` is `Decimal("1234.5")`.
+    fn from_int(s: &mut Window<impl Source>, wide: bool, comma: bool) -> Result<(Self, usize), LexErrorKind> {
+        // `0x`, `0o` and `0b` need a digit after them, so that `0x` alone
+        // is still 0 followed by the symbol `x`.
+        let radix = match (s.get(0), s.get(1)) {
+            (Some(b'0'), Some(b'x')) => 16,
+            (Some(b'0'), Some(b'o')) => 8,
+            (Some(b'0'), Some(b'b')) => 2,
+            _ => 10,
+        };
+        if radix != 10 && s.get(2).is_some_and(|c| (c as char).is_digit(radix)) {
+            let i = Self::digits(s, 2, radix);
+            let num = s.text(0..i);
+            let digits = num[2..].replace('_', "");
+            return match i32::from_str_radix(&digits, radix) {
+                Ok(n) => Ok((Self::Int(n), i)),
+                Err(_) => match u128::from_str_radix(&digits, radix) {
+                    Ok(n) if wide => Ok((Self::Decimal(n.to_string()), i)),
+                    _ => Err(LexErrorKind::IntegerOverflow(num)),
+                },
+            };
+        }
+
+        let digit = |c: Option<u8>| c.is_some_and(|c| c.is_ascii_digit());
+        let mut i = Self::digits(s, 0, 10);
+        while comma && s.get(i) == Some(b'.') && (1..4).all(|k| digit(s.get(i + k))) && !digit(s.get(i + 4)) {
+            i += 4;
+        }
+        let int_len = i;
+        if s.get(i) == Some(if comma { b',' } else { b'.' }) && digit(s.get(i + 1)) {
+            i = Self::digits(s, i + 1, 10);
+        }
+
+        // An exponent (`1e-3`, `2.5E2`) needs at least one digit, so that
+        // `2e` is still the integer 2 followed by the symbol `e`.
+        if matches!(s.get(i), Some(b'e' | b'E')) {
+            let j = if matches!(s.get(i + 1), Some(b'+' | b'-')) { i + 2 } else { i + 1 };
+            if s.get(j).is_some_and(|c| c.is_ascii_digit()) {
+                i = Self::digits(s, j, 10);
+            }
+        }
+
+        let num = s.text(0..i);
+        let plain = if comma { num.replace(['_', '.'], "").replace(',', ".") } else { num.replace('_', "") };
+        if i > int_len {
+            return Ok((Self::Decimal(plain), i));
+        }
+
+        match plain.parse() {
+            Ok(n) => Ok((Self::Int(n), i)),
+            Err(_) if wide => Ok((Self::Decimal(plain), i)),
+            Err(_) => Err(LexErrorKind::IntegerOverflow(num)),
         }
     }
 
-    fn from_int(s: &[u8]) -> (Self, usize) {
-        let mut i = 0;
-        while s.get(i).map_or(false, |c| c.is_ascii_digit()) {
+    /// The end of the digits in `radix` from `i` on. Underscores may
+    /// separate digits, but not end them: `1_000` is one number, `1_` is 1
+    /// and a stray `_`.
+    fn digits(s: &mut Window<impl Source>, mut i: usize, radix: u32) -> usize {
+        let digit = |c: Option<u8>| c.is_some_and(|c| (c as char).is_digit(radix));
+        loop {
+            while digit(s.get(i)) {
+                i += 1;
+            }
+            let mut j = i;
+            while s.get(j) == Some(b'_') {
+                j += 1;
+            }
+            if j == i || !digit(s.get(j)) {
+                return i;
+            }
+            i = j;
+        }
+    }
+
+    fn from_placeholder(s: &mut Window<impl Source>) -> Result<(Self, usize), LexErrorKind> {
+        let mut i = 1;
+        while s.get(i).is_some_and(|c| c.is_ascii_digit()) {
             i += 1
         }
 
-        let num = str::from_utf8(&s[0..i]).unwrap();
-        let num = num.parse().unwrap();
+        let n = s.text(1..i);
+        let n = n.parse().map_err(|_| LexErrorKind::PlaceholderOverflow(n))?;
 
-        (Self::Int(num), i)
+        Ok((Self::Placeholder(n), i))
     }
 
-    fn from_symbol(s: &[u8]) -> (Self, usize) {
+    /// The operator in `WORDS` spelled at the start, if any, and its
+    /// length. Its words are separated by spaces or tabs, and the last one
+    /// can't run on into a longer word.
+    fn from_words(s: &mut Window<impl Source>) -> Option<(Self, usize)> {
+        'phrases: for (phrase, t) in WORDS {
+            let mut i = 0;
+            for (k, word) in phrase.split(' ').enumerate() {
+                if k > 0 {
+                    let blanks = i;
+                    while matches!(s.get(i), Some(b' ' | b'\t')) {
+                        i += 1;
+                    }
+                    if i == blanks {
+                        continue 'phrases;
+                    }
+                }
+                if !word.bytes().enumerate().all(|(j, b)| s.get(i + j) == Some(b)) {
+                    continue 'phrases;
+                }
+                i += word.len();
+            }
+            if s.char_at(i).is_some_and(|(c, _)| c.is_alphanumeric() || c == '_') {
+                continue;
+            }
+            return Some((t.clone(), i));
+        }
+        None
+    }
+
+    /// A symbol or keyword. Letters of any script start one, and letters,
+    /// digits and `_` continue it, which is close to Unicode's XID rules.
+    fn from_symbol(s: &mut Window<impl Source>) -> (Self, usize) {
         let mut i = 0;
-        while s.get(i).map_or(false, |c| c.is_ascii_alphanumeric()) {
-            i += 1;
+        while let Some((_, len)) = s.char_at(i).filter(|&(c, _)| c.is_alphanumeric() || c == '_') {
+            i += len;
         }
 
-        let sym = str::from_utf8(&s[0..i]).unwrap().to_string();
+        let t = match s.text(0..i).as_str() {
+            "if" => Self::If,
+            "else" => Self::Else,
+            "while" => Self::While,
+            "true" => Self::Bool(true),
+            "false" => Self::Bool(false),
+            sym => Self::Sym(sym.into()),
+        };
 
-        (Self::Sym(sym), i)
+        (t, i)
+    }
+
+    /// What kind of token this is, as token streams name it: `int`,
+    /// `decimal`, `symbol`, `placeholder`, `keyword`, `punct` or `unknown`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Token::Int(_) => "int",
+            Token::Decimal(_) => "decimal",
+            Token::Sym(_) => "symbol",
+            Token::Placeholder(_) => "placeholder",
+            Token::Literal(..) => "literal",
+            Token::If | Token::Else | Token::While | Token::Bool(_) => "keyword",
+            Token::Unknown(..) => "unknown",
+            Token::Eof => "eof",
+            _ => "punct",
+        }
+    }
+
+    /// The token of kind `kind` written as `text`, the inverse of `kind`.
+    /// Punctuation that is no built-in token is a `Token::Op`, and a symbol
+    /// may be spelled any way at all.
+    pub fn from_kind(kind: &str, text: &str, span: Span) -> Result<Self, String> {
+        let invalid = || format!("{text:?} is not a valid {kind}");
+        // Literals are spelled as in source, `0x1f` and `1_000` included.
+        let literal = || match Lexer::from_bytes(text.as_bytes()).tokens().as_deref() {
+            Ok([t]) if t.token.kind() == kind && t.span == (Span { start: 0, end: text.len() }) => Ok(t.token.clone()),
+            _ => Err(invalid()),
+        };
+        Ok(match kind {
+            "int" | "decimal" | "placeholder" => literal()?,
+            "symbol" if !text.is_empty() => Token::Sym(text.into()),
+            "keyword" => match text {
+                "if" => Token::If,
+                "else" => Token::Else,
+                "while" => Token::While,
+                "true" => Token::Bool(true),
+                "false" => Token::Bool(false),
+                _ => return Err(invalid()),
+            },
+            "punct" if text.chars().count() == 1 && Token::from_math(text.chars().next().unwrap()).is_some() => {
+                Token::from_math(text.chars().next().unwrap()).unwrap()
+            }
+            "punct" => match *text.as_bytes() {
+                [c] => Token::from_single(c).unwrap_or_else(|_| Token::Op(text.to_string())),
+                [c, next] => match Token::from_op(c, Some(next)) {
+                    Ok((t, 2)) => t,
+                    _ => Token::Op(text.to_string()),
+                },
+                [] => return Err(invalid()),
+                _ => Token::Op(text.to_string()),
+            },
+            "unknown" if !text.is_empty() => Token::Unknown(text.as_bytes()[0], span),
+            "symbol" | "unknown" => return Err(invalid()),
+            _ => return Err(format!("unknown token kind '{kind}'")),
+        })
     }
 }
 
+/// The literal of one of `literals` at input offset `i`, and its length.
+fn literal(literals: &[Literal], s: &mut Buffer<impl Source>, i: usize) -> Option<(Token, usize)> {
+    if literals.is_empty() {
+        return None;
+    }
+    let mut end = i;
+    while let Some((_, len)) = s.char_at(end).filter(|&(c, len)| !c.is_whitespace() && !"(){},;".contains(c) && end + len - i <= MAX_LITERAL) {
+        end += len;
+    }
+    if end == i {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&s.slice(i, end)).into_owned();
+    literals.iter().find_map(|l| {
+        let (len, args) = (l.recognize)(&text)?;
+        (len > 0 && text.is_char_boundary(len)).then(|| (Token::Literal(l.name.clone(), args), len))
+    })
+}
+
+/// Operators spelled out in words, for `Lexer::with_words`.
+pub const WORDS: [(&str, Token); 5] = [
+    ("plus", Token::Plus),
+    ("minus", Token::Minus),
+    ("times", Token::Star),
+    ("divided by", Token::Slash),
+    ("to the power of", Token::Caret),
+];
+
+/// Where a lexer reads its input from, a chunk at a time: with `std`, any
+/// `io::BufRead`, and without it, a byte slice.
+pub trait Source {
+    /// The bytes after those consumed, reading more if there are none yet:
+    /// nothing at the end of the input, or why it can't be read.
+    fn fill(&mut self) -> Result<&[u8], String>;
+
+    /// Marks the first `n` bytes `fill` gave as read.
+    fn consume(&mut self, n: usize);
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::BufRead> Source for R {
+    fn fill(&mut self) -> Result<&[u8], String> {
+        // Once it has filled, the buffer is there again without a read.
+        while let Err(e) = self.fill_buf() {
+            if e.kind() != std::io::ErrorKind::Interrupted {
+                return Err(e.to_string());
+            }
+        }
+        Ok(self.fill_buf().unwrap_or_default())
+    }
+
+    fn consume(&mut self, n: usize) {
+        std::io::BufRead::consume(self, n)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Source for &[u8] {
+    fn fill(&mut self) -> Result<&[u8], String> {
+        Ok(self)
+    }
+
+    fn consume(&mut self, n: usize) {
+        *self = &self[n..];
+    }
+}
+
+/// The input as far as the lexer has read it: a ring buffer holding the
+/// bytes from the start of the current token up to the furthest byte
+/// looked at. Earlier bytes are dropped, so memory use is bounded by the
+/// longest token rather than by the input.
 #[derive(Debug)]
-pub struct Lexer<'a> {
+struct Buffer<R> {
+    r: R,
+    bytes: VecDeque<u8>,
+    /// Input offset of `bytes[0]`.
+    start: usize,
+    eof: bool,
+    /// A read error, reported once the bytes read before it are lexed.
+    error: Option<String>,
+}
+
+impl<R: Source> Buffer<R> {
+    /// The byte at input offset `i`, reading as much input as it takes.
+    fn get(&mut self, i: usize) -> Option<u8> {
+        while i >= self.start + self.bytes.len() && !self.eof {
+            match self.r.fill() {
+                Ok([]) => self.eof = true,
+                Ok(chunk) => {
+                    let n = chunk.len();
+                    self.bytes.extend(chunk);
+                    self.r.consume(n);
+                }
+                Err(e) => {
+                    self.error = Some(e);
+                    self.eof = true;
+                }
+            }
+        }
+        self.bytes.get(i.checked_sub(self.start)?).copied()
+    }
+
+    /// The bytes at input offsets `start..end`, which must have been read
+    /// and not dropped since.
+    fn slice(&self, start: usize, end: usize) -> Vec<u8> {
+        self.bytes.range(start - self.start..end - self.start).copied().collect()
+    }
+
+    /// The character at input offset `i` and its length in bytes, or `None`
+    /// if the bytes there are not UTF-8.
+    fn char_at(&mut self, i: usize) -> Option<(char, usize)> {
+        let len = match self.get(i)? {
+            0..=0x7f => 1,
+            0xc2..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf4 => 4,
+            _ => return None,
+        };
+        let bytes: Vec<u8> = (i..i + len).map(|k| self.get(k)).collect::<Option<_>>()?;
+        core::str::from_utf8(&bytes).ok()?.chars().next().map(|c| (c, len))
+    }
+
+    /// Drops the bytes before input offset `i`.
+    fn drop_before(&mut self, i: usize) {
+        let n = i.saturating_sub(self.start).min(self.bytes.len());
+        self.bytes.drain(..n);
+        self.start += n;
+    }
+}
+
+/// A view of the buffer from the start of a token, for the `Token::from_*`
+/// functions.
+struct Window<'b, R> {
+    buf: &'b mut Buffer<R>,
+    at: usize,
+}
+
+impl<R: Source> Window<'_, R> {
+    fn get(&mut self, i: usize) -> Option<u8> {
+        self.buf.get(self.at + i)
+    }
+
+    fn char_at(&mut self, i: usize) -> Option<(char, usize)> {
+        self.buf.char_at(self.at + i)
+    }
+
+    /// Bytes `range` of the token, which the caller has checked are UTF-8.
+    fn text(&self, range: Range<usize>) -> String {
+        String::from_utf8_lossy(&self.buf.slice(self.at + range.start, self.at + range.end)).into_owned()
+    }
+}
+
+/// Splits input into tokens on demand, one token of lookahead at a time.
+/// The input is read incrementally from any `Source`, so it need not fit
+/// in memory; spans are offsets from the start of the whole input.
+#[derive(Debug)]
+pub struct Lexer<R> {
     peeked: Option<Token>,
-    s: &'a [u8],
+    s: Buffer<R>,
     i: usize,
+    /// Span of the token most recently returned by `next` or `peek`.
+    span: Span,
+    /// Whether a line break precedes that token.
+    newline: bool,
+    /// Spellings lexed as `Token::Op`, longest first.
+    ops: Vec<Vec<u8>>,
+    /// Tokens lexed elsewhere, returned in place of lexing the input.
+    replay: Option<Replay>,
+    /// How the token most recently replayed was written.
+    text: Vec<u8>,
+    /// Whether integers too large for an `i32` are lexed as `Decimal`s.
+    wide_ints: bool,
+    /// Whether the phrases in `WORDS` are lexed as the operators they name.
+    words: bool,
+    /// Whether numbers are written with a decimal comma.
+    decimal_comma: bool,
+    /// Literals to try before any other token.
+    literals: Vec<Literal>,
 }
 
-impl<'a> Lexer<'a> {
-    pub fn new(s: &'a [u8]) -> Self {
+/// Tokens for a `Lexer` to return in place of lexing.
+struct Replay(Box<dyn Iterator<Item = Lexed> + Send>);
+
+impl fmt::Debug for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Replay")
+    }
+}
+
+impl<'a> Lexer<&'a [u8]> {
+    /// Lexes input that is already in memory.
+    pub fn from_bytes(s: &'a [u8]) -> Self {
+        Self::new(s)
+    }
+
+    /// Returns `tokens` in turn rather than lexing anything, with their
+    /// spans and line breaks as given.
+    pub fn from_tokens(tokens: Vec<Lexed>) -> Self {
+        Self::from_token_iter(tokens.into_iter())
+    }
+
+    /// Like `from_tokens`, taking each token only when it is read, so that
+    /// a parse that stops early need not have copied the rest.
+    pub fn from_token_iter(tokens: impl Iterator<Item = Lexed> + Send + 'static) -> Self {
+        Lexer { replay: Some(Replay(Box::new(tokens))), ..Self::new(&[][..]) }
+    }
+}
+
+impl<R: Source> Lexer<R> {
+    pub fn new(r: R) -> Self {
+        let mut s = Buffer { r, bytes: VecDeque::new(), start: 0, eof: false, error: None };
+
+        // Skip a UTF-8 byte-order mark without shifting later offsets.
+        let bom = (0..3).map(|i| s.get(i)).eq(b"\xef\xbb\xbf".iter().map(|&b| Some(b)));
+        let i = if bom { 3 } else { 0 };
+
         Self {
             peeked: None,
-            i: 0,
+            i,
             s,
+            span: Span { start: i, end: i },
+            newline: false,
+            ops: Vec::new(),
+            replay: None,
+            text: Vec::new(),
+            wide_ints: false,
+            words: false,
+            decimal_comma: false,
+            literals: Vec::new(),
+        }
+    }
+
+    /// Lexes each of `spellings`, runs of punctuation, as a `Token::Op`.
+    /// They take precedence over the built-in tokens: with `**` added, `**`
+    /// is one token rather than two `*`.
+    pub fn with_operators(mut self, spellings: impl IntoIterator<Item = String>) -> Self {
+        self.ops = spellings.into_iter().map(String::into_bytes).collect();
+        self.ops.sort_by_key(|op| core::cmp::Reverse(op.len()));
+        self
+    }
+
+    /// Lexes integer literals too large for an `i32` as `Decimal`s, kept
+    /// in base 10, rather than failing, for modes that hold them exactly.
+    pub fn with_wide_ints(mut self, on: bool) -> Self {
+        self.wide_ints = on;
+        self
+    }
+
+    /// Lexes the phrases in `WORDS`, like `plus` and `divided by`, as the
+    /// operators they name rather than as symbols.
+    pub fn with_words(mut self, on: bool) -> Self {
+        self.words = on;
+        self
+    }
+
+    /// Lexes numbers written with a decimal comma and dots between groups
+    /// of thousands, like `3,14` and `1.234.567,8`, as European
+    /// spreadsheets show them. A comma after a digit is then part of the
+    /// number, so arguments are separated by a comma and a space.
+    pub fn with_decimal_comma(mut self, on: bool) -> Self {
+        self.decimal_comma = on;
+        self
+    }
+
+    /// Lexes the literals that `literals` recognize as `Token::Literal`s.
+    pub fn with_literals(mut self, literals: Vec<Literal>) -> Self {
+        self.literals = literals;
+        self
+    }
+
+    /// Span of the token most recently returned by `next` or `peek`.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Whether a line break separates the token most recently returned by
+    /// `next` or `peek` from the one before it.
+    pub fn newline_before(&self) -> bool {
+        self.newline
+    }
+
+    /// The input bytes covered by `span`, which must lie within the token
+    /// most recently lexed: earlier input is no longer kept.
+    pub fn slice(&self, span: Span) -> Vec<u8> {
+        if self.replay.is_some() {
+            let at = |i: usize| i.saturating_sub(self.span.start).min(self.text.len());
+            return self.text[at(span.start)..at(span.end).max(at(span.start))].to_vec();
         }
+        self.s.slice(span.start, span.end)
     }
 
-    pub fn next(&mut self) -> Token {
+    /// Lexes the rest of the input, up to but not including `Token::Eof`.
+    pub fn tokens(mut self) -> Result<Vec<Lexed>, LexError> {
+        let mut out = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == Token::Eof {
+                return Ok(out);
+            }
+            let text = String::from_utf8_lossy(&self.slice(self.span)).into_owned();
+            out.push(Lexed { token, span: self.span, newline: self.newline, text });
+        }
+    }
+
+    /// Lexes the next token. This is not `Iterator::next`: lexing can fail,
+    /// and at the end of input it keeps returning `Token::Eof`.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Token, LexError> {
         if let Some(t) = self.peeked.take() {
-            return t;
+            return Ok(t);
+        }
+        if let Some(Replay(tokens)) = &mut self.replay {
+            return Ok(match tokens.next() {
+                Some(t) => {
+                    (self.span, self.newline, self.text) = (t.span, t.newline, t.text.into_bytes());
+                    t.token
+                }
+                None => {
+                    (self.span.start, self.newline) = (self.span.end, false);
+                    Token::Eof
+                }
+            });
         }
 
+        let t = self.lex()?;
+        self.span.end = self.i;
+        Ok(t)
+    }
+
+    fn lex(&mut self) -> Result<Token, LexError> {
         let s = &mut self.s;
         let i = &mut self.i;
+        self.newline = false;
 
-        while *i < s.len() {
-            let c = s[*i];
+        while let Some(c) = s.get(*i) {
+            s.drop_before(*i);
+            self.span.start = *i;
+            if let Some(op) = self.ops.iter().find(|op| op.iter().enumerate().all(|(k, &b)| s.get(*i + k) == Some(b))) {
+                *i += op.len();
+                return Ok(Token::Op(String::from_utf8_lossy(op).into_owned()));
+            }
+            if let Some((t, len)) = literal(&self.literals, s, *i) {
+                *i += len;
+                return Ok(t);
+            }
+            // Comments are whitespace, unless an operator in the table
+            // starts the same way. What they say is not kept.
+            if c == b'#' || (c == b'/' && s.get(*i + 1) == Some(b'/')) {
+                while s.get(*i).is_some_and(|c| c != b'\n') {
+                    *i += 1;
+                    s.drop_before(*i);
+                }
+                continue;
+            }
+            if c == b'/' && s.get(*i + 1) == Some(b'*') {
+                let start = *i;
+                *i += 2;
+                loop {
+                    match s.get(*i) {
+                        Some(b'*') if s.get(*i + 1) == Some(b'/') => break,
+                        Some(c) => {
+                            self.newline |= c == b'\n';
+                            *i += 1;
+                            s.drop_before(*i);
+                        }
+                        None => {
+                            return Err(match s.error.take() {
+                                Some(e) => LexError::at(LexErrorKind::Io(e), *i),
+                                None => LexError::at(LexErrorKind::UnterminatedComment, start),
+                            });
+                        }
+                    }
+                }
+                *i += 2;
+                continue;
+            }
             match c {
                 b'+' | b'-' |
                 b'*' | b'/' |
                 b'^' | b'!' |
-                b'(' | b')' => {
-                    let t = Token::from_op(&s[*i..]);
-                    *i += 1;
+                b'(' | b')' |
+                b'=' | b';' |
+                b'<' | b'>' |
+                b':' | b',' |
+                b'{' | b'}' | b'%' |
+                b'&' | b'|' | b'~' => {
+                    let (t, j) = Token::from_op(c, s.get(*i + 1)).map_err(|k| LexError::at(k, *i))?;
+                    *i += j;
 
-                    return t;
+                    return Ok(t);
                 }
                 b'0'..=b'9' => {
-                    let (t, j) = Token::from_int(&s[*i..]);
+                    let (t, j) = Token::from_int(&mut Window { buf: s, at: *i }, self.wide_ints, self.decimal_comma).map_err(|k| LexError::at(k, *i))?;
                     *i += j;
 
-                    return t;
+                    return Ok(t);
                 }
                 _ if c.is_ascii_alphabetic() => {
-                    let (t, j) = Token::from_symbol(&s[*i..]);
+                    let window = &mut Window { buf: s, at: *i };
+                    let (t, j) = self.words.then(|| Token::from_words(window)).flatten().unwrap_or_else(|| Token::from_symbol(window));
+                    *i += j;
+
+                    return Ok(t);
+                }
+                b'?' if s.get(*i + 1).is_some_and(|c| c.is_ascii_digit()) => {
+                    let (t, j) =
+                        Token::from_placeholder(&mut Window { buf: s, at: *i }).map_err(|k| LexError::at(k, *i + 1))?;
                     *i += j;
 
-                    return t;
+                    return Ok(t);
+                }
+                b'?' => {
+                    let (t, j) = Token::from_op(c, s.get(*i + 1)).map_err(|k| LexError::at(k, *i))?;
+                    *i += j;
+
+                    return Ok(t);
+                }
+                _ if c.is_ascii_whitespace() => {
+                    self.newline |= c == b'\n';
+                    *i += 1;
+                }
+                _ => {
+                    let Some((ch, len)) = s.char_at(*i) else {
+                        // Past the byte, so that lexing can go on.
+                        *i += 1;
+                        return Err(LexError::at(LexErrorKind::InvalidUtf8, *i - 1));
+                    };
+                    if let Some(t) = Token::from_math(ch) {
+                        *i += len;
+                        return Ok(t);
+                    }
+                    if ch.is_alphabetic() {
+                        let (t, j) = Token::from_symbol(&mut Window { buf: s, at: *i });
+                        *i += j;
+                        return Ok(t);
+                    }
+                    if !ch.is_whitespace() {
+                        // The whole character, not just its first byte.
+                        let span = Span { start: *i, end: *i + len };
+                        *i += len;
+                        return Ok(Token::Unknown(c, span));
+                    }
+                    self.newline |= matches!(ch, '\u{85}' | '\u{2028}' | '\u{2029}');
+                    *i += len;
                 }
-                _ if c.is_ascii_whitespace() => *i += 1,
-                _ => self.error(),
             };
         }
 
-        Token::Eof
+        self.span.start = *i;
+        if let Some(e) = s.error.take() {
+            return Err(LexError::at(LexErrorKind::Io(e), *i));
+        }
+        Ok(Token::Eof)
+    }
+
+    /// Puts back `t`, the token `next` most recently returned, so that it
+    /// is next again.
+    pub(crate) fn unread(&mut self, t: Token) {
+        debug_assert!(self.peeked.is_none());
+        self.peeked = Some(t);
     }
 
-    pub fn peek(&mut self) -> &Token {
+    pub fn peek(&mut self) -> Result<&Token, LexError> {
         if self.peeked.is_none() {
-            self.peeked = Some(self.next());
+            self.peeked = Some(self.next()?);
         }
-        self.peeked.as_ref().unwrap()
+        Ok(self.peeked.as_ref().unwrap())
+    }
+}
+
+#[test]
+fn tests() {
+    use std::io::{self, BufRead, BufReader};
+
+    fn tokens(mut lexer: Lexer<impl BufRead>) -> Vec<(Result<Token, LexError>, Span)> {
+        let mut out = Vec::new();
+        loop {
+            let t = lexer.next();
+            let end = matches!(t, Ok(Token::Eof) | Err(_));
+            out.push((t, lexer.span()));
+            if end {
+                return out;
+            }
+        }
+    }
+
+    // Reading a byte at a time splits every token across reads.
+    let input = "\u{feff}x1 = 12.5e-3 +% ?12\n\u{20ac} || f(99999999999)";
+    let whole = tokens(Lexer::from_bytes(input.as_bytes()));
+    assert_eq!(whole, tokens(Lexer::new(BufReader::with_capacity(1, input.as_bytes()))));
+    assert_eq!(whole[0], (Ok(Token::Sym("x1".into())), Span { start: 3, end: 5 }));
+    assert_eq!(whole[2].0, Ok(Token::Decimal("12.5e-3".into())));
+    assert_eq!(whole[5].0, Ok(Token::Unknown(0xe2, Span { start: 23, end: 26 })));
+    let words = tokens(Lexer::from_bytes(b"true false truer"));
+    assert_eq!(words.iter().map(|(t, _)| t.clone()).collect::<Vec<_>>()[..3], [Ok(Token::Bool(true)), Ok(Token::Bool(false)), Ok(Token::Sym("truer".into()))]);
+    assert_eq!(whole[9].0, Err(LexError::at(LexErrorKind::IntegerOverflow("99999999999".into()), 32)));
+
+    // Other bases and digit separators; a prefix or separator needs a digit
+    // after it to belong to the number.
+    let nums = tokens(Lexer::from_bytes(b"0x1F 0o755 0b10_10 1_000_000 1_0.2_5e1_0 0x 1_ 0b12 0x8000_0000"));
+    let nums: Vec<_> = nums.into_iter().map(|(t, _)| t).collect();
+    assert_eq!(nums[..6], [Ok(Token::Int(31)), Ok(Token::Int(493)), Ok(Token::Int(10)), Ok(Token::Int(1_000_000)), Ok(Token::Decimal("10.25e10".into())), Ok(Token::Int(0))]);
+    assert_eq!(nums[6..9], [Ok(Token::Sym("x".into())), Ok(Token::Int(1)), Ok(Token::Unknown(b'_', Span { start: 45, end: 46 }))]);
+    assert_eq!(nums[9..11], [Ok(Token::Int(1)), Ok(Token::Int(2))]);
+    assert_eq!(nums[11], Err(LexError::at(LexErrorKind::IntegerOverflow("0x8000_0000".into()), 52)));
+    assert_eq!(Token::from_kind("int", "0xff_ff", Span::default()), Ok(Token::Int(0xffff)));
+    assert_eq!(nums[11].as_ref().unwrap_err().kind.to_string(), "integer literal 0x8000_0000 is out of range for i32");
+    let big = tokens(Lexer::from_bytes(b"?99999999999999999999999"));
+    assert_eq!(big[0].0.as_ref().unwrap_err().kind.to_string(), "placeholder ?99999999999999999999999 is out of range");
+
+    // The exact modes take integers of any size, kept in base 10.
+    let wide = tokens(Lexer::from_bytes(b"2147483647 2147483648 0x8000_0000 99_999_999_999_999_999_999 0x1_0000_0000_0000_0000_0000_0000_0000_0000").with_wide_ints(true));
+    let wide: Vec<_> = wide.into_iter().map(|(t, _)| t).collect();
+    assert_eq!(wide[..4], [Ok(Token::Int(i32::MAX)), Ok(Token::Decimal("2147483648".into())), Ok(Token::Decimal("2147483648".into())), Ok(Token::Decimal("99999999999999999999".into()))]);
+    assert!(matches!(&wide[4], Err(LexError { kind: LexErrorKind::IntegerOverflow(_), .. })));
+
+    // With a decimal comma, `.` only groups thousands, and a comma needs a
+    // digit right after it to belong to the number.
+    let comma = tokens(Lexer::from_bytes(b"3,14 1.234.567,8e2 1.000 f(1, 2,5) 1.2345").with_decimal_comma(true));
+    let comma: Vec<_> = comma.into_iter().map(|(t, _)| t.unwrap()).collect();
+    assert_eq!(comma[..5], [Token::Decimal("3.14".into()), Token::Decimal("1234567.8e2".into()), Token::Int(1000), Token::Sym("f".into()), Token::LParen]);
+    assert_eq!(comma[5..9], [Token::Int(1), Token::Comma, Token::Decimal("2.5".into()), Token::RParen]);
+    assert_eq!(comma[9..], [Token::Int(1), Token::Unknown(b'.', Span { start: 36, end: 37 }), Token::Int(2345), Token::Eof]);
+
+    let ops = tokens(Lexer::from_bytes(b"a<<b>>c&&d&e||f|g+|~h%i<=j")).into_iter().map(|(t, _)| t.unwrap());
+    let ops: Vec<_> = ops.filter(|t| !matches!(t, Token::Sym(_) | Token::Eof)).collect();
+    let expected = [Token::Shl, Token::Shr, Token::AndAnd, Token::Amp, Token::OrOr, Token::Pipe, Token::PlusSat, Token::Tilde, Token::Percent, Token::Le];
+    assert_eq!(ops, expected);
+
+    // Comments are skipped like whitespace; those with a line break in
+    // them end a line. `/` alone is still division.
+    let input = "1 // x\n# y /* z\n2 /* a\n * b */ / /**/3/* // */4";
+    let whole = tokens(Lexer::from_bytes(input.as_bytes()));
+    assert_eq!(whole, tokens(Lexer::new(BufReader::with_capacity(1, input.as_bytes()))));
+    let ts: Vec<_> = whole.iter().map(|(t, _)| t.clone().unwrap()).collect();
+    assert_eq!(ts, [Token::Int(1), Token::Int(2), Token::Slash, Token::Int(3), Token::Int(4), Token::Eof]);
+    assert_eq!(whole[2].1, Span { start: 31, end: 32 });
+    let mut lexer = Lexer::from_bytes(b"1 /* \n */ 2 /* */ 3");
+    let lines: Vec<_> = (0..3).map(|_| (lexer.next().unwrap(), lexer.newline_before())).collect();
+    assert_eq!(lines, [(Token::Int(1), false), (Token::Int(2), true), (Token::Int(3), false)]);
+
+    let mut lexer = Lexer::from_bytes(b"1 + /* never closed * /");
+    lexer.next().unwrap();
+    lexer.next().unwrap();
+    let err = lexer.next().unwrap_err();
+    assert_eq!(err, LexError { kind: LexErrorKind::UnterminatedComment, span: Span { start: 4, end: 6 } });
+    assert_eq!((err.kind.code(), err.to_string().as_str()), ("unterminated-comment", "syntax error at 4: comment is not closed with */"));
+
+    let mut lexer = Lexer::new(BufReader::with_capacity(1, "a \u{20ac}".as_bytes()));
+    lexer.next().unwrap();
+    assert!(matches!(lexer.peek(), Ok(Token::Unknown(..))));
+    assert_eq!(lexer.slice(lexer.span()), "\u{20ac}".as_bytes());
+
+    // Tokens lexed beforehand replay as they were, and name their kind.
+    let lexed = Lexer::from_bytes("if x <= ?2 {\n1.5 +% \u{e9}}".as_bytes()).tokens().unwrap();
+    for t in &lexed {
+        assert_eq!(Token::from_kind(t.token.kind(), &t.text, t.span).as_ref(), Ok(&t.token), "{t:?}");
     }
+    let mut lexer = Lexer::from_tokens(lexed.clone());
+    let replayed: Vec<_> = (0..lexed.len()).map(|_| (lexer.next().unwrap(), lexer.span(), lexer.newline_before())).collect();
+    assert_eq!(replayed, lexed.iter().map(|t| (t.token.clone(), t.span, t.newline)).collect::<Vec<_>>());
+    assert_eq!(lexer.slice(lexer.span()), b"}");
+    assert_eq!((lexer.next(), lexer.span()), (Ok(Token::Eof), Span { start: 23, end: 23 }));
+    assert_eq!(Token::from_kind("punct", "**", Span::default()), Ok(Token::Op("**".into())));
+    assert_eq!(Token::from_kind("keyword", "of", Span::default()), Err("\"of\" is not a valid keyword".into()));
+    assert_eq!(Token::from_kind("punct", "\u{d7}", Span::default()), Ok(Token::Star));
+
+    // Letters of any script make symbols, and math symbols are operators.
+    let ts = tokens(Lexer::new(BufReader::with_capacity(1, "2\u{3c0}r_1 \u{d7} \u{f7} \u{2212}x \u{2264}\u{2265}\u{2260} \u{221a}\u{2003}\u{2028}\u{e9}t\u{e9}".as_bytes())));
+    let ts: Vec<_> = ts.into_iter().map(|(t, _)| t.unwrap()).collect();
+    assert_eq!(ts, [
+        Token::Int(2), Token::Sym("\u{3c0}r_1".into()), Token::Star, Token::Slash, Token::Minus, Token::Sym("x".into()),
+        Token::Le, Token::Ge, Token::Ne, Token::Sym("sqrt".into()), Token::Sym("\u{e9}t\u{e9}".into()), Token::Eof,
+    ]);
+    let mut lexer = Lexer::from_bytes("a\u{2028}b".as_bytes());
+    lexer.next().unwrap();
+    assert_eq!((lexer.next(), lexer.newline_before()), (Ok(Token::Sym("b".into())), true));
 
-    fn error(&self) -> ! {
-        eprintln!("Syntax error at {}", self.i);
-        std::process::exit(1);
+    // Bytes that are not UTF-8 are an error at their offset.
+    for input in [&b"1 + \xff"[..], b"1 + \xe2\x88", b"1 + \xc3("] {
+        let mut lexer = Lexer::from_bytes(input);
+        lexer.next().unwrap();
+        lexer.next().unwrap();
+        let err = lexer.next().unwrap_err();
+        assert_eq!(err, LexError { kind: LexErrorKind::InvalidUtf8, span: Span { start: 4, end: 5 } });
+        assert_eq!((err.kind.code(), err.kind.to_string().as_str()), ("invalid-utf8", "input is not valid UTF-8"));
+    }
+
+    // A read error ends the input where it happens.
+    struct Broken;
+    impl io::Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk on fire"))
+        }
     }
+    let mut lexer = Lexer::new(BufReader::new(io::Read::chain("1 +".as_bytes(), Broken)));
+    assert_eq!(lexer.next(), Ok(Token::Int(1)));
+    assert_eq!(lexer.next(), Ok(Token::Plus));
+    let err = lexer.next().unwrap_err();
+    assert_eq!((err.kind.code(), err.span.start), ("read-error", 3));
 }
@@ -0,0 +1,164 @@
+//! The stoncc expression engine: a lexer, a precedence-climbing parser and
+//! a tree-walking evaluator.
+//!
+//! ```
+//! use stoncc::limits::{EvalLimits, Meter};
+//! use stoncc::{Env, Value};
+//!
+//! let ast = stoncc::expr(b"x * (2 + 1.5)").unwrap();
+//! assert_eq!(ast.to_string(), "(* x (+ 2 1.5))");
+//!
+//! let mut env = Env::new();
+//! env.insert("x".to_string(), Value::Int(2));
+//! let mut meter = Meter::new(EvalLimits::default());
+//! assert_eq!(stoncc::eval(&ast, &env, &mut meter), Ok(Value::Float(7.0)));
+//! ```
+//!
+//! What integer overflow does is set by an [`ArithMode`] on the meter.
+//! Evaluation in exact fixed-point decimal lives in [`decimal`], with
+//! arbitrary-precision integers in [`bigint`], exact fractions in
+//! [`rational`], and resource limits for untrusted input in [`limits`]. [`ir`] flattens trees
+//! into three-address code, [`duration`] does the arithmetic of duration
+//! values, [`units`] that of physical quantities, checking their
+//! dimensions, and [`bytes`] reads and writes byte sizes. [`format`] prints
+//! results in hexadecimal, binary or scientific notation. [`arena`] keeps a
+//! whole tree in one pool, for very large expressions. The parser takes its
+//! operators from an [`operators::OperatorTable`], to which library users
+//! can add their own, [`program_from_tokens`] parses tokens that another
+//! tool lexed, and [`program_recovering`] reads past syntax errors to
+//! report them all. [`sema`] checks a program before it runs, and [`resolve`]
+//! finds names used before they are defined without any input values. [`vm`]
+//! compiles an expression once to bytecode for evaluating it many times,
+//! and a [`Session`] runs whole programs, keeping what they define and, if
+//! asked, [`metrics`] on them and an [`audit`] log of them. A [`document::Document`] parses a program
+//! again after an edit without starting over, for editors. [`generate`] makes random expressions from
+//! a seed, for benchmarks and tests. [`snapshot`] saves the variables and
+//! functions a program defined, to load again later.
+//! [`trace`] evaluates one step at a time, showing each subtree it reduces.
+//! Symbols in tokens and trees are [`symbol::SymId`]s, each name stored once.
+//! [`dot`] draws trees, or any other graph a pass builds, for Graphviz.
+//! Passes of your own can walk a tree with a [`Visitor`] or rewrite it
+//! with a [`Folder`], without matching on every kind of node.
+//! [`Node::partial_eval`] evaluates what it can of a tree whose symbols
+//! are not all bound, leaving an expression in the rest, and
+//! [`Node::subtree_values`] gives the value of each subtree that has what
+//! it needs, for editors showing values around syntax errors.
+//!
+//! `decimal`, `bigint` and `rational` are behind cargo features of the same
+//! names, off by default; `full` turns on every feature. Without them the
+//! crate is the core lexer, parser and evaluators, and what is built on
+//! them.
+//!
+//! All of that but the core needs the standard library, behind the `std`
+//! feature, the only one on by default. Without it the core is `#![no_std]`, needing
+//! only `alloc`, and takes the float math `std` would give it from `libm`:
+//! the lexer reads a [`lexer::Source`] rather than any `io::BufRead`, an
+//! [`Env`] is a `BTreeMap`, and `EvalLimits::max_millis` has no clock to
+//! go by. Errors are values there as everywhere, and nothing the core is
+//! given to parse or evaluate makes it panic.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("stoncc needs the `std` feature, or `libm` for float math without it");
+
+extern crate alloc;
+
+/// What the `std` prelude has that `core`'s doesn't, for the modules that
+/// build without `std`.
+mod prelude {
+    pub use alloc::boxed::Box;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec::Vec;
+    pub use alloc::{format, vec};
+
+    #[cfg(not(feature = "std"))]
+    pub use alloc::collections::BTreeMap as Map;
+    #[cfg(feature = "std")]
+    pub use std::collections::HashMap as Map;
+
+    /// The float math `f64` has only with `std`.
+    #[cfg(not(feature = "std"))]
+    pub trait Float {
+        fn sqrt(self) -> f64;
+        fn round(self) -> f64;
+        fn powf(self, n: f64) -> f64;
+    }
+
+    #[cfg(not(feature = "std"))]
+    impl Float for f64 {
+        fn sqrt(self) -> f64 {
+            libm::sqrt(self)
+        }
+
+        fn round(self) -> f64 {
+            libm::round(self)
+        }
+
+        fn powf(self, n: f64) -> f64 {
+            libm::pow(self, n)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+pub mod arena;
+pub mod arith;
+#[cfg(feature = "std")]
+pub mod audit;
+mod ast;
+#[cfg(feature = "bigint")]
+pub mod bigint;
+mod builtins;
+pub mod bytes;
+#[cfg(feature = "decimal")]
+pub mod decimal;
+mod derive;
+#[cfg(feature = "std")]
+pub mod document;
+#[cfg(feature = "std")]
+pub mod dot;
+pub mod duration;
+mod eval;
+#[cfg(feature = "std")]
+pub mod format;
+#[cfg(feature = "std")]
+pub mod generate;
+mod infix;
+#[cfg(feature = "std")]
+pub mod ir;
+pub mod json;
+pub mod lexer;
+pub mod limits;
+#[cfg(feature = "std")]
+pub mod metrics;
+pub mod operators;
+mod optimize;
+pub mod parser;
+pub mod passes;
+mod partial;
+#[cfg(feature = "rational")]
+pub mod rational;
+#[cfg(feature = "std")]
+pub mod resolve;
+#[cfg(feature = "std")]
+pub mod sema;
+#[cfg(feature = "std")]
+pub mod session;
+pub mod snapshot;
+pub mod symbol;
+#[cfg(feature = "std")]
+pub mod trace;
+pub mod units;
+#[cfg(feature = "std")]
+pub mod vm;
+
+pub use arith::{ArithError, ArithMode, NonInteger};
+pub use ast::{Folder, Visitor, SCHEMA_VERSION};
+pub use builtins::{Builtins, Function};
+pub use eval::{eval, eval_in, eval_with, Env, EvalError, Layered, Scope, MAX_CALL_DEPTH};
+pub use lexer::{LexError, LexErrorKind, Lexer, Span, Token};
+pub use optimize::Rewrite;
+#[cfg(feature = "std")]
+pub use session::{exec, Busy, Session, SessionBuilder, SessionError, Truth};
+pub use parser::{expr, parse, program, program_from, program_with, program_with_operators, program_from_tokens, program_recovering, Doc, Extensions, LeafVal, Node, NodeVal, ParseError, ParseErrorKind, Program, ShortCircuit, Stmt, Value, MAX_DEPTH, MAX_NESTING};
@@ -0,0 +1,235 @@
+use alloc::sync::Arc;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use crate::arith::ArithMode;
+use crate::eval::EvalError;
+use crate::prelude::*;
+
+/// Resource limits for evaluating untrusted expressions. Each limit is
+/// optional and enforced independently.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EvalLimits {
+    /// Deepest operator nesting that may be evaluated.
+    pub max_depth: Option<usize>,
+    /// Most operators that may be applied.
+    pub max_ops: Option<u64>,
+    /// Widest magnitude, in bits, any intermediate value may have.
+    pub max_value_bits: Option<u32>,
+    /// Longest wall-clock time evaluation may take. Without `std` there is
+    /// no clock to check it by, and it is ignored.
+    pub max_millis: Option<u64>,
+}
+
+/// The limit that stopped an evaluation, with its configured value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LimitExceeded {
+    Depth(usize),
+    Ops(u64),
+    ValueBits(u32),
+    Millis(u64),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Depth(n) => write!(f, "evaluation exceeded max_depth ({n} levels)"),
+            LimitExceeded::Ops(n) => write!(f, "evaluation exceeded max_ops ({n} operations)"),
+            LimitExceeded::ValueBits(n) => write!(f, "evaluation exceeded max_value_bits ({n} bits)"),
+            LimitExceeded::Millis(n) => write!(f, "evaluation exceeded max_millis ({n} ms)"),
+        }
+    }
+}
+
+impl core::error::Error for LimitExceeded {}
+
+impl LimitExceeded {
+    /// The flag that set the limit, without its leading dashes.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LimitExceeded::Depth(_) => "max-depth",
+            LimitExceeded::Ops(_) => "max-ops",
+            LimitExceeded::ValueBits(_) => "max-value-bits",
+            LimitExceeded::Millis(_) => "max-millis",
+        }
+    }
+}
+
+impl EvalLimits {
+    /// Limits for formulas from untrusted users, as `--sandbox` sets them:
+    /// ample for any formula a person would write, but small enough that
+    /// none can hold up a shared service.
+    pub const SANDBOX: EvalLimits = EvalLimits {
+        max_depth: Some(1000),
+        max_ops: Some(1_000_000),
+        max_value_bits: Some(4096),
+        max_millis: Some(1000),
+    };
+
+    /// Each limit of `self` or `other`, whichever is stricter.
+    pub fn min(self, other: EvalLimits) -> EvalLimits {
+        fn min<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+        EvalLimits {
+            max_depth: min(self.max_depth, other.max_depth),
+            max_ops: min(self.max_ops, other.max_ops),
+            max_value_bits: min(self.max_value_bits, other.max_value_bits),
+            max_millis: min(self.max_millis, other.max_millis),
+        }
+    }
+
+    /// Sets the limit named by a `--max-NAME=N` flag.
+    pub fn set(&mut self, name: &str, n: &str) -> Result<(), String> {
+        let bad = |_| format!("--max-{name} expects a number, found '{n}'");
+        match name {
+            "depth" => self.max_depth = Some(n.parse().map_err(bad)?),
+            "ops" => self.max_ops = Some(n.parse().map_err(bad)?),
+            "value-bits" => self.max_value_bits = Some(n.parse().map_err(bad)?),
+            "millis" => self.max_millis = Some(n.parse().map_err(bad)?),
+            _ => return Err(format!("unknown limit --max-{name}")),
+        }
+        Ok(())
+    }
+}
+
+/// Stops evaluations from another thread, or from a signal handler: one
+/// metered with the token fails with `EvalError::Cancelled` before its
+/// next operator once the token is cancelled. Clones share the token.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Lets evaluations run again, once the one cancelled has stopped.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Tracks one evaluation against its limits. It also carries the
+/// `ArithMode` the evaluation uses, `Checked` unless set.
+pub struct Meter {
+    limits: EvalLimits,
+    arith: ArithMode,
+    ops: Arc<AtomicU64>,
+    cancel: Option<CancelToken>,
+    #[cfg(feature = "std")]
+    start: Instant,
+}
+
+impl Meter {
+    pub fn new(limits: EvalLimits) -> Self {
+        Self {
+            limits,
+            arith: ArithMode::default(),
+            ops: Arc::default(),
+            cancel: None,
+            #[cfg(feature = "std")]
+            start: Instant::now(),
+        }
+    }
+
+    pub fn with_arith(self, arith: ArithMode) -> Self {
+        Self { arith, ..self }
+    }
+
+    /// Stops the evaluation once `token` is cancelled.
+    pub fn with_cancel(self, token: CancelToken) -> Self {
+        Self { cancel: Some(token), ..self }
+    }
+
+    pub fn arith(&self) -> ArithMode {
+        self.arith
+    }
+
+    /// The number of operators applied so far, which another thread can
+    /// watch while evaluation runs.
+    pub fn ops(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.ops)
+    }
+
+    /// Checks that evaluation may descend to `depth`.
+    pub fn enter(&self, depth: usize) -> Result<(), LimitExceeded> {
+        match self.limits.max_depth {
+            Some(max) if depth > max => Err(LimitExceeded::Depth(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Accounts for one more operator application, unless the evaluation
+    /// has been cancelled.
+    pub fn op(&mut self) -> Result<(), EvalError> {
+        if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            return Err(EvalError::Cancelled);
+        }
+        let ops = self.ops.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if let Some(max) = self.limits.max_ops.filter(|&max| ops > max) {
+            return Err(LimitExceeded::Ops(max).into());
+        }
+        #[cfg(feature = "std")]
+        if let Some(max) = self.limits.max_millis.filter(|&max| self.start.elapsed().as_millis() > max as u128) {
+            return Err(LimitExceeded::Millis(max).into());
+        }
+        Ok(())
+    }
+
+    /// Checks the magnitude of a value produced by evaluation.
+    pub fn value(&self, v: i128) -> Result<(), LimitExceeded> {
+        self.bits((128 - v.unsigned_abs().leading_zeros()) as u64)
+    }
+
+    /// Checks a value that takes `bits` bits, not counting its sign.
+    pub fn bits(&self, bits: u64) -> Result<(), LimitExceeded> {
+        match self.limits.max_value_bits {
+            Some(max) if bits > max as u64 => Err(LimitExceeded::ValueBits(max)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn tests() {
+    let limits = EvalLimits { max_depth: Some(2), max_ops: Some(1), max_value_bits: Some(8), max_millis: None };
+    let mut m = Meter::new(limits);
+
+    assert_eq!(m.enter(2), Ok(()));
+    assert_eq!(m.enter(3), Err(LimitExceeded::Depth(2)));
+    assert_eq!(m.value(-255), Ok(()));
+    assert_eq!(m.value(256), Err(LimitExceeded::ValueBits(8)));
+    assert_eq!(m.op(), Ok(()));
+    assert_eq!(m.op(), Err(EvalError::Limit(LimitExceeded::Ops(1))));
+    assert_eq!(m.ops().load(Ordering::Relaxed), 2);
+
+    // Cancelling stops the next operator, before any limit is checked.
+    let token = CancelToken::new();
+    let mut m = Meter::new(EvalLimits::default()).with_cancel(token.clone());
+    assert_eq!(m.op(), Ok(()));
+    token.clone().cancel();
+    assert_eq!(m.op(), Err(EvalError::Cancelled));
+    token.reset();
+    assert_eq!(m.op(), Ok(()));
+    assert_eq!(m.ops().load(Ordering::Relaxed), 2);
+
+    let sandboxed = limits.min(EvalLimits::SANDBOX);
+    assert_eq!((sandboxed.max_depth, sandboxed.max_millis), (Some(2), Some(1000)));
+    assert_eq!(EvalLimits::default().min(EvalLimits::SANDBOX), EvalLimits::SANDBOX);
+}
@@ -0,0 +1,90 @@
+//! A fast path for `stoncc -e EXPR` when EXPR is only integer literals,
+//! `+ - * / ^` and parentheses, the usual one-liner in shell scripts. It
+//! evaluates straight from the tokens, without building a tree, and prints
+//! what the full pipeline would. Anything else, including an expression
+//! that fails to evaluate, takes the usual way, so its diagnostics are the
+//! same.
+
+use stoncc::parser::NodeVal;
+use stoncc::{ArithMode, Lexer, Token, Value};
+
+/// Deeper parentheses than this take the usual way, which doesn't recurse.
+const MAX_NESTING: usize = 64;
+
+struct Literal {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Literal {
+    fn peek(&self) -> &Token {
+        self.tokens.get(self.pos).unwrap_or(&Token::Eof)
+    }
+
+    /// An operand, with its value and its tree as it would print.
+    fn operand(&mut self, depth: usize) -> Option<(Value, String)> {
+        self.pos += 1;
+        match self.tokens.get(self.pos - 1)? {
+            Token::Int(v) => Some((Value::Int(*v), v.to_string())),
+            Token::LParen if depth < MAX_NESTING => {
+                let e = self.expr(0, depth + 1)?;
+                (*self.peek() == Token::RParen).then(|| self.pos += 1)?;
+                Some(e)
+            }
+            _ => None,
+        }
+    }
+
+    /// Operands joined by operators binding at least as tightly as `min`.
+    fn expr(&mut self, min: i32, depth: usize) -> Option<(Value, String)> {
+        let (mut v, mut tree) = self.operand(depth)?;
+        loop {
+            let op = match self.peek() {
+                t @ (Token::Plus | Token::Minus | Token::Star | Token::Slash | Token::Caret) => NodeVal::try_from(t).ok()?,
+                _ => return Some((v, tree)),
+            };
+            let prec = op.infix_prec();
+            if prec < min {
+                return Some((v, tree));
+            }
+            self.pos += 1;
+            let (w, rhs) = self.expr(if op.is_lassoc() { prec + 1 } else { prec }, depth)?;
+            v = op.apply(&[v, w], ArithMode::default()).ok()?;
+            tree = format!("({op} {tree} {rhs})");
+        }
+    }
+}
+
+/// The line `stoncc -e src` prints, if `src` can take the fast path.
+pub fn eval(src: &str) -> Option<String> {
+    let mut lexer = Lexer::new(src.as_bytes());
+    let mut tokens = Vec::new();
+    loop {
+        match lexer.next().ok()? {
+            Token::Eof => break,
+            t => tokens.push(t),
+        }
+    }
+
+    let mut literal = Literal { tokens, pos: 0 };
+    let (v, tree) = literal.expr(0, 0)?;
+    (literal.pos == literal.tokens.len()).then(|| format!("Evaluating {tree}: {v}"))
+}
+
+#[test]
+fn tests() {
+    use stoncc::limits::Meter;
+
+    // Whatever takes the fast path prints what the full pipeline does.
+    for s in ["2+2", "2 + 3 * 4 ^ 2", "(1 - 2) - 3 - (4 - 5)", "2 ^ 3 ^ 2", "7 / 2 * 2", "((((9))))", "0x10 * 1_000", "2 ^ 30 - 1 + 2 ^ 30"] {
+        let ast = stoncc::expr(s.as_bytes()).unwrap();
+        let v = stoncc::eval(&ast, &Default::default(), &mut Meter::new(Default::default())).unwrap();
+        assert_eq!(eval(s), Some(format!("Evaluating {ast}: {v}")), "{s}");
+    }
+
+    for s in ["-2", "2 * -1", "1.5 + 1", "x + 1", "2 < 3", "3!", "1 / 0", "2 ^ 31", "(1 + 2", "1 + 2)", "2 (3)", "1; 2", "f(1)", "", "#"] {
+        assert_eq!(eval(s), None, "{s}");
+    }
+    assert_eq!(eval(&format!("{}1{}", "(".repeat(MAX_NESTING), ")".repeat(MAX_NESTING))), Some("Evaluating 1: 1".into()));
+    assert_eq!(eval(&format!("{}1{}", "(".repeat(MAX_NESTING + 1), ")".repeat(MAX_NESTING + 1))), None);
+}
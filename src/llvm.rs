@@ -0,0 +1,557 @@
+//! LLVM backend (`--emit=llvm`). The output is a module of textual LLVM IR
+//! for `clang` or `llc` to finish for any target. Its `main` prints the
+//! value of every statement with `printf`, one per line, like the x86-64
+//! backend's. Pointers are typed, as LLVM 14 and older need them to be;
+//! later versions read them as opaque `ptr`s.
+//!
+//! Pushes cost nothing here either: each value is an SSA register or a
+//! constant, and the operands waiting for an operation are kept by name.
+//! Division by zero traps.
+//!
+//! Floats are `double`s, and `^` on them calls libm's `pow`, so a module
+//! that uses it links with `-lm`. They print with the fewest digits that
+//! read back as the same double, the way `%g` writes them: `3` and `1e+21`
+//! where the evaluator shows `3.0` and `1e21`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use stoncc::Value;
+
+use crate::codegen::{Backend, Binary, Tally, Ty, Unary};
+
+/// Declarations, and helpers for operators with loops or checks in them.
+const HELPERS: &str = r#"
+declare i32 @printf(i8*, ...)
+declare void @llvm.trap()
+declare i32 @llvm.abs.i32(i32, i1)
+declare i32 @llvm.smin.i32(i32, i32)
+declare i32 @llvm.smax.i32(i32, i32)
+declare i32 @llvm.umin.i32(i32, i32)
+declare i32 @llvm.sadd.sat.i32(i32, i32)
+declare i32 @llvm.ctpop.i32(i32)
+declare i32 @llvm.ctlz.i32(i32, i1)
+declare i32 @llvm.cttz.i32(i32, i1)
+declare i32 @llvm.fshl.i32(i32, i32, i32)
+declare i32 @llvm.fshr.i32(i32, i32, i32)
+declare i64 @llvm.smin.i64(i64, i64)
+declare i64 @llvm.smax.i64(i64, i64)
+declare double @llvm.fabs.f64(double)
+declare double @llvm.minnum.f64(double, double)
+declare double @llvm.maxnum.f64(double, double)
+declare double @pow(double, double)
+declare i32 @snprintf(i8*, i64, i8*, ...)
+declare double @strtod(i8*, i8**)
+declare i32 @puts(i8*)
+
+define internal i32 @stoncc_pow(i32 %b, i32 %e) {
+entry:
+  %neg = icmp slt i32 %e, 0
+  br i1 %neg, label %inverse, label %loop
+loop:
+  %r = phi i32 [1, %entry], [%r2, %body]
+  %x = phi i32 [%b, %entry], [%x1, %body]
+  %n = phi i32 [%e, %entry], [%n1, %body]
+  %more = icmp ne i32 %n, 0
+  br i1 %more, label %body, label %done
+body:
+  %bit = and i32 %n, 1
+  %odd = icmp ne i32 %bit, 0
+  %r1 = mul i32 %r, %x
+  %r2 = select i1 %odd, i32 %r1, i32 %r
+  %x1 = mul i32 %x, %x
+  %n1 = lshr i32 %n, 1
+  br label %loop
+done:
+  ret i32 %r
+inverse:
+  ; Truncated like 1 / b^-e: 0 unless b is 1 or -1.
+  call void @stoncc_check_div(i32 %b)
+  %ebit = and i32 %e, 1
+  %eodd = icmp ne i32 %ebit, 0
+  %m1 = icmp eq i32 %b, -1
+  %sign = select i1 %eodd, i32 -1, i32 1
+  %one = icmp eq i32 %b, 1
+  %unit = zext i1 %one to i32
+  %i = select i1 %m1, i32 %sign, i32 %unit
+  ret i32 %i
+}
+
+define internal i32 @stoncc_fac(i32 %a) {
+entry:
+  br label %loop
+loop:
+  %r = phi i32 [1, %entry], [%r1, %body]
+  %n = phi i32 [%a, %entry], [%n1, %body]
+  %more = icmp sgt i32 %n, 1
+  br i1 %more, label %body, label %done
+body:
+  %r1 = mul i32 %r, %n
+  %n1 = sub i32 %n, 1
+  br label %loop
+done:
+  ret i32 %r
+}
+
+define internal i32 @stoncc_gcd(i32 %a, i32 %b) {
+entry:
+  br label %loop
+loop:
+  %x = phi i32 [%a, %entry], [%y, %body]
+  %y = phi i32 [%b, %entry], [%r, %body]
+  %zero = icmp eq i32 %y, 0
+  br i1 %zero, label %done, label %body
+body:
+  ; Anything divided by -1 leaves 0, but srem may overflow getting there.
+  %m1 = icmp eq i32 %y, -1
+  %d = select i1 %m1, i32 1, i32 %y
+  %r = srem i32 %x, %d
+  br label %loop
+done:
+  %g = call i32 @llvm.abs.i32(i32 %x, i1 false)
+  ret i32 %g
+}
+
+; Tries 1 to 17 significant digits, the most a double can need.
+define internal void @stoncc_print_f64(double %x) {
+entry:
+  %buf = alloca [32 x i8]
+  %s = getelementptr [32 x i8], [32 x i8]* %buf, i64 0, i64 0
+  br label %loop
+loop:
+  %digits = phi i32 [1, %entry], [%more, %loop]
+  %n = call i32 (i8*, i64, i8*, ...) @snprintf(i8* %s, i64 32, i8* getelementptr ([5 x i8], [5 x i8]* @fmt.g, i64 0, i64 0), i32 %digits, double %x)
+  %back = call double @strtod(i8* %s, i8** null)
+  %more = add i32 %digits, 1
+  %same = fcmp oeq double %back, %x
+  %last = icmp eq i32 %digits, 17
+  %done = or i1 %same, %last
+  br i1 %done, label %print, label %loop
+print:
+  %p = call i32 @puts(i8* %s)
+  ret void
+}
+
+define internal i32 @stoncc_mul_sat(i32 %a, i32 %b) {
+  %x = sext i32 %a to i64
+  %y = sext i32 %b to i64
+  %p = mul i64 %x, %y
+  %lo = call i64 @llvm.smax.i64(i64 %p, i64 -2147483648)
+  %hi = call i64 @llvm.smin.i64(i64 %lo, i64 2147483647)
+  %r = trunc i64 %hi to i32
+  ret i32 %r
+}
+
+define internal void @stoncc_check_div(i32 %b) {
+  %zero = icmp eq i32 %b, 0
+  br i1 %zero, label %trap, label %ok
+trap:
+  call void @llvm.trap()
+  unreachable
+ok:
+  ret void
+}
+
+; sdiv and srem overflow on a / -1, which wraps to -a with nothing left over.
+define internal i32 @stoncc_div(i32 %a, i32 %b) {
+  call void @stoncc_check_div(i32 %b)
+  %m1 = icmp eq i32 %b, -1
+  %d = select i1 %m1, i32 1, i32 %b
+  %q = sdiv i32 %a, %d
+  %n = sub i32 0, %q
+  %r = select i1 %m1, i32 %n, i32 %q
+  ret i32 %r
+}
+
+define internal i32 @stoncc_rem(i32 %a, i32 %b) {
+  call void @stoncc_check_div(i32 %b)
+  %m1 = icmp eq i32 %b, -1
+  %d = select i1 %m1, i32 1, i32 %b
+  %r = srem i32 %a, %d
+  ret i32 %r
+}
+"#;
+
+/// A conditional being compiled.
+struct If {
+    label: usize,
+    /// The type of its value if it has one, and once the first branch is
+    /// done, the value it left and the block it ended in.
+    value: Option<Ty>,
+    then: Option<(String, String)>,
+}
+
+#[derive(Default)]
+pub struct Llvm {
+    out: String,
+    tally: Tally,
+    /// The current value, a register or a constant.
+    cur: String,
+    /// Values pushed by the expression being compiled.
+    stack: Vec<String>,
+    /// The block being compiled into, for phis to name.
+    block: String,
+    temps: usize,
+    labels: usize,
+    ifs: Vec<If>,
+    loops: Vec<usize>,
+}
+
+/// The LLVM type of `ty`.
+fn ir(ty: Ty) -> &'static str {
+    match ty {
+        Ty::Int => "i32",
+        Ty::Float => "double",
+    }
+}
+
+/// `v` as an LLVM constant, in hex, which always holds it exactly.
+fn double(v: f64) -> String {
+    format!("0x{:016X}", v.to_bits())
+}
+
+/// A global identifier, quoted so that any symbol can be one.
+fn global(prefix: &str, name: &str) -> String {
+    let mut out = format!("@\"{prefix}.");
+    for b in name.bytes() {
+        match b {
+            b'"' | b'\\' | ..b' ' | 0x7f.. => {
+                let _ = write!(out, "\\{b:02X}");
+            }
+            _ => out.push(b as char),
+        }
+    }
+    out + "\""
+}
+
+impl Llvm {
+    fn emit(&mut self, line: &str) {
+        self.tally.count(line);
+        self.out += "  ";
+        self.out += line;
+        self.out.push('\n');
+    }
+
+    /// Emits `rhs` into a new register, which becomes current.
+    fn op(&mut self, rhs: &str) {
+        self.temps += 1;
+        self.cur = format!("%t{}", self.temps);
+        self.emit(&format!("{} = {rhs}", self.cur));
+    }
+
+    fn label(&mut self, name: String) {
+        self.out += &format!("{name}:\n");
+        self.block = name;
+    }
+
+    /// Branches to `yes` if the current value is nonzero, or else to `no`.
+    fn branch(&mut self, yes: &str, no: &str) {
+        let cur = self.cur.clone();
+        self.op(&format!("icmp ne i32 {cur}, 0"));
+        let c = self.cur.clone();
+        self.emit(&format!("br i1 {c}, label %{yes}, label %{no}"));
+    }
+}
+
+impl Backend for Llvm {
+    const COMMENT: &'static str = ";";
+    const FLOATS: bool = true;
+
+    fn begin_main(&mut self) {
+        self.out += "define i32 @main() {\n";
+        self.label("entry".into());
+    }
+
+    fn end_main(&mut self) {
+        self.emit("ret i32 0");
+        self.out += "}\n\n";
+    }
+
+    fn begin_function(&mut self, name: &str, params: usize) {
+        let params: Vec<String> = (0..params).map(|i| format!("i32 %a{i}")).collect();
+        let _ = writeln!(self.out, "define internal i32 {}({}) {{", global("fn", name), params.join(", "));
+        self.label("entry".into());
+    }
+
+    fn end_function(&mut self) {
+        let cur = self.cur.clone();
+        self.emit(&format!("ret i32 {cur}"));
+        self.out += "}\n\n";
+    }
+
+    fn push(&mut self) {
+        self.stack.push(self.cur.clone());
+    }
+
+    fn int(&mut self, v: i32) {
+        self.cur = v.to_string();
+    }
+
+    fn load_global(&mut self, name: &str, ty: Ty) {
+        let t = ir(ty);
+        self.op(&format!("load {t}, {t}* {}", global("var", name)));
+    }
+
+    fn load_param(&mut self, index: usize) {
+        self.cur = format!("%a{index}");
+    }
+
+    fn store_global(&mut self, name: &str, ty: Ty) {
+        let (t, cur) = (ir(ty), self.cur.clone());
+        self.emit(&format!("store {t} {cur}, {t}* {}", global("var", name)));
+    }
+
+    fn unary(&mut self, op: Unary) {
+        let a = self.cur.clone();
+        self.op(&match op {
+            Unary::Neg => format!("sub i32 0, {a}"),
+            Unary::Fac => format!("call i32 @stoncc_fac(i32 {a})"),
+            Unary::Abs => format!("call i32 @llvm.abs.i32(i32 {a}, i1 false)"),
+            Unary::Not => format!("xor i32 {a}, -1"),
+            Unary::Popcount => format!("call i32 @llvm.ctpop.i32(i32 {a})"),
+            Unary::Clz => format!("call i32 @llvm.ctlz.i32(i32 {a}, i1 false)"),
+            Unary::Ctz => format!("call i32 @llvm.cttz.i32(i32 {a}, i1 false)"),
+        });
+    }
+
+    fn binary(&mut self, op: Binary) {
+        let (a, b) = (self.stack.pop().unwrap(), self.cur.clone());
+        let call = |f: &str| format!("call i32 @{f}(i32 {a}, i32 {b})");
+        let rhs = match op {
+            Binary::Add | Binary::AddWrap => format!("add i32 {a}, {b}"),
+            Binary::Sub => format!("sub i32 {a}, {b}"),
+            Binary::Mul | Binary::MulWrap => format!("mul i32 {a}, {b}"),
+            Binary::Div => call("stoncc_div"),
+            Binary::Rem => call("stoncc_rem"),
+            Binary::Pow => call("stoncc_pow"),
+            Binary::AddSat => call("llvm.sadd.sat.i32"),
+            Binary::MulSat => call("stoncc_mul_sat"),
+            Binary::Min => call("llvm.smin.i32"),
+            Binary::Max => call("llvm.smax.i32"),
+            Binary::Gcd => call("stoncc_gcd"),
+            // A shift by 32 or more is poison, which `select` never picks.
+            Binary::Shl => {
+                self.op(&format!("shl i32 {a}, {b}"));
+                let shifted = self.cur.clone();
+                self.op(&format!("icmp ult i32 {b}, 32"));
+                format!("select i1 {}, i32 {shifted}, i32 0", self.cur)
+            }
+            Binary::Shr => {
+                self.op(&format!("call i32 @llvm.umin.i32(i32 {b}, i32 31)"));
+                format!("ashr i32 {a}, {}", self.cur)
+            }
+            Binary::And => format!("and i32 {a}, {b}"),
+            Binary::Or => format!("or i32 {a}, {b}"),
+            // A funnel shift of a value with itself rotates it.
+            Binary::Rotl => format!("call i32 @llvm.fshl.i32(i32 {a}, i32 {a}, i32 {b})"),
+            Binary::Rotr => format!("call i32 @llvm.fshr.i32(i32 {a}, i32 {a}, i32 {b})"),
+            Binary::Lt | Binary::Le | Binary::Gt | Binary::Ge | Binary::Eq | Binary::Ne => {
+                let cond = match op {
+                    Binary::Lt => "slt",
+                    Binary::Le => "sle",
+                    Binary::Gt => "sgt",
+                    Binary::Ge => "sge",
+                    Binary::Eq => "eq",
+                    _ => "ne",
+                };
+                self.op(&format!("icmp {cond} i32 {a}, {b}"));
+                format!("zext i1 {} to i32", self.cur)
+            }
+        };
+        self.op(&rhs);
+    }
+
+    fn float(&mut self, v: f64) {
+        self.cur = double(v);
+    }
+
+    fn to_float(&mut self) {
+        let a = self.cur.clone();
+        self.op(&format!("sitofp i32 {a} to double"));
+    }
+
+    fn float_unary(&mut self, op: Unary) {
+        let a = self.cur.clone();
+        self.op(&match op {
+            Unary::Neg => format!("fneg double {a}"),
+            _ => format!("call double @llvm.fabs.f64(double {a})"),
+        });
+    }
+
+    fn float_binary(&mut self, op: Binary) {
+        let (a, b) = (self.stack.pop().unwrap(), self.cur.clone());
+        let call = |f: &str| format!("call double @{f}(double {a}, double {b})");
+        let rhs = match op {
+            Binary::Add => format!("fadd double {a}, {b}"),
+            Binary::Sub => format!("fsub double {a}, {b}"),
+            Binary::Mul => format!("fmul double {a}, {b}"),
+            Binary::Div => format!("fdiv double {a}, {b}"),
+            Binary::Pow => call("pow"),
+            Binary::Min => call("llvm.minnum.f64"),
+            Binary::Max => call("llvm.maxnum.f64"),
+            // Ordered comparisons are false with a NaN, and `une` true.
+            _ => {
+                let cond = match op {
+                    Binary::Lt => "olt",
+                    Binary::Le => "ole",
+                    Binary::Gt => "ogt",
+                    Binary::Ge => "oge",
+                    Binary::Eq => "oeq",
+                    _ => "une",
+                };
+                self.op(&format!("fcmp {cond} double {a}, {b}"));
+                format!("zext i1 {} to i32", self.cur)
+            }
+        };
+        self.op(&rhs);
+    }
+
+    fn call(&mut self, name: &str, argc: usize) {
+        let args: Vec<String> = self.stack.split_off(self.stack.len() - argc).into_iter().map(|a| format!("i32 {a}")).collect();
+        self.op(&format!("call i32 {}({})", global("fn", name), args.join(", ")));
+    }
+
+    fn begin_if(&mut self, value: Option<Ty>) {
+        self.labels += 1;
+        let l = self.labels;
+        self.ifs.push(If { label: l, value, then: None });
+        self.branch(&format!("then.{l}"), &format!("else.{l}"));
+        self.label(format!("then.{l}"));
+    }
+
+    fn begin_else(&mut self) {
+        let (cur, block) = (self.cur.clone(), self.block.clone());
+        let i = self.ifs.last_mut().unwrap();
+        let l = i.label;
+        if i.value.is_some() {
+            i.then = Some((cur, block));
+        }
+        self.emit(&format!("br label %end.{l}"));
+        self.label(format!("else.{l}"));
+    }
+
+    fn end_if(&mut self) {
+        let i = self.ifs.pop().unwrap();
+        let (els, els_block) = (self.cur.clone(), self.block.clone());
+        self.emit(&format!("br label %end.{}", i.label));
+        self.label(format!("end.{}", i.label));
+        if let (Some(ty), Some((then, then_block))) = (i.value, i.then) {
+            self.op(&format!("phi {} [{then}, %{then_block}], [{els}, %{els_block}]", ir(ty)));
+        }
+    }
+
+    fn begin_loop(&mut self) {
+        self.labels += 1;
+        self.loops.push(self.labels);
+        self.emit(&format!("br label %loop.{}", self.labels));
+        self.label(format!("loop.{}", self.labels));
+    }
+
+    fn loop_test(&mut self) {
+        let l = *self.loops.last().unwrap();
+        self.branch(&format!("body.{l}"), &format!("done.{l}"));
+        self.label(format!("body.{l}"));
+    }
+
+    fn end_loop(&mut self) {
+        let l = self.loops.pop().unwrap();
+        self.emit(&format!("br label %loop.{l}"));
+        self.label(format!("done.{l}"));
+    }
+
+    // The current value is simply forgotten.
+    fn discard(&mut self) {}
+
+    fn result(&mut self, ty: Ty) {
+        debug_assert!(self.stack.is_empty());
+        let cur = self.cur.clone();
+        if ty == Ty::Float {
+            self.emit(&format!("call void @stoncc_print_f64(double {cur})"));
+            return;
+        }
+        self.op(&format!("call i32 (i8*, ...) @printf(i8* getelementptr ([4 x i8], [4 x i8]* @fmt, i64 0, i64 0), i32 {cur})"));
+    }
+
+    fn tally(&self) -> Tally {
+        self.tally
+    }
+
+    fn finish(self, globals: &BTreeMap<String, Value>) -> String {
+        let mut out = String::from("@fmt = private constant [4 x i8] c\"%d\\0A\\00\"\n@fmt.g = private constant [5 x i8] c\"%.*g\\00\"\n");
+        for (name, v) in globals {
+            let v = match *v {
+                Value::Float(v) => format!("double {}", double(v)),
+                v => format!("i32 {v}"),
+            };
+            let _ = writeln!(out, "{} = internal global {v}", global("var", name));
+        }
+        out.push('\n');
+        out += &self.out;
+        out += HELPERS.trim_start();
+        out
+    }
+}
+
+#[test]
+fn tests() {
+    let compile = |s: &str| {
+        let program = stoncc::program(s.as_bytes()).0.unwrap();
+        crate::codegen::compile(Llvm::default(), &program, &stoncc::Env::new()).unwrap()
+    };
+
+    let ll = compile("x = 2 + 3\nx * -4");
+    let (built_by, ll) = ll.split_once('\n').unwrap();
+    assert_eq!(built_by, format!("; {}", crate::provenance::describe()));
+    assert!(ll.starts_with("@fmt = private constant [4 x i8] c\"%d\\0A\\00\"\n@fmt.g = private constant [5 x i8] c\"%.*g\\00\"\n@\"var.x\" = internal global i32 0\n"), "{ll}");
+    assert!(ll.contains("  %t1 = add i32 2, 3\n  store i32 %t1, i32* @\"var.x\"\n  %t2 = call i32 (i8*, ...) @printf(i8* getelementptr ([4 x i8], [4 x i8]* @fmt, i64 0, i64 0), i32 %t1)\n"), "{ll}");
+    assert!(ll.contains("  %t3 = load i32, i32* @\"var.x\"\n  %t4 = sub i32 0, 4\n  %t5 = mul i32 %t3, %t4\n"), "{ll}");
+    assert!(ll.contains("  ret i32 0\n}\n"), "{ll}");
+
+    // Values of conditionals meet in a phi, from whichever block each
+    // branch ended in.
+    let ll = compile("1 ? 2 : (0 || 5)");
+    assert!(ll.contains("  br label %end.1\nelse.1:\n  %t2 = icmp ne i32 0, 0\n  br i1 %t2, label %then.2, label %else.2\n"), "{ll}");
+    assert!(ll.contains("end.2:\n  %t5 = phi i32 [1, %then.2], [%t4, %else.2]\n  br label %end.1\nend.1:\n  %t6 = phi i32 [2, %then.1], [%t5, %end.2]\n"), "{ll}");
+
+    let ll = compile("i = 0; while (i < 3) i = i + 1");
+    assert!(ll.contains("  br label %loop.1\nloop.1:\n  %t2 = load i32, i32* @\"var.i\"\n  %t3 = icmp slt i32 %t2, 3\n  %t4 = zext i1 %t3 to i32\n"), "{ll}");
+    assert!(ll.contains("  br i1 %t5, label %body.1, label %done.1\nbody.1:\n"), "{ll}");
+    assert!(ll.contains("  br label %loop.1\ndone.1:\n  ret i32 0\n"), "{ll}");
+
+    let ll = compile("popcount(clz(ctz(rotl(rotr(1, 2), 3))))");
+    assert!(ll.contains("  %t1 = call i32 @llvm.fshr.i32(i32 1, i32 1, i32 2)\n  %t2 = call i32 @llvm.fshl.i32(i32 %t1, i32 %t1, i32 3)\n"), "{ll}");
+    assert!(ll.contains("  %t3 = call i32 @llvm.cttz.i32(i32 %t2, i1 false)\n  %t4 = call i32 @llvm.ctlz.i32(i32 %t3, i1 false)\n  %t5 = call i32 @llvm.ctpop.i32(i32 %t4)\n"), "{ll}");
+
+    let ll = compile("7 % 2 << 40 >> 1");
+    assert!(ll.contains("  %t1 = call i32 @stoncc_rem(i32 7, i32 2)\n"), "{ll}");
+    assert!(ll.contains("  %t2 = shl i32 %t1, 40\n  %t3 = icmp ult i32 40, 32\n  %t4 = select i1 %t3, i32 %t2, i32 0\n"), "{ll}");
+    assert!(ll.contains("  %t5 = call i32 @llvm.umin.i32(i32 1, i32 31)\n  %t6 = ashr i32 %t4, %t5\n"), "{ll}");
+
+    // Negative powers are 0 unless the base is 1 or -1, and 0 has none.
+    let ll = compile("2 ^ -1");
+    assert!(ll.contains("  %t2 = call i32 @stoncc_pow(i32 2, i32 %t1)\n"), "{ll}");
+    assert!(ll.contains("inverse:\n  ; Truncated like 1 / b^-e: 0 unless b is 1 or -1.\n  call void @stoncc_check_div(i32 %b)\n"), "{ll}");
+
+    let ll = compile("f(a, b) = max(a, b) - b; f(1, 2)");
+    assert!(ll.contains("define internal i32 @\"fn.f\"(i32 %a0, i32 %a1) {\nentry:\n"), "{ll}");
+    assert!(ll.contains("  %t3 = call i32 @llvm.smax.i32(i32 %a0, i32 %a1)\n  %t4 = sub i32 %t3, %a1\n  ret i32 %t4\n"), "{ll}");
+    assert!(ll.contains("  %t1 = call i32 @\"fn.f\"(i32 1, i32 2)\n"), "{ll}");
+
+    // Integers convert where they meet a float, and a variable given a
+    // float anywhere is one everywhere.
+    let ll = compile("x = 2\nx = x / 4.0\nmax(3, 1.5) ^ -x < 1");
+    assert!(ll.contains("@\"var.x\" = internal global double 0x0000000000000000\n"), "{ll}");
+    assert!(ll.contains("  %t1 = sitofp i32 2 to double\n  store double %t1, double* @\"var.x\"\n  call void @stoncc_print_f64(double %t1)\n"), "{ll}");
+    assert!(ll.contains("  %t2 = load double, double* @\"var.x\"\n  %t3 = fdiv double %t2, 0x4010000000000000\n"), "{ll}");
+    assert!(ll.contains("  %t4 = sitofp i32 3 to double\n  %t5 = call double @llvm.maxnum.f64(double %t4, double 0x3FF8000000000000)\n"), "{ll}");
+    assert!(ll.contains("  %t7 = fneg double %t6\n  %t8 = call double @pow(double %t5, double %t7)\n  %t9 = sitofp i32 1 to double\n"), "{ll}");
+    assert!(ll.contains("  %t10 = fcmp olt double %t8, %t9\n  %t11 = zext i1 %t10 to i32\n"), "{ll}");
+    assert!(ll.contains("declare double @pow(double, double)\n"), "{ll}");
+
+    let ll = compile("y = 0.5 ? 1 : 2.5");
+    assert!(ll.contains("  %t1 = fcmp une double 0x3FE0000000000000, 0x0000000000000000\n"), "{ll}");
+    assert!(ll.contains("  %t4 = sitofp i32 1 to double\n  br label %end.1\n"), "{ll}");
+    assert!(ll.contains("  %t5 = phi double [%t4, %then.1], [0x4004000000000000, %else.1]\n"), "{ll}");
+
+    assert_eq!(global("var", "a\"b\n"), "@\"var.a\\22b\\0A\"");
+}
@@ -0,0 +1,462 @@
+//! `stoncc --lsp`: a Language Server Protocol server on standard input and
+//! output, so that editors can check stoncc programs as they are typed. It
+//! publishes the parser's and the checker's diagnostics for each open
+//! document, shows the value of the expression under the cursor on hover,
+//! as far as what is defined before it lets it be computed, together with
+//! the documentation of the operator or builtin function there, and formats
+//! documents as `--format` does.
+//!
+//! Documents are synced whole on every change; they are small.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use stoncc::json::{self, Json};
+use stoncc::limits::{EvalLimits, Meter};
+use stoncc::operators::OperatorTable;
+use stoncc::parser::{self, Extensions};
+use stoncc::{eval_with, sema, Builtins, Env, Function, Lexer, Program, Span, Stmt, Token};
+
+use crate::{doc, fail, Failure};
+
+/// The body of the next message, or `None` at the end of input. Headers
+/// other than `Content-Length` are ignored.
+fn read_message(r: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let Some(length) = length else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "message without a Content-Length"));
+    };
+    let mut body = vec![0; length];
+    r.read_exact(&mut body)?;
+    String::from_utf8(body).map(Some).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "message is not UTF-8"))
+}
+
+fn write_message(w: &mut impl Write, body: &str) -> io::Result<()> {
+    write!(w, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    w.flush()
+}
+
+/// Converts between byte offsets in a document and LSP positions, which
+/// count lines from 0 and characters in UTF-16 code units.
+struct Lines<'a> {
+    text: &'a str,
+    /// The offset each line starts at.
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str) -> Self {
+        let starts = std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        Lines { text, starts }
+    }
+
+    fn position(&self, offset: usize) -> String {
+        let offset = offset.min(self.text.len());
+        let line = self.starts.partition_point(|&s| s <= offset) - 1;
+        let before = self.text.get(self.starts[line]..offset).unwrap_or("");
+        format!("{{\"line\": {line}, \"character\": {}}}", before.encode_utf16().count())
+    }
+
+    fn range(&self, span: Span) -> String {
+        format!("{{\"start\": {}, \"end\": {}}}", self.position(span.start), self.position(span.end))
+    }
+
+    /// The offset of character `character` of line `line`, or the end of
+    /// the line or of the document if they are shorter.
+    fn offset(&self, line: usize, character: usize) -> usize {
+        let Some(&start) = self.starts.get(line) else { return self.text.len() };
+        let end = self.starts.get(line + 1).map_or(self.text.len(), |&e| e - 1);
+        let mut units = 0;
+        for (i, c) in self.text[start..end].char_indices() {
+            if units >= character {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+        end
+    }
+}
+
+fn parse(text: &str, ext: Extensions) -> (Program, Vec<stoncc::ParseError>) {
+    parser::program_recovering(text.as_bytes(), ext, &OperatorTable::default())
+}
+
+/// The diagnostics of `text` as a JSON array. Syntax errors are errors.
+/// What the checker finds is a warning, as the editor can't know which
+/// variables will be bound when the program runs.
+fn diagnostics(text: &str, ext: Extensions) -> String {
+    let lines = Lines::new(text);
+    let diagnostic = |span, severity, code, message: &str| {
+        format!(
+            "{{\"range\": {}, \"severity\": {severity}, \"code\": {}, \"source\": \"stoncc\", \"message\": {}}}",
+            lines.range(span),
+            json::string(code),
+            json::string(message)
+        )
+    };
+
+    let (program, errors) = parse(text, ext);
+    let mut out: Vec<String> = errors.iter().map(|e| diagnostic(e.span, 1, e.kind.code(), &e.kind.to_string())).collect();
+    for e in sema::check(&program.stmts, &Env::new(), &Builtins::default()) {
+        let span = e.span().unwrap_or_default();
+        out.push(diagnostic(span, 2, e.code(), &e.to_string()));
+    }
+    json::array(out)
+}
+
+/// The span and value of the innermost expression over `at` that can be
+/// evaluated, if any, as Markdown. Variables and functions that the
+/// statements before it define are known, as far as they evaluate, so
+/// hovers work around syntax errors elsewhere in the document. An
+/// expression that fails shows its error.
+fn value(text: &str, ext: Extensions, at: usize) -> Option<(Span, String)> {
+    let (program, _) = parse(text, ext);
+    let mut env = Env::new();
+    let mut builtins = Builtins::default();
+    let mut meter = Meter::new(EvalLimits::SANDBOX);
+    let mut root = None;
+    for stmt in &program.stmts {
+        if let Some(e) = stmt.exprs().into_iter().find(|e| e.span().start <= at && at < e.span().end) {
+            root = Some(e);
+            break;
+        }
+        match stmt {
+            Stmt::Assign { name, value, .. } => {
+                if let Ok(v) = eval_with(value, &env, &builtins, &mut meter) {
+                    env.insert(name.clone(), v);
+                }
+            }
+            Stmt::Def { name, params, body, .. } => builtins.define(name, Function { params: params.clone(), body: body.clone() }),
+            _ => {}
+        }
+    }
+
+    // Inner subtrees come first.
+    let values = root?.subtree_values(&env, &builtins, &mut meter);
+    let (span, value) = values.into_iter().find(|(span, _)| span.start <= at && at < span.end)?;
+    let source = text.get(span.start..span.end).unwrap_or("");
+    let value = match value {
+        Ok(v) => format!("= {v}"),
+        Err(e) => format!("error: {e}"),
+    };
+    Some((span, format!("```\n{source}\n```\n{value}")))
+}
+
+/// The span of the token over `at` and, if it is an operator or the name
+/// of a builtin function, its documentation as `stoncc doc` gives it.
+fn documentation(text: &str, ext: Extensions, at: usize) -> Option<(Span, String)> {
+    let mut lexer = Lexer::new(text.as_bytes()).with_wide_ints(ext.wide_ints).with_words(ext.words).with_decimal_comma(ext.decimal_comma);
+    loop {
+        let token = lexer.next().ok()?;
+        let span = lexer.span();
+        if token == Token::Eof || span.start > at {
+            return None;
+        }
+        if at < span.end {
+            let doc = doc::lookup(text.get(span.start..span.end)?)?;
+            return Some((span, format!("```\n{}\n```", doc.trim_end())));
+        }
+    }
+}
+
+/// What hovering over `at` shows: the value of the expression there, and
+/// the documentation of the operator or function under the cursor.
+fn hover(text: &str, ext: Extensions, at: usize) -> Option<(Span, String)> {
+    match (value(text, ext, at), documentation(text, ext, at)) {
+        (Some((span, value)), Some((_, doc))) => Some((span, format!("{value}\n\n---\n\n{doc}"))),
+        (value, doc) => value.or(doc),
+    }
+}
+
+/// `text` formatted as by `--format`, unless it doesn't parse.
+fn format(text: &str, ext: Extensions) -> Result<String, String> {
+    match parse(text, ext) {
+        (program, errors) if errors.is_empty() => Ok(program.to_infix()),
+        (_, errors) => Err(format!("cannot format a document with syntax errors ({})", errors[0].kind)),
+    }
+}
+
+/// A request id, as it is sent back.
+fn id(v: &Json) -> String {
+    match v {
+        Json::Number(n) => n.clone(),
+        Json::String(s) => json::string(s),
+        _ => "null".to_string(),
+    }
+}
+
+fn number(v: Option<&Json>) -> Option<usize> {
+    match v? {
+        Json::Number(n) => n.parse().ok(),
+        _ => None,
+    }
+}
+
+fn string(v: Option<&Json>) -> Option<&str> {
+    match v? {
+        Json::String(s) => Some(s),
+        _ => None,
+    }
+}
+
+/// The state of a session with one editor.
+pub struct Server {
+    ext: Extensions,
+    /// The text of each open document, by URI.
+    docs: HashMap<String, String>,
+    shutdown: bool,
+}
+
+/// What the server does after a message.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    /// Sends these messages, which may be none.
+    Send(Vec<String>),
+    /// Stops, successfully if the editor asked to shut down first.
+    Exit(bool),
+}
+
+impl Server {
+    pub fn new(ext: Extensions) -> Self {
+        Server { ext, docs: HashMap::new(), shutdown: false }
+    }
+
+    fn publish(&self, uri: &str) -> String {
+        let diagnostics = self.docs.get(uri).map_or_else(|| "[]".to_string(), |text| diagnostics(text, self.ext));
+        format!(
+            "{{\"jsonrpc\": \"2.0\", \"method\": \"textDocument/publishDiagnostics\", \"params\": {{\"uri\": {}, \"diagnostics\": {diagnostics}}}}}",
+            json::string(uri)
+        )
+    }
+
+    /// The result of the request `method`, as JSON, or an error code and
+    /// message. `None` if the method is a notification.
+    fn request(&mut self, method: &str, params: Option<&Json>) -> Option<Result<String, (i32, String)>> {
+        let uri = string(params.and_then(|p| p.get("textDocument")?.get("uri")));
+        let doc = uri.and_then(|uri| self.docs.get(uri));
+        Some(match method {
+            "initialize" => Ok(format!(
+                concat!(
+                    "{{\"capabilities\": {{\"textDocumentSync\": 1, \"hoverProvider\": true, \"documentFormattingProvider\": true}}, ",
+                    "\"serverInfo\": {{\"name\": \"stoncc\", \"version\": \"{}\"}}}}"
+                ),
+                env!("CARGO_PKG_VERSION")
+            )),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok("null".to_string())
+            }
+            "textDocument/hover" => {
+                let Some(text) = doc else { return Some(Ok("null".to_string())) };
+                let position = params.and_then(|p| p.get("position"));
+                let line = number(position.and_then(|p| p.get("line"))).unwrap_or(0);
+                let character = number(position.and_then(|p| p.get("character"))).unwrap_or(0);
+                let lines = Lines::new(text);
+                Ok(match hover(text, self.ext, lines.offset(line, character)) {
+                    Some((span, value)) => format!(
+                        "{{\"contents\": {{\"kind\": \"markdown\", \"value\": {}}}, \"range\": {}}}",
+                        json::string(&value),
+                        lines.range(span)
+                    ),
+                    None => "null".to_string(),
+                })
+            }
+            "textDocument/formatting" => {
+                let Some(text) = doc else { return Some(Ok("[]".to_string())) };
+                let lines = Lines::new(text);
+                format(text, self.ext).map(|formatted| {
+                    let all = lines.range(Span { start: 0, end: text.len() });
+                    format!("[{{\"range\": {all}, \"newText\": {}}}]", json::string(&formatted))
+                })
+                // RequestFailed.
+                .map_err(|e| (-32803, e))
+            }
+            _ => return None,
+        })
+    }
+
+    /// Handles the message `body`.
+    pub fn handle(&mut self, body: &str) -> Reply {
+        let msg = match json::parse(body) {
+            Ok(msg) => msg,
+            Err(e) => {
+                let error = format!("{{\"code\": -32700, \"message\": {}}}", json::string(&e));
+                return Reply::Send(vec![format!("{{\"jsonrpc\": \"2.0\", \"id\": null, \"error\": {error}}}")]);
+            }
+        };
+        let method = string(msg.get("method")).unwrap_or("");
+        let params = msg.get("params");
+
+        if let Some(id) = msg.get("id") {
+            let reply = match self.request(method, params) {
+                Some(Ok(result)) => format!("\"result\": {result}"),
+                Some(Err((code, message))) => format!("\"error\": {{\"code\": {code}, \"message\": {}}}", json::string(&message)),
+                None => format!("\"error\": {{\"code\": -32601, \"message\": {}}}", json::string(&format!("unknown method {method}"))),
+            };
+            return Reply::Send(vec![format!("{{\"jsonrpc\": \"2.0\", \"id\": {}, {reply}}}", self::id(id))]);
+        }
+
+        let doc = params.and_then(|p| p.get("textDocument"));
+        let Some(uri) = string(doc.and_then(|d| d.get("uri"))).map(str::to_string) else {
+            return match method {
+                "exit" => Reply::Exit(self.shutdown),
+                _ => Reply::Send(Vec::new()),
+            };
+        };
+        match method {
+            "textDocument/didOpen" => {
+                let text = string(doc.and_then(|d| d.get("text"))).unwrap_or("");
+                self.docs.insert(uri.clone(), text.to_string());
+            }
+            // With full sync, the last change is the whole document.
+            "textDocument/didChange" => match params.and_then(|p| p.get("contentChanges")) {
+                Some(Json::Array(changes)) => {
+                    if let Some(text) = string(changes.last().and_then(|c| c.get("text"))) {
+                        self.docs.insert(uri.clone(), text.to_string());
+                    }
+                }
+                _ => return Reply::Send(Vec::new()),
+            },
+            "textDocument/didClose" => {
+                self.docs.remove(&uri);
+            }
+            _ => return Reply::Send(Vec::new()),
+        }
+        Reply::Send(vec![self.publish(&uri)])
+    }
+}
+
+/// `stoncc --lsp [--calc] [--durations] [--bytes] [--units] [--implicit-mul] [--words] [--decimal-comma]`.
+pub fn main(args: &[String]) {
+    let mut ext = Extensions::default();
+    for arg in args {
+        match arg.as_str() {
+            "--calc" => ext.calculator = true,
+            "--durations" => ext.durations = true,
+            "--units" => ext.units = true,
+            "--bytes" => ext.bytes = true,
+            "--implicit-mul" => ext.implicit_mul = true,
+            "--words" => ext.words = true,
+            "--decimal-comma" => ext.decimal_comma = true,
+            _ => {
+                eprintln!("usage: stoncc --lsp [--calc] [--durations] [--bytes] [--units] [--implicit-mul] [--words] [--decimal-comma]");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let mut server = Server::new(ext);
+    let (stdin, stdout) = (io::stdin(), io::stdout());
+    let (mut input, mut output) = (stdin.lock(), stdout.lock());
+    loop {
+        let body = match read_message(&mut input) {
+            Ok(Some(body)) => body,
+            Ok(None) => std::process::exit(1),
+            Err(e) => fail(Failure::Run, e),
+        };
+        match server.handle(&body) {
+            Reply::Send(messages) => {
+                for m in messages {
+                    write_message(&mut output, &m).unwrap_or_else(|e| fail(Failure::Run, e));
+                }
+            }
+            Reply::Exit(clean) => std::process::exit(if clean { 0 } else { 1 }),
+        }
+    }
+}
+
+#[test]
+fn tests() {
+    let lines = Lines::new("a = 1\n\u{20ac}\u{1f600} + x\n");
+    assert_eq!(lines.position(9), "{\"line\": 1, \"character\": 1}");
+    assert_eq!(lines.position(13), "{\"line\": 1, \"character\": 3}");
+    assert_eq!((lines.offset(1, 3), lines.offset(1, 99), lines.offset(9, 0)), (13, 17, 18));
+
+    let mut server = Server::new(Extensions::default());
+    let send = |server: &mut Server, body: &str| match server.handle(body) {
+        Reply::Send(messages) => messages,
+        exit => panic!("{exit:?}"),
+    };
+    let init = send(&mut server, r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"capabilities": {"x": true}, "rootUri": null}}"#);
+    assert!(init[0].starts_with(r#"{"jsonrpc": "2.0", "id": 1, "result": {"capabilities": {"textDocumentSync": 1, "hoverProvider": true"#));
+    assert!(send(&mut server, r#"{"jsonrpc": "2.0", "method": "initialized", "params": {}}"#).is_empty());
+
+    // Every syntax error is published, and the checker's findings as
+    // warnings.
+    let open = r#"{"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {"textDocument": {"uri": "file:///a.st", "text": "a = 1 )\nb = * 2\nc = y + 2 * 3"}}}"#;
+    let published = send(&mut server, open);
+    let published = json::parse(&published[0]).unwrap();
+    let Some(Json::Array(diagnostics)) = published.get("params").and_then(|p| p.get("diagnostics")) else { panic!() };
+    let codes: Vec<_> = diagnostics.iter().map(|d| (string(d.get("code")).unwrap(), number(d.get("severity")).unwrap())).collect();
+    assert_eq!(codes, [("unexpected-token", 1), ("unexpected-token", 1), ("unbound-symbol", 2)]);
+    let start = diagnostics[1].get("range").and_then(|r| r.get("start")).unwrap();
+    assert_eq!((number(start.get("line")), number(start.get("character"))), (Some(1), Some(4)));
+
+    // Hovering shows the value of the constant expression there.
+    let hover = |server: &mut Server, line: usize, character: usize| {
+        let body = format!(
+            r#"{{"jsonrpc": "2.0", "id": "h", "method": "textDocument/hover", "params": {{"textDocument": {{"uri": "file:///a.st"}}, "position": {{"line": {line}, "character": {character}}}}}}}"#
+        );
+        send(server, &body).remove(0)
+    };
+    assert!(hover(&mut server, 2, 9).contains(r#""value": "```\n2 * 3\n```\n= 6"}, "range": {"start": {"line": 2, "character": 8}"#));
+    assert!(hover(&mut server, 2, 4).ends_with(r#""id": "h", "result": null}"#));
+
+    let change = r#"{"jsonrpc": "2.0", "method": "textDocument/didChange", "params": {"textDocument": {"uri": "file:///a.st", "version": 2}, "contentChanges": [{"text": "x=sqrt(16)+(1/0)\nf(a)=a*2"}]}}"#;
+    assert!(send(&mut server, change)[0].ends_with(r#""diagnostics": []}}"#));
+    assert!(hover(&mut server, 0, 4).contains("= 4.0"));
+    assert!(hover(&mut server, 0, 13).contains("error: division by zero"));
+    // Over an operator or a builtin function, its documentation follows.
+    let sqrt = hover(&mut server, 0, 3);
+    assert!(sqrt.contains(r#""value": "```\nsqrt(16)\n```\n= 4.0\n\n---\n\n```\nsqrt(a)  1 argument\n  Square root, as a float."#), "{sqrt}");
+    let slash = hover(&mut server, 0, 13);
+    assert!(slash.contains(r#"error: division by zero\n\n---\n\n```\n/  infix, precedence"#), "{slash}");
+    assert!(!hover(&mut server, 0, 0).contains("---"));
+
+    // Hovers know what the statements before them define, even with
+    // syntax errors around.
+    send(&mut server, &change.replace("x=sqrt(16)+(1/0)\\nf(a)=a*2", "n = 3\\nsq(a) = a * a\\ny = * 2\\nsq(n) + z\\nn +"));
+    assert!(hover(&mut server, 3, 1).contains(r#""value": "```\nsq(n)\n```\n= 9"}"#));
+    assert!(hover(&mut server, 3, 3).contains(r#""value": "```\nn\n```\n= 3"}"#));
+    assert!(hover(&mut server, 3, 7).ends_with(r#""result": null}"#));
+    send(&mut server, change);
+
+    // Formatting replaces the whole document, unless it doesn't parse.
+    let formatting = r#"{"jsonrpc": "2.0", "id": 7, "method": "textDocument/formatting", "params": {"textDocument": {"uri": "file:///a.st"}, "options": {"tabSize": 4, "insertSpaces": true}}}"#;
+    let edits = send(&mut server, formatting).remove(0);
+    assert!(edits.contains(r#""newText": "x = sqrt(16) + 1 / 0\nf(a) = a * 2\n"}"#), "{edits}");
+    assert!(edits.contains(r#""end": {"line": 1, "character": 8}"#));
+    send(&mut server, &change.replace("x=sqrt", "x=*sqrt"));
+    assert!(send(&mut server, formatting)[0].contains(r#""error": {"code": -32803"#));
+
+    assert!(send(&mut server, r#"{"jsonrpc": "2.0", "id": 8, "method": "workspace/symbol", "params": {}}"#)[0].contains("-32601"));
+    let closed = send(&mut server, r#"{"jsonrpc": "2.0", "method": "textDocument/didClose", "params": {"textDocument": {"uri": "file:///a.st"}}}"#);
+    assert!(closed[0].ends_with(r#""diagnostics": []}}"#));
+    assert_eq!(server.handle(r#"{"jsonrpc": "2.0", "method": "exit"}"#), Reply::Exit(false));
+    send(&mut server, r#"{"jsonrpc": "2.0", "id": 9, "method": "shutdown"}"#);
+    assert_eq!(server.handle(r#"{"jsonrpc": "2.0", "method": "exit"}"#), Reply::Exit(true));
+
+    let mut input = &b"Content-Length: 2\r\nContent-Type: x\r\n\r\n{}Content-Length: 3\r\n\r\n[1]"[..];
+    assert_eq!(read_message(&mut input).unwrap().as_deref(), Some("{}"));
+    assert_eq!(read_message(&mut input).unwrap().as_deref(), Some("[1]"));
+    assert_eq!(read_message(&mut input).unwrap(), None);
+    let mut out = Vec::new();
+    write_message(&mut out, "{}").unwrap();
+    assert_eq!(out, b"Content-Length: 2\r\n\r\n{}");
+}
@@ -1,44 +1,1382 @@
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 
-mod lexer;
-mod parser;
+mod bench;
+mod cache;
+mod capabilities;
+mod check;
+mod cli;
+mod codegen;
+mod config;
+mod conformance;
+mod corpus;
+mod coverage;
+mod deps;
+mod egraph;
+mod diagnostic;
+mod driver;
+mod doc;
+mod explain;
+mod generator;
+mod glob;
+mod input;
+mod interrupt;
+mod literal;
+mod lsp;
+mod llvm;
+mod map;
+mod matrix;
+mod migrate;
+mod pattern;
+mod plugin;
+mod provenance;
+mod quiz;
+mod repl;
+mod rules;
+mod satisfies;
+mod shake;
+mod solve;
+mod stdlib;
+mod strict;
+mod template;
+mod theme;
+mod timeout;
+mod tokens;
+mod transcript;
+mod truth;
+#[cfg(feature = "wasm")]
+mod wat;
+mod x86;
 
-use parser::*;
+#[cfg(feature = "decimal")]
+use stoncc::decimal;
+use stoncc::limits::{EvalLimits, Meter};
+use stoncc::passes::PassManager;
+use stoncc::*;
 
-fn eval(ast: &Node) -> i32 {
-    match ast {
-        Node::Node { v, children } => {
-            let args: Vec<i32> = children.iter().map(|i| eval(i)).collect();
-            v.apply(&args)
+/// Codes of the errors that can stop a run, as `--expect-error` takes
+/// them.
+const ERROR_CODES: &[&str] = &[
+    "unexpected-char",
+    "integer-overflow",
+    "read-error",
+    "unterminated-comment",
+    "invalid-utf8",
+    "unexpected-token",
+    "split-operator",
+    "adjacent-operands",
+    "invalid-assignment",
+    "duplicate-param",
+    "chained-comparison",
+    "too-deep",
+    "unbound-symbol",
+    "unfilled-placeholder",
+    "unknown-function",
+    "bad-arguments",
+    "not-an-integer",
+    "used-before-assignment",
+    "called-before-definition",
+    "redefined",
+    "recursion",
+    "max-depth",
+    "max-ops",
+    "max-value-bits",
+    "max-millis",
+    "arithmetic-overflow",
+    "division-by-zero",
+    "negative-factorial",
+    "negative-shift",
+    "duration-operand",
+    "unit-mismatch",
+    "unsupported",
+    "timeout",
+];
+
+/// How a run must end for the process to succeed (`--expect=VALUE`,
+/// `--expect-error=CODE`).
+#[derive(Clone)]
+enum Expect {
+    Value(String),
+    Error(String),
+}
+
+/// What stopped a run, which its exit status tells.
+#[derive(Clone, Copy)]
+pub(crate) enum Failure {
+    /// A mistake in how stoncc was invoked: an unknown option, a bad
+    /// value for one, or options that don't go together.
+    Usage = 2,
+    /// Anything else: a file that can't be read or written, or input that
+    /// makes no sense.
+    Run = 1,
+}
+
+/// Ends the run with `error: MESSAGE`, and the status of `failure`.
+pub(crate) fn fail(failure: Failure, message: impl fmt::Display) -> ! {
+    eprintln!("{}: {message}", theme::label("error"));
+    std::process::exit(failure as i32);
+}
+
+/// Exits with status 1 unless `outcome` is what `expect` asks for. The
+/// outcome is the printed value of the last statement, or the code of the
+/// error that stopped the run.
+fn conclude(expect: Option<&Expect>, outcome: Result<Option<String>, &str>) {
+    let met = match (expect, &outcome) {
+        (None, outcome) => outcome.is_ok(),
+        (Some(Expect::Value(v)), Ok(w)) => w.as_ref() == Some(v),
+        (Some(Expect::Error(c)), Err(code)) => c == code,
+        _ => false,
+    };
+    if met {
+        return;
+    }
+
+    let got = match outcome {
+        Ok(v) => v.unwrap_or_else(|| "no value".to_string()),
+        Err(code) => format!("error {code}"),
+    };
+    match expect {
+        Some(Expect::Value(v)) => eprintln!("{}: expected {v}, got {got}", theme::label("error")),
+        Some(Expect::Error(c)) => eprintln!("{}: expected error {c}, got {got}", theme::label("error")),
+        None => {}
+    }
+    std::process::exit(1);
+}
+
+/// Appends the audit log entry of a run that started at `time` and took
+/// `duration` to the file at `path`, creating it if need be.
+fn audit(path: &str, time: SystemTime, duration: std::time::Duration, inputs: &[Input], vars: &Env, outcome: Result<Option<String>, (String, String)>) {
+    use std::io::Write;
+
+    let entry = audit::Entry {
+        time,
+        inputs: inputs.iter().map(|(name, s, _)| (name.clone(), &s[..])).collect(),
+        vars: sorted(vars).into_iter().map(|(k, v)| (k.clone(), v.to_string())).collect(),
+        outcome,
+        duration,
+    };
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path).unwrap_or_else(|e| fail(Failure::Run, format!("cannot open {path}: {e}")));
+    writeln!(file, "{}", entry.to_json()).unwrap_or_else(|e| fail(Failure::Run, format!("cannot write {path}: {e}")));
+}
+
+/// How much of a tree to print: all of it, or `depth` levels with deeper
+/// subtrees as `...` or, with `sizes`, as their number of nodes.
+#[derive(Clone, Copy, Default)]
+struct Elide {
+    depth: Option<usize>,
+    sizes: bool,
+}
+
+fn elided(tree: &impl fmt::Display, elide: Elide) -> String {
+    match elide.depth {
+        Some(depth) if elide.sizes => format!("{tree:#.depth$}"),
+        Some(depth) => format!("{tree:.depth$}"),
+        None => tree.to_string(),
+    }
+}
+
+/// Runs each top-level statement of each program in turn, printing its
+/// value. Later programs see the variables and functions of earlier ones.
+/// Returns the printed value of the last statement that has one. Trees
+/// print as `elide` says.
+fn run<V: Truth>(
+    programs: &[Program],
+    elide: Elide,
+    env: &mut Env<V>,
+    builtins: &mut Builtins,
+    mut eval: impl FnMut(&Node, &Env<V>, &Builtins) -> Result<V, EvalError>,
+    format: impl Fn(V) -> String,
+) -> Result<Option<String>, EvalError> {
+    let mut last = None;
+
+    for stmt in programs.iter().flat_map(|p| &p.stmts) {
+        match exec(stmt, env, builtins, &mut eval)? {
+            _ if matches!(stmt, Stmt::Def { .. }) => println!("Defining {}", elided(stmt, elide)),
+            Some(v) => {
+                let v = format(v);
+                println!("Evaluating {}: {v}", elided(stmt, elide));
+                last = Some(v);
+            }
+            None => {}
+        }
+    }
+    Ok(last)
+}
+
+/// Whether a tree reads any symbol.
+#[derive(Default)]
+struct Reads(bool);
+
+impl Visitor for Reads {
+    fn enter(&mut self, node: &Node) {
+        self.0 |= matches!(node, Node::Leaf(LeafVal::Sym(_), _));
+    }
+}
+
+/// `run` for `--symbolic`. An assignment or expression that needs a symbol
+/// nothing defines evaluates as far as it can instead of failing, and a
+/// variable assigned such an expression stands for it from then on. Other
+/// errors in it wait until its symbols are known, whichever operand would
+/// have failed first.
+fn run_symbolic(
+    programs: &[Program],
+    elide: Elide,
+    env: &mut Env,
+    meter: &mut Meter,
+    format: impl Fn(Value) -> String,
+) -> Result<Option<String>, EvalError> {
+    let mut builtins = Builtins::default();
+    let mut exprs = Env::new();
+    let mut last = None;
+
+    for stmt in programs.iter().flat_map(|p| &p.stmts) {
+        let (name, e) = match stmt {
+            Stmt::Assign { name, value, .. } => (Some(name), value),
+            Stmt::Expr(e) => (None, e),
+            _ => {
+                match exec(stmt, env, &mut builtins, &mut |ast, env, b| eval_with(ast, env, b, meter))? {
+                    _ if matches!(stmt, Stmt::Def { .. }) => println!("Defining {}", elided(stmt, elide)),
+                    Some(v) => {
+                        let v = format(v);
+                        println!("Evaluating {}: {v}", elided(stmt, elide));
+                        last = Some(v);
+                    }
+                    None => {}
+                }
+                continue;
+            }
+        };
+        let mut value = eval_with(e, env, &builtins, meter);
+        let v = match value {
+            Ok(_) => None,
+            Err(_) => {
+                // Variables standing for expressions may have become
+                // computable, so only symbols still in the result matter.
+                let partial = e.partial_eval(env, &exprs, &builtins, meter);
+                let mut reads = Reads::default();
+                partial.visit(&mut reads);
+                if reads.0 {
+                    Some(partial)
+                } else {
+                    value = eval_with(&partial, env, &builtins, meter);
+                    None
+                }
+            }
+        };
+        let v = match v {
+            Some(partial) => {
+                if let Some(name) = name {
+                    env.remove(name);
+                    exprs.insert(name.clone(), partial.clone());
+                }
+                partial.to_infix()
+            }
+            None => {
+                let v = value?;
+                if let Some(name) = name {
+                    env.insert(name.clone(), v);
+                    exprs.remove(name);
+                }
+                format(v)
+            }
+        };
+        println!("Evaluating {}: {v}", elided(stmt, elide));
+        last = Some(v);
+    }
+    Ok(last)
+}
+
+/// Prints the three-address code of each expression statement in `stmt`.
+fn print_ir(stmt: &Stmt) {
+    match stmt {
+        Stmt::Block { stmts, .. } => stmts.iter().for_each(print_ir),
+        Stmt::If { cond, then, els, .. } => {
+            println!("if {cond}:\n{}", ir::lower(cond));
+            print_ir(then);
+            els.iter().for_each(|s| print_ir(s));
+        }
+        Stmt::While { cond, body, .. } => {
+            println!("while {cond}:\n{}", ir::lower(cond));
+            print_ir(body);
+        }
+        Stmt::Assign { value: e, .. } | Stmt::Def { body: e, .. } | Stmt::Expr(e) => {
+            println!("{stmt}:\n{}", ir::lower(e));
+        }
+    }
+}
+
+/// The name of an input, its text, and the offset of its first byte among
+/// all the inputs of a run.
+type Input = (String, Vec<u8>, usize);
+
+/// Prints an error about `span`, an offset into whichever of `inputs` it
+/// falls in.
+fn report(inputs: &[Input], message: &str, span: Option<Span>) {
+    report_as("error", inputs, message, span)
+}
+
+/// `report` for a diagnostic of any severity.
+fn report_as(severity: &str, inputs: &[Input], message: &str, span: Option<Span>) {
+    match span {
+        Some(span) => {
+            let (name, s, base) = inputs.iter().rfind(|(_, _, base)| *base <= span.start).unwrap();
+            let span = Span { start: span.start - base, end: span.end - base };
+            eprintln!("{}", diagnostic::render(severity, message, name, s, span));
+        }
+        None => eprintln!("{}: {message}", theme::label(severity)),
+    }
+}
+
+/// Where a program comes from.
+enum Source<'a> {
+    /// A file, or standard input for `-`.
+    File(&'a str),
+    /// The text of `-e EXPR`, numbered if there are several.
+    Expr(&'a str, Option<usize>),
+    /// A token stream in JSON, from `--from=FILE`.
+    Tokens(&'a str),
+}
+
+impl Source<'_> {
+    /// How diagnostics refer to the source.
+    fn name(&self) -> String {
+        match self {
+            Source::File("-") => "<stdin>".to_string(),
+            Source::File(path) | Source::Tokens(path) => path.to_string(),
+            Source::Expr(_, None) => "<expr>".to_string(),
+            Source::Expr(_, Some(n)) => format!("<expr {n}>"),
+        }
+    }
+
+    /// The text of the source, and the tokens of a token stream, whose
+    /// text only stands in for the source they were lexed from.
+    fn read(&self, encoding: input::Encoding) -> (Vec<u8>, Option<Vec<lexer::Lexed>>) {
+        match self {
+            Source::File(path) => (input::read(path, encoding), None),
+            Source::Expr(e, _) => (e.as_bytes().to_vec(), None),
+            Source::Tokens(path) => {
+                let (tokens, s) = tokens::load(path);
+                (s, Some(tokens))
+            }
+        }
+    }
+}
+
+/// Parses a number the way it would be written in a program: a float if
+/// it has a fractional part or exponent, otherwise an integer.
+fn number(s: &str) -> Option<Value> {
+    let plain = if s.contains(['.', 'e', 'E']) {
+        s.parse().ok().filter(|v: &f64| v.is_finite()).map(Value::Float)
+    } else {
+        s.parse().ok().map(Value::Int)
+    };
+    plain.or_else(|| {
+        // `0x1f`, `1_000` and the like, after an optional minus.
+        let (minus, t) = s.strip_prefix('-').map_or(("", s), |t| ("-", t));
+        let literal = |kind| lexer::Token::from_kind(kind, t, Span::default());
+        match literal("int").or_else(|_| literal("decimal")).ok()? {
+            lexer::Token::Int(v) if minus.is_empty() => Some(Value::Int(v)),
+            lexer::Token::Int(v) => v.checked_neg().map(Value::Int),
+            lexer::Token::Decimal(d) => number(&format!("{minus}{d}")),
+            _ => None,
+        }
+    })
+}
+
+fn valid_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric())
+        && !lexer::KEYWORDS.contains(&name)
+}
+
+/// Defines a variable for every key of the JSON object in `path` (or
+/// standard input, for `-`). Values are numbers, or strings holding one.
+fn import_json(path: &str, env: &mut Env) {
+    let s = String::from_utf8(input::read(path, input::Encoding::Utf8)).unwrap();
+    let path = if path == "-" { "<stdin>" } else { path };
+
+    for (name, value) in json::parse_object(&s).unwrap_or_else(|e| fail(Failure::Run, format!("{path}: {e}"))) {
+        if !valid_name(&name) {
+            eprintln!("{}: {path}: '{name}' is not a valid variable name", theme::label("warning"));
+            continue;
         }
-        Node::Leaf(LeafVal::Int(v)) => {
-            *v
+
+        let (json::Scalar::Number(value) | json::Scalar::String(value)) = value;
+        match number(value.trim()) {
+            Some(v) => {
+                env.insert(name, v);
+            }
+            None => eprintln!("{}: {path}: {name}: '{value}' is not a number", theme::label("warning")),
         }
-        Node::Leaf(LeafVal::Sym(_)) => panic!("Cannot eval symbol"),
     }
 }
 
+/// The variables of `env` in name order, so that whatever is reported
+/// about them comes out the same on every run.
+fn sorted<V>(env: &Env<V>) -> Vec<(&String, &V)> {
+    let mut vars: Vec<_> = env.iter().collect();
+    vars.sort_by(|a, b| a.0.cmp(b.0));
+    vars
+}
+
+/// Defines a variable for every environment variable starting with
+/// `prefix`, named by the rest of its name (`STON_N=100` becomes `N`).
+fn import_env(prefix: &str, env: &mut Env) {
+    let mut vars: Vec<(String, String)> = env::vars().collect();
+    vars.sort();
+
+    for (key, value) in vars {
+        let Some(name) = key.strip_prefix(prefix) else { continue };
+
+        if !valid_name(name) {
+            eprintln!("{}: {key}: '{name}' is not a valid variable name", theme::label("warning"));
+            continue;
+        }
+
+        let value = value.trim();
+        match number(value) {
+            Some(v) => {
+                env.insert(name.to_string(), v);
+            }
+            None => eprintln!("{}: {key}: '{value}' is not a number", theme::label("warning")),
+        }
+    }
+}
+
+/// The stack the command runs on. Parsing and evaluation take none for
+/// the depth of a tree, but printing, optimizing and compiling walk it
+/// recursively, and a tree can be `MAX_DEPTH` deep.
+const STACK: usize = 1 << 30;
+
 fn main() {
-    let mut args = env::args();
+    // Only the part of the stack that is used is ever committed.
+    let command = std::thread::Builder::new().stack_size(STACK).spawn(command).unwrap_or_else(|e| fail(Failure::Run, format!("cannot start: {e}")));
+    // A panic is a bug in stoncc, which Rust has already reported.
+    if command.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+fn command() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    theme::init(&mut args);
 
-    if args.len() != 2 {
-        panic!(
-            "Exactly one argument is expected, {} were supplied.",
-            args.len() - 1
-        );
+    match args.first().map(String::as_str) {
+        Some("apply") => return map::apply_main(&args[1..]),
+        Some("bench") => return bench::main(&args[1..]),
+        Some("bool-min") => return truth::min_main(&args[1..]),
+        Some("build") => return driver::main(&args[1..]),
+        Some("check") => return check::main(&args[1..]),
+        Some("test-corpus") => return corpus::main(&args[1..]),
+        Some("conformance") => return conformance::main(&args[1..]),
+        Some("coverage") => return coverage::main(&args[1..]),
+        Some("gen") => return generator::main(&args[1..]),
+        Some("completions") => return cli::completions(&args[1..]),
+        Some("deps") => return deps::main(&args[1..]),
+        Some("doc") => return doc::main(&args[1..]),
+        Some("explain-parse") => return explain::main(&args[1..]),
+        Some("map") => return map::main(&args[1..]),
+        Some("match") => return pattern::main(&args[1..]),
+        Some("migrate-ast") => return migrate::main(&args[1..]),
+        Some("parse-matrix") => return matrix::main(&args[1..]),
+        Some("quiz") => return quiz::main(&args[1..]),
+        Some("repl") => return repl::main(&args[1..]),
+        Some("replay") => return transcript::main(&args[1..]),
+        Some("template") => return template::main(&args[1..]),
+        Some("satisfies") => return satisfies::main(&args[1..]),
+        Some("shake") => return shake::main(&args[1..]),
+        Some("solve-sym") => return solve::main(&args[1..]),
+        Some("truth-table") => return truth::main(&args[1..]),
+        Some("--capabilities") => return println!("{}", capabilities::document()),
+        Some("--build-info") => return print!("{}", provenance::report()),
+        Some("--lsp") => return lsp::main(&args[1..]),
+        Some(arg) if arg.starts_with("--gen=") => return generator::gen_flag(&arg["--gen=".len()..], &args[1..]),
+        Some("--help" | "-h") => return print!("{}", cli::help()),
+        _ => {}
     }
 
-    let path = args.nth(1).unwrap();
-    let mut file = File::open(path).unwrap();
-    let metadata = file.metadata().unwrap();
-    let mut s = Vec::<u8>::with_capacity(metadata.len() as usize);
+    if let Some(i) = args.iter().position(|a| a.starts_with("--record=")) {
+        if args.iter().any(|a| a == "--sandbox") {
+            fail(Failure::Usage, "--sandbox cannot be combined with --record, which writes a transcript to a file");
+        }
+        let path = args.remove(i)["--record=".len()..].to_string();
+        transcript::record(&path, &args);
+    }
 
-    file.read_to_end(&mut s).unwrap();
+    // The fast path prints as the defaults say, not as a config would.
+    let config = config::Config::load();
+    if let [e, src] = &args[..] {
+        if e == "-e" && config.arith.is_none() && config.out.is_none() {
+            if let Some(line) = literal::eval(src) {
+                return println!("{line}");
+            }
+        }
+    }
+    evaluate(options(&args, &config));
+}
+
+/// Everything the command line of a run says, with what the config files
+/// say where it says nothing.
+struct Options<'a> {
+    encoding: input::Encoding,
+    parse_only: bool,
+    resolve_names: bool,
+    trace: bool,
+    explain_result: bool,
+    visualize: bool,
+    pause: bool,
+    format: bool,
+    /// How `--rational` prints fractions: `exact` or `cfrac`.
+    fractions: Option<&'a str>,
+    dump_ast: Option<&'a str>,
+    elide: Elide,
+    diff: Option<&'a str>,
+    ext: Extensions,
+    out: format::Format,
+    decimal: Option<u32>,
+    big: bool,
+    rational: bool,
+    symbolic: bool,
+    arith: Option<ArithMode>,
+    int_width: Option<u32>,
+    vars: Env,
+    limits: EvalLimits,
+    rules: Vec<rules::Rule>,
+    egraph: Option<egraph::Cost>,
+    emit: Option<&'a str>,
+    report: bool,
+    check_ir: bool,
+    optimize: bool,
+    passes: Vec<&'a str>,
+    explain_opt: bool,
+    strict_fp: bool,
+    strict_tokens: bool,
+    expect: Option<Expect>,
+    timeout: Option<(&'a str, std::time::Duration)>,
+    use_cache: bool,
+    sandbox: bool,
+    plugins: bool,
+    /// An `--out` format that isn't built in, which a plugin registers.
+    plugin_out: Option<&'a str>,
+    no_stdlib: bool,
+    audit_log: Option<&'a str>,
+    env_file: Option<&'a str>,
+    sources: Vec<Source<'a>>,
+}
+
+/// Reads the options of a run from `args`, and checks that they go
+/// together.
+fn options<'a>(args: &'a [String], config: &config::Config) -> Options<'a> {
+    let mut o = Options {
+        encoding: config.encoding.unwrap_or_default(),
+        parse_only: false,
+        resolve_names: false,
+        trace: false,
+        explain_result: false,
+        visualize: false,
+        pause: false,
+        format: false,
+        fractions: None,
+        dump_ast: None,
+        elide: Elide::default(),
+        diff: None,
+        ext: Extensions::default(),
+        out: format::Format::default(),
+        decimal: None,
+        big: false,
+        rational: false,
+        symbolic: false,
+        arith: None,
+        int_width: None,
+        vars: Env::new(),
+        limits: EvalLimits::default(),
+        rules: Vec::new(),
+        egraph: None,
+        emit: None,
+        report: false,
+        check_ir: false,
+        optimize: false,
+        passes: Vec::new(),
+        explain_opt: false,
+        strict_fp: false,
+        strict_tokens: false,
+        expect: None,
+        timeout: None,
+        use_cache: false,
+        sandbox: false,
+        plugins: false,
+        plugin_out: None,
+        no_stdlib: config.stdlib == Some(false),
+        audit_log: None,
+        env_file: None,
+        sources: Vec::new(),
+    };
+    let usage = |message: String| -> ! { fail(Failure::Usage, message) };
+    let mut notation_given = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(enc) = arg.strip_prefix("--encoding=") {
+            o.encoding = enc.parse().unwrap_or_else(|e| usage(e));
+        } else if arg == "--calc" {
+            o.ext.calculator = true;
+        } else if arg == "--durations" {
+            o.ext.durations = true;
+        } else if arg == "--units" {
+            o.ext.units = true;
+        } else if arg == "--bytes" {
+            o.ext.bytes = true;
+        } else if arg == "--implicit-mul" {
+            o.ext.implicit_mul = true;
+        } else if arg == "--words" {
+            o.ext.words = true;
+        } else if arg == "--decimal-comma" {
+            o.ext.decimal_comma = true;
+        } else if let Some(notation) = arg.strip_prefix("--out=") {
+            // A plugin loaded later may register it.
+            match notation.parse() {
+                Ok(notation) => (o.out.notation, o.plugin_out) = (notation, None),
+                Err(_) => o.plugin_out = Some(notation),
+            }
+            notation_given = true;
+        } else if let Some(n) = arg.strip_prefix("--precision=") {
+            o.out.precision = Some(n.parse().unwrap_or_else(|_| usage(format!("--precision expects a number of digits, got '{n}'"))));
+        } else if arg == "--thousands" {
+            o.out.thousands = true;
+        } else if let Some(prefix) = arg.strip_prefix("--env-prefix=") {
+            import_env(prefix, &mut o.vars);
+        } else if let Some(path) = arg.strip_prefix("--vars=") {
+            import_json(path, &mut o.vars);
+        } else if let Some(mode) = arg.strip_prefix("--arith=") {
+            o.arith = Some(mode.parse::<ArithMode>().unwrap_or_else(|e| usage(e)));
+        } else if let Some(bits) = arg.strip_prefix("--int-width=") {
+            o.int_width = Some(bits.parse().unwrap_or_else(|_| usage(format!("--int-width expects a number of bits, got '{bits}'"))));
+        } else if arg == "--bigint" {
+            if !cfg!(feature = "bigint") {
+                usage("--bigint requires building with the `bigint` feature".into());
+            }
+            o.big = true;
+        } else if arg == "--rational" {
+            if !cfg!(feature = "rational") {
+                usage("--rational requires building with the `rational` feature".into());
+            }
+            o.rational = true;
+        } else if arg == "--symbolic" {
+            o.symbolic = true;
+        } else if arg == "--decimal" || arg.starts_with("--decimal=") {
+            #[cfg(not(feature = "decimal"))]
+            usage("--decimal requires building with the `decimal` feature".into());
+            #[cfg(feature = "decimal")]
+            {
+                o.decimal = match arg.strip_prefix("--decimal=").map(str::parse) {
+                    None => Some(decimal::DEFAULT_SCALE),
+                    Some(Ok(n)) if n <= decimal::MAX_SCALE => Some(n),
+                    Some(_) => usage(format!("--decimal expects a scale between 0 and {}", decimal::MAX_SCALE)),
+                };
+            }
+        } else if let Some(n) = arg.strip_prefix("--max-print-depth=") {
+            let n: u16 = n.parse().unwrap_or_else(|_| usage(format!("--max-print-depth expects a number up to {}, got '{n}'", u16::MAX)));
+            o.elide.depth = Some(n as usize);
+        } else if let Some((name, n)) = arg.strip_prefix("--max-").and_then(|a| a.split_once('=')) {
+            o.limits.set(name, n).unwrap_or_else(|e| usage(e.to_string()));
+        } else if let Some(path) = arg.strip_prefix("--rules=") {
+            o.rules.extend(rules::load(path));
+        } else if let Some(opt) = arg.strip_prefix("--opt=") {
+            if opt != "egraph" {
+                usage(format!("unknown optimizer '{opt}' (expected egraph)"));
+            }
+            o.egraph.get_or_insert_with(egraph::Cost::default);
+        } else if let Some(cost) = arg.strip_prefix("--opt-cost=") {
+            o.egraph = Some(cost.parse().unwrap_or_else(|e| usage(e)));
+        } else if let Some(target) = arg.strip_prefix("--emit=") {
+            if !["asm", "wat", "llvm", "ir", "tokens"].contains(&target) {
+                usage(format!("unknown --emit target '{target}' (expected asm, wat, llvm, ir or tokens)"));
+            }
+            if target == "wat" && !cfg!(feature = "wasm") {
+                usage("--emit=wat requires building with the `wasm` feature".into());
+            }
+            o.emit = Some(target);
+        } else if let Some(what) = arg.strip_prefix("--report=") {
+            if what != "codegen" {
+                usage(format!("unknown --report '{what}' (expected codegen)"));
+            }
+            o.report = true;
+        } else if arg == "--optimize" {
+            o.optimize = true;
+        } else if let Some(passes) = arg.strip_prefix("--passes=") {
+            o.passes = passes.split(',').filter(|p| !p.is_empty()).collect();
+        } else if arg == "--explain-opt" {
+            o.explain_opt = true;
+        } else if arg == "--strict-fp" {
+            o.strict_fp = true;
+        } else if arg == "--strict-tokens" {
+            o.strict_tokens = true;
+        } else if let Some(v) = arg.strip_prefix("--expect=") {
+            o.expect = Some(Expect::Value(v.to_string()));
+        } else if let Some(t) = arg.strip_prefix("--timeout=") {
+            o.timeout = Some((t, timeout::parse(t).unwrap_or_else(|e| usage(e))));
+        } else if let Some(code) = arg.strip_prefix("--expect-error=") {
+            if !ERROR_CODES.contains(&code) {
+                usage(format!("unknown error code '{code}' (see --capabilities)"));
+            }
+            o.expect = Some(Expect::Error(code.to_string()));
+        } else if arg == "--check-ir" {
+            o.check_ir = true;
+        } else if arg == "--print-sizes" {
+            o.elide.sizes = true;
+        } else if arg == "--parse-only" {
+            o.parse_only = true;
+        } else if arg == "--resolve" {
+            o.resolve_names = true;
+        } else if arg == "--trace" {
+            o.trace = true;
+        } else if arg == "--explain-result" {
+            o.explain_result = true;
+        } else if let Some(what) = arg.strip_prefix("--visualize=") {
+            if what != "stack" {
+                usage(format!("unknown --visualize mode '{what}' (expected stack)"));
+            }
+            o.visualize = true;
+        } else if arg == "--pause" {
+            o.pause = true;
+        } else if arg == "--format" {
+            o.format = true;
+        } else if let Some(form) = arg.strip_prefix("--format=") {
+            if !["exact", "cfrac"].contains(&form) {
+                usage(format!("unknown --format form '{form}' (expected exact or cfrac)"));
+            }
+            o.fractions = Some(form);
+        } else if let Some(format) = arg.strip_prefix("--dump-ast=") {
+            if !["json", "sexp", "dot"].contains(&format) {
+                usage(format!("unknown --dump-ast format '{format}' (expected json, sexp or dot)"));
+            }
+            o.dump_ast = Some(format);
+        } else if let Some(var) = arg.strip_prefix("--diff=") {
+            o.diff = Some(var);
+        } else if arg == "--cache" {
+            o.use_cache = true;
+        } else if arg == "--sandbox" {
+            o.sandbox = true;
+        } else if arg == "--plugin" || arg.starts_with("--plugin=") {
+            let path = match arg.strip_prefix("--plugin=") {
+                Some(path) => path,
+                None => args.next().unwrap_or_else(|| usage("--plugin expects a shared library".into())),
+            };
+            plugin::load(path).unwrap_or_else(|e| fail(Failure::Run, format!("cannot load plugin {e}")));
+            o.plugins = true;
+        } else if arg == "--no-stdlib" {
+            o.no_stdlib = true;
+        } else if let Some(path) = arg.strip_prefix("--env=") {
+            o.env_file = Some(path);
+        } else if let Some(path) = arg.strip_prefix("--audit-log=") {
+            o.audit_log = Some(path);
+        } else if arg == "-e" {
+            let e = args.next().unwrap_or_else(|| usage("-e expects an expression".into()));
+            o.sources.push(Source::Expr(e, None));
+        } else if let Some(e) = arg.strip_prefix("--expr=") {
+            o.sources.push(Source::Expr(e, None));
+        } else if let Some(path) = arg.strip_prefix("--from=") {
+            o.sources.push(Source::Tokens(path));
+        } else if arg.starts_with('-') && arg != "-" {
+            usage(format!("unknown option '{arg}' (see --help)"));
+        } else {
+            o.sources.push(Source::File(arg));
+        }
+    }
+
+    if let Some(name) = o.plugin_out.filter(|name| !plugin::has_format(name)) {
+        usage(name.parse::<format::Notation>().unwrap_err());
+    }
+
+    // A width is another way of choosing the arithmetic, which the
+    // config's doesn't override.
+    if let Some(bits) = o.int_width {
+        o.arith = Some(o.arith.unwrap_or_default().with_width(bits).unwrap_or_else(|e| usage(format!("--int-width={bits}: {e}"))));
+    }
+
+    // The config's defaults give way to the modes chosen here that they
+    // don't go with.
+    let exact = o.big || o.rational || o.decimal.is_some();
+    if !exact && !o.report && !matches!(o.emit, Some("asm" | "wat" | "llvm")) {
+        o.arith = o.arith.or(config.arith);
+    }
+    if !exact && !notation_given && !o.out.thousands {
+        o.out.notation = config.out.unwrap_or_default();
+    }
+    check_options(&mut o);
+    o
+}
+
+/// Fails unless the options of `o` go together, and settles what follows
+/// from them.
+fn check_options(o: &mut Options) {
+    let usage = |message: String| -> ! { fail(Failure::Usage, message) };
+    let exact = o.big || o.rational || o.decimal.is_some();
+
+    // Programs can't reach outside themselves anyway: every builtin is a
+    // pure function of its arguments. What is left is bounding their cost
+    // and not writing files on their behalf.
+    if o.sandbox {
+        if o.use_cache {
+            usage("--sandbox cannot be combined with --cache, which writes to the cache directory".into());
+        }
+        if let Some(target @ ("asm" | "wat" | "llvm")) = o.emit {
+            usage(format!("--sandbox cannot be combined with --emit={target}, which writes a file"));
+        }
+        if o.env_file.is_some() {
+            usage("--sandbox cannot be combined with --env, which writes what the program defines to a file".into());
+        }
+        if o.audit_log.is_some() {
+            usage("--sandbox cannot be combined with --audit-log, which appends to a file".into());
+        }
+        if o.plugins {
+            usage("--sandbox cannot be combined with --plugin, which runs native code".into());
+        }
+        o.limits = o.limits.min(EvalLimits::SANDBOX);
+    }
+
+    if let Err(e) = PassManager::new(false).run(&o.passes, &mut Program::default()) {
+        usage(format!("--passes: {e}"));
+    }
+    if o.explain_opt && !o.optimize && o.egraph.is_none() && o.rules.is_empty() && o.passes.is_empty() {
+        usage("--explain-opt shows the rewrites of --optimize, --passes, --opt or --rules, and none was given".into());
+    }
 
-    let ast = parser::expr(&s);
+    // Rules may reassociate, which rounds floats differently.
+    if o.strict_fp && !o.rules.is_empty() {
+        usage("--strict-fp fixes the order floats are evaluated in, so it cannot be combined with --rules, which may reorder them".into());
+    }
+
+    if o.env_file.is_some() && (exact || o.symbolic) {
+        usage("--env keeps values of the default arithmetic, and cannot be combined with --bigint, --rational, --decimal or --symbolic".into());
+    }
+
+    if o.sources.is_empty() {
+        usage("no input: give a FILE, - for standard input, or -e EXPR".into());
+    }
+
+    // Diagnostics tell several expressions apart by their position.
+    if o.sources.iter().filter(|s| matches!(s, Source::Expr(..))).count() > 1 {
+        let numbers = o.sources.iter_mut().filter_map(|s| match s {
+            Source::Expr(_, n) => Some(n),
+            _ => None,
+        });
+        numbers.zip(1..).for_each(|(n, i)| *n = Some(i));
+    }
+
+    let stdin = |s: &Source| matches!(s, Source::File("-") | Source::Tokens("-"));
+    if o.sources.iter().filter(|s| stdin(s)).count() > 1 {
+        usage("standard input can only be read once".into());
+    }
+
+    if matches!(o.emit, Some("asm" | "wat" | "llvm")) && !matches!(o.sources[..], [Source::File(path)] if path != "-") {
+        usage(format!("--emit={} compiles a single FILE, whose name the output is named after", o.emit.unwrap()));
+    }
+
+    if o.check_ir && o.decimal.is_some() {
+        usage("--check-ir cannot be combined with --decimal".into());
+    }
+
+    if o.out != format::Format::default() && exact {
+        usage("--out, --precision and --thousands cannot be combined with --decimal, --bigint or --rational".into());
+    }
+
+    if let Some(name) = o.plugin_out.filter(|_| o.out.precision.is_some() || o.out.thousands) {
+        usage(format!("--precision and --thousands cannot be combined with --out={name}, which a plugin writes"));
+    }
+    if o.out.thousands && o.out.notation != format::Notation::Plain {
+        usage(format!("--thousands only groups plain decimal results, not --out={}", format::Notation::NAMES[o.out.notation as usize]));
+    }
+
+    // Plugin builtins take and give doubles.
+    if o.plugins && exact {
+        usage("--plugin cannot be combined with --decimal, --bigint or --rational".into());
+    }
+
+    if o.big && (o.decimal.is_some() || o.check_ir) {
+        usage("--bigint cannot be combined with --decimal or --check-ir".into());
+    }
+
+    if o.rational && (o.big || o.decimal.is_some() || o.check_ir) {
+        usage("--rational cannot be combined with --decimal, --bigint or --check-ir".into());
+    }
+    if o.symbolic && (exact || o.check_ir) {
+        usage("--symbolic cannot be combined with --decimal, --bigint, --rational or --check-ir".into());
+    }
+    if o.trace && (exact || o.symbolic || o.check_ir) {
+        usage("--trace cannot be combined with --decimal, --bigint, --rational, --symbolic or --check-ir".into());
+    }
+    if o.explain_result && (exact || o.symbolic || o.check_ir || o.trace || o.visualize) {
+        usage("--explain-result cannot be combined with --decimal, --bigint, --rational, --symbolic, --check-ir, --trace or --visualize".into());
+    }
+    if o.visualize && (exact || o.symbolic || o.check_ir || o.trace) {
+        usage("--visualize cannot be combined with --decimal, --bigint, --rational, --symbolic, --check-ir or --trace".into());
+    }
+    if o.pause && !o.visualize {
+        usage("--pause needs --visualize".into());
+    }
+    if o.pause && o.sources.iter().any(stdin) {
+        usage("--pause waits for Enter on standard input, so the program cannot be read from it".into());
+    }
+    o.ext.wide_ints = exact;
+
+    if let Some(form) = o.fractions.filter(|_| !o.rational) {
+        usage(format!("--format={form} prints the fractions of --rational, and needs it"));
+    }
+
+    if o.arith.is_some() && exact {
+        usage("--arith cannot be combined with --decimal, --bigint or --rational".into());
+    }
+
+    if matches!(o.emit, Some("asm" | "wat" | "llvm")) && o.arith.is_some_and(|m| m != ArithMode::Wrap) {
+        usage(format!("compiled code always wraps, so --emit={} needs --arith=wrap if any", o.emit.unwrap()));
+    }
+
+    let prints_trees = o.parse_only || o.dump_ast.is_some();
+    if o.report {
+        if o.sources.len() > 1 {
+            usage("--report=codegen compiles a single program".into());
+        }
+        if o.emit.is_some() || prints_trees || o.diff.is_some() || o.format || o.expect.is_some() {
+            usage("--report=codegen cannot be combined with --emit, --parse-only, --dump-ast, --diff, --format or --expect".into());
+        }
+        if exact || o.symbolic || o.arith.is_some_and(|m| m != ArithMode::Wrap) {
+            usage("compiled code always wraps, so --report=codegen needs --arith=wrap if any, and not --decimal, --bigint, --rational or --symbolic".into());
+        }
+    }
+
+    if o.expect.is_some() && (o.emit.is_some() || prints_trees || o.diff.is_some() || o.format) {
+        usage("--expect and --expect-error cannot be combined with --emit, --parse-only, --dump-ast, --diff or --format".into());
+    }
+
+    if o.diff.is_some() && (o.emit.is_some() || prints_trees) {
+        usage("--diff cannot be combined with --emit, --parse-only or --dump-ast".into());
+    }
+
+    if o.format && (o.emit.is_some() || prints_trees || o.diff.is_some()) {
+        usage("--format cannot be combined with --emit, --parse-only, --dump-ast or --diff".into());
+    }
+}
+
+/// Runs, compiles or prints the programs as `o` says.
+fn evaluate(mut o: Options) {
+    // What an earlier run saved, under the variables given here.
+    let mut builtins = Builtins::default();
+    plugin::builtins(&mut builtins);
+    if let Some(path) = o.env_file {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                let mut saved = Env::new();
+                snapshot::load(&text, &mut saved, &mut builtins).unwrap_or_else(|e| fail(Failure::Run, format!("cannot load {path}: {e}")));
+                saved.extend(std::mem::take(&mut o.vars));
+                o.vars = saved;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => fail(Failure::Run, format!("cannot read {path}: {e}")),
+        }
+    }
+
+    if let Some((text, limit)) = o.timeout {
+        let (text, expect) = (text.to_string(), o.expect.clone());
+        timeout::start(limit, move |phase| {
+            eprintln!("{}: timed out after {text} while {phase}", theme::label("error"));
+            conclude(expect.as_ref(), Err("timeout"));
+            std::process::exit(0);
+        });
+    }
+
+    if o.emit == Some("tokens") {
+        return print_tokens(&o);
+    }
+    let (inputs, programs) = parse(&o);
+
+    // Names resolve without any input values, so even a program that only
+    // parses can be checked.
+    if o.resolve_names {
+        timeout::phase("resolving names");
+        let errors = resolve::resolve(programs.iter().flat_map(|p| &p.stmts));
+        for e in &errors {
+            report_as(if e.is_warning() { "warning" } else { "error" }, &inputs, &e.to_string(), e.span());
+        }
+        if let Some(e) = errors.iter().find(|e| !e.is_warning()) {
+            conclude(o.expect.as_ref(), Err(e.code()));
+            std::process::exit(0);
+        }
+    }
+
+    // The functions of the standard library that the programs call, which
+    // take the place of definitions of their own.
+    let stdlib = if o.no_stdlib { Vec::new() } else { stdlib::needed(&programs, &builtins) };
+    for (name, f) in &stdlib {
+        builtins.define(name, f.clone());
+    }
+
+    // Only what runs or is compiled needs to make sense.
+    if !(o.parse_only || o.format || o.dump_ast.is_some() || o.diff.is_some()) {
+        timeout::phase("checking");
+        let mut errors = sema::check(programs.iter().flat_map(|p| &p.stmts), &o.vars, &builtins);
+        // Symbols nothing defines are what --symbolic leaves in its results.
+        errors.retain(|e| !(o.symbolic && e.code() == "unbound-symbol"));
+        // Exact modes read `1.5` as a fraction or decimal, and `2.0` is an
+        // integer there, so evaluation tells which operands are whole.
+        errors.retain(|e| !((o.rational || o.decimal.is_some()) && e.code() == "not-an-integer"));
+        errors.iter().for_each(|e| report(&inputs, &e.to_string(), e.span()));
+        if let Some(e) = errors.first() {
+            // Any of the errors can be the one expected.
+            let code = match &o.expect {
+                Some(Expect::Error(c)) if errors.iter().any(|e| e.code() == c) => c,
+                _ => e.code(),
+            };
+            conclude(o.expect.as_ref(), Err(code));
+            std::process::exit(0);
+        }
+    }
+
+    match o.emit {
+        Some("ir") => {
+            timeout::phase("compiling");
+            programs.iter().flat_map(|p| &p.stmts).for_each(print_ir);
+        }
+        Some(target) => compile(target, &inputs[0], &programs[0], &o.vars),
+        None if o.report => report_codegen(&inputs[0], &programs[0], &o.vars),
+        None if o.dump_ast.is_some() || o.diff.is_some() || o.format || o.parse_only => print_trees(&o, &programs),
+        None => run_programs(o, &inputs, &programs, builtins, &stdlib),
+    }
+}
+
+/// `--emit=tokens`: prints the tokens of every source as JSON.
+fn print_tokens(o: &Options) {
+    for source in &o.sources {
+        timeout::phase("reading the input");
+        let (s, lexed) = source.read(o.encoding);
+        timeout::phase("lexing");
+        let lexed = lexed.map_or_else(|| lexer::Lexer::from_bytes(&s).with_words(o.ext.words).with_decimal_comma(o.ext.decimal_comma).tokens(), Ok).unwrap_or_else(|e| {
+            let e = ParseError::from(e);
+            eprintln!("{}", diagnostic::render("error", &e.kind.to_string(), &source.name(), &s, e.span));
+            conclude(o.expect.as_ref(), Err(e.kind.code()));
+            std::process::exit(0);
+        });
+        println!("{}", tokens::to_json(&lexed, &s));
+    }
+}
+
+/// Reads, parses and rewrites every source: the input of each, and its
+/// program.
+fn parse(o: &Options) -> (Vec<Input>, Vec<Program>) {
+    let ops = plugin::operators();
+    let mut inputs = Vec::new();
+    let mut programs = Vec::new();
+    for source in &o.sources {
+        timeout::phase("reading the input");
+        let (s, lexed) = source.read(o.encoding);
+
+        let report = |e: &ParseError| {
+            eprintln!("{}", diagnostic::render("error", &e.kind.to_string(), &source.name(), &s, e.span));
+        };
+
+        if o.strict_tokens {
+            // Input that doesn't lex fails in the parser with a better error.
+            let tokens = lexed.clone().map_or_else(|| lexer::Lexer::from_bytes(&s).with_words(o.ext.words).with_decimal_comma(o.ext.decimal_comma).tokens(), Ok);
+            let problems = tokens.map(|t| strict::check(&t, o.ext)).unwrap_or_default();
+            for p in &problems {
+                eprintln!("{}", diagnostic::render("error", &p.message, &source.name(), &s, p.span));
+            }
+            if let Some(p) = problems.first() {
+                conclude(o.expect.as_ref(), Err(p.code));
+                std::process::exit(0);
+            }
+        }
+
+        timeout::phase("parsing");
+        // A token stream's text only stands in for its source, so it can't
+        // tell streams apart. A program from the cache isn't rewritten, so
+        // there would be nothing to explain. What plugins add isn't part of
+        // the key.
+        let key = (o.use_cache && lexed.is_none() && !o.explain_opt && !o.plugins).then(|| cache::key(&s, &format!("{:?} {:?} {:?} {} {:?} {}", o.ext, o.rules, o.egraph, o.optimize, o.passes, o.strict_fp)));
+        let cached = key.and_then(|k| cache::load(k, &s));
+        let hit = cached.is_some();
+        let (program, skipped) = match (cached, lexed) {
+            (Some((program, skipped)), _) => (Ok(program), skipped),
+            (None, Some(tokens)) => parser::program_from_tokens(tokens, o.ext, &ops),
+            // Nothing runs, so every syntax error can be reported at once.
+            (None, None) if o.parse_only => {
+                let (program, errors) = parser::program_recovering(&s[..], o.ext, &ops);
+                errors.iter().for_each(report);
+                if let Some(e) = errors.iter().find(|e| !matches!(e.kind, ParseErrorKind::UnknownChar(_))) {
+                    conclude(o.expect.as_ref(), Err(e.kind.code()));
+                    std::process::exit(0);
+                }
+                (Ok(program), Vec::new())
+            }
+            (None, None) => parser::program_with_operators(&s[..], o.ext, &ops),
+        };
+        // A stray character is a mistake, even though the parser got past it.
+        skipped.iter().for_each(report);
+        if let Some(e) = skipped.first() {
+            conclude(o.expect.as_ref(), Err(e.kind.code()));
+            std::process::exit(0);
+        }
+        let mut program = program.unwrap_or_else(|e| {
+            report(&e);
+            conclude(o.expect.as_ref(), Err(e.kind.code()));
+            std::process::exit(0);
+        });
+
+        if !hit {
+            let rewritten = Arc::new(AtomicU64::new(0));
+            let count = Arc::clone(&rewritten);
+            timeout::phase_counting("rewriting", "expressions done", move || count.load(Ordering::Relaxed));
+            let mut log = Vec::new();
+            for ast in program.stmts.iter_mut().flat_map(Stmt::exprs_mut) {
+                *ast = match o.egraph {
+                    // Extraction picks the whole expression at once, so it
+                    // is one rewrite.
+                    Some(cost) => {
+                        let after = egraph::simplify(ast, &o.rules, cost, o.strict_fp);
+                        if o.explain_opt && after.to_string() != ast.to_string() {
+                            log.push(Rewrite { rule: "egraph".into(), before: ast.clone(), after: after.clone(), span: ast.span() });
+                        }
+                        after
+                    }
+                    None if o.explain_opt => {
+                        let (after, steps) = rules::rewrite_explained(ast, &o.rules).unwrap_or_else(|e| fail(Failure::Run, e));
+                        log.extend(steps);
+                        after
+                    }
+                    None => rules::rewrite(ast, &o.rules).unwrap_or_else(|e| fail(Failure::Run, e)),
+                };
+                if o.optimize && o.explain_opt {
+                    let (after, steps) = ast.simplify_explained(o.strict_fp);
+                    log.extend(steps);
+                    *ast = after;
+                } else if o.optimize {
+                    *ast = if o.strict_fp { ast.simplify_strict_fp() } else { ast.simplify() };
+                }
+                rewritten.fetch_add(1, Ordering::Relaxed);
+            }
+            // Every name was checked with the options.
+            log.extend(PassManager::new(o.strict_fp).run(&o.passes, &mut program).unwrap());
+            for r in log.iter().filter(|_| o.explain_opt) {
+                let message = format!("{}: {} => {}", r.rule, r.before.to_infix(), r.after.to_infix());
+                eprintln!("{}", diagnostic::render("note", &message, &source.name(), &s, r.span));
+            }
+            if let Some(k) = key {
+                cache::store(k, &s, &program, &skipped);
+            }
+        }
+
+        // A function defined by one input can fail while another runs, so
+        // each input gets its own range of offsets to tell where an error
+        // is. Inputs are a byte apart, so that none starts where the
+        // previous one ends.
+        let base = inputs.last().map_or(0, |(_, s, base): &Input| base + s.len() + 1);
+        program.stmts.iter_mut().flat_map(Stmt::exprs_mut).for_each(|e| e.shift(base));
+        inputs.push((source.name(), s, base));
+        programs.push(program);
+    }
+    (inputs, programs)
+}
+
+/// `--emit=asm`, `wat` or `llvm`: compiles `program`, read from `input`,
+/// to a file named after it.
+fn compile(target: &str, input: &Input, program: &Program, vars: &Env) {
+    let (path, ..) = input;
+    timeout::phase_counting("compiling", "instructions emitted", || codegen::EMITTED.load(Ordering::Relaxed));
+    let compiled = match target {
+        #[cfg(feature = "wasm")]
+        "wat" => codegen::compile(wat::Wat::default(), program, vars),
+        "llvm" => codegen::compile(llvm::Llvm::default(), program, vars),
+        _ => codegen::compile(x86::X86::default(), program, vars),
+    };
+    let code = compiled_or_exit(input, compiled);
+    let out = std::path::Path::new(path).with_extension(match target {
+        "wat" => "wat",
+        "llvm" => "ll",
+        _ => "s",
+    });
+    std::fs::write(&out, code).unwrap_or_else(|e| fail(Failure::Run, format!("cannot write {}: {e}", out.display())));
+}
+
+/// `--report=codegen`: prints how many instructions each operator of
+/// `program` compiles to with every target, and roughly what they cost.
+fn report_codegen(input: &Input, program: &Program, vars: &Env) {
+    timeout::phase_counting("compiling", "instructions emitted", || codegen::EMITTED.load(Ordering::Relaxed));
+    let mut profiles = vec![("asm", compiled_or_exit(input, codegen::profile(x86::X86::default(), program, vars)))];
+    #[cfg(feature = "wasm")]
+    profiles.push(("wat", compiled_or_exit(input, codegen::profile(wat::Wat::default(), program, vars))));
+    profiles.push(("llvm", compiled_or_exit(input, codegen::profile(llvm::Llvm::default(), program, vars))));
+    print!("{}", codegen::table(&profiles));
+}
+
+/// The result of compiling `input`, or its error reported and the run
+/// stopped.
+fn compiled_or_exit<T>(input: &Input, compiled: Result<T, codegen::Unsupported>) -> T {
+    let (path, s, _) = input;
+    compiled.unwrap_or_else(|e| match Some(e.span).filter(|s| s.end > s.start) {
+        Some(span) => {
+            eprintln!("{}", diagnostic::render("error", &e.to_string(), path, s, span));
+            std::process::exit(1);
+        }
+        None => fail(Failure::Run, e),
+    })
+}
+
+/// `--dump-ast`, `--diff`, `--format` and `--parse-only`, which print the
+/// programs instead of running them.
+fn print_trees(o: &Options, programs: &[Program]) {
+    let stmts = || programs.iter().flat_map(|p| &p.stmts);
+    if let Some("dot") = o.dump_ast {
+        let mut graph = dot::Graph::new();
+        for stmt in stmts() {
+            graph.stmt(stmt);
+        }
+        println!("{graph}");
+    } else if let Some(format) = o.dump_ast {
+        for stmt in stmts() {
+            match format {
+                // Which compiler wrote it, ahead of what `Stmt::from_json` reads.
+                "json" => println!("{{\"built_by\": {}, {}", json::string(&provenance::describe()), &stmt.to_versioned_json()[1..]),
+                _ => println!("{}", elided(stmt, o.elide)),
+            }
+        }
+    } else if let Some(var) = o.diff {
+        for stmt in stmts() {
+            let e = match stmt {
+                Stmt::Expr(e) | Stmt::Assign { value: e, .. } => e,
+                _ => fail(Failure::Run, format!("--diff differentiates expressions and assignments, not {stmt}")),
+            };
+            println!("{}", e.differentiate(var).unwrap_or_else(|e| fail(Failure::Run, e)));
+        }
+    } else if o.format {
+        programs.iter().for_each(|p| print!("{}", p.to_infix()));
+    } else {
+        programs.iter().for_each(|p| print!("{}", elided(p, o.elide)));
+    }
+}
+
+/// Runs the programs in the arithmetic `o` says, printing the value of
+/// each statement, and ends the run as `--expect` says.
+fn run_programs(o: Options, inputs: &[Input], programs: &[Program], mut builtins: Builtins, stdlib: &[(String, Function)]) {
+    let Options { mut vars, elide, out, plugin_out, trace, explain_result, visualize, pause, check_ir, strict_fp, .. } = o;
+    // For the exact arithmetics, whose builtins are the defaults.
+    #[cfg(any(feature = "bigint", feature = "decimal"))]
+    let with_stdlib = || {
+        let mut builtins = Builtins::default();
+        stdlib.iter().for_each(|(name, f)| builtins.define(name, f.clone()));
+        builtins
+    };
+    let arith = o.arith.unwrap_or_default();
+    let mut meter = Meter::new(o.limits).with_arith(arith).with_cancel(interrupt::token());
+    let ops = meter.ops();
+    timeout::phase_counting("evaluating", "operators applied", move || ops.load(Ordering::Relaxed));
+    // What the run was given, before it assigns anything.
+    let given = o.audit_log.map(|path| (path, vars.clone()));
+    let (time, start) = (SystemTime::now(), Instant::now());
+    let result = match o.decimal {
+        #[cfg(feature = "bigint")]
+        _ if o.big => {
+            let mut env = sorted(&vars).into_iter().map(|(k, &v)| {
+                let n = bigint::from_value(v).unwrap_or_else(|| fail(Failure::Run, format!("--bigint only works with integers, but {k} is {v}")));
+                (k.clone(), n)
+            }).collect();
+            let eval = |ast: &Node, env: &Env<bigint::BigInt>, b: &Builtins| bigint::eval(ast, env, b, &mut meter);
+            run(programs, elide, &mut env, &mut with_stdlib(), eval, |v| v.to_string())
+        }
+        #[cfg(feature = "rational")]
+        _ if o.rational => {
+            let mut env = sorted(&vars).into_iter().map(|(k, &v)| {
+                let q = rational::from_value(v).unwrap_or_else(|| fail(Failure::Run, format!("--rational only works with finite numbers, but {k} is {v}")));
+                (k.clone(), q)
+            }).collect();
+            let eval = |ast: &Node, env: &Env<rational::Rational>, b: &Builtins| rational::eval(ast, env, b, &mut meter);
+            let cfrac = o.fractions == Some("cfrac");
+            run(programs, elide, &mut env, &mut with_stdlib(), eval, |v| if cfrac { v.to_cfrac() } else { v.to_string() })
+        }
+        #[cfg(feature = "decimal")]
+        Some(scale) => {
+            let mut env = sorted(&vars).into_iter().map(|(k, &v)| {
+                let d = decimal::from_value(v, scale).unwrap_or_else(|| fail(Failure::Run, format!("--decimal can't hold {k} = {v} with {scale} decimal places")));
+                (k.clone(), d)
+            }).collect();
+            let eval = |ast: &Node, env: &Env<i128>, b: &Builtins| decimal::eval(ast, env, b, scale, &mut meter);
+            run(programs, elide, &mut env, &mut with_stdlib(), eval, |v| decimal::format(v, scale))
+        }
+        _ if o.symbolic => run_symbolic(programs, elide, &mut vars, &mut meter, |v: Value| out.value(v)),
+        _ => {
+            let format = |v: Value| match plugin_out {
+                Some(name) => plugin::format(name, v).unwrap_or_else(|e| fail(Failure::Run, e)),
+                None => out.value(v),
+            };
+            let eval = |ast: &Node, env: &Env, b: &Builtins| {
+                if trace {
+                    // Each tree, then the subtree that reduces to give the next.
+                    println!("{ast}");
+                    return trace::trace(ast, env, b, &mut meter, |step| println!("  {} -> {}\n{}", step.redex, step.by, step.tree));
+                }
+                if explain_result {
+                    // Each step as a sentence, then what they came to.
+                    let mut steps = 0;
+                    let v = trace::trace(ast, env, b, &mut meter, |step| {
+                        println!("{} {}.", if steps == 0 { "First," } else { "Then," }, trace::explain(step));
+                        steps += 1;
+                    });
+                    match &v {
+                        Ok(v) if steps == 0 => println!("{} is a value already.", out.value(*v)),
+                        Ok(v) => println!("So {} = {}.", ast.to_infix(), out.value(*v)),
+                        Err(_) => {}
+                    }
+                    return v;
+                }
+                if visualize {
+                    // The tree, then each instruction the VM runs and the
+                    // stack it leaves.
+                    println!("{ast}");
+                    let code = vm::CompiledExpr::with_builtins(ast, Builtins::empty());
+                    return code.eval_watched(env, b, &mut meter, |step| {
+                        println!("{step}");
+                        if pause {
+                            let _ = std::io::stdin().read_line(&mut String::new());
+                        }
+                    });
+                }
+                let v = eval_with(ast, env, b, &mut meter);
+                if check_ir && !matches!(v, Err(EvalError::Limit(_) | EvalError::Recursion(..))) {
+                    let w = ir::eval(&ir::lower(ast), env, b, &mut Meter::new(EvalLimits::default()).with_arith(arith));
+                    // Floats agree bit for bit under --strict-fp.
+                    let agree = match (&w, &v) {
+                        (Ok(w), Ok(v)) if strict_fp => w.identical(v),
+                        _ => w == v,
+                    };
+                    if !agree {
+                        fail(Failure::Run, format!("IR evaluation of {ast} gave {w:?}, the tree gave {v:?}"));
+                    }
+                }
+                v
+            };
+            run(programs, elide, &mut vars, &mut builtins, eval, format)
+        }
+    };
+
+    if let Some((path, vars)) = given {
+        let outcome = result.clone().map_err(|e| (e.code().to_string(), e.to_string()));
+        audit(path, time, start.elapsed(), inputs, &vars, outcome);
+    }
+
+    if let (Some(path), Ok(_)) = (o.env_file, &result) {
+        // What the standard library defined is left to the next run's.
+        let mut own = Builtins::empty();
+        for (name, f) in builtins.definitions().into_iter().filter(|(name, _)| !stdlib.iter().any(|(n, _)| n == name)) {
+            own.define(name, f.clone());
+        }
+        std::fs::write(path, snapshot::save(&vars, &own)).unwrap_or_else(|e| fail(Failure::Run, format!("cannot write {path}: {e}")));
+    }
 
-    print!("Evaluating {ast}: ");
-    println!("{}", eval(&ast));
+    let outcome = result.map_err(|e| {
+        report(inputs, &e.to_string(), e.span());
+        e.code()
+    });
+    conclude(o.expect.as_ref(), outcome);
 }
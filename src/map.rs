@@ -0,0 +1,204 @@
+use std::io::{self, BufRead, Read, Write};
+
+use crate::{fail, input, theme, Failure};
+use stoncc::limits::{EvalLimits, Meter};
+use stoncc::{parser, Env, Node};
+
+/// Splits one CSV record into its fields, undoing `"..."` quoting.
+/// Records are one line each; quoted fields can't span lines.
+fn split(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(String::new()),
+            (c, _) => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Evaluates `expr` once per row of `csv`, with each column bound to the
+/// variable of the same name, and appends the results as a new column
+/// called `column`, or without one, gives only the results, a line each.
+/// Rows that fail to evaluate get an empty cell and a warning.
+pub fn map(csv: &str, expr: &Node, column: Option<&str>) -> (String, Vec<String>) {
+    let mut lines = csv.lines().enumerate().filter(|(_, l)| !l.trim().is_empty());
+    let mut out = String::new();
+    let mut warnings = Vec::new();
+
+    let Some((_, header)) = lines.next() else {
+        return (out, warnings);
+    };
+    let names: Vec<String> = split(header).iter().map(|s| s.trim().to_string()).collect();
+    if let Some(column) = column {
+        out += &format!("{},{}\n", header, quote(column));
+    }
+
+    for (i, line) in lines {
+        let fields = split(line);
+        let mut env = Env::new();
+        for (name, field) in names.iter().zip(&fields) {
+            if let Some(v) = crate::number(field.trim()) {
+                env.insert(name.clone(), v);
+            }
+        }
+
+        let value = match stoncc::eval(expr, &env, &mut Meter::new(EvalLimits::default())) {
+            Ok(v) => v.to_string(),
+            Err(e) => {
+                warnings.push(format!("line {}: {e}", i + 1));
+                String::new()
+            }
+        };
+        out += &match column {
+            Some(_) => format!("{line},{}\n", quote(&value)),
+            None => format!("{value}\n"),
+        };
+    }
+
+    (out, warnings)
+}
+
+fn usage() -> ! {
+    eprintln!("usage: stoncc map --csv FILE --expr EXPR [--as COLUMN | --values]");
+    std::process::exit(2);
+}
+
+/// `stoncc map --csv FILE --expr EXPR`: prints the CSV file with a column
+/// of `EXPR` evaluated over each row added, or with `--values`, just that
+/// column. `--csv -` reads standard input.
+pub fn main(args: &[String]) {
+    let (mut path, mut expr, mut column, mut values) = (None, None, None, false);
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--csv" => path = Some(value()),
+            "--expr" => expr = Some(value()),
+            "--as" => column = Some(value().as_str()),
+            "--values" => values = true,
+            _ => usage(),
+        }
+    }
+
+    let (Some(path), Some(expr)) = (path, expr) else { usage() };
+    if values && column.is_some() {
+        usage();
+    }
+    let column = (!values).then(|| column.unwrap_or("value"));
+    let expr = parser::expr(expr.as_bytes()).unwrap_or_else(|e| fail(Failure::Run, format!("{expr:?}: {e}")));
+
+    let csv = if path == "-" {
+        let mut s = String::new();
+        std::io::stdin().read_to_string(&mut s).unwrap_or_else(|e| fail(Failure::Run, format!("cannot read standard input: {e}")));
+        s
+    } else {
+        String::from_utf8(input::read(path, input::Encoding::Utf8)).unwrap()
+    };
+
+    let (out, warnings) = map(&csv, &expr, column);
+    for w in &warnings {
+        eprintln!("{}: {path}: {w}", theme::label("warning"));
+    }
+    print!("{out}");
+}
+
+/// Evaluates `expr` for one line of `stoncc apply`, with the number on
+/// it bound to `x`, or with `csv`, its fields to `f1`, `f2` and so on.
+/// Fields that aren't numbers are left unbound.
+fn apply(line: &str, expr: &Node, csv: bool) -> Result<String, String> {
+    let mut env = Env::new();
+    if csv {
+        for (i, field) in split(line).iter().enumerate() {
+            if let Some(v) = crate::number(field.trim()) {
+                env.insert(format!("f{}", i + 1), v);
+            }
+        }
+    } else {
+        let v = crate::number(line.trim()).ok_or_else(|| format!("'{}' is not a number", line.trim()))?;
+        env.insert("x".to_string(), v);
+    }
+
+    match stoncc::eval(expr, &env, &mut Meter::new(EvalLimits::default())) {
+        Ok(v) => Ok(v.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn apply_usage() -> ! {
+    eprintln!("usage: stoncc apply EXPR [--csv]");
+    std::process::exit(2);
+}
+
+/// `stoncc apply EXPR`: prints `EXPR` evaluated for each line of standard
+/// input as it comes, one result per line. Lines that fail to evaluate
+/// print as empty lines, with a warning; blank lines stay blank.
+pub fn apply_main(args: &[String]) {
+    let (mut expr, mut csv) = (None, false);
+    for arg in args {
+        match arg.as_str() {
+            "--csv" => csv = true,
+            _ if expr.is_none() && !arg.starts_with("--") => expr = Some(arg),
+            _ => apply_usage(),
+        }
+    }
+
+    let Some(expr) = expr else { apply_usage() };
+    let expr = parser::expr(expr.as_bytes()).unwrap_or_else(|e| fail(Failure::Run, format!("{expr:?}: {e}")));
+
+    let mut out = io::stdout().lock();
+    for (i, line) in io::stdin().lock().lines().enumerate() {
+        let line = line.unwrap_or_else(|e| fail(Failure::Run, format!("cannot read standard input: {e}")));
+        let value = match apply(&line, &expr, csv) {
+            _ if line.trim().is_empty() => String::new(),
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("{}: line {}: {e}", theme::label("warning"),  i + 1);
+                String::new()
+            }
+        };
+        // Flushed line by line, for pipelines that wait on each result.
+        writeln!(out, "{value}").and_then(|()| out.flush()).unwrap_or_else(|e| fail(Failure::Run, format!("cannot write output: {e}")));
+    }
+}
+
+#[test]
+fn tests() {
+    assert_eq!(split(r#"a,"b, c","say ""hi""",,"#), ["a", "b, c", "say \"hi\"", "", ""]);
+    assert_eq!(quote("b, c"), "\"b, c\"");
+    assert_eq!(quote("12"), "12");
+
+    let expr = parser::expr(b"price * qty * (1 + tax)").unwrap();
+    let csv = "price,qty,tax,note\n10,3,0.5,\"x, y\"\n\n4,2,0,\n2,,0,missing qty\n";
+    let (out, warnings) = map(csv, &expr, Some("total"));
+    assert_eq!(out, "price,qty,tax,note,total\n10,3,0.5,\"x, y\",45.0\n4,2,0,,8\n2,,0,missing qty,\n");
+    assert_eq!(warnings, ["line 5: symbol qty is not defined"]);
+    let (out, warnings) = map(csv, &expr, None);
+    assert_eq!(out, "45.0\n8\n\n");
+    assert_eq!(warnings.len(), 1);
+
+    let expr = parser::expr(b"x * 1.08").unwrap();
+    assert_eq!(apply(" 100\r", &expr, false), Ok("108.0".into()));
+    assert_eq!(apply("0x10", &expr, false), Ok("17.28".into()));
+    assert_eq!(apply("ten", &expr, false), Err("'ten' is not a number".into()));
+    let expr = parser::expr(b"f1 * f3").unwrap();
+    assert_eq!(apply("6,\"a, b\",7", &expr, true), Ok("42".into()));
+    assert_eq!(apply("6,7", &expr, true), Err("symbol f3 is not defined".into()));
+}
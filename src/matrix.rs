@@ -0,0 +1,84 @@
+use stoncc::parser;
+
+/// Expressions whose parse depends on precedence, associativity or the
+/// lexer's choices, where a change to the parser would show. Entries are
+/// only ever added, at the end, so that tables from two versions line up.
+pub const CORPUS: [&str; 40] = [
+    "1 + 2 * 3",
+    "1 - 2 - 3",
+    "8 / 4 / 2",
+    "2 ^ 3 ^ 2",
+    "-2 ^ 2",
+    "2 ^ -1",
+    "--x",
+    "-x!",
+    "3!!",
+    "2 ^ 3!",
+    "-3 % 2",
+    "a % b * c",
+    "1 << 2 + 3",
+    "a & b | c",
+    "~a & b",
+    "a | b ^ c",
+    "1 < 2 == 3 > 4",
+    "a < b < c",
+    "a == b != c",
+    "a && b || c && d",
+    "!a && b",
+    "a || b ? c : d",
+    "a ? b : c ? d : e",
+    "a ? b ? c : d : e",
+    "x = y = 1",
+    "a +% b * c",
+    "a *| b + c",
+    "1 - -1",
+    "1--1",
+    "f(a, b)(c)",
+    "f(-x, y ^ 2)",
+    "(a + b) * c",
+    "((a))",
+    "1.5e3 - 2",
+    "2x",
+    "x y",
+    "1 +",
+    "(1 + 2",
+    "a ? b",
+    "1 2",
+];
+
+/// One row: the expression, a tab, and its S-expression or the error
+/// parsing it, with statements separated by `; `.
+fn row(source: &str) -> String {
+    let parsed = match parser::program(source.as_bytes()).0 {
+        Ok(program) => program.stmts.iter().map(|s| s.to_string()).collect::<Vec<_>>().join("; "),
+        Err(e) => format!("error: {e}"),
+    };
+    format!("{source}\t{parsed}")
+}
+
+/// `stoncc parse-matrix`: the table of `CORPUS` as this version parses
+/// it, to diff against another version's before upgrading.
+pub fn main(args: &[String]) {
+    if !args.is_empty() {
+        eprintln!("usage: stoncc parse-matrix");
+        std::process::exit(2);
+    }
+    for source in CORPUS {
+        println!("{}", row(source));
+    }
+}
+
+#[test]
+fn tests() {
+    assert_eq!(row("1 + 2 * 3"), "1 + 2 * 3\t(+ 1 (* 2 3))");
+    assert_eq!(row("2 ^ 3 ^ 2"), "2 ^ 3 ^ 2\t(^ 2 (^ 3 2))");
+    assert!(row("1 +").starts_with("1 +\terror: syntax error at 3"), "{}", row("1 +"));
+
+    // Every row is one line with one tab, and no entry is there twice.
+    for (i, source) in CORPUS.iter().enumerate() {
+        let row = row(source);
+        assert_eq!(row.matches('\t').count(), 1, "{row}");
+        assert!(!row.contains('\n'), "{row}");
+        assert!(!CORPUS[..i].contains(source), "{source}");
+    }
+}
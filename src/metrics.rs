@@ -0,0 +1,121 @@
+//! Counters and latency histograms for a service built on a
+//! [`Session`](crate::Session), printed in the Prometheus text format for a
+//! metrics endpoint to serve. A session keeps them when built with
+//! [`SessionBuilder::metrics`](crate::SessionBuilder::metrics).
+//!
+//! ```
+//! use stoncc::Session;
+//!
+//! let session = Session::builder().metrics(true).build();
+//! session.eval_str("1 / 0").unwrap_err();
+//! let text = session.metrics().unwrap();
+//! assert!(text.contains("stoncc_requests_total 1\n"));
+//! assert!(text.contains("stoncc_errors_total{code=\"division-by-zero\"} 1\n"));
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Upper bounds of the latency buckets, in seconds.
+const BUCKETS: [f64; 8] = [0.000_01, 0.000_1, 0.001, 0.01, 0.1, 1.0, 10.0, 100.0];
+
+/// How long something took, over many times.
+#[derive(Default)]
+struct Histogram {
+    /// How many times fell in each bucket, without the ones before it.
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, d: Duration) {
+        let secs = d.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|&b| secs <= b) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.nanos.fetch_add(d.as_nanos().try_into().unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+        let mut below = 0;
+        for (bound, n) in BUCKETS.iter().zip(&self.buckets) {
+            below += n.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {below}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum {}", self.nanos.load(Ordering::Relaxed) as f64 / 1e9);
+        let _ = writeln!(out, "{name}_count {count}");
+    }
+}
+
+/// What a session has done: how many programs it was given, how long
+/// parsing and running them took, and the errors they ended with.
+#[derive(Default)]
+pub struct Metrics {
+    requests: AtomicU64,
+    parse: Histogram,
+    eval: Histogram,
+    errors: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Metrics {
+    pub fn request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn parsed(&self, d: Duration) {
+        self.parse.observe(d);
+    }
+
+    pub fn evaluated(&self, d: Duration) {
+        self.eval.observe(d);
+    }
+
+    /// Counts an error by its code, such as `EvalError::code` gives.
+    pub fn error(&self, code: &'static str) {
+        *self.errors.lock().unwrap_or_else(PoisonError::into_inner).entry(code).or_default() += 1;
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out += "# HELP stoncc_requests_total Programs given to the session.\n# TYPE stoncc_requests_total counter\n";
+        let _ = writeln!(out, "stoncc_requests_total {}", self.requests.load(Ordering::Relaxed));
+        self.parse.render(&mut out, "stoncc_parse_seconds", "Time spent parsing each program.");
+        self.eval.render(&mut out, "stoncc_eval_seconds", "Time spent running each program that parsed.");
+        out += "# HELP stoncc_errors_total Programs that failed, by error code.\n# TYPE stoncc_errors_total counter\n";
+        for (code, n) in self.errors.lock().unwrap_or_else(PoisonError::into_inner).iter() {
+            let _ = writeln!(out, "stoncc_errors_total{{code=\"{code}\"}} {n}");
+        }
+        out
+    }
+}
+
+#[test]
+fn tests() {
+    let m = Metrics::default();
+    m.request();
+    m.request();
+    m.parsed(Duration::from_micros(50));
+    m.parsed(Duration::from_millis(50));
+    m.evaluated(Duration::from_secs(1000));
+    m.error("unbound-symbol");
+    m.error("arithmetic-overflow");
+    m.error("unbound-symbol");
+
+    let text = m.render();
+    assert!(text.starts_with("# HELP stoncc_requests_total Programs given to the session.\n# TYPE stoncc_requests_total counter\nstoncc_requests_total 2\n"));
+    // Buckets count everything at or below their bound.
+    assert!(text.contains("stoncc_parse_seconds_bucket{le=\"0.00001\"} 0\nstoncc_parse_seconds_bucket{le=\"0.0001\"} 1\n"), "{text}");
+    assert!(text.contains("stoncc_parse_seconds_bucket{le=\"0.1\"} 2\n"));
+    assert!(text.contains("stoncc_parse_seconds_sum 0.05005\nstoncc_parse_seconds_count 2\n"));
+    assert!(text.contains("stoncc_eval_seconds_bucket{le=\"100\"} 0\nstoncc_eval_seconds_bucket{le=\"+Inf\"} 1\n"));
+    assert!(text.ends_with("stoncc_errors_total{code=\"arithmetic-overflow\"} 1\nstoncc_errors_total{code=\"unbound-symbol\"} 2\n"));
+}
@@ -0,0 +1,34 @@
+//! `stoncc migrate-ast`: rewrites statements stored as JSON by an older
+//! `--dump-ast=json`, one per line, in the current version of the form.
+
+use crate::{fail, input, Failure};
+use stoncc::Stmt;
+
+/// `text` with each statement upgraded, or the first line that cannot be
+/// read.
+pub fn migrate(text: &str) -> Result<String, String> {
+    let mut out = String::new();
+    for (i, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let stmt = Stmt::from_json(line).map_err(|e| format!("line {}: {e}", i + 1))?;
+        out += &stmt.to_versioned_json();
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+pub fn main(args: &[String]) {
+    let [path] = args else {
+        eprintln!("usage: stoncc migrate-ast FILE");
+        std::process::exit(2);
+    };
+
+    let text = String::from_utf8(input::read(path, input::Encoding::Utf8)).unwrap();
+    print!("{}", migrate(&text).unwrap_or_else(|e| fail(Failure::Run, format!("{path}: {e}"))));
+}
+
+#[test]
+fn tests() {
+    let old = "{\"assign\": \"x\", \"value\": {\"int\": 1}}\n\n{\"version\": 1, \"expr\": {\"sym\": \"x\"}}\n";
+    assert_eq!(migrate(old), Ok("{\"version\": 2, \"assign\": \"x\", \"value\": {\"int\": 1}}\n{\"version\": 2, \"expr\": {\"sym\": \"x\"}}\n".into()));
+    assert_eq!(migrate("{\"expr\": {\"int\": 1}}\n{\"expr\": 1}"), Err("line 2: expected a node: an object with \"int\", \"bool\", \"decimal\", \"sym\", \"placeholder\", \"op\" or \"call\"".into()));
+}
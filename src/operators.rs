@@ -0,0 +1,284 @@
+//! The operators the parser knows, with their binding powers: an
+//! `OperatorTable`. The default table holds stoncc's own operators; a
+//! library user can add more and parse with `program_with_operators`.
+//!
+//! An added operator parses to a tree of the existing kind. It is either
+//! another spelling of a built-in operator (`**` for `^`), or a call: with
+//! `NodeVal::Call("floordiv")`, `a // b` parses to `(floordiv a b)` and
+//! evaluates by calling the function `floordiv`, which can be a builtin
+//! added with `Builtins::register`. Trees print the way they always do,
+//! so `a // b` prints as `floordiv(a, b)` in infix.
+//!
+//! ```
+//! use stoncc::limits::{EvalLimits, Meter};
+//! use stoncc::operators::{Fixity, Operator, OperatorTable};
+//! use stoncc::{Builtins, Env, Extensions, NodeVal, Stmt, Value};
+//!
+//! let mut ops = OperatorTable::default();
+//! let mul = ops.get("*", Fixity::Infix).unwrap().clone();
+//! ops.add("//", Fixity::Infix, Operator { v: NodeVal::Call("floordiv".into()), ..mul });
+//! ops.add("**", Fixity::Infix, ops.get("^", Fixity::Infix).unwrap().clone());
+//!
+//! let p = stoncc::program_with_operators(&b"1 + 7 // 2 ** 2"[..], Extensions::default(), &ops).0.unwrap();
+//! assert_eq!(p.stmts[0].to_string(), "(+ 1 (floordiv 7 (^ 2 2)))");
+//!
+//! let mut builtins = Builtins::default();
+//! builtins.register("floordiv", 2..=2, |args| {
+//!     let (a, b) = (args[0].as_f64(), args[1].as_f64());
+//!     Ok(Value::Float((a / b).floor()))
+//! });
+//! let Stmt::Expr(e) = &p.stmts[0] else { unreachable!() };
+//! let mut meter = Meter::new(EvalLimits::default());
+//! assert_eq!(stoncc::eval_with(e, &Env::new(), &builtins, &mut meter), Ok(Value::Float(2.0)));
+//! ```
+//!
+//! A table can also hold literals of a domain, like IP addresses or
+//! version numbers, which the lexer hands to a function to recognize. A
+//! literal parses to a call of the integers the function makes of it:
+//!
+//! ```
+//! use stoncc::operators::OperatorTable;
+//! use stoncc::Extensions;
+//!
+//! let mut ops = OperatorTable::default();
+//! ops.add_literal("version", |s| {
+//!     let text = s.split(|c: char| !c.is_ascii_digit() && c != '.').next()?;
+//!     let parts: Vec<i32> = text.split('.').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+//!     (parts.len() == 3).then(|| (text.len(), parts))
+//! });
+//! let p = stoncc::program_with_operators(&b"1.2.10 > 1.2.9"[..], Extensions::default(), &ops).0.unwrap();
+//! assert_eq!(p.stmts[0].to_string(), "(> (version 1 2 10) (version 1 2 9))");
+//! ```
+
+use alloc::sync::Arc;
+use core::fmt;
+use core::panic::RefUnwindSafe;
+
+use crate::lexer::{Token, KEYWORDS};
+use crate::parser::NodeVal;
+use crate::prelude::*;
+
+/// Where an operator goes relative to its operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fixity {
+    /// Before its one operand, like `-x`.
+    Prefix,
+    /// Between two operands, like `a + b`.
+    Infix,
+    /// After its one operand, like `n!`.
+    Postfix,
+}
+
+/// What an operator parses to, and how tightly it binds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operator {
+    /// The node it makes of its operands. With `NodeVal::Call(name)`, the
+    /// operands are the arguments of a call to `name`.
+    pub v: NodeVal,
+    /// Operators with higher precedence take their operands first.
+    pub prec: i32,
+    /// For infix operators, whether `a op b op c` groups as `(a op b) op c`.
+    pub lassoc: bool,
+}
+
+/// Operators by spelling and fixity. A spelling can be both prefix and
+/// infix, like `-`; if it is both infix and postfix, postfix wins.
+#[derive(Debug, Clone)]
+pub struct OperatorTable {
+    ops: Vec<(Token, Fixity, Operator)>,
+    literals: Vec<Literal>,
+}
+
+/// A kind of literal added with `OperatorTable::add_literal`: the
+/// function it calls, and what recognizes it.
+#[derive(Clone)]
+pub struct Literal {
+    pub(crate) name: String,
+    pub(crate) recognize: Arc<Recognizer>,
+}
+
+/// What `OperatorTable::add_literal` takes: given the rest of the text,
+/// how many bytes of it are the literal and the arguments of its call.
+pub type Recognizer = dyn Fn(&str) -> Option<(usize, Vec<i32>)> + Send + Sync + RefUnwindSafe;
+
+impl fmt::Debug for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Literal({:?})", self.name)
+    }
+}
+
+/// The tokens of the built-in operators.
+const BUILTIN: [Token; 25] = [
+    Token::Plus, Token::Minus, Token::Star, Token::Slash, Token::Caret, Token::Fac,
+    Token::PlusWrap, Token::StarWrap, Token::PlusSat, Token::StarSat,
+    Token::Lt, Token::Le, Token::Gt, Token::Ge, Token::Eq, Token::Ne,
+    Token::AndAnd, Token::OrOr, Token::Question,
+    Token::Percent, Token::Shl, Token::Shr, Token::Amp, Token::Pipe, Token::Tilde,
+];
+
+/// The token `spelling` lexes to once it is in a table: a word stays a
+/// symbol, which is an operator only where an operator can go.
+fn token(spelling: &str) -> Token {
+    let builtin = BUILTIN.iter().find(|t| t.to_string() == format!("'{spelling}'"));
+    match builtin {
+        Some(t) => t.clone(),
+        None if spelling.bytes().all(|c| c.is_ascii_alphanumeric()) => Token::Sym(spelling.into()),
+        None => Token::Op(spelling.to_string()),
+    }
+}
+
+impl Default for OperatorTable {
+    /// stoncc's own operators.
+    fn default() -> Self {
+        let mut ops = Vec::new();
+        for t in BUILTIN {
+            let v = NodeVal::try_from(&t).unwrap();
+            for (_, arity) in NodeVal::SHAPES.into_iter().filter(|(w, _)| *w == v) {
+                let (fixity, prec) = match v.fixity(arity) {
+                    "prefix" => (Fixity::Prefix, v.prefix_prec()),
+                    "postfix" => (Fixity::Postfix, v.postfix_prec().unwrap()),
+                    _ => (Fixity::Infix, v.infix_prec()),
+                };
+                ops.push((t.clone(), fixity, Operator { v: v.clone(), prec, lassoc: fixity == Fixity::Infix && v.is_lassoc() }));
+            }
+        }
+        Self { ops, literals: Vec::new() }
+    }
+}
+
+impl OperatorTable {
+    /// Adds `op` as `spelling`, replacing the operator of that spelling and
+    /// fixity if there is one. A new spelling is a word, like `mod`, or a
+    /// run of punctuation, like `//`; the punctuation is lexed as one token,
+    /// taking precedence over built-in tokens and comments that start the
+    /// same way.
+    ///
+    /// Panics if `spelling` is neither, is a keyword, or includes a
+    /// bracket, `,` or `;`; `try_add` says so instead.
+    pub fn add(&mut self, spelling: &str, fixity: Fixity, op: Operator) -> &mut Self {
+        self.try_add(spelling, fixity, op).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Like `add`, but with an error for a spelling that can't be one.
+    pub fn try_add(&mut self, spelling: &str, fixity: Fixity, op: Operator) -> Result<&mut Self, String> {
+        let word = spelling.bytes().all(|c| c.is_ascii_alphanumeric()) && !KEYWORDS.contains(&spelling);
+        let punctuation = spelling.bytes().all(|c| c.is_ascii_punctuation() && !b"(){},;".contains(&c));
+        if spelling.is_empty() || !(word && spelling.as_bytes()[0].is_ascii_alphabetic() || punctuation) {
+            return Err(format!("cannot use {spelling:?} as an operator"));
+        }
+        let t = token(spelling);
+        self.ops.retain(|(u, f, _)| (u, *f) != (&t, fixity));
+        self.ops.push((t, fixity, op));
+        Ok(self)
+    }
+
+    /// Adds literals that `recognize` knows, which parse to calls of
+    /// `name`. Where an operand can start, `recognize` is given the text up
+    /// to the next space, bracket, `,` or `;` (at most `MAX_LITERAL`
+    /// bytes), and returns the length of the literal it starts with and
+    /// the arguments of the call, or `None` if it doesn't start with one.
+    /// Literals take precedence over every other token, and the first
+    /// added over later ones.
+    pub fn add_literal(
+        &mut self,
+        name: &str,
+        recognize: impl Fn(&str) -> Option<(usize, Vec<i32>)> + Send + Sync + RefUnwindSafe + 'static,
+    ) -> &mut Self {
+        self.literals.push(Literal { name: name.to_string(), recognize: Arc::new(recognize) });
+        self
+    }
+
+    /// The operator spelled `spelling` with `fixity`.
+    pub fn get(&self, spelling: &str, fixity: Fixity) -> Option<&Operator> {
+        self.lookup(&token(spelling), fixity)
+    }
+
+    /// The operator that token `t` is when used with `fixity`.
+    pub(crate) fn lookup(&self, t: &Token, fixity: Fixity) -> Option<&Operator> {
+        self.ops.iter().find(|(u, f, _)| u == t && *f == fixity).map(|(_, _, op)| op)
+    }
+
+    /// The operator that token `t` is after an operand: postfix if it can
+    /// be, otherwise infix.
+    pub(crate) fn after_operand(&self, t: &Token) -> Option<(Fixity, &Operator)> {
+        [Fixity::Postfix, Fixity::Infix].into_iter().find_map(|f| Some((f, self.lookup(t, f)?)))
+    }
+
+    /// The punctuation the lexer must read as operators of their own.
+    pub(crate) fn spellings(&self) -> Vec<String> {
+        let spellings = self.ops.iter().filter_map(|(t, ..)| match t {
+            Token::Op(s) => Some(s.clone()),
+            _ => None,
+        });
+        spellings.collect()
+    }
+
+    /// The literals the lexer must recognize.
+    pub(crate) fn literals(&self) -> Vec<Literal> {
+        self.literals.clone()
+    }
+}
+
+/// The most text a literal recognizer is given.
+pub const MAX_LITERAL: usize = 64;
+
+#[test]
+fn tests() {
+    let ops = OperatorTable::default();
+    for (v, arity) in NodeVal::SHAPES {
+        let (fixity, prec) = match v.fixity(arity) {
+            "prefix" => (Fixity::Prefix, v.prefix_prec()),
+            "postfix" => (Fixity::Postfix, v.postfix_prec().unwrap()),
+            _ => (Fixity::Infix, v.infix_prec()),
+        };
+        let spelling = if v == NodeVal::Cond { "?".to_string() } else { v.to_string() };
+        assert_eq!(ops.get(&spelling, fixity), Some(&Operator { lassoc: v.is_lassoc() && fixity == Fixity::Infix, v, prec }));
+    }
+    assert!(ops.spellings().is_empty());
+
+    let mut ops = ops;
+    let call = |name: &str, prec| Operator { v: NodeVal::Call(name.to_string()), prec, lassoc: true };
+    ops.add("//", Fixity::Infix, call("floordiv", 9)).add("mod", Fixity::Infix, call("mod", 9));
+    ops.add("-", Fixity::Infix, call("minus", 7));
+    assert_eq!(ops.get("mod", Fixity::Infix), Some(&call("mod", 9)));
+    assert_eq!(ops.lookup(&Token::Minus, Fixity::Infix), Some(&call("minus", 7)));
+    assert_eq!(ops.lookup(&Token::Minus, Fixity::Prefix).map(|op| &op.v), Some(&NodeVal::Sub));
+    assert_eq!(ops.spellings(), ["//"]);
+    for bad in ["", "(", "a,", "2x", "a+", "while"] {
+        let mut ops = ops.clone();
+        assert_eq!(ops.try_add(bad, Fixity::Infix, call("f", 1)).err(), Some(format!("cannot use {bad:?} as an operator")));
+        assert!(std::panic::catch_unwind(move || { ops.add(bad, Fixity::Infix, call("f", 1)); }).is_err(), "{bad}");
+    }
+
+    let parse = |ops: &OperatorTable, s: &str| {
+        let (result, _) = crate::parser::program_with_operators(s.as_bytes(), Default::default(), ops);
+        result.map(|p| p.stmts.iter().map(|s| s.to_string()).collect::<Vec<_>>().join("; ")).map_err(|e| e.to_string())
+    };
+    ops.add("not", Fixity::Prefix, call("not", 11)).add("?!", Fixity::Postfix, call("huh", 12));
+    ops.add("**", Fixity::Infix, Operator { lassoc: false, ..call("pow", 13) });
+    assert_eq!(parse(&ops, "a // b / c mod d - e"), Ok("(minus (mod (/ (floordiv a b) c) d) e)".into()));
+    assert_eq!(parse(&ops, "2 ** 3 ** -x?!"), Ok("(pow 2 (pow 3 (- (huh x))))".into()));
+    assert_eq!(parse(&ops, "not mod\nmod = 1"), Ok("(not mod); (= mod 1)".into()));
+    assert_eq!(parse(&ops, "a mod"), Err("syntax error at 5: expected literal, found end of input".into()));
+    // Without the table, `//` starts a comment and `mod` is a symbol.
+    assert_eq!(parse(&OperatorTable::default(), "a mod b").unwrap_err(), "syntax error at 2: expected operator, found symbol mod");
+    assert_eq!(parse(&OperatorTable::default(), "a // b"), Ok("a".into()));
+
+    // Literals go to their recognizer first, which can take any part of
+    // the text up to a space or bracket.
+    let ip = |s: &str| {
+        let text = s.split(|c: char| !c.is_ascii_digit() && c != '.').next()?;
+        let parts: Vec<i32> = text.split('.').map(|p| p.parse().ok().filter(|&b| b < 256)).collect::<Option<_>>()?;
+        (parts.len() == 4).then_some((text.len(), parts))
+    };
+    let clock = |s: &str| {
+        let (m, rest) = s.split_once(':')?;
+        let (m, secs) = (m.parse::<i32>().ok()?, rest.get(..2)?.parse::<i32>().ok()?);
+        Some((s.find(':')? + 3, vec![m * 60 + secs]))
+    };
+    let mut ops = OperatorTable::default();
+    ops.add_literal("ip", ip).add_literal("clock", clock);
+    assert_eq!(parse(&ops, "ip_mask(10.0.0.1, 8) + 5:07-1.5"), Ok("(- (+ (ip_mask (ip 10 0 0 1) 8) (clock 307)) 1.5)".into()));
+    assert_eq!(parse(&ops, "x = 1.2.3.4\n256.1.1.1"), Err("syntax error at 18: expected operator, found number 1.1".into()));
+    assert_eq!(parse(&ops, "1.5 ? 9:30 : 2"), Ok("(?: 1.5 (clock 570) 2)".into()));
+}
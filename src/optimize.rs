@@ -0,0 +1,205 @@
+//! Constant folding and algebraic identities.
+//!
+//! Only integer subtrees are folded. Folding floats would round decimal
+//! literals to binary before `--decimal` sees them. A subtree whose
+//! evaluation fails (overflow, division by zero) is left alone, so the
+//! error is still reported when the program runs.
+
+use core::convert::Infallible;
+
+use crate::ast::Folder;
+use crate::lexer::Span;
+use crate::parser::{LeafVal, Node, NodeVal, ShortCircuit};
+use crate::prelude::*;
+
+/// Evaluates `v` over integer operands, or `None` if that would fail.
+fn fold(v: &NodeVal, args: &[i32]) -> Option<i32> {
+    match (v, args) {
+        (NodeVal::Add, [a]) => Some(*a),
+        (NodeVal::Sub, [a]) => a.checked_neg(),
+        (NodeVal::Fac, [a @ 0..=12]) => Some((1..=*a).product()),
+        (NodeVal::Add, [a, b]) => a.checked_add(*b),
+        (NodeVal::Sub, [a, b]) => a.checked_sub(*b),
+        (NodeVal::Mul, [a, b]) => a.checked_mul(*b),
+        (NodeVal::Div, [a, b]) => a.checked_div(*b),
+        (NodeVal::Exp, [a, b]) => a.checked_pow(u32::try_from(*b).ok()?),
+        (NodeVal::AddWrap, [a, b]) => Some(a.wrapping_add(*b)),
+        (NodeVal::MulWrap, [a, b]) => Some(a.wrapping_mul(*b)),
+        (NodeVal::AddSat, [a, b]) => Some(a.saturating_add(*b)),
+        (NodeVal::MulSat, [a, b]) => Some(a.saturating_mul(*b)),
+        (NodeVal::Mod, [a, b]) => a.checked_rem(*b),
+        (NodeVal::Shl, [a, b @ 0..=30]) => a.checked_mul(1 << b),
+        (NodeVal::Shr, [a, b]) if *b >= 0 => Some(a >> (*b).min(31)),
+        (NodeVal::BitAnd, [a, b]) => Some(a & b),
+        (NodeVal::BitOr, [a, b]) => Some(a | b),
+        (NodeVal::BitNot, [a]) => Some(!a),
+        (v, [a, b]) if v.is_comparison() => Some(v.compare(a, b)),
+        _ => None,
+    }
+}
+
+fn int(node: &Node) -> Option<i32> {
+    match node {
+        Node::Leaf(LeafVal::Int(v), _) => Some(*v),
+        Node::Leaf(LeafVal::Bool(b), _) => Some(*b as i32),
+        _ => None,
+    }
+}
+
+/// One rewrite `Node::simplify_explained` made: the rule, and the
+/// subtree at `span` before and after it. The operands in `before` are
+/// already simplified.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rewrite {
+    pub rule: String,
+    pub before: Node,
+    pub after: Node,
+    pub span: Span,
+}
+
+/// The rules `passes` splits `Node::simplify` into.
+pub(crate) const FOLD: &[&str] = &["fold"];
+pub(crate) const IDENTITIES: &[&str] = &["add-zero", "unary-plus", "double-negation", "sub-zero", "mul-one", "div-one", "pow-one"];
+pub(crate) const SHORT_CIRCUIT: &[&str] = &["short-circuit"];
+
+/// The rewrites of `Node::simplify`, applied to each node once its
+/// operands are simplified: all of them, or only the named `rules`. With
+/// `strict_fp`, only those that give a float operand back bit for bit.
+/// Each is added to `log`, if there is one.
+struct Simplify {
+    strict_fp: bool,
+    rules: Option<&'static [&'static str]>,
+    log: Option<Vec<Rewrite>>,
+}
+
+impl Simplify {
+    /// What the node becomes, and the name of the rule that says so, or
+    /// `None` if it stays as it is.
+    fn rewrite(&self, v: &NodeVal, children: &[Node], span: Span) -> Option<(&'static str, Node)> {
+        let ints: Vec<Option<i32>> = children.iter().map(int).collect();
+
+        if let Some(sc) = ints.first().copied().flatten().and_then(|c| v.short_circuit(c != 0)) {
+            return Some(("short-circuit", match sc {
+                ShortCircuit::Done(b) => Node::Leaf(LeafVal::Int(b as i32), span),
+                ShortCircuit::Truth(i) => match ints[i] {
+                    Some(n) => Node::Leaf(LeafVal::Int((n != 0) as i32), span),
+                    None => Node::op(NodeVal::Ne, vec![children[i].clone(), Node::leaf(LeafVal::Int(0))]),
+                },
+                ShortCircuit::Operand(i) => children[i].clone(),
+            }));
+        }
+
+        if !matches!(v, NodeVal::Call(_)) {
+            if let Some(args) = ints.iter().copied().collect::<Option<Vec<_>>>() {
+                if let Some(n) = fold(v, &args) {
+                    return Some(("fold", Node::Leaf(LeafVal::Int(n), span)));
+                }
+            }
+        }
+
+        let (rule, x) = match (v, children, &ints[..]) {
+            // -0.0 + 0 is 0.0.
+            (NodeVal::Add, [x, _], [_, Some(0)]) | (NodeVal::Add, [_, x], [Some(0), _]) if !self.strict_fp => ("add-zero", x),
+            (NodeVal::Add, [x], _) => ("unary-plus", x),
+            (NodeVal::Sub, [Node::Node { v: NodeVal::Sub, children: inner, .. }], _) if inner.len() == 1 => ("double-negation", &inner[0]),
+            (NodeVal::Sub, [x, _], [_, Some(0)]) => ("sub-zero", x),
+            (NodeVal::Mul, [x, _], [_, Some(1)]) | (NodeVal::Mul, [_, x], [Some(1), _]) => ("mul-one", x),
+            (NodeVal::Div, [x, _], [_, Some(1)]) => ("div-one", x),
+            (NodeVal::Exp, [x, _], [_, Some(1)]) => ("pow-one", x),
+            _ => return None,
+        };
+        Some((rule, x.clone()))
+    }
+}
+
+impl Folder for Simplify {
+    type Error = Infallible;
+
+    fn fold_node(&mut self, v: &NodeVal, children: Vec<Node>, span: Span) -> Result<Node, Infallible> {
+        let rewrite = self.rewrite(v, &children, span).filter(|(rule, _)| self.rules.is_none_or(|rules| rules.contains(rule)));
+        let Some((rule, after)) = rewrite else {
+            return Ok(Node::Node { v: v.clone(), children, span });
+        };
+        if let Some(log) = &mut self.log {
+            let before = Node::Node { v: v.clone(), children, span };
+            log.push(Rewrite { rule: rule.to_string(), before, after: after.clone(), span });
+        }
+        Ok(after)
+    }
+}
+
+impl Node {
+    /// Folds constant integer subtrees (`2*3+x` to `6+x`) and removes
+    /// identities: `x+0`, `0+x`, `x-0`, `x*1`, `1*x`, `x/1`, `x^1`, `+x`
+    /// and `--x` all become `x`. `&&`, `||` and `?:` with a constant first
+    /// operand are resolved. Calls are kept, since the program may
+    /// define its own functions under any name.
+    pub fn simplify(&self) -> Node {
+        let Ok(node) = self.fold(&mut Simplify { strict_fp: false, rules: None, log: None });
+        node
+    }
+
+    /// `simplify` for `--strict-fp`: floats come out of the simplified tree
+    /// bit for bit as they would have from this one. None of the rewrites
+    /// reassociate, so that only means keeping `x+0` and `0+x`.
+    pub fn simplify_strict_fp(&self) -> Node {
+        let Ok(node) = self.fold(&mut Simplify { strict_fp: true, rules: None, log: None });
+        node
+    }
+
+    /// `simplify`, or with `strict_fp` `simplify_strict_fp`, along with
+    /// every rewrite it made, innermost first, for `--explain-opt`.
+    pub fn simplify_explained(&self, strict_fp: bool) -> (Node, Vec<Rewrite>) {
+        let mut simplify = Simplify { strict_fp, rules: None, log: Some(Vec::new()) };
+        let Ok(node) = self.fold(&mut simplify);
+        (node, simplify.log.unwrap_or_default())
+    }
+
+    /// `simplify_explained` with only the named `rules`, adding each
+    /// rewrite to `log`.
+    pub(crate) fn simplify_only(&self, rules: &'static [&'static str], strict_fp: bool, log: &mut Vec<Rewrite>) -> Node {
+        let mut simplify = Simplify { strict_fp, rules: Some(rules), log: Some(Vec::new()) };
+        let Ok(node) = self.fold(&mut simplify);
+        log.extend(simplify.log.unwrap_or_default());
+        node
+    }
+}
+
+#[test]
+fn tests() {
+    let simplify = |s: &str| crate::parser::expr(s.as_bytes()).unwrap().simplify().to_string();
+
+    assert_eq!(simplify("2*3+x"), "(+ 6 x)");
+    assert_eq!(simplify("x + 0"), "x");
+    assert_eq!(simplify("1 * (x ^ 1) / 1"), "x");
+    assert_eq!(simplify("--x"), "x");
+    assert_eq!(simplify("-(-(y * (2 - 1)))"), "y");
+    assert_eq!(simplify("x * (4 - 2 * 2) + 3!"), "(+ (* x 0) 6)");
+    assert_eq!(simplify("max(1 + 1, x)"), "(max 2 x)");
+    assert_eq!(simplify("0.5 + 0"), "0.5");
+    assert_eq!(simplify("x * (1 < 2)"), "x");
+    assert_eq!(simplify("1 + 1 ? x : 1 / 0"), "x");
+    assert_eq!(simplify("2 > 3 && x || y"), "(!= y 0)");
+    assert_eq!(simplify("x && 1 ? 2 : 3"), "(?: (&& x 1) 2 3)");
+    assert_eq!(simplify("x & ~(1 << 4 | 7 % 4 >> 1)"), "(& x -18)");
+
+    // Failing subtrees stay, so the error surfaces at run time.
+    assert_eq!(simplify("1 / 0 + x"), "(+ (/ 1 0) x)");
+    assert_eq!(simplify("2147483647 + 1"), "(+ 2147483647 1)");
+    assert_eq!(simplify("2 ^ -1"), "(^ 2 -1)");
+    assert_eq!(simplify("2147483647 +| 1"), "2147483647");
+
+    // Only the identities that hold for -0.0 too, under --strict-fp.
+    let strict = |s: &str| crate::parser::expr(s.as_bytes()).unwrap().simplify_strict_fp().to_string();
+    assert_eq!(strict("x + 0"), "(+ x 0)");
+    assert_eq!(strict("0 + (x - 0) * 1"), "(+ 0 x)");
+    assert_eq!(strict("2*3+x"), "(+ 6 x)");
+
+    // What each rewrite was, where, with its operands already simplified.
+    let (node, log) = crate::parser::expr(b"y + x * (3 - 2)").unwrap().simplify_explained(false);
+    assert_eq!(node.to_string(), "(+ y x)");
+    let log: Vec<_> = log.iter().map(|r| (r.rule.as_str(), r.before.to_string(), r.after.to_string(), r.span.start..r.span.end)).collect();
+    assert_eq!(log, [("fold", "(- 3 2)".into(), "1".into(), 8..15), ("mul-one", "(* x 1)".into(), "x".into(), 4..15)]);
+    let (_, log) = crate::parser::expr(b"x + 0").unwrap().simplify_explained(true);
+    assert!(log.is_empty());
+}
@@ -1,165 +1,1547 @@
-use std::fmt;
+use core::fmt;
+use core::mem;
+
+use crate::arith::{ArithError, ArithMode, NonInteger};
+use crate::eval::{Tree, View};
 use crate::lexer::*;
+use crate::operators::{Fixity, Operator, OperatorTable};
+use crate::prelude::*;
+use crate::symbol::SymId;
+use crate::units::Unit;
 
+/// An operator, applied to the children of a `Node::Node`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum NodeVal {
-    Add, Sub, Mul, Div, Exp, Fac
+    Add, Sub, Mul, Div, Exp, Fac,
+    /// Explicitly wrapping (`+%`, `*%`) and saturating (`+|`, `*|`)
+    /// arithmetic, whatever the default overflow behaviour.
+    AddWrap, MulWrap, AddSat, MulSat,
+    /// Comparisons, giving 1 if they hold and 0 if not, as in C.
+    Lt, Le, Gt, Ge, Eq, Ne,
+    /// `&&`, `||` and `c ? a : b`, which evaluate only the operands they
+    /// need (see `short_circuit`).
+    And, Or, Cond,
+    /// Remainder, shifts and bitwise operators, on integers only, with the
+    /// precedences they have in C.
+    Mod, Shl, Shr, BitAnd, BitOr, BitNot,
+    /// `name(args...)`, a call to a builtin function.
+    Call(String),
+}
+
+/// Documentation for an operator or builtin function, for `stoncc doc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Doc {
+    pub summary: &'static str,
+    /// An expression using it, which evaluates without error.
+    pub example: &'static str,
 }
 
+/// An operand: a literal, a variable or a template placeholder.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum LeafVal {
     Int(i32),
-    Sym(String),
+    /// `true` or `false`, which are the integers 1 and 0.
+    Bool(bool),
+    Decimal(String),
+    Sym(SymId),
+    Placeholder(usize),
+}
+
+/// A value computed by evaluation. Operators on two integers stay integer
+/// (`1 / 2` is `0`); if either operand is a float, both are promoted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Float(f64),
+    /// An integer outside the 32-bit range, which only `ArithMode::Promote`
+    /// produces.
+    Wide(i128),
+    /// A duration in milliseconds (see `duration`).
+    Duration(i64),
+    /// A physical quantity, in SI base units (see `units`).
+    Quantity(f64, Unit),
+}
+
+/// What a short-circuiting operator does once it knows whether its first
+/// operand is true.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShortCircuit {
+    /// The result is this truth value, without evaluating anything else.
+    Done(bool),
+    /// The result is the truth value of operand `i`.
+    Truth(usize),
+    /// The result is operand `i` itself.
+    Operand(usize),
+}
+
+impl Value {
+    /// Whether `self` and `other` are the same bit for bit: unlike `==`,
+    /// NaN is identical to itself, and -0.0 is not 0.0.
+    pub fn identical(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+            (Value::Quantity(a, u), Value::Quantity(b, v)) => a.to_bits() == b.to_bits() && u == v,
+            _ => self == other,
+        }
+    }
+
+    /// Nonzero values are true, as in C. `true` and `false` are just the
+    /// integers 1 and 0, and comparisons, `&&` and `||` give those,
+    /// but a condition may be any number.
+    pub fn is_true(self) -> bool {
+        self.as_f64() != 0.0
+    }
+
+    /// An integer, `Wide` only if it has to be.
+    pub fn int(v: i128) -> Value {
+        i32::try_from(v).map_or(Value::Wide(v), Value::Int)
+    }
+
+    pub fn as_f64(self) -> f64 {
+        match self {
+            Value::Int(v) => v as f64,
+            Value::Float(v) => v,
+            Value::Wide(v) => v as f64,
+            Value::Duration(v) => v as f64,
+            Value::Quantity(v, _) => v,
+        }
+    }
+
+    /// The integer part, saturating, for checking magnitude limits.
+    pub fn as_i128(self) -> i128 {
+        match self {
+            Value::Int(v) => v as i128,
+            Value::Float(v) => v as i128,
+            Value::Wide(v) => v,
+            Value::Duration(v) => v as i128,
+            Value::Quantity(v, _) => v as i128,
+        }
+    }
 }
 
+/// An expression tree. It prints as an S-expression, `(+ 1 (* 2 3))`.
+/// Trees are cloned, compared and dropped without recursion, so however
+/// deep one is it takes no stack.
+#[derive(Debug)]
 pub enum Node {
-    Leaf(LeafVal),
+    Leaf(LeafVal, Span),
     Node {
         v: NodeVal,
-        children: Vec<Node>
+        children: Vec<Node>,
+        span: Span,
     },
+    /// Where the parser, recovering from a syntax error, gave up on an
+    /// expression (see `program_recovering`). Never evaluates.
+    Error(Span),
+}
+
+/// One line (or `;`-separated part) of a program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    /// `name = value`
+    Assign { name: String, value: Node, span: Span },
+    /// `name(params...) = body`, defining a function.
+    Def { name: String, params: Vec<String>, body: Node, span: Span },
+    Expr(Node),
+    /// `{ stmts... }`, run in order. Its value is that of the last
+    /// statement. Variables assigned inside stay assigned after it.
+    Block { stmts: Vec<Stmt>, span: Span },
+    /// `if (cond) then else els`. It has the value of the branch taken
+    /// if both branches have one.
+    If { cond: Node, then: Box<Stmt>, els: Option<Box<Stmt>>, span: Span },
+    /// `while (cond) body`, which has no value.
+    While { cond: Node, body: Box<Stmt>, span: Span },
 }
 
-fn binexpr(tokens: &mut Lexer, min_prec: i32) -> Node {
-    let mut lhs = match tokens.next() {
-        v @ (Token::Int(_) | Token::Sym(_))
-            => Node::Leaf(LeafVal::from(v)),
-        Token::LParen => {
-            let lhs = binexpr(tokens, 0);
-            assert_eq!(tokens.next(), Token::RParen);
-            lhs
+/// A parsed source file: its statements, in order.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub stmts: Vec<Stmt>,
+}
+
+impl Stmt {
+    /// The expressions this statement evaluates, and the bodies of the
+    /// functions it defines, including those of nested statements.
+    pub fn exprs(&self) -> Vec<&Node> {
+        match self {
+            Stmt::Assign { value: e, .. } | Stmt::Def { body: e, .. } | Stmt::Expr(e) => vec![e],
+            Stmt::Block { stmts, .. } => stmts.iter().flat_map(Stmt::exprs).collect(),
+            Stmt::If { cond, then, els, .. } => {
+                let mut out = vec![cond];
+                out.extend(then.exprs());
+                out.extend(els.iter().flat_map(|s| s.exprs()));
+                out
+            }
+            Stmt::While { cond, body, .. } => core::iter::once(cond).chain(body.exprs()).collect(),
         }
-        op @ (Token::Minus | Token::Plus) => {
-            let op = NodeVal::from(&op);
-            let prec = op.prefix_prec();
-            let rhs = binexpr(tokens, prec);
-            Node::Node { v: op, children: vec![rhs] }
+    }
+
+    pub fn exprs_mut(&mut self) -> Vec<&mut Node> {
+        match self {
+            Stmt::Assign { value: e, .. } | Stmt::Def { body: e, .. } | Stmt::Expr(e) => vec![e],
+            Stmt::Block { stmts, .. } => stmts.iter_mut().flat_map(Stmt::exprs_mut).collect(),
+            Stmt::If { cond, then, els, .. } => {
+                let mut out = vec![cond];
+                out.extend(then.exprs_mut());
+                out.extend(els.iter_mut().flat_map(|s| s.exprs_mut()));
+                out
+            }
+            Stmt::While { cond, body, .. } => core::iter::once(cond).chain(body.exprs_mut()).collect(),
         }
-        e => panic!("Expected literal, found {e:?}")
-    };
+    }
+
+    /// Whether running the statement gives a value, whichever way its
+    /// conditions turn out.
+    pub fn has_value(&self) -> bool {
+        match self {
+            Stmt::Assign { .. } | Stmt::Expr(_) => true,
+            Stmt::Def { .. } | Stmt::While { .. } => false,
+            Stmt::Block { stmts, .. } => stmts.last().is_some_and(Stmt::has_value),
+            Stmt::If { then, els, .. } => then.has_value() && els.as_ref().is_some_and(|s| s.has_value()),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Expr(e) => e.span(),
+            Stmt::Assign { span, .. }
+            | Stmt::Def { span, .. }
+            | Stmt::Block { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::While { span, .. } => *span,
+        }
+    }
+
+    /// Whether the statement ends with the `}` of a block, so that it needs
+    /// no separator after it.
+    fn ends_in_brace(&self) -> bool {
+        match self {
+            Stmt::Block { .. } => true,
+            Stmt::If { els: Some(s), .. } | Stmt::If { then: s, els: None, .. } | Stmt::While { body: s, .. } => {
+                s.ends_in_brace()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Node {
+    /// A leaf with no source location.
+    pub fn leaf(v: LeafVal) -> Self {
+        Node::Leaf(v, Span::default())
+    }
+
+    /// An operator node with no source location.
+    pub fn op(v: NodeVal, children: Vec<Node>) -> Self {
+        Node::Node { v, children, span: Span::default() }
+    }
+
+    /// How many nodes the tree has, leaves included.
+    pub fn size(&self) -> usize {
+        size(Nodes, self)
+    }
+
+    /// The source text this node was parsed from, operands included.
+    pub fn span(&self) -> Span {
+        match self {
+            Node::Leaf(_, span) | Node::Node { span, .. } | Node::Error(span) => *span,
+        }
+    }
+
+    /// Moves every span in the tree `by` bytes further into the input, as
+    /// if its text had been preceded by `by` more bytes.
+    pub fn shift(&mut self, by: usize) {
+        let (Node::Leaf(_, span) | Node::Node { span, .. } | Node::Error(span)) = self;
+        *span = Span { start: span.start + by, end: span.end + by };
+        if let Node::Node { children, .. } = self {
+            children.iter_mut().for_each(|c| c.shift(by));
+        }
+    }
+}
+
+/// Trees are equal when they have the same shape, wherever they came from.
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        let mut pairs = vec![(self, other)];
+        while let Some(pair) = pairs.pop() {
+            match pair {
+                (Node::Leaf(a, _), Node::Leaf(b, _)) if a == b => {}
+                (Node::Node { v: a, children: ac, .. }, Node::Node { v: b, children: bc, .. }) if a == b && ac.len() == bc.len() => {
+                    pairs.extend(ac.iter().zip(bc));
+                }
+                (Node::Error(_), Node::Error(_)) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Clone for Node {
+    fn clone(&self) -> Self {
+        // A fold that changes nothing builds the same tree again.
+        struct Same;
+        impl crate::ast::Folder for Same {
+            type Error = core::convert::Infallible;
+        }
+        let Ok(node) = self.fold(&mut Same);
+        node
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        let Node::Node { children, .. } = self else { return };
+        let mut doomed = mem::take(children);
+        while let Some(mut node) = doomed.pop() {
+            if let Node::Node { children, .. } = &mut node {
+                doomed.append(children);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseErrorKind {
+    Lex(LexError),
+    /// A stray character the lexer could not make sense of. It is skipped,
+    /// so parsing continues past it.
+    UnknownChar(String),
+    Unexpected { expected: &'static str, found: String },
+    /// Something other than a symbol or a function head on the left of `=`.
+    InvalidAssignment(String),
+    /// A function definition naming the same parameter twice.
+    DuplicateParam(String),
+    /// `a < b < c`: comparisons don't associate, so the operator found
+    /// after a comparison is an error.
+    ChainedComparison(String),
+    /// Nesting, or a tree, deeper than the limit given, which keeps the
+    /// parser and everything that walks its trees within the stack.
+    TooDeep(usize),
+}
+
+/// Why parsing failed, and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+}
+
+impl From<LexError> for ParseError {
+    fn from(e: LexError) -> Self {
+        Self { span: e.span, kind: ParseErrorKind::Lex(e) }
+    }
+}
+
+impl ParseErrorKind {
+    /// A stable name for the kind of error, for `--expect-error`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseErrorKind::Lex(e) => e.kind.code(),
+            ParseErrorKind::UnknownChar(_) => "unexpected-char",
+            ParseErrorKind::Unexpected { .. } => "unexpected-token",
+            ParseErrorKind::InvalidAssignment(_) => "invalid-assignment",
+            ParseErrorKind::DuplicateParam(_) => "duplicate-param",
+            ParseErrorKind::ChainedComparison(_) => "chained-comparison",
+            ParseErrorKind::TooDeep(_) => "too-deep",
+        }
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::Lex(e) => write!(f, "{}", e.kind),
+            ParseErrorKind::UnknownChar(text) => write!(f, "unexpected character {text:?}"),
+            ParseErrorKind::Unexpected { expected, found } => write!(f, "expected {expected}, found {found}"),
+            ParseErrorKind::InvalidAssignment(lhs) => write!(f, "can only assign to a symbol or define f(x, ...), not {lhs}"),
+            ParseErrorKind::DuplicateParam(p) => write!(f, "parameter {p} is declared twice"),
+            ParseErrorKind::ChainedComparison(op) => {
+                write!(f, "comparisons cannot be chained; parenthesize the operands of {op}")
+            }
+            ParseErrorKind::TooDeep(n) => write!(f, "nested more than {n} levels deep"),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "syntax error at {}: {}", self.span.start, self.kind)
+    }
+}
 
-    loop {
-        let op = match tokens.peek() {
-            Token::Eof | Token::RParen => break,
-            e @ Token::Int(_) => panic!("Expected operator, found {e:?}"),
-            op => NodeVal::from(op),
+/// The message already says what went wrong in the lexer, so that one line
+/// is enough for a diagnostic; `source` is there for matching on it.
+impl core::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match &self.kind {
+            ParseErrorKind::Lex(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Optional syntax, off by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Extensions {
+    /// Calculator-style operators, desugared as they are parsed: `p%` is
+    /// `p / 100.0`, `p% of x` is `p * x / 100.0`, `a of b` is `a * b` and
+    /// `a per b` is `a / b`. `of` and `per` bind more loosely than
+    /// arithmetic and associate to the right, so `20% of 300 + 50` is 70.0.
+    pub calculator: bool,
+    /// Duration literals: a number followed by one of the units in
+    /// `duration::UNITS`, like `3h` or `1.5min`, is a call
+    /// `duration(n, ms)` with `ms` the length of the unit.
+    pub durations: bool,
+    /// Byte-size literals: a number followed by one of the units in
+    /// `bytes::UNITS`, like `4KiB` or `1.5GB`, is a call
+    /// `bytes(n, base, power)` for units of `base ^ power` bytes.
+    pub bytes: bool,
+    /// Physical units: a number followed by one of the units in
+    /// `units::UNITS`, like `3 m` or `9.81 N`, is a call `quantity(n, i)`
+    /// with `i` the index of the unit there. Duration units take
+    /// precedence when both are on.
+    pub units: bool,
+    /// Integer literals too large for an `i32` are decimal literals, for
+    /// the modes that hold them exactly (`--bigint`, `--rational`,
+    /// `--decimal`), rather than errors.
+    pub wide_ints: bool,
+    /// Operands side by side multiply, as in mathematical writing: `2x`,
+    /// `3(x + 1)` and `(a)(b)` are products that bind like `*`. A symbol
+    /// right before `(` is still a call, and an operand on a new line
+    /// still starts the next statement.
+    pub implicit_mul: bool,
+    /// Operators in words, for dictated input: `plus`, `minus`, `times`,
+    /// `divided by` and `to the power of` (`lexer::WORDS`) are `+`, `-`,
+    /// `*`, `/` and `^`, so `2 times 3 plus 1` is 7. They are no longer
+    /// names then.
+    pub words: bool,
+    /// Numbers with a decimal comma, as `Lexer::with_decimal_comma` reads
+    /// them: `3,14` is 3.14 and `1.000` is 1000.
+    pub decimal_comma: bool,
+}
+
+/// How deeply statements can nest: blocks and the bodies of `if` and
+/// `while`. Expressions are only limited by `MAX_DEPTH`.
+pub const MAX_NESTING: usize = 100;
+/// How deep an expression tree can be. Parsing, evaluation, cloning and
+/// dropping take no stack for depth, but printing, optimizing and
+/// compiling trees do: one this deep can take a hundred megabytes of stack
+/// in a debug build, far more than a thread gets by default.
+pub const MAX_DEPTH: usize = 10_000;
+
+/// Where the parser puts the expressions it reads: in `Node`s, or in an
+/// `arena::Ast`.
+pub(crate) trait Builder {
+    type Expr;
+
+    fn leaf(&mut self, v: LeafVal, span: Span) -> Self::Expr;
+    fn node(&mut self, v: NodeVal, children: impl IntoIterator<Item = Self::Expr>, span: Span) -> Self::Expr;
+    fn span(&self, e: &Self::Expr) -> Span;
+    /// `e`, taking up `span` of the source instead.
+    fn respan(&mut self, e: Self::Expr, span: Span) -> Self::Expr;
+    /// The operands of `e` if it is a percentage `p / 100.0`, or `e` back.
+    fn percentage(&mut self, e: Self::Expr) -> Result<(Self::Expr, Self::Expr), Self::Expr>;
+}
+
+/// Builds every expression as a tree of `Node`s.
+#[derive(Clone, Copy)]
+pub(crate) struct Nodes;
+
+impl Builder for Nodes {
+    type Expr = Node;
+
+    fn leaf(&mut self, v: LeafVal, span: Span) -> Node {
+        Node::Leaf(v, span)
+    }
+
+    fn node(&mut self, v: NodeVal, children: impl IntoIterator<Item = Node>, span: Span) -> Node {
+        Node::Node { v, children: children.into_iter().collect(), span }
+    }
+
+    fn span(&self, e: &Node) -> Span {
+        e.span()
+    }
+
+    fn respan(&mut self, mut e: Node, to: Span) -> Node {
+        let (Node::Leaf(_, span) | Node::Node { span, .. } | Node::Error(span)) = &mut e;
+        *span = to;
+        e
+    }
+
+    fn percentage(&mut self, mut e: Node) -> Result<(Node, Node), Node> {
+        match &mut e {
+            Node::Node { v: NodeVal::Div, children, .. }
+                if matches!(&children[1], Node::Leaf(LeafVal::Decimal(d), _) if d == "100.0") =>
+            {
+                let hundred = children.pop().unwrap();
+                Ok((children.pop().unwrap(), hundred))
+            }
+            _ => Err(e),
+        }
+    }
+}
+
+/// A decision of the expression parser, as `explain` records them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// The operand at the span was read, units and all.
+    Operand(Span),
+    /// `op` at `span`, a prefix operator, `(` or call, waits for an
+    /// expression of operators binding tighter than `prec`.
+    Open { op: String, prec: i32, span: Span },
+    /// `op` at `span` was weighed against the expression under way, which
+    /// takes operators binding tighter than `min_prec`. If it `binds`, the
+    /// operand so far is its left operand; otherwise the expression ends
+    /// before it. Implicit multiplication has an empty span.
+    Compare { op: String, fixity: Fixity, prec: i32, lassoc: bool, min_prec: i32, binds: bool, span: Span },
+    /// The expression under way ended at the token at the span, which no
+    /// operator can be.
+    End(Span),
+    /// A node of `op` was built, covering `span`.
+    Reduce { op: String, span: Span },
+}
+
+struct Parser<R, B = Nodes> {
+    tokens: Lexer<R>,
+    /// Errors for characters that were skipped over.
+    skipped: Vec<ParseError>,
+    ext: Extensions,
+    ops: OperatorTable,
+    b: B,
+    /// How many `statement` calls are under way.
+    nesting: usize,
+    /// The depth of the tree `binexpr` last returned.
+    depth: usize,
+    /// Whether a statement that fails to parse becomes a `Node::Error`
+    /// rather than the end of parsing.
+    recover: bool,
+    /// The syntax errors recovered from.
+    errors: Vec<ParseError>,
+    /// The decisions made so far, if they are being recorded.
+    trace: Option<Vec<Step>>,
+}
+
+/// An operator loop of `binexpr`, taking operators that bind tighter than
+/// `min_prec`.
+struct Level {
+    min_prec: i32,
+    /// The depth of the operand so far.
+    depth: usize,
+    /// Whether the operand is a comparison made by this loop, not one in
+    /// parentheses.
+    compared: bool,
+}
+
+impl Level {
+    fn new(min_prec: i32) -> Self {
+        Level { min_prec, depth: 1, compared: false }
+    }
+}
+
+/// A call whose arguments are being read.
+struct Call<E> {
+    name: String,
+    start: usize,
+    args: Vec<E>,
+    /// The depth of the deepest argument.
+    depth: usize,
+}
+
+/// What an expression being read is for.
+enum Pending<E> {
+    /// The operand of a prefix operator, which starts at the offset.
+    Prefix(Operator, usize),
+    /// Parentheses, from the offset of the `(`.
+    Paren(usize),
+    Arg(Call<E>),
+    /// The right-hand side of `of` or `per`.
+    Calculator(String, E),
+    /// The middle operand of `?:`, of the precedence given.
+    Then(E, i32),
+    /// The last operand of `?:`.
+    Else(E, E),
+    /// The right operand of an infix operator.
+    Infix(NodeVal, E),
+}
+
+/// Where `binexpr` is.
+enum State<E> {
+    /// An operand is next.
+    Operand,
+    /// Operators may follow the operand.
+    Operators(E),
+    /// An expression of operators binding tighter than the precedence
+    /// given is next, for the pending part of the one under way.
+    Nested(Pending<E>, i32),
+    /// The expression under way is complete.
+    Done(E),
+}
+
+impl<R: Source, B: Builder> Parser<R, B> {
+    fn new(r: R, ext: Extensions, ops: &OperatorTable, b: B) -> Self {
+        Self::with_lexer(Lexer::new(r).with_operators(ops.spellings()).with_wide_ints(ext.wide_ints).with_words(ext.words).with_decimal_comma(ext.decimal_comma).with_literals(ops.literals()), ext, ops, b)
+    }
+
+    fn with_lexer(tokens: Lexer<R>, ext: Extensions, ops: &OperatorTable, b: B) -> Self {
+        Parser { tokens, skipped: Vec::new(), ext, ops: ops.clone(), b, nesting: 0, depth: 0, recover: false, errors: Vec::new(), trace: None }
+    }
+
+    /// Records and drops bytes the lexer could not make sense of, so that a
+    /// stray character doesn't stop the rest of the input from being parsed.
+    fn skip_unknown(&mut self) -> Result<(), ParseError> {
+        loop {
+            match self.tokens.peek()? {
+                Token::Unknown(..) => {}
+                _ => return Ok(()),
+            }
+            self.tokens.next()?;
+            let span = self.tokens.span();
+            let text = String::from_utf8_lossy(&self.tokens.slice(span)).into_owned();
+            self.skipped.push(ParseError { kind: ParseErrorKind::UnknownChar(text), span });
+        }
+    }
+
+    /// The number `n`, or the duration, byte count or quantity if a unit of
+    /// an extension that is on follows it on the same line, one level
+    /// deeper.
+    fn quantity(&mut self, n: B::Expr, level: &mut Level) -> Result<B::Expr, ParseError> {
+        self.tokens.peek()?;
+        let newline = self.tokens.newline_before();
+        let call = match self.tokens.peek()? {
+            Token::Sym(_) if newline => None,
+            Token::Sym(unit) if self.ext.durations && crate::duration::unit(unit).is_some() => {
+                Some(("duration", vec![crate::duration::unit(unit).unwrap() as i32]))
+            }
+            Token::Sym(unit) if self.ext.bytes && crate::bytes::unit(unit).is_some() => {
+                crate::bytes::unit(unit).map(|(base, power)| ("bytes", vec![base, power]))
+            }
+            Token::Sym(unit) if self.ext.units => crate::units::index(unit).map(|i| ("quantity", vec![i as i32])),
+            _ => None,
         };
+        let Some((name, args)) = call else { return Ok(n) };
+        self.tokens.next()?;
+        level.depth += 1;
+        let unit = self.tokens.span();
+        let span = Span { start: self.b.span(&n).start, end: unit.end };
+        let args: Vec<_> = args.into_iter().map(|a| self.b.leaf(LeafVal::Int(a), unit)).collect();
+        Ok(self.b.node(NodeVal::Call(name.to_string()), [n].into_iter().chain(args), span))
+    }
 
-        if let Some(prec) = op.postfix_prec() {
-            if prec <= min_prec {
+    /// `lhs of rhs` or `lhs per rhs`, desugared.
+    fn calculator(&mut self, word: &str, lhs: B::Expr, rhs: B::Expr) -> B::Expr {
+        let span = Span { start: self.b.span(&lhs).start, end: self.b.span(&rhs).end };
+        if word != "of" {
+            return self.b.node(NodeVal::Div, [lhs, rhs], span);
+        }
+        match self.b.percentage(lhs) {
+            Ok((p, hundred)) => {
+                let product = self.b.node(NodeVal::Mul, [p, rhs], span);
+                self.b.node(NodeVal::Div, [product, hundred], span)
+            }
+            Err(lhs) => self.b.node(NodeVal::Mul, [lhs, rhs], span),
+        }
+    }
+
+    fn too_deep(&self, limit: usize) -> ParseError {
+        ParseError { kind: ParseErrorKind::TooDeep(limit), span: self.tokens.span() }
+    }
+
+    /// Runs `f` one level of nesting deeper.
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        if self.nesting == MAX_NESTING {
+            return Err(self.too_deep(MAX_NESTING));
+        }
+        self.nesting += 1;
+        let result = f(self);
+        self.nesting -= 1;
+        result
+    }
+
+    /// `depth`, if a tree can be that deep. Otherwise the error is the tree's
+    /// `span`, which a deep tree may only reach at the end of the input.
+    fn check_depth(&self, depth: usize, span: Span) -> Result<usize, ParseError> {
+        if depth > MAX_DEPTH {
+            return Err(ParseError { kind: ParseErrorKind::TooDeep(MAX_DEPTH), span });
+        }
+        Ok(depth)
+    }
+
+    fn unexpected(&self, expected: &'static str, found: &Token) -> ParseError {
+        ParseError {
+            kind: ParseErrorKind::Unexpected { expected, found: found.to_string() },
+            span: self.tokens.span(),
+        }
+    }
+
+    /// Records the step `f` makes of the builder, when tracing.
+    fn note(&mut self, f: impl FnOnce(&B) -> Step) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(f(&self.b));
+        }
+    }
+
+    /// Records that a node of `op` is built over `span`.
+    fn reduced(&mut self, op: impl fmt::Display, span: Span) {
+        self.note(|_| Step::Reduce { op: op.to_string(), span });
+    }
+
+    /// Like `unexpected`, for a token `next` returned, which is put back so
+    /// that `program_recovering` can resume at it should it end the
+    /// statement.
+    fn reject(&mut self, expected: &'static str, found: Token) -> ParseError {
+        let e = self.unexpected(expected, &found);
+        self.tokens.unread(found);
+        e
+    }
+
+    /// An expression of operators binding tighter than `min_prec`. Operands
+    /// that nest, like parentheses and the right operand of `^`, are kept on
+    /// a stack of their own, so deep input takes memory rather than stack.
+    fn binexpr(&mut self, min_prec: i32) -> Result<B::Expr, ParseError> {
+        // What each expression under way is waiting for, innermost last.
+        let mut stack = Vec::new();
+        let mut level = Level::new(min_prec);
+        let mut state = State::Operand;
+        loop {
+            state = match state {
+                State::Operand => self.operand(&mut level)?,
+                State::Operators(lhs) => self.operators(lhs, &mut level)?,
+                State::Nested(pending, prec) => {
+                    stack.push((mem::replace(&mut level, Level::new(prec)), pending));
+                    State::Operand
+                }
+                State::Done(node) => {
+                    let Some((outer, pending)) = stack.pop() else {
+                        self.depth = level.depth;
+                        return Ok(node);
+                    };
+                    let depth = mem::replace(&mut level, outer).depth;
+                    self.resume(pending, node, depth, &mut level)?
+                }
+            };
+        }
+    }
+
+    /// The operand an expression starts with.
+    fn operand(&mut self, level: &mut Level) -> Result<State<B::Expr>, ParseError> {
+        self.skip_unknown()?;
+        let lhs = match self.tokens.next()? {
+            t if self.ops.lookup(&t, Fixity::Prefix).is_some() => {
+                let start = self.tokens.span().start;
+                let op = self.ops.lookup(&t, Fixity::Prefix).unwrap().clone();
+                let prec = op.prec;
+                let span = self.tokens.span();
+                self.note(|_| Step::Open { op: op.v.to_string(), prec, span });
+                return Ok(State::Nested(Pending::Prefix(op, start), prec));
+            }
+            v @ (Token::Int(_) | Token::Decimal(_)) if self.ext.durations || self.ext.bytes || self.ext.units => {
+                let n = self.b.leaf(LeafVal::from(v), self.tokens.span());
+                self.quantity(n, level)?
+            }
+            v @ (Token::Int(_) | Token::Bool(_) | Token::Decimal(_) | Token::Placeholder(_))
+                => self.b.leaf(LeafVal::from(v), self.tokens.span()),
+            Token::Literal(name, args) => {
+                level.depth += 1;
+                let span = self.tokens.span();
+                let args: Vec<_> = args.into_iter().map(|a| self.b.leaf(LeafVal::Int(a), span)).collect();
+                self.b.node(NodeVal::Call(name), args, span)
+            }
+            Token::Sym(name) => {
+                let span = self.tokens.span();
+                if *self.tokens.peek()? != Token::LParen || self.tokens.newline_before() {
+                    self.note(|_| Step::Operand(span));
+                    return Ok(State::Operators(self.b.leaf(LeafVal::Sym(name), span)));
+                }
+                self.tokens.next()?;
+                let paren = self.tokens.span();
+                self.note(|_| Step::Open { op: format!("{name}("), prec: 0, span: Span { start: span.start, end: paren.end } });
+                self.skip_unknown()?;
+                let call = Call { name: name.to_string(), start: span.start, args: Vec::new(), depth: 0 };
+                if *self.tokens.peek()? != Token::RParen {
+                    return Ok(State::Nested(Pending::Arg(call), 0));
+                }
+                return self.call(call, level);
+            }
+            Token::LParen => {
+                let span = self.tokens.span();
+                self.note(|_| Step::Open { op: "(".to_string(), prec: 0, span });
+                return Ok(State::Nested(Pending::Paren(span.start), 0));
+            }
+            e => return Err(self.reject("literal", e)),
+        };
+        self.note(|b| Step::Operand(b.span(&lhs)));
+        Ok(State::Operators(lhs))
+    }
+
+    /// Operators after `lhs`, up to one that needs another operand.
+    fn operators(&mut self, mut lhs: B::Expr, level: &mut Level) -> Result<State<B::Expr>, ParseError> {
+        loop {
+            self.skip_unknown()?;
+            let newline = self.tokens.newline_before();
+            let min_prec = level.min_prec;
+            let compare = |op: &dyn fmt::Display, fixity, prec, lassoc, binds, span| Step::Compare {
+                op: op.to_string(),
+                fixity,
+                prec,
+                lassoc,
+                min_prec,
+                binds,
+                span,
+            };
+            let op = match self.tokens.peek()? {
+                Token::Eof | Token::RParen | Token::Semi | Token::Assign | Token::Comma | Token::Colon
+                | Token::RBrace | Token::Else => {
+                    let span = self.tokens.span();
+                    self.note(|_| Step::End(span));
+                    break;
+                }
+                // An operand on a new line starts the next statement.
+                Token::Int(_) | Token::Bool(_) | Token::Decimal(_) | Token::Sym(_) | Token::Placeholder(_) | Token::Literal(..)
+                | Token::LParen | Token::LBrace | Token::If | Token::While if newline => {
+                    let span = self.tokens.span();
+                    self.note(|_| Step::End(span));
+                    break;
+                }
+                // A percentage, with the calculator extensions on, binds
+                // like `!`. Otherwise `%` is the remainder.
+                Token::Percent if self.ext.calculator => {
+                    let prec = NodeVal::Fac.postfix_prec().unwrap();
+                    let span = self.tokens.span();
+                    self.note(|_| compare(&"%", Fixity::Postfix, prec, true, prec > min_prec, span));
+                    if prec <= level.min_prec {
+                        break;
+                    }
+                    self.tokens.next()?;
+                    let percent = self.tokens.span();
+                    let span = Span { start: self.b.span(&lhs).start, end: percent.end };
+                    level.depth = self.check_depth(level.depth + 1, span)?;
+                    let hundred = self.b.leaf(LeafVal::Decimal("100.0".to_string()), percent);
+                    self.reduced("%", span);
+                    lhs = self.b.node(NodeVal::Div, [lhs, hundred], span);
+                    continue;
+                }
+                Token::Sym(word) if self.ext.calculator && (word == "of" || word == "per") => {
+                    // Between comparisons and arithmetic, to the right.
+                    let word = word.to_string();
+                    let prec = NodeVal::Lt.infix_prec();
+                    let span = self.tokens.span();
+                    self.note(|_| compare(&word, Fixity::Infix, prec, false, prec >= min_prec, span));
+                    if prec < level.min_prec {
+                        break;
+                    }
+                    self.tokens.next()?;
+                    return Ok(State::Nested(Pending::Calculator(word, lhs), prec));
+                }
+                // The operand is left for the multiplication to read.
+                Token::Int(_) | Token::Bool(_) | Token::Decimal(_) | Token::Sym(_) | Token::Placeholder(_) | Token::Literal(..)
+                | Token::LParen if self.ext.implicit_mul =>
+                {
+                    let prec = NodeVal::Mul.infix_prec();
+                    let at = self.tokens.span().start;
+                    self.note(|_| compare(&NodeVal::Mul, Fixity::Infix, prec, true, prec > min_prec, Span { start: at, end: at }));
+                    if prec <= level.min_prec {
+                        break;
+                    }
+                    return Ok(State::Nested(Pending::Infix(NodeVal::Mul, lhs), prec));
+                }
+                op => self.ops.after_operand(op).map(|(f, op)| (f, op.clone())).ok_or_else(|| op.clone()),
+            };
+            let (fixity, Operator { v: op, prec, lassoc }) = op.map_err(|e| self.unexpected("operator", &e))?;
+
+            let binds = match fixity {
+                Fixity::Postfix => prec > level.min_prec,
+                _ => !(prec < level.min_prec || (prec == level.min_prec && lassoc)),
+            };
+            let span = self.tokens.span();
+            self.note(|_| compare(&op, fixity, prec, lassoc, binds, span));
+            if !binds {
                 break;
             }
 
-            tokens.next();
+            if fixity == Fixity::Postfix {
+                self.tokens.next()?;
+                let span = Span { start: self.b.span(&lhs).start, end: self.tokens.span().end };
+                level.depth = self.check_depth(level.depth + 1, span)?;
+                self.reduced(&op, span);
+                lhs = self.b.node(op, [lhs], span);
+                continue;
+            }
+
+            self.tokens.next()?;
+            if op.is_comparison() {
+                if level.compared {
+                    let kind = ParseErrorKind::ChainedComparison(format!("'{op}'"));
+                    return Err(ParseError { kind, span: self.tokens.span() });
+                }
+                level.compared = true;
+            }
 
-            lhs = Node::Node { v: op, children: vec![lhs] };
-            continue;
+            if op == NodeVal::Cond {
+                return Ok(State::Nested(Pending::Then(lhs, prec), 0));
+            }
+            return Ok(State::Nested(Pending::Infix(op, lhs), prec));
         }
+        Ok(State::Done(lhs))
+    }
 
-        let prec = op.infix_prec();
-        if prec < min_prec || (prec == min_prec && op.is_lassoc()) {
-            break;
+    /// Continues with `pending` now that the expression it waited for,
+    /// `node` of depth `depth`, is complete.
+    fn resume(
+        &mut self,
+        pending: Pending<B::Expr>,
+        node: B::Expr,
+        depth: usize,
+        level: &mut Level,
+    ) -> Result<State<B::Expr>, ParseError> {
+        let lhs = match pending {
+            Pending::Prefix(op, start) => {
+                let span = Span { start, end: self.b.span(&node).end };
+                level.depth = self.check_depth(depth + 1, span)?;
+                self.reduced(&op.v, span);
+                self.b.node(op.v, [node], span)
+            }
+            Pending::Paren(start) => {
+                level.depth = depth;
+                self.skip_unknown()?;
+                match self.tokens.next()? {
+                    // Parentheses belong to the subexpression they group.
+                    Token::RParen => {
+                        let span = Span { start, end: self.tokens.span().end };
+                        self.reduced("()", span);
+                        self.b.respan(node, span)
+                    }
+                    e => return Err(self.reject("')'", e)),
+                }
+            }
+            Pending::Arg(mut call) => {
+                call.args.push(node);
+                call.depth = call.depth.max(depth);
+                self.skip_unknown()?;
+                if *self.tokens.peek()? != Token::Comma {
+                    return self.call(call, level);
+                }
+                self.tokens.next()?;
+                return Ok(State::Nested(Pending::Arg(call), 0));
+            }
+            Pending::Calculator(word, lhs) => {
+                let span = Span { start: self.b.span(&lhs).start, end: self.b.span(&node).end };
+                level.depth = self.check_depth(level.depth.max(depth) + 2, span)?;
+                self.reduced(&word, span);
+                self.calculator(&word, lhs, node)
+            }
+            Pending::Then(lhs, prec) => {
+                level.depth = level.depth.max(depth);
+                self.skip_unknown()?;
+                match self.tokens.next()? {
+                    Token::Colon => {}
+                    e => return Err(self.reject("':'", e)),
+                }
+                return Ok(State::Nested(Pending::Else(lhs, node), prec));
+            }
+            Pending::Else(cond, then) => {
+                let span = Span { start: self.b.span(&cond).start, end: self.b.span(&node).end };
+                level.depth = self.check_depth(level.depth.max(depth) + 1, span)?;
+                self.reduced(NodeVal::Cond, span);
+                self.b.node(NodeVal::Cond, [cond, then, node], span)
+            }
+            Pending::Infix(op, lhs) => {
+                let span = Span { start: self.b.span(&lhs).start, end: self.b.span(&node).end };
+                level.depth = self.check_depth(level.depth.max(depth) + 1, span)?;
+                self.reduced(&op, span);
+                self.b.node(op, [lhs, node], span)
+            }
+        };
+        Ok(State::Operators(lhs))
+    }
+
+    /// The call with the arguments in `call`, ending at its `)`.
+    fn call(&mut self, call: Call<B::Expr>, level: &mut Level) -> Result<State<B::Expr>, ParseError> {
+        match self.tokens.next()? {
+            Token::RParen => {
+                let span = Span { start: call.start, end: self.tokens.span().end };
+                level.depth = self.check_depth(call.depth + 1, span)?;
+                self.reduced(format_args!("{}()", call.name), span);
+                Ok(State::Operators(self.b.node(NodeVal::Call(call.name), call.args, span)))
+            }
+            e => Err(self.reject("',' or ')'", e)),
         }
+    }
 
-        tokens.next();
+    /// An expression that makes up the rest of the input.
+    fn whole(&mut self) -> Result<B::Expr, ParseError> {
+        let node = self.binexpr(0)?;
+        match self.tokens.next()? {
+            Token::Eof => Ok(node),
+            e => Err(self.unexpected("end of input", &e)),
+        }
+    }
+}
 
-        let rhs = binexpr(tokens, prec);
+impl<R: Source> Parser<R> {
 
-        lhs = Node::Node { v: op, children: vec![lhs, rhs]};
-    };
+    /// The parenthesized condition of `if` or `while`.
+    fn condition(&mut self) -> Result<Node, ParseError> {
+        self.skip_unknown()?;
+        match self.tokens.next()? {
+            Token::LParen => {}
+            e => return Err(self.reject("'('", e)),
+        }
+        let cond = self.binexpr(0)?;
+        self.skip_unknown()?;
+        match self.tokens.next()? {
+            Token::RParen => Ok(cond),
+            e => Err(self.reject("')'", e)),
+        }
+    }
+
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
+        self.nested(Self::stmt)
+    }
 
-    lhs
+    fn stmt(&mut self) -> Result<Stmt, ParseError> {
+        self.skip_unknown()?;
+        match self.tokens.peek()? {
+            Token::LBrace => {
+                self.tokens.next()?;
+                let start = self.tokens.span().start;
+                let stmts = self.statements(Token::RBrace)?;
+                self.tokens.next()?;
+                return Ok(Stmt::Block { stmts, span: Span { start, end: self.tokens.span().end } });
+            }
+            Token::If => {
+                self.tokens.next()?;
+                let start = self.tokens.span().start;
+                let cond = self.condition()?;
+                let then = Box::new(self.statement()?);
+                self.skip_unknown()?;
+                let els = match self.tokens.peek()? {
+                    Token::Else => {
+                        self.tokens.next()?;
+                        Some(Box::new(self.statement()?))
+                    }
+                    _ => None,
+                };
+                let end = els.as_ref().unwrap_or(&then).span().end;
+                return Ok(Stmt::If { cond, then, els, span: Span { start, end } });
+            }
+            Token::While => {
+                self.tokens.next()?;
+                let start = self.tokens.span().start;
+                let cond = self.condition()?;
+                let body = Box::new(self.statement()?);
+                let span = Span { start, end: body.span().end };
+                return Ok(Stmt::While { cond, body, span });
+            }
+            _ => {}
+        }
+
+        let lhs = self.binexpr(0)?;
+        self.skip_unknown()?;
+        if *self.tokens.peek()? != Token::Assign {
+            return Ok(Stmt::Expr(lhs));
+        }
+        self.tokens.next()?;
+
+        let invalid = |lhs: &Node| ParseError { kind: ParseErrorKind::InvalidAssignment(lhs.to_string()), span: lhs.span() };
+        match lhs {
+            Node::Leaf(LeafVal::Sym(name), Span { start, .. }) => {
+                let value = self.binexpr(0)?;
+                let span = Span { start, end: value.span().end };
+                Ok(Stmt::Assign { name: name.to_string(), value, span })
+            }
+            Node::Node { v: NodeVal::Call(ref name), ref children, span: Span { start, .. } } => {
+                let mut params: Vec<String> = Vec::new();
+                for c in children {
+                    let Node::Leaf(LeafVal::Sym(p), span) = c else { return Err(invalid(&lhs)) };
+                    if params.iter().any(|q| q == p.as_str()) {
+                        return Err(ParseError { kind: ParseErrorKind::DuplicateParam(p.to_string()), span: *span });
+                    }
+                    params.push(p.to_string());
+                }
+
+                let body = self.binexpr(0)?;
+                let span = Span { start, end: body.span().end };
+                Ok(Stmt::Def { name: name.clone(), params, body, span })
+            }
+            lhs => Err(invalid(&lhs)),
+        }
+    }
+
+    /// Statements up to `end` (the end of input, or the `}` of a block),
+    /// separated by `;` or line breaks. A block needs no separator after
+    /// it. `end` itself is left for the caller.
+    fn statements(&mut self, end: Token) -> Result<Vec<Stmt>, ParseError> {
+        let mut out = Vec::new();
+
+        loop {
+            match self.separated(&end, &mut out) {
+                Ok(true) => return Ok(out),
+                Ok(false) => {}
+                Err((e, start)) if self.recover => {
+                    out.extend(self.resync(e, start, &end));
+                    // A block missing its `}` ends with the input.
+                    if end != Token::Eof && matches!(self.tokens.peek(), Ok(Token::Eof)) {
+                        let missing = self.unexpected("'}'", &Token::Eof);
+                        if self.errors.last() != Some(&missing) {
+                            self.errors.push(missing);
+                        }
+                        return Ok(out);
+                    }
+                }
+                Err((e, _)) => return Err(e),
+            }
+        }
+    }
+
+    /// A statement, if one is next, and the separator after it, onto `out`.
+    /// Returns whether `end` is next. An error comes with where the
+    /// statement it spoils starts.
+    fn separated(&mut self, end: &Token, out: &mut Vec<Stmt>) -> Result<bool, (ParseError, usize)> {
+        let here = |e: ParseError| {
+            let start = e.span.start;
+            (e, start)
+        };
+
+        self.skip_unknown().map_err(here)?;
+        match self.tokens.peek().map_err(|e| here(e.into()))? {
+            t if t == end => return Ok(true),
+            Token::Eof => return Err(here(self.unexpected("'}'", &Token::Eof))),
+            Token::Semi => {
+                self.tokens.next().map_err(|e| here(e.into()))?;
+                return Ok(false);
+            }
+            _ => {
+                let start = self.tokens.span().start;
+                out.push(self.statement().map_err(|e| (e, start))?);
+            }
+        }
+
+        self.skip_unknown().map_err(here)?;
+        let newline = self.tokens.newline_before();
+        match self.tokens.peek().map_err(|e| here(e.into()))? {
+            t if t == end => Ok(true),
+            Token::Eof => Err(here(self.unexpected("'}'", &Token::Eof))),
+            Token::Semi => {
+                self.tokens.next().map_err(|e| here(e.into()))?;
+                Ok(false)
+            }
+            _ if newline || out.last().is_some_and(Stmt::ends_in_brace) => Ok(false),
+            e => {
+                let e = e.clone();
+                Err(here(self.unexpected("end of statement", &e)))
+            }
+        }
+    }
+
+    /// Records `e` and skips what is left of the statement it spoils, which
+    /// started at `start`: up to a `;` or line break outside brackets, or
+    /// to `end`, which is left for the caller. Returns the statement as a
+    /// `Node::Error` over what was skipped, unless that is nothing.
+    fn resync(&mut self, e: ParseError, start: usize, end: &Token) -> Option<Stmt> {
+        let mut to = e.span.end.max(start);
+        self.errors.push(e);
+
+        let mut brackets = 0usize;
+        loop {
+            let t = match self.tokens.peek() {
+                Ok(t) => t.clone(),
+                // The lexer has moved past what it could not read.
+                Err(e) => {
+                    self.errors.push(e.into());
+                    continue;
+                }
+            };
+            match t {
+                Token::Eof => break,
+                _ if brackets == 0 && (t == *end || t == Token::Semi || self.tokens.newline_before()) => break,
+                Token::LParen | Token::LBrace => brackets += 1,
+                Token::RParen | Token::RBrace => brackets = brackets.saturating_sub(1),
+                _ => {}
+            }
+            let _ = self.tokens.next();
+            to = to.max(self.tokens.span().end);
+        }
+
+        (to > start).then_some(Stmt::Expr(Node::Error(Span { start, end: to })))
+    }
+}
+
+/// Parses `s`, returning the tree (or the error that stopped parsing)
+/// together with the stray characters that were skipped on the way.
+pub fn parse(s: &[u8]) -> (Result<Node, ParseError>, Vec<ParseError>) {
+    let mut p = Parser::new(s, Extensions::default(), &OperatorTable::default(), Nodes);
+    (p.whole(), p.skipped)
+}
+
+/// Parses `s` with `b`, silently skipping stray characters. Returns `b`
+/// with what it built, and the expression it built last.
+#[cfg(feature = "std")]
+pub(crate) fn expr_into<B: Builder>(s: &[u8], b: B) -> (B, Result<B::Expr, ParseError>) {
+    let mut p = Parser::new(s, Extensions::default(), &OperatorTable::default(), b);
+    let result = p.whole();
+    (p.b, result)
+}
+
+/// Parses a whole program: any number of statements, each an expression,
+/// an assignment or a block. Stray characters are skipped as in `parse`.
+pub fn program(s: &[u8]) -> (Result<Program, ParseError>, Vec<ParseError>) {
+    program_from(s)
+}
+
+/// Like `program`, but reads the input from `r` as it goes rather than
+/// needing all of it up front.
+pub fn program_from(r: impl Source) -> (Result<Program, ParseError>, Vec<ParseError>) {
+    program_with(r, Extensions::default())
+}
+
+/// Like `program_from`, with the syntax extensions in `ext`.
+pub fn program_with(r: impl Source, ext: Extensions) -> (Result<Program, ParseError>, Vec<ParseError>) {
+    program_with_operators(r, ext, &OperatorTable::default())
+}
+
+/// Like `program_with`, with the operators in `ops` rather than the
+/// built-in ones.
+pub fn program_with_operators(
+    r: impl Source,
+    ext: Extensions,
+    ops: &OperatorTable,
+) -> (Result<Program, ParseError>, Vec<ParseError>) {
+    let mut p = Parser::new(r, ext, ops, Nodes);
+    let result = p.statements(Token::Eof).map(|stmts| Program { stmts });
+    (result, p.skipped)
+}
+
+/// Like `program_with_operators`, but does not stop at the first syntax
+/// error, for editors that want everything wrong with a file at once. A
+/// statement that fails to parse becomes an expression `Node::Error`
+/// spanning it, and parsing resumes after it, at the next `;` or line
+/// break outside brackets or at the `}` of its block. Returns the program
+/// with every error, stray characters included, in the order they are in
+/// the input.
+pub fn program_recovering(r: impl Source, ext: Extensions, ops: &OperatorTable) -> (Program, Vec<ParseError>) {
+    let mut p = Parser::new(r, ext, ops, Nodes);
+    p.recover = true;
+    let stmts = p.statements(Token::Eof).unwrap_or_else(|e| {
+        p.errors.push(e);
+        Vec::new()
+    });
+    let mut errors = p.errors;
+    errors.extend(p.skipped);
+    errors.sort_by_key(|e| e.span.start);
+    (Program { stmts }, errors)
+}
+
+/// Like `program_with_operators`, but parses `tokens` lexed beforehand,
+/// perhaps by another tool, rather than lexing any input.
+pub fn program_from_tokens(
+    tokens: Vec<Lexed>,
+    ext: Extensions,
+    ops: &OperatorTable,
+) -> (Result<Program, ParseError>, Vec<ParseError>) {
+    let mut p = Parser::with_lexer(Lexer::from_tokens(tokens), ext, ops, Nodes);
+    let result = p.statements(Token::Eof).map(|stmts| Program { stmts });
+    (result, p.skipped)
 }
 
-pub fn expr(s: &[u8]) -> Node {
-    let mut lexer = Lexer::new(s);
-    binexpr(&mut lexer, 0)
+/// What `resumable` parsed: each point where the parser was about to read
+/// a top-level statement, as the offset of the token next, with the
+/// statement read from there if it was one rather than a lone `;`. Then
+/// the point it stopped at, if it did not parse to the end or an error,
+/// and the stray characters it skipped.
+#[cfg(feature = "std")]
+pub(crate) type Resumed = (Vec<(usize, Option<Stmt>)>, Result<Option<usize>, ParseError>, Vec<ParseError>);
+
+/// The top-level statements of `tokens`, for `Document`. How the rest of
+/// the input parses from each point it reports depends only on the tokens
+/// from there on, so parsing stops at the first point after the first for
+/// which `stop` is true: the caller has the rest already.
+#[cfg(feature = "std")]
+pub(crate) fn resumable(tokens: Lexer<&[u8]>, ext: Extensions, ops: &OperatorTable, mut stop: impl FnMut(usize) -> bool) -> Resumed {
+    let mut p = Parser::with_lexer(tokens, ext, ops, Nodes);
+    let mut points = Vec::new();
+    let end = loop {
+        let at = match p.tokens.peek() {
+            Ok(Token::Eof) => break Ok(None),
+            Ok(_) => p.tokens.span().start,
+            Err(e) => break Err(e.clone().into()),
+        };
+        if !points.is_empty() && stop(at) {
+            break Ok(Some(at));
+        }
+        let mut out = Vec::new();
+        match p.separated(&Token::Eof, &mut out) {
+            Ok(done) => {
+                points.push((at, out.pop()));
+                if done {
+                    break Ok(None);
+                }
+            }
+            Err((e, _)) => break Err(e),
+        }
+    };
+    (points, end, p.skipped)
 }
 
-fn fac(n: i32) -> i32 {
-    match n {
-        0 | 1 => 1,
-        n => fac(n-1) * n,
+/// Parses `s` as a single expression. A stray character is an error, the
+/// first one there is; `parse` skips them instead.
+pub fn expr(s: &[u8]) -> Result<Node, ParseError> {
+    let (result, skipped) = parse(s);
+    match skipped.into_iter().next() {
+        Some(e) => Err(e),
+        None => result,
     }
 }
 
+/// Parses `s` as a single expression, like `expr` but with the extensions
+/// and operators given, and records every decision the parser makes on the
+/// way, up to an error if there is one.
+pub fn explain(s: &[u8], ext: Extensions, ops: &OperatorTable) -> (Result<Node, ParseError>, Vec<Step>) {
+    let mut p = Parser::new(s, ext, ops, Nodes);
+    p.trace = Some(Vec::new());
+    let result = p.whole();
+    (result, p.trace.unwrap_or_default())
+}
+
 impl NodeVal {
+    /// Every operator in the grammar paired with each arity it accepts
+    /// (1 for prefix/postfix, 2 for infix).
+    pub const SHAPES: [(NodeVal, usize); 27] = [
+        (NodeVal::Add, 1), (NodeVal::Sub, 1),
+        (NodeVal::Add, 2), (NodeVal::Sub, 2),
+        (NodeVal::Mul, 2), (NodeVal::Div, 2),
+        (NodeVal::Exp, 2), (NodeVal::Fac, 1),
+        (NodeVal::AddWrap, 2), (NodeVal::MulWrap, 2),
+        (NodeVal::AddSat, 2), (NodeVal::MulSat, 2),
+        (NodeVal::Lt, 2), (NodeVal::Le, 2),
+        (NodeVal::Gt, 2), (NodeVal::Ge, 2),
+        (NodeVal::Eq, 2), (NodeVal::Ne, 2),
+        (NodeVal::And, 2), (NodeVal::Or, 2),
+        (NodeVal::Cond, 3),
+        (NodeVal::Mod, 2), (NodeVal::Shl, 2),
+        (NodeVal::Shr, 2), (NodeVal::BitAnd, 2),
+        (NodeVal::BitOr, 2), (NodeVal::BitNot, 1),
+    ];
+
+    /// `"prefix"`, `"infix"` or `"postfix"` for this operator used with
+    /// `arity` operands.
+    pub fn fixity(&self, arity: usize) -> &'static str {
+        if let NodeVal::Call(_) = self {
+            return "call";
+        }
+        match arity {
+            1 if self.postfix_prec().is_some() => "postfix",
+            1 => "prefix",
+            3 => "ternary",
+            _ => "infix",
+        }
+    }
+
+    /// What the operator does when used with `arity` operands, which must
+    /// be one of its `SHAPES`.
+    pub fn doc(&self, arity: usize) -> Doc {
+        let (summary, example) = match (self, arity) {
+            (NodeVal::Add, 1) => ("The operand, unchanged.", "+7"),
+            (NodeVal::Sub, 1) => ("Negation.", "-(2 - 5)"),
+            (NodeVal::Add, _) => ("Addition. Integer overflow follows --arith.", "1 + 2"),
+            (NodeVal::Sub, _) => ("Subtraction.", "10 - 4 - 3"),
+            (NodeVal::Mul, _) => ("Multiplication.", "6 * 7"),
+            (NodeVal::Div, _) => ("Division. Between integers it truncates toward zero. Dividing by zero is an error.", "7 / 2"),
+            (NodeVal::Exp, _) => ("Exponentiation. A negative integer exponent truncates like 1 / a^n.", "2 ^ 3 ^ 2"),
+            (NodeVal::Fac, _) => ("Factorial of a non-negative integer.", "5!"),
+            (NodeVal::AddWrap, _) => ("Addition that wraps around at 32 bits, whatever --arith says.", "2147483647 +% 1"),
+            (NodeVal::MulWrap, _) => ("Multiplication that wraps around at 32 bits, whatever --arith says.", "65536 *% 65537"),
+            (NodeVal::AddSat, _) => ("Addition that saturates at the 32-bit limits, whatever --arith says.", "2147483647 +| 1"),
+            (NodeVal::MulSat, _) => ("Multiplication that saturates at the 32-bit limits, whatever --arith says.", "-65536 *| 65536"),
+            (NodeVal::Lt, _) => ("1 if the left operand is less than the right, otherwise 0. Comparisons cannot be chained.", "2 < 3"),
+            (NodeVal::Le, _) => ("1 if the left operand is at most the right, otherwise 0.", "3 <= 3"),
+            (NodeVal::Gt, _) => ("1 if the left operand is greater than the right, otherwise 0.", "2 > 3"),
+            (NodeVal::Ge, _) => ("1 if the left operand is at least the right, otherwise 0.", "2.5 >= 2"),
+            (NodeVal::Eq, _) => ("1 if the operands are equal, otherwise 0.", "1 == 1.0"),
+            (NodeVal::Ne, _) => ("1 if the operands differ, otherwise 0.", "1 != 2"),
+            (NodeVal::And, _) => ("1 if both operands are nonzero, otherwise 0. The right operand is only evaluated if the left is nonzero.", "2 && 0"),
+            (NodeVal::Or, _) => ("1 if either operand is nonzero, otherwise 0. The right operand is only evaluated if the left is zero.", "0 || 5"),
+            (NodeVal::Cond, _) => ("c ? a : b is a if c is nonzero, otherwise b. Only the operand chosen is evaluated.", "1 < 2 ? 10 : 20"),
+            (NodeVal::Mod, _) => ("Remainder of integer division, with the sign of the left operand. Without --calc; with it, % is a percentage.", "-7 % 3"),
+            (NodeVal::Shl, _) => ("Shift left: a * 2^b for a shift count b of at least 0. Overflow follows --arith.", "1 << 10"),
+            (NodeVal::Shr, _) => ("Arithmetic shift right, which rounds toward negative infinity.", "-16 >> 2"),
+            (NodeVal::BitAnd, _) => ("Bitwise and of two's-complement integers. Binds more loosely than comparisons, as in C.", "12 & 10"),
+            (NodeVal::BitOr, _) => ("Bitwise or of two's-complement integers. Binds more loosely than &.", "12 | 3"),
+            (NodeVal::BitNot, _) => ("Bitwise complement, which is -a - 1.", "~0"),
+            (NodeVal::Call(_), _) => panic!("functions are documented by their Builtins"),
+        };
+        Doc { summary, example }
+    }
+
+    /// The precedence of a binary operator, or of `?` in `c ? a : b`.
     pub fn infix_prec(&self) -> i32 {
         match self {
-            NodeVal::Add | NodeVal::Sub => 1,
-            NodeVal::Mul | NodeVal::Div => 3,
-            NodeVal::Exp => 7,
+            NodeVal::Cond => 0,
+            NodeVal::Or => 1,
+            NodeVal::And => 2,
+            NodeVal::BitOr => 3,
+            NodeVal::BitAnd => 4,
+            NodeVal::Lt | NodeVal::Le | NodeVal::Gt |
+            NodeVal::Ge | NodeVal::Eq | NodeVal::Ne => 5,
+            NodeVal::Shl | NodeVal::Shr => 6,
+            NodeVal::Add | NodeVal::Sub |
+            NodeVal::AddWrap | NodeVal::AddSat => 7,
+            NodeVal::Mul | NodeVal::Div | NodeVal::Mod |
+            NodeVal::MulWrap | NodeVal::MulSat => 9,
+            NodeVal::Exp => 13,
             _ => panic!(),
         }
     }
 
+    /// Comparisons are parsed as left-associative, but the parser rejects
+    /// chaining them.
     pub fn is_lassoc(&self) -> bool {
-        match self {
-            NodeVal::Exp | NodeVal::Mul => false,
-            _ => true,
-        }
+        !matches!(self, NodeVal::Exp | NodeVal::Cond)
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        matches!(self, NodeVal::Lt | NodeVal::Le | NodeVal::Gt | NodeVal::Ge | NodeVal::Eq | NodeVal::Ne)
     }
 
     pub fn prefix_prec(&self) -> i32 {
         match self {
-            NodeVal::Add | NodeVal::Sub => 5,
-                                      _ => panic!(),
+            NodeVal::Add | NodeVal::Sub | NodeVal::BitNot => 11,
+                                                      _ => panic!(),
         }
     }
 
     pub fn postfix_prec(&self) -> Option<i32> {
         match self {
-            NodeVal::Fac => Some(6),
+            NodeVal::Fac => Some(12),
                        _ => None,
         }
     }
 
-    pub fn apply(&self, args: &[i32]) -> i32 {
-        match self {
-            NodeVal::Add => args.iter().sum(),
-            NodeVal::Sub => {
-                match args.len() {
-                    1 => -args[0],
-                    2 => args[0]-args[1],
-                    _ => panic!(),
-                }
-            },
-            NodeVal::Mul => args.iter().product(),
-            NodeVal::Div => {
-                assert_eq!(args.len(), 2);
-                args[0]/args[1]
-            },
-            NodeVal::Exp => {
-                assert_eq!(args.len(), 2);
-                args[0].pow(args[1] as u32)
-            },
-            NodeVal::Fac => {
-                assert_eq!(args.len(), 1);
-                fac(args[0])
-            },
+    /// For `&&`, `||` and `?:`, what to do given the truth of the first
+    /// operand; `None` for operators that need all their operands.
+    pub fn short_circuit(&self, first: bool) -> Option<ShortCircuit> {
+        Some(match (self, first) {
+            (NodeVal::And, false) => ShortCircuit::Done(false),
+            (NodeVal::Or, true) => ShortCircuit::Done(true),
+            (NodeVal::And | NodeVal::Or, _) => ShortCircuit::Truth(1),
+            (NodeVal::Cond, true) => ShortCircuit::Operand(1),
+            (NodeVal::Cond, false) => ShortCircuit::Operand(2),
+            _ => return None,
+        })
+    }
+
+    /// 1 if the comparison holds between `a` and `b`, otherwise 0.
+    pub fn compare<T: PartialOrd>(&self, a: T, b: T) -> i32 {
+        let holds = match self {
+            NodeVal::Lt => a < b,
+            NodeVal::Le => a <= b,
+            NodeVal::Gt => a > b,
+            NodeVal::Ge => a >= b,
+            NodeVal::Eq => a == b,
+            NodeVal::Ne => a != b,
+            _ => panic!("{self} is not a comparison"),
+        };
+        holds as i32
+    }
+
+    /// Applies the operator to already evaluated operands, with integer
+    /// overflow handled as `mode` says. Calls need a `Builtins` table, so
+    /// `eval` evaluates them instead.
+    pub fn apply(&self, args: &[Value], mode: ArithMode) -> Result<Value, ArithError> {
+        if let NodeVal::Call(name) = self {
+            panic!("{name}() can only be evaluated with builtins");
+        }
+        if let Some(sc) = self.short_circuit(args[0].is_true()) {
+            return Ok(match sc {
+                ShortCircuit::Done(b) => Value::Int(b as i32),
+                ShortCircuit::Truth(i) => Value::Int(args[i].is_true() as i32),
+                ShortCircuit::Operand(i) => args[i],
+            });
+        }
+
+        if args.iter().any(|a| matches!(a, Value::Quantity(..))) {
+            return crate::units::apply(self, args);
+        }
+        if args.iter().any(|a| matches!(a, Value::Duration(_))) {
+            return crate::duration::apply(self, args);
+        }
+
+        let ints: Option<Vec<i128>> = args.iter().map(|a| match a {
+            Value::Int(v) => Some(*v as i128),
+            Value::Wide(v) => Some(*v),
+            Value::Float(_) | Value::Duration(_) | Value::Quantity(..) => None,
+        }).collect();
+
+        match ints {
+            Some(ints) => mode.apply(self, &ints),
+            None if self.is_comparison() => Ok(Value::Int(self.compare(args[0].as_f64(), args[1].as_f64()))),
+            None => self.apply_float(&args.iter().map(|a| a.as_f64()).collect::<Vec<_>>()).map(Value::Float),
         }
     }
+
+    fn apply_float(&self, args: &[f64]) -> Result<f64, ArithError> {
+        Ok(match (self, args) {
+            (NodeVal::Add, [a]) => *a,
+            (NodeVal::Sub, [a]) => -a,
+            (NodeVal::Add, [a, b]) => a + b,
+            (NodeVal::Sub, [a, b]) => a - b,
+            (NodeVal::Mul, [a, b]) => a * b,
+            (NodeVal::Div, [a, b]) => a / b,
+            (NodeVal::Exp, [a, b]) => a.powf(*b),
+            // `!`, the operators that model fixed-width integers, and the
+            // bitwise ones.
+            (_, [_]) | (_, [_, _]) => return Err(ArithError::NotInteger(self.clone(), NonInteger::Float)),
+            _ => panic!("Operator {self} cannot take {} operands", args.len()),
+        })
+    }
 }
 
 impl From<Token> for LeafVal {
     fn from(t: Token) -> Self {
         match t {
             Token::Int(v) => Self::Int(v),
+            Token::Bool(b) => Self::Bool(b),
+            Token::Decimal(v) => Self::Decimal(v),
             Token::Sym(v) => Self::Sym(v),
+            Token::Placeholder(n) => Self::Placeholder(n),
                         _ => panic!(),
         }
     }
 }
 
-impl From<&Token> for NodeVal {
-    fn from(t: &Token) -> Self {
-        match t {
+impl TryFrom<&Token> for NodeVal {
+    type Error = ();
+
+    fn try_from(t: &Token) -> Result<Self, ()> {
+        Ok(match t {
             Token::Plus  => NodeVal::Add,
             Token::Minus => NodeVal::Sub,
             Token::Star  => NodeVal::Mul,
             Token::Slash => NodeVal::Div,
             Token::Caret => NodeVal::Exp,
             Token::Fac   => NodeVal::Fac,
-                       _ => panic!(),
-        }
+            Token::PlusWrap => NodeVal::AddWrap,
+            Token::StarWrap => NodeVal::MulWrap,
+            Token::PlusSat  => NodeVal::AddSat,
+            Token::StarSat  => NodeVal::MulSat,
+            Token::Lt => NodeVal::Lt,
+            Token::Le => NodeVal::Le,
+            Token::Gt => NodeVal::Gt,
+            Token::Ge => NodeVal::Ge,
+            Token::Eq => NodeVal::Eq,
+            Token::Ne => NodeVal::Ne,
+            Token::AndAnd => NodeVal::And,
+            Token::OrOr => NodeVal::Or,
+            Token::Question => NodeVal::Cond,
+            Token::Percent => NodeVal::Mod,
+            Token::Shl => NodeVal::Shl,
+            Token::Shr => NodeVal::Shr,
+            Token::Amp => NodeVal::BitAnd,
+            Token::Pipe => NodeVal::BitOr,
+            Token::Tilde => NodeVal::BitNot,
+                       _ => return Err(()),
+        })
     }
 }
 
@@ -167,7 +1549,10 @@ impl fmt::Display for LeafVal {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", match self {
             LeafVal::Int(v) => v.to_string(),
-            LeafVal::Sym(v) => v.to_string(),
+            LeafVal::Bool(b) => b.to_string(),
+            LeafVal::Decimal(v) => v.to_string(),
+            LeafVal::Sym(v) => crate::ast::quote(v).into_owned(),
+            LeafVal::Placeholder(n) => format!("?{n}"),
         })
     }
 }
@@ -181,55 +1566,463 @@ impl fmt::Display for NodeVal {
             NodeVal::Div => "/",
             NodeVal::Exp => "^",
             NodeVal::Fac => "!",
+            NodeVal::AddWrap => "+%",
+            NodeVal::MulWrap => "*%",
+            NodeVal::AddSat => "+|",
+            NodeVal::MulSat => "*|",
+            NodeVal::Lt => "<",
+            NodeVal::Le => "<=",
+            NodeVal::Gt => ">",
+            NodeVal::Ge => ">=",
+            NodeVal::Eq => "==",
+            NodeVal::Ne => "!=",
+            NodeVal::And => "&&",
+            NodeVal::Or => "||",
+            NodeVal::Cond => "?:",
+            NodeVal::Mod => "%",
+            NodeVal::Shl => "<<",
+            NodeVal::Shr => ">>",
+            NodeVal::BitAnd => "&",
+            NodeVal::BitOr => "|",
+            NodeVal::BitNot => "~",
+            NodeVal::Call(name) => name,
         })
     }
 }
 
-impl fmt::Display for Node {
+/// Floats always show a fractional part or exponent (`1.0`, `1e-7`), so
+/// they can't be mistaken for integers.
+impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Leaf(v) => write!(f, "{v}")?,
-            Self::Node { v, children } => {
-                write!(f, "({}", v)?;
-                for i in children {
-                    write!(f, " {}", i)?;
+            Value::Int(v) => write!(f, "{v}"),
+            Value::Float(v) => write!(f, "{v:?}"),
+            Value::Wide(v) => write!(f, "{v}"),
+            Value::Duration(v) => write!(f, "{}", crate::duration::format(*v)),
+            Value::Quantity(v, unit) => write!(f, "{v:?} {unit}"),
+        }
+    }
+}
+
+/// A precision limits how deep each expression prints, as for `Node`.
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Parts are formatted with `f` itself, which keeps the precision.
+        match self {
+            Stmt::Assign { name, value, .. } => {
+                write!(f, "(= {name} ")?;
+                value.fmt(f)?;
+            }
+            Stmt::Def { name, params, body, .. } => {
+                write!(f, "(= ({name}")?;
+                for p in params {
+                    write!(f, " {p}")?;
+                }
+                f.write_str(") ")?;
+                body.fmt(f)?;
+            }
+            Stmt::Expr(e) => return e.fmt(f),
+            Stmt::Block { stmts, .. } => {
+                f.write_str("(block")?;
+                for s in stmts {
+                    f.write_str(" ")?;
+                    s.fmt(f)?;
                 }
-                write!(f, ")")?;
+            }
+            Stmt::If { cond, then, els, .. } => {
+                f.write_str("(if ")?;
+                cond.fmt(f)?;
+                f.write_str(" ")?;
+                then.fmt(f)?;
+                if let Some(els) = els {
+                    f.write_str(" ")?;
+                    els.fmt(f)?;
+                }
+            }
+            Stmt::While { cond, body, .. } => {
+                f.write_str("(while ")?;
+                cond.fmt(f)?;
+                f.write_str(" ")?;
+                body.fmt(f)?;
             }
         }
+        f.write_str(")")
+    }
+}
+
+/// One statement per line.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for s in &self.stmts {
+            s.fmt(f)?;
+            f.write_str("\n")?;
+        }
         Ok(())
     }
 }
 
+/// With a precision, as in `{:.3}`, subtrees deeper than that many levels
+/// print as `...`, or with `#` as their size, like `<87 nodes>`. Trees
+/// print without recursion, however deep they are.
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_tree(f, Nodes, self)
+    }
+}
+
+/// Writes the tree under `root` as an S-expression, as `Display` does for
+/// `Node`.
+pub(crate) fn write_tree<'a, T: Tree<'a>>(f: &mut fmt::Formatter<'_>, tree: T, root: T::Id) -> fmt::Result {
+    enum Piece<Id> {
+        Tree(Id, usize),
+        Text(&'static str),
+    }
+
+    let max = f.precision().unwrap_or(usize::MAX);
+    let mut pieces = vec![Piece::Tree(root, 1)];
+    while let Some(piece) = pieces.pop() {
+        match piece {
+            Piece::Text(s) => f.write_str(s)?,
+            Piece::Tree(id, depth) if depth > max && f.alternate() => match size(tree, id) {
+                1 => f.write_str("<1 node>")?,
+                n => write!(f, "<{n} nodes>")?,
+            },
+            Piece::Tree(_, depth) if depth > max => f.write_str("...")?,
+            Piece::Tree(id, depth) => match tree.view(id) {
+                View::Leaf(v, _) => write!(f, "{v}")?,
+                View::Error(_) => f.write_str("<error>")?,
+                View::Node(v, children, _) => {
+                    match v {
+                        NodeVal::Call(name) => write!(f, "({}", crate::ast::quote(name))?,
+                        v => write!(f, "({v}")?,
+                    }
+                    pieces.push(Piece::Text(")"));
+                    for c in children.rev() {
+                        pieces.extend([Piece::Tree(c, depth + 1), Piece::Text(" ")]);
+                    }
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+/// How many nodes the tree under `root` has, leaves included.
+pub(crate) fn size<'a, T: Tree<'a>>(tree: T, root: T::Id) -> usize {
+    let (mut n, mut todo) = (0, vec![root]);
+    while let Some(id) = todo.pop() {
+        n += 1;
+        if let View::Node(_, children, _) = tree.view(id) {
+            todo.extend(children);
+        }
+    }
+    n
+}
+
+#[test]
+fn overflow_ops() {
+    let eval = |s: &[u8]| {
+        let Node::Node { v, children, .. } = &expr(s).unwrap() else { panic!() };
+        let args: Vec<Value> = children.iter().map(|c| match c {
+            Node::Leaf(LeafVal::Int(v), _) => Value::Int(*v),
+            Node::Leaf(LeafVal::Decimal(s), _) => Value::Float(s.parse().unwrap()),
+            _ => panic!(),
+        }).collect();
+        v.apply(&args, ArithMode::default()).unwrap()
+    };
+
+    assert_eq!(eval(b"2147483647 +% 1"), Value::Int(i32::MIN));
+    assert_eq!(eval(b"2147483647 +| 1"), Value::Int(i32::MAX));
+    assert_eq!(eval(b"65536 *% 65536"), Value::Int(0));
+    assert_eq!(eval(b"65536 *| 65536"), Value::Int(i32::MAX));
+
+    assert_eq!(eval(b"1 / 2"), Value::Int(0));
+    assert_eq!(eval(b"1.0 / 2"), Value::Float(0.5));
+    assert_eq!(eval(b"2 ^ 0.5"), Value::Float(2f64.sqrt()));
+    assert_eq!(eval(b"1e-3 * 2.5E2").to_string(), "0.25");
+    assert_eq!(Value::Float(3.0).to_string(), "3.0");
+    assert_eq!(eval(b"2 < 3"), Value::Int(1));
+    assert_eq!(eval(b"2 >= 3"), Value::Int(0));
+    assert_eq!(eval(b"0.5 == 0.5"), Value::Int(1));
+    assert_eq!(eval(b"1 != 1.0"), Value::Int(0));
+    assert_eq!(eval(b"0.5 && 2"), Value::Int(1));
+    assert_eq!(eval(b"0 || 0"), Value::Int(0));
+    assert_eq!(eval(b"0.0 ? 1 : 2.5"), Value::Float(2.5));
+}
+
 #[test]
 fn tests() {
-    let s = expr(b"1");
+    let s = expr(b"1").unwrap();
     assert_eq!(s.to_string(), "1");
 
-    let s = expr(b"1 + 2 * 3");
+    let s = expr(b"1 + 2 * 3").unwrap();
     assert_eq!(s.to_string(), "(+ 1 (* 2 3))");
 
-    let s = expr(b"a + b * c * d + e");
+    let s = expr(b"a + b * c * d + e").unwrap();
     assert_eq!(s.to_string(), "(+ (+ a (* (* b c) d)) e)");
 
-    let s = expr(b"f ^ g ^ h");
+    let s = expr(b"f ^ g ^ h").unwrap();
     assert_eq!(s.to_string(), "(^ f (^ g h))");
 
-    let s = expr(b" 1 + 2 + f ^ g ^ h * 3 * 4");
+    let s = expr(b" 1 + 2 + f ^ g ^ h * 3 * 4").unwrap();
     assert_eq!(s.to_string(), "(+ (+ 1 2) (* (* (^ f (^ g h)) 3) 4))");
 
-    let s = expr(b"--1 * 2");
+    let s = expr(b"--1 * 2").unwrap();
     assert_eq!(s.to_string(), "(* (- (- 1)) 2)");
 
-    let s = expr(b"--f ^ g");
+    let s = expr(b"--f ^ g").unwrap();
     assert_eq!(s.to_string(), "(- (- (^ f g)))");
 
-    let s = expr(b"-9!");
+    let s = expr(b"-9!").unwrap();
     assert_eq!(s.to_string(), "(- (! 9))");
 
-    let s = expr(b"f ^ g !");
+    let s = expr(b"f ^ g !").unwrap();
     assert_eq!(s.to_string(), "(! (^ f g))");
 
-    let s = expr(b"(((0)))");
+    let s = expr(b"1.5 + 1e-3 * 2.5E+2").unwrap();
+    assert_eq!(s.to_string(), "(+ 1.5 (* 1e-3 2.5E+2))");
+
+    let s = expr(b"max(1, a + 2) * f() - abs(-b)!").unwrap();
+    assert_eq!(s.to_string(), "(- (* (max 1 (+ a 2)) (f)) (! (abs (- b))))");
+
+    let s = expr(b"min(1)").unwrap();
+    assert_eq!(s.span(), Span { start: 0, end: 6 });
+
+    let s = expr(b"(((0)))").unwrap();
     assert_eq!(s.to_string(), "0");
+
+    let s = expr(b"a +% b *| c *% d +| e").unwrap();
+    assert_eq!(s.to_string(), "(+| (+% a (*% (*| b c) d)) e)");
+
+    let s = expr(b"1 + $2 @* 3 #").unwrap_err();
+    assert_eq!(s.to_string(), "syntax error at 4: unexpected character \"$\"");
+    assert_eq!(parse(b"1 + $2 @* 3 #").0.unwrap().to_string(), "(+ 1 (* 2 3))");
+
+    let s = expr(b"a + 1 <= b * 2 != (c == 3!)").unwrap_err();
+    assert_eq!(s.to_string(), "syntax error at 15: comparisons cannot be chained; parenthesize the operands of '!='");
+
+    let s = expr(b"(a + 1 <= b * 2) != (c == 3! > -d)").unwrap_err();
+    assert_eq!(s.span.start, 29);
+
+    let s = expr(b"(a + 1 <= b * 2) != (c == 3!)").unwrap();
+    assert_eq!(s.to_string(), "(!= (<= (+ a 1) (* b 2)) (== c (! 3)))");
+
+    let s = expr(b"a || b && c == d ? e ? 1 : 2 : f ? g : h").unwrap();
+    assert_eq!(s.to_string(), "(?: (|| a (&& b (== c d))) (?: e 1 2) (?: f g h))");
+
+    // C precedence: `&` binds looser than `==`, shifts looser than `+`.
+    let s = expr(b"a | b & 1 == c && d << 2 + e >> f % 3 * g | ~-h!").unwrap();
+    assert_eq!(s.to_string(), "(&& (| a (& b (== 1 c))) (| (>> (<< d (+ 2 e)) (* (% f 3) g)) (~ (- (! h)))))");
+
+    let s = expr(b"-a ? b : c").unwrap();
+    assert_eq!(s.span(), Span { start: 0, end: 10 });
+
+    let s = expr(b"a ? b").unwrap_err();
+    assert_eq!(s.to_string(), "syntax error at 5: expected ':', found end of input");
+    assert_eq!(s.kind.code(), "unexpected-token");
+
+    let s = expr(b"a<b>=c").unwrap_err();
+    assert_eq!(s.kind, ParseErrorKind::ChainedComparison("'>='".into()));
+
+    let (_, skipped) = parse(b"1 + $2 @* 3 `");
+    assert_eq!(skipped.iter().map(|e| e.span.start).collect::<Vec<_>>(), [4, 7, 12]);
+
+    // A comment with a line break in it ends the statement, like the line
+    // break would.
+    let (p, _) = program(b"x = 1 # one\ny = x /* two */ * 2 /* ends\nthe line */ y // three");
+    assert_eq!(p.unwrap().to_string(), "(= x 1)\n(= y (* x 2))\ny\n");
+    let e = program(b"x = 1 /* x").0.unwrap_err();
+    assert_eq!((e.kind.code(), e.span), ("unterminated-comment", Span { start: 6, end: 8 }));
+
+    // Tokens from elsewhere keep their spans and line breaks, and need not
+    // be spelled the way the lexer would have them.
+    let mut tokens = Lexer::from_bytes("x = 1 \u{20ac}\ny = x * 2".as_bytes()).tokens().unwrap();
+    tokens[6].token = Token::Sym("my-x".into());
+    let (p, skipped) = program_from_tokens(tokens.clone(), Extensions::default(), &OperatorTable::default());
+    let p = p.unwrap();
+    assert_eq!(p.to_string(), "(= x 1)\n(= y (* |my-x| 2))\n");
+    assert_eq!(p.stmts[1].exprs()[0].span(), Span { start: 14, end: 19 });
+    assert_eq!((skipped[0].kind.to_string(), skipped[0].span), ("unexpected character \"\u{20ac}\"".into(), Span { start: 6, end: 9 }));
+    tokens[4].newline = false;
+    let e = program_from_tokens(tokens, Extensions::default(), &OperatorTable::default()).0.unwrap_err();
+    assert_eq!((e.kind.code(), e.span), ("unexpected-token", Span { start: 10, end: 11 }));
+
+    let s = expr(b"-a + b ! * (c)").unwrap();
+    let Node::Node { children, span, .. } = &s else { panic!() };
+    assert_eq!(*span, Span { start: 0, end: 14 });
+    assert_eq!(children[0].span(), Span { start: 0, end: 2 });
+    assert_eq!(children[1].span(), Span { start: 5, end: 14 });
+
+    let prog = |s: &[u8]| program(s).0.map(|p| p.stmts.iter().map(|s| s.to_string()).collect::<Vec<_>>().join("; "));
+    assert_eq!(prog(b"x = 3 + 4\ny = x\n  * 2; x - y\n"), Ok("(= x (+ 3 4)); (= y (* x 2)); (- x y)".into()));
+    assert_eq!(prog(b"a\n-1\n(b)"), Ok("(- a 1); b".into()));
+    assert_eq!(prog(b"").map_err(drop), Ok("".into()));
+    assert_eq!(prog(b"f(x, y) = x ^ 2 + y; f(1, 2)"), Ok("(= (f x y) (+ (^ x 2) y)); (f 1 2)".into()));
+    assert_eq!(prog(b"a + 1 = 2").unwrap_err().to_string(), "syntax error at 0: can only assign to a symbol or define f(x, ...), not (+ a 1)");
+    assert_eq!(prog(b"f(x, 1) = x").unwrap_err().to_string(), "syntax error at 0: can only assign to a symbol or define f(x, ...), not (f x 1)");
+    assert_eq!(prog(b"f(x, x) = x").unwrap_err().to_string(), "syntax error at 5: parameter x is declared twice");
+    assert_eq!(prog(b"a b").unwrap_err().to_string(), "syntax error at 2: expected operator, found symbol b");
+    assert_eq!(prog(b"x = 1; { y = x + 1\n  z = y } z"), Ok("(= x 1); (block (= y (+ x 1)) (= z y)); z".into()));
+    assert_eq!(prog(b"{}{ {1}; }"), Ok("(block); (block (block 1))".into()));
+    assert_eq!(prog(b"if (x < 1) y = 1 else if (x) { y = 2 } z"), Ok("(if (< x 1) (= y 1) (if x (block (= y 2)))); z".into()));
+    assert_eq!(prog(b"while (i < 3)\n  i = i + 1\nif (i)\n  1\nelse\n  2"), Ok("(while (< i 3) (= i (+ i 1))); (if i 1 2)".into()));
+    assert_eq!(prog(b"if x 1").unwrap_err().to_string(), "syntax error at 3: expected '(', found symbol x");
+    assert_eq!(prog(b"else = 1").unwrap_err().to_string(), "syntax error at 0: expected literal, found 'else'");
+    assert_eq!(prog(b"{ 1; 2").unwrap_err().to_string(), "syntax error at 6: expected '}', found end of input");
+    assert_eq!(prog(b"1 }").unwrap_err().to_string(), "syntax error at 2: expected end of statement, found '}'");
+
+    let calc = |s: &[u8]| {
+        let ext = Extensions { calculator: true, ..Extensions::default() };
+        program_with(s, ext).0.map(|p| p.stmts.iter().map(|s| s.to_string()).collect::<Vec<_>>().join("; "))
+    };
+    assert_eq!(calc(b"20% of 300 + 50"), Ok("(/ (* 20 (+ 300 50)) 100.0)".into()));
+    assert_eq!(calc(b"x = 3 per 4 of y; 2 * 5%"), Ok("(= x (/ 3 (* 4 y))); (* 2 (/ 5 100.0))".into()));
+    assert_eq!(calc(b"a < 10% of b"), Ok("(< a (/ (* 10 b) 100.0))".into()));
+    assert_eq!(calc(b"of = 1\nof of 2"), Ok("(= of 1); (* of 2)".into()));
+    assert_eq!(calc(b"a of b < c < d").unwrap_err().kind, ParseErrorKind::ChainedComparison("'<'".into()));
+
+    let durations = |s: &[u8]| {
+        let ext = Extensions { durations: true, ..Extensions::default() };
+        program_with(s, ext).0.map(|p| p.stmts.iter().map(|s| s.to_string()).collect::<Vec<_>>().join("; "))
+    };
+    assert_eq!(durations(b"3h + 45 min"), Ok("(+ (duration 3 3600000) (duration 45 60000))".into()));
+    assert_eq!(durations(b"1.5d * h"), Ok("(* (duration 1.5 86400000) h)".into()));
+    assert_eq!(durations(b"x = 2\nh"), Ok("(= x 2); h".into()));
+    assert_eq!(durations(b"min(1ms, 2)"), Ok("(min (duration 1 1) 2)".into()));
+    let bytes = |s: &[u8]| {
+        let ext = Extensions { bytes: true, ..Extensions::default() };
+        program_with(s, ext).0.map(|p| p.stmts.iter().map(|s| s.to_string()).collect::<Vec<_>>().join("; "))
+    };
+    assert_eq!(bytes(b"4KiB + 1.5 GB"), Ok("(+ (bytes 4 1024 1) (bytes 1.5 1000 3))".into()));
+    assert_eq!(bytes(b"2 * B"), Ok("(* 2 B)".into()));
+    assert_eq!(bytes(b"3h").unwrap_err().to_string(), "syntax error at 1: expected operator, found symbol h");
+    let units = |s: &[u8], durations| {
+        let ext = Extensions { units: true, durations, ..Extensions::default() };
+        program_with(s, ext).0.map(|p| p.stmts.iter().map(|s| s.to_string()).collect::<Vec<_>>().join("; "))
+    };
+    assert_eq!(units(b"3 m / 2s", false), Ok("(/ (quantity 3 0) (quantity 2 7))".into()));
+    assert_eq!(units(b"9.81 N * m", false), Ok("(* (quantity 9.81 16) m)".into()));
+    assert_eq!(units(b"2 h", true), Ok("(duration 2 3600000)".into()));
+    let implicit = |s: &[u8], ext: Extensions| {
+        let ext = Extensions { implicit_mul: true, ..ext };
+        program_with(s, ext).0.map(|p| p.stmts.iter().map(|s| s.to_string()).collect::<Vec<_>>().join("; "))
+    };
+    let plain = Extensions::default();
+    assert_eq!(implicit(b"2x + 3(x + 1)", plain), Ok("(+ (* 2 x) (* 3 (+ x 1)))".into()));
+    assert_eq!(implicit(b"(a)(b) c - 2x^2 y", plain), Ok("(- (* (* a b) c) (* (* 2 (^ x 2)) y))".into()));
+    // Like `*`, so a product after `/` is not the divisor.
+    assert_eq!(implicit(b"x = 2\n4f(x) / 2pi ?1", plain), Ok("(= x 2); (* (* (/ (* 4 (f x)) 2) pi) ?1)".into()));
+    assert_eq!(implicit(b"-2x! < 3 y ? 1 : 2", plain), Ok("(?: (< (* (- 2) (! x)) (* 3 y)) 1 2)".into()));
+    assert_eq!(implicit(b"2 3h", Extensions { durations: true, ..plain }), Ok("(* 2 (duration 3 3600000))".into()));
+
+    let words = |s: &[u8]| {
+        let ext = Extensions { words: true, ..Extensions::default() };
+        program_with(s, ext).0.map(|p| p.stmts.iter().map(|s| s.to_string()).collect::<Vec<_>>().join("; "))
+    };
+    assert_eq!(words(b"2 times 3 plus x divided  by 4"), Ok("(+ (* 2 3) (/ x 4))".into()));
+    assert_eq!(words(b"minus 2 to the power of\tn minus 1"), Ok("(- (- (^ 2 n)) 1)".into()));
+    // Only whole words on one line: `timestamp` and `divided` alone are names.
+    assert_eq!(words(b"timestamp plus divided"), Ok("(+ timestamp divided)".into()));
+    assert!(words(b"1 divided\nby 2").is_err());
+    assert!(program(b"2 times 3").0.is_err());
+    assert_eq!(implicit(b"a < b c < d", plain).unwrap_err().kind, ParseErrorKind::ChainedComparison("'<'".into()));
+    // Without the extension, `%` is the remainder and `of` a symbol.
+    let (result, skipped) = program(b"20% of 2");
+    assert_eq!(result.unwrap_err().to_string(), "syntax error at 7: expected operator, found integer 2");
+    assert!(skipped.is_empty());
+
+    let err = |s: &[u8]| expr(s).err().map(|e| e.to_string());
+    assert_eq!(err(b"1 + (2 * 3"), Some("syntax error at 10: expected ')', found end of input".into()));
+    assert_eq!(err(b"1 2"), Some("syntax error at 2: expected operator, found integer 2".into()));
+    assert_eq!(err(b"1 + * 2"), Some("syntax error at 4: expected literal, found '*'".into()));
+    assert_eq!(err(b"1 + 2)"), Some("syntax error at 5: expected end of input, found ')'".into()));
+    assert_eq!(err(b"max(1 2)"), Some("syntax error at 6: expected operator, found integer 2".into()));
+    assert_eq!(err(b"max(1,)"), Some("syntax error at 6: expected literal, found ')'".into()));
+    assert_eq!(err(b"2e"), Some("syntax error at 1: expected operator, found symbol e".into()));
+    assert_eq!(err(b"99999999999"), Some("syntax error at 0: integer literal 99999999999 is out of range for i32".into()));
+
+    // Lexer errors are the source of the parse errors they cause.
+    use std::error::Error;
+    let e = expr(b"1 + 99999999999").unwrap_err();
+    let lexed = e.source().and_then(|s| s.downcast_ref::<LexError>()).unwrap();
+    assert_eq!(lexed.kind, LexErrorKind::IntegerOverflow("99999999999".into()));
+    assert!(expr(b"1 2").unwrap_err().source().is_none());
+
+    // Deep input is an error rather than a stack overflow, and nesting
+    // that doesn't deepen the tree is no error at all.
+    let deep = |s: String| expr(s.as_bytes()).unwrap_err().kind;
+    let n = 100_000;
+    assert_eq!(expr(format!("{}1{}", "(".repeat(n), ")".repeat(n)).as_bytes()).unwrap().to_string(), "1");
+    assert_eq!(expr(format!("{}x{}", "f((".repeat(300), "))".repeat(300)).as_bytes()).unwrap().to_string().len(), 1201);
+    assert_eq!(deep(format!("{}1", "-".repeat(n))), ParseErrorKind::TooDeep(MAX_DEPTH));
+    assert_eq!(deep(format!("{}1", "2 ^ ".repeat(n))), ParseErrorKind::TooDeep(MAX_DEPTH));
+    // The error is the tree that is too deep, even one that is only known
+    // to be at the end of the input.
+    let s = format!("{}1", "2 ^ ".repeat(n));
+    assert_eq!(expr(s.as_bytes()).unwrap_err().span, Span { start: s.len() - 4 * MAX_DEPTH - 1, end: s.len() });
+    assert_eq!(deep(format!("{}1", "x ? 1 : ".repeat(n))), ParseErrorKind::TooDeep(MAX_DEPTH));
+    assert_eq!(deep(format!("{}1{}", "g(1, ".repeat(n), ")".repeat(n))), ParseErrorKind::TooDeep(MAX_DEPTH));
+    assert!(expr(format!("{}1", "2 ^ ".repeat(MAX_DEPTH - 1)).as_bytes()).is_ok());
+    assert_eq!(deep(format!("1{}", " + 1".repeat(MAX_DEPTH))), ParseErrorKind::TooDeep(MAX_DEPTH));
+    assert_eq!(deep(format!("1{}", "!".repeat(MAX_DEPTH))), ParseErrorKind::TooDeep(MAX_DEPTH));
+    assert!(expr(format!("1{}", " + 1".repeat(MAX_DEPTH - 1)).as_bytes()).is_ok());
+    let (result, _) = program(format!("{}{}", "{".repeat(MAX_NESTING + 1), "}".repeat(MAX_NESTING + 1)).as_bytes());
+    assert_eq!(result.unwrap_err().kind, ParseErrorKind::TooDeep(MAX_NESTING));
+
+    // Trees as deep as the parser allows print on a small stack, and a
+    // precision elides what is deeper.
+    let s = format!("{}x", "-".repeat(MAX_DEPTH - 1));
+    assert_eq!(expr(s.as_bytes()).unwrap().to_string(), format!("{}x{}", "(- ".repeat(MAX_DEPTH - 1), ")".repeat(MAX_DEPTH - 1)));
+    let e = expr(b"1 + 2 * abs(f(3, x))").unwrap();
+    assert_eq!(format!("{e:.3}"), "(+ 1 (* 2 (abs ...)))");
+    assert_eq!(format!("{e:.1} {e:.0}"), "(+ ... ...) ...");
+    assert_eq!(format!("{e:#.2} {e:#.0}"), "(+ 1 (* <1 node> <4 nodes>)) <8 nodes>");
+    assert_eq!(format!("{e:#}"), e.to_string());
+    assert_eq!((e.size(), expr(s.as_bytes()).unwrap().size()), (8, MAX_DEPTH));
+    let (p, _) = program(b"f(x) = -x\nif (f(1) < 0) { y = 2 * 3 }");
+    assert_eq!(format!("{:.2}", p.unwrap()), "(= (f x) (- x))\n(if (< (f ...) 0) (block (= y (* 2 3))))\n");
+
+    let mut e = expr(b"a + f(1)").unwrap();
+    e.shift(10);
+    let Node::Node { children, span, .. } = &e else { unreachable!() };
+    assert_eq!((*span, children[1].span()), (Span { start: 10, end: 18 }, Span { start: 14, end: 18 }));
+
+    // Recovering, every bad statement is an error of its own, and the rest
+    // still parses.
+    let recover = |s: &str| {
+        let (p, errors) = program_recovering(s.as_bytes(), Extensions::default(), &OperatorTable::default());
+        let errors: Vec<String> = errors.iter().map(|e| format!("{} {}", e.span.start, e.kind)).collect();
+        (p.to_string(), errors)
+    };
+    assert_eq!(
+        recover("a = 1 ) + 2; b = 2\nc = * 3\nd = 4"),
+        ("(= a 1)\n<error>\n(= b 2)\n<error>\n(= d 4)\n".to_string(), vec![
+            "6 expected end of statement, found ')'".to_string(),
+            "23 expected literal, found '*'".to_string(),
+        ])
+    );
+    // A `;` or `}` the error is about still ends the statement, and a
+    // line break inside brackets does not.
+    assert_eq!(recover("if (x) { y = ( ; z = 1 }").0, "(if x (block <error> (= z 1)))\n");
+    assert_eq!(recover("while (x) {\n y = *\n}\nw = 1").0, "(while x (block <error>))\n(= w 1)\n");
+    assert_eq!(recover("f(1,\n2 +) + 1\nok = 1"), ("<error>\n(= ok 1)\n".to_string(), vec!["8 expected literal, found ')'".to_string()]));
+    // A block left open ends with the input, reported once.
+    assert_eq!(
+        recover("{ x = 1 +"),
+        ("(block <error>)\n".to_string(), vec!["9 expected literal, found end of input".to_string(), "9 expected '}', found end of input".to_string()])
+    );
+    assert_eq!(recover("{ x = 1").1, ["7 expected '}', found end of input"]);
+    // Stray characters are reported among the rest, in order.
+    assert_eq!(recover("x = 1 € 2\n3 3").1.len(), 3);
+    let (p, _) = program_recovering(&b"x = 1\ny = (2 +"[..], Extensions::default(), &OperatorTable::default());
+    assert_eq!(p.stmts[1], Stmt::Expr(Node::Error(Span::default())));
+    let Stmt::Expr(e) = &p.stmts[1] else { unreachable!() };
+    assert_eq!(e.span(), Span { start: 6, end: 14 });
+    assert_eq!(recover("x = 1\ny = 2"), ("(= x 1)\n(= y 2)\n".to_string(), vec![]));
+
+    // Tracing, each node built is a reduction, over the node's span.
+    let (e, steps) = explain(b"(1 + 2) * 3", Extensions::default(), &OperatorTable::default());
+    let reduced: Vec<_> = steps.iter().filter_map(|s| if let Step::Reduce { op, span } = s { Some((op.as_str(), *span)) } else { None }).collect();
+    assert_eq!(reduced, [("+", Span { start: 1, end: 6 }), ("()", Span { start: 0, end: 7 }), ("*", Span { start: 0, end: 11 })]);
+    assert_eq!(e.unwrap().span(), Span { start: 0, end: 11 });
 }
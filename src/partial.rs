@@ -0,0 +1,178 @@
+//! Partial evaluation (`--symbolic`): what can be computed is, and what
+//! depends on unbound symbols stays as an expression, so `x + 2*3` comes
+//! out as `x + 6`.
+
+use core::convert::Infallible;
+
+use crate::ast::{Folder, Visitor};
+use crate::lexer::Span;
+use crate::limits::Meter;
+use crate::parser::{LeafVal, Node, NodeVal, Value};
+use crate::prelude::*;
+use crate::{eval_with, Builtins, Env, EvalError};
+
+/// `v` as a literal, if it has one. Floats keep their point, so that `6.0`
+/// doesn't turn into the integer `6`.
+fn literal(v: Value) -> Option<LeafVal> {
+    match v {
+        Value::Int(v) => Some(LeafVal::Int(v)),
+        Value::Wide(v) => Some(LeafVal::Decimal(v.to_string())),
+        Value::Float(v) if v.is_finite() => Some(LeafVal::Decimal(format!("{v:?}"))),
+        Value::Float(_) | Value::Duration(_) | Value::Quantity(..) => None,
+    }
+}
+
+fn number(node: &Node) -> bool {
+    matches!(node, Node::Leaf(LeafVal::Int(_) | LeafVal::Bool(_) | LeafVal::Decimal(_), _))
+}
+
+struct Partial<'a> {
+    env: &'a Env,
+    exprs: &'a Env<Node>,
+    builtins: &'a Builtins,
+    meter: &'a mut Meter,
+}
+
+impl Partial<'_> {
+    /// `node` evaluated to a literal, or `node` itself if that fails or the
+    /// value has no literal.
+    fn compute(&mut self, node: Node) -> Node {
+        match eval_with(&node, self.env, self.builtins, self.meter).ok().and_then(literal) {
+            Some(leaf) => Node::Leaf(leaf, node.span()),
+            None => node,
+        }
+    }
+}
+
+impl Folder for Partial<'_> {
+    type Error = Infallible;
+
+    fn fold_leaf(&mut self, leaf: &LeafVal, span: Span) -> Result<Node, Infallible> {
+        let node = Node::Leaf(leaf.clone(), span);
+        Ok(match leaf {
+            LeafVal::Sym(s) if self.env.contains_key(s.as_str()) => self.compute(node),
+            // The expression may read symbols bound since it was stored,
+            // but not other expressions: it has those substituted already.
+            LeafVal::Sym(s) => match self.exprs.get(s.as_str()) {
+                Some(e) => {
+                    let Ok(e) = e.fold(&mut Partial { exprs: &Env::new(), meter: &mut *self.meter, ..*self });
+                    e
+                }
+                None => node,
+            },
+            _ => node,
+        })
+    }
+
+    fn fold_node(&mut self, v: &NodeVal, children: Vec<Node>, span: Span) -> Result<Node, Infallible> {
+        let constant = children.iter().all(number);
+        let node = Node::Node { v: v.clone(), children, span };
+        Ok(if constant { self.compute(node) } else { node })
+    }
+}
+
+/// Evaluates each subtree that has everything it needs: no
+/// `Node::Error`, only symbols bound in `env` and only functions in
+/// `builtins`.
+struct Values<'a> {
+    env: &'a Env,
+    builtins: &'a Builtins,
+    meter: &'a mut Meter,
+    /// For each node entered and not yet left, whether it can be evaluated.
+    open: Vec<bool>,
+    found: Vec<(Span, Result<Value, EvalError>)>,
+}
+
+impl Visitor for Values<'_> {
+    fn enter(&mut self, node: &Node) {
+        let known = match node {
+            Node::Leaf(LeafVal::Sym(s), _) => self.env.contains_key(s.as_str()),
+            Node::Leaf(v, _) => !matches!(v, LeafVal::Placeholder(_)),
+            Node::Node { v: NodeVal::Call(name), .. } => self.builtins.contains(name),
+            Node::Node { .. } => true,
+            Node::Error(_) => false,
+        };
+        self.open.push(known);
+    }
+
+    fn leave(&mut self, node: &Node) {
+        let known = self.open.pop().unwrap();
+        if let Some(parent) = self.open.last_mut() {
+            *parent &= known;
+        }
+        if known && !number(node) {
+            self.found.push((node.span(), eval_with(node, self.env, self.builtins, self.meter)));
+        }
+    }
+}
+
+impl Node {
+    /// The value of every subtree that can be evaluated with what `env`
+    /// and `builtins` bind, each with its span, inner subtrees before those
+    /// around them. Number literals are left out, and so is every subtree
+    /// over a `Node::Error` or an unbound symbol or function, so a tree
+    /// from `program_recovering` still has values for the parts of it that
+    /// parsed. A subtree that fails gives its error; the subtrees around it
+    /// may still have values, like a `?:` that doesn't take it.
+    pub fn subtree_values(&self, env: &Env, builtins: &Builtins, meter: &mut Meter) -> Vec<(Span, Result<Value, EvalError>)> {
+        let mut values = Values { env, builtins, meter, open: Vec::new(), found: Vec::new() };
+        self.visit(&mut values);
+        values.found
+    }
+
+    /// Evaluates what of the tree it can: symbols in `env` become their
+    /// values, symbols in `exprs` the expressions they stand for, and every
+    /// subtree whose operands are all numbers is computed. Symbols bound in
+    /// neither are kept, and so is a subtree that fails to evaluate, so
+    /// that its error shows once the symbols are known. The result is
+    /// simplified as by `simplify`.
+    pub fn partial_eval(&self, env: &Env, exprs: &Env<Node>, builtins: &Builtins, meter: &mut Meter) -> Node {
+        let Ok(node) = self.fold(&mut Partial { env, exprs, builtins, meter });
+        node.simplify()
+    }
+}
+
+#[test]
+fn tests() {
+    use crate::limits::EvalLimits;
+
+    let env: Env = [("n".to_string(), Value::Int(4)), ("h".to_string(), Value::Float(0.5))].into();
+    let exprs: Env<Node> = [("y".to_string(), crate::parser::expr(b"x + 1").unwrap())].into();
+    let mut builtins = Builtins::default();
+    builtins.define("sq", crate::Function { params: vec!["a".into()], body: crate::parser::expr(b"a * a").unwrap() });
+    let partial = |s: &str| {
+        let mut meter = Meter::new(EvalLimits::default());
+        crate::parser::expr(s.as_bytes()).unwrap().partial_eval(&env, &exprs, &builtins, &mut meter).to_infix()
+    };
+
+    assert_eq!(partial("x + 2*3"), "x + 6");
+    assert_eq!(partial("x * n - sq(n) / 2"), "x * 4 - 8");
+    assert_eq!(partial("sqrt(n) * h + x ^ (n - 3)"), "1.0 + x");
+    assert_eq!(partial("y * 2 + max(1, n, 2)"), "(x + 1) * 2 + 4");
+    let exprs: Env<Node> = [("y".to_string(), crate::parser::expr(b"n * z").unwrap())].into();
+    let mut meter = Meter::new(EvalLimits::default());
+    assert_eq!(crate::parser::expr(b"y + y").unwrap().partial_eval(&env, &exprs, &builtins, &mut meter).to_infix(), "4 * z + 4 * z");
+    assert_eq!(partial("f(n, x) + sq(x)"), "f(4, x) + sq(x)");
+    assert_eq!(partial("n > 3 ? x : 1 / 0"), "x");
+    assert_eq!(partial("0 - h * 3 + x"), "-1.5 + x");
+
+    // Failing subtrees are kept for the error to surface later.
+    assert_eq!(partial("x + 1 / (n - 4)"), "x + 1 / 0");
+    assert_eq!(partial("x + 2147483647 * n"), "x + 2147483647 * 4");
+    assert_eq!(partial("2 * 3"), "6");
+
+    // Subtrees get values wherever they have all they need, around the
+    // errors of a tree parsed with recovery too.
+    let values = |s: &str| {
+        let (program, _) = crate::parser::program_recovering(s.as_bytes(), Default::default(), &Default::default());
+        let mut meter = Meter::new(EvalLimits::default());
+        let node = program.stmts[0].exprs()[0].clone();
+        node.subtree_values(&env, &builtins, &mut meter)
+            .into_iter()
+            .map(|(span, v)| format!("{}: {}", &s[span.start..span.end], v.map_or_else(|e| e.code().to_string(), |v| v.to_string())))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(values("sq(n) - x * (n + 1)"), ["n: 4", "sq(n): 16", "n: 4", "(n + 1): 5"]);
+    assert_eq!(values("n > 3 ? h : 1 / 0"), ["n: 4", "n > 3: 1", "h: 0.5", "1 / 0: division-by-zero", "n > 3 ? h : 1 / 0: 0.5"]);
+    assert!(values("2 * 3 +* n").is_empty());
+}
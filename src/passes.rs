@@ -0,0 +1,377 @@
+//! Named rewrites of whole programs, run in the order asked for
+//! (`--passes=fold,simplify`). The built-in passes split up what
+//! `Node::simplify` does at once, and add two that need the program:
+//!
+//! - `fold` evaluates operators on integer constants (`2*3` to `6`);
+//! - `simplify` removes identities (`x+0`, `x*1`, `--x`, ...);
+//! - `canonicalize` moves constants to the right of commutative operators
+//!   and comparisons (`2 < x` to `x > 2`), so that equal expressions are
+//!   written alike;
+//! - `cse` replaces an expression that a variable already holds with the
+//!   variable (`a = x*y; b = x*y + 1` to `a = x*y; b = a + 1`);
+//! - `dce` drops the operands of `&&`, `||` and `?:`, and the branches of
+//!   `if` and `while`, that a constant condition rules out.
+//!
+//! A library user adds passes of their own with [`PassManager::register`].
+//!
+//! ```
+//! use stoncc::passes::PassManager;
+//!
+//! let mut program = stoncc::program(b"a = x * y; b = 1 * (x * y) + 0").0.unwrap();
+//! PassManager::new(false).run(&["simplify", "cse"], &mut program).unwrap();
+//! assert_eq!(program.to_string(), "(= a (* x y))\n(= b a)\n");
+//! ```
+
+use core::fmt;
+
+use crate::optimize::{FOLD, IDENTITIES, SHORT_CIRCUIT};
+use crate::parser::{LeafVal, Node, NodeVal, Program, Stmt};
+use crate::prelude::*;
+use crate::Rewrite;
+
+/// A rewrite of a whole program, which a `PassManager` runs by name.
+pub trait Pass {
+    /// What `--passes` calls it.
+    fn name(&self) -> &str;
+
+    /// Rewrites `program` without changing what it computes, adding each
+    /// rewrite of an expression to `log`.
+    fn run(&self, program: &mut Program, log: &mut Vec<Rewrite>);
+}
+
+/// A `--passes` list named a pass that isn't registered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPass {
+    pub name: String,
+    /// The passes that are.
+    pub known: Vec<String>,
+}
+
+impl fmt::Display for UnknownPass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown pass '{}' (expected {})", self.name, self.known.join(", "))
+    }
+}
+
+/// The passes there are, by name.
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl PassManager {
+    /// The built-in passes. With `strict_fp`, `simplify` keeps `x+0`,
+    /// which turns -0.0 into 0.0; none of the others change a float.
+    pub fn new(strict_fp: bool) -> Self {
+        Self {
+            passes: vec![
+                Box::new(Rules { name: "fold", rules: FOLD, strict_fp }),
+                Box::new(Rules { name: "simplify", rules: IDENTITIES, strict_fp }),
+                Box::new(CanonicalizePass),
+                Box::new(Cse),
+                Box::new(Dce),
+            ],
+        }
+    }
+
+    /// Adds `pass`, in place of any registered under the same name.
+    pub fn register(&mut self, pass: impl Pass + 'static) {
+        match self.passes.iter().position(|p| p.name() == pass.name()) {
+            Some(i) => self.passes[i] = Box::new(pass),
+            None => self.passes.push(Box::new(pass)),
+        }
+    }
+
+    /// The names of the registered passes, in the order registered.
+    pub fn names(&self) -> Vec<&str> {
+        self.passes.iter().map(|p| p.name()).collect()
+    }
+
+    /// Runs the passes `names`, in that order, and returns every rewrite
+    /// they made. Fails before running any if one isn't registered.
+    pub fn run(&self, names: &[&str], program: &mut Program) -> Result<Vec<Rewrite>, UnknownPass> {
+        let pipeline = names
+            .iter()
+            .map(|&name| {
+                self.passes.iter().find(|p| p.name() == name).ok_or_else(|| UnknownPass {
+                    name: name.to_string(),
+                    known: self.names().into_iter().map(String::from).collect(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut log = Vec::new();
+        for pass in pipeline {
+            pass.run(program, &mut log);
+        }
+        Ok(log)
+    }
+}
+
+/// A pass of some of the rules of `Node::simplify`, on every expression.
+struct Rules {
+    name: &'static str,
+    rules: &'static [&'static str],
+    strict_fp: bool,
+}
+
+impl Pass for Rules {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn run(&self, program: &mut Program, log: &mut Vec<Rewrite>) {
+        for e in program.stmts.iter_mut().flat_map(Stmt::exprs_mut) {
+            *e = e.simplify_only(self.rules, self.strict_fp, log);
+        }
+    }
+}
+
+fn is_constant(node: &Node) -> bool {
+    matches!(node, Node::Leaf(LeafVal::Int(_) | LeafVal::Bool(_) | LeafVal::Decimal(_), _))
+}
+
+/// The truth of a constant condition.
+fn constant_truth(node: &Node) -> Option<bool> {
+    match node {
+        Node::Leaf(LeafVal::Int(v), _) => Some(*v != 0),
+        Node::Leaf(LeafVal::Bool(b), _) => Some(*b),
+        Node::Leaf(LeafVal::Decimal(s), _) => s.parse::<f64>().ok().map(|v| v != 0.0),
+        _ => None,
+    }
+}
+
+/// Moves constants right, adding each operator it turns around to `log`.
+struct Canonicalize<'a> {
+    log: &'a mut Vec<Rewrite>,
+}
+
+impl crate::Folder for Canonicalize<'_> {
+    type Error = core::convert::Infallible;
+
+    fn fold_node(&mut self, v: &NodeVal, children: Vec<Node>, span: crate::Span) -> Result<Node, Self::Error> {
+        // `&&` and `||` only evaluate their second operand if they need
+        // to, so they stay as they are.
+        let swapped = match v {
+            NodeVal::Add | NodeVal::Mul | NodeVal::AddWrap | NodeVal::MulWrap | NodeVal::AddSat | NodeVal::MulSat => v.clone(),
+            NodeVal::Eq | NodeVal::Ne | NodeVal::BitAnd | NodeVal::BitOr => v.clone(),
+            NodeVal::Lt => NodeVal::Gt,
+            NodeVal::Gt => NodeVal::Lt,
+            NodeVal::Le => NodeVal::Ge,
+            NodeVal::Ge => NodeVal::Le,
+            _ => return Ok(Node::Node { v: v.clone(), children, span }),
+        };
+        let [a, b] = &children[..] else { return Ok(Node::Node { v: v.clone(), children, span }) };
+        if !is_constant(a) || is_constant(b) {
+            return Ok(Node::Node { v: v.clone(), children, span });
+        }
+        let after = Node::Node { v: swapped, children: vec![b.clone(), a.clone()], span };
+        let before = Node::Node { v: v.clone(), children, span };
+        self.log.push(Rewrite { rule: "constant-right".into(), before, after: after.clone(), span });
+        Ok(after)
+    }
+}
+
+struct CanonicalizePass;
+
+impl Pass for CanonicalizePass {
+    fn name(&self) -> &str {
+        "canonicalize"
+    }
+
+    fn run(&self, program: &mut Program, log: &mut Vec<Rewrite>) {
+        for e in program.stmts.iter_mut().flat_map(Stmt::exprs_mut) {
+            let Ok(after) = e.fold(&mut Canonicalize { log: &mut *log });
+            *e = after;
+        }
+    }
+}
+
+/// An expression a variable holds, while nothing it reads is assigned.
+struct Held {
+    /// The expression, as `Display` writes it, which leaves out spans.
+    key: String,
+    root: NodeVal,
+    var: String,
+    reads: Vec<String>,
+    /// A function it calls may read any variable.
+    calls: bool,
+}
+
+/// Common subexpression elimination over straight-line code: after
+/// `a = e`, `e` is replaced with `a` until `a` or a variable `e` reads is
+/// assigned. Errors stop a program, so if `e` failed nothing after it
+/// runs. The bodies of `if` and `while`, which may run any number of
+/// times, are left as they are, and forget what was held; so does a
+/// function definition, which changes what calls do.
+struct Cse;
+
+impl Cse {
+    fn stmts(stmts: &mut [Stmt], held: &mut Vec<Held>, log: &mut Vec<Rewrite>) {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Assign { name, value, .. } => {
+                    Self::replace(value, held, log);
+                    held.retain(|h| h.var != *name && !h.calls && !h.reads.contains(name));
+                    let (reads, calls) = Self::reads(value);
+                    if let Node::Node { v, .. } = &value {
+                        if !reads.contains(name) && !Self::has_placeholder(value) {
+                            held.push(Held { key: value.to_string(), root: v.clone(), var: name.clone(), reads, calls });
+                        }
+                    }
+                }
+                Stmt::Expr(e) => Self::replace(e, held, log),
+                Stmt::Block { stmts, .. } => Self::stmts(stmts, held, log),
+                Stmt::If { cond, .. } => {
+                    Self::replace(cond, held, log);
+                    held.clear();
+                }
+                Stmt::While { .. } | Stmt::Def { .. } => held.clear(),
+            }
+        }
+    }
+
+    /// Replaces the outermost subtrees of `node` that a variable holds,
+    /// working down from the root without recursing.
+    fn replace(node: &mut Node, held: &[Held], log: &mut Vec<Rewrite>) {
+        let mut todo = vec![node];
+        while let Some(node) = todo.pop() {
+            let Node::Node { v, .. } = &*node else { continue };
+            let found = held.iter().filter(|h| h.root == *v).find(|h| h.key == node.to_string());
+            match found {
+                Some(h) => {
+                    let after = Node::Leaf(LeafVal::Sym(h.var.as_str().into()), node.span());
+                    log.push(Rewrite { rule: "cse".into(), before: node.clone(), after: after.clone(), span: node.span() });
+                    *node = after;
+                }
+                None => {
+                    if let Node::Node { children, .. } = node {
+                        todo.extend(children.iter_mut());
+                    }
+                }
+            }
+        }
+    }
+
+    /// The variables `node` reads, and whether it calls a function.
+    fn reads(node: &Node) -> (Vec<String>, bool) {
+        let (mut reads, mut calls) = (Vec::new(), false);
+        let mut todo = vec![node];
+        while let Some(node) = todo.pop() {
+            match node {
+                Node::Leaf(LeafVal::Sym(s), _) => reads.push(s.to_string()),
+                Node::Node { v, children, .. } => {
+                    calls |= matches!(v, NodeVal::Call(_));
+                    todo.extend(children);
+                }
+                _ => {}
+            }
+        }
+        (reads, calls)
+    }
+
+    fn has_placeholder(node: &Node) -> bool {
+        let mut todo = vec![node];
+        while let Some(node) = todo.pop() {
+            match node {
+                Node::Leaf(LeafVal::Placeholder(_), _) | Node::Error(_) => return true,
+                Node::Node { children, .. } => todo.extend(children),
+                _ => {}
+            }
+        }
+        false
+    }
+}
+
+impl Pass for Cse {
+    fn name(&self) -> &str {
+        "cse"
+    }
+
+    fn run(&self, program: &mut Program, log: &mut Vec<Rewrite>) {
+        Cse::stmts(&mut program.stmts, &mut Vec::new(), log);
+    }
+}
+
+/// Dead code elimination. The statements it drops aren't logged, since a
+/// `Rewrite` is of an expression; the operands it drops are.
+struct Dce;
+
+impl Dce {
+    fn stmt(stmt: Stmt) -> Stmt {
+        match stmt {
+            Stmt::If { cond, then, els, span } => match constant_truth(&cond) {
+                Some(true) => Self::stmt(*then),
+                Some(false) => els.map_or(Stmt::Block { stmts: Vec::new(), span }, |els| Self::stmt(*els)),
+                None => Stmt::If { cond, then: Box::new(Self::stmt(*then)), els: els.map(|els| Box::new(Self::stmt(*els))), span },
+            },
+            Stmt::While { cond, span, .. } if constant_truth(&cond) == Some(false) => Stmt::Block { stmts: Vec::new(), span },
+            Stmt::While { cond, body, span } => Stmt::While { cond, body: Box::new(Self::stmt(*body)), span },
+            Stmt::Block { stmts, span } => Stmt::Block { stmts: stmts.into_iter().map(Self::stmt).collect(), span },
+            stmt => stmt,
+        }
+    }
+}
+
+impl Pass for Dce {
+    fn name(&self) -> &str {
+        "dce"
+    }
+
+    fn run(&self, program: &mut Program, log: &mut Vec<Rewrite>) {
+        for e in program.stmts.iter_mut().flat_map(Stmt::exprs_mut) {
+            *e = e.simplify_only(SHORT_CIRCUIT, false, log);
+        }
+        program.stmts = core::mem::take(&mut program.stmts).into_iter().map(Dce::stmt).collect();
+    }
+}
+
+#[test]
+fn tests() {
+    let run = |passes: &[&str], s: &str| {
+        let mut program = crate::parser::program(s.as_bytes()).0.unwrap();
+        let log = PassManager::new(false).run(passes, &mut program).unwrap();
+        (program.to_string().trim_end().to_string(), log.into_iter().map(|r| r.rule).collect::<Vec<_>>())
+    };
+
+    assert_eq!(run(&["fold"], "x * (3 - 2) + 2 * 4"), ("(+ (* x 1) 8)".into(), vec!["fold".into(), "fold".into()]));
+    assert_eq!(run(&["fold", "simplify"], "x * (3 - 2) + 0").0, "x");
+    // Each pass runs once, in the order given.
+    assert_eq!(run(&["simplify", "fold"], "x * (3 - 2)").0, "(* x 1)");
+    assert_eq!(run(&[], "1 + 1").0, "(+ 1 1)");
+
+    assert_eq!(run(&["canonicalize"], "2 * x + (3 < y) - (1 && z)"), ("(- (+ (* x 2) (> y 3)) (&& 1 z))".into(), vec!["constant-right".into(); 2]));
+    assert_eq!(run(&["canonicalize"], "2 * x + (3 < y) - (1 && z)").0, "(- (+ (* x 2) (> y 3)) (&& 1 z))");
+    assert_eq!(run(&["canonicalize"], "1 + 2").0, "(+ 1 2)");
+
+    // What a variable holds is reused until it, or what it read, changes.
+    assert_eq!(run(&["cse"], "a = x * y; b = x * y + 1; c = (x * y) * (x * y)").0, "(= a (* x y))\n(= b (+ a 1))\n(= c (* a a))");
+    assert_eq!(run(&["cse"], "a = x * y; x = 2; b = x * y").0, "(= a (* x y))\n(= x 2)\n(= b (* x y))");
+    assert_eq!(run(&["cse"], "a = x * y; a = 1; b = x * y").0, "(= a (* x y))\n(= a 1)\n(= b (* x y))");
+    assert_eq!(run(&["cse"], "x = x + 1; y = x + 1").0, "(= x (+ x 1))\n(= y (+ x 1))");
+    assert_eq!(run(&["cse"], "a = f(x); g = 1; b = f(x)").0, "(= a (f x))\n(= g 1)\n(= b (f x))");
+    assert_eq!(run(&["cse"], "a = x + 1; while (a < 9) a = x + 1").0, "(= a (+ x 1))\n(while (< a 9) (= a (+ x 1)))");
+    assert_eq!(run(&["cse"], "a = x + 1; { b = x + 1 }").1, ["cse"]);
+
+    assert_eq!(run(&["dce"], "if (1) a = 1 else a = 2").0, "(= a 1)");
+    assert_eq!(run(&["dce"], "while (0) x = 1; 0 && y; 1 ? x : y").0, "(block)\n0\nx");
+    assert_eq!(run(&["fold", "dce"], "if (2 > 3) a = 1; if (x) { if (1 - 1) b = 2 }").0, "(block)\n(if x (block (block)))");
+
+    let mut program = crate::parser::program(b"1").0.unwrap();
+    let e = PassManager::new(false).run(&["fold", "inline"], &mut program).unwrap_err();
+    assert_eq!(e.to_string(), "unknown pass 'inline' (expected fold, simplify, canonicalize, cse, dce)");
+
+    // Passes of a library's own, which may replace built-in ones.
+    struct Zero;
+    impl Pass for Zero {
+        fn name(&self) -> &str {
+            "fold"
+        }
+        fn run(&self, program: &mut Program, _: &mut Vec<Rewrite>) {
+            program.stmts = vec![Stmt::Expr(Node::leaf(LeafVal::Int(0)))];
+        }
+    }
+    let mut passes = PassManager::new(false);
+    passes.register(Zero);
+    assert_eq!(passes.names(), ["fold", "simplify", "canonicalize", "cse", "dce"]);
+    passes.run(&["fold"], &mut program).unwrap();
+    assert_eq!(program.to_string(), "0\n");
+}
@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::iter::Peekable;
+
+use stoncc::parser::{self, LeafVal, Node, NodeVal};
+
+use crate::{fail, Failure};
+
+/// A tree pattern written as an S-expression, e.g. `(+ ?a (* ?b ?b))`.
+/// `?name` matches any subtree; a name used twice must match equal
+/// subtrees both times.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Var(String),
+    Leaf(LeafVal),
+    Node {
+        v: NodeVal,
+        children: Vec<Pattern>,
+    },
+}
+
+/// Subtrees captured by each `?name` of a matched pattern.
+pub type Bindings = BTreeMap<String, Node>;
+
+pub fn match_pattern(node: &Node, pattern: &Pattern) -> Option<Bindings> {
+    let mut b = Bindings::new();
+    bind(node, pattern, &mut b).then_some(b)
+}
+
+fn bind(node: &Node, pattern: &Pattern, b: &mut Bindings) -> bool {
+    match (pattern, node) {
+        (Pattern::Var(name), _) => match b.get(name) {
+            Some(bound) => bound == node,
+            None => {
+                b.insert(name.clone(), node.clone());
+                true
+            }
+        },
+        (Pattern::Leaf(p), Node::Leaf(v, _)) => p == v,
+        (Pattern::Node { v: pv, children: pc }, Node::Node { v, children, .. }) => {
+            pv == v && pc.len() == children.len() && pc.iter().zip(children).all(|(p, c)| bind(c, p, b))
+        }
+        _ => false,
+    }
+}
+
+impl Pattern {
+    /// Builds the tree this pattern describes, with each `?name` replaced by
+    /// its binding. Every variable must be bound.
+    pub fn instantiate(&self, b: &Bindings) -> Node {
+        match self {
+            Pattern::Var(name) => b[name].clone(),
+            Pattern::Leaf(v) => Node::leaf(v.clone()),
+            Pattern::Node { v, children } => Node::op(v.clone(), children.iter().map(|c| c.instantiate(b)).collect()),
+        }
+    }
+
+    /// Names of the variables used in this pattern.
+    pub fn vars(&self) -> Vec<&str> {
+        match self {
+            Pattern::Var(name) => vec![name],
+            Pattern::Leaf(_) => vec![],
+            Pattern::Node { children, .. } => children.iter().flat_map(|c| c.vars()).collect(),
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let spaced = s.replace('(', " ( ").replace(')', " ) ");
+        let mut tokens = spaced.split_whitespace().peekable();
+        let p = Self::parse_one(&mut tokens)?;
+        match tokens.next() {
+            None => Ok(p),
+            Some(t) => Err(format!("unexpected '{t}' after pattern")),
+        }
+    }
+
+    fn parse_one<'a>(tokens: &mut Peekable<impl Iterator<Item = &'a str>>) -> Result<Self, String> {
+        match tokens.next() {
+            None => Err("unexpected end of pattern".to_string()),
+            Some(")") => Err("unexpected ')'".to_string()),
+            Some("(") => {
+                let head = tokens.next().ok_or("unexpected end of pattern")?;
+                // Any name heads a function call, with any number of operands.
+                let call = head.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && head.chars().all(|c| c.is_ascii_alphanumeric());
+                let v = match NodeVal::SHAPES.into_iter().map(|(v, _)| v).find(|v| v.to_string() == head) {
+                    Some(v) => v,
+                    None if call => NodeVal::Call(head.to_string()),
+                    None => return Err(format!("unknown operator '{head}'")),
+                };
+
+                let mut children = Vec::new();
+                while tokens.next_if_eq(&")").is_none() {
+                    children.push(Self::parse_one(tokens)?);
+                }
+
+                if !call && !NodeVal::SHAPES.contains(&(v.clone(), children.len())) {
+                    return Err(format!("operator '{v}' cannot take {} operands", children.len()));
+                }
+                Ok(Pattern::Node { v, children })
+            }
+            Some(t) => match t.strip_prefix('?') {
+                Some(name) if !name.is_empty() => Ok(Pattern::Var(name.to_string())),
+                _ => match &parser::expr(t.as_bytes()) {
+                    Ok(Node::Leaf(v, _)) => Ok(Pattern::Leaf(v.clone())),
+                    _ => Err(format!("invalid atom '{t}'")),
+                },
+            },
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Var(name) => write!(f, "?{name}"),
+            Pattern::Leaf(v) => write!(f, "{v}"),
+            Pattern::Node { v, children } => {
+                write!(f, "({v}")?;
+                for c in children {
+                    write!(f, " {c}")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// `stoncc match PATTERN EXPR`: prints what each `?name` of the pattern
+/// captured, or exits with 1 if the expression doesn't match.
+pub fn main(args: &[String]) {
+    let [pattern, expr] = args else {
+        eprintln!("usage: stoncc match PATTERN EXPR");
+        std::process::exit(2);
+    };
+
+    let pattern = Pattern::parse(pattern).unwrap_or_else(|e| fail(Failure::Usage, format!("invalid pattern: {e}")));
+    let expr = parser::expr(expr.as_bytes()).unwrap_or_else(|e| fail(Failure::Run, e));
+
+    match match_pattern(&expr, &pattern) {
+        Some(b) => {
+            for (name, node) in b {
+                println!("?{name} = {node}");
+            }
+        }
+        None => std::process::exit(1),
+    }
+}
+
+#[test]
+fn tests() {
+    let run = |p: &str, e: &str| {
+        let b = match_pattern(&parser::expr(e.as_bytes()).unwrap(), &Pattern::parse(p).unwrap())?;
+        Some(b.iter().map(|(k, v)| format!("{k}={v}")).collect::<Vec<_>>().join(" "))
+    };
+
+    assert_eq!(run("(+ ?a (* ?b ?b))", "x + (y + 1) * (y + 1)"), Some("a=x b=(+ y 1)".into()));
+    assert_eq!(run("(+ ?a (* ?b ?b))", "x + y * z"), None);
+    assert_eq!(run("(* 2 ?x)", "2 * a!"), Some("x=(! a)".into()));
+    assert_eq!(run("(- ?x)", "a - b"), None);
+    assert_eq!(run("(max ?a (abs ?a))", "max(x, abs(x))"), Some("a=x".into()));
+    assert_eq!(run("(max ?a)", "max(x, 1)"), None);
+
+    assert_eq!(Pattern::parse("(+ ?a (* 1 x))").unwrap().to_string(), "(+ ?a (* 1 x))");
+    assert!(Pattern::parse("(! ?a ?b)").is_err());
+    assert!(Pattern::parse("(+ ?a").is_err());
+}
@@ -0,0 +1,222 @@
+//! `--plugin LIB`: shared libraries that add builtins, infix operators and
+//! output formats through the C interface in `include/stoncc_plugin.h`.
+//! Each is loaded once, before any program is read, and what its init
+//! registers is kept for the rest of the run.
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::ops::RangeInclusive;
+use std::sync::Mutex;
+
+use stoncc::format::Notation;
+use stoncc::operators::{Fixity, OperatorTable};
+use stoncc::{Builtins, NodeVal, Value};
+
+/// `STONCC_PLUGIN_ABI` in the header.
+const ABI: u32 = 1;
+
+type BuiltinFn = extern "C" fn(args: *const f64, argc: usize, result: *mut f64) -> c_int;
+type FormatFn = extern "C" fn(value: f64, buf: *mut c_char, len: usize) -> c_int;
+
+/// `struct stoncc_host`.
+#[repr(C)]
+struct Host {
+    abi: u32,
+    ctx: *mut c_void,
+    builtin: extern "C" fn(*mut c_void, *const c_char, usize, usize, BuiltinFn) -> c_int,
+    infix: extern "C" fn(*mut c_void, *const c_char, *const c_char, *const c_char) -> c_int,
+    format: extern "C" fn(*mut c_void, *const c_char, FormatFn) -> c_int,
+}
+
+type Init = extern "C" fn(*const Host) -> c_int;
+
+/// What the plugins loaded so far registered.
+#[derive(Default)]
+struct Registry {
+    builtins: Vec<(String, RangeInclusive<usize>, BuiltinFn)>,
+    /// Spelling, the operator it parses like, and the builtin it calls.
+    infix: Vec<(String, String, String)>,
+    formats: Vec<(String, FormatFn)>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry { builtins: Vec::new(), infix: Vec::new(), formats: Vec::new() });
+
+/// What a plugin is registering, checked against what is there already.
+struct Loading {
+    registry: Registry,
+}
+
+impl Loading {
+    fn builtin_taken(&self, name: &str) -> bool {
+        let registered = REGISTRY.lock().unwrap();
+        Builtins::default().contains(name) || [&registered.builtins, &self.registry.builtins].iter().any(|b| b.iter().any(|(n, ..)| n == name))
+    }
+
+    fn format_taken(&self, name: &str) -> bool {
+        let registered = REGISTRY.lock().unwrap();
+        Notation::NAMES.contains(&name) || [&registered.formats, &self.registry.formats].iter().any(|f| f.iter().any(|(n, _)| n == name))
+    }
+
+    /// The operators there are with those registered so far.
+    fn operators(&self) -> OperatorTable {
+        let mut ops = operators();
+        add_infix(&mut ops, &self.registry.infix).unwrap();
+        ops
+    }
+}
+
+/// `s` if it is a valid, nonempty C string.
+///
+/// # Safety
+/// `s` must be null or point to a NUL-terminated string.
+unsafe fn text(s: *const c_char) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok().filter(|s| !s.is_empty()).map(String::from)
+}
+
+extern "C" fn register_builtin(ctx: *mut c_void, name: *const c_char, min: usize, max: usize, f: BuiltinFn) -> c_int {
+    // SAFETY: `ctx` is the `Loading` that `load` passed, and the header
+    // asks for NUL-terminated names.
+    let (loading, name) = unsafe { (&mut *(ctx as *mut Loading), text(name)) };
+    match name {
+        Some(name) if min <= max && !loading.builtin_taken(&name) => {
+            loading.registry.builtins.push((name, min..=max, f));
+            0
+        }
+        _ => 1,
+    }
+}
+
+extern "C" fn register_infix(ctx: *mut c_void, spelling: *const c_char, like: *const c_char, function: *const c_char) -> c_int {
+    // SAFETY: as for `register_builtin`.
+    let (loading, spelling, like, function) = unsafe { (&mut *(ctx as *mut Loading), text(spelling), text(like), text(function)) };
+    let (Some(spelling), Some(like), Some(function)) = (spelling, like, function) else { return 1 };
+    let infix = [(spelling, like, function)];
+    if add_infix(&mut loading.operators(), &infix).is_err() {
+        return 1;
+    }
+    loading.registry.infix.extend(infix);
+    0
+}
+
+extern "C" fn register_format(ctx: *mut c_void, name: *const c_char, f: FormatFn) -> c_int {
+    // SAFETY: as for `register_builtin`.
+    let (loading, name) = unsafe { (&mut *(ctx as *mut Loading), text(name)) };
+    match name {
+        Some(name) if !loading.format_taken(&name) => {
+            loading.registry.formats.push((name, f));
+            0
+        }
+        _ => 1,
+    }
+}
+
+/// Adds each of `infix` to `ops`, failing if one parses like an operator
+/// that isn't there, or can't be spelled that way.
+fn add_infix(ops: &mut OperatorTable, infix: &[(String, String, String)]) -> Result<(), String> {
+    for (spelling, like, function) in infix {
+        if ops.get(spelling, Fixity::Infix).is_some() {
+            return Err(format!("'{spelling}' is an operator already"));
+        }
+        let base = ops.get(like, Fixity::Infix).ok_or_else(|| format!("'{like}' is not an infix operator"))?.clone();
+        ops.try_add(spelling, Fixity::Infix, stoncc::operators::Operator { v: NodeVal::Call(function.clone()), ..base })?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::{c_char, c_int, c_void};
+
+    pub const RTLD_NOW: c_int = 2;
+
+    extern "C" {
+        pub fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+        pub fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        pub fn dlerror() -> *const c_char;
+    }
+}
+
+/// Loads the plugin at `path` and runs its init. It is never unloaded, so
+/// the functions it registered stay valid.
+#[cfg(unix)]
+pub fn load(path: &str) -> Result<(), String> {
+    let c_path = CString::new(path).map_err(|_| format!("{path}: not a valid path"))?;
+    // SAFETY: dlopen and dlsym take NUL-terminated strings, and dlerror
+    // gives one or null. `stoncc_plugin_init` has the type the header
+    // declares, and is called with a host table that outlives the call.
+    unsafe {
+        let error = || text(sys::dlerror()).unwrap_or_else(|| "unknown error".into());
+        let handle = sys::dlopen(c_path.as_ptr(), sys::RTLD_NOW);
+        if handle.is_null() {
+            return Err(error());
+        }
+        let init = sys::dlsym(handle, c"stoncc_plugin_init".as_ptr());
+        if init.is_null() {
+            return Err(format!("{path}: no stoncc_plugin_init: {}", error()));
+        }
+        let init: Init = std::mem::transmute::<*mut c_void, Init>(init);
+        let mut loading = Loading { registry: Registry::default() };
+        let host = Host {
+            abi: ABI,
+            ctx: &mut loading as *mut Loading as *mut c_void,
+            builtin: register_builtin,
+            infix: register_infix,
+            format: register_format,
+        };
+        let status = init(&host);
+        if status != 0 {
+            return Err(format!("{path}: stoncc_plugin_init failed with {status}"));
+        }
+        let mut registry = REGISTRY.lock().unwrap();
+        registry.builtins.extend(loading.registry.builtins);
+        registry.infix.extend(loading.registry.infix);
+        registry.formats.extend(loading.registry.formats);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn load(path: &str) -> Result<(), String> {
+    Err(format!("{path}: plugins can only be loaded on Unix"))
+}
+
+/// Adds the builtins of the plugins to `builtins`.
+pub fn builtins(builtins: &mut Builtins) {
+    for (name, arity, f) in &REGISTRY.lock().unwrap().builtins {
+        let (name, f) = (name.clone(), *f);
+        builtins.register(&name.clone(), arity.clone(), move |args| {
+            let args: Vec<f64> = args.iter().map(|a| a.as_f64()).collect();
+            let mut result = 0.0;
+            match f(args.as_ptr(), args.len(), &mut result) {
+                0 => Ok(Value::Float(result)),
+                status => Err(format!("{name}() failed with {status}")),
+            }
+        });
+    }
+}
+
+/// The default operators with those of the plugins.
+pub fn operators() -> OperatorTable {
+    let mut ops = OperatorTable::default();
+    // Each was checked against the others when it was registered.
+    add_infix(&mut ops, &REGISTRY.lock().unwrap().infix).unwrap();
+    ops
+}
+
+/// Whether a plugin registered the output format `name`.
+pub fn has_format(name: &str) -> bool {
+    REGISTRY.lock().unwrap().formats.iter().any(|(n, _)| n == name)
+}
+
+/// `v` in the output format `name` of a plugin.
+pub fn format(name: &str, v: Value) -> Result<String, String> {
+    let f = REGISTRY.lock().unwrap().formats.iter().find(|(n, _)| n == name).map(|(_, f)| *f).unwrap();
+    let mut buf = vec![0 as c_char; 256];
+    if f(v.as_f64(), buf.as_mut_ptr(), buf.len()) != 0 || !buf.contains(&0) {
+        return Err(format!("output format {name} cannot write {v}"));
+    }
+    // SAFETY: `buf` holds a NUL.
+    Ok(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned())
+}
@@ -0,0 +1,40 @@
+//! What built this stoncc: its version, the commit it was built from and
+//! the cargo features it was built with. `stoncc --build-info` prints
+//! them, and emitted assembly, `--dump-ast=json` and cached trees carry
+//! them, so that any of those can be traced back to the compiler that
+//! made it.
+
+/// The commit, or `unknown` if it wasn't built from a git checkout.
+pub const COMMIT: &str = env!("STONCC_COMMIT");
+
+/// The cargo features that change what stoncc does.
+pub fn features() -> Vec<&'static str> {
+    let all = [
+        ("std", cfg!(feature = "std")),
+        ("bigint", cfg!(feature = "bigint")),
+        ("decimal", cfg!(feature = "decimal")),
+        ("rational", cfg!(feature = "rational")),
+        ("wasm", cfg!(feature = "wasm")),
+        ("compressed", cfg!(feature = "compressed")),
+    ];
+    all.into_iter().filter(|&(_, on)| on).map(|(name, _)| name).collect()
+}
+
+/// All of it on one line, like `stoncc 0.1.0 (3d55350abcde; std, bigint)`.
+pub fn describe() -> String {
+    format!("stoncc {} ({COMMIT}; {})", env!("CARGO_PKG_VERSION"), features().join(", "))
+}
+
+/// What `stoncc --build-info` prints.
+pub fn report() -> String {
+    format!("version: {}\ncommit: {COMMIT}\nfeatures: {}\n", env!("CARGO_PKG_VERSION"), features().join(", "))
+}
+
+#[test]
+fn tests() {
+    assert!(features().contains(&"std"));
+    let line = describe();
+    assert!(line.starts_with(&format!("stoncc {} (", env!("CARGO_PKG_VERSION"))), "{line}");
+    assert!(!line.contains('\n') && line.ends_with(&format!("{COMMIT}; {})", features().join(", "))));
+    assert!(report().starts_with("version: ") && report().contains(&format!("\ncommit: {COMMIT}\n")));
+}
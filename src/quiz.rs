@@ -0,0 +1,219 @@
+use std::io::{self, BufRead, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use stoncc::generate::Rng;
+use stoncc::parser::{LeafVal, Node, NodeVal};
+use stoncc::limits::Meter;
+use stoncc::Env;
+
+use crate::{fail, Failure};
+
+/// What a question asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    InfixToSexp,
+    SexpToInfix,
+    InfixToRpn,
+    RpnToInfix,
+    Evaluate,
+}
+
+const KINDS: [Kind; 5] = [Kind::InfixToSexp, Kind::SexpToInfix, Kind::InfixToRpn, Kind::RpnToInfix, Kind::Evaluate];
+
+/// A random tree of binary operators from `ops` over the digits 1 to 9,
+/// with at most `depth` levels of operators.
+fn random(rng: &mut Rng, ops: &[NodeVal], depth: usize) -> Node {
+    if depth == 0 || rng.below(3) == 0 {
+        return Node::leaf(LeafVal::Int(1 + rng.below(9) as i32));
+    }
+    let v = ops[rng.below(ops.len())].clone();
+    let children = vec![random(rng, ops, depth - 1), random(rng, ops, depth - 1)];
+    Node::op(v, children)
+}
+
+/// `node` in reverse Polish notation: operands, then their operator.
+fn rpn(node: &Node) -> String {
+    match node {
+        Node::Node { v, children, .. } => {
+            let mut words: Vec<String> = children.iter().map(rpn).collect();
+            words.push(v.to_string());
+            words.join(" ")
+        }
+        leaf => leaf.to_string(),
+    }
+}
+
+/// Reads what `rpn` writes, or `None` if it isn't a single expression.
+fn from_rpn(s: &str) -> Option<Node> {
+    let mut stack = Vec::new();
+    for word in s.split_whitespace() {
+        if let Ok(n) = word.parse() {
+            stack.push(Node::leaf(LeafVal::Int(n)));
+            continue;
+        }
+        let v = [NodeVal::Add, NodeVal::Sub, NodeVal::Mul, NodeVal::Div, NodeVal::Exp].into_iter().find(|v| v.to_string() == word)?;
+        let b = stack.pop()?;
+        let a = stack.pop()?;
+        stack.push(Node::op(v, vec![a, b]));
+    }
+    match <[Node; 1]>::try_from(stack) {
+        Ok([node]) => Some(node),
+        Err(_) => None,
+    }
+}
+
+/// One question: what it asks for, about which expression.
+struct Question {
+    kind: Kind,
+    node: Node,
+}
+
+impl Question {
+    /// A question of a random kind. Expressions to evaluate only add,
+    /// subtract and multiply, so that the answer is a plain integer.
+    fn random(rng: &mut Rng) -> Self {
+        let kind = KINDS[rng.below(KINDS.len())];
+        let ops = match kind {
+            Kind::Evaluate => &[NodeVal::Add, NodeVal::Sub, NodeVal::Mul][..],
+            _ => &[NodeVal::Add, NodeVal::Sub, NodeVal::Mul, NodeVal::Div, NodeVal::Exp],
+        };
+        // At least one operator, or there is nothing to convert.
+        let node = loop {
+            let node = random(rng, ops, 3);
+            if matches!(node, Node::Node { .. }) {
+                break node;
+            }
+        };
+        Self { kind, node }
+    }
+
+    /// The question as it is put.
+    fn prompt(&self) -> String {
+        match self.kind {
+            Kind::InfixToSexp => format!("Write as an S-expression: {}", self.node.to_infix()),
+            Kind::SexpToInfix => format!("Write in infix: {}", self.node),
+            Kind::InfixToRpn => format!("Write in reverse Polish notation: {}", self.node.to_infix()),
+            Kind::RpnToInfix => format!("Write in infix: {}", rpn(&self.node)),
+            Kind::Evaluate => format!("Evaluate: {}", self.node.to_infix()),
+        }
+    }
+
+    /// The answer, as it is shown after a wrong one.
+    fn answer(&self) -> String {
+        match self.kind {
+            Kind::InfixToSexp => self.node.to_string(),
+            Kind::SexpToInfix | Kind::RpnToInfix => self.node.to_infix(),
+            Kind::InfixToRpn => rpn(&self.node),
+            Kind::Evaluate => stoncc::eval(&self.node, &Env::new(), &mut Meter::new(Default::default())).map_or_else(|e| e.to_string(), |v| v.to_string()),
+        }
+    }
+
+    /// Whether `answer` is right. Conversions are right if they read back
+    /// as the same tree, whatever their spacing and extra parentheses.
+    fn check(&self, answer: &str) -> bool {
+        match self.kind {
+            Kind::InfixToSexp => Node::from_sexp(answer).is_ok_and(|n| n == self.node),
+            Kind::SexpToInfix | Kind::RpnToInfix => stoncc::expr(answer.as_bytes()).is_ok_and(|n| n == self.node),
+            Kind::InfixToRpn => from_rpn(answer).is_some_and(|n| n == self.node),
+            Kind::Evaluate => answer.trim() == self.answer(),
+        }
+    }
+}
+
+/// Asks `rounds` questions, reading an answer to each from `input` and
+/// saying whether it was right. Stops early at the end of `input`.
+/// Returns how many answers were right, and how many were given.
+fn quiz(rng: &mut Rng, rounds: usize, input: impl BufRead, out: &mut impl Write) -> io::Result<(usize, usize)> {
+    let mut lines = input.lines();
+    let (mut right, mut given) = (0, 0);
+    for round in 1..=rounds {
+        let q = Question::random(rng);
+        write!(out, "{round}/{rounds}. {}\n> ", q.prompt())?;
+        out.flush()?;
+        let Some(answer) = lines.next().transpose()? else {
+            writeln!(out)?;
+            break;
+        };
+        given += 1;
+        if q.check(&answer) {
+            right += 1;
+            writeln!(out, "Right.")?;
+        } else {
+            writeln!(out, "Not quite: {}", q.answer())?;
+        }
+    }
+    writeln!(out, "Score: {right}/{given}")?;
+    Ok((right, given))
+}
+
+fn usage() -> ! {
+    eprintln!("usage: stoncc quiz [--seed N] [--rounds N]");
+    std::process::exit(2);
+}
+
+/// `stoncc quiz`: asks for expressions to be converted between infix,
+/// S-expressions and reverse Polish notation, or evaluated, and checks the
+/// answers typed in. The same seed asks the same questions.
+pub fn main(args: &[String]) {
+    let mut seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+    let mut rounds = 5;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--seed" => seed = value().parse().unwrap_or_else(|_| usage()),
+            "--rounds" => rounds = value().parse().unwrap_or_else(|_| usage()),
+            _ => usage(),
+        }
+    }
+
+    quiz(&mut Rng::new(seed), rounds, io::stdin().lock(), &mut io::stdout()).unwrap_or_else(|e| fail(Failure::Run, e));
+}
+
+#[test]
+fn tests() {
+    let sum = |a, b| Node::op(NodeVal::Add, vec![a, b]);
+    let n = |v| Node::leaf(LeafVal::Int(v));
+    let node = Node::op(NodeVal::Mul, vec![sum(n(1), n(2)), n(3)]);
+    assert_eq!(rpn(&node), "1 2 + 3 *");
+    assert_eq!(from_rpn("1 2 + 3 *"), Some(node.clone()));
+    assert_eq!(from_rpn("1 2 3 +"), None);
+    assert_eq!(from_rpn("1 +"), None);
+
+    let q = |kind| Question { kind, node: node.clone() };
+    assert_eq!(q(Kind::InfixToSexp).prompt(), "Write as an S-expression: (1 + 2) * 3");
+    assert!(q(Kind::InfixToSexp).check(" (* (+ 1 2)  3)"));
+    assert!(!q(Kind::InfixToSexp).check("(+ 1 (* 2 3))"));
+    assert_eq!(q(Kind::RpnToInfix).prompt(), "Write in infix: 1 2 + 3 *");
+    assert!(q(Kind::RpnToInfix).check("((1 + 2)) * 3"));
+    assert!(!q(Kind::SexpToInfix).check("1 + 2 * 3"));
+    assert!(q(Kind::InfixToRpn).check("1 2 +  3 *"));
+    assert!(q(Kind::Evaluate).check("9"));
+    assert!(!q(Kind::Evaluate).check("7"));
+
+    // Every kind comes up, each answer is right for its own question, and
+    // expressions to evaluate have an integer value.
+    let mut rng = Rng::new(1);
+    let mut kinds = Vec::new();
+    for _ in 0..100 {
+        let q = Question::random(&mut rng);
+        assert!(q.check(&q.answer()), "{}", q.prompt());
+        if q.kind == Kind::Evaluate {
+            assert!(q.answer().parse::<i32>().is_ok(), "{}", q.answer());
+        }
+        kinds.push(q.kind);
+    }
+    assert!(KINDS.iter().all(|k| kinds.contains(k)));
+
+    // The same seed asks the same questions; the end of the input ends the
+    // quiz early.
+    let mut out = Vec::new();
+    let first = Question::random(&mut Rng::new(5));
+    let input = format!("{}\nwrong\n", first.answer());
+    assert_eq!(quiz(&mut Rng::new(5), 3, input.as_bytes(), &mut out).unwrap(), (1, 2));
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with(&format!("1/3. {}\n> Right.\n2/3. ", first.prompt())), "{out}");
+    assert!(out.contains("\n> Not quite: "), "{out}");
+    assert!(out.ends_with("\nScore: 1/2\n"), "{out}");
+}
@@ -0,0 +1,345 @@
+//! Exact rational evaluation (`--rational`). Every value is a fraction of
+//! arbitrary-precision integers in lowest terms, so `1/3 + 1/6` is `1/2`
+//! rather than `0`, and decimal literals are exact: `0.1` is `1/10`.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use crate::arith::{ArithError, NonInteger};
+use crate::bigint::{self, BigInt};
+use crate::eval::{walk, Numbers};
+use crate::lexer::Span;
+use crate::limits::{LimitExceeded, Meter};
+use crate::parser::{LeafVal, Node, NodeVal, Nodes, Value};
+use crate::{Builtins, Env, EvalError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rational {
+    num: BigInt,
+    /// Always positive, and sharing no factor with `num`.
+    den: BigInt,
+}
+
+impl Rational {
+    /// `num / den` in lowest terms, unless `den` is zero.
+    pub fn new(num: BigInt, den: BigInt) -> Option<Self> {
+        (!den.is_zero()).then(|| Self::reduced(num, den))
+    }
+
+    /// `num / den` in lowest terms, for a `den` that isn't zero.
+    fn reduced(num: BigInt, den: BigInt) -> Self {
+        let g = num.gcd(&den);
+        let (num, den) = (&num / &g, &den / &g);
+        if den < BigInt::default() {
+            Self { num: -&num, den: -&den }
+        } else {
+            Self { num, den }
+        }
+    }
+
+    pub fn from_int(v: BigInt) -> Self {
+        Self { num: v, den: BigInt::from_i64(1) }
+    }
+
+    pub fn numer(&self) -> &BigInt {
+        &self.num
+    }
+
+    pub fn denom(&self) -> &BigInt {
+        &self.den
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.num.is_zero()
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.den == BigInt::from_i64(1)
+    }
+
+    /// Bits needed for the numerator and denominator together.
+    pub fn bits(&self) -> u64 {
+        self.num.bits() + self.den.bits()
+    }
+
+    fn abs(&self) -> Self {
+        Self { num: self.num.abs(), den: self.den.clone() }
+    }
+
+    /// The terms of the continued fraction `a0 + 1/(a1 + 1/(a2 + ...))`
+    /// equal to `self`, where `a0` is its floor and the rest are positive.
+    /// The last term is more than 1, unless it is the only one.
+    pub fn continued_fraction(&self) -> Vec<BigInt> {
+        let (mut num, mut den) = (self.num.clone(), self.den.clone());
+        let mut terms = Vec::new();
+        while !den.is_zero() {
+            let mut q = &num / &den;
+            let mut r = &num % &den;
+            if r < BigInt::default() {
+                q = &q - &BigInt::from_i64(1);
+                r = &r + &den;
+            }
+            terms.push(q);
+            (num, den) = (den, r);
+        }
+        terms
+    }
+
+    /// The continued fraction of `self`, written `[a0; a1, a2, ...]`.
+    pub fn to_cfrac(&self) -> String {
+        let terms: Vec<String> = self.continued_fraction().iter().map(BigInt::to_string).collect();
+        match terms.split_first() {
+            Some((a0, [])) => format!("[{a0}]"),
+            Some((a0, rest)) => format!("[{a0}; {}]", rest.join(", ")),
+            None => unreachable!(),
+        }
+    }
+
+    /// `self ^ e`, for an `e` that `fits`; a negative `e` takes the
+    /// reciprocal, which must not be of zero.
+    fn pow(&self, e: &BigInt) -> Self {
+        let n = e.abs().to_u32().unwrap();
+        let (num, den) = (self.num.pow(n), self.den.pow(n));
+        if *e < BigInt::default() {
+            Self::reduced(den, num)
+        } else {
+            Self { num, den }
+        }
+    }
+}
+
+impl Add for &Rational {
+    type Output = Rational;
+
+    fn add(self, o: &Rational) -> Rational {
+        Rational::reduced(&(&self.num * &o.den) + &(&o.num * &self.den), &self.den * &o.den)
+    }
+}
+
+impl Neg for &Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational { num: -&self.num, den: self.den.clone() }
+    }
+}
+
+impl Sub for &Rational {
+    type Output = Rational;
+
+    fn sub(self, o: &Rational) -> Rational {
+        self + &-o
+    }
+}
+
+impl Mul for &Rational {
+    type Output = Rational;
+
+    fn mul(self, o: &Rational) -> Rational {
+        Rational::reduced(&self.num * &o.num, &self.den * &o.den)
+    }
+}
+
+/// Panics if `o` is zero, like `/` on machine integers.
+impl Div for &Rational {
+    type Output = Rational;
+
+    fn div(self, o: &Rational) -> Rational {
+        Rational::new(&self.num * &o.den, &self.den * &o.num).expect("division by zero")
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, o: &Self) -> Ordering {
+        (&self.num * &o.den).cmp(&(&o.num * &self.den))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, o: &Self) -> Option<Ordering> {
+        Some(self.cmp(o))
+    }
+}
+
+/// `n` for integers, otherwise `n/d`.
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_integer() {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+/// Converts a literal like `12`, `0.125` or `1e-3`, found at `span`,
+/// exactly.
+pub fn parse(text: &str, span: Span) -> Result<Rational, EvalError> {
+    let too_large = EvalError::Arith(ArithError::Overflow, span);
+    let (mantissa, exp) = match text.split_once(['e', 'E']) {
+        Some((m, e)) => (m, e.parse::<i64>().map_err(|_| too_large.clone())?),
+        None => (text, 0),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = bigint::parse(&format!("{int}{frac}"), span)?;
+
+    // Powers of ten left after the digits are read as an integer.
+    let shift = exp - frac.len() as i64;
+    let n = u32::try_from(shift.unsigned_abs()).map_err(|_| too_large)?;
+    // 10^n takes a little over 3.32 bits a power.
+    bigint::fits(digits.bits() + n as u64 * 10 / 3, span)?;
+    let scale = BigInt::from_i64(10).pow(n);
+    if shift < 0 {
+        Ok(Rational::reduced(digits, scale))
+    } else {
+        Ok(Rational::from_int(&digits * &scale))
+    }
+}
+
+/// `v` exactly, if it is a finite number. Floats are converted from their
+/// shortest decimal representation, so `0.1` is exactly one tenth.
+pub fn from_value(v: Value) -> Option<Rational> {
+    match v {
+        Value::Int(_) | Value::Wide(_) => bigint::from_value(v).map(Rational::from_int),
+        Value::Float(f) if f.is_finite() => parse(&format!("{f:?}"), Span::default()).ok(),
+        Value::Float(_) | Value::Duration(_) | Value::Quantity(..) => None,
+    }
+}
+
+fn apply(op: &NodeVal, args: &[Rational], span: Span, meter: &Meter) -> Result<Rational, EvalError> {
+    let arith = |e| EvalError::Arith(e, span);
+    // Results too wide are refused before the time goes into them.
+    let room = |bits: u64| -> Result<(), EvalError> {
+        bigint::fits(bits, span)?;
+        Ok(meter.bits(bits)?)
+    };
+    let integral = |v: &Rational| if v.is_integer() { Ok(v.num.clone()) } else { Err(arith(ArithError::NotInteger(op.clone(), NonInteger::Fraction))) };
+    Ok(match (op, args) {
+        (NodeVal::Add, [a]) => a.clone(),
+        (NodeVal::Sub, [a]) => -a,
+        // Fractions are brought to a common denominator on the way.
+        (NodeVal::Add | NodeVal::Sub | NodeVal::Mul | NodeVal::Div | NodeVal::Mod, [a, b]) if a.bits() + b.bits() > bigint::MAX_BITS => {
+            return Err(arith(ArithError::Overflow));
+        }
+        (NodeVal::Div | NodeVal::Mod, [_, b]) if b.is_zero() => return Err(arith(ArithError::DivisionByZero)),
+        (NodeVal::Add, [a, b]) => a + b,
+        (NodeVal::Sub, [a, b]) => a - b,
+        (NodeVal::Mul, [a, b]) => a * b,
+        (NodeVal::Div, [a, b]) => a / b,
+        // The remainder of the quotient rounded towards zero, with the sign
+        // of the dividend as for integers.
+        (NodeVal::Mod, [a, b]) => {
+            let q = a / b;
+            a - &(b * &Rational::from_int(&q.num / &q.den))
+        }
+        (NodeVal::Exp, [a, b]) => {
+            let e = integral(b)?;
+            match e.abs().to_u32() {
+                _ if a.is_zero() && e < BigInt::default() => return Err(arith(ArithError::DivisionByZero)),
+                // Only 0 and ±1 have powers this large that aren't too large.
+                None if a.bits() <= 2 => if e.is_odd() { a.clone() } else { a.abs() },
+                None => return Err(arith(ArithError::Overflow)),
+                Some(n) => {
+                    room(a.bits().saturating_sub(2) * n as u64 + 2)?;
+                    a.pow(&e)
+                }
+            }
+        }
+        (NodeVal::Fac, [a]) => Rational::from_int(bigint::factorial(&integral(a)?, span, meter)?),
+        (op, [a, b]) if op.is_comparison() => Rational::from_int(BigInt::from_i64(op.compare(a, b) as i64)),
+        (NodeVal::Call(name), _) => match (name.as_str(), args) {
+            ("abs", [a]) => a.abs(),
+            ("min", [_, ..]) => args.iter().min().unwrap().clone(),
+            ("max", [_, ..]) => args.iter().max().unwrap().clone(),
+            ("pow", [a, b]) => return apply(&NodeVal::Exp, &[a.clone(), b.clone()], span, meter),
+            _ => return Err(EvalError::Unsupported(format!("function {name}() is not available in --rational mode"), span)),
+        },
+        (NodeVal::AddWrap | NodeVal::MulWrap | NodeVal::AddSat | NodeVal::MulSat, _)
+        | (NodeVal::Shl | NodeVal::Shr | NodeVal::BitAnd | NodeVal::BitOr | NodeVal::BitNot, _) => {
+            let msg = format!("operator {op} models fixed-width integers and is not available in --rational mode");
+            return Err(EvalError::Unsupported(msg, span));
+        }
+        _ => unreachable!("operator {op} cannot take {} operands", args.len()),
+    })
+}
+
+/// Evaluates `ast` with exact fractions. Of `builtins`, only the functions
+/// the program defined itself are used, as in `decimal`.
+pub fn eval(ast: &Node, env: &Env<Rational>, builtins: &Builtins, meter: &mut Meter) -> Result<Rational, EvalError> {
+    walk(&Fractions, Nodes, ast, env, builtins, meter)
+}
+
+struct Fractions;
+
+impl Numbers for Fractions {
+    type V = Rational;
+
+    fn literal(&self, leaf: &LeafVal, span: Span) -> Result<Rational, EvalError> {
+        match leaf {
+            LeafVal::Int(v) => Ok(Rational::from_int(BigInt::from_i64(*v as i64))),
+            LeafVal::Decimal(s) => parse(s, span),
+            _ => unreachable!(),
+        }
+    }
+
+    fn is_true(&self, v: &Rational) -> bool {
+        !v.is_zero()
+    }
+
+    fn truth(&self, b: bool) -> Rational {
+        Rational::from_int(BigInt::from_i64(b as i64))
+    }
+
+    fn apply(&self, v: &NodeVal, args: &[Rational], span: Span, _: &Builtins, meter: &Meter) -> Result<Rational, EvalError> {
+        apply(v, args, span, meter)
+    }
+
+    fn check(&self, v: &Rational, meter: &Meter) -> Result<(), LimitExceeded> {
+        meter.bits(v.bits())
+    }
+}
+
+#[test]
+fn tests() {
+    let result = |s: &str| {
+        let mut meter = Meter::new(Default::default());
+        eval(&crate::parser::expr(s.as_bytes()).unwrap(), &Env::new(), &Builtins::default(), &mut meter)
+    };
+    let run = |s: &str| result(s).unwrap().to_string();
+
+    assert_eq!(run("1/3 + 1/6"), "1/2");
+    assert_eq!(run("0.1 + 0.2 == 0.3"), "1");
+    assert_eq!(run("-6 / 4"), "-3/2");
+    assert_eq!(run("3 / -6 * 4"), "-2");
+    assert_eq!(run("(2/3) ^ -2 - 1.25"), "1");
+    assert_eq!(run("1e-3 + 2.5E2"), "250001/1000");
+    assert_eq!(run("7/2 % 1"), "1/2");
+    assert_eq!(run("-7/2 % 1"), "-1/2");
+    assert_eq!(run("20! / 19!"), "20");
+    assert_eq!(run("max(1/3, abs(-0.3), 1/4) - min(1, 2/7)"), "1/21");
+    assert_eq!(run("1/3 < 0.34 && 1/3 ? 2/4 : 1/0"), "1/2");
+    assert_eq!(run("(-1) ^ (2 ^ 40 + 1) + 0 ^ (2 ^ 40) + 1 ^ -(2 ^ 40)"), "0");
+    let cfrac = |s: &str| result(s).unwrap().to_cfrac();
+    assert_eq!(cfrac("415/93"), "[4; 2, 6, 7]");
+    assert_eq!(cfrac("-7/2"), "[-4; 2]");
+    assert_eq!(cfrac("1/3"), "[0; 3]");
+    assert_eq!(cfrac("-6"), "[-6]");
+    assert_eq!(from_value(Value::Float(0.1)), parse("0.1", Span::default()).ok());
+    assert_eq!(from_value(Value::Wide(-(1 << 80))).unwrap().to_string(), "-1208925819614629174706176");
+    assert_eq!(from_value(Value::Float(f64::NAN)), None);
+    assert_eq!(Rational::new(BigInt::from_i64(1), BigInt::default()), None);
+
+    // Errors, at the operator that has no result.
+    let span = |start, end| Span { start, end };
+    assert_eq!(result("1 / (1/2 - 0.5)"), Err(EvalError::Arith(ArithError::DivisionByZero, span(0, 15))));
+    assert_eq!(result("1/2 % 0"), Err(EvalError::Arith(ArithError::DivisionByZero, span(0, 7))));
+    assert_eq!(result("0 ^ -2"), Err(EvalError::Arith(ArithError::DivisionByZero, span(0, 6))));
+    assert_eq!(result("2 ^ 0.5"), Err(EvalError::Arith(ArithError::NotInteger(NodeVal::Exp, NonInteger::Fraction), span(0, 7))));
+    assert_eq!(result("(1/2)!"), Err(EvalError::Arith(ArithError::NotInteger(NodeVal::Fac, NonInteger::Fraction), span(0, 6))));
+    assert_eq!(result("(-3)!"), Err(EvalError::Arith(ArithError::NegativeFactorial, span(0, 5))));
+    assert_eq!(result("(2/3) ^ (2 ^ 40)"), Err(EvalError::Arith(ArithError::Overflow, span(0, 16))));
+    assert_eq!(result("1 + 1e-99999999").map_err(|e| e.code()), Err("arithmetic-overflow"));
+    assert_eq!(result("sqrt(2)").map_err(|e| e.to_string()), Err("function sqrt() is not available in --rational mode".into()));
+    assert_eq!(result("1 +% 2").map_err(|e| e.code()), Err("unsupported"));
+}
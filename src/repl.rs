@@ -0,0 +1,68 @@
+//! `stoncc repl`: reads programs a line at a time and runs each in one
+//! session, printing its value. Ctrl-C stops a runaway program with an
+//! error and leaves the session, and what was defined before, as it was.
+
+use std::io::{self, BufRead, Write};
+
+use stoncc::limits::CancelToken;
+use stoncc::Session;
+
+use crate::{fail, interrupt, Failure};
+
+/// Runs each line of `input` in `session`, writing its value, or the
+/// error, to `out` after a `> ` prompt. Resets `token` before each line,
+/// so that a Ctrl-C only stops the program it came during.
+fn repl(session: &Session, token: &CancelToken, input: impl BufRead, out: &mut impl Write) -> io::Result<()> {
+    let mut lines = input.lines();
+    loop {
+        write!(out, "> ")?;
+        out.flush()?;
+        let Some(line) = lines.next().transpose()? else {
+            return writeln!(out);
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        token.reset();
+        match session.eval_str(&line) {
+            Ok(Some(v)) => writeln!(out, "{v}")?,
+            Ok(None) => {}
+            Err(e) => writeln!(out, "error: {e}")?,
+        }
+    }
+}
+
+pub fn main(args: &[String]) {
+    if !args.is_empty() {
+        eprintln!("usage: stoncc repl");
+        std::process::exit(2);
+    }
+    let token = interrupt::token();
+    let session = Session::builder().cancel(token.clone()).build();
+    repl(&session, &token, io::stdin().lock(), &mut io::stdout()).unwrap_or_else(|e| fail(Failure::Run, e));
+}
+
+#[test]
+fn tests() {
+    let run = |session: &Session, token: &CancelToken, input: &str| {
+        let mut out = Vec::new();
+        repl(session, token, input.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    let token = CancelToken::new();
+    let session = Session::builder().cancel(token.clone()).build();
+    assert_eq!(run(&session, &token, "x = 6\n\nx * 7\n1 / 0\nx\n"), "> 6\n> > 42\n> error: division by zero\n> 6\n> \n");
+
+    // A cancelled program fails, but the next line runs, with what was
+    // defined before.
+    token.cancel();
+    assert_eq!(run(&session, &token, "x + 1\n"), "> 7\n> \n");
+    let stop = token.clone();
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        stop.cancel();
+    });
+    let out = run(&session, &token, "i = 0; while (1) i = i + 1\nx - 1\n");
+    canceller.join().unwrap();
+    assert_eq!(out, "> error: evaluation was cancelled\n> 5\n> \n");
+}
@@ -0,0 +1,253 @@
+//! Name resolution. `resolve` reports every variable read before the
+//! statement that assigns it, every function called before it is defined
+//! or defined twice, and every variable nothing reads, without any values:
+//! a symbol that nothing assigns is an input, and is fine.
+//!
+//! Like [`sema`](crate::sema), a variable assigned in a branch counts as
+//! assigned after it, one assigned in a loop body counts all through the
+//! body, and a function body sees whatever is defined by the time it runs.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::lexer::Span;
+use crate::parser::{LeafVal, Node, NodeVal, Stmt};
+
+/// A problem `resolve` found, and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ResolveError {
+    /// A variable read before anything assigns it.
+    UsedBeforeAssignment(String, Span),
+    /// A function called before its definition.
+    CalledBeforeDefinition(String, Span),
+    /// A function defined again, and where it was first.
+    Redefined(String, Span, Span),
+    /// A variable assigned but never read.
+    Unused(String, Span),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::UsedBeforeAssignment(s, _) => write!(f, "variable {s} is read before it is assigned"),
+            ResolveError::CalledBeforeDefinition(s, _) => write!(f, "function {s} is called before it is defined"),
+            ResolveError::Redefined(s, _, _) => write!(f, "function {s} is defined more than once"),
+            ResolveError::Unused(s, _) => write!(f, "variable {s} is assigned but never read"),
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+impl ResolveError {
+    /// A stable name for the kind of problem.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ResolveError::UsedBeforeAssignment(..) => "used-before-assignment",
+            ResolveError::CalledBeforeDefinition(..) => "called-before-definition",
+            ResolveError::Redefined(..) => "redefined",
+            ResolveError::Unused(..) => "unused-variable",
+        }
+    }
+
+    /// Whether the program can still be right: an unused variable may be
+    /// a result worth printing.
+    pub fn is_warning(&self) -> bool {
+        matches!(self, ResolveError::Unused(..))
+    }
+
+    /// The offending name, if the tree still knows where it was.
+    pub fn span(&self) -> Option<Span> {
+        let (ResolveError::UsedBeforeAssignment(_, span)
+        | ResolveError::CalledBeforeDefinition(_, span)
+        | ResolveError::Redefined(_, span, _)
+        | ResolveError::Unused(_, span)) = self;
+        Some(*span).filter(|s| s.end > s.start)
+    }
+}
+
+/// Where the name of an assignment or definition is: at its start.
+fn name_span(name: &str, span: Span) -> Span {
+    Span { start: span.start, end: span.start + name.len() }
+}
+
+/// The variables a tree reads, but for `params`, and the functions it
+/// calls, each with where.
+#[derive(Default)]
+struct Names<'a> {
+    params: &'a [String],
+    vars: Vec<(&'a str, Span)>,
+    calls: Vec<(&'a str, Span)>,
+}
+
+impl<'a> Names<'a> {
+    fn of(node: &'a Node, params: &'a [String]) -> Self {
+        /// `Visitor` hands out borrows that don't outlive each call, so
+        /// the walk is by hand.
+        fn walk<'a>(names: &mut Names<'a>, node: &'a Node) {
+            match node {
+                Node::Leaf(LeafVal::Sym(s), span) if !names.params.iter().any(|p| p == s.as_str()) => names.vars.push((s.as_str(), *span)),
+                Node::Node { v, children, span } => {
+                    if let NodeVal::Call(name) = v {
+                        names.calls.push((name.as_str(), *span));
+                    }
+                    children.iter().for_each(|c| walk(names, c));
+                }
+                _ => {}
+            }
+        }
+
+        let mut names = Names { params, ..Default::default() };
+        walk(&mut names, node);
+        names
+    }
+}
+
+/// The assignments in `stmt`, nested ones included, and the functions it
+/// defines.
+fn definitions<'s>(stmt: &'s Stmt, vars: &mut Vec<(&'s str, Span)>, fns: &mut HashSet<&'s str>) {
+    match stmt {
+        Stmt::Assign { name, span, .. } => vars.push((name, name_span(name, *span))),
+        Stmt::Def { name, .. } => {
+            fns.insert(name);
+        }
+        Stmt::Block { stmts, .. } => stmts.iter().for_each(|s| definitions(s, vars, fns)),
+        Stmt::If { then, els, .. } => {
+            definitions(then, vars, fns);
+            els.iter().for_each(|s| definitions(s, vars, fns));
+        }
+        Stmt::While { body, .. } => definitions(body, vars, fns),
+        Stmt::Expr(_) => {}
+    }
+}
+
+struct Resolver<'a> {
+    /// Every variable the program assigns, and every function it defines.
+    assigned: HashSet<&'a str>,
+    defined: HashSet<&'a str>,
+    /// Variables assigned so far, and where each function was defined.
+    vars: HashSet<&'a str>,
+    functions: HashMap<&'a str, Span>,
+    errors: Vec<ResolveError>,
+}
+
+impl<'a> Resolver<'a> {
+    fn expr(&mut self, node: &'a Node) {
+        let names = Names::of(node, &[]);
+        for (s, span) in names.vars {
+            if self.assigned.contains(s) && !self.vars.contains(s) {
+                self.errors.push(ResolveError::UsedBeforeAssignment(s.to_string(), span));
+            }
+        }
+        for (s, span) in names.calls {
+            if self.defined.contains(s) && !self.functions.contains_key(s) {
+                self.errors.push(ResolveError::CalledBeforeDefinition(s.to_string(), span));
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt) {
+        match stmt {
+            Stmt::Assign { name, value, .. } => {
+                self.expr(value);
+                self.vars.insert(name);
+            }
+            Stmt::Def { name, span, .. } => {
+                let span = name_span(name, *span);
+                if let Some(&first) = self.functions.get(name.as_str()) {
+                    self.errors.push(ResolveError::Redefined(name.clone(), span, first));
+                } else {
+                    self.functions.insert(name, span);
+                }
+            }
+            Stmt::Expr(e) => self.expr(e),
+            Stmt::Block { stmts, .. } => stmts.iter().for_each(|s| self.stmt(s)),
+            Stmt::If { cond, then, els, .. } => {
+                self.expr(cond);
+                self.stmt(then);
+                els.iter().for_each(|s| self.stmt(s));
+            }
+            Stmt::While { cond, body, .. } => {
+                self.expr(cond);
+                let mut assigned = Vec::new();
+                definitions(body, &mut assigned, &mut HashSet::new());
+                self.vars.extend(assigned.into_iter().map(|(name, _)| name));
+                self.stmt(body);
+            }
+        }
+    }
+}
+
+/// Resolves the names in `stmts`, run in order, and returns what is wrong
+/// with them in source order.
+pub fn resolve<'a>(stmts: impl IntoIterator<Item = &'a Stmt>) -> Vec<ResolveError> {
+    let stmts: Vec<&Stmt> = stmts.into_iter().collect();
+    let mut assignments = Vec::new();
+    let mut defined = HashSet::new();
+    stmts.iter().for_each(|s| definitions(s, &mut assignments, &mut defined));
+
+    let mut r = Resolver {
+        assigned: assignments.iter().map(|&(name, _)| name).collect(),
+        defined,
+        vars: HashSet::new(),
+        functions: HashMap::new(),
+        errors: Vec::new(),
+    };
+    stmts.iter().for_each(|s| r.stmt(s));
+
+    // Function bodies run when they are called, so they only count as
+    // reading what they read.
+    let mut read = HashSet::new();
+    for stmt in &stmts {
+        let names = match stmt {
+            Stmt::Def { params, body, .. } => Names::of(body, params).vars,
+            stmt => stmt.exprs().into_iter().flat_map(|e| Names::of(e, &[]).vars).collect(),
+        };
+        read.extend(names.into_iter().map(|(s, _)| s));
+    }
+    let mut reported = HashSet::new();
+    for (name, span) in assignments {
+        if !read.contains(name) && reported.insert(name) {
+            r.errors.push(ResolveError::Unused(name.to_string(), span));
+        }
+    }
+
+    r.errors.sort_by_key(|e| e.span().map_or(0, |s| s.start));
+    r.errors
+}
+
+#[test]
+fn tests() {
+    let run = |s: &str| {
+        let program = crate::program(s.as_bytes()).0.unwrap();
+        resolve(&program.stmts).iter().map(|e| format!("{}: {e}", e.code())).collect::<Vec<_>>()
+    };
+
+    // Symbols nothing assigns are inputs; the rest must be assigned first.
+    assert_eq!(run("x = a + b\ny = x * c\ny"), [] as [&str; 0]);
+    assert_eq!(
+        run("y = x + 1\nx = 2\nf(1)\nf(a) = a * y\nf(a) = a\nx + f(2)"),
+        [
+            "used-before-assignment: variable x is read before it is assigned",
+            "called-before-definition: function f is called before it is defined",
+            "redefined: function f is defined more than once",
+        ]
+    );
+
+    // Bodies see what is defined by the time they run, and what they read
+    // is used.
+    assert_eq!(run("f(a) = a * k + g(a); g(b) = b; k = 2; f(3)"), [] as [&str; 0]);
+    assert_eq!(run("t = 1; u = 2; t = 3; u"), ["unused-variable: variable t is assigned but never read"]);
+
+    // Assignments in a branch count after it, and in a loop body, all
+    // through it.
+    assert_eq!(run("if (n) a = 1; a"), [] as [&str; 0]);
+    assert_eq!(run("i = 0; while (i < 3) { if (i) t + 1; t = i; i = i + 1 }"), [] as [&str; 0]);
+    assert_eq!(run("while (j < 3) j = j + 1"), ["used-before-assignment: variable j is read before it is assigned"]);
+
+    let program = crate::program(b"p = 1\nq = 2\np").0.unwrap();
+    let errors = resolve(&program.stmts);
+    assert_eq!(errors[0].span(), Some(Span { start: 6, end: 7 }));
+    assert!(errors[0].is_warning());
+}
@@ -0,0 +1,142 @@
+//! User-defined rewrite rules (`--rules=FILE`). A rules file holds one
+//! `pattern => replacement` pair per line in the pattern syntax, e.g.
+//!
+//! ```text
+//! # x * 1 is x
+//! (* ?x 1) => ?x
+//! (- (- ?x)) => ?x
+//! ```
+
+use stoncc::parser::{LeafVal, Node, NodeVal};
+use stoncc::{Folder, Span};
+use crate::input::{self, Encoding};
+use crate::pattern::{self, Pattern};
+
+use crate::{fail, Failure};
+
+/// Rewriting gives up after this many rewrites, so that rules which undo
+/// each other can't loop forever.
+pub const MAX_REWRITES: usize = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    /// Where the rule was written, for `--explain-opt`: `line 3`, or
+    /// `FILE line 3` once loaded.
+    pub name: String,
+    pub lhs: Pattern,
+    pub rhs: Pattern,
+}
+
+pub fn parse(text: &str) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let at = |e: String| format!("line {}: {e}", n + 1);
+        let (lhs, rhs) = line.split_once("=>").ok_or_else(|| at("expected 'pattern => replacement'".into()))?;
+        let lhs = Pattern::parse(lhs).map_err(at)?;
+        let rhs = Pattern::parse(rhs).map_err(at)?;
+
+        if let Some(v) = rhs.vars().into_iter().find(|v| !lhs.vars().contains(v)) {
+            return Err(at(format!("?{v} is not bound by the pattern")));
+        }
+        rules.push(Rule { name: format!("line {}", n + 1), lhs, rhs });
+    }
+
+    Ok(rules)
+}
+
+pub fn load(path: &str) -> Vec<Rule> {
+    let text = String::from_utf8(input::read(path, Encoding::Utf8)).unwrap();
+    let mut rules = parse(&text).unwrap_or_else(|e| fail(Failure::Run, format!("{path}: {e}")));
+    rules.iter_mut().for_each(|r| r.name = format!("{path} {}", r.name));
+    rules
+}
+
+/// Rewrites `node` bottom-up, applying the first matching rule at each
+/// subtree until none match anymore.
+pub fn rewrite(node: &Node, rules: &[Rule]) -> Result<Node, String> {
+    // Every level copies its subtree, which is slow for huge trees.
+    if rules.is_empty() {
+        return Ok(node.clone());
+    }
+    node.fold(&mut Rewrite { rules, budget: MAX_REWRITES, log: None })
+}
+
+/// `rewrite`, along with every rewrite it made, in order, for
+/// `--explain-opt`.
+pub fn rewrite_explained(node: &Node, rules: &[Rule]) -> Result<(Node, Vec<stoncc::Rewrite>), String> {
+    let mut rewrite = Rewrite { rules, budget: MAX_REWRITES, log: Some(Vec::new()) };
+    let node = node.fold(&mut rewrite)?;
+    Ok((node, rewrite.log.unwrap_or_default()))
+}
+
+/// Rewrites each node, once its operands are rewritten, until no rule
+/// matches it. Each rewrite is added to `log`, if there is one.
+struct Rewrite<'a> {
+    rules: &'a [Rule],
+    budget: usize,
+    log: Option<Vec<stoncc::Rewrite>>,
+}
+
+impl Rewrite<'_> {
+    fn settle(&mut self, mut node: Node) -> Result<Node, String> {
+        loop {
+            let Some((rule, b)) = self.rules.iter().find_map(|r| Some((r, pattern::match_pattern(&node, &r.lhs)?))) else {
+                return Ok(node);
+            };
+            if self.budget == 0 {
+                return Err(format!("rewrite rules did not terminate after {MAX_REWRITES} rewrites"));
+            }
+            self.budget -= 1;
+
+            // Operands taken over from the match are already rewritten, but
+            // the operators the replacement introduces may match again.
+            let after = rule.rhs.instantiate(&b);
+            if let Some(log) = &mut self.log {
+                let span = node.span();
+                log.push(stoncc::Rewrite { rule: rule.name.clone(), before: node, after: after.clone(), span });
+            }
+            node = after;
+            if let Node::Node { children, .. } = &mut node {
+                *children = children.iter().map(|c| c.fold(self)).collect::<Result<_, _>>()?;
+            }
+        }
+    }
+}
+
+impl Folder for Rewrite<'_> {
+    type Error = String;
+
+    fn fold_leaf(&mut self, v: &LeafVal, span: Span) -> Result<Node, String> {
+        self.settle(Node::Leaf(v.clone(), span))
+    }
+
+    fn fold_node(&mut self, v: &NodeVal, children: Vec<Node>, span: Span) -> Result<Node, String> {
+        self.settle(Node::Node { v: v.clone(), children, span })
+    }
+}
+
+#[test]
+fn tests() {
+    let rules = parse("# identities\n(* ?x 1) => ?x\n(- (- ?x)) => ?x\n(+ ?x ?x) => (* 2 ?x)\n").unwrap();
+    let run = |s: &str| rewrite(&stoncc::parser::expr(s.as_bytes()).unwrap(), &rules).map(|n| n.to_string());
+
+    assert_eq!(run("--(a * 1) + b"), Ok("(+ a b)".into()));
+    assert_eq!(run("(c * 1) + c"), Ok("(* 2 c)".into()));
+
+    assert!(parse("(+ ?x 0) => ?y").unwrap_err().contains("?y is not bound"));
+    assert!(parse("(+ ?x 0)").is_err());
+
+    let (node, log) = rewrite_explained(&stoncc::parser::expr(b"--(a * 1) + b").unwrap(), &rules).unwrap();
+    assert_eq!(node.to_string(), "(+ a b)");
+    let log: Vec<_> = log.iter().map(|r| (r.rule.as_str(), r.before.to_string(), r.after.to_string(), r.span.start)).collect();
+    assert_eq!(log, [("line 2", "(* a 1)".into(), "a".into(), 2), ("line 3", "(- (- a))".into(), "a".into(), 0)]);
+
+    let looping = parse("(+ ?a ?b) => (+ ?b ?a)").unwrap();
+    assert!(rewrite(&stoncc::parser::expr(b"1 + 2").unwrap(), &looping).is_err());
+}
@@ -0,0 +1,114 @@
+use std::ops::RangeInclusive;
+
+use crate::{fail, theme, Failure};
+use stoncc::{parser, vm, Env, Value};
+
+/// The most assignments `stoncc satisfies` tries, over all its ranges.
+const MAX_ASSIGNMENTS: usize = 1 << 20;
+
+/// Reads `--var NAME=FROM..TO` into the name and the range of integers.
+fn var(spec: &str) -> Result<(String, RangeInclusive<i32>), String> {
+    let (name, range) = spec.split_once('=').ok_or_else(|| format!("--var expects NAME=FROM..TO, got '{spec}'"))?;
+    let (from, to) = range.split_once("..").ok_or_else(|| format!("--var expects NAME=FROM..TO, got '{spec}'"))?;
+    let bound = |s: &str| s.trim().parse::<i32>().map_err(|_| format!("'{}' in --var {spec} is not an integer", s.trim()));
+    let (from, to) = (bound(from)?, bound(to)?);
+    if from > to {
+        return Err(format!("--var {spec} is an empty range"));
+    }
+    Ok((name.trim().to_string(), from..=to))
+}
+
+/// Every assignment of the variables in `vars`, the first varying slowest.
+fn assignments(vars: &[(String, RangeInclusive<i32>)]) -> Vec<Env> {
+    vars.iter().fold(vec![Env::new()], |envs, (name, range)| {
+        envs.iter()
+            .flat_map(|env| range.clone().map(move |v| {
+                let mut env = env.clone();
+                env.insert(name.clone(), Value::Int(v));
+                env
+            }))
+            .collect()
+    })
+}
+
+/// The assignments of `vars` under which `expr` holds, each as a line like
+/// `x = 3, y = 4`, and a warning for each one under which it fails to
+/// evaluate.
+pub fn satisfies(expr: &str, vars: &[(String, RangeInclusive<i32>)]) -> Result<(Vec<String>, Vec<String>), String> {
+    let expr = parser::expr(expr.as_bytes()).map_err(|e| format!("{expr:?}: {e}"))?;
+    let count = vars.iter().try_fold(1usize, |n, (_, range)| n.checked_mul(range.clone().count()));
+    if count.is_none_or(|n| n > MAX_ASSIGNMENTS) {
+        return Err(format!("the ranges have more than {MAX_ASSIGNMENTS} assignments between them"));
+    }
+
+    let envs = assignments(vars);
+    let results = vm::CompiledExpr::new(&expr).eval_batch(&envs);
+    let (mut holds, mut warnings) = (Vec::new(), Vec::new());
+    for (env, v) in envs.iter().zip(results) {
+        let assignment = vars.iter().map(|(name, _)| format!("{name} = {}", env[name])).collect::<Vec<_>>().join(", ");
+        match v {
+            Ok(v) if v.is_true() => holds.push(assignment),
+            Ok(_) => {}
+            Err(e) => warnings.push(format!("{assignment}: {e}")),
+        }
+    }
+    Ok((holds, warnings))
+}
+
+fn usage() -> ! {
+    eprintln!("usage: stoncc satisfies EXPR --var NAME=FROM..TO...");
+    std::process::exit(2);
+}
+
+/// `stoncc satisfies EXPR --var x=1..20`: prints each assignment of the
+/// integers in the ranges under which `EXPR` is true, that is nonzero.
+/// With several `--var`, every combination is tried. Exits with 1 if none
+/// is, like grep.
+pub fn main(args: &[String]) {
+    let (mut expr, mut vars) = (None, Vec::new());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--var" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                vars.push(var(spec).unwrap_or_else(|e| fail(Failure::Usage, e)));
+            }
+            _ if expr.is_none() && !arg.starts_with("--") => expr = Some(arg),
+            _ => usage(),
+        }
+    }
+    let Some(expr) = expr.filter(|_| !vars.is_empty()) else { usage() };
+
+    let (holds, warnings) = satisfies(expr, &vars).unwrap_or_else(|e| fail(Failure::Run, e));
+    for w in &warnings {
+        eprintln!("{}: {w}", theme::label("warning"));
+    }
+    holds.iter().for_each(|line| println!("{line}"));
+    if holds.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+#[test]
+fn tests() {
+    assert_eq!(var("x=1..20"), Ok(("x".into(), 1..=20)));
+    assert_eq!(var("n = -3 .. 3"), Ok(("n".into(), -3..=3)));
+    assert_eq!(var("x=5..1"), Err("--var x=5..1 is an empty range".into()));
+    assert_eq!(var("x=1..y"), Err("'y' in --var x=1..y is not an integer".into()));
+    assert!(var("x").is_err());
+
+    let (holds, warnings) = satisfies("x^2 < 50", &[var("x=1..20").unwrap()]).unwrap();
+    assert_eq!(holds, (1..=7).map(|x| format!("x = {x}")).collect::<Vec<_>>());
+    assert!(warnings.is_empty());
+
+    let pythagorean = [var("a=1..10").unwrap(), var("b=1..10").unwrap(), var("c=1..15").unwrap()];
+    let (holds, _) = satisfies("a < b && a*a + b*b == c*c", &pythagorean).unwrap();
+    assert_eq!(holds, ["a = 3, b = 4, c = 5", "a = 6, b = 8, c = 10"]);
+
+    let (holds, warnings) = satisfies("12 % x == 0", &[var("x=-1..4").unwrap()]).unwrap();
+    assert_eq!(holds, ["x = -1", "x = 1", "x = 2", "x = 3", "x = 4"]);
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].starts_with("x = 0: "), "{warnings:?}");
+
+    assert!(satisfies("x < 1", &[var("x=0..100000").unwrap(), var("y=0..100").unwrap()]).is_err());
+}
@@ -0,0 +1,318 @@
+//! Semantic analysis. `check` walks a program before it runs or is
+//! compiled and reports every symbol read before anything assigns it,
+//! every call to a function that doesn't exist or with the wrong number of
+//! arguments, and every float given to an operator that only takes
+//! integers, rather than stopping at the first.
+//!
+//! It gives the program the benefit of the doubt: a variable assigned in
+//! one branch of an `if` counts as assigned after it, and one whose values
+//! may be integers or floats is never an error.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::Visitor;
+use crate::builtins::{expects, Builtins};
+use crate::eval::Env;
+use crate::lexer::Span;
+use crate::parser::{LeafVal, Node, NodeVal, Stmt, Value};
+
+/// A problem `check` found, and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SemaError {
+    Unbound(String, Span),
+    UnknownFunction(String, Span),
+    /// A call with a number of arguments the function doesn't take.
+    Arity(String, String, Span),
+    /// A float operand of an operator that only takes integers.
+    NotInteger(NodeVal, Span),
+}
+
+impl fmt::Display for SemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SemaError::Unbound(s, _) => write!(f, "symbol {s} is not defined"),
+            SemaError::UnknownFunction(s, _) => write!(f, "function {s} is not defined"),
+            SemaError::Arity(s, msg, _) => write!(f, "{s}() {msg}"),
+            SemaError::NotInteger(v, _) => write!(f, "operator {v} takes integers, not floats"),
+        }
+    }
+}
+
+impl std::error::Error for SemaError {}
+
+impl SemaError {
+    /// A stable name for the kind of error, for `--expect-error`: the same
+    /// as evaluation gives where they overlap.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SemaError::Unbound(..) => "unbound-symbol",
+            SemaError::UnknownFunction(..) => "unknown-function",
+            SemaError::Arity(..) => "bad-arguments",
+            SemaError::NotInteger(..) => "not-an-integer",
+        }
+    }
+
+    /// The offending source text, if the tree still knows where it was.
+    pub fn span(&self) -> Option<Span> {
+        let (SemaError::Unbound(_, span)
+        | SemaError::UnknownFunction(_, span)
+        | SemaError::Arity(_, _, span)
+        | SemaError::NotInteger(_, span)) = self;
+        Some(*span).filter(|s| s.end > s.start)
+    }
+}
+
+/// What is known of the values of an expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ty {
+    Int,
+    Float,
+    Any,
+}
+
+impl Ty {
+    fn of(v: &Value) -> Ty {
+        match v {
+            Value::Int(_) | Value::Wide(_) => Ty::Int,
+            Value::Float(_) => Ty::Float,
+            Value::Duration(_) | Value::Quantity(..) => Ty::Any,
+        }
+    }
+
+    fn join(self, other: Ty) -> Ty {
+        if self == other {
+            self
+        } else {
+            Ty::Any
+        }
+    }
+}
+
+/// Whether `v` only takes integers.
+fn integral(v: &NodeVal) -> bool {
+    use NodeVal::*;
+    matches!(v, Fac | AddWrap | MulWrap | AddSat | MulSat | Mod | Shl | Shr | BitAnd | BitOr | BitNot)
+}
+
+/// The assignments in `stmt`, nested ones included.
+fn assignments<'s>(stmt: &'s Stmt, out: &mut Vec<(&'s String, &'s Node)>) {
+    match stmt {
+        Stmt::Assign { name, value, .. } => out.push((name, value)),
+        Stmt::Block { stmts, .. } => stmts.iter().for_each(|s| assignments(s, out)),
+        Stmt::If { then, els, .. } => {
+            assignments(then, out);
+            els.iter().for_each(|s| assignments(s, out));
+        }
+        Stmt::While { body, .. } => assignments(body, out),
+        Stmt::Def { .. } | Stmt::Expr(_) => {}
+    }
+}
+
+struct Checker<'a> {
+    builtins: &'a Builtins,
+    /// Variables assigned so far.
+    vars: HashSet<String>,
+    /// Parameter counts of the functions defined so far.
+    functions: HashMap<String, usize>,
+    /// What each variable holds over the whole program.
+    types: HashMap<String, Ty>,
+    errors: Vec<SemaError>,
+}
+
+impl<'a> Checker<'a> {
+    /// Checks `node`, in which `params` are bound, and returns its type.
+    fn expr(&mut self, node: &Node, params: &[String]) -> Ty {
+        /// The type of each node, worked out once its operands have theirs.
+        struct Types<'c, 'a> {
+            checker: &'c mut Checker<'a>,
+            params: &'c [String],
+            done: Vec<Ty>,
+        }
+
+        impl Visitor for Types<'_, '_> {
+            fn leave(&mut self, node: &Node) {
+                let ty = match node {
+                    Node::Leaf(v, span) => self.checker.leaf(v, *span, self.params),
+                    // Reported by the parser already.
+                    Node::Error(_) => Ty::Any,
+                    Node::Node { v, children, span } => {
+                        let tys = self.done.split_off(self.done.len() - children.len());
+                        self.checker.apply(v, children, &tys, *span)
+                    }
+                };
+                self.done.push(ty);
+            }
+        }
+
+        let mut types = Types { checker: self, params, done: Vec::new() };
+        node.visit(&mut types);
+        types.done.pop().unwrap()
+    }
+
+    fn leaf(&mut self, v: &LeafVal, span: Span, params: &[String]) -> Ty {
+        match v {
+            LeafVal::Int(_) | LeafVal::Bool(_) => Ty::Int,
+            // An integer too wide for an `i32`, in the modes that allow one.
+            LeafVal::Decimal(s) if s.bytes().all(|c| c.is_ascii_digit()) => Ty::Int,
+            LeafVal::Decimal(_) => Ty::Float,
+            LeafVal::Sym(s) if params.iter().any(|p| p == s.as_str()) => Ty::Any,
+            LeafVal::Sym(s) => {
+                if !self.vars.contains(s.as_str()) {
+                    self.errors.push(SemaError::Unbound(s.to_string(), span));
+                }
+                self.types.get(s.as_str()).copied().unwrap_or(Ty::Any)
+            }
+            LeafVal::Placeholder(_) => Ty::Any,
+        }
+    }
+
+    fn apply(&mut self, v: &NodeVal, children: &[Node], tys: &[Ty], span: Span) -> Ty {
+        match v {
+            NodeVal::Call(name) => {
+                self.call(name, children.len(), span);
+                Ty::Any
+            }
+            NodeVal::And | NodeVal::Or => Ty::Int,
+            NodeVal::Cond => tys[1].join(tys[2]),
+            v if v.is_comparison() => Ty::Int,
+            v if integral(v) => {
+                for (c, _) in children.iter().zip(tys).filter(|(_, &t)| t == Ty::Float) {
+                    self.errors.push(SemaError::NotInteger(v.clone(), c.span()));
+                }
+                Ty::Int
+            }
+            _ if tys.contains(&Ty::Float) => Ty::Float,
+            _ if tys.contains(&Ty::Any) => Ty::Any,
+            _ => Ty::Int,
+        }
+    }
+
+    fn call(&mut self, name: &str, argc: usize, span: Span) {
+        let defined = self.functions.get(name).copied().or_else(|| self.builtins.defined(name).map(|f| f.params.len()));
+        let Some(arity) = defined.map(|n| n..=n).or_else(|| self.builtins.arity(name)) else {
+            self.errors.push(SemaError::UnknownFunction(name.to_string(), span));
+            return;
+        };
+        if !arity.contains(&argc) {
+            self.errors.push(SemaError::Arity(name.to_string(), expects(&arity, argc), span));
+        }
+    }
+
+    /// Checks `stmt`, leaving the bodies of the functions it defines in
+    /// `bodies` for later.
+    fn stmt(&mut self, stmt: &'a Stmt, bodies: &mut Vec<(&'a [String], &'a Node)>) {
+        match stmt {
+            Stmt::Assign { name, value, .. } => {
+                self.expr(value, &[]);
+                self.vars.insert(name.clone());
+            }
+            Stmt::Def { name, params, body, .. } => {
+                self.functions.insert(name.clone(), params.len());
+                bodies.push((params, body));
+            }
+            Stmt::Expr(e) => {
+                self.expr(e, &[]);
+            }
+            Stmt::Block { stmts, .. } => stmts.iter().for_each(|s| self.stmt(s, bodies)),
+            Stmt::If { cond, then, els, .. } => {
+                self.expr(cond, &[]);
+                self.stmt(then, bodies);
+                els.iter().for_each(|s| self.stmt(s, bodies));
+            }
+            Stmt::While { cond, body, .. } => {
+                self.expr(cond, &[]);
+                // A pass through the body can read what an earlier pass
+                // assigned.
+                let mut assigned = Vec::new();
+                assignments(body, &mut assigned);
+                self.vars.extend(assigned.into_iter().map(|(name, _)| name.clone()));
+                self.stmt(body, bodies);
+            }
+        }
+    }
+}
+
+/// Checks `stmts`, run in order with the variables in `env` defined and
+/// calls going to `builtins`, and returns what is wrong with them in
+/// source order.
+pub fn check<'a>(stmts: impl IntoIterator<Item = &'a Stmt>, env: &Env, builtins: &Builtins) -> Vec<SemaError> {
+    let stmts: Vec<&Stmt> = stmts.into_iter().collect();
+    let mut c = Checker {
+        builtins,
+        vars: env.keys().cloned().collect(),
+        functions: HashMap::new(),
+        types: env.iter().map(|(name, v)| (name.clone(), Ty::of(v))).collect(),
+        errors: Vec::new(),
+    };
+
+    // A variable is an integer or a float only if every value it is given
+    // is one, whatever the order.
+    let mut assigned = Vec::new();
+    stmts.iter().for_each(|s| assignments(s, &mut assigned));
+    loop {
+        let before = c.types.clone();
+        for &(name, value) in &assigned {
+            let ty = c.expr(value, &[]);
+            c.types.entry(name.clone()).and_modify(|t| *t = t.join(ty)).or_insert(ty);
+        }
+        if c.types == before {
+            break;
+        }
+    }
+    c.errors.clear();
+
+    let mut bodies = Vec::new();
+    for s in stmts {
+        c.stmt(s, &mut bodies);
+    }
+    // A body runs when it is called, by when any variable or function may
+    // have been defined.
+    for (params, body) in bodies {
+        c.expr(body, params);
+    }
+
+    c.errors.sort_by_key(|e| e.span().map_or(0, |s| s.start));
+    c.errors
+}
+
+#[test]
+fn tests() {
+    let run = |s: &str| {
+        let env: Env = [("n".to_string(), Value::Int(3)), ("r".to_string(), Value::Float(0.5))].into();
+        let program = crate::program(s.as_bytes()).0.unwrap();
+        check(&program.stmts, &env, &Builtins::default()).iter().map(|e| format!("{}: {e}", e.code())).collect::<Vec<_>>()
+    };
+
+    // Everything wrong is reported, in source order.
+    assert_eq!(
+        run("x = y + 1\nz(x) + abs(1, 2) + min()\nx"),
+        [
+            "unbound-symbol: symbol y is not defined",
+            "unknown-function: function z is not defined",
+            "bad-arguments: abs() expects 1 argument, got 2",
+            "bad-arguments: min() expects at least 1 argument, got 0",
+        ]
+    );
+    assert_eq!(run("f(1); f(a) = a\nf(1, 2)"), ["unknown-function: function f is not defined", "bad-arguments: f() expects 1 argument, got 2"]);
+
+    // Bodies see what is defined by the time they are called.
+    assert_eq!(run("f(a) = a * k + g(a); g(b) = b; k = 2; f(3) + n"), [] as [&str; 0]);
+    assert_eq!(run("f(a) = b"), ["unbound-symbol: symbol b is not defined"]);
+
+    // Assignments in a branch count after it, and in a loop body, all
+    // through it.
+    assert_eq!(run("if (n) a = 1; a"), [] as [&str; 0]);
+    assert_eq!(run("i = 0; while (i < 3) { if (i) t + 1; t = i; i = i + 1 }"), [] as [&str; 0]);
+    assert_eq!(run("while (j < 3) j = j + 1"), ["unbound-symbol: symbol j is not defined"]);
+
+    // Floats where integers are needed, when they are sure to be floats.
+    assert_eq!(run("x = 2.5; (x % 2) + (n << 1)"), ["not-an-integer: operator % takes integers, not floats"]);
+    assert_eq!(run("(1 + r)! + (n ? 1.5 : 2.5) & 1"), ["not-an-integer: operator ! takes integers, not floats", "not-an-integer: operator & takes integers, not floats"]);
+    assert_eq!(run("y = 1; y = 0.5; (y % 2) + (sqrt(4) % 2) + ((n ? 1 : 2.5) % 2)"), [] as [&str; 0]);
+    assert_eq!(run("z = w; w = 1.5; v = w; v >> 1"), ["unbound-symbol: symbol w is not defined", "not-an-integer: operator >> takes integers, not floats"]);
+
+    let program = crate::program(b"1 +\n  q").0.unwrap();
+    assert_eq!(check(&program.stmts, &Env::new(), &Builtins::default())[0].span(), Some(Span { start: 6, end: 7 }));
+}
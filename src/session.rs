@@ -0,0 +1,828 @@
+//! Running programs. [`exec`] runs one statement against an environment,
+//! for any kind of number, and [`Session`] keeps the variables, functions
+//! and settings of an embedding between calls, so that an application can
+//! evaluate source text without putting the lexer, parser and evaluator
+//! together itself.
+//!
+//! A session can be shared by threads. Programs that only read run at the
+//! same time as each other. A program that assigns a variable or defines a
+//! function runs alone: it waits for the programs running to finish, and
+//! the programs after it wait for it, so none ever sees some of its
+//! effects but not others. If it fails, the statements before the failing
+//! one keep their effects, as they would in a single thread.
+//! [`Session::reload`] swaps in a whole new set of definitions, such as an
+//! updated formula library, in the same way, and
+//! [`Session::eval_str_over`] runs a program with variables of its own
+//! over those of the session, which no other program sees.
+//!
+//! A session serving many clients can bound what they take from each
+//! other: [`SessionBuilder::max_running`] turns programs away while as
+//! many are running, and [`SessionBuilder::fuel`] gives all programs
+//! together a budget of operators to apply, which [`Session::refuel`] tops
+//! up. Either way the program fails with [`SessionError::Busy`] rather
+//! than waiting or running on.
+//!
+//! ```
+//! use stoncc::{ArithMode, Session, Value};
+//!
+//! let session = Session::builder().arith(ArithMode::Wrap).build();
+//! session.eval_str("f(x) = x * 2; a = f(21)").unwrap();
+//! assert_eq!(session.eval_str("a + 2147483647"), Ok(Some(Value::Int(-2147483607))));
+//! assert_eq!(session.get("a"), Some(Value::Int(42)));
+//! ```
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Instant, SystemTime};
+
+use crate::arith::ArithMode;
+use crate::audit::Entry;
+#[cfg(feature = "bigint")]
+use crate::bigint::BigInt;
+use crate::builtins::{Builtins, Function};
+use crate::eval::{eval_in, eval_with, Env, EvalError, Layered};
+use crate::lexer::Span;
+use crate::limits::{CancelToken, EvalLimits, LimitExceeded, Meter};
+use crate::metrics::Metrics;
+use crate::parser::{program_with, Extensions, Node, ParseError, Program, Stmt, Value};
+use crate::snapshot::{self, LoadError};
+
+/// Values that conditions can test.
+pub trait Truth: Clone {
+    fn is_true(&self) -> bool;
+}
+
+impl Truth for Value {
+    fn is_true(&self) -> bool {
+        Value::is_true(*self)
+    }
+}
+
+/// Decimal units.
+impl Truth for i128 {
+    fn is_true(&self) -> bool {
+        *self != 0
+    }
+}
+
+#[cfg(feature = "bigint")]
+impl Truth for BigInt {
+    fn is_true(&self) -> bool {
+        !self.is_zero()
+    }
+}
+
+#[cfg(feature = "rational")]
+impl Truth for crate::rational::Rational {
+    fn is_true(&self) -> bool {
+        !self.is_zero()
+    }
+}
+
+/// Runs `stmt`, binding the variables and functions it defines. Returns
+/// its value, if it has one (see `Stmt::has_value`).
+pub fn exec<V: Truth>(
+    stmt: &Stmt,
+    env: &mut Env<V>,
+    builtins: &mut Builtins,
+    eval: &mut impl FnMut(&Node, &Env<V>, &Builtins) -> Result<V, EvalError>,
+) -> Result<Option<V>, EvalError> {
+    match stmt {
+        Stmt::Def { name, params, body, .. } => {
+            builtins.define(name, Function { params: params.clone(), body: body.clone() });
+            Ok(None)
+        }
+        Stmt::Assign { name, value, .. } => {
+            let v = eval(value, env, builtins)?;
+            env.insert(name.clone(), v.clone());
+            Ok(Some(v))
+        }
+        Stmt::Expr(e) => eval(e, env, builtins).map(Some),
+        Stmt::Block { stmts, .. } => {
+            let mut v = None;
+            for s in stmts {
+                v = exec(s, env, builtins, eval)?;
+            }
+            Ok(v)
+        }
+        Stmt::If { cond, then, els, .. } => {
+            let v = match els {
+                _ if eval(cond, env, builtins)?.is_true() => exec(then, env, builtins, eval)?,
+                Some(els) => exec(els, env, builtins, eval)?,
+                None => None,
+            };
+            Ok(v.filter(|_| stmt.has_value()))
+        }
+        Stmt::While { cond, body, .. } => {
+            while eval(cond, env, builtins)?.is_true() {
+                exec(body, env, builtins, eval)?;
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Why `Session::eval_str` failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SessionError {
+    Parse(ParseError),
+    Eval(EvalError),
+    /// A function definition in a program run over an overlay, which only
+    /// keeps its effects to itself for variables.
+    Define(String, Span),
+    /// The session turned the program away, or stopped it, to keep it from
+    /// taking more than its share.
+    Busy(Busy),
+}
+
+/// What a busy session has run out of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Busy {
+    /// As many programs as it runs at once, `SessionBuilder::max_running`,
+    /// are running.
+    Running(usize),
+    /// The fuel of `SessionBuilder::fuel` is spent.
+    Fuel,
+}
+
+impl From<ParseError> for SessionError {
+    fn from(e: ParseError) -> Self {
+        SessionError::Parse(e)
+    }
+}
+
+impl From<EvalError> for SessionError {
+    fn from(e: EvalError) -> Self {
+        SessionError::Eval(e)
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Parse(e) => write!(f, "{e}"),
+            SessionError::Eval(e) => write!(f, "{e}"),
+            SessionError::Define(name, _) => write!(f, "a program run over an overlay can't define function {name}"),
+            SessionError::Busy(Busy::Running(n)) => write!(f, "the session is busy running {n} programs already"),
+            SessionError::Busy(Busy::Fuel) => write!(f, "the session is busy: its evaluation fuel is spent"),
+        }
+    }
+}
+
+impl SessionError {
+    /// A stable name for the kind of error: that of the parse or
+    /// evaluation error, `define-in-overlay` or `busy`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SessionError::Parse(e) => e.kind.code(),
+            SessionError::Eval(e) => e.code(),
+            SessionError::Define(..) => "define-in-overlay",
+            SessionError::Busy(_) => "busy",
+        }
+    }
+}
+
+impl std::error::Error for SessionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SessionError::Parse(e) => Some(e),
+            SessionError::Eval(e) => Some(e),
+            SessionError::Define(..) | SessionError::Busy(_) => None,
+        }
+    }
+}
+
+/// Where a session writes its audit log.
+#[derive(Clone)]
+struct Audit(Arc<dyn Fn(&str) + Send + Sync>);
+
+impl fmt::Debug for Audit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Audit(..)")
+    }
+}
+
+/// Settings for a `Session`, from `Session::builder`.
+#[derive(Debug, Clone)]
+pub struct SessionBuilder {
+    arith: ArithMode,
+    int_width: u32,
+    limits: EvalLimits,
+    ext: Extensions,
+    prelude: bool,
+    metrics: bool,
+    max_running: Option<usize>,
+    fuel: Option<u64>,
+    audit: Option<Audit>,
+    cancel: Option<CancelToken>,
+}
+
+impl SessionBuilder {
+    /// What integer overflow does (default `ArithMode::Checked`).
+    pub fn arith(self, arith: ArithMode) -> Self {
+        Self { arith, ..self }
+    }
+
+    /// How many bits integers have (default 32), as `ArithMode::with_width`
+    /// takes them: 32, or 128, which fails on overflow.
+    ///
+    /// # Panics
+    ///
+    /// `build` panics if the width is neither, or is 128 with an `arith`
+    /// that wraps or saturates.
+    pub fn int_width(self, bits: u32) -> Self {
+        Self { int_width: bits, ..self }
+    }
+
+    /// Limits on each call to `eval_str` (default none).
+    pub fn limits(self, limits: EvalLimits) -> Self {
+        Self { limits, ..self }
+    }
+
+    /// Optional syntax to accept (default none).
+    pub fn extensions(self, ext: Extensions) -> Self {
+        Self { ext, ..self }
+    }
+
+    /// Whether `min`, `max` and the other default functions are defined
+    /// (default yes). Without them, only functions the programs define or
+    /// `Session::builtins_mut` registers can be called.
+    pub fn prelude(self, prelude: bool) -> Self {
+        Self { prelude, ..self }
+    }
+
+    /// Whether to keep `Metrics` of the programs run (default no), for
+    /// `Session::metrics`.
+    pub fn metrics(self, metrics: bool) -> Self {
+        Self { metrics, ..self }
+    }
+
+    /// Most programs that may run at once (default no limit). Past it, a
+    /// program fails with `Busy::Running` without starting.
+    pub fn max_running(self, n: usize) -> Self {
+        Self { max_running: Some(n), ..self }
+    }
+
+    /// Operators that all programs together may apply (default no limit),
+    /// as `EvalLimits::max_ops` counts them for each. A program that finds
+    /// the fuel spent fails with `Busy::Fuel`, and so does one that spends
+    /// the last of it. Programs running at once can each spend what was
+    /// left when they started, so the budget is overrun by that at most.
+    pub fn fuel(self, ops: u64) -> Self {
+        Self { fuel: Some(ops), ..self }
+    }
+
+    /// Gives `log` a line of JSON, an `audit::Entry`, for every program
+    /// the session is given (default none), as it finishes. Its input is
+    /// named `<program>`, or `<reload>` for `Session::reload`, and its
+    /// variables are the overlay of `Session::eval_str_over`: those of the
+    /// session come from the programs logged before it.
+    pub fn audit(self, log: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self { audit: Some(Audit(Arc::new(log))), ..self }
+    }
+
+    /// Lets `token` stop the programs the session runs (default none):
+    /// once it is cancelled, each fails with `EvalError::Cancelled` before
+    /// its next operator, and so does every program started until it is
+    /// reset. The session stays usable, with what they did before.
+    pub fn cancel(self, token: CancelToken) -> Self {
+        Self { cancel: Some(token), ..self }
+    }
+
+    pub fn build(self) -> Session {
+        let builtins = if self.prelude { Builtins::default() } else { Builtins::empty() };
+        let state = RwLock::new(State { env: Env::new(), builtins });
+        let metrics = self.metrics.then(Metrics::default);
+        Session {
+            state,
+            arith: self.arith.with_width(self.int_width).unwrap_or_else(|e| panic!("{e}")),
+            limits: self.limits,
+            ext: self.ext,
+            metrics,
+            max_running: self.max_running,
+            running: AtomicUsize::new(0),
+            fuel: self.fuel.map(AtomicU64::new),
+            audit: self.audit,
+            cancel: self.cancel,
+        }
+    }
+}
+
+/// What programs have defined so far.
+struct State {
+    env: Env,
+    builtins: Builtins,
+}
+
+/// Variables and functions that programs define, kept from one call to
+/// the next.
+pub struct Session {
+    state: RwLock<State>,
+    arith: ArithMode,
+    limits: EvalLimits,
+    ext: Extensions,
+    metrics: Option<Metrics>,
+    max_running: Option<usize>,
+    running: AtomicUsize,
+    /// Operators left to apply, if there is a budget.
+    fuel: Option<AtomicU64>,
+    audit: Option<Audit>,
+    cancel: Option<CancelToken>,
+}
+
+/// A program counted as running, until dropped.
+struct Running<'a>(&'a AtomicUsize);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::builder().build()
+    }
+}
+
+impl Session {
+    pub fn builder() -> SessionBuilder {
+        SessionBuilder {
+            arith: ArithMode::default(),
+            int_width: 32,
+            limits: EvalLimits::default(),
+            ext: Extensions::default(),
+            prelude: true,
+            metrics: false,
+            max_running: None,
+            fuel: None,
+            audit: None,
+            cancel: None,
+        }
+    }
+
+    // A program that panicked leaves the state as a failed one would.
+    fn read(&self) -> RwLockReadGuard<'_, State> {
+        self.state.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, State> {
+        self.state.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Parses `s` and gives the program to `run` with a meter, if the
+    /// session isn't busy, timing both and counting what fails if the
+    /// session keeps metrics. Characters the lexer skips are an error, and
+    /// nothing runs. The audit log, if any, names `s` as `name`, given
+    /// `vars`.
+    fn request<T: Outcome>(
+        &self,
+        name: &str,
+        s: &str,
+        vars: &Env,
+        run: impl FnOnce(Program, &mut Meter) -> Result<T, SessionError>,
+    ) -> Result<T, SessionError> {
+        if let Some(m) = &self.metrics {
+            m.request();
+        }
+        let (time, start) = (SystemTime::now(), Instant::now());
+        let result = self.admit().and_then(|_running| self.metered(s, run));
+        if let (Some(m), Err(e)) = (&self.metrics, &result) {
+            m.error(e.code());
+        }
+        if let Some(Audit(log)) = &self.audit {
+            let mut vars: Vec<_> = vars.iter().map(|(k, v)| (k.clone(), v.to_string())).collect();
+            vars.sort();
+            let entry = Entry {
+                time,
+                inputs: vec![(name.to_string(), s.as_bytes())],
+                vars,
+                outcome: result.as_ref().map(T::printed).map_err(|e| (e.code().to_string(), e.to_string())),
+                duration: start.elapsed(),
+            };
+            log(&entry.to_json());
+        }
+        result
+    }
+
+    /// Counts a program as running, if it may run.
+    fn admit(&self) -> Result<Running<'_>, SessionError> {
+        let before = self.running.fetch_add(1, Ordering::Relaxed);
+        let running = Running(&self.running);
+        match self.max_running {
+            Some(max) if before >= max => Err(SessionError::Busy(Busy::Running(max))),
+            _ => Ok(running),
+        }
+    }
+
+    fn metered<T>(&self, s: &str, run: impl FnOnce(Program, &mut Meter) -> Result<T, SessionError>) -> Result<T, SessionError> {
+        let mut limits = self.limits;
+        let fuel = self.fuel.as_ref().map(|fuel| fuel.load(Ordering::Relaxed));
+        match fuel {
+            Some(0) => return Err(SessionError::Busy(Busy::Fuel)),
+            Some(left) => limits = limits.min(EvalLimits { max_ops: Some(left), ..Default::default() }),
+            None => {}
+        }
+        let mut meter = Meter::new(limits).with_arith(self.arith);
+        if let Some(token) = &self.cancel {
+            meter = meter.with_cancel(token.clone());
+        }
+
+        let start = Instant::now();
+        let (program, skipped) = program_with(s.as_bytes(), self.ext);
+        let parsed = match skipped.into_iter().next() {
+            Some(e) => Err(e.into()),
+            None => program.map_err(SessionError::from),
+        };
+        if let Some(m) = &self.metrics {
+            m.parsed(start.elapsed());
+        }
+        let program = parsed?;
+
+        let start = Instant::now();
+        let result = run(program, &mut meter);
+        if let Some(m) = &self.metrics {
+            m.evaluated(start.elapsed());
+        }
+        let (Some(fuel), Some(left)) = (&self.fuel, fuel) else { return result };
+        let spent = meter.ops().load(Ordering::Relaxed);
+        let _ = fuel.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |f| Some(f.saturating_sub(spent)));
+        match result {
+            // Stopped by the fuel rather than by its own limit.
+            Err(SessionError::Eval(EvalError::Limit(LimitExceeded::Ops(max)))) if max == left && self.limits.max_ops.is_none_or(|own| own > left) => {
+                Err(SessionError::Busy(Busy::Fuel))
+            }
+            result => result,
+        }
+    }
+
+    /// The fuel left, if the session has a budget.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel.as_ref().map(|fuel| fuel.load(Ordering::Relaxed))
+    }
+
+    /// Adds `ops` to the fuel, as a service might every second, if the
+    /// session has a budget.
+    pub fn refuel(&self, ops: u64) {
+        if let Some(fuel) = &self.fuel {
+            let _ = fuel.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |f| Some(f.saturating_add(ops)));
+        }
+    }
+
+    /// The metrics of the programs run so far in the Prometheus text
+    /// format, if the session keeps them.
+    pub fn metrics(&self) -> Option<String> {
+        self.metrics.as_ref().map(Metrics::render)
+    }
+
+    /// Parses `s` as a program and runs it, returning the value of its last
+    /// statement that has one. Characters the lexer skips are an error
+    /// here, and nothing runs. Statements before one that fails keep their
+    /// effects.
+    pub fn eval_str(&self, s: &str) -> Result<Option<Value>, SessionError> {
+        self.request("<program>", s, &Env::new(), |program, meter| self.run(program, meter))
+    }
+
+    fn run(&self, program: Program, meter: &mut Meter) -> Result<Option<Value>, SessionError> {
+        let mut last = None;
+        if program.stmts.iter().all(reads_only) {
+            let state = self.read();
+            let mut eval = |e: &Node, _: &Env| eval_with(e, &state.env, &state.builtins, meter);
+            for stmt in &program.stmts {
+                last = reading(stmt, &mut Env::new(), &mut eval)?.or(last);
+            }
+        } else {
+            let state = &mut *self.write();
+            let mut eval = |e: &Node, env: &Env, b: &Builtins| eval_with(e, env, b, meter);
+            for stmt in &program.stmts {
+                last = exec(stmt, &mut state.env, &mut state.builtins, &mut eval)?.or(last);
+            }
+        }
+        Ok(last)
+    }
+
+    /// Like `eval_str`, with the variables in `overlay` over those of the
+    /// session, for this program only. What the program assigns stays
+    /// with it too, so it can run alongside other programs, but it can't
+    /// define functions.
+    pub fn eval_str_over(&self, s: &str, overlay: &Env) -> Result<Option<Value>, SessionError> {
+        self.request("<program>", s, overlay, |program, meter| self.run_over(program, overlay, meter))
+    }
+
+    fn run_over(&self, program: Program, overlay: &Env, meter: &mut Meter) -> Result<Option<Value>, SessionError> {
+        if let Some((name, span)) = program.stmts.iter().find_map(definition) {
+            return Err(SessionError::Define(name.clone(), span));
+        }
+
+        let state = self.read();
+        let below = Layered { top: overlay, below: &state.env };
+        let mut eval = |e: &Node, local: &Env| eval_in(e, &Layered { top: local, below: &below }, &state.builtins, meter);
+        let mut local = Env::new();
+        let mut last = None;
+        for stmt in &program.stmts {
+            last = reading(stmt, &mut local, &mut eval)?.or(last);
+        }
+        Ok(last)
+    }
+
+    /// Replaces every variable and function programs have defined with
+    /// those the program `s` defines, as one change: programs running
+    /// finish with the old ones, and programs after see only the new ones.
+    /// Functions registered with `Session::builtins_mut` stay. If `s` fails
+    /// to parse or to run, nothing changes.
+    pub fn reload(&self, s: &str) -> Result<(), SessionError> {
+        self.request("<reload>", s, &Env::new(), |program, meter| self.swap(program, meter))
+    }
+
+    fn swap(&self, program: Program, meter: &mut Meter) -> Result<(), SessionError> {
+        let mut eval = |e: &Node, env: &Env, b: &Builtins| eval_with(e, env, b, meter);
+
+        let state = &mut *self.write();
+        let mut env = Env::new();
+        let old = state.builtins.swap_defined(Default::default());
+        if let Err(e) = program.stmts.iter().try_for_each(|stmt| exec(stmt, &mut env, &mut state.builtins, &mut eval).map(drop)) {
+            state.builtins.swap_defined(old);
+            return Err(e.into());
+        }
+        state.env = env;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.read().env.get(name).copied()
+    }
+
+    pub fn set(&self, name: &str, v: Value) {
+        self.write().env.insert(name.to_string(), v);
+    }
+
+    /// A copy of the variables as they are now.
+    pub fn env(&self) -> Env {
+        self.read().env.clone()
+    }
+
+    /// The variables and the functions programs defined, as text for
+    /// `load` to read back, maybe in another session (see `snapshot`).
+    pub fn save(&self) -> String {
+        let state = self.read();
+        snapshot::save(&state.env, &state.builtins)
+    }
+
+    /// Adds the variables and functions of text that `save` gave, in one
+    /// change, replacing those of the same names.
+    pub fn load(&self, text: &str) -> Result<(), LoadError> {
+        let state = &mut *self.write();
+        snapshot::load(text, &mut state.env, &mut state.builtins)
+    }
+
+    /// The functions programs can call, to `register` more.
+    pub fn builtins_mut(&mut self) -> &mut Builtins {
+        &mut self.state.get_mut().unwrap_or_else(PoisonError::into_inner).builtins
+    }
+}
+
+/// What a program came to, as an audit log shows it.
+trait Outcome {
+    fn printed(&self) -> Option<String>;
+}
+
+impl Outcome for Option<Value> {
+    fn printed(&self) -> Option<String> {
+        self.map(|v| v.to_string())
+    }
+}
+
+impl Outcome for () {
+    fn printed(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Runs `stmt` like `exec`, but with the variables it assigns in `local`,
+/// so that the state can stay shared. `eval` is given `local` too.
+fn reading(
+    stmt: &Stmt,
+    local: &mut Env,
+    eval: &mut impl FnMut(&Node, &Env) -> Result<Value, EvalError>,
+) -> Result<Option<Value>, EvalError> {
+    match stmt {
+        Stmt::Expr(e) => eval(e, local).map(Some),
+        Stmt::Assign { name, value, .. } => {
+            let v = eval(value, local)?;
+            local.insert(name.clone(), v);
+            Ok(Some(v))
+        }
+        Stmt::Block { stmts, .. } => stmts.iter().try_fold(None, |_, s| reading(s, local, eval)),
+        Stmt::If { cond, then, els, .. } => {
+            let v = match els {
+                _ if eval(cond, local)?.is_true() => reading(then, local, eval)?,
+                Some(els) => reading(els, local, eval)?,
+                None => None,
+            };
+            Ok(v.filter(|_| stmt.has_value()))
+        }
+        Stmt::While { cond, body, .. } => {
+            while eval(cond, local)?.is_true() {
+                reading(body, local, eval)?;
+            }
+            Ok(None)
+        }
+        Stmt::Def { .. } => unreachable!("programs that define functions aren't run this way"),
+    }
+}
+
+/// The first function `stmt` defines, and where.
+fn definition(stmt: &Stmt) -> Option<(&String, Span)> {
+    match stmt {
+        Stmt::Def { name, span, .. } => Some((name, *span)),
+        Stmt::Assign { .. } | Stmt::Expr(_) => None,
+        Stmt::Block { stmts, .. } => stmts.iter().find_map(definition),
+        Stmt::If { then, els, .. } => definition(then).or_else(|| els.as_deref().and_then(definition)),
+        Stmt::While { body, .. } => definition(body),
+    }
+}
+
+/// Whether `stmt` neither assigns nor defines anything.
+fn reads_only(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Assign { .. } | Stmt::Def { .. } => false,
+        Stmt::Expr(_) => true,
+        Stmt::Block { stmts, .. } => stmts.iter().all(reads_only),
+        Stmt::If { then, els, .. } => reads_only(then) && els.as_deref().is_none_or(reads_only),
+        Stmt::While { body, .. } => reads_only(body),
+    }
+}
+
+#[test]
+fn tests() {
+    let s = Session::default();
+    s.set("n", Value::Int(5));
+    assert_eq!(s.eval_str("fac(k) = k <= 1 ? 1 : k * fac(k - 1)\ni = 0; while (i < n) i = i + 1"), Ok(Some(Value::Int(0))));
+    assert_eq!(s.eval_str("if (i == n) fac(n) else 0"), Ok(Some(Value::Int(120))));
+    assert_eq!(s.eval_str("max(i, 2.5)"), Ok(Some(Value::Float(5.0))));
+    assert_eq!(s.eval_str("{ i; 7 }; if (0) 1; while (0) 2"), Ok(Some(Value::Int(7))));
+
+    // A failing statement leaves what came before it, and a program that
+    // doesn't parse runs nothing.
+    assert!(matches!(s.eval_str("j = 1; k = i / 0; l = 1"), Err(SessionError::Eval(EvalError::Arith(..)))));
+    assert_eq!((s.get("j"), s.get("k")), (Some(Value::Int(1)), None));
+    assert!(matches!(s.eval_str("m = 1; n = $"), Err(SessionError::Parse(_))));
+    assert_eq!(s.get("m"), None);
+    assert_eq!(s.eval_str("2147483647 + 1").unwrap_err().to_string(), "integer overflow");
+
+    let mut s = Session::builder().prelude(false).limits(EvalLimits { max_ops: Some(10), ..Default::default() }).build();
+    assert!(matches!(s.eval_str("max(1, 2)"), Err(SessionError::Eval(EvalError::UnknownFunction(..)))));
+    s.builtins_mut().register("twice", 1..=1, |args| Ok(Value::Int(args[0].as_i128() as i32 * 2)));
+    assert_eq!(s.eval_str("twice(4)"), Ok(Some(Value::Int(8))));
+    assert_eq!(s.eval_str("i = 0; while (1) i = i + 1").unwrap_err().to_string(), "evaluation exceeded max_ops (10 operations)");
+    // Limits apply to each call on its own.
+    assert_eq!(s.eval_str("1 + 2"), Ok(Some(Value::Int(3))));
+
+    let s = Session::builder().extensions(Extensions { calculator: true, ..Default::default() }).build();
+    assert_eq!(s.eval_str("50% of 8"), Ok(Some(Value::Float(4.0))));
+
+    let s = Session::builder().int_width(128).build();
+    assert_eq!(s.eval_str("2147483647 + 1"), Ok(Some(Value::Wide(1 << 31))));
+    assert_eq!(s.eval_str("2 ^ 127").unwrap_err().to_string(), "integer overflow");
+    assert!(std::panic::catch_unwind(|| Session::builder().int_width(128).arith(ArithMode::Wrap).build()).is_err());
+
+    // Readers never see a program that writes halfway through.
+    let s = Session::default();
+    s.eval_str("x = 0; y = 0; f(a) = a - y").unwrap();
+    std::thread::scope(|scope| {
+        scope.spawn(|| (0..200).for_each(|_| _ = s.eval_str("x = x + 1; y = x").unwrap()));
+        for _ in 0..4 {
+            scope.spawn(|| (0..200).for_each(|_| assert_eq!(s.eval_str("f(x)"), Ok(Some(Value::Int(0))))));
+        }
+    });
+    assert_eq!(s.env().get("y"), Some(&Value::Int(200)));
+
+    // Overlays shadow the session's variables for one program, and what
+    // it assigns goes no further.
+    let s = Session::default();
+    s.eval_str("rate = 2; base = 10; f(a) = a * rate").unwrap();
+    let overlay: Env = [("rate".to_string(), Value::Int(3))].into();
+    assert_eq!(s.eval_str_over("t = f(base); t + 1", &overlay), Ok(Some(Value::Int(31))));
+    assert_eq!(s.eval_str_over("base = base + rate; base", &overlay), Ok(Some(Value::Int(13))));
+    assert_eq!((s.get("t"), s.get("base"), s.get("rate")), (None, Some(Value::Int(10)), Some(Value::Int(2))));
+    assert!(matches!(s.eval_str_over("1; { g(a) = a }", &overlay), Err(SessionError::Define(..))));
+    std::thread::scope(|scope| {
+        for i in 0..4 {
+            let s = &s;
+            scope.spawn(move || {
+                let overlay: Env = [("rate".to_string(), Value::Int(i))].into();
+                (0..100).for_each(|_| assert_eq!(s.eval_str_over("base = 1; f(base)", &overlay), Ok(Some(Value::Int(i)))));
+            });
+        }
+    });
+
+    // Metrics count every program, and each error by its code.
+    let s = Session::builder().metrics(true).build();
+    s.eval_str("a = 1").unwrap();
+    s.eval_str("a +").unwrap_err();
+    s.eval_str_over("b", &Env::new()).unwrap_err();
+    s.reload("c = d").unwrap_err();
+    let text = s.metrics().unwrap();
+    assert!(text.contains("stoncc_requests_total 4
+") && text.contains("stoncc_parse_seconds_count 4
+"), "{text}");
+    assert!(text.contains("stoncc_eval_seconds_count 3
+"));
+    assert!(text.contains("stoncc_errors_total{code=\"unbound-symbol\"} 2
+") && text.contains("{code=\"unexpected-token\"} 1
+"));
+    assert_eq!(Session::default().metrics(), None);
+
+    // A reload replaces everything defined at once, or nothing.
+    let mut s = Session::default();
+    s.builtins_mut().register("one", 0..=0, |_| Ok(Value::Int(1)));
+    s.eval_str("rate = 2; f(a) = a * rate; g(a) = a").unwrap();
+    assert!(matches!(s.reload("rate = 3; f(a) = a; b = h(rate)"), Err(SessionError::Eval(EvalError::UnknownFunction(..)))));
+    assert_eq!(s.eval_str("f(5) + g(1)"), Ok(Some(Value::Int(11))));
+    s.reload("rate = one() + 2; f(a) = a * rate").unwrap();
+    assert_eq!(s.eval_str("f(5)"), Ok(Some(Value::Int(15))));
+    assert!(matches!(s.eval_str("g(1)"), Err(SessionError::Eval(EvalError::UnknownFunction(..)))));
+    s.reload("x = 0; y = 0").unwrap();
+    std::thread::scope(|scope| {
+        scope.spawn(|| (0..200).for_each(|i| s.reload(&format!("x = {i}; y = {i}")).unwrap()));
+        for _ in 0..4 {
+            scope.spawn(|| (0..200).for_each(|_| assert_eq!(s.eval_str("x - y"), Ok(Some(Value::Int(0))))));
+        }
+    });
+
+    // Past `max_running`, programs are turned away until one finishes.
+    use std::sync::{Arc, Barrier};
+    let mut s = Session::builder().max_running(1).metrics(true).build();
+    let (started, release) = (Arc::new(Barrier::new(2)), Arc::new(Barrier::new(2)));
+    let (a, b) = (Arc::clone(&started), Arc::clone(&release));
+    s.builtins_mut().register("wait", 0..=0, move |_| {
+        a.wait();
+        b.wait();
+        Ok(Value::Int(1))
+    });
+    std::thread::scope(|scope| {
+        let first = scope.spawn(|| s.eval_str("wait()"));
+        started.wait();
+        assert_eq!(s.eval_str("1"), Err(SessionError::Busy(Busy::Running(1))));
+        release.wait();
+        assert_eq!(first.join().unwrap(), Ok(Some(Value::Int(1))));
+    });
+    assert_eq!(s.eval_str("2"), Ok(Some(Value::Int(2))));
+    assert!(s.metrics().unwrap().contains("stoncc_errors_total{code=\"busy\"} 1\n"));
+
+    // Fuel is shared by every program, and stops the one that spends the
+    // last of it, but not one that hits its own limit first.
+    let s = Session::builder().fuel(100).limits(EvalLimits { max_ops: Some(30), ..Default::default() }).build();
+    assert_eq!(s.eval_str("1 + 2 * 3"), Ok(Some(Value::Int(7))));
+    assert_eq!(s.fuel(), Some(98));
+    let spin = "i = 0; while (1) i = i + 1";
+    assert_eq!(s.eval_str(spin).unwrap_err().code(), "max-ops");
+    assert_eq!(s.eval_str(spin).unwrap_err().code(), "max-ops");
+    assert_eq!(s.eval_str(spin).unwrap_err().code(), "max-ops");
+    assert_eq!(s.eval_str(spin), Err(SessionError::Busy(Busy::Fuel)));
+    assert_eq!((s.fuel(), s.eval_str("1")), (Some(0), Err(SessionError::Busy(Busy::Fuel))));
+    s.refuel(5);
+    assert_eq!(s.eval_str("-1"), Ok(Some(Value::Int(-1))));
+    assert_eq!(Session::default().fuel(), None);
+
+    // A line of the audit log for every program, failed ones included.
+    let lines = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let log = Arc::clone(&lines);
+    let s = Session::builder().audit(move |line| log.lock().unwrap().push(line.to_string())).build();
+    s.eval_str("x = 6 * 7").unwrap();
+    s.eval_str_over("x / y", &[("y".to_string(), Value::Int(0))].into()).unwrap_err();
+    s.reload("k = 1").unwrap();
+    let lines = lines.lock().unwrap();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].contains("\"inputs\": [{\"name\": \"<program>\", \"sha256\": \""), "{}", lines[0]);
+    assert!(lines[0].contains("\"vars\": {}, \"result\": \"42\", "), "{}", lines[0]);
+    assert!(lines[1].contains("\"vars\": {\"y\": \"0\"}, \"error\": {\"code\": \"division-by-zero\", "), "{}", lines[1]);
+    assert!(lines[2].contains("\"name\": \"<reload>\"") && lines[2].contains("\"result\": null"), "{}", lines[2]);
+
+    // What one session saves, another loads.
+    let s = Session::default();
+    s.eval_str("rate = 0.5; f(a) = a * rate").unwrap();
+    let other = Session::default();
+    other.load(&s.save()).unwrap();
+    assert_eq!(other.eval_str("f(3)"), Ok(Some(Value::Float(1.5))));
+    assert_eq!(other.load("x = 1").unwrap_err().code(), "not-an-environment");
+
+    // Cancelling stops a runaway program from another thread, and after a
+    // reset the session carries on with what it had.
+    let token = CancelToken::new();
+    let s = Session::builder().cancel(token.clone()).build();
+    s.eval_str("k = 7").unwrap();
+    let stop = token.clone();
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        stop.cancel();
+    });
+    assert_eq!(s.eval_str("i = 0; while (1) i = i + 1").unwrap_err().code(), "cancelled");
+    canceller.join().unwrap();
+    assert_eq!(s.eval_str("k + 1").unwrap_err().code(), "cancelled");
+    token.reset();
+    assert_eq!(s.eval_str("k * 6"), Ok(Some(Value::Int(42))));
+}
@@ -0,0 +1,127 @@
+//! `stoncc shake`: cuts a file of definitions down to those an entry
+//! expression needs, directly or through other definitions, in an order
+//! where each comes after what it uses. Only top-level assignments and
+//! function definitions count as definitions; other statements are dropped.
+
+use std::fs;
+
+use stoncc::parser::{self, Node, Program, Stmt};
+use stoncc::Builtins;
+
+use crate::deps::{definitions, needs, uses, Name};
+use crate::{fail, theme, Failure};
+
+/// What the definitions `program` needs for `entry` are, as indices of its
+/// statements in the order they should be written, and what they and
+/// `entry` use that nothing in `program` defines.
+pub fn shake(program: &Program, entry: &Node) -> Result<(Vec<usize>, Vec<String>), String> {
+    let defs = definitions(program)?;
+
+    let mut undefined = Vec::new();
+    let builtins = Builtins::default();
+    // The definitions each of `names` refers to, in source order.
+    let mut resolve = |names: Vec<Name>| {
+        let mut deps = Vec::new();
+        for name in names {
+            match defs.get(&name) {
+                Some(&i) => deps.push(i),
+                None if matches!(&name, Name::Fn(f) if builtins.arity(f).is_some()) => {}
+                None if !undefined.contains(&name.describe()) => undefined.push(name.describe()),
+                None => {}
+            }
+        }
+        deps.sort_unstable();
+        deps.dedup();
+        deps
+    };
+
+    // Depth first, on a stack of definitions each with the dependencies
+    // it has yet to write, so long chains take no stack.
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        New,
+        Open,
+        Written,
+    }
+    let mut marks = vec![Mark::New; program.stmts.len()];
+    let mut out = Vec::new();
+    let mut stack: Vec<(Option<usize>, std::vec::IntoIter<usize>)> = vec![(None, resolve(uses(entry, &[])).into_iter())];
+    while let Some((def, deps)) = stack.last_mut() {
+        let Some(dep) = deps.next() else {
+            if let Some(i) = *def {
+                marks[i] = Mark::Written;
+                out.push(i);
+            }
+            stack.pop();
+            continue;
+        };
+        match (marks[dep], &program.stmts[dep]) {
+            (Mark::New, stmt) => {
+                marks[dep] = Mark::Open;
+                stack.push((Some(dep), resolve(needs(stmt)).into_iter()));
+            }
+            // Functions are looked up when called, so they may call each
+            // other in any order.
+            (Mark::Open, Stmt::Def { .. }) | (Mark::Written, _) => {}
+            (Mark::Open, Stmt::Assign { name, .. }) => return Err(format!("'{name}' depends on itself")),
+            (Mark::Open, _) => unreachable!(),
+        }
+    }
+    Ok((out, undefined))
+}
+
+pub fn main(args: &[String]) {
+    let usage = || -> ! {
+        eprintln!("usage: stoncc shake --entry EXPR FILE");
+        std::process::exit(2);
+    };
+    let (entry, path) = match args {
+        [flag, entry, path] if flag == "--entry" => (entry, path),
+        [path, flag, entry] if flag == "--entry" => (entry, path),
+        _ => usage(),
+    };
+
+    let entry = parser::expr(entry.as_bytes()).unwrap_or_else(|e| fail(Failure::Run, format!("--entry: {e}")));
+    let s = fs::read(path).unwrap_or_else(|e| fail(Failure::Run, format!("cannot read {path}: {e}")));
+    let program = parser::program(&s).0.unwrap_or_else(|e| fail(Failure::Run, format!("{path}: {e}")));
+    let (kept, undefined) = shake(&program, &entry).unwrap_or_else(|e| fail(Failure::Run, format!("{path}: {e}")));
+    for name in undefined {
+        eprintln!("{}: nothing in {path} defines {name}", theme::label("warning"));
+    }
+    for i in kept {
+        let span = program.stmts[i].span();
+        println!("{}", String::from_utf8_lossy(&s[span.start..span.end]));
+    }
+}
+
+#[test]
+fn tests() {
+    let run = |src: &str, entry: &str| {
+        let program = parser::program(src.as_bytes()).0.unwrap();
+        shake(&program, &parser::expr(entry.as_bytes()).unwrap()).map(|(kept, undefined)| {
+            let kept: Vec<&str> = kept.iter().map(|&i| program.stmts[i].span()).map(|s| &src[s.start..s.end]).collect();
+            (kept.join("; "), undefined)
+        })
+    };
+
+    let lib = "rate = 0.05\nunused = 99\nresult = total(base) * (1 + rate)\ntotal(x) = x + fee(x)\nbase = 100\nfee(x) = x * rate + flat\nsq(x) = x*x";
+    assert_eq!(
+        run(lib, "result"),
+        Ok(("rate = 0.05; fee(x) = x * rate + flat; total(x) = x + fee(x); base = 100; result = total(base) * (1 + rate)".into(), vec!["'flat'".into()]))
+    );
+    assert_eq!(run(lib, "sq(rate) + sqrt(2)"), Ok(("rate = 0.05; sq(x) = x*x".into(), vec![])));
+    assert_eq!(run(lib, "y"), Ok((String::new(), vec!["'y'".into()])));
+    // A parameter hides the variable of the same name.
+    assert_eq!(run("x = 1\nf(x) = x + 1", "f(2)").unwrap().0, "f(x) = x + 1");
+
+    // Functions may call each other; variables may not need themselves.
+    assert_eq!(run("even(n) = n == 0 || odd(n - 1)\nodd(n) = n != 0 && even(n - 1)", "even(4)").unwrap().0, "odd(n) = n != 0 && even(n - 1); even(n) = n == 0 || odd(n - 1)");
+    assert_eq!(run("a = f(1)\nf(x) = a + x", "a"), Err("'a' depends on itself".into()));
+    assert_eq!(run("a = 1\na = 2", "a"), Err("'a' is defined more than once".into()));
+    assert_eq!(run("f = 1\nf(x) = f + x", "f(f)").unwrap().0, "f = 1; f(x) = f + x");
+
+    let n = 100_000;
+    let chain: String = (1..n).map(|i| format!("v{i} = v{} + 1\n", i - 1)).collect();
+    let (kept, _) = shake(&parser::program(format!("v0 = 0\n{chain}").as_bytes()).0.unwrap(), &parser::expr(format!("v{}", n - 1).as_bytes()).unwrap()).unwrap();
+    assert_eq!((kept.len(), kept[0], kept[n - 1]), (n, 0, n - 1));
+}
@@ -0,0 +1,215 @@
+//! Saved environments: the variables and the functions a program defined,
+//! as text to write to a file and read back later, for `stoncc --env=FILE`
+//! and [`Session::save`](crate::Session::save).
+//!
+//! ```text
+//! stoncc environment 1
+//! var "rate" = float 0.075
+//! var "n" = int 12
+//! fn monthly(p) = p * rate / n
+//! ```
+//!
+//! Each variable is a line of its own with its name as a JSON string and
+//! its value with its kind, so that every `Value` reads back as it was.
+//! Functions are their definitions as infix source. The first line gives
+//! the version of the format, and text saved by a newer version fails to
+//! load, with `LoadError::Version`, rather than loading wrong.
+
+use core::fmt;
+
+use crate::builtins::Builtins;
+use crate::eval::Env;
+use crate::json::{self, Json};
+use crate::parser::{program, Stmt, Value};
+use crate::prelude::*;
+use crate::units::Unit;
+
+/// The version of the format `save` writes, and the newest `load` reads.
+pub const VERSION: u32 = 1;
+
+const HEADER: &str = "stoncc environment ";
+
+/// Why `load` failed. Nothing is loaded then.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LoadError {
+    /// The text doesn't start with the header `save` writes.
+    NotSaved,
+    /// The text was saved in this newer version of the format.
+    Version(u32),
+    /// Line `n`, counting from 1, is not a variable or a function.
+    Line(usize, String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::NotSaved => write!(f, "not a saved environment: it should start with '{HEADER}{VERSION}'"),
+            LoadError::Version(v) => write!(f, "the environment was saved in format {v}, and this version reads format {VERSION} at most"),
+            LoadError::Line(n, why) => write!(f, "line {n} of the environment: {why}"),
+        }
+    }
+}
+
+impl core::error::Error for LoadError {}
+
+impl LoadError {
+    /// A stable name for the kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LoadError::NotSaved => "not-an-environment",
+            LoadError::Version(_) => "environment-version",
+            LoadError::Line(..) => "bad-environment",
+        }
+    }
+}
+
+/// `v` as its kind and the words that give it back.
+fn value(v: Value) -> String {
+    match v {
+        Value::Int(v) => format!("int {v}"),
+        Value::Wide(v) => format!("wide {v}"),
+        Value::Float(v) => format!("float {v:?}"),
+        Value::Duration(ms) => format!("duration {ms}"),
+        Value::Quantity(v, unit) => format!("quantity {v:?} {}", unit.0.map(|p| p.to_string()).join(" ")),
+    }
+}
+
+/// The variables in `env` and the functions programs defined in
+/// `builtins`, by name, in the format `load` reads.
+pub fn save(env: &Env, builtins: &Builtins) -> String {
+    let mut vars: Vec<_> = env.iter().collect();
+    vars.sort_by_key(|&(name, _)| name);
+    let mut out = format!("{HEADER}{VERSION}\n");
+    for (name, v) in vars {
+        out += &format!("var {} = {}\n", json::string(name), value(*v));
+    }
+    for (name, f) in builtins.definitions() {
+        let def = Stmt::Def { name: name.to_string(), params: f.params.clone(), body: f.body.clone(), span: Default::default() };
+        out += &format!("fn {}\n", def.to_infix());
+    }
+    out
+}
+
+/// The value that `value` gave as `words`.
+fn parse_value(words: &str) -> Result<Value, String> {
+    let mut words = words.split(' ');
+    let kind = words.next().unwrap_or_default();
+    let mut word = || words.next().ok_or_else(|| format!("{kind} value cut short"));
+    let bad = |e: &dyn fmt::Display| format!("bad {kind} value: {e}");
+    let v = match kind {
+        "int" => Value::Int(word()?.parse().map_err(|e| bad(&e))?),
+        "wide" => Value::Wide(word()?.parse().map_err(|e| bad(&e))?),
+        "float" => Value::Float(word()?.parse().map_err(|e| bad(&e))?),
+        "duration" => Value::Duration(word()?.parse().map_err(|e| bad(&e))?),
+        "quantity" => {
+            let v = word()?.parse().map_err(|e| bad(&e))?;
+            let mut unit = Unit::NONE;
+            for p in &mut unit.0 {
+                *p = word()?.parse().map_err(|e| bad(&e))?;
+            }
+            Value::Quantity(v, unit)
+        }
+        kind => return Err(format!("unknown kind of value '{kind}'")),
+    };
+    match words.next() {
+        Some(extra) => Err(format!("'{extra}' after the value")),
+        None => Ok(v),
+    }
+}
+
+/// A `var` line, without the `var `.
+fn parse_var(line: &str) -> Result<(String, Value), String> {
+    // The name runs to the first quote that isn't escaped.
+    let mut escaped = false;
+    let end = line.char_indices().skip(1).find(|&(_, c)| {
+        let end = c == '"' && !escaped;
+        escaped = c == '\\' && !escaped;
+        end
+    });
+    let (name, rest) = match end {
+        Some((end, _)) if line.starts_with('"') => (json::parse(&line[..=end]), &line[end + 1..]),
+        _ => return Err("expected a variable name in quotes".to_string()),
+    };
+    let Ok(Json::String(name)) = name else { return Err("bad variable name".to_string()) };
+    let words = rest.strip_prefix(" = ").ok_or("expected ' = ' after the name")?;
+    Ok((name, parse_value(words)?))
+}
+
+/// Adds the variables and functions saved in `text` to `env` and
+/// `builtins`, replacing those of the same names, or nothing if any line
+/// of it can't be read.
+pub fn load(text: &str, env: &mut Env, builtins: &mut Builtins) -> Result<(), LoadError> {
+    let mut lines = text.lines();
+    let version = lines.next().and_then(|l| l.strip_prefix(HEADER)).and_then(|v| v.parse().ok()).ok_or(LoadError::NotSaved)?;
+    if version > VERSION {
+        return Err(LoadError::Version(version));
+    }
+
+    let mut vars = Vec::new();
+    let mut fns = Vec::new();
+    for (n, line) in lines.enumerate().map(|(i, line)| (i + 2, line)) {
+        let at = |why: String| LoadError::Line(n, why);
+        if let Some(var) = line.strip_prefix("var ") {
+            vars.push(parse_var(var).map_err(at)?);
+        } else if let Some(def) = line.strip_prefix("fn ") {
+            let parsed = program(def.as_bytes()).0.map_err(|e| at(e.to_string()))?;
+            match <[Stmt; 1]>::try_from(parsed.stmts) {
+                Ok([Stmt::Def { name, params, body, .. }]) => fns.push((name, crate::Function { params, body })),
+                _ => return Err(at("expected one function definition".to_string())),
+            }
+        } else if !line.is_empty() {
+            return Err(at("expected 'var' or 'fn'".to_string()));
+        }
+    }
+    env.extend(vars);
+    for (name, f) in fns {
+        builtins.define(&name, f);
+    }
+    Ok(())
+}
+
+#[test]
+fn tests() {
+    let mut env = Env::new();
+    env.insert("x".to_string(), Value::Int(-7));
+    env.insert("a \"b\"".to_string(), Value::Float(0.1));
+    env.insert("w".to_string(), Value::Wide(1 << 100));
+    env.insert("d".to_string(), Value::Duration(5_400_000));
+    env.insert("v".to_string(), Value::Quantity(1.5, Unit([0, 1, -1, 0, 0, 0, 0])));
+    env.insert("nan".to_string(), Value::Float(f64::NAN));
+    let mut builtins = Builtins::default();
+    let def = program(b"f(a, b) = a * (b + x)").0.unwrap();
+    let Stmt::Def { name, params, body, .. } = &def.stmts[0] else { unreachable!() };
+    builtins.define(name, crate::Function { params: params.clone(), body: body.clone() });
+
+    let text = save(&env, &builtins);
+    assert!(text.starts_with("stoncc environment 1\nvar \"a \\\"b\\\"\" = float 0.1\nvar \"d\" = duration 5400000\n"), "{text}");
+    assert!(text.contains("var \"v\" = quantity 1.5 0 1 -1 0 0 0 0\n"));
+    assert!(text.ends_with("var \"x\" = int -7\nfn f(a, b) = a * (b + x)\n"), "{text}");
+
+    // Everything reads back as it was, and replaces what is there.
+    let (mut loaded, mut b) = (Env::new(), Builtins::default());
+    loaded.insert("x".to_string(), Value::Int(1));
+    load(&text, &mut loaded, &mut b).unwrap();
+    assert_eq!(loaded.len(), env.len());
+    for (name, v) in &env {
+        assert_eq!(loaded[name].to_string(), v.to_string());
+    }
+    assert_eq!(b.defined("f"), builtins.defined("f"));
+
+    // Nothing loads from text that is broken anywhere.
+    let load = |text: &str| {
+        let mut env = Env::new();
+        let result = load(text, &mut env, &mut Builtins::default());
+        assert!(result.is_ok() || env.is_empty());
+        result
+    };
+    assert_eq!(load("x = 1\n"), Err(LoadError::NotSaved));
+    assert_eq!(load("stoncc environment 2\n").map_err(|e| e.to_string()), Err("the environment was saved in format 2, and this version reads format 1 at most".into()));
+    assert_eq!(load("stoncc environment 1\nvar \"x\" = int 1\nvar \"y\" = int 1.5\n"), Err(LoadError::Line(3, "bad int value: invalid digit found in string".into())));
+    assert_eq!(load("stoncc environment 1\nvar \"x = int 1\n"), Err(LoadError::Line(2, "expected a variable name in quotes".into())));
+    assert_eq!(load("stoncc environment 1\nvar \"q\" = quantity 1 0 1\n"), Err(LoadError::Line(2, "quantity value cut short".into())));
+    assert_eq!(load("stoncc environment 1\nfn x = 1\n").unwrap_err().code(), "bad-environment");
+    assert_eq!(load("stoncc environment 1\n\nvar \"x\" = int 1 2\n"), Err(LoadError::Line(3, "'2' after the value".into())));
+}
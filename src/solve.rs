@@ -0,0 +1,416 @@
+//! `stoncc solve-sym`: solves an equation for one variable symbolically.
+//!
+//! If the variable occurs once, the operations around it are undone one at
+//! a time (`2*x + 3 = 11` becomes `2*x = 11 - 3`, then `x = (11 - 3) / 2`).
+//! Otherwise both sides are collected into a polynomial in the variable,
+//! which is solved directly if it is linear or quadratic. Solutions are
+//! printed with their integer arithmetic done where it is exact.
+
+use stoncc::limits::{EvalLimits, Meter};
+use stoncc::parser::{self, LeafVal, Node, NodeVal, Value};
+use stoncc::Env;
+
+use crate::{fail, Failure};
+
+pub const DEFAULT_MAX_STEPS: usize = 1000;
+
+/// Highest polynomial degree that has a closed-form solution here.
+const MAX_DEGREE: usize = 2;
+
+/// Coefficients of a polynomial in the variable, lowest degree first.
+/// `None` is a zero coefficient, so that it doesn't clutter the solution.
+type Poly = Vec<Option<Node>>;
+
+struct Solver<'a> {
+    var: &'a str,
+    steps: usize,
+    max_steps: usize,
+}
+
+fn int(v: i32) -> Node {
+    Node::leaf(LeafVal::Int(v))
+}
+
+fn op(v: NodeVal, children: Vec<Node>) -> Node {
+    Node::op(v, children)
+}
+
+/// `a * b`, leaving out factors of 1.
+fn times(a: Node, b: Node) -> Node {
+    match (a, b) {
+        (a, b) if a == int(1) => b,
+        (a, b) if b == int(1) => a,
+        (a, b) => op(NodeVal::Mul, vec![a, b]),
+    }
+}
+
+impl Solver<'_> {
+    /// Accounts for one rewrite, failing once there have been too many.
+    fn step(&mut self) -> Result<(), String> {
+        self.steps += 1;
+        if self.steps > self.max_steps {
+            return Err(format!("gave up after {} steps", self.max_steps));
+        }
+        Ok(())
+    }
+
+    fn occurrences(&self, node: &Node) -> usize {
+        match node {
+            Node::Leaf(LeafVal::Sym(s), _) => (s == self.var) as usize,
+            Node::Leaf(..) | Node::Error(_) => 0,
+            Node::Node { children, .. } => children.iter().map(|c| self.occurrences(c)).sum(),
+        }
+    }
+
+    /// Solves `lhs = rhs` where the variable occurs exactly once, in `lhs`,
+    /// by applying the inverse of each operation around it to `rhs`. An even
+    /// power has two roots, so there can be more than one solution.
+    fn isolate(&mut self, mut lhs: Node, rhs: Node) -> Result<Vec<Node>, String> {
+        let mut solutions = vec![rhs];
+        loop {
+            self.step()?;
+
+            let Node::Node { v, children, .. } = &mut lhs else { return Ok(solutions) };
+            let (v, mut children) = (v.clone(), std::mem::take(children));
+            let right = self.occurrences(&children[children.len() - 1]) > 0;
+            let inner = if right { children.pop() } else { Some(children.remove(0)) }.unwrap();
+            let other = children.pop();
+
+            solutions = match (v, other, right) {
+                (NodeVal::Exp, Some(b), false) => {
+                    let even = value(&b).is_some_and(|n| n != 0.0 && n % 2.0 == 0.0);
+                    let one = Node::leaf(LeafVal::Decimal("1.0".to_string()));
+                    let roots = solutions.into_iter().map(|rhs| op(NodeVal::Exp, vec![rhs, op(NodeVal::Div, vec![one.clone(), b.clone()])]));
+                    roots.flat_map(|r| if even { vec![r.clone(), op(NodeVal::Sub, vec![r])] } else { vec![r] }).collect()
+                }
+                (v, other, right) => solutions
+                    .into_iter()
+                    .map(|rhs| {
+                        Ok(match (&v, other.clone(), right) {
+                            (NodeVal::Add, None, _) => rhs,
+                            (NodeVal::Sub, None, _) => op(NodeVal::Sub, vec![rhs]),
+                            (NodeVal::Add, Some(b), false) => op(NodeVal::Sub, vec![rhs, b]),
+                            (NodeVal::Add, Some(a), true) => op(NodeVal::Sub, vec![rhs, a]),
+                            (NodeVal::Sub, Some(b), false) => op(NodeVal::Add, vec![rhs, b]),
+                            (NodeVal::Sub, Some(a), true) => op(NodeVal::Sub, vec![a, rhs]),
+                            (NodeVal::Mul, Some(b), false) => op(NodeVal::Div, vec![rhs, b]),
+                            (NodeVal::Mul, Some(a), true) => op(NodeVal::Div, vec![rhs, a]),
+                            (NodeVal::Div, Some(b), false) => op(NodeVal::Mul, vec![rhs, b]),
+                            (NodeVal::Div, Some(a), true) => op(NodeVal::Div, vec![a, rhs]),
+                            (v, _, _) => return Err(format!("cannot undo '{v}' around {}", self.var)),
+                        })
+                    })
+                    .collect::<Result<_, String>>()?,
+            };
+            lhs = inner;
+        }
+    }
+
+    fn add(&mut self, a: Option<Node>, b: Option<Node>) -> Result<Option<Node>, String> {
+        self.step()?;
+        Ok(match (a, b) {
+            (Some(a), Some(b)) => Some(op(NodeVal::Add, vec![a, b])),
+            (a, b) => a.or(b),
+        })
+    }
+
+    fn mul(&mut self, a: &Option<Node>, b: &Option<Node>) -> Result<Option<Node>, String> {
+        self.step()?;
+        Ok(match (a, b) {
+            (Some(a), Some(b)) => Some(times(a.clone(), b.clone())),
+            _ => None,
+        })
+    }
+
+    fn neg(&mut self, p: Poly) -> Result<Poly, String> {
+        p.into_iter()
+            .map(|c| {
+                self.step()?;
+                Ok(c.map(|c| op(NodeVal::Sub, vec![c])))
+            })
+            .collect()
+    }
+
+    fn sum(&mut self, a: Poly, b: Poly) -> Result<Poly, String> {
+        let n = a.len().max(b.len());
+        let (mut a, mut b) = (a.into_iter(), b.into_iter());
+        (0..n).map(|_| self.add(a.next().flatten(), b.next().flatten())).collect()
+    }
+
+    fn product(&mut self, a: &Poly, b: &Poly) -> Result<Poly, String> {
+        let mut out = vec![None; a.len() + b.len() - 1];
+        if out.len() > MAX_DEGREE + 1 {
+            return Err(format!("only equations up to degree {MAX_DEGREE} in {} are supported", self.var));
+        }
+        for (i, x) in a.iter().enumerate() {
+            for (j, y) in b.iter().enumerate() {
+                let term = self.mul(x, y)?;
+                out[i + j] = self.add(out[i + j].take(), term)?;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Collects `node` into a polynomial in the variable.
+    fn poly(&mut self, node: &Node) -> Result<Poly, String> {
+        self.step()?;
+
+        if self.occurrences(node) == 0 {
+            return Ok(vec![Some(node.clone())]);
+        }
+
+        let Node::Node { v, children, .. } = node else {
+            return Ok(vec![None, Some(int(1))]);
+        };
+
+        match (v, &children[..]) {
+            (NodeVal::Add, [a]) => self.poly(a),
+            (NodeVal::Sub, [a]) => {
+                let a = self.poly(a)?;
+                self.neg(a)
+            }
+            (NodeVal::Add, [a, b]) => {
+                let (a, b) = (self.poly(a)?, self.poly(b)?);
+                self.sum(a, b)
+            }
+            (NodeVal::Sub, [a, b]) => {
+                let (a, b) = (self.poly(a)?, self.poly(b)?);
+                let b = self.neg(b)?;
+                self.sum(a, b)
+            }
+            (NodeVal::Mul, [a, b]) => {
+                let (a, b) = (self.poly(a)?, self.poly(b)?);
+                self.product(&a, &b)
+            }
+            (NodeVal::Div, [a, b]) if self.occurrences(b) == 0 => {
+                let a = self.poly(a)?;
+                Ok(a.into_iter().map(|c| c.map(|c| op(NodeVal::Div, vec![c, b.clone()]))).collect())
+            }
+            (NodeVal::Exp, [a, Node::Leaf(LeafVal::Int(n), _)]) if (0..=MAX_DEGREE as i32).contains(n) => {
+                let a = self.poly(a)?;
+                let mut acc = vec![Some(int(1))];
+                for _ in 0..*n {
+                    acc = self.product(&acc, &a)?;
+                }
+                Ok(acc)
+            }
+            (v, _) => Err(format!("cannot collect '{v}' into a polynomial in {}", self.var)),
+        }
+    }
+
+    /// Solves `p = 0`.
+    fn roots(&mut self, mut p: Poly) -> Result<Vec<Node>, String> {
+        // Terms can cancel out without their coefficient becoming `None`,
+        // as in `x - x`.
+        while p.last().is_some_and(|c| c.as_ref().is_none_or(|c| value(c) == Some(0.0))) {
+            p.pop();
+        }
+        let zero = || int(0);
+
+        match &p[..] {
+            [] | [_] => Err(format!("{} cancels out of the equation", self.var)),
+            // b*x + c = 0
+            [c, Some(b)] => {
+                let c = c.clone().unwrap_or_else(zero);
+                Ok(vec![op(NodeVal::Div, vec![op(NodeVal::Sub, vec![c]), b.clone()])])
+            }
+            // a*x^2 + b*x + c = 0
+            [c, b, Some(a)] => {
+                let (c, b) = (c.clone().unwrap_or_else(zero), b.clone().unwrap_or_else(zero));
+                let disc = op(NodeVal::Sub, vec![
+                    op(NodeVal::Exp, vec![b.clone(), int(2)]),
+                    times(times(int(4), a.clone()), c),
+                ]);
+                let root = op(NodeVal::Exp, vec![disc, Node::leaf(LeafVal::Decimal("0.5".to_string()))]);
+                let denom = times(int(2), a.clone());
+
+                Ok([NodeVal::Add, NodeVal::Sub]
+                    .into_iter()
+                    .map(|v| {
+                        let num = op(v, vec![op(NodeVal::Sub, vec![b.clone()]), root.clone()]);
+                        op(NodeVal::Div, vec![num, denom.clone()])
+                    })
+                    .collect())
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Solves `lhs = rhs` for `var` within `max_steps` rewrites, returning
+/// one expression per solution.
+pub fn solve(lhs: Node, rhs: Node, var: &str, max_steps: usize) -> Result<Vec<Node>, String> {
+    let mut s = Solver { var, steps: 0, max_steps };
+
+    let solutions = match (s.occurrences(&lhs), s.occurrences(&rhs)) {
+        (0, 0) => return Err(format!("{var} does not occur in the equation")),
+        (1, 0) => s.isolate(lhs, rhs)?,
+        (0, 1) => s.isolate(rhs, lhs)?,
+        _ => {
+            let p = s.poly(&op(NodeVal::Sub, vec![lhs, rhs]))?;
+            s.roots(p)?
+        }
+    };
+
+    // A square root of a negative number, or a division by zero, is no
+    // solution, and a root of both signs of 0 is one.
+    let mut solutions: Vec<Node> = solutions.iter().map(tidy).filter(|n| value(n).is_none_or(f64::is_finite)).collect();
+    solutions.dedup_by(|a, b| value(a).is_some() && value(a) == value(b));
+    if solutions.is_empty() {
+        return Err(format!("no real value of {var} solves the equation"));
+    }
+    Ok(solutions)
+}
+
+/// `node` with its integer arithmetic done where the result is exact, and
+/// without additions of 0, factors of 1 or double negations.
+fn tidy(node: &Node) -> Node {
+    let Node::Node { v, children, .. } = node else { return node.clone() };
+    let children: Vec<Node> = children.iter().map(tidy).collect();
+    let int_of = |n: &Node| match n {
+        Node::Leaf(LeafVal::Int(v), _) => Some(*v),
+        _ => None,
+    };
+    // What `n` is the negation of, if it is one.
+    let negated = |n: &Node| match n {
+        Node::Node { v: NodeVal::Sub, children, .. } if children.len() == 1 => Some(children[0].clone()),
+        Node::Leaf(LeafVal::Int(v), _) if *v < 0 => v.checked_neg().map(int),
+        _ => None,
+    };
+    let ints: Vec<Option<i32>> = children.iter().map(int_of).collect();
+
+    let folded = match (v, &ints[..]) {
+        (NodeVal::Sub, [Some(a)]) => a.checked_neg(),
+        (NodeVal::Add, [Some(a), Some(b)]) => a.checked_add(*b),
+        (NodeVal::Sub, [Some(a), Some(b)]) => a.checked_sub(*b),
+        (NodeVal::Mul, [Some(a), Some(b)]) => a.checked_mul(*b),
+        // `7 / 2` stays as it is, rather than becoming 3.
+        (NodeVal::Div, [Some(a), Some(b)]) if *b != 0 && a % b == 0 => a.checked_div(*b),
+        (NodeVal::Exp, [Some(a), Some(b)]) => u32::try_from(*b).ok().and_then(|b| a.checked_pow(b)),
+        _ => None,
+    };
+    if let Some(n) = folded {
+        return int(n);
+    }
+
+    match (v, &children[..], &ints[..]) {
+        (NodeVal::Sub, [x], _) if negated(x).is_some() => negated(x).unwrap(),
+        (NodeVal::Add, [x, _], [_, Some(0)])
+        | (NodeVal::Add, [_, x], [Some(0), _])
+        | (NodeVal::Sub, [x, _], [_, Some(0)])
+        | (NodeVal::Mul, [x, _], [_, Some(1)])
+        | (NodeVal::Mul, [_, x], [Some(1), _])
+        | (NodeVal::Div, [x, _], [_, Some(1)])
+        | (NodeVal::Exp, [x, _], [_, Some(1)]) => x.clone(),
+        (NodeVal::Mul, _, [Some(0), _] | [_, Some(0)]) => int(0),
+        // Negations move out of products and into subtractions, where they
+        // can cancel.
+        (NodeVal::Sub, [_, x], [Some(0), _]) => tidy(&op(NodeVal::Sub, vec![x.clone()])),
+        (NodeVal::Mul, [x, y], _) if negated(y).is_some() => tidy(&op(NodeVal::Sub, vec![op(NodeVal::Mul, vec![x.clone(), negated(y).unwrap()])])),
+        (NodeVal::Mul, [x, y], _) if negated(x).is_some() => tidy(&op(NodeVal::Sub, vec![op(NodeVal::Mul, vec![negated(x).unwrap(), y.clone()])])),
+        (NodeVal::Add, [x, y], _) if negated(y).is_some() => op(NodeVal::Sub, vec![x.clone(), negated(y).unwrap()]),
+        (NodeVal::Sub, [x, y], _) if negated(y).is_some() => op(NodeVal::Add, vec![x.clone(), negated(y).unwrap()]),
+        _ => op(v.clone(), children),
+    }
+}
+
+/// The value of a solution without free variables, computed in floats so
+/// that `7 / 2` is `3.5`.
+fn value(node: &Node) -> Option<f64> {
+    fn promote(node: &Node) -> Node {
+        match node {
+            Node::Leaf(LeafVal::Int(v), _) => Node::leaf(LeafVal::Decimal(format!("{v}.0"))),
+            Node::Leaf(v, _) => Node::leaf(v.clone()),
+            Node::Error(span) => Node::Error(*span),
+            Node::Node { v, children, .. } => Node::op(v.clone(), children.iter().map(promote).collect()),
+        }
+    }
+
+    match stoncc::eval(&promote(node), &Env::new(), &mut Meter::new(EvalLimits::default())) {
+        Ok(Value::Float(v)) => Some(v),
+        _ => None,
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: stoncc solve-sym EQUATION --for VAR [--max-steps N]");
+    std::process::exit(2);
+}
+
+/// `stoncc solve-sym EQUATION --for VAR`: prints each solution as
+/// `VAR = expression`, followed by its value when it has no other variables.
+pub fn main(args: &[String]) {
+    let (mut equation, mut var, mut max_steps) = (None, None, DEFAULT_MAX_STEPS);
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--for" => var = Some(value()),
+            "--max-steps" => max_steps = value().parse().unwrap_or_else(|_| usage()),
+            _ if equation.is_none() => equation = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let (Some(equation), Some(var)) = (equation, var) else { usage() };
+    let Some((lhs, rhs)) = equation.split_once('=') else {
+        fail(Failure::Usage, format!("{equation:?} is not an equation"));
+    };
+    let parse = |s: &str| parser::expr(s.as_bytes()).unwrap_or_else(|e| fail(Failure::Run, format!("{s:?}: {e}")));
+
+    let solutions = solve(parse(lhs), parse(rhs), var, max_steps).unwrap_or_else(|e| fail(Failure::Run, e));
+    for node in solutions {
+        match value(&node) {
+            Some(v) => println!("{var} = {} = {v}", node.to_infix()),
+            None => println!("{var} = {}", node.to_infix()),
+        }
+    }
+}
+
+#[test]
+fn tests() {
+    let run = |s: &str| {
+        let (lhs, rhs) = s.split_once('=').unwrap();
+        let p = |s: &str| parser::expr(s.as_bytes()).unwrap();
+        solve(p(lhs), p(rhs), "x", 100).map(|v| {
+            v.iter().map(|n| match value(n) {
+                Some(v) => v.to_string(),
+                None => n.to_infix(),
+            }).collect::<Vec<_>>().join(", ")
+        })
+    };
+
+    assert_eq!(run("2*x + 3 = 11"), Ok("4".into()));
+    assert_eq!(run("11 = 3 + 2*x"), Ok("4".into()));
+    assert_eq!(run("10 - x / 4 = 8"), Ok("8".into()));
+    assert_eq!(run("x ^ 3 = 8"), Ok("2".into()));
+    assert_eq!(run("a * x = b"), Ok("b / a".into()));
+    assert_eq!(run("2*x + 3*x = x + 6"), Ok("1.5".into()));
+    assert_eq!(run("x^2 - 3*x = -2"), Ok("2, 1".into()));
+    assert_eq!(run("(x + 1) * (x - 1) = 0"), Ok("1, -1".into()));
+
+    // Both roots of an even power, and none of a negative number.
+    assert_eq!(run("x^2 = 4"), Ok("2, -2".into()));
+    assert_eq!(run("2 * x^2 = a"), Ok("(a / 2) ^ (1.0 / 2), -(a / 2) ^ (1.0 / 2)".into()));
+    assert_eq!(run("x^2 = 0"), Ok("0".into()));
+    assert_eq!(run("x^2 + x + 1 = 0"), Err("no real value of x solves the equation".into()));
+    assert_eq!(run("0*x = 1"), Err("no real value of x solves the equation".into()));
+
+    // Solutions are printed without double negations or additions of 0.
+    let printed = |s: &str| {
+        let (lhs, rhs) = s.split_once('=').unwrap();
+        let p = |s: &str| parser::expr(s.as_bytes()).unwrap();
+        solve(p(lhs), p(rhs), "x", 100).unwrap().iter().map(Node::to_infix).collect::<Vec<_>>().join(", ")
+    };
+    assert_eq!(printed("-x = -a"), "a");
+    assert_eq!(printed("5 - x = 10"), "-5");
+    assert_eq!(printed("2 * x = 7"), "7 / 2");
+    assert_eq!(printed("x^2 - 3*x = -2"), "(3 + 1 ^ 0.5) / 2, (3 - 1 ^ 0.5) / 2");
+    assert_eq!(printed("x*x = b"), "(4 * b) ^ 0.5 / 2, -(4 * b) ^ 0.5 / 2");
+
+    assert!(run("x! = 6").is_err());
+    assert!(run("x - x = 1").is_err());
+    assert!(run("x^3 + x = 2").is_err());
+    assert!(run("y = 2").is_err());
+}
@@ -0,0 +1,80 @@
+//! The standard library: combinatorics, unit conversions and statistics
+//! written in stoncc itself, in `stdlib.stc`, which is built into the
+//! binary. A run gets the functions its programs call and nothing defines
+//! for them, and the library is only parsed when there are some.
+
+use stoncc::parser::{self, Program, Stmt};
+use stoncc::{Builtins, Function};
+
+use crate::deps::{defines, needs, uses, Name};
+
+const SOURCE: &[u8] = include_bytes!("stdlib.stc");
+
+/// Every function of the library, in the order it defines them.
+fn library() -> Vec<(String, Function)> {
+    let program = parser::program(SOURCE).0.unwrap_or_else(|e| panic!("the standard library doesn't parse: {e}"));
+    program
+        .stmts
+        .into_iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::Def { name, params, body, .. } => Some((name, Function { params, body })),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The functions of the library that `programs` or the functions defined
+/// in `builtins` call, directly or through each other, that neither the
+/// programs nor `builtins` define.
+pub fn needed(programs: &[Program], builtins: &Builtins) -> Vec<(String, Function)> {
+    let stmts = || programs.iter().flat_map(|p| &p.stmts);
+    let own: Vec<Name> = stmts().filter_map(defines).collect();
+    let missing = |names: Vec<Name>| {
+        names.into_iter().filter_map(|name| match name {
+            Name::Fn(f) if !builtins.contains(&f) && !own.contains(&Name::Fn(f.clone())) => Some(f),
+            _ => None,
+        })
+    };
+    let saved = builtins.definitions().into_iter().flat_map(|(_, f)| missing(uses(&f.body, &f.params)));
+    let mut wanted: Vec<String> = stmts().flat_map(|s| missing(needs(s))).chain(saved).collect();
+    if wanted.is_empty() {
+        return Vec::new();
+    }
+
+    let mut library = library();
+    let mut out = Vec::new();
+    while let Some(name) = wanted.pop() {
+        // Calls of functions nowhere at all are left for the checks.
+        let Some(i) = library.iter().position(|(n, _)| *n == name) else { continue };
+        let (name, f) = library.swap_remove(i);
+        wanted.extend(missing(uses(&f.body, &f.params)));
+        out.push((name, f));
+    }
+    out.sort_by(|(a, _), (b, _)| a.cmp(b));
+    out
+}
+
+#[test]
+fn tests() {
+    let program = |s: &str| parser::program(s.as_bytes()).0.unwrap();
+    let names = |programs: &[Program]| needed(programs, &Builtins::default()).into_iter().map(|(n, _)| n).collect::<Vec<_>>();
+    assert_eq!(names(&[program("catalan(4) + lcm(2, 3)")]), ["catalan", "choose", "lcm"]);
+    assert_eq!(names(&[program("stddev(1, 2)"), program("mean(1, 2)")]), ["mean", "stddev", "variance"]);
+    // The programs' own functions, and those nothing defines, are theirs.
+    assert_eq!(names(&[program("mean(a, b) = a\ncatalan(mean(1, 2)) + nosuch(1)")]), ["catalan", "choose"]);
+    assert!(names(&[program("sqrt(2) + x")]).is_empty());
+    let mut saved = Builtins::default();
+    saved.define("f", Function { params: vec!["x".into()], body: parser::expr(b"clamp(x, 0, 1)").unwrap() });
+    assert_eq!(needed(&[program("f(2)")], &saved).into_iter().map(|(n, _)| n).collect::<Vec<_>>(), ["clamp"]);
+
+    // The library itself calls only what it or the builtins define, and
+    // every function in it is found when called.
+    let builtins = Builtins::default();
+    let library = library();
+    for (name, f) in &library {
+        for used in uses(&f.body, &f.params) {
+            assert!(matches!(&used, Name::Fn(g) if builtins.contains(g) || library.iter().any(|(n, _)| n == g)), "{name} uses {used:?}");
+        }
+        assert!(names(&[program(&format!("{name}(1)"))]).contains(name));
+    }
+}
@@ -0,0 +1,115 @@
+//! `--strict-tokens`: rejects token sequences that are legal to lex but
+//! are more likely a slip than intended, as when generated or pasted input
+//! has lost or gained a space. Two kinds are caught:
+//!
+//! - an operator split by spaces, like `! =` for `!=`, and
+//! - two operands side by side on one line, like `2 2` or `x y`, where an
+//!   operator or a line break went missing.
+
+use stoncc::lexer::{Lexed, Lexer, Span, Token};
+use stoncc::parser::Extensions;
+
+/// A suspicious pair of tokens, with the code `--expect-error` knows it by.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+/// The single built-in operator `a` and `b` make when written together.
+fn joined(a: &Lexed, b: &Lexed) -> Option<String> {
+    let text = format!("{}{}", a.text, b.text);
+    match Lexer::from_bytes(text.as_bytes()).tokens().ok()?.as_slice() {
+        [t] if t.token.kind() == "punct" && !matches!(t.token, Token::Op(_)) => Some(text),
+        _ => None,
+    }
+}
+
+fn ends_operand(t: &Token) -> bool {
+    matches!(t, Token::Int(_) | Token::Bool(_) | Token::Decimal(_) | Token::Sym(_) | Token::Placeholder(_) | Token::Literal(..) | Token::RParen)
+}
+
+fn starts_operand(t: &Token) -> bool {
+    matches!(t, Token::Int(_) | Token::Bool(_) | Token::Decimal(_) | Token::Sym(_) | Token::Placeholder(_) | Token::Literal(..) | Token::LParen)
+}
+
+/// Every suspicious pair in `tokens`, in order. `ext` says which words and
+/// units are operators or literals rather than operands, and whether
+/// operands side by side are a product.
+pub fn check(tokens: &[Lexed], ext: Extensions) -> Vec<Problem> {
+    let mut out = Vec::new();
+    // Parentheses still open, each marked if it holds the condition of an
+    // `if` or `while`, which a statement follows directly.
+    let mut parens = Vec::new();
+
+    for (i, pair) in tokens.windows(2).enumerate() {
+        let (a, b) = (&pair[0], &pair[1]);
+        let span = Span { start: a.span.start, end: b.span.end };
+        let closes_condition = match a.token {
+            Token::LParen => {
+                parens.push(i > 0 && matches!(tokens[i - 1].token, Token::If | Token::While));
+                false
+            }
+            Token::RParen => parens.pop().unwrap_or(false),
+            _ => false,
+        };
+        if b.newline {
+            continue;
+        }
+
+        if a.span.end < b.span.start {
+            if let Some(op) = joined(a, b) {
+                out.push(Problem {
+                    code: "split-operator",
+                    message: format!("'{}' and '{}' are separate tokens; did you mean '{op}'?", a.text, b.text),
+                    span,
+                });
+                continue;
+            }
+        }
+
+        let word = |t: &Token| matches!(t, Token::Sym(w) if ext.calculator && (w == "of" || w == "per"));
+        let unit = matches!(a.token, Token::Int(_) | Token::Decimal(_))
+            && matches!(b.token, Token::Sym(_))
+            && (ext.durations || ext.bytes || ext.units);
+        let call = matches!(a.token, Token::Sym(_)) && b.token == Token::LParen;
+        if ends_operand(&a.token) && starts_operand(&b.token) && !ext.implicit_mul && !closes_condition && !word(&a.token) && !word(&b.token) && !unit && !call {
+            out.push(Problem {
+                code: "adjacent-operands",
+                message: format!("'{}' follows '{}' with no operator between them", b.text, a.text),
+                span,
+            });
+        }
+    }
+
+    out
+}
+
+#[test]
+fn tests() {
+    let found = |s: &str, ext| -> Vec<String> {
+        let tokens = Lexer::from_bytes(s.as_bytes()).tokens().unwrap();
+        check(&tokens, ext).into_iter().map(|p| format!("{}: {}", p.code, &s[p.span.start..p.span.end])).collect()
+    };
+    let plain = Extensions::default();
+
+    assert_eq!(
+        found("x = 1 ! = 2\ny = x < = 3 & & x > = 1", plain),
+        ["split-operator: ! =", "split-operator: < =", "split-operator: & &", "split-operator: > ="]
+    );
+    assert_eq!(
+        found("2 2 + x y\n(1) (2) ?1 3", plain),
+        ["adjacent-operands: 2 2", "adjacent-operands: x y", "adjacent-operands: ) (", "adjacent-operands: ) ?1", "adjacent-operands: ?1 3"]
+    );
+    let problems = check(&Lexer::from_bytes(b"a < = b").tokens().unwrap(), plain);
+    assert_eq!(problems[0].message, "'<' and '=' are separate tokens; did you mean '<='?");
+
+    // Ordinary programs are left alone.
+    let fine = "f(x) = x! == 6 ? -x : x <= 1\nif (x) y = 1 else { y = -2 }\nwhile (f (y) < 3) y = y + +1\nz = 1\n2";
+    assert!(found(fine, plain).is_empty());
+    let calc = Extensions { calculator: true, durations: true, ..plain };
+    assert!(found("20% of 50 + 3 min + 1 per 2", calc).is_empty());
+    assert_eq!(found("3 min", plain), ["adjacent-operands: 3 min"]);
+    assert!(found("2 x (y)", Extensions { implicit_mul: true, ..plain }).is_empty());
+}
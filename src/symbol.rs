@@ -0,0 +1,215 @@
+//! Interned symbol names. The lexer turns each name into a [`SymId`], a
+//! number standing for it, so that a variable used thousands of times is
+//! stored once rather than once per use. Ids are shared by every lexer,
+//! parser and tree in the process, and compare and hash as numbers.
+//!
+//! ```
+//! use stoncc::symbol::SymId;
+//!
+//! let x = SymId::intern("x");
+//! assert_eq!(x, SymId::intern("x"));
+//! assert_eq!(x.as_str(), "x");
+//! ```
+//!
+//! Names are kept for as long as the process runs, so a process that reads
+//! unboundedly many different names grows with them.
+
+use core::cmp::Ordering;
+use core::fmt;
+use core::ops::Deref;
+
+use crate::prelude::*;
+
+/// Every name interned so far, and the id of each.
+#[derive(Default)]
+struct Interner {
+    names: Vec<&'static str>,
+    ids: Map<&'static str, u32>,
+}
+
+#[cfg(feature = "std")]
+mod lock {
+    use std::sync::{OnceLock, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+    use super::Interner;
+
+    fn interner() -> &'static RwLock<Interner> {
+        static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+        INTERNER.get_or_init(Default::default)
+    }
+
+    pub(super) fn read() -> RwLockReadGuard<'static, Interner> {
+        interner().read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(super) fn write() -> RwLockWriteGuard<'static, Interner> {
+        interner().write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Without `std` there is no `RwLock`, and the interner spins on a flag
+/// instead: it is held only as long as a lookup takes.
+#[cfg(not(feature = "std"))]
+mod lock {
+    use core::cell::UnsafeCell;
+    use core::ops::{Deref, DerefMut};
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use super::{Interner, Map, Vec};
+
+    struct Spin {
+        held: AtomicBool,
+        interner: UnsafeCell<Interner>,
+    }
+
+    // Only the holder of `held` touches the interner.
+    unsafe impl Sync for Spin {}
+
+    static INTERNER: Spin = Spin {
+        held: AtomicBool::new(false),
+        interner: UnsafeCell::new(Interner { names: Vec::new(), ids: Map::new() }),
+    };
+
+    pub(super) struct Guard(());
+
+    impl Deref for Guard {
+        type Target = Interner;
+
+        fn deref(&self) -> &Interner {
+            // SAFETY: the guard holds the flag.
+            unsafe { &*INTERNER.interner.get() }
+        }
+    }
+
+    impl DerefMut for Guard {
+        fn deref_mut(&mut self) -> &mut Interner {
+            // SAFETY: the guard holds the flag, and so is the only one.
+            unsafe { &mut *INTERNER.interner.get() }
+        }
+    }
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            INTERNER.held.store(false, Ordering::Release);
+        }
+    }
+
+    pub(super) fn write() -> Guard {
+        while INTERNER.held.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            core::hint::spin_loop();
+        }
+        Guard(())
+    }
+
+    pub(super) use write as read;
+}
+
+/// A symbol name, interned. It dereferences to the name, and orders and
+/// prints as the name does, whatever order names were interned in.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SymId(u32);
+
+impl SymId {
+    /// The id of `name`, the same every time within a process.
+    pub fn intern(name: &str) -> SymId {
+        if let Some(&id) = lock::read().ids.get(name) {
+            return SymId(id);
+        }
+        let mut interner = lock::write();
+        // Another thread may have interned it since.
+        if let Some(&id) = interner.ids.get(name) {
+            return SymId(id);
+        }
+        let id = u32::try_from(interner.names.len()).expect("too many distinct symbols");
+        let name: &'static str = Box::leak(name.into());
+        interner.names.push(name);
+        interner.ids.insert(name, id);
+        SymId(id)
+    }
+
+    /// The name the id stands for.
+    pub fn as_str(self) -> &'static str {
+        lock::read().names[self.0 as usize]
+    }
+
+    /// The number standing for the name, for tables indexed by symbol.
+    pub fn index(self) -> u32 {
+        self.0
+    }
+}
+
+impl Deref for SymId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for SymId {
+    fn from(name: &str) -> Self {
+        SymId::intern(name)
+    }
+}
+
+impl From<String> for SymId {
+    fn from(name: String) -> Self {
+        SymId::intern(&name)
+    }
+}
+
+impl PartialEq<str> for SymId {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for SymId {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialOrd for SymId {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SymId {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.0 == other.0 {
+            true => Ordering::Equal,
+            false => self.as_str().cmp(other.as_str()),
+        }
+    }
+}
+
+impl fmt::Display for SymId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for SymId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[test]
+fn tests() {
+    let (a, b) = (SymId::intern("sym_test_b"), SymId::intern("sym_test_a"));
+    assert_eq!(a, SymId::from("sym_test_b".to_string()));
+    assert_ne!(a, b);
+    assert_eq!((&*a, b.to_string(), format!("{a:?}")), ("sym_test_b", "sym_test_a".to_string(), "\"sym_test_b\"".to_string()));
+    // By name, not by when each was interned.
+    assert!(b < a && a == "sym_test_b");
+
+    let ids: Vec<Vec<SymId>> = std::thread::scope(|s| {
+        let threads: Vec<_> = (0..4).map(|_| s.spawn(|| (0..100).map(|i| SymId::intern(&format!("sym_test_{i}"))).collect())).collect();
+        threads.into_iter().map(|t| t.join().unwrap()).collect()
+    });
+    assert!(ids.windows(2).all(|w| w[0] == w[1]));
+    assert_eq!(ids[0][42].as_str(), "sym_test_42");
+}
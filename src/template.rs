@@ -0,0 +1,73 @@
+use stoncc::parser::{self, LeafVal, Node};
+use stoncc::{Folder, Span};
+
+use crate::{fail, Failure};
+
+struct Fill<'a>(&'a [Node]);
+
+impl Folder for Fill<'_> {
+    type Error = String;
+
+    fn fold_leaf(&mut self, v: &LeafVal, span: Span) -> Result<Node, String> {
+        match v {
+            LeafVal::Placeholder(n) => match n.checked_sub(1).and_then(|i| self.0.get(i)) {
+                Some(arg) => Ok(arg.clone()),
+                None => Err(format!("placeholder ?{n} has no argument ({} given)", self.0.len())),
+            },
+            v => Ok(Node::Leaf(v.clone(), span)),
+        }
+    }
+}
+
+/// Replaces every `?N` in `template` with a copy of `args[N - 1]`. The
+/// arguments are trees, not text, so they keep their grouping no matter
+/// which operator they end up under.
+pub fn fill(template: &Node, args: &[Node]) -> Result<Node, String> {
+    template.fold(&mut Fill(args))
+}
+
+/// `stoncc template TEMPLATE [--sexp] --args ARG...`: prints the template
+/// with each `?N` replaced by the N-th parsed argument.
+pub fn main(args: &[String]) {
+    let usage = || -> ! {
+        eprintln!("usage: stoncc template TEMPLATE [--sexp] --args ARG...");
+        std::process::exit(2);
+    };
+
+    let mut sexp = false;
+    let mut template = None;
+    let mut rest = args.iter();
+    for arg in rest.by_ref() {
+        match arg.as_str() {
+            "--sexp" => sexp = true,
+            "--args" => break,
+            _ if template.is_none() => template = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let Some(template) = template else { usage() };
+    let parse = |s: &String| parser::expr(s.as_bytes()).unwrap_or_else(|e| fail(Failure::Run, format!("{s:?}: {e}")));
+    let template = parse(template);
+    let args: Vec<Node> = rest.map(parse).collect();
+
+    let node = fill(&template, &args).unwrap_or_else(|e| fail(Failure::Run, e));
+    if sexp {
+        println!("{node}");
+    } else {
+        println!("{}", node.to_infix());
+    }
+}
+
+#[test]
+fn tests() {
+    let run = |t: &str, args: &[&str]| {
+        let args: Vec<Node> = args.iter().map(|a| parser::expr(a.as_bytes()).unwrap()).collect();
+        fill(&parser::expr(t.as_bytes()).unwrap(), &args).map(|n| n.to_infix())
+    };
+
+    assert_eq!(run("a * ?1 + ?2", &["3", "x + 1"]), Ok("a * 3 + (x + 1)".to_string()));
+    assert_eq!(run("?1 * ?1", &["x + 1"]), Ok("(x + 1) * (x + 1)".to_string()));
+    assert_eq!(run("?1 ^ 2", &["-x"]), Ok("(-x) ^ 2".to_string()));
+    assert!(run("?2", &["1"]).is_err());
+}
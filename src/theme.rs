@@ -0,0 +1,136 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::{fail, Failure};
+
+/// How terminal output is colored. Everything that styles what it prints
+/// goes through `paint`, so that one flag changes all of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    /// No escape codes at all.
+    Plain,
+    /// Red errors, yellow warnings and blue locations, as rustc has them.
+    Default,
+    /// Bright colors, bold and underlined, that keep apart without telling
+    /// red from green.
+    HighContrast,
+    /// Bold, underline and reverse video, without colors.
+    Monochrome,
+}
+
+/// What a piece of output is, for the theme to style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Error,
+    Warning,
+    Note,
+    /// The `-->` and `|` of a diagnostic.
+    Location,
+}
+
+impl Theme {
+    pub const NAMES: [&str; 4] = ["plain", "default", "high-contrast", "monochrome"];
+    const ALL: [Theme; 4] = [Theme::Plain, Theme::Default, Theme::HighContrast, Theme::Monochrome];
+
+    pub fn from_name(name: &str) -> Option<Theme> {
+        Self::NAMES.iter().position(|&n| n == name).map(|i| Self::ALL[i])
+    }
+
+    /// The SGR parameters of `role`, or `None` to leave it unstyled.
+    fn sgr(self, role: Role) -> Option<&'static str> {
+        match (self, role) {
+            (Theme::Plain, _) => None,
+            (Theme::Default, Role::Error) => Some("1;31"),
+            (Theme::Default, Role::Warning) => Some("1;33"),
+            (Theme::Default, Role::Note) => Some("1;32"),
+            (Theme::Default, Role::Location) => Some("1;34"),
+            (Theme::HighContrast, Role::Error) => Some("1;4;93"),
+            (Theme::HighContrast, Role::Warning) => Some("1;96"),
+            (Theme::HighContrast, Role::Note) => Some("1;97"),
+            (Theme::HighContrast, Role::Location) => Some("97"),
+            (Theme::Monochrome, Role::Error) => Some("1;7"),
+            (Theme::Monochrome, Role::Warning) => Some("1;4"),
+            (Theme::Monochrome, Role::Note | Role::Location) => Some("1"),
+        }
+    }
+
+    /// `text` styled as `role`.
+    pub fn paint(self, role: Role, text: &str) -> String {
+        match self.sgr(role) {
+            Some(sgr) if !text.is_empty() => format!("\x1b[{sgr}m{text}\x1b[0m"),
+            _ => text.to_string(),
+        }
+    }
+
+    /// The theme to use when `--theme` doesn't say: none when `NO_COLOR` is
+    /// set or standard error isn't a terminal, and without colors when
+    /// `TERM` is `dumb`.
+    fn detect() -> Theme {
+        let set = |var| std::env::var_os(var).is_some_and(|v| !v.is_empty());
+        if set("NO_COLOR") || !std::io::stderr().is_terminal() {
+            Theme::Plain
+        } else if std::env::var_os("TERM").is_some_and(|t| t == "dumb") {
+            Theme::Monochrome
+        } else {
+            Theme::Default
+        }
+    }
+}
+
+/// The theme in use, as its index in `Theme::ALL`. Plain until `init`.
+static CURRENT: AtomicU8 = AtomicU8::new(0);
+
+/// Picks the theme from a `--theme=NAME` in `args`, which it takes out so
+/// that every subcommand accepts it, or from the environment.
+pub fn init(args: &mut Vec<String>) {
+    let theme = match args.iter().position(|a| a.starts_with("--theme=")) {
+        Some(i) => {
+            let name = &args.remove(i)["--theme=".len()..];
+            Theme::from_name(name)
+                .unwrap_or_else(|| fail(Failure::Usage, format!("unknown theme '{name}', expected one of {}", Theme::NAMES.join(", "))))
+        }
+        None => Theme::detect(),
+    };
+    let i = Theme::ALL.iter().position(|&t| t == theme).unwrap();
+    CURRENT.store(i as u8, Ordering::Relaxed);
+}
+
+/// `text` styled as `role` in the theme in use.
+pub fn paint(role: Role, text: &str) -> String {
+    Theme::ALL[CURRENT.load(Ordering::Relaxed) as usize].paint(role, text)
+}
+
+/// `severity`, like `error` or `warning`, styled as the start of a
+/// diagnostic of its kind.
+pub fn label(severity: &str) -> String {
+    paint(self::severity(severity), severity)
+}
+
+/// The role of a diagnostic of `severity`, like `error` or `warning`.
+pub fn severity(severity: &str) -> Role {
+    match severity {
+        "error" => Role::Error,
+        "warning" => Role::Warning,
+        _ => Role::Note,
+    }
+}
+
+#[test]
+fn tests() {
+    assert_eq!(Theme::from_name("high-contrast"), Some(Theme::HighContrast));
+    assert_eq!(Theme::from_name("rainbow"), None);
+    for (name, theme) in Theme::NAMES.into_iter().zip(Theme::ALL) {
+        assert_eq!(Theme::from_name(name), Some(theme));
+    }
+
+    assert_eq!(Theme::Plain.paint(Role::Error, "error"), "error");
+    assert_eq!(Theme::Default.paint(Role::Error, "error"), "\x1b[1;31merror\x1b[0m");
+    assert_eq!(Theme::Monochrome.paint(Role::Warning, "warning"), "\x1b[1;4mwarning\x1b[0m");
+    assert_eq!(Theme::HighContrast.paint(Role::Location, ""), "");
+
+    // The monochrome theme uses no colors, only attributes below 30.
+    for role in [Role::Error, Role::Warning, Role::Note, Role::Location] {
+        let sgr = Theme::Monochrome.sgr(role).unwrap();
+        assert!(sgr.split(';').all(|p| p.parse::<u8>().unwrap() < 30), "{sgr}");
+    }
+}
@@ -0,0 +1,66 @@
+//! `--timeout`: a watchdog thread that ends the process once the deadline
+//! passes, saying which phase was running and how far it had got.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+type Count = Box<dyn Fn() -> u64 + Send>;
+
+/// What the main thread is doing, and how to count the work it has done
+/// so far, with the unit of the count.
+struct Phase {
+    name: &'static str,
+    progress: Option<(&'static str, Count)>,
+}
+
+static PHASE: Mutex<Phase> = Mutex::new(Phase { name: "starting", progress: None });
+
+/// Records that `name` is running.
+pub fn phase(name: &'static str) {
+    *PHASE.lock().unwrap() = Phase { name, progress: None };
+}
+
+/// Records that `name` is running, with `count` telling how many `unit`s
+/// of work it has done.
+pub fn phase_counting(name: &'static str, unit: &'static str, count: impl Fn() -> u64 + Send + 'static) {
+    *PHASE.lock().unwrap() = Phase { name, progress: Some((unit, Box::new(count))) };
+}
+
+/// Parses a duration like `5s`, `1.5s`, `500ms` or `2m`.
+pub fn parse(s: &str) -> Result<Duration, String> {
+    let bad = || format!("--timeout expects a duration like 5s or 500ms, found '{s}'");
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').ok_or_else(bad)?;
+    let n: f64 = s[..split].parse().map_err(|_| bad())?;
+    let scale = match &s[split..] {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        _ => return Err(bad()),
+    };
+    Duration::try_from_secs_f64(n * scale).map_err(|_| bad())
+}
+
+/// Starts the watchdog. Once `limit` has passed, it calls `expired` with a
+/// description of the phase that was running, which must end the process.
+pub fn start(limit: Duration, expired: impl FnOnce(String) + Send + 'static) {
+    std::thread::spawn(move || {
+        std::thread::sleep(limit);
+        let phase = PHASE.lock().unwrap_or_else(|e| e.into_inner());
+        let mut report = phase.name.to_string();
+        if let Some((unit, count)) = &phase.progress {
+            report += &format!(" ({} {unit})", count());
+        }
+        expired(report);
+    });
+}
+
+#[test]
+fn tests() {
+    assert_eq!(parse("5s"), Ok(Duration::from_secs(5)));
+    assert_eq!(parse("1.5s"), Ok(Duration::from_millis(1500)));
+    assert_eq!(parse("250ms"), Ok(Duration::from_millis(250)));
+    assert_eq!(parse("2m"), Ok(Duration::from_secs(120)));
+    assert_eq!(parse("5"), Err("--timeout expects a duration like 5s or 500ms, found '5'".into()));
+    assert!(parse("s").is_err());
+    assert!(parse("1h").is_err());
+}
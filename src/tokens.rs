@@ -0,0 +1,110 @@
+//! Token streams as JSON (`--emit=tokens`, `--from=FILE`), so that other
+//! tools can lex for stoncc or work on what it lexed. A stream is an array
+//! of tokens like
+//!
+//! ```text
+//! [{"kind": "symbol", "text": "x", "start": 0, "end": 1, "line": 1}, ...]
+//! ```
+//!
+//! with the kinds of `Token::kind`. `line` may be left out to stay on the
+//! line of the token before.
+
+use stoncc::json::{self, Json};
+use stoncc::lexer::{Lexed, Span, Token};
+
+use crate::input::{self, Encoding};
+
+use crate::{fail, Failure};
+
+/// Renders `tokens`, lexed from `s`, as a stream.
+pub fn to_json(tokens: &[Lexed], s: &[u8]) -> String {
+    let (mut line, mut at) = (1, 0);
+    json::array(tokens.iter().map(|t| {
+        line += s.get(at..t.span.start).map_or(0, |gap| gap.iter().filter(|&&c| c == b'\n').count());
+        at = t.span.start.max(at);
+        format!(
+            "{{\"kind\": {}, \"text\": {}, \"start\": {}, \"end\": {}, \"line\": {line}}}",
+            json::string(t.token.kind()),
+            json::string(&t.text),
+            t.span.start,
+            t.span.end,
+        )
+    }))
+}
+
+/// Reads a stream, returning its tokens and a stand-in for the source they
+/// came from: their text at their offsets, for diagnostics to point into.
+pub fn parse(text: &str) -> Result<(Vec<Lexed>, Vec<u8>), String> {
+    let Json::Array(items) = json::parse(text)? else {
+        return Err("expected an array of tokens".into());
+    };
+
+    let (mut tokens, mut source, mut last_line) = (Vec::new(), Vec::new(), 1);
+    for (n, item) in items.iter().enumerate() {
+        let at = |e: String| format!("token {n}: {e}");
+        let string = |key| match item.get(key) {
+            Some(Json::String(s)) => Ok(s.as_str()),
+            _ => Err(at(format!("expected a string \"{key}\""))),
+        };
+        let number = |key| match item.get(key) {
+            Some(Json::Number(n)) => n.parse::<usize>().map_err(|_| at(format!("\"{key}\" must be an offset"))),
+            _ => Err(at(format!("expected a number \"{key}\""))),
+        };
+
+        let (kind, text) = (string("kind")?, string("text")?);
+        let span = Span { start: number("start")?, end: number("end")? };
+        if span.end < span.start {
+            return Err(at("\"end\" comes before \"start\"".into()));
+        }
+        let line = if item.get("line").is_some() { number("line")? } else { last_line };
+        if line < last_line {
+            return Err(at(format!("line {line} comes after line {last_line}")));
+        }
+        let token = Token::from_kind(kind, text, span).map_err(at)?;
+
+        // Line breaks go first in the gap before the token, as many as fit.
+        let gap = span.start.saturating_sub(source.len());
+        source.extend(std::iter::repeat_n(b'\n', (line - last_line).min(gap)));
+        source.resize(span.start.max(source.len()), b' ');
+        source.extend_from_slice(text.as_bytes());
+
+        tokens.push(Lexed { token, span, newline: line > last_line, text: text.to_string() });
+        last_line = line;
+    }
+
+    Ok((tokens, source))
+}
+
+pub fn load(path: &str) -> (Vec<Lexed>, Vec<u8>) {
+    let text = String::from_utf8(input::read(path, Encoding::Utf8)).unwrap();
+    parse(&text).unwrap_or_else(|e| fail(Failure::Run, format!("{path}: {e}")))
+}
+
+#[test]
+fn tests() {
+    use stoncc::lexer::Lexer;
+
+    let s = "x = 2.5 +% f(?1)\n\n  if y { 1 } // done\n\u{e9}";
+    let lexed = Lexer::from_bytes(s.as_bytes()).tokens().unwrap();
+    let stream = to_json(&lexed, s.as_bytes());
+    assert!(stream.starts_with(r#"[{"kind": "symbol", "text": "x", "start": 0, "end": 1, "line": 1}, {"kind": "punct", "text": "=""#), "{stream}");
+    assert!(stream.contains(r#"{"kind": "keyword", "text": "if", "start": 20, "end": 22, "line": 3}"#), "{stream}");
+
+    // Streams read back as they were written, and the stand-in source has
+    // each token where it was.
+    let (tokens, source) = parse(&stream).unwrap();
+    assert_eq!(tokens, lexed);
+    assert_eq!(source, "x = 2.5 +% f(?1)\n\n  if y { 1 }\n        \u{e9}".as_bytes());
+
+    // Anything can be a symbol, and unknown punctuation is an operator.
+    let (tokens, source) = parse(r#"[{"kind": "symbol", "text": "a-b", "start": 3, "end": 6}, {"kind": "punct", "text": "<=>", "start": 7, "end": 10, "line": 2}]"#).unwrap();
+    assert_eq!(tokens[0].token, Token::Sym("a-b".into()));
+    assert_eq!((&tokens[1].token, tokens[1].newline), (&Token::Op("<=>".into()), true));
+    assert_eq!(source, b"   a-b\n<=>");
+
+    let err = |s: &str| parse(s).unwrap_err();
+    assert_eq!(err("{}"), "expected an array of tokens");
+    assert_eq!(err(r#"[{"kind": "int", "text": "1e3", "start": 0, "end": 3}]"#), "token 0: \"1e3\" is not a valid int");
+    assert_eq!(err(r#"[{"kind": "int", "text": "1", "start": 0}]"#), "token 0: expected a number \"end\"");
+    assert_eq!(err(r#"[{"kind": "space", "text": " ", "start": 0, "end": 1}]"#), "token 0: unknown token kind 'space'");
+}
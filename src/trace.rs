@@ -0,0 +1,294 @@
+//! Evaluation one step at a time (`--trace`), for teaching: each step
+//! reduces the leftmost subtree whose operands are all values, so
+//! `(+ 1 (* 2 3))` becomes `(+ 1 6)` and then `7`. Symbols step to their
+//! values, a call to a function the program defined steps to its body with
+//! the arguments in place of the parameters, and `&&`, `||` and `?:` step
+//! to the operand their first one selects. [`explain`] says what a step
+//! did and why, for `--explain-result`.
+//!
+//! ```
+//! use stoncc::limits::{EvalLimits, Meter};
+//! use stoncc::{trace, Builtins, Env, Value};
+//!
+//! let ast = stoncc::expr(b"1 + 2 * 3").unwrap();
+//! let mut trees = Vec::new();
+//! let mut meter = Meter::new(EvalLimits::default());
+//! let v = trace::trace(&ast, &Env::new(), &Builtins::default(), &mut meter, |step| trees.push(step.tree.to_string()));
+//! assert_eq!(v, Ok(Value::Int(7)));
+//! assert_eq!(trees, ["(+ 1 6)", "7"]);
+//! ```
+
+use std::convert::Infallible;
+
+use crate::ast::Folder;
+use crate::infix;
+use crate::lexer::Span;
+use crate::limits::Meter;
+use crate::parser::{LeafVal, Node, NodeVal, ShortCircuit, Value};
+use crate::{eval_with, Builtins, Env, EvalError};
+
+/// Steps a trace takes at most. Past them, what is left of the tree is
+/// evaluated in one go, which also stops runaway recursion with the usual
+/// error.
+pub const MAX_STEPS: usize = 10_000;
+
+/// One step of a trace.
+pub struct Step<'a> {
+    /// The subtree reduced.
+    pub redex: &'a Node,
+    /// What replaced it.
+    pub by: &'a Node,
+    /// The whole tree after the step.
+    pub tree: &'a Node,
+    /// The node the redex is an operand of, before the step, and which
+    /// operand it is, unless the redex is the whole tree.
+    pub parent: Option<(&'a Node, usize)>,
+}
+
+/// The value of a literal leaf, as evaluation reads it.
+fn value(node: &Node) -> Option<Value> {
+    match node {
+        Node::Leaf(LeafVal::Int(v), _) => Some(Value::Int(*v)),
+        Node::Leaf(LeafVal::Bool(b), _) => Some(Value::Int(*b as i32)),
+        Node::Leaf(LeafVal::Decimal(s), _) => s.parse().ok().map(Value::Float),
+        _ => None,
+    }
+}
+
+/// `v` as a leaf that evaluates to it again, if there is one. Floats keep
+/// their point, so that `6.0` doesn't turn into the integer `6`.
+fn literal(v: Value, span: Span) -> Option<Node> {
+    let leaf = match v {
+        Value::Int(v) => LeafVal::Int(v),
+        Value::Float(v) if v.is_finite() => LeafVal::Decimal(format!("{v:?}")),
+        Value::Float(_) | Value::Wide(_) | Value::Duration(_) | Value::Quantity(..) => return None,
+    };
+    Some(Node::Leaf(leaf, span))
+}
+
+/// Puts arguments in place of the parameters of a body.
+struct Substitute<'a>(&'a Env<Node>);
+
+impl Folder for Substitute<'_> {
+    type Error = Infallible;
+
+    fn fold_leaf(&mut self, leaf: &LeafVal, span: Span) -> Result<Node, Infallible> {
+        Ok(match leaf {
+            LeafVal::Sym(s) if self.0.contains_key(s.as_str()) => self.0[s.as_str()].clone(),
+            leaf => Node::Leaf(leaf.clone(), span),
+        })
+    }
+}
+
+/// What `Stepper::step` did to a tree.
+enum Stepped {
+    /// Nothing: the tree is a value already.
+    Value,
+    /// Reduced the subtree `redex`, an operand of `parent` if it isn't
+    /// the whole tree, to `by`, giving the tree.
+    Reduced { redex: Node, by: Node, tree: Node, parent: Option<Box<(Node, usize)>> },
+    /// Found a subtree whose value has no literal, so it can't go on.
+    Stuck,
+}
+
+struct Stepper<'a> {
+    env: &'a Env,
+    builtins: &'a Builtins,
+    meter: &'a mut Meter,
+}
+
+impl Stepper<'_> {
+    /// Replaces `node` as a whole with `by`.
+    fn replace(&mut self, node: &Node, by: Node) -> Result<Stepped, EvalError> {
+        self.meter.op()?;
+        Ok(Stepped::Reduced { redex: node.clone(), tree: by.clone(), by, parent: None })
+    }
+
+    /// Evaluates `node`, whose operands are values, to a literal.
+    fn compute(&mut self, node: &Node) -> Result<Stepped, EvalError> {
+        let v = eval_with(node, self.env, self.builtins, self.meter)?;
+        match literal(v, node.span()) {
+            Some(by) => Ok(Stepped::Reduced { redex: node.clone(), tree: by.clone(), by, parent: None }),
+            None => Ok(Stepped::Stuck),
+        }
+    }
+
+    /// Steps operand `i` of `node`, keeping the rest.
+    fn operand(&mut self, node: &Node, i: usize) -> Result<Stepped, EvalError> {
+        let Node::Node { v, children, span } = node else { unreachable!() };
+        Ok(match self.step(&children[i])? {
+            Stepped::Reduced { redex, by, tree, parent } => {
+                let parent = parent.or_else(|| Some(Box::new((node.clone(), i))));
+                let mut children = children.clone();
+                children[i] = tree;
+                Stepped::Reduced { redex, by, tree: Node::Node { v: v.clone(), children, span: *span }, parent }
+            }
+            stepped => stepped,
+        })
+    }
+
+    /// Reduces the leftmost subtree of `node` that can be.
+    fn step(&mut self, node: &Node) -> Result<Stepped, EvalError> {
+        let (v, children) = match node {
+            Node::Leaf(LeafVal::Int(_) | LeafVal::Bool(_) | LeafVal::Decimal(_), _) => return Ok(Stepped::Value),
+            Node::Leaf(LeafVal::Sym(s), span) => {
+                let v = self.env.get(s.as_str()).ok_or_else(|| EvalError::Unbound(s.to_string(), *span))?;
+                return match literal(*v, *span) {
+                    Some(by) => self.replace(node, by),
+                    None => Ok(Stepped::Stuck),
+                };
+            }
+            Node::Leaf(LeafVal::Placeholder(i), span) => return Err(EvalError::Unfilled(*i, *span)),
+            Node::Error(span) => return Err(EvalError::Syntax(*span)),
+            Node::Node { v, children, .. } => (v, children),
+        };
+
+        if v.short_circuit(true).is_some() {
+            let Some(first) = value(&children[0]) else { return self.operand(node, 0) };
+            return match v.short_circuit(first.is_true()).unwrap() {
+                ShortCircuit::Done(b) => self.replace(node, Node::Leaf(LeafVal::Int(b as i32), node.span())),
+                ShortCircuit::Operand(i) => self.replace(node, children[i].clone()),
+                ShortCircuit::Truth(i) if value(&children[i]).is_none() => self.operand(node, i),
+                ShortCircuit::Truth(_) => self.compute(node),
+            };
+        }
+
+        if let Some(i) = children.iter().position(|c| value(c).is_none()) {
+            return self.operand(node, i);
+        }
+        let defined = match v {
+            NodeVal::Call(name) => self.builtins.defined(name).map(|f| (name, f)),
+            _ => None,
+        };
+        let Some((name, f)) = defined else { return self.compute(node) };
+        let params = f.bind(children).map_err(|msg| EvalError::Call(name.clone(), msg, node.span()))?;
+        let Ok(body) = f.body.fold(&mut Substitute(&params));
+        self.replace(node, body)
+    }
+}
+
+/// Evaluates `ast` as `eval_with` does, but one step at a time, calling
+/// `each` with every step. A value with no literal, such as a duration,
+/// ends the steps early, and so does `MAX_STEPS`; the rest of the tree is
+/// then evaluated at once.
+pub fn trace(ast: &Node, env: &Env, builtins: &Builtins, meter: &mut Meter, mut each: impl FnMut(&Step)) -> Result<Value, EvalError> {
+    let mut tree = ast.clone();
+    let mut stepper = Stepper { env, builtins, meter };
+    for _ in 0..MAX_STEPS {
+        match stepper.step(&tree)? {
+            Stepped::Value => return Ok(value(&tree).unwrap()),
+            Stepped::Reduced { redex, by, tree: next, parent } => {
+                each(&Step { redex: &redex, by: &by, tree: &next, parent: parent.as_deref().map(|(p, i)| (p, *i)) });
+                tree = next;
+            }
+            Stepped::Stuck => break,
+        }
+    }
+    eval_with(&tree, env, builtins, stepper.meter)
+}
+
+/// Why `child` is reduced before the rest of `parent`, of which it is
+/// operand `i`.
+fn reason(parent: &Node, i: usize, child: &Node) -> Option<String> {
+    let (Node::Node { v: p, children, .. }, Node::Node { v: c, .. }) = (parent, child) else { return None };
+    if infix::parenthesized(parent, i, child) {
+        return Some("it is in parentheses".to_string());
+    }
+    if p.fixity(children.len()) == "call" {
+        return Some(format!("{p} needs its arguments first"));
+    }
+    if p.short_circuit(true).is_some() {
+        return Some(format!("{p} needs it to decide"));
+    }
+    let (inner, outer) = (infix::binding(child), infix::binding(parent));
+    let ops = if c == p { format!("{p} groups") } else { format!("{c} and {p} group") };
+    match i {
+        _ if inner > outer => Some(format!("{c} binds tighter than {p}")),
+        _ if inner < outer => None,
+        0 => Some(format!("{ops} from the left")),
+        _ => Some(format!("{ops} from the right")),
+    }
+}
+
+/// What `step` did, in words, like `2 * 3 = 6, because * binds tighter
+/// than +`.
+pub fn explain(step: &Step) -> String {
+    let (redex, by) = (step.redex.to_infix(), step.by.to_infix());
+    let Node::Node { v, children, .. } = step.redex else {
+        return format!("{redex} is {by}");
+    };
+    if let Some(first) = v.short_circuit(true).and(value(&children[0])) {
+        let truth = if first.is_true() { "true" } else { "false" };
+        return format!("{redex} is {by}, since {} is {truth}", children[0].to_infix());
+    }
+    if let (NodeVal::Call(name), None) = (v, value(step.by)) {
+        return format!("{redex} is {by}, by the definition of {name}");
+    }
+    match step.parent.and_then(|(parent, i)| reason(parent, i, step.redex)) {
+        Some(why) => format!("{redex} = {by}, because {why}"),
+        None => format!("{redex} = {by}"),
+    }
+}
+
+#[test]
+fn tests() {
+    use crate::generate::{random, Rng};
+    use crate::limits::EvalLimits;
+
+    let env: Env = [("x".to_string(), Value::Int(4)), ("h".to_string(), Value::Float(0.5))].into();
+    let mut builtins = Builtins::default();
+    builtins.define("sq", crate::Function { params: vec!["a".into()], body: crate::parser::expr(b"a * a").unwrap() });
+    builtins.define("fact", crate::Function { params: vec!["n".into()], body: crate::parser::expr(b"n < 2 ? 1 : n * fact(n - 1)").unwrap() });
+    let run = |s: &str| {
+        let mut steps = Vec::new();
+        let mut meter = Meter::new(EvalLimits::default());
+        let v = trace(&crate::parser::expr(s.as_bytes()).unwrap(), &env, &builtins, &mut meter, |s| {
+            steps.push(format!("{} => {}: {}", s.redex, s.by, s.tree))
+        });
+        (steps, v)
+    };
+
+    let (steps, v) = run("1 + 2 * 3");
+    assert_eq!(steps, ["(* 2 3) => 6: (+ 1 6)", "(+ 1 6) => 7: 7"]);
+    assert_eq!(v, Ok(Value::Int(7)));
+
+    // Symbols, then calls by their bodies.
+    let (steps, v) = run("sq(x) - h");
+    assert_eq!(steps, ["x => 4: (- (sq 4) h)", "(sq 4) => (* 4 4): (- (* 4 4) h)", "(* 4 4) => 16: (- 16 h)", "h => 0.5: (- 16 0.5)", "(- 16 0.5) => 15.5: 15.5"]);
+    assert_eq!(v, Ok(Value::Float(15.5)));
+
+    // Only the operand the first one selects is reduced.
+    let (steps, _) = run("x > 9 && 1 / 0");
+    assert_eq!(steps.last().unwrap(), "(&& 0 (/ 1 0)) => 0: 0");
+    let (steps, _) = run("(x ? 2 : 1 / 0) || 0");
+    assert_eq!(steps[1..], ["(?: 4 2 (/ 1 0)) => 2: (|| 2 0)", "(|| 2 0) => 1: 1"]);
+    assert_eq!(run("fact(5)").1, Ok(Value::Int(120)));
+    assert_eq!(run("fact(2) + y").1, Err(EvalError::Unbound("y".to_string(), Span { start: 10, end: 11 })));
+
+    // Each step in words, with why it comes first.
+    let explained = |s: &str| {
+        let mut meter = Meter::new(EvalLimits::default());
+        let mut lines = Vec::new();
+        trace(&crate::parser::expr(s.as_bytes()).unwrap(), &env, &builtins, &mut meter, |s| lines.push(explain(s))).unwrap();
+        lines
+    };
+    assert_eq!(explained("1 + 2 * 3"), ["2 * 3 = 6, because * binds tighter than +", "1 + 6 = 7"]);
+    assert_eq!(explained("10 - 2 + 3 - (1 - 1)"), ["10 - 2 = 8, because - and + group from the left", "8 + 3 = 11, because + and - group from the left", "1 - 1 = 0, because it is in parentheses", "11 - 0 = 11"]);
+    assert_eq!(explained("2 ^ 1 ^ 2")[0], "1 ^ 2 = 1, because ^ groups from the right");
+    assert_eq!(explained("sq(x)"), ["x is 4", "sq(4) is 4 * 4, by the definition of sq", "4 * 4 = 16"]);
+    assert_eq!(explained("max(1 + 1, 3)")[0], "1 + 1 = 2, because max needs its arguments first");
+    assert_eq!(explained("x > 9 ? 1 : 2"), ["x is 4", "4 > 9 = 0, because ?: needs it to decide", "0 ? 1 : 2 is 2, since 0 is false"]);
+
+    // The same values as evaluation, errors included.
+    let mut rng = Rng::new(1);
+    let env: Env = ["a", "b", "c", "d", "e"].iter().zip(1..).map(|(s, v)| (s.to_string(), Value::Int(v))).collect();
+    for _ in 0..2000 {
+        let ast = random(&mut rng, 4);
+        let mut meter = Meter::new(EvalLimits::default());
+        let traced = trace(&ast, &env, &builtins, &mut meter, |_| {});
+        let mut meter = Meter::new(EvalLimits::default());
+        let v = eval_with(&ast, &env, &builtins, &mut meter);
+        // NaNs compare by how they print.
+        assert_eq!(format!("{:?}", traced.map_err(|e| e.code())), format!("{:?}", v.map_err(|e| e.code())), "{ast}");
+    }
+}
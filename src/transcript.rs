@@ -0,0 +1,195 @@
+//! Session transcripts: `--record=FILE` and `stoncc replay FILE`.
+//!
+//! A transcript is JSON Lines. It holds the arguments of the run, every
+//! input the run read, what it printed and how it exited:
+//!
+//! ```text
+//! {"version": "0.1.0", "args": ["--decimal", "prog.stc"]}
+//! {"input": "prog.stc", "text": "x = 1 / 3\n"}
+//! {"stdout": "Evaluating (= x (/ 1 3)): 0.3333333333\n"}
+//! {"stderr": ""}
+//! {"status": 0}
+//! ```
+//!
+//! Both commands do the actual work in a child process, so that all of
+//! its output can be captured. An environment variable tells the child
+//! what to do with its inputs. Under `--record` it appends each one to the
+//! transcript. Under `replay` it takes them from the transcript instead of
+//! from files and standard input, so the replay sees exactly what the
+//! recorded run saw.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::process::{self, Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use stoncc::json::{self, Json};
+
+use crate::{fail, theme, Failure};
+
+/// Set for the child of `--record`, to the transcript.
+const RECORD: &str = "STONCC_RECORD";
+/// Set for the child of `replay`, to the transcript.
+const REPLAY: &str = "STONCC_REPLAY";
+
+fn append(path: &str, line: &str) {
+    let file = OpenOptions::new().append(true).open(path);
+    file.and_then(|mut f| writeln!(f, "{line}")).unwrap_or_else(|e| fail(Failure::Run, format!("cannot write {path}: {e}")));
+}
+
+fn entries(text: &str) -> Result<Vec<Json>, String> {
+    let lines = text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty());
+    lines.map(|(i, l)| json::parse(l).map_err(|e| format!("line {}: {e}", i + 1))).collect()
+}
+
+fn load(path: &str) -> Vec<Json> {
+    let text = fs::read_to_string(path).unwrap_or_else(|e| fail(Failure::Run, format!("cannot read {path}: {e}")));
+    entries(&text).unwrap_or_else(|e| fail(Failure::Run, format!("{path}: {e}")))
+}
+
+/// The string value of the first entry that has `key`.
+fn string<'a>(entries: &'a [Json], key: &str) -> Option<&'a str> {
+    entries.iter().find_map(|e| match e.get(key) {
+        Some(Json::String(s)) => Some(s.as_str()),
+        _ => None,
+    })
+}
+
+/// The input `name`, read with `read`. Under `--record` it is appended to
+/// the transcript; under `replay` it comes from the transcript instead,
+/// where inputs are taken in the order they were recorded.
+pub fn input(name: &str, read: impl FnOnce() -> Vec<u8>) -> Vec<u8> {
+    if let Ok(path) = env::var(REPLAY) {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let entries = load(&path);
+        let inputs = entries.iter().filter_map(|e| Some((e.get("input")?, e.get("text")?)));
+        return match inputs.clone().nth(n) {
+            Some((Json::String(recorded), Json::String(text))) if recorded == name => text.clone().into_bytes(),
+            _ => fail(Failure::Run, format!("{path}: the recorded run did not read {name} at this point")),
+        };
+    }
+
+    let bytes = read();
+    if let Ok(path) = env::var(RECORD) {
+        let text = String::from_utf8_lossy(&bytes);
+        append(&path, &format!("{{\"input\": {}, \"text\": {}}}", json::string(name), json::string(&text)));
+    }
+    bytes
+}
+
+/// Runs this binary with `args`, and `var` set to the transcript `path`.
+fn child(args: &[String], var: &str, path: &str, stdin: Stdio) -> Output {
+    let exe = env::current_exe().unwrap_or_else(|e| fail(Failure::Run, format!("cannot find the stoncc binary: {e}")));
+    Command::new(exe)
+        .args(args)
+        .env_remove(RECORD)
+        .env_remove(REPLAY)
+        .env(var, path)
+        .stdin(stdin)
+        .output()
+        .unwrap_or_else(|e| fail(Failure::Run, format!("cannot run stoncc: {e}")))
+}
+
+/// `stoncc --record=FILE ARGS...`: runs `stoncc ARGS...`, passing its
+/// output through, and writes the transcript of the run to `path`.
+pub fn record(path: &str, args: &[String]) -> ! {
+    let args_json = json::array(args.iter().map(|a| json::string(a)));
+    let header = format!("{{\"version\": {}, \"args\": {args_json}}}\n", json::string(env!("CARGO_PKG_VERSION")));
+    fs::write(path, header).unwrap_or_else(|e| fail(Failure::Run, format!("cannot write {path}: {e}")));
+
+    let output = child(args, RECORD, path, Stdio::inherit());
+    let _ = io::stdout().write_all(&output.stdout);
+    let _ = io::stderr().write_all(&output.stderr);
+
+    let status = output.status.code().unwrap_or(-1);
+    append(path, &format!("{{\"stdout\": {}}}", json::string(&String::from_utf8_lossy(&output.stdout))));
+    append(path, &format!("{{\"stderr\": {}}}", json::string(&String::from_utf8_lossy(&output.stderr))));
+    append(path, &format!("{{\"status\": {status}}}"));
+    process::exit(status);
+}
+
+/// The first line where `got` differs from `expected`, numbered from 1.
+fn first_difference<'a>(expected: &'a str, got: &'a str) -> Option<(usize, &'a str, &'a str)> {
+    if expected == got {
+        return None;
+    }
+    let (mut e, mut g) = (expected.lines(), got.lines());
+    for n in 1.. {
+        match (e.next(), g.next()) {
+            (None, None) => break,
+            (a, b) if a == b => {}
+            (a, b) => return Some((n, a.unwrap_or("(end of output)"), b.unwrap_or("(end of output)"))),
+        }
+    }
+    Some((expected.lines().count() + 1, "(a final line break)", "(none)"))
+}
+
+fn usage() -> ! {
+    eprintln!("usage: stoncc replay TRANSCRIPT");
+    process::exit(2);
+}
+
+/// `stoncc replay FILE`: reruns the session recorded in `FILE` on the
+/// recorded input, prints its output, and exits with 1 if the output or
+/// exit status differs from the recording.
+pub fn main(args: &[String]) {
+    let [path] = args else { usage() };
+    let entries = load(path);
+
+    let args: Vec<String> = match entries.first().and_then(|e| e.get("args")) {
+        Some(Json::Array(args)) => args
+            .iter()
+            .map(|a| match a {
+                Json::String(a) => a.clone(),
+                _ => fail(Failure::Run, format!("{path}: arguments must be strings")),
+            })
+            .collect(),
+        _ => fail(Failure::Run, format!("{path}: not a stoncc transcript")),
+    };
+    if let Some(version) = string(&entries, "version").filter(|&v| v != env!("CARGO_PKG_VERSION")) {
+        eprintln!("{}: {path} was recorded by stoncc {version}", theme::label("warning"));
+    }
+
+    let output = child(&args, REPLAY, path, Stdio::null());
+    let _ = io::stdout().write_all(&output.stdout);
+    let _ = io::stderr().write_all(&output.stderr);
+
+    let mut same = true;
+    for (stream, got) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+        let expected = string(&entries, stream).unwrap_or_default();
+        if let Some((line, e, g)) = first_difference(expected, &String::from_utf8_lossy(got)) {
+            eprintln!("replay: {stream} differs at line {line}\n  recorded: {e}\n  replayed: {g}");
+            same = false;
+        }
+    }
+    let recorded = entries.iter().find_map(|e| match e.get("status") {
+        Some(Json::Number(n)) => n.parse::<i32>().ok(),
+        _ => None,
+    });
+    let status = output.status.code().unwrap_or(-1);
+    if recorded != Some(status) {
+        let recorded = recorded.map_or("none".to_string(), |r| r.to_string());
+        eprintln!("replay: exited with status {status}, but the recorded status is {recorded}");
+        same = false;
+    }
+
+    if !same {
+        process::exit(1);
+    }
+}
+
+#[test]
+fn tests() {
+    assert_eq!(first_difference("a\nb\n", "a\nb\n"), None);
+    assert_eq!(first_difference("a\nb\n", "a\nc\n"), Some((2, "b", "c")));
+    assert_eq!(first_difference("a\n", "a\nb\n"), Some((2, "(end of output)", "b")));
+    assert_eq!(first_difference("a\n", "a"), Some((2, "(a final line break)", "(none)")));
+
+    let text = "{\"version\": \"0.1.0\", \"args\": [\"-\"]}\n{\"input\": \"-\", \"text\": \"1 +\\n2\"}\n\n{\"status\": 1}\n";
+    let parsed = entries(text).unwrap();
+    assert_eq!(parsed.len(), 3);
+    assert_eq!(string(&parsed, "text"), Some("1 +\n2"));
+    assert!(entries("{\"status\": 1}\n{oops}").unwrap_err().starts_with("line 2: "));
+}
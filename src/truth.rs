@@ -0,0 +1,243 @@
+use std::collections::BTreeSet;
+use std::io::Read;
+
+use crate::deps::{self, Name};
+use crate::{fail, input, Failure};
+use stoncc::{parser, vm, Env, Node, Value};
+
+/// How many variables `stoncc truth-table` and `stoncc bool-min` take by
+/// default: 2^12 rows.
+const MAX_VARS: usize = 12;
+
+/// The variables of `expr`, in alphabetical order.
+fn variables(expr: &Node) -> Vec<String> {
+    let mut vars: Vec<String> = deps::uses(expr, &[]).into_iter().filter_map(|n| match n {
+        Name::Var(v) => Some(v),
+        Name::Fn(_) => None,
+    }).collect();
+    vars.sort();
+    vars.dedup();
+    vars
+}
+
+/// Whether `expr` holds for each assignment of 0 and 1 to `vars`, the
+/// first varying slowest, so that row `i` gives each variable its bit of
+/// `i`. Fails with the first assignment under which it doesn't evaluate.
+fn table(expr: &Node, vars: &[String]) -> Result<Vec<bool>, String> {
+    let envs: Vec<Env> = (0..1usize << vars.len())
+        .map(|row| vars.iter().enumerate().map(|(i, v)| (v.clone(), Value::Int((row >> (vars.len() - 1 - i)) as i32 & 1))).collect())
+        .collect();
+    let results = vm::CompiledExpr::new(expr).eval_batch(&envs);
+    envs.iter().zip(results).map(|(env, v)| {
+        v.map(Value::is_true).map_err(|e| {
+            let assignment = vars.iter().map(|v| format!("{v} = {}", env[v])).collect::<Vec<_>>().join(", ");
+            format!("with {assignment}: {e}")
+        })
+    }).collect()
+}
+
+/// The truth table of `expr` over `vars`: a header of the variables and
+/// the expression, then a row of 0s and 1s for each assignment.
+fn render(expr: &Node, vars: &[String], rows: &[bool]) -> String {
+    let mut out = vars.iter().map(|v| format!("{v} ")).collect::<String>() + &format!("| {}\n", expr.to_infix());
+    for (row, &holds) in rows.iter().enumerate() {
+        for (i, v) in vars.iter().enumerate() {
+            out += &format!("{:<w$} ", (row >> (vars.len() - 1 - i)) & 1, w = v.len());
+        }
+        out += &format!("| {}\n", holds as u8);
+    }
+    out
+}
+
+/// Reads the boolean expression in `path`, or standard input for `-`.
+fn read(path: &str) -> Node {
+    let text = if path == "-" {
+        let mut s = String::new();
+        std::io::stdin().read_to_string(&mut s).unwrap_or_else(|e| fail(Failure::Run, format!("cannot read standard input: {e}")));
+        s
+    } else {
+        String::from_utf8(input::read(path, input::Encoding::Utf8)).unwrap()
+    };
+    parser::expr(text.trim().as_bytes()).unwrap_or_else(|e| fail(Failure::Run, format!("{path}: {e}")))
+}
+
+/// A product of variables and their negations: the variables whose bit is
+/// set in `mask` are those of `value`, the others can be anything. Bits are
+/// numbered as the rows of `table` are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Implicant {
+    value: usize,
+    mask: usize,
+}
+
+impl Implicant {
+    fn covers(self, row: usize) -> bool {
+        row & self.mask == self.value
+    }
+}
+
+/// The prime implicants of the rows in `minterms`, by Quine–McCluskey:
+/// implicants that differ in one variable merge into one without it, until
+/// none do.
+fn primes(minterms: &[usize], n: usize) -> Vec<Implicant> {
+    let mut current: BTreeSet<Implicant> = minterms.iter().map(|&m| Implicant { value: m, mask: (1 << n) - 1 }).collect();
+    let mut primes = Vec::new();
+    while !current.is_empty() {
+        let (mut next, mut merged) = (BTreeSet::new(), BTreeSet::new());
+        for &a in &current {
+            for &b in current.range(a..).filter(|b| b.mask == a.mask) {
+                let diff = a.value ^ b.value;
+                if diff.count_ones() == 1 {
+                    next.insert(Implicant { value: a.value & !diff, mask: a.mask & !diff });
+                    merged.extend([a, b]);
+                }
+            }
+        }
+        primes.extend(current.difference(&merged));
+        current = next;
+    }
+    primes
+}
+
+/// Primes covering every row of `minterms`: those that are the only one
+/// to cover some row, then whichever covers the most rows left, with the
+/// fewest variables. Past the first, the choice is greedy, so for a few
+/// functions there are smaller covers.
+fn cover(primes: &[Implicant], minterms: &[usize]) -> Vec<Implicant> {
+    let mut chosen: Vec<Implicant> = Vec::new();
+    for &m in minterms {
+        if let [only] = primes.iter().filter(|p| p.covers(m)).collect::<Vec<_>>()[..] {
+            if !chosen.contains(only) {
+                chosen.push(*only);
+            }
+        }
+    }
+    let mut left: Vec<usize> = minterms.iter().copied().filter(|&m| !chosen.iter().any(|p| p.covers(m))).collect();
+    while !left.is_empty() {
+        let best = *primes.iter().max_by_key(|p| (left.iter().filter(|&&m| p.covers(m)).count(), std::cmp::Reverse(p.mask.count_ones()))).unwrap();
+        chosen.push(best);
+        left.retain(|&m| !best.covers(m));
+    }
+    chosen
+}
+
+/// A sum of prime implicants with the truth table `rows` over `vars`, as
+/// few as `cover` finds, as a stoncc expression: `||` of `&&` of each variable or its `== 0`.
+pub fn minimize(vars: &[String], rows: &[bool]) -> String {
+    let minterms: Vec<usize> = (0..rows.len()).filter(|&row| rows[row]).collect();
+    let n = vars.len();
+    let mut terms: Vec<String> = cover(&primes(&minterms, n), &minterms).into_iter().map(|p| {
+        let literals: Vec<String> = vars.iter().enumerate().filter(|&(i, _)| p.mask >> (n - 1 - i) & 1 == 1).map(|(i, v)| {
+            if p.value >> (n - 1 - i) & 1 == 1 { v.clone() } else { format!("{v} == 0") }
+        }).collect();
+        if literals.is_empty() { "true".to_string() } else { literals.join(" && ") }
+    }).collect();
+    terms.sort();
+    if terms.is_empty() { "false".to_string() } else { terms.join(" || ") }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: stoncc truth-table FILE [--max-vars N]");
+    std::process::exit(2);
+}
+
+/// `stoncc truth-table FILE`: prints whether the expression in `FILE`
+/// holds, that is is nonzero, for each assignment of 0 and 1 to its
+/// variables. There are 2^n of them, so more than `--max-vars` variables
+/// is an error.
+pub fn main(args: &[String]) {
+    let (expr, vars, rows) = read_table(args, usage);
+    print!("{}", render(&expr, &vars, &rows));
+}
+
+fn min_usage() -> ! {
+    eprintln!("usage: stoncc bool-min FILE [--max-vars N]");
+    std::process::exit(2);
+}
+
+/// Reads the arguments of `truth-table` and `bool-min`, which both take a
+/// file and `--max-vars`, and gives the expression and its table.
+fn read_table(args: &[String], usage: fn() -> !) -> (Node, Vec<String>, Vec<bool>) {
+    let (mut path, mut max_vars) = (None, MAX_VARS);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-vars" => {
+                let n = args.next().unwrap_or_else(|| usage());
+                max_vars = n.parse().ok().filter(|&n| n < usize::BITS as usize).unwrap_or_else(|| fail(Failure::Usage, format!("--max-vars expects a number of variables, got '{n}'")));
+            }
+            _ if path.is_none() && (arg == "-" || !arg.starts_with("--")) => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let Some(path) = path else { usage() };
+
+    let expr = read(path);
+    let vars = variables(&expr);
+    if vars.len() > max_vars {
+        fail(Failure::Run, format!("{path} has {} variables, more than --max-vars {max_vars}", vars.len()));
+    }
+    let rows = table(&expr, &vars).unwrap_or_else(|e| fail(Failure::Run, format!("{path}: {e}")));
+    (expr, vars, rows)
+}
+
+/// `stoncc bool-min FILE`: prints a minimal sum of products with the truth
+/// table of the expression in `FILE`, which is the same expression
+/// wherever its variables are 0 or 1.
+pub fn min_main(args: &[String]) {
+    let (_, vars, rows) = read_table(args, min_usage);
+    println!("{}", minimize(&vars, &rows));
+}
+
+#[test]
+fn tests() {
+    let expr = parser::expr(b"carry || a && b").unwrap();
+    let vars = variables(&expr);
+    assert_eq!(vars, ["a", "b", "carry"]);
+    let rows = table(&expr, &vars).unwrap();
+    assert_eq!(rows, [false, true, false, true, false, true, true, true]);
+    assert_eq!(
+        render(&expr, &vars, &rows[..]),
+        "a b carry | carry || a && b\n\
+         0 0 0     | 0\n0 0 1     | 1\n0 1 0     | 0\n0 1 1     | 1\n\
+         1 0 0     | 0\n1 0 1     | 1\n1 1 0     | 1\n1 1 1     | 1\n"
+    );
+
+    // Any value that isn't zero holds, and constants need no variables.
+    let expr = parser::expr(b"x - y").unwrap();
+    assert_eq!(table(&expr, &variables(&expr)).unwrap(), [false, true, true, false]);
+    assert_eq!(table(&parser::expr(b"2 > 1").unwrap(), &[]).unwrap(), [true]);
+    let expr = parser::expr(b"1 / p").unwrap();
+    assert!(table(&expr, &variables(&expr)).unwrap_err().starts_with("with p = 0: "));
+
+    // The textbook example: the minterms 4, 8, 10, 11, 12 and 15 of
+    // a, b, c, d, which take three terms.
+    let vars: Vec<String> = ["a", "b", "c", "d"].map(String::from).to_vec();
+    let rows: Vec<bool> = (0..16).map(|m| [4, 8, 10, 11, 12, 15].contains(&m)).collect();
+    assert_eq!(minimize(&vars, &rows), "a && b == 0 && d == 0 || a && c && d || b && c == 0 && d == 0");
+    assert_eq!(minimize(&vars, &[false; 16]), "false");
+    assert_eq!(minimize(&vars, &[true; 16]), "true");
+
+    // A random expression and its minimum have the same table, and the
+    // minimum has no more terms than the expression has rows that hold.
+    let mut rng = stoncc::generate::Rng::new(509);
+    for _ in 0..200 {
+        let src = (0..1 + rng.below(6))
+            .map(|_| (0..1 + rng.below(3)).map(|_| format!("{}{}", ["a", "b", "c", "d", "e"][rng.below(5)], ["", " == 0"][rng.below(2)])).collect::<Vec<_>>().join(" && "))
+            .collect::<Vec<_>>()
+            .join(" || ");
+        let expr = parser::expr(src.as_bytes()).unwrap();
+        let vars = variables(&expr);
+        let rows = table(&expr, &vars).unwrap();
+        let min = minimize(&vars, &rows);
+        let min_expr = parser::expr(min.as_bytes()).unwrap();
+        let min_vars = variables(&min_expr);
+        // The minimum may not need every variable.
+        let widened: Vec<bool> = (0..rows.len()).map(|row| {
+            let env: Env = vars.iter().enumerate().map(|(i, v)| (v.clone(), Value::Int((row >> (vars.len() - 1 - i)) as i32 & 1))).collect();
+            min_vars.iter().all(|v| vars.contains(v)) && vm::CompiledExpr::new(&min_expr).eval(&env).is_ok_and(Value::is_true)
+        }).collect();
+        assert_eq!(widened, rows, "{src} minimized to {min}");
+        assert!(min.split(" || ").count() <= src.split(" || ").count().max(1), "{src} minimized to {min}");
+    }
+}
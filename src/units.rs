@@ -0,0 +1,184 @@
+//! Physical quantities: `Value::Quantity`, a magnitude in SI base units
+//! and the power of each base unit. With `Extensions::units`, a number
+//! followed by a unit (`3 m`, `2.5 kg`, `9.81 N`) is a quantity literal,
+//! and `3 m / 2 s` prints as `1.5 m/s`.
+//!
+//! Quantities multiply and divide by anything, adding up the powers of
+//! their units, and raise to whole powers. Adding, subtracting and
+//! comparing take two quantities of the same dimension, numbers being
+//! dimensionless, so `1 m + 1 s` is an `ArithError::Units`, and so is a
+//! quantity given to any other operator. Durations count as seconds, and a
+//! result whose units all cancel out is a plain float.
+
+use core::fmt;
+
+use crate::arith::ArithError;
+use crate::parser::{NodeVal, Value};
+use crate::prelude::*;
+
+/// The SI base units, in the order `Unit` keeps their powers and prints
+/// them.
+pub const BASE: [&str; 7] = ["kg", "m", "s", "A", "K", "mol", "cd"];
+
+/// A dimension: the power of each of the `BASE` units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Unit(pub [i8; 7]);
+
+impl Unit {
+    /// No unit at all, that of plain numbers.
+    pub const NONE: Unit = Unit([0; 7]);
+
+    const fn base(i: usize) -> Unit {
+        let mut powers = [0; 7];
+        powers[i] = 1;
+        Unit(powers)
+    }
+
+    /// The unit of a product, or with `sign` -1, of a quotient.
+    fn times(self, other: Unit, sign: i8) -> Result<Unit, ArithError> {
+        let mut powers = self.0;
+        for (p, q) in powers.iter_mut().zip(other.0) {
+            *p = q.checked_mul(sign).and_then(|q| p.checked_add(q)).ok_or(ArithError::Overflow)?;
+        }
+        Ok(Unit(powers))
+    }
+
+    /// The unit raised to the power `n`.
+    fn pow(self, n: f64) -> Result<Unit, ArithError> {
+        let mut powers = self.0;
+        for p in &mut powers {
+            let q = *p as f64 * n;
+            *p = if (-(i8::MAX as f64)..=i8::MAX as f64).contains(&q) { q as i8 } else { return Err(ArithError::Overflow) };
+        }
+        Ok(Unit(powers))
+    }
+}
+
+const KG: Unit = Unit::base(0);
+const M: Unit = Unit::base(1);
+const S: Unit = Unit::base(2);
+
+/// Each unit with its size in SI base units and its dimension.
+pub const UNITS: [(&str, f64, Unit); 21] = [
+    ("m", 1.0, M),
+    ("km", 1000.0, M),
+    ("cm", 0.01, M),
+    ("mm", 0.001, M),
+    ("kg", 1.0, KG),
+    ("g", 0.001, KG),
+    ("t", 1000.0, KG),
+    ("s", 1.0, S),
+    ("ms", 0.001, S),
+    ("min", 60.0, S),
+    ("h", 3600.0, S),
+    ("A", 1.0, Unit::base(3)),
+    ("K", 1.0, Unit::base(4)),
+    ("mol", 1.0, Unit::base(5)),
+    ("cd", 1.0, Unit::base(6)),
+    ("Hz", 1.0, Unit([0, 0, -1, 0, 0, 0, 0])),
+    ("N", 1.0, Unit([1, 1, -2, 0, 0, 0, 0])),
+    ("Pa", 1.0, Unit([1, -1, -2, 0, 0, 0, 0])),
+    ("J", 1.0, Unit([1, 2, -2, 0, 0, 0, 0])),
+    ("W", 1.0, Unit([1, 2, -3, 0, 0, 0, 0])),
+    ("V", 1.0, Unit([1, 2, -3, -1, 0, 0, 0])),
+];
+
+/// Derived units that results print in when they have their dimension.
+/// Hertz isn't one: `s^-1` may count anything.
+const NAMED: [&str; 5] = ["N", "Pa", "J", "W", "V"];
+
+/// Where `name` is in `UNITS`.
+pub fn index(name: &str) -> Option<usize> {
+    UNITS.iter().position(|(u, ..)| *u == name)
+}
+
+/// `kg*m/s^2`, `s^-1` and the like, or one of the `NAMED` units.
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(name) = NAMED.iter().find(|name| UNITS[index(name).unwrap()].2 == *self) {
+            return f.write_str(name);
+        }
+        let part = |(name, p): (&str, i8)| if p == 1 { name.to_string() } else { format!("{name}^{p}") };
+        let powers = |sign: i8| BASE.into_iter().zip(self.0).filter(move |&(_, p)| p.signum() == sign);
+        let above: Vec<_> = powers(1).map(part).collect();
+        if above.is_empty() {
+            return write!(f, "{}", powers(-1).map(part).collect::<Vec<_>>().join("*"));
+        }
+        let below: Vec<_> = powers(-1).map(|(name, p)| part((name, -p))).collect();
+        match &below[..] {
+            [] => write!(f, "{}", above.join("*")),
+            [one] => write!(f, "{}/{one}", above.join("*")),
+            _ => write!(f, "{}/({})", above.join("*"), below.join("*")),
+        }
+    }
+}
+
+/// `n` of the unit at `i` in `UNITS`, for the `quantity` builtin that
+/// quantity literals become.
+pub fn from(n: Value, i: Value) -> Result<Value, String> {
+    let unit = usize::try_from(i.as_i128()).ok().and_then(|i| UNITS.get(i)).filter(|_| matches!(i, Value::Int(_)));
+    match (n, unit) {
+        (Value::Duration(_) | Value::Quantity(..), _) | (_, None) => {
+            Err(format!("expects a number and the index of a unit, below {}", UNITS.len()))
+        }
+        (n, Some(&(_, size, unit))) => Ok(Value::Quantity(n.as_f64() * size, unit)),
+    }
+}
+
+/// `v` as a magnitude and a unit.
+fn split(v: Value) -> (f64, Unit) {
+    match v {
+        Value::Quantity(x, u) => (x, u),
+        Value::Duration(ms) => (ms as f64 / 1000.0, S),
+        v => (v.as_f64(), Unit::NONE),
+    }
+}
+
+/// Applies `op` to `args`, at least one of which is a quantity.
+pub fn apply(op: &NodeVal, args: &[Value]) -> Result<Value, ArithError> {
+    let args: Vec<_> = args.iter().map(|&a| split(a)).collect();
+    let (x, unit) = match (op, &args[..]) {
+        (NodeVal::Add, [a]) => *a,
+        (NodeVal::Sub, [(a, u)]) => (-a, *u),
+        (NodeVal::Add, [(a, u), (b, w)]) if u == w => (a + b, *u),
+        (NodeVal::Sub, [(a, u), (b, w)]) if u == w => (a - b, *u),
+        (NodeVal::Mul, [(a, u), (b, w)]) => (a * b, u.times(*w, 1)?),
+        (NodeVal::Div, [(a, u), (b, w)]) => (a / b, u.times(*w, -1)?),
+        (NodeVal::Exp, [(a, u), (b, Unit::NONE)]) if b.round() == *b => (a.powf(*b), u.pow(*b)?),
+        (op, [(a, u), (b, w)]) if op.is_comparison() && u == w => return Ok(Value::Int(op.compare(a, b))),
+        _ => return Err(ArithError::Units),
+    };
+    Ok(if unit == Unit::NONE { Value::Float(x) } else { Value::Quantity(x, unit) })
+}
+
+#[test]
+fn tests() {
+    use crate::arith::ArithMode;
+
+    let q = |n: f64, name: &str| from(Value::Float(n), Value::Int(index(name).unwrap() as i32)).unwrap();
+    assert_eq!(q(3.0, "km"), Value::Quantity(3000.0, M));
+    assert_eq!(index("ft"), None);
+    assert!(from(Value::Int(1), Value::Int(UNITS.len() as i32)).is_err());
+    assert!(from(Value::Duration(1), Value::Int(0)).is_err());
+
+    let apply = |op: NodeVal, args: &[Value]| op.apply(args, ArithMode::default()).map(|v| v.to_string());
+    assert_eq!(apply(NodeVal::Div, &[q(3.0, "m"), q(2.0, "s")]), Ok("1.5 m/s".into()));
+    assert_eq!(apply(NodeVal::Add, &[q(1.0, "m"), q(1.0, "s")]), Err(ArithError::Units));
+    assert_eq!(apply(NodeVal::Add, &[q(1.0, "km"), q(5.0, "m")]), Ok("1005.0 m".into()));
+    assert_eq!(apply(NodeVal::Sub, &[q(1.0, "kg"), Value::Int(1)]), Err(ArithError::Units));
+    assert_eq!(apply(NodeVal::Gt, &[q(1.0, "h"), q(59.0, "min")]), Ok("1".into()));
+    assert_eq!(apply(NodeVal::Mod, &[q(1.0, "m"), Value::Int(2)]), Err(ArithError::Units));
+
+    // Powers add up, and print as named units where there is one.
+    assert_eq!(apply(NodeVal::Mul, &[q(2.0, "kg"), q(9.0, "m")]), Ok("18.0 kg*m".into()));
+    assert_eq!(apply(NodeVal::Div, &[q(10.0, "J"), q(2.0, "s")]), Ok("5.0 W".into()));
+    assert_eq!(apply(NodeVal::Exp, &[q(3.0, "m"), Value::Int(2)]), Ok("9.0 m^2".into()));
+    assert_eq!(apply(NodeVal::Exp, &[q(3.0, "m"), Value::Float(0.5)]), Err(ArithError::Units));
+    assert_eq!(apply(NodeVal::Div, &[Value::Int(1), q(4.0, "s")]), Ok("0.25 s^-1".into()));
+    assert_eq!(apply(NodeVal::Div, &[q(1.0, "mol"), Value::Quantity(2.0, Unit([0, 3, 0, 0, 1, 0, 0]))]), Ok("0.5 mol/(m^3*K)".into()));
+
+    // Units that cancel leave a number, and durations are in seconds.
+    assert_eq!(apply(NodeVal::Div, &[q(1.0, "km"), q(500.0, "m")]), Ok("2.0".into()));
+    assert_eq!(apply(NodeVal::Mul, &[q(3.0, "m"), Value::Duration(2000)]), Ok("6.0 m*s".into()));
+    assert_eq!(apply(NodeVal::Exp, &[q(1.0, "m"), Value::Int(200)]), Err(ArithError::Overflow));
+}
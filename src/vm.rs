@@ -0,0 +1,349 @@
+//! A bytecode virtual machine, for evaluating the same expression many
+//! times with different variables. `CompiledExpr::new` flattens a tree
+//! once into instructions for a stack machine, and `eval` runs them
+//! without walking the tree again: operands go on one value stack, and
+//! `&&`, `||` and `?:` are jumps over the code they skip.
+//! `CompiledExpr::eval_watched` shows the stack after every instruction,
+//! for `stoncc --visualize=stack`.
+//!
+//! ```
+//! use stoncc::vm::CompiledExpr;
+//! use stoncc::{Env, Value};
+//!
+//! let f = CompiledExpr::new(&stoncc::expr(b"x * x + 1").unwrap());
+//! let mut env = Env::new();
+//! for x in 0..3 {
+//!     env.insert("x".to_string(), Value::Int(x));
+//!     assert_eq!(f.eval(&env), Ok(Value::Int(x * x + 1)));
+//! }
+//! ```
+
+use std::fmt;
+
+use crate::builtins::Builtins;
+use crate::eval::{Env, EvalError, MAX_CALL_DEPTH};
+use crate::lexer::Span;
+use crate::limits::{EvalLimits, Meter};
+use crate::parser::{LeafVal, Node, NodeVal, Value};
+use crate::symbol::SymId;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Op {
+    Const(Value),
+    /// A parameter or variable.
+    Load(SymId),
+    /// A placeholder, which fails when reached.
+    Hole(usize),
+    /// Replaces the top `argc` values with the result of `op`, or of the
+    /// call, on them.
+    Apply(NodeVal, usize),
+    /// Pops the top value and jumps if it is false.
+    JumpUnless(usize),
+    Jump(usize),
+}
+
+/// An expression compiled to bytecode. It is `Send` and `Sync`, so one can
+/// be shared by threads evaluating it with different variables.
+pub struct CompiledExpr {
+    /// Each instruction with the source it came from, for errors.
+    code: Vec<(Op, Span)>,
+    builtins: Builtins,
+}
+
+struct Compiler {
+    code: Vec<(Op, Span)>,
+}
+
+impl Compiler {
+    fn op(&mut self, op: Op, span: Span) {
+        self.code.push((op, span));
+    }
+
+    /// Emits a jump to be patched, returning where it is.
+    fn jump(&mut self, op: Op, span: Span) -> usize {
+        self.op(op, span);
+        self.code.len() - 1
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let to = self.code.len();
+        if let (Op::JumpUnless(t) | Op::Jump(t), _) = &mut self.code[at] {
+            *t = to;
+        }
+    }
+
+    /// 1 if `node` is nonzero, otherwise 0.
+    fn truth(&mut self, node: &Node) {
+        self.emit(node);
+        self.op(Op::Const(Value::Int(0)), node.span());
+        self.op(Op::Apply(NodeVal::Ne, 2), node.span());
+    }
+
+    fn emit(&mut self, node: &Node) {
+        match node {
+            Node::Leaf(LeafVal::Int(v), span) => self.op(Op::Const(Value::Int(*v)), *span),
+            Node::Leaf(LeafVal::Bool(b), span) => self.op(Op::Const(Value::Int(*b as i32)), *span),
+            Node::Leaf(LeafVal::Decimal(s), span) => self.op(Op::Const(Value::Float(s.parse().unwrap())), *span),
+            Node::Leaf(LeafVal::Sym(s), span) => self.op(Op::Load(*s), *span),
+            Node::Leaf(LeafVal::Placeholder(n), span) => self.op(Op::Hole(*n), *span),
+            Node::Error(_) => panic!("cannot compile a tree with syntax errors"),
+            Node::Node { v, children, span } if v.short_circuit(true).is_some() => {
+                self.emit(&children[0]);
+                let skip = self.jump(Op::JumpUnless(0), *span);
+                match v {
+                    NodeVal::And => self.truth(&children[1]),
+                    NodeVal::Or => self.op(Op::Const(Value::Int(1)), *span),
+                    _ => self.emit(&children[1]),
+                }
+                let done = self.jump(Op::Jump(0), *span);
+                self.patch(skip);
+                match v {
+                    NodeVal::And => self.op(Op::Const(Value::Int(0)), *span),
+                    NodeVal::Or => self.truth(&children[1]),
+                    _ => self.emit(&children[2]),
+                }
+                self.patch(done);
+            }
+            Node::Node { v, children, span } => {
+                children.iter().for_each(|c| self.emit(c));
+                self.op(Op::Apply(v.clone(), children.len()), *span)
+            }
+        }
+    }
+}
+
+fn compile(node: &Node) -> Vec<(Op, Span)> {
+    let mut c = Compiler { code: Vec::new() };
+    c.emit(node);
+    c.code
+}
+
+impl CompiledExpr {
+    /// Compiles `node`, with calls going to the default `Builtins`.
+    pub fn new(node: &Node) -> Self {
+        Self::with_builtins(node, Builtins::default())
+    }
+
+    /// Compiles `node`, with calls going to `builtins`.
+    pub fn with_builtins(node: &Node, builtins: Builtins) -> Self {
+        Self { code: compile(node), builtins }
+    }
+
+    /// Evaluates the expression with the variables in `env`, with no limits.
+    pub fn eval(&self, env: &Env) -> Result<Value, EvalError> {
+        self.eval_with(env, &self.builtins, &mut Meter::new(EvalLimits::default()))
+    }
+
+    /// Evaluates the expression with each of `envs`, as `eval` does, spread
+    /// over a thread per core. The results are in the order of `envs`.
+    pub fn eval_batch(&self, envs: &[Env]) -> Vec<Result<Value, EvalError>> {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk = envs.len().div_ceil(threads).max(1);
+        std::thread::scope(|s| {
+            let workers: Vec<_> = envs.chunks(chunk).map(|envs| s.spawn(move || envs.iter().map(|env| self.eval(env)).collect::<Vec<_>>())).collect();
+            workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+        })
+    }
+
+    /// Like `eval`, with calls going to `builtins` and every step charged
+    /// to `meter`, as `eval_with` does for trees.
+    pub fn eval_with(&self, env: &Env, builtins: &Builtins, meter: &mut Meter) -> Result<Value, EvalError> {
+        run(&self.code, &mut Vec::new(), env, None, builtins, meter, 0, &mut None)
+    }
+
+    /// `eval_with`, calling `each` after every instruction, those of the
+    /// functions it calls included, with the stack that it left.
+    pub fn eval_watched(&self, env: &Env, builtins: &Builtins, meter: &mut Meter, mut each: impl FnMut(&Step)) -> Result<Value, EvalError> {
+        run(&self.code, &mut Vec::new(), env, None, builtins, meter, 0, &mut Some(&mut each))
+    }
+}
+
+/// An instruction that ran, for `CompiledExpr::eval_watched`.
+pub struct Step<'a> {
+    /// How many calls deep it is, 0 in the expression itself.
+    pub depth: usize,
+    /// Where it is in the code of the expression or of the function.
+    pub pc: usize,
+    op: &'a Op,
+    /// The whole stack after it, bottom first, values of the callers
+    /// included.
+    pub stack: &'a [Value],
+}
+
+/// `pc`, the instruction as `CompiledExpr` prints it and the stack,
+/// indented by the depth.
+impl fmt::Display for Step<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stack: Vec<String> = self.stack.iter().map(Value::to_string).collect();
+        let op = self.op.to_string();
+        write!(f, "{:indent$}{:>4}  {op:<16} [{}]", "", self.pc, stack.join(", "), indent = 2 * self.depth)
+    }
+}
+
+type Watch<'w> = Option<&'w mut dyn FnMut(&Step)>;
+
+#[allow(clippy::too_many_arguments)]
+fn run(
+    code: &[(Op, Span)],
+    stack: &mut Vec<Value>,
+    globals: &Env,
+    locals: Option<&Env>,
+    builtins: &Builtins,
+    meter: &mut Meter,
+    depth: usize,
+    watch: &mut Watch,
+) -> Result<Value, EvalError> {
+    let mut pc = 0;
+    while let Some((op, span)) = code.get(pc) {
+        let at = pc;
+        pc += 1;
+        let v = match op {
+            Op::Const(v) => Some(*v),
+            Op::Load(name) => match locals.and_then(|l| l.get(name.as_str())).or_else(|| globals.get(name.as_str())) {
+                Some(v) => Some(*v),
+                None => return Err(EvalError::Unbound(name.to_string(), *span)),
+            },
+            Op::Hole(n) => return Err(EvalError::Unfilled(*n, *span)),
+            Op::JumpUnless(to) => {
+                meter.op()?;
+                if !stack.pop().unwrap().is_true() {
+                    pc = *to;
+                }
+                None
+            }
+            Op::Jump(to) => {
+                pc = *to;
+                None
+            }
+            Op::Apply(op, argc) => {
+                let base = stack.len() - argc;
+                meter.op()?;
+                let v = match op {
+                    NodeVal::Call(name) => match builtins.defined(name) {
+                        Some(f) => {
+                            if depth >= MAX_CALL_DEPTH {
+                                return Err(EvalError::Recursion(name.clone(), *span));
+                            }
+                            let locals = f.bind(&stack[base..]).map_err(|msg| EvalError::Call(name.clone(), msg, *span))?;
+                            stack.truncate(base);
+                            run(&compile(&f.body), stack, globals, Some(&locals), builtins, meter, depth + 1, watch)?
+                        }
+                        None => match builtins.call(name, &stack[base..]) {
+                            Some(v) => {
+                                let v = v.map_err(|msg| EvalError::Call(name.clone(), msg, *span))?;
+                                builtins.narrow(name, v, meter.arith()).map_err(|e| EvalError::Arith(e, *span))?
+                            }
+                            None => return Err(EvalError::UnknownFunction(name.clone(), *span)),
+                        },
+                    },
+                    op => op.apply(&stack[base..], meter.arith()).map_err(|e| EvalError::Arith(e, *span))?,
+                };
+                stack.truncate(base);
+                Some(v)
+            }
+        };
+        if let Some(v) = v {
+            meter.value(v.as_i128())?;
+            stack.push(v);
+        }
+        if let Some(each) = watch {
+            each(&Step { depth, pc: at, op, stack });
+        }
+    }
+
+    Ok(stack.pop().unwrap())
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Const(v) => write!(f, "const {v}"),
+            Op::Load(name) => write!(f, "load {name}"),
+            Op::Hole(n) => write!(f, "hole ?{n}"),
+            Op::Apply(op, argc) => write!(f, "apply {op}/{argc}"),
+            Op::JumpUnless(to) => write!(f, "jump-unless {to}"),
+            Op::Jump(to) => write!(f, "jump {to}"),
+        }
+    }
+}
+
+/// One numbered instruction per line.
+impl fmt::Display for CompiledExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self.code.iter().enumerate().map(|(i, (op, _))| format!("{i:>4}  {op}")).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[test]
+fn tests() {
+    let c = CompiledExpr::new(&crate::parser::expr(b"-x + max(2, 3!) * 1.5").unwrap());
+    assert_eq!(
+        c.to_string(),
+        "   0  load x\n   1  apply -/1\n   2  const 2\n   3  const 3\n   4  apply !/1\n   5  apply max/2\n   6  const 1.5\n   7  apply */2\n   8  apply +/2"
+    );
+    let c = CompiledExpr::new(&crate::parser::expr(b"a && b").unwrap());
+    assert_eq!(c.to_string(), "   0  load a\n   1  jump-unless 6\n   2  load b\n   3  const 0\n   4  apply !=/2\n   5  jump 7\n   6  const 0");
+
+    let mut builtins = Builtins::default();
+    let body = crate::parser::expr(b"n * 2 + x").unwrap();
+    builtins.define("f", crate::Function { params: vec!["n".into()], body });
+    let env: Env = [("x".to_string(), Value::Int(1))].into();
+    let run = |s: &str| {
+        let ast = crate::parser::expr(s.as_bytes()).unwrap();
+        let mut meter = Meter::new(Default::default());
+        let v = CompiledExpr::new(&ast).eval_with(&env, &builtins, &mut meter);
+        // The same as walking the tree.
+        assert_eq!(v, crate::eval_with(&ast, &env, &builtins, &mut Meter::new(Default::default())), "{s}");
+        v
+    };
+
+    assert_eq!(run("f(f(3)) - x"), Ok(Value::Int(14)));
+    assert_eq!(run("x / 2.0"), Ok(Value::Float(0.5)));
+    assert!(matches!(run("y + ?1"), Err(EvalError::Unbound(..))));
+    assert!(matches!(run("x + ?1"), Err(EvalError::Unfilled(1, _))));
+    assert!(matches!(run("g(1)"), Err(EvalError::UnknownFunction(..))));
+    assert_eq!(run("x > 0 ? f(2) : g(1)"), Ok(Value::Int(5)));
+    assert_eq!(run("0 && g(1) || 2.5"), Ok(Value::Int(1)));
+    assert_eq!(run("(x || g(1)) + (0 ? 1 : x ? 2 : 3)"), Ok(Value::Int(3)));
+    assert!(matches!(run("2147483647 + x"), Err(EvalError::Arith(..))));
+
+    // Watching shows each instruction with the stack it left, those of
+    // calls indented.
+    let mut steps = Vec::new();
+    let c = CompiledExpr::new(&crate::parser::expr(b"x ? 1 + f(2) : 0").unwrap());
+    let v = c.eval_watched(&env, &builtins, &mut Meter::new(Default::default()), |s| steps.push(s.to_string()));
+    assert_eq!(v, Ok(Value::Int(6)));
+    assert_eq!(
+        steps,
+        [
+            "   0  load x           [1]",
+            "   1  jump-unless 7    []",
+            "   2  const 1          [1]",
+            "   3  const 2          [1, 2]",
+            "     0  load n           [1, 2]",
+            "     1  const 2          [1, 2, 2]",
+            "     2  apply */2        [1, 4]",
+            "     3  load x           [1, 4, 1]",
+            "     4  apply +/2        [1, 5]",
+            "   4  apply f/1        [1, 5]",
+            "   5  apply +/2        [6]",
+            "   6  jump 8           [6]",
+        ]
+    );
+
+    // One compilation serves any number of evaluations, on any thread.
+    fn shared<T: Send + Sync>() {}
+    shared::<Node>();
+    shared::<CompiledExpr>();
+    shared::<crate::Session>();
+    let c = CompiledExpr::new(&crate::parser::expr(b"x < 10 ? x * 2 : 10 / (x - 20)").unwrap());
+    let envs: Vec<Env> = (0..1000).map(|x| [("x".to_string(), Value::Int(x))].into()).collect();
+    let results = c.eval_batch(&envs);
+    assert_eq!(results.len(), 1000);
+    assert_eq!([&results[1], &results[15]], [&Ok(Value::Int(2)), &Ok(Value::Int(-2))]);
+    assert!(matches!(results[20], Err(EvalError::Arith(..))));
+    assert!(results.iter().zip(&envs).all(|(r, env)| *r == c.eval(env)));
+    assert!(c.eval_batch(&[]).is_empty());
+}
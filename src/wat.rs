@@ -0,0 +1,461 @@
+//! WebAssembly backend (`--emit=wat`). WebAssembly is a stack machine
+//! already, so pushes cost nothing: operands simply stay on the operand
+//! stack. The output is a module in text format whose exported `main`
+//! returns the value of the last statement, an `i32` or an `f64`.
+//!
+//! WebAssembly has no instruction for `^` on floats, so a module that
+//! needs one imports it as `Math.pow`, which a JavaScript host can pass
+//! on as it is: `WebAssembly.instantiate(module, { Math })`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use stoncc::Value;
+
+use crate::codegen::{mangle, Backend, Binary, Tally, Ty, Unary};
+
+/// Helpers for operators WebAssembly has no instruction for.
+const HELPERS: &str = r#"  (func $stoncc_pow (param $b i32) (param $e i32) (result i32) (local $r i32)
+    ;; Truncated like 1 / b^-e, which is 1 / b or its square, and traps
+    ;; for 0.
+    local.get $e
+    i32.const 0
+    i32.lt_s
+    if
+      i32.const 1
+      local.get $b
+      i32.div_s
+      local.tee $r
+      local.get $r
+      local.get $r
+      i32.mul
+      local.get $e
+      i32.const 1
+      i32.and
+      select
+      return
+    end
+    i32.const 1
+    local.set $r
+    block $done
+      loop $next
+        local.get $e
+        i32.const 0
+        i32.le_s
+        br_if $done
+        local.get $r
+        local.get $b
+        i32.mul
+        local.set $r
+        local.get $e
+        i32.const 1
+        i32.sub
+        local.set $e
+        br $next
+      end
+    end
+    local.get $r)
+  (func $stoncc_fac (param $n i32) (result i32) (local $r i32)
+    i32.const 1
+    local.set $r
+    block $done
+      loop $next
+        local.get $n
+        i32.const 1
+        i32.le_s
+        br_if $done
+        local.get $r
+        local.get $n
+        i32.mul
+        local.set $r
+        local.get $n
+        i32.const 1
+        i32.sub
+        local.set $n
+        br $next
+      end
+    end
+    local.get $r)
+  (func $stoncc_gcd (param $a i32) (param $b i32) (result i32) (local $t i32)
+    block $done
+      loop $next
+        local.get $b
+        i32.eqz
+        br_if $done
+        local.get $a
+        local.get $b
+        i32.rem_s
+        local.set $t
+        local.get $b
+        local.set $a
+        local.get $t
+        local.set $b
+        br $next
+      end
+    end
+    local.get $a
+    call $stoncc_abs)
+  (func $stoncc_abs (param $a i32) (result i32)
+    i32.const 0
+    local.get $a
+    i32.sub
+    local.get $a
+    local.get $a
+    i32.const 0
+    i32.lt_s
+    select)
+  (func $stoncc_min (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    local.get $b
+    local.get $a
+    local.get $b
+    i32.lt_s
+    select)
+  (func $stoncc_max (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    local.get $b
+    local.get $a
+    local.get $b
+    i32.gt_s
+    select)
+  (func $stoncc_add_sat (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    i64.extend_i32_s
+    local.get $b
+    i64.extend_i32_s
+    i64.add
+    call $stoncc_clamp)
+  (func $stoncc_mul_sat (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    i64.extend_i32_s
+    local.get $b
+    i64.extend_i32_s
+    i64.mul
+    call $stoncc_clamp)
+  (func $stoncc_shl (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    local.get $b
+    i32.shl
+    i32.const 0
+    local.get $b
+    i32.const 32
+    i32.lt_u
+    select)
+  (func $stoncc_shr (param $a i32) (param $b i32) (result i32)
+    local.get $a
+    local.get $b
+    i32.const 31
+    local.get $b
+    i32.const 31
+    i32.lt_u
+    select
+    i32.shr_s)
+  (func $stoncc_clamp (param $v i64) (result i32)
+    local.get $v
+    i64.const 2147483647
+    local.get $v
+    i64.const 2147483647
+    i64.lt_s
+    select
+    local.set $v
+    local.get $v
+    i64.const -2147483648
+    local.get $v
+    i64.const -2147483648
+    i64.gt_s
+    select
+    i32.wrap_i64)
+"#;
+
+#[derive(Default)]
+pub struct Wat {
+    out: String,
+    tally: Tally,
+    /// Where `main` starts in `out`, and the type of the last result it
+    /// has set so far.
+    main: usize,
+    result: Option<Ty>,
+    /// Whether `main` has any float results, which it keeps in a local of
+    /// their own.
+    floats: bool,
+    /// Whether `Math.pow` is called.
+    pow: bool,
+}
+
+/// `v` as WebAssembly writes it, with the digits that read back as the
+/// same double.
+fn number(v: f64) -> String {
+    match v {
+        v if v.is_nan() => "nan".to_string(),
+        v if v.is_infinite() => format!("{}inf", if v < 0.0 { "-" } else { "" }),
+        v => format!("{v:?}"),
+    }
+}
+
+/// The WebAssembly type of `ty`.
+fn wasm(ty: Ty) -> &'static str {
+    match ty {
+        Ty::Int => "i32",
+        Ty::Float => "f64",
+    }
+}
+
+impl Wat {
+    fn emit(&mut self, line: &str) {
+        self.tally.count(line);
+        self.out += "    ";
+        self.out += line;
+        self.out.push('\n');
+    }
+}
+
+impl Backend for Wat {
+    const COMMENT: &'static str = ";;";
+    const FLOATS: bool = true;
+
+    // The header goes in at the end, once the type of the result is known.
+    fn begin_main(&mut self) {
+        self.main = self.out.len();
+    }
+
+    fn end_main(&mut self) {
+        let ty = self.result.unwrap_or(Ty::Int);
+        self.emit(if ty == Ty::Float { "local.get $fresult)" } else { "local.get $result)" });
+        let locals = if self.floats { " (local $result i32) (local $fresult f64)" } else { " (local $result i32)" };
+        let header = format!("  (func $main (export \"main\") (result {}){locals}\n", wasm(ty));
+        self.out.insert_str(self.main, &header);
+    }
+
+    fn begin_function(&mut self, name: &str, params: usize) {
+        let name = mangle(name);
+        let _ = writeln!(self.out, "  (func $fn.{name}{} (result i32)", " (param i32)".repeat(params));
+    }
+
+    fn end_function(&mut self) {
+        self.out.pop();
+        self.out += ")\n";
+    }
+
+    fn push(&mut self) {}
+
+    fn int(&mut self, v: i32) {
+        self.emit(&format!("i32.const {v}"));
+    }
+
+    fn load_global(&mut self, name: &str, _ty: Ty) {
+        let name = mangle(name);
+        self.emit(&format!("global.get $var.{name}"));
+    }
+
+    fn load_param(&mut self, index: usize) {
+        self.emit(&format!("local.get {index}"));
+    }
+
+    fn store_global(&mut self, name: &str, ty: Ty) {
+        self.emit(&format!("global.set $var.{}", mangle(name)));
+        self.load_global(name, ty);
+    }
+
+    fn unary(&mut self, op: Unary) {
+        match op {
+            Unary::Neg => {
+                self.emit("i32.const -1");
+                self.emit("i32.mul");
+            }
+            Unary::Fac => self.emit("call $stoncc_fac"),
+            Unary::Abs => self.emit("call $stoncc_abs"),
+            Unary::Not => {
+                self.emit("i32.const -1");
+                self.emit("i32.xor");
+            }
+            Unary::Popcount => self.emit("i32.popcnt"),
+            Unary::Clz => self.emit("i32.clz"),
+            Unary::Ctz => self.emit("i32.ctz"),
+        }
+    }
+
+    fn binary(&mut self, op: Binary) {
+        self.emit(match op {
+            Binary::Add | Binary::AddWrap => "i32.add",
+            Binary::Sub => "i32.sub",
+            Binary::Mul | Binary::MulWrap => "i32.mul",
+            Binary::Div => "i32.div_s",
+            Binary::Rem => "i32.rem_s",
+            Binary::Pow => "call $stoncc_pow",
+            Binary::AddSat => "call $stoncc_add_sat",
+            Binary::MulSat => "call $stoncc_mul_sat",
+            Binary::Min => "call $stoncc_min",
+            Binary::Max => "call $stoncc_max",
+            Binary::Gcd => "call $stoncc_gcd",
+            Binary::Shl => "call $stoncc_shl",
+            Binary::Shr => "call $stoncc_shr",
+            Binary::And => "i32.and",
+            Binary::Or => "i32.or",
+            Binary::Rotl => "i32.rotl",
+            Binary::Rotr => "i32.rotr",
+            Binary::Lt => "i32.lt_s",
+            Binary::Le => "i32.le_s",
+            Binary::Gt => "i32.gt_s",
+            Binary::Ge => "i32.ge_s",
+            Binary::Eq => "i32.eq",
+            Binary::Ne => "i32.ne",
+        });
+    }
+
+    fn float(&mut self, v: f64) {
+        self.emit(&format!("f64.const {}", number(v)));
+    }
+
+    fn to_float(&mut self) {
+        self.emit("f64.convert_i32_s");
+    }
+
+    fn float_unary(&mut self, op: Unary) {
+        self.emit(if op == Unary::Neg { "f64.neg" } else { "f64.abs" });
+    }
+
+    fn float_binary(&mut self, op: Binary) {
+        self.pow |= op == Binary::Pow;
+        self.emit(match op {
+            Binary::Add => "f64.add",
+            Binary::Sub => "f64.sub",
+            Binary::Mul => "f64.mul",
+            Binary::Div => "f64.div",
+            Binary::Pow => "call $pow",
+            Binary::Min => "f64.min",
+            Binary::Max => "f64.max",
+            Binary::Lt => "f64.lt",
+            Binary::Le => "f64.le",
+            Binary::Gt => "f64.gt",
+            Binary::Ge => "f64.ge",
+            Binary::Eq => "f64.eq",
+            _ => "f64.ne",
+        });
+    }
+
+    fn call(&mut self, name: &str, _argc: usize) {
+        let name = mangle(name);
+        self.emit(&format!("call $fn.{name}"));
+    }
+
+    fn begin_if(&mut self, value: Option<Ty>) {
+        match value {
+            Some(ty) => self.emit(&format!("if (result {})", wasm(ty))),
+            None => self.emit("if"),
+        }
+    }
+
+    fn begin_else(&mut self) {
+        self.emit("else");
+    }
+
+    fn end_if(&mut self) {
+        self.emit("end");
+    }
+
+    fn begin_loop(&mut self) {
+        self.emit("block");
+        self.emit("loop");
+    }
+
+    fn loop_test(&mut self) {
+        self.emit("i32.eqz");
+        self.emit("br_if 1");
+    }
+
+    fn end_loop(&mut self) {
+        self.emit("br 0");
+        self.emit("end");
+        self.emit("end");
+    }
+
+    fn discard(&mut self) {
+        self.emit("drop");
+    }
+
+    fn result(&mut self, ty: Ty) {
+        self.result = Some(ty);
+        self.floats |= ty == Ty::Float;
+        self.emit(if ty == Ty::Float { "local.set $fresult" } else { "local.set $result" });
+    }
+
+    fn tally(&self) -> Tally {
+        self.tally
+    }
+
+    fn finish(self, globals: &BTreeMap<String, Value>) -> String {
+        let mut out = String::from("(module\n");
+        if self.pow {
+            out += "  (import \"Math\" \"pow\" (func $pow (param f64 f64) (result f64)))\n";
+        }
+        for (name, v) in globals {
+            let (ty, v) = match *v {
+                Value::Float(v) => ("f64", number(v)),
+                v => ("i32", v.to_string()),
+            };
+            let _ = writeln!(out, "  (global $var.{} (mut {ty}) ({ty}.const {v}))", mangle(name));
+        }
+        out += &self.out;
+        out += HELPERS;
+        out += ")\n";
+        out
+    }
+}
+
+#[test]
+fn tests() {
+    let compile = |s: &str| {
+        let program = stoncc::program(s.as_bytes()).0.unwrap();
+        crate::codegen::compile(Wat::default(), &program, &stoncc::Env::new()).unwrap()
+    };
+
+    let wat = compile("x = 2 + 3\nx * -4");
+    let (built_by, wat) = wat.split_once('\n').unwrap();
+    assert_eq!(built_by, format!(";; {}", crate::provenance::describe()));
+    assert!(wat.starts_with("(module\n  (global $var.x (mut i32) (i32.const 0))\n  (func $main (export \"main\") (result i32) (local $result i32)\n"));
+    assert!(wat.contains("    i32.const 2\n    i32.const 3\n    i32.add\n    global.set $var.x\n"));
+    assert!(wat.contains("    local.get $result)\n"));
+
+    let wat = compile("0 || 5");
+    assert!(wat.contains("    i32.const 0\n    if (result i32)\n    i32.const 1\n    else\n    i32.const 5\n    i32.const 0\n    i32.ne\n    end\n"));
+
+    let wat = compile("while (x) { x = x - 1; 2 }");
+    assert!(wat.contains("    block\n    loop\n    global.get $var.x\n    i32.eqz\n    br_if 1\n"));
+    assert!(wat.contains("    global.get $var.x\n    drop\n    i32.const 2\n    drop\n    br 0\n    end\n    end\n"));
+
+    let wat = compile("~1 & 6 | 9 % 4 << 1");
+    assert!(wat.contains("    i32.const 1\n    i32.const -1\n    i32.xor\n    i32.const 6\n    i32.and\n"));
+    assert!(wat.contains("    i32.rem_s\n    i32.const 1\n    call $stoncc_shl\n    i32.or\n"));
+
+    let wat = compile("popcount(clz(ctz(rotl(rotr(1, 2), 3))))");
+    assert!(wat.contains("    i32.const 1\n    i32.const 2\n    i32.rotr\n    i32.const 3\n    i32.rotl\n    i32.ctz\n    i32.clz\n    i32.popcnt\n"));
+
+    // Negative powers are 1 / b, or its square for even exponents: 0 for
+    // `2 ^ -1`, -1 for `(-1) ^ -1`.
+    let wat = compile("2 ^ -1 + (-1) ^ -1");
+    assert!(wat.contains("    i32.const 2\n    i32.const 1\n    i32.const -1\n    i32.mul\n    call $stoncc_pow\n"), "{wat}");
+    assert!(wat.contains("    i32.const 1\n    i32.const -1\n    i32.mul\n    i32.const 1\n    i32.const -1\n    i32.mul\n    call $stoncc_pow\n    i32.add\n"), "{wat}");
+    assert!(wat.contains("    i32.lt_s\n    if\n      i32.const 1\n      local.get $b\n      i32.div_s\n      local.tee $r\n"), "{wat}");
+    assert!(wat.contains("      i32.and\n      select\n      return\n    end\n"), "{wat}");
+
+    let wat = compile("if (1) 2");
+    assert!(wat.contains("    if\n    i32.const 2\n    drop\n    else\n    end\n"));
+
+    // Integers convert where they meet a float, and `main` gives what the
+    // last statement does.
+    let wat = compile("x = 1 / 2.0\nmin(x, -1) ^ 2 ? 1 : 0.5\n2 < 1.5");
+    assert!(wat.contains("\n(module\n  (import \"Math\" \"pow\" (func $pow (param f64 f64) (result f64)))\n  (global $var.x (mut f64) (f64.const 0.0))\n"), "{wat}");
+    assert!(wat.contains("  (func $main (export \"main\") (result i32) (local $result i32) (local $fresult f64)\n"), "{wat}");
+    assert!(wat.contains("    i32.const 1\n    f64.convert_i32_s\n    f64.const 2.0\n    f64.div\n    global.set $var.x\n"), "{wat}");
+    assert!(wat.contains("    i32.const -1\n    i32.mul\n    f64.convert_i32_s\n    f64.min\n"), "{wat}");
+    assert!(wat.contains("    call $pow\n    f64.const 0.0\n    f64.ne\n    if (result f64)\n    i32.const 1\n    f64.convert_i32_s\n    else\n    f64.const 0.5\n    end\n    local.set $fresult\n"), "{wat}");
+    assert!(wat.contains("    f64.lt\n    local.set $result\n    local.get $result)\n"), "{wat}");
+    let wat = compile("-1.5");
+    assert!(wat.contains("  (func $main (export \"main\") (result f64) (local $result i32) (local $fresult f64)\n    f64.const 1.5\n    f64.neg\n    local.set $fresult\n    local.get $fresult)\n"), "{wat}");
+    assert_eq!([number(0.1), number(1e21), number(f64::NEG_INFINITY), number(f64::NAN)], ["0.1", "1e21", "-inf", "nan"]);
+
+    let wat = compile("f(a, b) = max(a, b) - b; f(1, 2)");
+    assert!(wat.contains("  (func $fn.f (param i32) (param i32) (result i32)\n    local.get 0\n"));
+    assert!(wat.contains("    call $stoncc_max\n    local.get 1\n    i32.sub)\n"));
+    assert!(wat.contains("    call $fn.f\n"));
+}
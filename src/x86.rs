@@ -0,0 +1,538 @@
+//! x86-64 backend (`--emit=asm`). Expressions are compiled the way chibicc
+//! compiles them: the current value lives in `%eax`, with pushed operands
+//! on the hardware stack. The output is AT&T-syntax assembly for the
+//! System V ABI. Its `main` prints the value of every statement with
+//! `printf`, one per line. `popcount`, `clz` and `ctz` are the `popcnt`,
+//! `lzcnt` and `tzcnt` instructions, which processors have had since 2013
+//! (x86-64-v3).
+//!
+//! Arithmetic wraps, unless the backend is `X86::checked`: then every
+//! result that `--arith=checked` would report as an overflow stops the
+//! program with the same message instead.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use stoncc::Value;
+
+use crate::codegen::{mangle, Backend, Binary, Tally, Ty, Unary, MAX_PARAMS};
+
+/// Registers that hold the first arguments of a call.
+const ARG_REGS: [&str; MAX_PARAMS] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+
+/// Out-of-line helpers for operators with loops in them. Each takes its
+/// operands in `%eax` and `%edi` and returns in `%eax`. Negative powers
+/// are truncated as `1 / a^-b` is, and the errors the evaluator reports
+/// whatever `--arith` says stop the program with the same message.
+const HELPERS: &str = "
+stoncc_pow:
+  mov $1, %ecx
+  test %edi, %edi
+  js 3f
+1:
+  test %edi, %edi
+  jz 2f
+  test $1, %edi
+  jz 4f
+  imul %eax, %ecx
+4:
+  imul %eax, %eax
+  shr %edi
+  jmp 1b
+2:
+  mov %ecx, %eax
+  ret
+3:
+  test %eax, %eax
+  jz stoncc_div0
+  cmp $-1, %eax
+  jne 5f
+  test $1, %edi
+  cmovz %ecx, %eax
+  ret
+5:
+  cmp $1, %eax
+  sete %al
+  movzbl %al, %eax
+  ret
+stoncc_fac:
+  test %eax, %eax
+  js stoncc_negfac
+  mov %eax, %edi
+  mov $1, %eax
+1:
+  cmp $1, %edi
+  jle 2f
+  imul %edi, %eax
+  dec %edi
+  jmp 1b
+2:
+  ret
+stoncc_pow_checked:
+  test %edi, %edi
+  js stoncc_pow
+  mov $1, %ecx
+1:
+  test $1, %edi
+  jz 2f
+  imul %eax, %ecx
+  jo stoncc_overflow
+2:
+  shr %edi
+  jz 3f
+  imul %eax, %eax
+  jo stoncc_overflow
+  jmp 1b
+3:
+  mov %ecx, %eax
+  ret
+stoncc_fac_checked:
+  test %eax, %eax
+  js stoncc_negfac
+  mov %eax, %edi
+  mov $1, %eax
+1:
+  cmp $1, %edi
+  jle 2f
+  imul %edi, %eax
+  jo stoncc_overflow
+  dec %edi
+  jmp 1b
+2:
+  ret
+stoncc_gcd:
+  mov %edi, %ecx
+1:
+  test %ecx, %ecx
+  jz 2f
+  cmp $-1, %ecx
+  je 3f
+  cltd
+  idiv %ecx
+  mov %ecx, %eax
+  mov %edx, %ecx
+  jmp 1b
+2:
+  mov %eax, %ecx
+  neg %eax
+  cmovs %ecx, %eax
+  ret
+3:
+  mov $1, %eax
+  ret
+stoncc_div0:
+  lea .L.div0(%rip), %rdi
+  jmp stoncc_fail
+stoncc_negfac:
+  lea .L.negfac(%rip), %rdi
+  jmp stoncc_fail
+stoncc_negshift:
+  lea .L.negshift(%rip), %rdi
+  jmp stoncc_fail
+stoncc_overflow:
+  lea .L.overflow(%rip), %rdi
+  jmp stoncc_fail
+stoncc_fail:
+  and $-16, %rsp
+  mov stderr(%rip), %rsi
+  call fputs@PLT
+  mov $1, %edi
+  call exit@PLT
+";
+
+/// The messages of the errors the helpers stop with.
+const MESSAGES: &str = "
+.L.div0:
+  .string \"error: division by zero\\n\"
+.L.negfac:
+  .string \"error: factorial of a negative number\\n\"
+.L.negshift:
+  .string \"error: negative shift count\\n\"
+.L.overflow:
+  .string \"error: integer overflow\\n\"
+";
+
+#[derive(Default)]
+pub struct X86 {
+    out: String,
+    tally: Tally,
+    /// Values pushed onto the stack by the expression being compiled.
+    depth: usize,
+    labels: usize,
+    /// Labels of the conditionals being compiled, innermost last.
+    ifs: Vec<usize>,
+    /// Labels of the loops being compiled, innermost last.
+    loops: Vec<usize>,
+    /// Whether overflow is an error rather than wrapping.
+    checked: bool,
+}
+
+impl X86 {
+    /// A backend whose code stops on overflow, as `--arith=checked` does.
+    pub fn checked() -> Self {
+        X86 { checked: true, ..Default::default() }
+    }
+
+    /// Stops the program if the last operation overflowed.
+    fn check_overflow(&mut self) {
+        if self.checked {
+            self.emit("jo stoncc_overflow");
+        }
+    }
+
+    fn emit(&mut self, line: &str) {
+        self.tally.count(line);
+        self.out += "  ";
+        self.out += line;
+        self.out.push('\n');
+    }
+
+    fn pop(&mut self, reg: &str) {
+        self.emit(&format!("pop {reg}"));
+        self.depth -= 1;
+    }
+
+    /// Saturates `%eax` after an overflowing operation, towards the sign
+    /// that was saved in `%ecx`.
+    fn saturate(&mut self) {
+        self.labels += 1;
+        let l = self.labels;
+        self.emit(&format!("jno .L.sat.{l}"));
+        self.emit("sar $31, %ecx");
+        self.emit("xor $0x7fffffff, %ecx");
+        self.emit("mov %ecx, %eax");
+        self.out += &format!(".L.sat.{l}:\n");
+    }
+
+    /// Divides `%eax` by `%edi`, leaving the quotient or the remainder.
+    /// `idiv` faults on the one quotient that overflows, which wraps to
+    /// the dividend negated, with nothing left over.
+    fn divide(&mut self, rem: bool) {
+        self.labels += 1;
+        let l = self.labels;
+        self.emit("test %edi, %edi");
+        self.emit("jz stoncc_div0");
+        self.emit("cmp $-1, %edi");
+        self.emit(&format!("jne .L.div.{l}"));
+        self.emit(if rem { "xor %eax, %eax" } else { "neg %eax" });
+        if !rem {
+            self.check_overflow();
+        }
+        self.emit(&format!("jmp .L.divided.{l}"));
+        self.out += &format!(".L.div.{l}:\n");
+        self.emit("cltd");
+        self.emit("idiv %edi");
+        if rem {
+            self.emit("mov %edx, %eax");
+        }
+        self.out += &format!(".L.divided.{l}:\n");
+    }
+}
+
+impl Backend for X86 {
+    const COMMENT: &'static str = "#";
+
+    fn begin_main(&mut self) {
+        self.out += "  .globl main\n  .text\nmain:\n";
+        self.emit("push %rbp");
+        self.emit("mov %rsp, %rbp");
+    }
+
+    fn end_main(&mut self) {
+        self.emit("xor %eax, %eax");
+        self.emit("leave");
+        self.emit("ret");
+    }
+
+    fn begin_function(&mut self, name: &str, params: usize) {
+        let name = mangle(name);
+        self.out += &format!(".L.fn.{name}:\n");
+        self.emit("push %rbp");
+        self.emit("mov %rsp, %rbp");
+        self.emit(&format!("sub ${}, %rsp", (8 * params).next_multiple_of(16)));
+        for (i, reg) in ARG_REGS[..params].iter().enumerate() {
+            self.emit(&format!("mov {reg}, -{}(%rbp)", 8 * (i + 1)));
+        }
+    }
+
+    fn end_function(&mut self) {
+        self.emit("leave");
+        self.emit("ret");
+    }
+
+    fn push(&mut self) {
+        self.emit("push %rax");
+        self.depth += 1;
+    }
+
+    fn int(&mut self, v: i32) {
+        self.emit(&format!("mov ${v}, %eax"));
+    }
+
+    fn load_global(&mut self, name: &str, _ty: Ty) {
+        let name = mangle(name);
+        self.emit(&format!("mov .L.var.{name}(%rip), %eax"));
+    }
+
+    fn load_param(&mut self, index: usize) {
+        self.emit(&format!("mov -{}(%rbp), %eax", 8 * (index + 1)));
+    }
+
+    fn store_global(&mut self, name: &str, _ty: Ty) {
+        let name = mangle(name);
+        self.emit(&format!("mov %eax, .L.var.{name}(%rip)"));
+    }
+
+    fn unary(&mut self, op: Unary) {
+        match op {
+            Unary::Neg => {
+                self.emit("neg %eax");
+                self.check_overflow();
+            }
+            Unary::Fac if self.checked => self.emit("call stoncc_fac_checked"),
+            Unary::Fac => self.emit("call stoncc_fac"),
+            Unary::Abs => {
+                self.emit("mov %eax, %ecx");
+                self.emit("neg %eax");
+                self.check_overflow();
+                self.emit("cmovs %ecx, %eax");
+            }
+            Unary::Not => self.emit("not %eax"),
+            Unary::Popcount => self.emit("popcnt %eax, %eax"),
+            Unary::Clz => self.emit("lzcnt %eax, %eax"),
+            Unary::Ctz => self.emit("tzcnt %eax, %eax"),
+        }
+    }
+
+    fn binary(&mut self, op: Binary) {
+        self.emit("mov %eax, %edi");
+        self.pop("%rax");
+        match op {
+            Binary::Add | Binary::Sub | Binary::Mul => {
+                self.emit(match op {
+                    Binary::Add => "add %edi, %eax",
+                    Binary::Sub => "sub %edi, %eax",
+                    _ => "imul %edi, %eax",
+                });
+                self.check_overflow();
+            }
+            Binary::AddWrap => self.emit("add %edi, %eax"),
+            Binary::MulWrap => self.emit("imul %edi, %eax"),
+            Binary::Div | Binary::Rem => self.divide(op == Binary::Rem),
+            // Only bits that come back when shifted the other way are kept.
+            Binary::Shl if self.checked => {
+                self.labels += 1;
+                let l = self.labels;
+                self.emit("test %edi, %edi");
+                self.emit("js stoncc_negshift");
+                self.emit("test %eax, %eax");
+                self.emit(&format!("jz .L.shl.{l}"));
+                self.emit("cmp $31, %edi");
+                self.emit("ja stoncc_overflow");
+                self.emit("mov %edi, %ecx");
+                self.emit("mov %eax, %edx");
+                self.emit("shl %cl, %eax");
+                self.emit("mov %eax, %esi");
+                self.emit("sar %cl, %esi");
+                self.emit("cmp %esi, %edx");
+                self.emit("jne stoncc_overflow");
+                self.out += &format!(".L.shl.{l}:\n");
+            }
+            // The hardware only looks at the low 5 bits of the count.
+            Binary::Shl => {
+                self.emit("test %edi, %edi");
+                self.emit("js stoncc_negshift");
+                self.emit("mov %edi, %ecx");
+                self.emit("shl %cl, %eax");
+                self.emit("xor %edx, %edx");
+                self.emit("cmp $31, %edi");
+                self.emit("cmova %edx, %eax");
+            }
+            Binary::Shr => {
+                self.emit("test %edi, %edi");
+                self.emit("js stoncc_negshift");
+                self.emit("mov $31, %ecx");
+                self.emit("cmp %ecx, %edi");
+                self.emit("cmovb %edi, %ecx");
+                self.emit("sar %cl, %eax");
+            }
+            Binary::And => self.emit("and %edi, %eax"),
+            Binary::Or => self.emit("or %edi, %eax"),
+            Binary::Rotl | Binary::Rotr => {
+                self.emit("mov %edi, %ecx");
+                self.emit(if op == Binary::Rotl { "rol %cl, %eax" } else { "ror %cl, %eax" });
+            }
+            Binary::Pow if self.checked => self.emit("call stoncc_pow_checked"),
+            Binary::Pow => self.emit("call stoncc_pow"),
+            Binary::Gcd => {
+                self.emit("call stoncc_gcd");
+                // Only gcd(-2^31, 0) and gcd(-2^31, -2^31) are negative.
+                if self.checked {
+                    self.emit("test %eax, %eax");
+                    self.emit("js stoncc_overflow");
+                }
+            }
+            Binary::Min | Binary::Max => {
+                self.emit("cmp %edi, %eax");
+                self.emit(if op == Binary::Min { "cmovg %edi, %eax" } else { "cmovl %edi, %eax" });
+            }
+            Binary::Lt | Binary::Le | Binary::Gt | Binary::Ge | Binary::Eq | Binary::Ne => {
+                let set = match op {
+                    Binary::Lt => "setl",
+                    Binary::Le => "setle",
+                    Binary::Gt => "setg",
+                    Binary::Ge => "setge",
+                    Binary::Eq => "sete",
+                    _ => "setne",
+                };
+                self.emit("cmp %edi, %eax");
+                self.emit(&format!("{set} %al"));
+                self.emit("movzbl %al, %eax");
+            }
+            Binary::AddSat => {
+                self.emit("mov %eax, %ecx");
+                self.emit("add %edi, %eax");
+                self.saturate();
+            }
+            Binary::MulSat => {
+                self.emit("mov %eax, %ecx");
+                self.emit("xor %edi, %ecx");
+                self.emit("imul %edi, %eax");
+                self.saturate();
+            }
+        }
+    }
+
+    fn call(&mut self, name: &str, argc: usize) {
+        let name = mangle(name);
+        for reg in ARG_REGS[..argc].iter().rev() {
+            self.pop(reg);
+        }
+        self.emit(&format!("call .L.fn.{name}"));
+    }
+
+    fn begin_if(&mut self, _value: Option<Ty>) {
+        self.labels += 1;
+        self.ifs.push(self.labels);
+        self.emit("test %eax, %eax");
+        self.emit(&format!("je .L.else.{}", self.labels));
+    }
+
+    fn begin_else(&mut self) {
+        let l = *self.ifs.last().unwrap();
+        self.emit(&format!("jmp .L.end.{l}"));
+        self.out += &format!(".L.else.{l}:\n");
+    }
+
+    fn end_if(&mut self) {
+        let l = self.ifs.pop().unwrap();
+        self.out += &format!(".L.end.{l}:\n");
+    }
+
+    fn begin_loop(&mut self) {
+        self.labels += 1;
+        self.loops.push(self.labels);
+        self.out += &format!(".L.loop.{}:\n", self.labels);
+    }
+
+    fn loop_test(&mut self) {
+        let l = *self.loops.last().unwrap();
+        self.emit("test %eax, %eax");
+        self.emit(&format!("je .L.done.{l}"));
+    }
+
+    fn end_loop(&mut self) {
+        let l = self.loops.pop().unwrap();
+        self.emit(&format!("jmp .L.loop.{l}"));
+        self.out += &format!(".L.done.{l}:\n");
+    }
+
+    // The current value is simply overwritten.
+    fn discard(&mut self) {}
+
+    fn result(&mut self, _ty: Ty) {
+        debug_assert_eq!(self.depth, 0);
+        self.emit("lea .L.fmt(%rip), %rdi");
+        self.emit("mov %eax, %esi");
+        self.emit("xor %eax, %eax");
+        self.emit("call printf@PLT");
+    }
+
+    fn tally(&self) -> Tally {
+        self.tally
+    }
+
+    fn finish(mut self, globals: &BTreeMap<String, Value>) -> String {
+        self.out += HELPERS;
+        self.out += "\n  .data\n.L.fmt:\n  .string \"%d\\n\"\n";
+        self.out += &MESSAGES[1..];
+        for (name, v) in globals {
+            let Value::Int(v) = v else { unreachable!("x86-64 code has no floats") };
+            let _ = writeln!(self.out, ".L.var.{}:\n  .long {v}", mangle(name));
+        }
+        self.out += "  .section .note.GNU-stack,\"\",@progbits\n";
+        self.out
+    }
+}
+
+#[test]
+fn tests() {
+    let compile = |s: &str| {
+        let program = stoncc::program(s.as_bytes()).0.unwrap();
+        crate::codegen::compile(X86::default(), &program, &stoncc::Env::new()).unwrap()
+    };
+
+    let asm = compile("x = 2 + 3\nx * 4");
+    assert!(asm.contains("add %edi, %eax"));
+    assert!(asm.contains("mov %eax, .L.var.x(%rip)"));
+    assert!(asm.contains(".L.var.x:\n  .long 0"));
+
+    let asm = compile("1 <= 2");
+    assert!(asm.contains("cmp %edi, %eax\n  setle %al\n  movzbl %al, %eax\n"));
+
+    let asm = compile("~7 % 3 << 2 >> 1");
+    assert!(asm.contains("not %eax"));
+    assert!(asm.contains("idiv %edi\n  mov %edx, %eax\n"));
+    assert!(asm.contains("shl %cl, %eax\n  xor %edx, %edx\n  cmp $31, %edi\n  cmova %edx, %eax\n"));
+    assert!(asm.contains("cmovb %edi, %ecx\n  sar %cl, %eax\n"));
+
+    // Division checks its divisor, and the quotient that `idiv` can't give.
+    let asm = compile("7 / x");
+    assert!(asm.contains("test %edi, %edi\n  jz stoncc_div0\n  cmp $-1, %edi\n  jne .L.div.1\n  neg %eax\n"));
+    assert!(asm.contains(".L.div.1:\n  cltd\n  idiv %edi\n.L.divided.1:\n"));
+    assert!(asm.contains(".L.div0:\n  .string \"error: division by zero\\n\"\n"));
+    assert!(compile("1 >> x").contains("test %edi, %edi\n  js stoncc_negshift\n"));
+
+    // Checked code jumps out after every operation that can overflow, but
+    // not after `+%`, which is asked to wrap.
+    let checked = |s: &str| {
+        let program = stoncc::program(s.as_bytes()).0.unwrap();
+        crate::codegen::compile(X86::checked(), &program, &stoncc::Env::new()).unwrap()
+    };
+    let jumps = |asm: String| asm.matches("jo ").count() - compile("0").matches("jo ").count();
+    assert_eq!(jumps(compile("x * 2 - -x")), 0);
+    assert!(checked("x * 2").contains("imul %edi, %eax\n  jo stoncc_overflow\n"));
+    assert!(checked("-x").contains("neg %eax\n  jo stoncc_overflow\n"));
+    assert_eq!(jumps(checked("x +% 2 *% 3")), 0);
+    assert_eq!(jumps(checked("x * 2 - -x")), 3);
+    assert!(checked("x^3 + 3!").contains("call stoncc_pow_checked"));
+    assert!(checked("3!").contains("call stoncc_fac_checked"));
+
+    let asm = compile("popcount(clz(ctz(rotl(rotr(1, 2), 3))))");
+    assert!(asm.contains("mov %edi, %ecx\n  ror %cl, %eax\n"));
+    assert!(asm.contains("mov %edi, %ecx\n  rol %cl, %eax\n  tzcnt %eax, %eax\n  lzcnt %eax, %eax\n  popcnt %eax, %eax\n"));
+
+    let asm = compile("1 ? 2 : 3 && 4");
+    assert!(asm.contains("test %eax, %eax\n  je .L.else.1\n  mov $2, %eax\n  jmp .L.end.1\n.L.else.1:\n"));
+    assert!(asm.contains(".L.end.2:\n.L.end.1:\n"));
+
+    let asm = compile("i = 0; while (i < 3) i = i + 1");
+    assert!(asm.contains(".L.loop.1:\n  mov .L.var.i(%rip), %eax\n"));
+    assert!(asm.contains("  je .L.done.1\n"));
+    assert!(asm.contains("  jmp .L.loop.1\n.L.done.1:\n"));
+
+    let asm = compile("f(a, b) = a - b; f(1, 2)");
+    assert!(asm.contains(".L.fn.f:"));
+    assert!(asm.contains("mov %rsi, -16(%rbp)"));
+    assert!(asm.contains("call .L.fn.f"));
+}
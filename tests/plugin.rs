@@ -0,0 +1,95 @@
+//! `--plugin`: a C plugin built against `include/stoncc_plugin.h` adds a
+//! builtin, an infix operator calling it and an output format. Skipped
+//! where there is no C compiler.
+
+use std::process::{self, Command};
+use std::{env, fs};
+
+const PLUGIN: &str = r#"
+#include <math.h>
+#include <stdio.h>
+#include <stoncc_plugin.h>
+
+static int floordiv(const double *args, size_t argc, double *result) {
+    if (args[1] == 0)
+        return 7;
+    *result = floor(args[0] / args[1]);
+    return 0;
+}
+
+static int percent(double value, char *buf, size_t len) {
+    return snprintf(buf, len, "%g%%", value * 100) < (int)len ? 0 : 1;
+}
+
+int stoncc_plugin_init(const struct stoncc_host *host) {
+    if (host->abi < STONCC_PLUGIN_ABI)
+        return 1;
+    if (host->builtin(host->ctx, "floordiv", 2, 2, floordiv) != 0)
+        return 2;
+    /* Taken already. */
+    if (host->builtin(host->ctx, "sqrt", 1, 1, floordiv) == 0)
+        return 3;
+    if (host->infix(host->ctx, "//", "*", "floordiv") != 0)
+        return 4;
+    if (host->infix(host->ctx, "+", "*", "floordiv") == 0)
+        return 5;
+    return host->format(host->ctx, "percent", percent) != 0 ? 6 : 0;
+}
+"#;
+
+#[test]
+fn tests() {
+    let scratch = env::temp_dir().join(format!("stoncc-plugin-{}", process::id()));
+    fs::create_dir_all(&scratch).unwrap();
+    fs::write(scratch.join("plugin.c"), PLUGIN).unwrap();
+    let include = concat!(env!("CARGO_MANIFEST_DIR"), "/include");
+    let built = Command::new("cc")
+        .current_dir(&scratch)
+        .args(["-shared", "-fPIC", "-I", include, "-o", "libplugin.so", "plugin.c", "-lm"])
+        .status();
+    if !built.is_ok_and(|s| s.success()) {
+        eprintln!("no C compiler, skipping");
+        fs::remove_dir_all(&scratch).unwrap();
+        return;
+    }
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_stoncc"))
+            .env("STONCC_NO_CONFIG", "1")
+            .current_dir(&scratch)
+            .args(args)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        (output.status.code(), stdout, String::from_utf8_lossy(&output.stderr).into_owned())
+    };
+
+    let (code, stdout, stderr) = run(&["--plugin", "./libplugin.so", "-e", "1 + 7 // 2 * 3"]);
+    assert_eq!(code, Some(0), "{stderr}");
+    assert!(stdout.ends_with(": 10.0\n"), "{stdout}");
+    let (code, stdout, stderr) = run(&["--plugin=./libplugin.so", "-e", "floordiv(-7, 2)"]);
+    assert_eq!(code, Some(0), "{stderr}");
+    assert!(stdout.ends_with(": -4.0\n"), "{stdout}");
+    // The format may come before the plugin that adds it.
+    let (code, stdout, stderr) = run(&["--out=percent", "--plugin", "./libplugin.so", "-e", "0.25"]);
+    assert_eq!(code, Some(0), "{stderr}");
+    assert!(stdout.ends_with(": 25%\n"), "{stdout}");
+
+    let (code, _, stderr) = run(&["--plugin", "./libplugin.so", "-e", "1 // 0"]);
+    assert_eq!(code, Some(1));
+    assert!(stderr.contains("floordiv() failed with 7"), "{stderr}");
+    let (code, _, stderr) = run(&["--out=percent", "-e", "1"]);
+    assert_eq!(code, Some(2));
+    assert!(stderr.contains("unknown --out format 'percent'"), "{stderr}");
+    let (code, _, stderr) = run(&["--plugin", "./libplugin.so", "--sandbox", "-e", "1"]);
+    assert_eq!(code, Some(2));
+    assert!(stderr.contains("--sandbox cannot be combined with --plugin"), "{stderr}");
+    if cfg!(feature = "rational") {
+        let (code, _, stderr) = run(&["--plugin", "./libplugin.so", "--rational", "-e", "1"]);
+        assert_eq!(code, Some(2));
+        assert!(stderr.contains("--plugin cannot be combined with"), "{stderr}");
+    }
+    let (code, _, stderr) = run(&["--plugin", "./missing.so", "-e", "1"]);
+    assert_eq!(code, Some(1));
+    assert!(stderr.contains("cannot load plugin"), "{stderr}");
+    fs::remove_dir_all(&scratch).unwrap();
+}
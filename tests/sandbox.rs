@@ -0,0 +1,33 @@
+//! `--sandbox` refuses every option that would write a file on the
+//! program's behalf, before anything is written.
+
+use std::process::{self, Command};
+use std::{env, fs};
+
+#[test]
+fn tests() {
+    let scratch = env::temp_dir().join(format!("stoncc-sandbox-{}", process::id()));
+    fs::create_dir_all(&scratch).unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_stoncc"))
+            .env("STONCC_NO_CONFIG", "1")
+            .current_dir(&scratch)
+            .args(args)
+            .output()
+            .unwrap();
+        (output.status.code(), String::from_utf8_lossy(&output.stderr).into_owned())
+    };
+
+    let (code, stderr) = run(&["--sandbox", "--record=out.txt", "-e", "1 + 2"]);
+    assert_eq!(code, Some(2), "{stderr}");
+    assert!(stderr.contains("--sandbox cannot be combined with --record"), "{stderr}");
+    assert!(!scratch.join("out.txt").exists());
+
+    let (code, stderr) = run(&["--sandbox", "--audit-log=audit.jsonl", "-e", "1 + 2"]);
+    assert_eq!(code, Some(2), "{stderr}");
+    assert!(stderr.contains("--sandbox cannot be combined with --audit-log"), "{stderr}");
+    assert!(!scratch.join("audit.jsonl").exists());
+
+    assert_eq!(run(&["--sandbox", "-e", "1 + 2"]).0, Some(0));
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,103 @@
+//! Snapshot tests over the expression corpus in `tests/corpus`.
+//!
+//! Every `.stc` file is run through the binary once per output mode and the
+//! combined output is compared against `tests/snapshots/<name>.snap`. Files
+//! are run from a scratch directory, where `--emit=asm` can write its output
+//! and diagnostics name them the same way on every machine. Run with
+//! `UPDATE_SNAPSHOTS=1` to (re)write the snapshots after an intended change,
+//! then review the diff like any other source change.
+
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::{env, fs};
+
+/// Output modes captured for each corpus file: a label and the extra
+/// arguments passed to the binary before the file name.
+const MODES: &[(&str, &[&str])] = &[
+    ("eval", &[]),
+    ("sexp", &["--dump-ast=sexp"]),
+    ("infix", &["--format"]),
+    ("ir", &["--emit=ir"]),
+    ("bytecode", &["--visualize=stack"]),
+    ("asm", &["--emit=asm"]),
+];
+
+fn render(file: &Path, scratch: &Path) -> String {
+    let name = file.file_name().unwrap();
+    fs::copy(file, scratch.join(name)).unwrap();
+    let mut out = String::new();
+
+    for (label, args) in MODES {
+        // The user's and any project's config would change the results.
+        let output = Command::new(env!("CARGO_BIN_EXE_stoncc"))
+            .env("STONCC_NO_CONFIG", "1")
+            .current_dir(scratch)
+            .args(*args)
+            .arg(name)
+            .output()
+            .unwrap();
+
+        out += &format!("--- {label} ---\n");
+        out += &String::from_utf8_lossy(&output.stdout);
+        if !output.stdout.ends_with(b"\n") {
+            out.push('\n');
+        }
+        if *label == "asm" && output.status.success() {
+            let asm = fs::read_to_string(scratch.join(name).with_extension("s")).unwrap();
+            // Past the comment naming the build, which changes with every
+            // commit, and without the helpers and messages every program
+            // has, from the first helper to the program's own variables.
+            let asm = asm.split_once('\n').map_or("", |(_, rest)| rest);
+            let (code, rest) = asm.split_once("stoncc_pow:").unwrap_or((asm, ""));
+            out += code;
+            out += rest.find(".L.var.").map_or("", |i| &rest[i..]);
+        }
+        if !output.status.success() {
+            out += &format!("[exit: {:?}]\n", output.status.code());
+            out += &String::from_utf8_lossy(&output.stderr);
+        }
+    }
+
+    out
+}
+
+#[test]
+fn corpus() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let update = env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let scratch = env::temp_dir().join(format!("stoncc-snapshots-{}", process::id()));
+    fs::create_dir_all(&scratch).unwrap();
+
+    let mut files: Vec<PathBuf> = fs::read_dir(root.join("corpus"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "stc"))
+        .collect();
+    files.sort();
+
+    let mut failed = Vec::new();
+    for file in &files {
+        let name = file.file_stem().unwrap().to_str().unwrap();
+        let snap = root.join("snapshots").join(format!("{name}.snap"));
+        let actual = render(file, &scratch);
+
+        match fs::read_to_string(&snap) {
+            Ok(expected) if expected == actual => {}
+            _ if update => {
+                fs::create_dir_all(snap.parent().unwrap()).unwrap();
+                fs::write(&snap, &actual).unwrap();
+            }
+            Ok(expected) => {
+                eprintln!("snapshot mismatch: {name}\n-- expected\n{expected}-- actual\n{actual}");
+                failed.push(name.to_string());
+            }
+            Err(_) => {
+                eprintln!("missing snapshot: {name}\n{actual}");
+                failed.push(name.to_string());
+            }
+        }
+    }
+
+    fs::remove_dir_all(&scratch).unwrap();
+    assert!(failed.is_empty(), "snapshots differ: {failed:?} (rerun with UPDATE_SNAPSHOTS=1)");
+}
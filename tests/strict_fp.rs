@@ -0,0 +1,105 @@
+//! `--strict-fp`: every evaluator gives the same floats bit for bit, and
+//! so do the programs `--optimize`, `--opt=egraph` and `--passes` rewrite. Random
+//! float expressions are run through the tree walker, the arena, the
+//! three-address code and the VM, and then through the binary with each
+//! optimizer.
+
+use std::process::{self, Command};
+use std::{env, fs};
+
+use stoncc::generate::Rng;
+use stoncc::limits::{EvalLimits, Meter};
+use stoncc::parser::{LeafVal, Node, NodeVal};
+use stoncc::{arena, ir, vm, Builtins, Env, Value};
+
+/// Variables the expressions use: -0.0 and NaN are where identities and
+/// reordering show, the rest round when combined.
+const VARS: &[(&str, f64)] = &[("n", -0.0), ("q", f64::NAN), ("t", 0.1), ("h", 0.3)];
+
+/// A tree of float arithmetic at most `depth` operators deep. Every leaf
+/// is a float, so no operator is ever applied to integers alone.
+fn random(rng: &mut Rng, depth: usize) -> Node {
+    if depth == 0 || rng.below(4) == 0 {
+        return match rng.below(3) {
+            0 => Node::leaf(LeafVal::Sym(VARS[rng.below(VARS.len())].0.into())),
+            _ => Node::leaf(LeafVal::Decimal(["0.1", "0.2", "1.5", "3.0", "1e300", "7.25"][rng.below(6)].into())),
+        };
+    }
+    let ops = [NodeVal::Add, NodeVal::Sub, NodeVal::Mul, NodeVal::Div, NodeVal::Exp];
+    let v = ops[rng.below(ops.len())].clone();
+    // An integer 0 or 1, where the identities would apply.
+    if rng.below(6) == 0 {
+        let one = Node::leaf(LeafVal::Int(rng.below(2) as i32));
+        return Node::op(v, vec![random(rng, depth - 1), one]);
+    }
+    Node::op(v, vec![random(rng, depth - 1), random(rng, depth - 1)])
+}
+
+#[test]
+fn tests() {
+    let mut env = Env::new();
+    VARS.iter().for_each(|&(name, v)| {
+        env.insert(name.to_string(), Value::Float(v));
+    });
+    let b = Builtins::default();
+    let meter = || Meter::new(EvalLimits::default());
+
+    let mut rng = Rng::new(495);
+    let mut program = VARS.iter().map(|(name, v)| format!("{name} = {}\n", if v.is_nan() { "0.0 / 0.0".into() } else { format!("{v:?}") })).collect::<String>();
+    for i in 0..300 {
+        let node = random(&mut rng, 5);
+        let src = node.to_infix();
+        let want = stoncc::eval_with(&node, &env, &b, &mut meter()).unwrap();
+        let got = [
+            ("arena", arena::eval_with(&arena::expr(src.as_bytes()).unwrap(), &env, &b, &mut meter())),
+            ("ir", ir::eval(&ir::lower(&node), &env, &b, &mut meter())),
+            ("vm", vm::CompiledExpr::new(&node).eval(&env)),
+            ("simplified", stoncc::eval_with(&node.simplify_strict_fp(), &env, &b, &mut meter())),
+        ];
+        for (backend, v) in got {
+            assert!(v.as_ref().is_ok_and(|v| v.identical(&want)), "{backend}: {src} gives {v:?}, the tree walker {want:?}");
+        }
+        program += &format!("r{i} = {src}\n");
+    }
+
+    // Without --strict-fp, x + 0 is simplified to x, which for -0.0 isn't.
+    let n = stoncc::expr(b"n + 0").unwrap();
+    let plain = stoncc::eval_with(&n.simplify(), &env, &b, &mut meter()).unwrap();
+    assert!(!plain.identical(&stoncc::eval_with(&n, &env, &b, &mut meter()).unwrap()));
+
+    // The binary prints the same values under every optimizer, and checks
+    // the three-address code bit for bit.
+    let scratch = env::temp_dir().join(format!("stoncc-strict-fp-{}", process::id()));
+    fs::create_dir_all(&scratch).unwrap();
+    fs::write(scratch.join("floats.stc"), &program).unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_stoncc"))
+            .env("STONCC_NO_CONFIG", "1")
+            .current_dir(&scratch)
+            .args(args)
+            .arg("floats.stc")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{args:?}: {}", String::from_utf8_lossy(&output.stderr));
+        // Each line shows the tree it evaluated, which the optimizers change.
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        stdout.lines().map(|line| line.rsplit_once(": ").unwrap().1.to_string() + "\n").collect::<String>()
+    };
+    let want = run(&["--strict-fp", "--check-ir"]);
+    assert!(want.contains("-0.0\n") && want.contains("NaN\n"), "{want}");
+    assert_eq!(run(&["--strict-fp", "--check-ir", "--optimize"]), want);
+    assert_eq!(run(&["--strict-fp", "--opt=egraph"]), want);
+    assert_eq!(run(&["--strict-fp", "--opt=egraph", "--optimize"]), want);
+    assert_eq!(run(&["--strict-fp", "--check-ir", "--passes=fold,simplify,canonicalize,cse,dce"]), want);
+
+    fs::write(scratch.join("assoc.rules"), "(+ (+ ?a ?b) ?c) => (+ ?a (+ ?b ?c))\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_stoncc"))
+        .env("STONCC_NO_CONFIG", "1")
+        .current_dir(&scratch)
+        .args(["--strict-fp", "--rules=assoc.rules", "floats.stc"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--strict-fp fixes the order floats are evaluated in"));
+    fs::remove_dir_all(&scratch).unwrap();
+}